@@ -4,6 +4,7 @@ use std::path;
 use std::time;
 
 use criterion::{criterion_group, criterion_main, Criterion};
+use qbsdiff::{Bsdiff, Codec, Format};
 use qbsdiff_test_bench_utils::*;
 
 pub fn patch(crit: &mut Criterion) {
@@ -25,6 +26,37 @@ pub fn patch(crit: &mut Criterion) {
     }
 }
 
+pub fn patch_codecs(crit: &mut Criterion) {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let benching = Benchmarking::new(assets);
+
+    let codecs = [
+        Codec::Stored,
+        Codec::Bzip2,
+        #[cfg(feature = "zstd")]
+        Codec::Zstd,
+    ];
+    let regular = benching.get_regular_samples().unwrap();
+
+    for sample in regular.iter() {
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        for codec in codecs.iter() {
+            let bench_name = format!("patch {} ({:?})", sample.name, codec);
+            let mut p = Vec::new();
+            Bsdiff::new(&s[..], &t[..])
+                .format(Format::Extended)
+                .codec(*codec)
+                .compression_level(1)
+                .compare(&mut p)
+                .unwrap();
+            crit.bench_function(bench_name.as_str(), |b| {
+                b.iter(|| benching.qbspatch(&s[..], &p[..]).unwrap())
+            });
+        }
+    }
+}
+
 pub fn diff(crit: &mut Criterion) {
     let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
     let benching = Benchmarking::new(assets);
@@ -51,7 +83,7 @@ criterion_group! {
         .noise_threshold(0.02)
         .warm_up_time(time::Duration::from_millis(200))
         .measurement_time(time::Duration::new(2, 0));
-    targets = patch,
+    targets = patch, patch_codecs,
 }
 
 criterion_group! {
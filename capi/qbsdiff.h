@@ -0,0 +1,71 @@
+/*
+ * C ABI of qbsdiff (feature `capi`), see `src/capi.rs`.
+ *
+ * Build the library by:
+ *     cargo rustc --release --lib --features capi --crate-type staticlib
+ * or `--crate-type cdylib` for a shared library.
+ */
+
+#ifndef QBSDIFF_H
+#define QBSDIFF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define QBS_ABI_VERSION 1
+
+#define QBS_OK 0
+#define QBS_EINVAL (-1)
+#define QBS_ECORRUPT (-2)
+#define QBS_EUNSUPPORTED (-3)
+#define QBS_EIO (-4)
+#define QBS_ENOMEM (-5)
+#define QBS_EINTERNAL (-6)
+
+/* Return bytes written (at least one for a non-empty buffer), or negative on failure. */
+typedef intptr_t (*qbs_write_fn)(void *user_data, const uint8_t *buf, size_t len);
+
+/* Return zero on success. */
+typedef int (*qbs_flush_fn)(void *user_data);
+
+/* Return bytes read (zero at the end), or negative on failure. */
+typedef intptr_t (*qbs_read_fn)(void *user_data, uint8_t *buf, size_t len);
+
+/* Whence is SEEK_SET, SEEK_CUR or SEEK_END (0, 1 and 2), return the new offset or negative on failure. */
+typedef int64_t (*qbs_seek_fn)(void *user_data, int64_t offset, int whence);
+
+typedef struct qbs_writer {
+    qbs_write_fn write;
+    qbs_flush_fn flush; /* optional */
+    void *user_data;
+} qbs_writer;
+
+typedef struct qbs_source {
+    qbs_read_fn read;
+    qbs_seek_fn seek;
+    void *user_data;
+} qbs_source;
+
+uint32_t qbs_abi_version(void);
+
+const char *qbs_strerror(int code);
+
+/* Compare source with target and stream the patch to `patch`, `patch_size` may be NULL. */
+int qbs_diff(const uint8_t *source, size_t source_len, const uint8_t *target, size_t target_len,
+             const qbs_writer *patch, uint64_t *patch_size);
+
+int qbs_patch_target_size(const uint8_t *patch, size_t patch_len, uint64_t *target_size);
+
+/* Apply the patch to `source` and stream the target to `target`, `target_size` may be NULL. */
+int qbs_patch(const uint8_t *patch, size_t patch_len, const qbs_source *source, const qbs_writer *target,
+              uint64_t *target_size);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* QBSDIFF_H */
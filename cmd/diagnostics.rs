@@ -0,0 +1,242 @@
+//! Exit codes and machine-readable diagnostics shared by `qbsdiff` and
+//! `qbspatch`.
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+/// Exit code of I/O errors.
+pub const EXIT_IO: i32 = 1;
+
+/// Exit code of invalid arguments (same as usage errors reported by clap).
+pub const EXIT_ARGS: i32 = 2;
+
+/// Exit code of corrupted or unsupported patches.
+pub const EXIT_CORRUPT_PATCH: i32 = 3;
+
+/// Exit code of sources not matching the patch.
+pub const EXIT_SOURCE_MISMATCH: i32 = 4;
+
+/// Classified failure of a command.
+pub enum Failure {
+    Args(io::Error),
+    CorruptPatch(io::Error),
+    SourceMismatch(io::Error),
+    Io(io::Error),
+}
+
+impl Failure {
+    /// Invalid arguments with message.
+    pub fn args(msg: &str) -> Self {
+        Failure::Args(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    }
+
+    fn code(&self) -> i32 {
+        match self {
+            Failure::Args(_) => EXIT_ARGS,
+            Failure::CorruptPatch(_) => EXIT_CORRUPT_PATCH,
+            Failure::SourceMismatch(_) => EXIT_SOURCE_MISMATCH,
+            Failure::Io(_) => EXIT_IO,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Failure::Args(_) => "bad_args",
+            Failure::CorruptPatch(_) => "corrupt_patch",
+            Failure::SourceMismatch(_) => "source_mismatch",
+            Failure::Io(_) => "io",
+        }
+    }
+
+    fn error(&self) -> &io::Error {
+        match self {
+            Failure::Args(e) | Failure::CorruptPatch(e) | Failure::SourceMismatch(e) | Failure::Io(e) => e,
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+/// Result object printed with `--json`.
+#[derive(Default)]
+pub struct Report {
+    fields: Vec<(&'static str, String)>,
+    durations: Vec<(&'static str, Duration)>,
+}
+
+impl Report {
+    /// Add an integer field.
+    pub fn size(&mut self, name: &'static str, size: u64) {
+        self.fields.push((name, size.to_string()));
+    }
+
+    /// Add a ratio field.
+    pub fn ratio(&mut self, name: &'static str, ratio: f64) {
+        self.fields.push((name, format!("{:.6}", ratio)));
+    }
+
+    /// Add a boolean field.
+    pub fn flag(&mut self, name: &'static str, flag: bool) {
+        self.fields.push((name, flag.to_string()));
+    }
+
+    /// Add a string field.
+    pub fn text(&mut self, name: &'static str, text: &str) {
+        self.fields.push((name, quote(text)));
+    }
+
+    /// Add a duration of stage, reported in seconds.
+    pub fn duration(&mut self, stage: &'static str, duration: Duration) {
+        self.durations.push((stage, duration));
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = String::from("{\"ok\": true");
+        for (name, value) in self.fields.iter() {
+            let _ = write!(json, ", \"{}\": {}", name, value);
+        }
+        json.push_str(", \"durations\": {");
+        for (i, (stage, duration)) in self.durations.iter().enumerate() {
+            let sep = if i > 0 { ", " } else { "" };
+            let _ = write!(json, "{}\"{}\": {:.6}", sep, stage, duration.as_secs_f64());
+        }
+        json.push_str("}}");
+        json
+    }
+}
+
+/// Print the result and exit.
+///
+/// With `json`, a result object is printed to stdout, or to stderr if stdout
+/// carries the output data.
+pub fn exit(result: Result<Report, Failure>, json: bool, stdout_busy: bool) -> ! {
+    let (line, code) = match result {
+        Ok(report) if json => (Some(report.to_json()), 0),
+        Ok(_) => (None, 0),
+        Err(failure) if json => {
+            let line = format!(
+                "{{\"ok\": false, \"error\": {}, \"message\": {}, \"exit_code\": {}}}",
+                quote(failure.kind()),
+                quote(&failure.error().to_string()),
+                failure.code()
+            );
+            (Some(line), failure.code())
+        }
+        Err(failure) => {
+            eprintln!("error: {}", failure.error());
+            (None, failure.code())
+        }
+    };
+    if let Some(line) = line {
+        if stdout_busy {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+    let _ = io::stdout().flush();
+    process::exit(code);
+}
+
+/// Quote a JSON string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// SHA-256 of the data in hex.
+pub fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// SHA-256 of the file in hex.
+pub fn sha256_file(path: &str) -> io::Result<String> {
+    let mut hasher = Hashed::new(io::sink(), true);
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.digest())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::with_capacity(64), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+/// Reader or writer counting the size of data passed through, and computing
+/// the SHA-256 if enabled.
+pub struct Hashed<T> {
+    inner: T,
+    hasher: Option<Sha256>,
+    size: u64,
+}
+
+impl<T> Hashed<T> {
+    pub fn new(inner: T, hash: bool) -> Self {
+        Hashed {
+            inner,
+            hasher: Some(Sha256::new()).filter(|_| hash),
+            size: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// SHA-256 in hex, empty if disabled.
+    pub fn digest(&self) -> String {
+        self.hasher
+            .as_ref()
+            .map(|hasher| hex(&hasher.clone().finalize()))
+            .unwrap_or_default()
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(data);
+        }
+        self.size += data.len() as u64;
+    }
+}
+
+impl<R: Read> Read for Hashed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Hashed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -2,10 +2,20 @@
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::process;
+use std::ops::Deref;
+use std::time::Instant;
 
 use clap::{ArgAction, Parser};
-use qbsdiff::{Bsdiff, ParallelScheme};
+use qbsdiff::input::{self, Compression, InputFile};
+use qbsdiff::{Bsdiff, DiffReport, ParallelScheme};
+
+use diagnostics::{Failure, Hashed, Report};
+
+#[allow(dead_code)]
+mod diagnostics;
+
+/// Default window size of streaming target from stdin.
+const DEFAULT_WINDOW: usize = 64 * 1024 * 1024;
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -24,8 +34,8 @@ struct BsdiffArgs {
     target_path: String,
 
     /// patch file
-    #[clap(value_name = "PATCH")]
-    patch_path: String,
+    #[clap(value_name = "PATCH", required_unless_present = "compare_only")]
+    patch_path: Option<String>,
 
     /// disable parallel searching
     #[clap(short = 'P', default_value_t = true, action = ArgAction::SetFalse)]
@@ -46,38 +56,67 @@ struct BsdiffArgs {
     /// skip small matches
     #[clap(short = 's', value_name = "SMALL")]
     small_match: Option<usize>,
+
+    /// compare target window by window with bounded memory (default when
+    /// TARGET is '-')
+    #[clap(long = "window", value_name = "WINDOW")]
+    window: Option<usize>,
+
+    /// decompress gzip or zstd compressed SOURCE and TARGET before comparing
+    #[clap(long = "decompress")]
+    decompress: bool,
+
+    /// run the matcher without writing a patch, and print the similarity
+    /// metrics and the patch size (PATCH is not required)
+    #[clap(long = "compare-only")]
+    compare_only: bool,
+
+    /// print a JSON result object (sizes, SHA-256 hashes, durations) to stdout,
+    /// or to stderr if PATCH is '-'
+    #[clap(long = "json")]
+    json: bool,
 }
 
 fn main() {
     let args = BsdiffArgs::parse();
-    if let Err(e) = execute(args) {
-        eprintln!("error: {}", e);
-        process::exit(1);
-    }
+    let (json, stdout_busy) = (args.json, args.patch_path.as_deref() == Some("-"));
+    diagnostics::exit(execute(args), json, stdout_busy);
 }
 
-fn execute(args: BsdiffArgs) -> io::Result<()> {
+fn execute(args: BsdiffArgs) -> Result<Report, Failure> {
+    let start = Instant::now();
+    let mut report = Report::default();
+
     // validate command line arguments
-    if !matches!(args.compress_level, Some(0..=9) | None) {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "compression level must be in range 0-9",
-        ));
+    if !matches!(args.compress_level, Some(1..=9) | None) {
+        return Err(Failure::args("compression level must be in range 1-9"));
     }
 
     // setup input/output
     if args.source_path == "-" && args.target_path == "-" {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "source and target are both from stdin",
-        ));
+        return Err(Failure::args("source and target are both from stdin"));
+    }
+    if args.compare_only && args.window.is_some() {
+        return Err(Failure::args("window is not supported with --compare-only"));
     }
-    let source = input_bytes(&args.source_path)?;
-    let target = input_bytes(&args.target_path)?;
-    let patch = output_writer(&args.patch_path)?;
+    let source = input_data(&args.source_path, args.decompress)?;
+    let window = match args.window {
+        None if args.target_path == "-" && !args.compare_only => Some(DEFAULT_WINDOW),
+        window => window,
+    };
+    let target = match window {
+        Some(_) => Box::new(Vec::new()),
+        None => input_data(&args.target_path, args.decompress)?,
+    };
+    let patch_writer = match args.patch_path {
+        Some(ref path) if !args.compare_only => output_writer(path)?,
+        _ => Box::new(io::sink()),
+    };
+    let mut patch = Hashed::new(patch_writer, args.json);
+    report.duration("load", start.elapsed());
 
     // setup delta compressor
-    let mut bsdiff = Bsdiff::new(source.as_slice(), target.as_slice());
+    let mut bsdiff = Bsdiff::new(&source, &target);
     if args.parallel {
         bsdiff = bsdiff.parallel_scheme(ParallelScheme::Auto);
     } else if let Some(mut chunk_size) = args.chunk_size {
@@ -90,26 +129,114 @@ fn execute(args: BsdiffArgs) -> io::Result<()> {
         bsdiff = bsdiff.compression_level(compress_level);
     }
     if let Some(buffer_size) = args.buffer_size {
-        bsdiff = bsdiff.buffer_size(buffer_size);
+        bsdiff = bsdiff.try_buffer_size(buffer_size).map_err(Failure::Args)?;
     }
     if let Some(small_match) = args.small_match {
         bsdiff = bsdiff.small_match(small_match);
     }
 
     // execute delta compressor
-    bsdiff.compare(patch)?;
-    Ok(())
+    let compare = Instant::now();
+    if args.compare_only {
+        let diff = bsdiff.analyze(true).compare_report(&mut patch).map_err(classify)?;
+        report.duration("compare", compare.elapsed());
+        report.duration("total", start.elapsed());
+        compare_only(&diff, &mut report, args.json);
+        return Ok(report);
+    }
+    let (target_size, target_sha256) = match window {
+        Some(window) => {
+            let mut target = Hashed::new(input_reader(&args.target_path, args.decompress)?, args.json);
+            bsdiff
+                .compare_reader(&mut target, window, &mut patch)
+                .map_err(classify)?;
+            (target.size(), Some(target.digest()))
+        }
+        None => {
+            bsdiff.compare(&mut patch).map_err(classify)?;
+            (target.len() as u64, None)
+        }
+    };
+    report.duration("compare", compare.elapsed());
+    report.duration("total", start.elapsed());
+    if !args.json {
+        return Ok(report);
+    }
+
+    let target_sha256 = target_sha256.unwrap_or_else(|| diagnostics::sha256(&target));
+    report.size("source_size", source.len() as u64);
+    report.size("target_size", target_size);
+    report.size("patch_size", patch.size());
+    report.text("source_sha256", &diagnostics::sha256(&source));
+    report.text("target_sha256", &target_sha256);
+    report.text("patch_sha256", &patch.digest());
+    Ok(report)
 }
 
-fn input_bytes(path: &str) -> io::Result<Vec<u8>> {
-    let mut data;
+/// Report the similarity metrics and the patch size of `--compare-only`,
+/// printed unless `json`.
+fn compare_only(diff: &DiffReport, report: &mut Report, json: bool) {
+    let similarity = diff.similarity().unwrap_or(0.0);
+    let ratio = diff.patch_size() as f64 / Ord::max(diff.target_size(), 1) as f64;
+    if json {
+        report.size("source_size", diff.source_size());
+        report.size("target_size", diff.target_size());
+        report.size("patch_size", diff.patch_size());
+        report.ratio("similarity", similarity);
+        report.ratio("patch_ratio", ratio);
+        report.size("extra_size", diff.extra_size());
+        report.size("controls", diff.controls());
+        let anomalies: Vec<&str> = diff.anomalies().iter().map(|a| a.code()).collect();
+        report.text("anomalies", &anomalies.join(","));
+        return;
+    }
+
+    println!("source size:  {}", diff.source_size());
+    println!("target size:  {}", diff.target_size());
+    println!("similarity:   {:.2}%", similarity * 100.0);
+    println!("extra size:   {}", diff.extra_size());
+    println!("controls:     {}", diff.controls());
+    println!("patch size:   {} ({:.2}% of target)", diff.patch_size(), ratio * 100.0);
+    for anomaly in diff.anomalies() {
+        println!("anomaly:      {}", anomaly.code());
+    }
+}
+
+/// Classify errors of delta compression.
+fn classify(e: io::Error) -> Failure {
+    match e.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => Failure::Args(e),
+        _ => Failure::Io(e),
+    }
+}
+
+fn input_data(path: &str, decompress: bool) -> io::Result<Box<dyn Deref<Target = [u8]>>> {
     if path == "-" {
-        data = Vec::new();
+        let mut data = Vec::new();
         io::stdin().read_to_end(&mut data)?;
+        if decompress {
+            data = input::decompress(data)?;
+        }
+        Ok(Box::new(data))
+    } else if decompress {
+        Ok(Box::new(InputFile::open_decompressed(path)?))
+    } else {
+        Ok(Box::new(fs::read(path)?))
+    }
+}
+
+fn input_reader(path: &str, decompress: bool) -> io::Result<Box<dyn Read>> {
+    let mut reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(io::stdin().lock())
     } else {
-        data = fs::read(path)?;
+        Box::new(io::BufReader::new(fs::File::open(path)?))
+    };
+    if decompress {
+        if let Some(compression) = Compression::detect(reader.fill_buf()?) {
+            return compression.decoder(reader);
+        }
     }
-    Ok(data)
+    Ok(reader)
 }
 
 fn output_writer(path: &str) -> io::Result<Box<dyn Write>> {
@@ -0,0 +1,363 @@
+#![forbid(unsafe_code)]
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use qbsdiff::bsdiff::MAX_LENGTH;
+use qbsdiff::{Bsdiff, SourceIndex};
+
+/// Max size of request line and headers.
+const MAX_HEADER: u64 = 16 * 1024;
+
+#[derive(Parser, Debug)]
+#[clap(
+name = "qbsdiff-server",
+version = "1.4.2",
+about = "delta server keeping source indexes warm",
+long_about = None,
+)]
+struct ServerArgs {
+    /// directory of source files
+    #[clap(value_name = "ROOT")]
+    root: PathBuf,
+
+    /// address to listen on
+    #[clap(short = 'l', long = "listen", value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// number of source indexes kept warm
+    #[clap(short = 'n', long = "pool", value_name = "SOURCES", default_value_t = 4)]
+    pool: usize,
+
+    /// number of requests served concurrently
+    #[clap(short = 'j', long = "workers", value_name = "WORKERS", default_value_t = 4)]
+    workers: usize,
+
+    /// number of connections queued for busy workers
+    #[clap(long = "backlog", value_name = "CONNECTIONS", default_value_t = 64)]
+    backlog: usize,
+
+    /// max target size per request
+    #[clap(long = "max-target", value_name = "BYTES", default_value_t = 256 * 1024 * 1024)]
+    max_target: u64,
+
+    /// searching steps per target byte
+    #[clap(long = "work-limit", value_name = "FACTOR", default_value_t = 64)]
+    work_limit: usize,
+
+    /// socket timeout in seconds
+    #[clap(long = "timeout", value_name = "SECS", default_value_t = 30)]
+    timeout: u64,
+}
+
+fn main() {
+    let args = ServerArgs::parse();
+    if let Err(e) = execute(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn execute(args: ServerArgs) -> io::Result<()> {
+    // validate command line arguments
+    if args.pool == 0 || args.workers == 0 {
+        return Err(io::Error::other("pool size and workers must be positive"));
+    }
+    if !args.root.is_dir() {
+        return Err(io::Error::other("root is not a directory"));
+    }
+
+    let listener = TcpListener::bind(&args.listen)?;
+    println!("listening on {}", listener.local_addr()?);
+    io::stdout().flush()?;
+
+    // setup request workers, connections exceeding the backlog are rejected
+    let server = Arc::new(Server {
+        pool: Mutex::new(Pool {
+            root: args.root,
+            capacity: args.pool,
+            work_limit: args.work_limit,
+            entries: VecDeque::new(),
+        }),
+        max_target: args.max_target,
+        timeout: Duration::from_secs(Ord::max(args.timeout, 1)),
+    });
+    let (queue, connections): (SyncSender<TcpStream>, Receiver<TcpStream>) = mpsc::sync_channel(args.backlog);
+    let connections = Arc::new(Mutex::new(connections));
+    for _ in 0..args.workers {
+        let server = server.clone();
+        let connections = connections.clone();
+        thread::spawn(move || loop {
+            let stream = connections.lock().unwrap().recv();
+            match stream {
+                Ok(stream) => server.handle(stream),
+                Err(_) => break,
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => match queue.try_send(stream) {
+                Ok(()) => {}
+                Err(TrySendError::Full(mut stream)) => {
+                    let _ = Response::text(503, "server busy").write(&mut stream);
+                }
+                Err(TrySendError::Disconnected(_)) => break,
+            },
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Delta server.
+struct Server {
+    pool: Mutex<Pool>,
+    max_target: u64,
+    timeout: Duration,
+}
+
+impl Server {
+    /// Serve a single request on the connection.
+    fn handle(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(self.timeout));
+        let _ = stream.set_write_timeout(Some(self.timeout));
+        let (line, response) = match Request::read(&mut stream, self.max_target) {
+            Ok(request) => (format!("{} {}", request.method, request.path), self.route(request)),
+            Err(response) => (String::from("-"), response),
+        };
+        eprintln!("{} {}", line, response.status);
+        let _ = response.write(&mut stream);
+    }
+
+    fn route(&self, request: Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/health") => Response::text(200, "ok"),
+            ("POST", path) if path.starts_with("/diff/") => {
+                let name = &path["/diff/".len()..];
+                if !valid_name(name) {
+                    return Response::text(400, "invalid source name");
+                }
+                match self.diff(name, request.body) {
+                    Ok(patch) => Response::new(200, "application/octet-stream", patch),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Response::text(404, "source not found"),
+                    Err(e) if e.kind() == io::ErrorKind::InvalidInput => Response::text(400, &e.to_string()),
+                    Err(e) => Response::text(500, &e.to_string()),
+                }
+            }
+            (_, "/health") => Response::text(405, "method not allowed"),
+            _ => Response::text(404, "not found"),
+        }
+    }
+
+    /// Compare the target against the warm source.
+    fn diff(&self, name: &str, target: Vec<u8>) -> io::Result<Vec<u8>> {
+        let (reply, result) = mpsc::channel();
+        let warm = self.warm(name)?;
+        if warm.send(Job { target, reply }).is_err() {
+            self.pool.lock().unwrap().remove(name);
+            return Err(io::Error::other("source worker exited"));
+        }
+        result
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("source worker exited")))
+    }
+
+    /// Get the warm source, loading it if absent.
+    fn warm(&self, name: &str) -> io::Result<Sender<Job>> {
+        if let Some(warm) = self.pool.lock().unwrap().get(name) {
+            return Ok(warm);
+        }
+
+        // Load outside of the lock, other sources are still served.
+        let root = self.pool.lock().unwrap().root.clone();
+        let source = fs::read(root.join(name))?;
+        if source.len() > MAX_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "source is too large"));
+        }
+
+        let mut pool = self.pool.lock().unwrap();
+        match pool.get(name) {
+            Some(warm) => Ok(warm),
+            None => Ok(pool.insert(name, source)),
+        }
+    }
+}
+
+/// Diff request sent to a warm source.
+struct Job {
+    target: Vec<u8>,
+    reply: Sender<io::Result<Vec<u8>>>,
+}
+
+/// Warm sources, least recently used first.
+///
+/// Each source is owned by a thread together with its index, serving jobs
+/// concurrently in scoped threads. Evicted sources are freed once their
+/// pending jobs are done.
+struct Pool {
+    root: PathBuf,
+    capacity: usize,
+    work_limit: usize,
+    entries: VecDeque<(String, Sender<Job>)>,
+}
+
+impl Pool {
+    fn get(&mut self, name: &str) -> Option<Sender<Job>> {
+        let i = self.entries.iter().position(|(n, _)| n == name)?;
+        let entry = self.entries.remove(i)?;
+        let warm = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(warm)
+    }
+
+    fn insert(&mut self, name: &str, source: Vec<u8>) -> Sender<Job> {
+        let (warm, jobs) = mpsc::channel::<Job>();
+        let work_limit = self.work_limit;
+        thread::spawn(move || {
+            let index = SourceIndex::new(&source[..]);
+            thread::scope(|scope| {
+                for job in jobs {
+                    let index = &index;
+                    scope.spawn(move || {
+                        let mut patch = Vec::new();
+                        let result = Bsdiff::with_index(index, &job.target[..])
+                            .work_limit(work_limit)
+                            .compare(io::Cursor::new(&mut patch))
+                            .map(|_| patch);
+                        let _ = job.reply.send(result);
+                    });
+                }
+            });
+        });
+
+        self.entries.push_back((name.to_string(), warm.clone()));
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        warm
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| n != name);
+    }
+}
+
+/// Plain file names in the root, hidden files excluded.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// HTTP/1.1 request.
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &mut TcpStream, max_body: u64) -> Result<Self, Response> {
+        let mut reader = io::BufReader::new(stream);
+        let mut head = (&mut reader).take(MAX_HEADER);
+        let bad_request = |_| Response::text(400, "bad request");
+
+        let mut line = String::new();
+        head.read_line(&mut line).map_err(bad_request)?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), path.to_string())
+            }
+            _ => return Err(Response::text(400, "bad request")),
+        };
+
+        let mut length = 0;
+        loop {
+            line.clear();
+            head.read_line(&mut line).map_err(bad_request)?;
+            if !line.ends_with('\n') {
+                return Err(Response::text(431, "request header fields too large"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value
+                        .trim()
+                        .parse::<u64>()
+                        .map_err(|_| Response::text(400, "bad request"))?;
+                }
+            }
+        }
+        if length > max_body {
+            return Err(Response::text(413, "target too large"));
+        }
+
+        let mut body = Vec::new();
+        (&mut reader).take(length).read_to_end(&mut body).map_err(bad_request)?;
+        if body.len() as u64 != length {
+            return Err(Response::text(400, "bad request"));
+        }
+        Ok(Request { method, path, body })
+    }
+}
+
+/// HTTP/1.1 response, the connection is always closed.
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: u16, message: &str) -> Self {
+        Response::new(status, "text/plain", format!("{}\n", message).into_bytes())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Content Too Large",
+            431 => "Request Header Fields Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )?;
+        w.write_all(&self.body[..])?;
+        w.flush()
+    }
+}
@@ -2,10 +2,20 @@
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::process;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
-use qbsdiff::Bspatch;
+use qbsdiff::diagnostics::peak_rss;
+use qbsdiff::input::{self, InputFile};
+use qbsdiff::{inspect, Bspatch, Format, SourceCorruption};
+
+use diagnostics::{Failure, Hashed, Report};
+
+#[allow(dead_code)]
+mod diagnostics;
+
+/// Upper bound of the memory reserved ahead for bench targets.
+const MAX_BENCH_RESERVE: u64 = 1 << 30;
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -16,52 +26,282 @@ long_about = None,
 )]
 struct BspatchArgs {
     /// source file
-    #[clap(value_name = "SOURCE")]
-    source_path: String,
+    #[clap(value_name = "SOURCE", required_unless_present = "check")]
+    source_path: Option<String>,
 
     /// target file
-    #[clap(value_name = "TARGET")]
-    target_path: String,
+    #[clap(value_name = "TARGET", required_unless_present = "check")]
+    target_path: Option<String>,
 
     /// patch file
-    #[clap(value_name = "PATCH")]
-    patch_path: String,
+    #[clap(value_name = "PATCH", required_unless_present = "check")]
+    patch_path: Option<String>,
+
+    /// check the patch deeply (header, sections, controls and checksums)
+    /// without producing the target, and print a summary
+    #[clap(long = "check", value_name = "PATCH", conflicts_with_all = ["source_path", "target_path", "patch_path"])]
+    check: Option<String>,
+
+    /// source file to check the patch against (with --check)
+    #[clap(long = "source", value_name = "SRC", requires = "check")]
+    check_source: Option<String>,
 
     /// buffer size
     #[clap(short = 'b', value_name = "BUFFER")]
     buffer_size: Option<usize>,
+
+    /// decompress gzip or zstd compressed SOURCE before patching
+    #[clap(long = "decompress")]
+    decompress: bool,
+
+    /// apply the patch N times to memory and print the min/avg throughput
+    /// and peak memory, then write the target once
+    #[clap(long = "bench", value_name = "N", conflicts_with = "check")]
+    bench: Option<u32>,
+
+    /// print a JSON result object (sizes, SHA-256 hashes, durations) to stdout,
+    /// or to stderr if TARGET is '-'
+    #[clap(long = "json")]
+    json: bool,
 }
 
 fn main() {
     let args = BspatchArgs::parse();
-    if let Err(e) = execute(args) {
-        eprintln!("error: {}", e);
-        process::exit(1);
-    }
+    let (json, stdout_busy) = (args.json, args.target_path.as_deref() == Some("-"));
+    diagnostics::exit(execute(args), json, stdout_busy);
 }
 
-fn execute(args: BspatchArgs) -> io::Result<()> {
+fn execute(args: BspatchArgs) -> Result<Report, Failure> {
+    let start = Instant::now();
+    let mut report = Report::default();
+    if let Some(ref patch_path) = args.check {
+        return check(patch_path, args.check_source.as_deref(), args.decompress, args.json);
+    }
+
     // setup input/output
-    if args.source_path == "-" && args.patch_path == "-" {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "source and patch are both from stdin",
-        ));
+    let (source_path, target_path, patch_path) = match (args.source_path, args.target_path, args.patch_path) {
+        (Some(source), Some(target), Some(patch)) => (source, target, patch),
+        _ => return Err(Failure::args("SOURCE, TARGET and PATCH are required")),
+    };
+    if source_path == "-" && patch_path == "-" {
+        return Err(Failure::args("source and patch are both from stdin"));
+    }
+    let patch = input_bytes(&patch_path)?;
+    report.duration("load", start.elapsed());
+    if let Some(runs) = args.bench {
+        let source = source_bytes(&source_path, args.decompress)?;
+        return bench(&source, &target_path, &patch, args.buffer_size, runs, args.json);
     }
-    let source = input_bytes(&args.source_path)?;
-    let target = output_writer(&args.target_path)?;
-    let patch = input_bytes(&args.patch_path)?;
 
     // setup delta patcher
-    let mut bspatch = Bspatch::new(patch.as_slice())?;
-    if let Some(buffer_size) = args.buffer_size {
-        bspatch = bspatch.buffer_size(buffer_size);
+    let bspatch = patcher(&patch, args.buffer_size)?;
+
+    // execute delta patcher
+    let apply = Instant::now();
+    let mut source_sha256 = None;
+    let mut target_sha256 = None;
+    let (source_size, target_size) = if target_path == "-" {
+        let source = source_bytes(&source_path, args.decompress)?;
+        let mut target = Hashed::new(io::stdout(), args.json);
+        let size = bspatch.apply(source.as_slice(), &mut target).map_err(classify)?;
+        if args.json {
+            source_sha256 = Some(diagnostics::sha256(&source));
+            target_sha256 = Some(target.digest());
+        }
+        (source.len() as u64, size)
+    } else if source_path == "-" {
+        let source = source_bytes(&source_path, args.decompress)?;
+        let size = bspatch
+            .apply_to_path(source.as_slice(), &target_path)
+            .map_err(classify)?;
+        if args.json {
+            source_sha256 = Some(diagnostics::sha256(&source));
+        }
+        (source.len() as u64, size)
+    } else {
+        let size = bspatch
+            .decompress_source(args.decompress)
+            .apply_file(&source_path, &target_path)
+            .map_err(classify)?;
+        if args.decompress && args.json {
+            let source = InputFile::open_decompressed(&source_path)?;
+            source_sha256 = Some(diagnostics::sha256(&source));
+            (source.len() as u64, size)
+        } else {
+            (fs::metadata(&source_path)?.len(), size)
+        }
+    };
+    report.duration("apply", apply.elapsed());
+    report.duration("total", start.elapsed());
+    if !args.json {
+        return Ok(report);
+    }
+
+    // files are hashed once more
+    let source_sha256 = match source_sha256 {
+        Some(digest) => digest,
+        None => diagnostics::sha256_file(&source_path)?,
+    };
+    let target_sha256 = match target_sha256 {
+        Some(digest) => digest,
+        None => diagnostics::sha256_file(&target_path)?,
+    };
+    report.size("source_size", source_size);
+    report.size("target_size", target_size);
+    report.size("patch_size", patch.len() as u64);
+    report.text("source_sha256", &source_sha256);
+    report.text("target_sha256", &target_sha256);
+    report.text("patch_sha256", &diagnostics::sha256(&patch));
+    Ok(report)
+}
+
+/// Check the patch deeply, optionally against the source, and report the
+/// summary, printed unless `json`.
+fn check(patch_path: &str, source_path: Option<&str>, decompress: bool, json: bool) -> Result<Report, Failure> {
+    let start = Instant::now();
+    let mut report = Report::default();
+    if patch_path == "-" && source_path == Some("-") {
+        return Err(Failure::args("source and patch are both from stdin"));
+    }
+    let patch = input_bytes(patch_path)?;
+    let source = source_path.map(|path| source_bytes(path, decompress)).transpose()?;
+    let summary = inspect::validate(&patch, source.as_deref()).map_err(classify)?;
+    report.duration("check", start.elapsed());
+
+    let format = match summary.format {
+        Format::Classic => "classic",
+        Format::Extended => "extended",
+    };
+    let [control_size, delta_size, extra_size] = summary.section_sizes;
+    if !json {
+        println!("format:            {}", format);
+        println!("target size:       {}", summary.target_size);
+        println!("patch size:        {}", patch.len());
+        println!("controls:          {}", summary.controls);
+        println!("sections:          {} {} {}", control_size, delta_size, extra_size);
+        println!("source span:       {}", summary.source_span);
+        println!("section checksums: {}", summary.section_checksums);
+        println!("source checked:    {}", summary.source_checked);
+        return Ok(report);
+    }
+    report.text("format", format);
+    report.size("target_size", summary.target_size);
+    report.size("patch_size", patch.len() as u64);
+    report.size("controls", summary.controls);
+    report.size("control_size", control_size);
+    report.size("delta_size", delta_size);
+    report.size("extra_size", extra_size);
+    report.size("source_span", summary.source_span);
+    report.flag("section_checksums", summary.section_checksums);
+    report.flag("source_checked", summary.source_checked);
+    report.text("patch_sha256", &diagnostics::sha256(&patch));
+    Ok(report)
+}
+
+/// Create the patcher with the command line settings.
+fn patcher(patch: &[u8], buffer_size: Option<usize>) -> Result<Bspatch<'_>, Failure> {
+    let mut bspatch = Bspatch::new(patch)
+        .map_err(Failure::CorruptPatch)?
+        .vectored_writes(true);
+    if let Some(buffer_size) = buffer_size {
+        bspatch = bspatch.try_buffer_size(buffer_size).map_err(Failure::Args)?;
         bspatch = bspatch.delta_min(buffer_size / 4);
     }
+    Ok(bspatch)
+}
 
-    // execute delta patcher
-    bspatch.apply(source.as_slice(), target)?;
-    Ok(())
+/// Apply the patch `runs` times to a memory sink, report the throughput and
+/// peak memory (printed unless `json`), then write the target (replaced
+/// atomically as a regular apply does).
+fn bench(
+    source: &[u8],
+    target_path: &str,
+    patch: &[u8],
+    buffer_size: Option<usize>,
+    runs: u32,
+    json: bool,
+) -> Result<Report, Failure> {
+    if runs == 0 {
+        return Err(Failure::args("the number of bench runs must be positive"));
+    }
+    let mut report = Report::default();
+    // The target size in the header is not trusted, the reservation is only a
+    // bounded hint.
+    let hint = Ord::min(patcher(patch, buffer_size)?.hint_target_size(), MAX_BENCH_RESERVE);
+    let mut target = Vec::new();
+    let _ = target.try_reserve_exact(hint as usize);
+    let (mut total, mut slowest) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..runs {
+        target.clear();
+        let start = Instant::now();
+        patcher(patch, buffer_size)?
+            .apply(source, io::Cursor::new(&mut target))
+            .map_err(classify)?;
+        let elapsed = start.elapsed();
+        total += elapsed;
+        slowest = Ord::max(slowest, elapsed);
+    }
+    let size = target.len() as u64;
+    let throughput = |size: u64, time: Duration| size as f64 / Ord::max(time, Duration::from_nanos(1)).as_secs_f64();
+    let min_throughput = throughput(size, slowest);
+    let avg_throughput = throughput(size * runs as u64, total);
+    let peak_rss = peak_rss();
+
+    if target_path == "-" {
+        io::stdout().write_all(&target)?;
+        io::stdout().flush()?;
+    } else {
+        patcher(patch, buffer_size)?
+            .apply_to_path(source, target_path)
+            .map_err(classify)?;
+    }
+    report.duration("apply", total);
+
+    if !json {
+        let mut out: Box<dyn Write> = if target_path == "-" {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        };
+        writeln!(out, "runs:           {}", runs)?;
+        writeln!(out, "target size:    {}", size)?;
+        writeln!(out, "min throughput: {:.1} MiB/s", min_throughput / (1 << 20) as f64)?;
+        writeln!(out, "avg throughput: {:.1} MiB/s", avg_throughput / (1 << 20) as f64)?;
+        match peak_rss {
+            Some(rss) => writeln!(out, "peak rss:       {} KiB", rss >> 10)?,
+            None => writeln!(out, "peak rss:       unknown")?,
+        }
+        return Ok(report);
+    }
+    report.size("bench_runs", runs as u64);
+    report.size("target_size", size);
+    report.size("patch_size", patch.len() as u64);
+    report.ratio("min_throughput", min_throughput);
+    report.ratio("avg_throughput", avg_throughput);
+    if let Some(rss) = peak_rss {
+        report.size("peak_rss", rss);
+    }
+    Ok(report)
+}
+
+/// Classify errors of patching.
+fn classify(e: io::Error) -> Failure {
+    if SourceCorruption::of(&e).is_some() || e.kind() == io::ErrorKind::InvalidInput {
+        return Failure::SourceMismatch(e);
+    }
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Failure::CorruptPatch(e),
+        _ => Failure::Io(e),
+    }
+}
+
+fn source_bytes(path: &str, decompress: bool) -> io::Result<Vec<u8>> {
+    let data = input_bytes(path)?;
+    if decompress {
+        input::decompress(data)
+    } else {
+        Ok(data)
+    }
 }
 
 fn input_bytes(path: &str) -> io::Result<Vec<u8>> {
@@ -74,11 +314,3 @@ fn input_bytes(path: &str) -> io::Result<Vec<u8>> {
     }
     Ok(data)
 }
-
-fn output_writer(path: &str) -> io::Result<Box<dyn Write>> {
-    if path == "-" {
-        Ok(Box::new(io::stdout()))
-    } else {
-        Ok(Box::new(fs::File::create(path)?))
-    }
-}
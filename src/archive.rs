@@ -0,0 +1,323 @@
+#![forbid(unsafe_code)]
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+use byteorder::{ByteOrder, LE};
+
+use super::bspatch::Bspatch;
+use super::format::Header;
+
+/// Magic number bytes of delta archives.
+pub const ARCHIVE_MAGIC: &[u8] = b"QBSARCH1";
+
+/// Magic number bytes of the archive trailer.
+const TRAILER_MAGIC: &[u8] = b"QBSAIDX1";
+
+/// Size of the archive trailer.
+const TRAILER_SIZE: usize = 24;
+
+/// Size of the fixed part of index entries.
+const INDEX_ENTRY_SIZE: usize = 26;
+
+/// Writer of delta archives, a single append-only file holding patches of
+/// many targets, each retrievable by its target id.
+///
+/// The archive consists of magic `QBSARCH1`, followed by the patches and the
+/// index.
+/// The index consists of entries sorted by target id, each of the length of
+/// its id (u16), the offset, the size and the target size of the patch (u64
+/// each) and the UTF-8 id, followed by the trailer: the offset of the index
+/// and the entry count (u64 each), and magic `QBSAIDX1`.
+/// All integers are in little endian.
+///
+/// Appending writes the new patches and a new index after the previous
+/// trailer, which is never overwritten, thus readers of a consistent prefix
+/// of the file keep seeing the previous version of the archive.
+/// Adding a target id again supersedes the earlier patch, which is left in
+/// place.
+///
+/// Example:
+///
+/// Append the patches of a release to the archive of a product:
+/// ```no_run
+/// use std::fs::OpenOptions;
+/// use std::io;
+/// use qbsdiff::archive::ArchiveWriter;
+///
+/// fn publish(path: &str, patches: &[(String, Vec<u8>)]) -> io::Result<()> {
+///     let file = OpenOptions::new().read(true).write(true).open(path)?;
+///     let mut writer = ArchiveWriter::append(file)?;
+///     for (id, patch) in patches {
+///         writer.add(id, patch)?;
+///     }
+///     writer.finish()?.sync_all()
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ArchiveWriter<W: Write> {
+    inner: W,
+    pos: u64,
+    index: BTreeMap<String, (u64, u64, u64)>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Start a new empty archive.
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(ARCHIVE_MAGIC)?;
+        Ok(ArchiveWriter {
+            inner,
+            pos: ARCHIVE_MAGIC.len() as u64,
+            index: BTreeMap::new(),
+        })
+    }
+
+    /// Write the patch of target `id`, superseding the earlier one if any.
+    ///
+    /// Return error if the id is empty or too long, or the patch header is
+    /// invalid.
+    pub fn add(&mut self, id: &str, patch: &[u8]) -> Result<()> {
+        if id.is_empty() || id.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid archive entry id"));
+        }
+        let header = Header::parse(patch)?;
+        self.inner.write_all(patch)?;
+        self.index
+            .insert(id.to_string(), (self.pos, patch.len() as u64, header.tsize));
+        self.pos += patch.len() as u64;
+        Ok(())
+    }
+
+    /// Number of entries, including those of the archive appended to.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check whether the archive is empty.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Write the index, then flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let mut index = Vec::new();
+        for (id, &(offset, size, tsize)) in self.index.iter() {
+            let mut entry = [0; INDEX_ENTRY_SIZE];
+            LE::write_u16(&mut entry[0..2], id.len() as u16);
+            LE::write_u64(&mut entry[2..10], offset);
+            LE::write_u64(&mut entry[10..18], size);
+            LE::write_u64(&mut entry[18..26], tsize);
+            index.extend_from_slice(&entry[..]);
+            index.extend_from_slice(id.as_bytes());
+        }
+        let mut trailer = [0; TRAILER_SIZE];
+        LE::write_u64(&mut trailer[0..8], self.pos);
+        LE::write_u64(&mut trailer[8..16], self.index.len() as u64);
+        trailer[16..24].copy_from_slice(TRAILER_MAGIC);
+        self.inner.write_all(&index[..])?;
+        self.inner.write_all(&trailer[..])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Read + Write + Seek> ArchiveWriter<W> {
+    /// Append to an existing archive, keeping its entries.
+    ///
+    /// Return error if the archive is corrupted.
+    pub fn append(mut inner: W) -> Result<Self> {
+        let end = inner.seek(SeekFrom::End(0))?;
+        if end < (ARCHIVE_MAGIC.len() + TRAILER_SIZE) as u64 {
+            return Err(corrupted());
+        }
+        let mut magic = [0; 8];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut magic[..])?;
+        let mut trailer = [0; TRAILER_SIZE];
+        inner.seek(SeekFrom::Start(end - TRAILER_SIZE as u64))?;
+        inner.read_exact(&mut trailer[..])?;
+        let (offset, count) = parse_trailer(&magic[..], &trailer[..], end)?;
+
+        let mut data = Vec::new();
+        inner.seek(SeekFrom::Start(offset))?;
+        (&mut inner)
+            .take(end - TRAILER_SIZE as u64 - offset)
+            .read_to_end(&mut data)?;
+        let index = parse_index(&data[..], count, offset)?
+            .into_iter()
+            .map(|(id, offset, size, tsize)| (id.to_string(), (offset, size, tsize)))
+            .collect();
+        inner.seek(SeekFrom::End(0))?;
+        Ok(ArchiveWriter { inner, pos: end, index })
+    }
+}
+
+/// Parsed delta archive, see `ArchiveWriter` for the layout.
+///
+/// Only the index is parsed, patches are sliced on access, thus parsing a
+/// memory mapped archive of any size only touches its index.
+///
+/// Example:
+///
+/// Serve the patch of a target:
+/// ```
+/// use std::io;
+/// use qbsdiff::archive::Archive;
+///
+/// fn serve(archive: &[u8], id: &str, source: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut target = Vec::new();
+///     Archive::parse(archive)?.apply(id, source, io::Cursor::new(&mut target))?;
+///     Ok(target)
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Archive<'a> {
+    entries: Vec<ArchiveEntry<'a>>,
+}
+
+impl<'a> Archive<'a> {
+    /// Parse the index of the delta archive.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let end = data.len() as u64;
+        if data.len() < ARCHIVE_MAGIC.len() + TRAILER_SIZE {
+            return Err(corrupted());
+        }
+        let trailer = &data[data.len() - TRAILER_SIZE..];
+        let (offset, count) = parse_trailer(&data[..ARCHIVE_MAGIC.len()], trailer, end)?;
+        let index = &data[offset as usize..data.len() - TRAILER_SIZE];
+        let entries = parse_index(index, count, offset)?
+            .into_iter()
+            .map(|(id, offset, size, tsize)| ArchiveEntry {
+                id,
+                offset,
+                target_size: tsize,
+                patch: &data[offset as usize..(offset + size) as usize],
+            })
+            .collect();
+        Ok(Archive { entries })
+    }
+
+    /// Get all entries in the archive, sorted by target id.
+    pub fn entries(&self) -> &[ArchiveEntry<'a>] {
+        &self.entries[..]
+    }
+
+    /// Find the entry of target `id`.
+    pub fn get(&self, id: &str) -> Option<&ArchiveEntry<'a>> {
+        self.entries
+            .binary_search_by(|e| e.id.cmp(id))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the archive is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Apply the patch of target `id` to the source data and output the
+    /// stream of target.
+    ///
+    /// Return `ErrorKind::NotFound` if there is no such target.
+    /// The target data size would be returned if no error occurs.
+    pub fn apply<T: Write>(&self, id: &str, source: &[u8], target: T) -> Result<u64> {
+        let entry = self
+            .get(id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no such archive entry"))?;
+        Bspatch::new(entry.patch)?.apply(source, target)
+    }
+}
+
+/// Entry of delta archives.
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry<'a> {
+    id: &'a str,
+    offset: u64,
+    target_size: u64,
+    patch: &'a [u8],
+}
+
+impl<'a> ArchiveEntry<'a> {
+    /// Target id.
+    pub fn id(&self) -> &'a str {
+        self.id
+    }
+
+    /// Offset of the patch in the archive, e.g. to serve it by range
+    /// requests.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the patch.
+    pub fn patch_size(&self) -> u64 {
+        self.patch.len() as u64
+    }
+
+    /// Size of the target.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Get the patch data.
+    pub fn patch(&self) -> &'a [u8] {
+        self.patch
+    }
+}
+
+/// Check the magic numbers, and return the offset of index and the entry
+/// count in the trailer.
+fn parse_trailer(magic: &[u8], trailer: &[u8], end: u64) -> Result<(u64, u64)> {
+    if magic != ARCHIVE_MAGIC || &trailer[16..24] != TRAILER_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a valid delta archive"));
+    }
+    let offset = LE::read_u64(&trailer[0..8]);
+    let count = LE::read_u64(&trailer[8..16]);
+    if offset < ARCHIVE_MAGIC.len() as u64 || offset > end - TRAILER_SIZE as u64 {
+        return Err(corrupted());
+    }
+    Ok((offset, count))
+}
+
+/// Parse the index entries of patches before `end`: id, offset, size and
+/// target size.
+fn parse_index(mut index: &[u8], count: u64, end: u64) -> Result<Vec<(&str, u64, u64, u64)>> {
+    let mut entries: Vec<(&str, u64, u64, u64)> =
+        Vec::with_capacity(Ord::min(count, (index.len() / INDEX_ENTRY_SIZE) as u64) as usize);
+    for _ in 0..count {
+        if index.len() < INDEX_ENTRY_SIZE {
+            return Err(corrupted());
+        }
+        let (entry, rest) = index.split_at(INDEX_ENTRY_SIZE);
+        let len = LE::read_u16(&entry[0..2]) as usize;
+        if len == 0 || rest.len() < len {
+            return Err(corrupted());
+        }
+        let (id, rest) = rest.split_at(len);
+        index = rest;
+
+        let id = std::str::from_utf8(id).map_err(|_| corrupted())?;
+        let offset = LE::read_u64(&entry[2..10]);
+        let size = LE::read_u64(&entry[10..18]);
+        let tsize = LE::read_u64(&entry[18..26]);
+        let sorted = entries.last().is_none_or(|last| last.0 < id);
+        let within = offset >= ARCHIVE_MAGIC.len() as u64 && offset.checked_add(size).is_some_and(|e| e <= end);
+        if !sorted || !within {
+            return Err(corrupted());
+        }
+        entries.push((id, offset, size, tsize));
+    }
+    if !index.is_empty() {
+        return Err(corrupted());
+    }
+    Ok(entries)
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "delta archive corrupted")
+}
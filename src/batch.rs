@@ -0,0 +1,110 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::bsdiff::{available_threads, Bsdiff, ParallelScheme, MIN_CHUNK};
+use super::profile::DiffProfile;
+
+/// Delta compression job of `diff_many`.
+pub struct Job<'s, 't, P> {
+    /// Source data.
+    pub source: &'s [u8],
+
+    /// Target data.
+    pub target: &'t [u8],
+
+    /// Sink of the patch.
+    pub patch: P,
+}
+
+impl<'s, 't, P: Write> Job<'s, 't, P> {
+    /// Create a new job.
+    pub fn new(source: &'s [u8], target: &'t [u8], patch: P) -> Self {
+        Job { source, target, patch }
+    }
+
+    /// Whether the job is too small to be split into parallel chunks.
+    fn is_small(&self) -> bool {
+        self.target.len() < 2 * MIN_CHUNK
+    }
+}
+
+/// Generate many patches with the default settings, see `diff_many_with`.
+pub fn diff_many<P: Write + Send>(jobs: Vec<Job<'_, '_, P>>) -> Vec<Result<u64>> {
+    diff_many_with(jobs, &DiffProfile::default())
+}
+
+/// Generate many patches, sharing one thread pool among the jobs.
+///
+/// Jobs are claimed by a bounded number of workers, the largest targets
+/// first.
+/// Small jobs run single-threaded side by side, while large jobs are split
+/// into parallel chunks, which idle workers help with.
+/// This keeps all threads busy throughout a batch dominated by either small
+/// or large files, unlike looping `Bsdiff::compare` over the jobs.
+///
+/// The parallel scheme of the profile is replaced by the per-job decisions,
+/// unless it is `ParallelScheme::Never`.
+///
+/// Example:
+///
+/// Generate the patches of a release:
+/// ```
+/// use std::io;
+/// use qbsdiff::batch::{diff_many, Job};
+///
+/// fn release(files: &[(Vec<u8>, Vec<u8>)]) -> io::Result<Vec<Vec<u8>>> {
+///     let mut patches = vec![Vec::new(); files.len()];
+///     let jobs = files
+///         .iter()
+///         .zip(patches.iter_mut())
+///         .map(|((source, target), patch)| Job::new(source, target, io::Cursor::new(patch)))
+///         .collect();
+///     for result in diff_many(jobs) {
+///         result?;
+///     }
+///     Ok(patches)
+/// }
+/// ```
+///
+/// The result of each job (size of the patch or error) is returned in the
+/// order of the jobs.
+pub fn diff_many_with<P: Write + Send>(jobs: Vec<Job<'_, '_, P>>, profile: &DiffProfile) -> Vec<Result<u64>> {
+    let mut order: Vec<usize> = (0..jobs.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(jobs[i].target.len()));
+    let jobs: Vec<Mutex<Option<Job<P>>>> = jobs.into_iter().map(|job| Mutex::new(Some(job))).collect();
+    let results: Vec<Mutex<Option<Result<u64>>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+
+    let next = AtomicUsize::new(0);
+    let worker = || loop {
+        let n = next.fetch_add(1, Ordering::Relaxed);
+        if n >= order.len() {
+            break;
+        }
+        let i = order[n];
+        let job = jobs[i].lock().unwrap().take().unwrap();
+        let scheme = if job.is_small() || profile.parallel_scheme == ParallelScheme::Never {
+            ParallelScheme::Never
+        } else {
+            ParallelScheme::Auto
+        };
+        let result = Bsdiff::try_new(job.source, job.target)
+            .and_then(|bsdiff| bsdiff.profile(profile).parallel_scheme(scheme).compare(job.patch));
+        *results[i].lock().unwrap() = Some(result);
+    };
+    #[cfg(feature = "parallel")]
+    rayon::scope(|scope| {
+        for _ in 0..Ord::min(available_threads(), order.len()) {
+            scope.spawn(|_| worker());
+        }
+    });
+    #[cfg(not(feature = "parallel"))]
+    worker();
+
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().unwrap())
+        .collect()
+}
@@ -1,14 +1,31 @@
 #![forbid(unsafe_code)]
 
-use std::io::{Cursor, Result, Write};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::iter;
+use std::mem;
 use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
 
-use bzip2::write::BzEncoder;
-use bzip2::Compression;
-use rayon::prelude::*;
-use suffix_array::SuffixArray;
+#[cfg(feature = "async")]
+use futures_util::io::{AsyncWrite, AsyncWriteExt};
 pub use suffix_array::MAX_LENGTH;
 
+pub use super::utils::Control;
+
+use super::bspatch;
+use super::checksum::ChecksumKind;
+use super::codec::{Codec, CodecPriority, SectionEncoder};
+use super::dedupe::dedupe;
+use super::format::{Format, Header, Section, MAX_BLOCK_SIZE};
+use super::index::{SourceIndex, SIMILARITY_INTERVAL};
+use super::inspect::{self, RegionKind};
+use super::lines::{self, Piece};
+use super::profile::DiffProfile;
+use super::report::{ControlStats, DiffReport, MatchHistogram};
+use super::sidecar::Sidecar;
 use super::utils::*;
 
 /// Default threshold to determine small exact match.
@@ -28,22 +45,112 @@ pub const COMPRESSION_LEVEL: u32 = 6;
 
 /// Min chunk size of each parallel job, used internally in
 /// `ParallelScheme::Auto`.
-const MIN_CHUNK: usize = 256 * 1024;
+pub(crate) const MIN_CHUNK: usize = 256 * 1024;
+
+/// Number of parallel jobs per thread preferred by `ParallelScheme::Auto`,
+/// for better load balancing.
+const JOBS_PER_THREAD: usize = 4;
+
+/// Size of target pieces aligned by a single lookup once the searching work
+/// exceeds `Bsdiff::work_limit`.
+const FALLBACK_PIECE: usize = 4096;
+
+/// Min length of changed lines searched byte by byte in line-aware mode.
+const LINE_SEARCH: usize = 256;
+
+/// Size of the sliding dictionary before the common prefix ends, see
+/// `Bsdiff::append_mostly`.
+const APPEND_WINDOW: usize = 1 << 20;
+
+/// Number of entries in the bucket table of suffix array.
+const BUCKETS: usize = 256 * 256 + 1;
+
+/// Default memory limit of the source index, see `Bsdiff::memory_limit`.
+#[cfg(target_pointer_width = "32")]
+pub const MEMORY_LIMIT: Option<usize> = Some(1 << 30);
+
+/// Default memory limit of the source index, see `Bsdiff::memory_limit`.
+#[cfg(not(target_pointer_width = "32"))]
+pub const MEMORY_LIMIT: Option<usize> = None;
+
+/// Min size of source windows indexed under memory limits.
+const MIN_SOURCE_WINDOW: usize = 1 << 16;
+
+/// Number and size of target windows sampled for the entropy probe.
+const ENTROPY_PROBES: usize = 16;
+const ENTROPY_PROBE_SIZE: usize = 4096;
+
+/// Settings affecting the controls searched, recorded by sidecars.
+const SEARCH_SETTINGS: [&str; 12] = [
+    "version",
+    "small_match",
+    "mismatch_count",
+    "scoring",
+    "work_limit",
+    "parallel_scheme",
+    "chunk_size",
+    "line_aware",
+    "anchors",
+    "hints",
+    "append_mostly",
+    "memory_limit",
+];
+
+/// Scoring function of similar bytes, see `Bsdiff::scoring`.
+pub type Scoring = dyn Fn(u8, u8) -> isize + Send + Sync;
+
+/// Finder of anchors in data, see `Bsdiff::anchors`.
+pub type AnchorFinder = dyn Fn(&[u8]) -> Vec<usize> + Send + Sync;
+
+/// Hook of large allocations, see `Bsdiff::allocation_hook`.
+pub type AllocationHook = dyn Fn(Allocation) -> Result<()> + Send + Sync;
+
+/// Large allocation of delta compression, with its size in bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Allocation {
+    /// Suffix array of the source, about 4 bytes per source byte.
+    SuffixArray(usize),
 
-/// Default chunk size of each parallel job, used internally in
-/// `ParallelScheme::Auto`.
-const DEFAULT_CHUNK: usize = 512 * 1024;
+    /// Bucket table of the suffix array.
+    Buckets(usize),
+
+    /// Controls of a parallel chunk buffered until all the earlier chunks are
+    /// packed, or controls of line-aware matching before packed.
+    Controls(usize),
+
+    /// Target window buffered by `compare_reader`.
+    Window(usize),
 
-/// Magic number bytes of bsdiff 4.x patch files.
-const BSDIFF4_MAGIC: &[u8] = b"BSDIFF40";
+    /// Patch assembled by `compare_to_vec`.
+    Patch(usize),
+}
+
+impl Allocation {
+    /// Size of the allocation in bytes.
+    pub fn size(&self) -> usize {
+        match *self {
+            Allocation::SuffixArray(size)
+            | Allocation::Buckets(size)
+            | Allocation::Controls(size)
+            | Allocation::Window(size)
+            | Allocation::Patch(size) => size,
+        }
+    }
+}
 
 /// Parallel searching scheme of bsdiff.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParallelScheme {
     /// Never search in parallel.
     Never,
 
     /// Automatically determine parallel scheme.
+    ///
+    /// The chunk size is chosen by the available parallelism, the sizes of
+    /// source and target, and the entropy of sampled target data (highly
+    /// redundant data prefers larger chunks to keep long matches).
+    /// The decision is exposed by `DiffReport`.
     Auto,
 
     /// Each parallel job works on a chunk no large than given size.
@@ -52,10 +159,126 @@ pub enum ParallelScheme {
     /// larger chunk size to avoid bad quality of patch.
     ChunkSize(usize),
 
-    /// Run no more than `N` parallel jobs.
+    /// Run no more than `N` parallel workers.
+    ///
+    /// The target is split into a few chunks per worker, which are claimed by
+    /// idle workers one by one, so that a slow chunk would not hold up the
+    /// others.
     NumJobs(usize),
 }
 
+/// Controls searched in a parallel chunk of target, see
+/// `Bsdiff::search_chunks`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkControls {
+    /// Range of the chunk in target.
+    pub range: Range<usize>,
+
+    /// Controls covering the chunk.
+    ///
+    /// The source cursor starts at zero, and is reset to zero by the last
+    /// control, thus chunks could be concatenated in any order.
+    pub controls: Vec<Control>,
+}
+
+/// Built-in thresholds and defaults of delta compression and patching, see
+/// `tuning_info`.
+///
+/// Example:
+///
+/// Sweep `small_match` around its default:
+/// ```
+/// use qbsdiff::{tuning_info, Bsdiff};
+///
+/// fn sweep(source: &[u8], target: &[u8]) -> Vec<(usize, usize)> {
+///     let default = tuning_info().small_match;
+///     (default / 2..=default * 2)
+///         .map(|small_match| {
+///             let patch = Bsdiff::new(source, target).small_match(small_match).compare_to_vec();
+///             (small_match, patch.map_or(usize::MAX, |p| p.len()))
+///         })
+///         .collect()
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TuningInfo {
+    /// Default threshold of small exact matches, see `Bsdiff::small_match`.
+    pub small_match: usize,
+
+    /// Number of mismatched bytes ending the extension of a match.
+    pub mismatch_count: usize,
+
+    /// Length of suffixes after the previous match that are binary searched
+    /// instead of scanned byte by byte.
+    pub long_suffix: usize,
+
+    /// Min chunk size of parallel jobs, see `Bsdiff::parallel_scheme`.
+    pub min_chunk: usize,
+
+    /// Number of parallel jobs per thread preferred by
+    /// `ParallelScheme::Auto`.
+    pub jobs_per_thread: usize,
+
+    /// Number of threads available for parallel jobs, only one without
+    /// feature `parallel`.
+    pub threads: usize,
+
+    /// Size of target pieces aligned by a single lookup once the searching
+    /// work exceeds `Bsdiff::work_limit`.
+    pub fallback_piece: usize,
+
+    /// Size of the sliding dictionary before the common prefix ends, see
+    /// `Bsdiff::append_mostly`.
+    pub append_window: usize,
+
+    /// Default memory limit of the source index on this platform, see
+    /// `Bsdiff::memory_limit`.
+    pub memory_limit: Option<usize>,
+
+    /// Min size of source windows indexed under memory limits.
+    pub min_source_window: usize,
+
+    /// Max size of source data, see `Bsdiff::new`.
+    pub max_source_size: usize,
+
+    /// Default buffer size of delta calculation, see `Bsdiff::buffer_size`.
+    pub diff_buffer_size: usize,
+
+    /// Default compression level, see `Bsdiff::compression_level`.
+    pub compression_level: u32,
+
+    /// Default buffer size of patching, see `Bspatch::buffer_size`.
+    pub patch_buffer_size: usize,
+
+    /// Default size of the delta cache of patching, see
+    /// `Bspatch::delta_min`.
+    pub delta_min: usize,
+}
+
+/// Get the built-in thresholds and defaults effective in this build, so that
+/// tuners and UIs do not hard-code copies of them.
+pub fn tuning_info() -> TuningInfo {
+    TuningInfo {
+        small_match: SMALL_MATCH,
+        mismatch_count: MISMATCH_COUNT,
+        long_suffix: LONG_SUFFIX,
+        min_chunk: MIN_CHUNK,
+        jobs_per_thread: JOBS_PER_THREAD,
+        threads: available_threads(),
+        fallback_piece: FALLBACK_PIECE,
+        append_window: APPEND_WINDOW,
+        memory_limit: MEMORY_LIMIT,
+        min_source_window: MIN_SOURCE_WINDOW,
+        max_source_size: MAX_LENGTH,
+        diff_buffer_size: BUFFER_SIZE,
+        compression_level: COMPRESSION_LEVEL,
+        patch_buffer_size: bspatch::BUFFER_SIZE,
+        delta_min: bspatch::DELTA_MIN,
+    }
+}
+
 /// Fast and memory saving bsdiff 4.x compatible delta compressor for
 /// executables.
 ///
@@ -79,21 +302,66 @@ pub enum ParallelScheme {
 ///     Ok(patch)
 /// }
 /// ```
+///
+/// Produce the patch data in extended format with uncompressed sections, which
+/// is much faster to apply (but not compatible with bspatch):
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bsdiff, Codec, Format};
+///
+/// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut patch = Vec::new();
+///     Bsdiff::new(source, target)
+///         .format(Format::Extended)
+///         .codec(Codec::Stored)
+///         .compare(io::Cursor::new(&mut patch))?;
+///     Ok(patch)
+/// }
+/// ```
 pub struct Bsdiff<'s, 't> {
     source: &'s [u8],
     target: &'t [u8],
+    index: Option<&'s SourceIndex<'s>>,
     parallel_scheme: ParallelScheme,
     small_match: usize,
     mismatch_count: usize,
     long_suffix: usize,
+    scoring: Option<Arc<Scoring>>,
+    allocation_hook: Option<Arc<AllocationHook>>,
+    memory_limit: Option<usize>,
+    work_limit: Option<usize>,
+    analyze: bool,
+    target_copy: bool,
+    compact_seek: bool,
+    decoded_sizes: bool,
+    explicit_sizes: bool,
+    block_size: usize,
+    max_add: usize,
+    max_copy: usize,
+    micro: usize,
+    dedupe: bool,
+    line_aware: bool,
+    anchors: Option<Arc<AnchorFinder>>,
+    hints: Arc<[(usize, usize)]>,
+    append_mostly: bool,
+    full_fallback: Option<f64>,
+    metadata: bool,
+    source_checksum: usize,
+    checksum: Option<ChecksumKind>,
     buffer_size: usize,
-    compression_level: Compression,
+    format: Format,
+    codec: Codec,
+    compression_level: u32,
+    section_codecs: [Option<(Codec, u32)>; 3],
+    auto_levels: bool,
+    auto_codec: Option<CodecPriority>,
 }
 
 impl<'s, 't> Bsdiff<'s, 't> {
     /// Create new configuration for bsdiff delta compression.
     ///
-    /// Panics if the length of source data is greater than MAX_LENGTH.
+    /// Panics if the length of source data is greater than MAX_LENGTH, see
+    /// `try_new` for the fallible variant.
     pub fn new(source: &'s [u8], target: &'t [u8]) -> Self {
         if source.len() > MAX_LENGTH {
             panic!("source data is too large to be indexed");
@@ -102,18 +370,79 @@ impl<'s, 't> Bsdiff<'s, 't> {
         Bsdiff {
             source,
             target,
-            parallel_scheme: ParallelScheme::Auto,
+            index: None,
+            parallel_scheme: if cfg!(feature = "parallel") {
+                ParallelScheme::Auto
+            } else {
+                ParallelScheme::Never
+            },
             small_match: SMALL_MATCH,
             mismatch_count: MISMATCH_COUNT,
             long_suffix: LONG_SUFFIX,
-            compression_level: Compression::new(COMPRESSION_LEVEL),
+            scoring: None,
+            allocation_hook: None,
+            memory_limit: MEMORY_LIMIT,
+            work_limit: None,
+            analyze: false,
+            target_copy: false,
+            compact_seek: false,
+            decoded_sizes: false,
+            explicit_sizes: false,
+            block_size: 0,
+            max_add: 0,
+            max_copy: 0,
+            micro: 0,
+            dedupe: false,
+            line_aware: false,
+            anchors: None,
+            hints: Arc::from([]),
+            append_mostly: false,
+            full_fallback: None,
+            metadata: false,
+            source_checksum: 0,
+            checksum: None,
+            compression_level: COMPRESSION_LEVEL,
             buffer_size: BUFFER_SIZE,
+            format: Format::Classic,
+            codec: Codec::Bzip2,
+            section_codecs: [None; 3],
+            auto_levels: false,
+            auto_codec: None,
         }
     }
 
+    /// Create new configuration for bsdiff delta compression, like `new` but
+    /// returning `ErrorKind::InvalidInput` if the length of source data is
+    /// greater than MAX_LENGTH.
+    pub fn try_new(source: &'s [u8], target: &'t [u8]) -> Result<Self> {
+        if source.len() > MAX_LENGTH {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source data is too large to be indexed",
+            ));
+        }
+        Ok(Bsdiff::new(source, target))
+    }
+
+    /// Create new configuration for bsdiff delta compression, reusing the
+    /// prebuilt index of source data.
+    pub fn with_index(index: &'s SourceIndex<'s>, target: &'t [u8]) -> Self {
+        let mut bsdiff = Bsdiff::new(index.source(), target);
+        bsdiff.index = Some(index);
+        bsdiff
+    }
+
     /// Set the source data.
+    ///
+    /// The prebuilt index of `with_index` would be dropped.
+    ///
+    /// Panics if the length of source data is greater than MAX_LENGTH.
     pub fn source(mut self, source: &'s [u8]) -> Self {
+        if source.len() > MAX_LENGTH {
+            panic!("source data is too large to be indexed");
+        }
         self.source = source;
+        self.index = None;
         self
     }
 
@@ -123,6 +452,48 @@ impl<'s, 't> Bsdiff<'s, 't> {
         self
     }
 
+    /// Copy the configuration with another target data.
+    pub(crate) fn retarget<'u>(&self, target: &'u [u8]) -> Bsdiff<'s, 'u> {
+        Bsdiff {
+            source: self.source,
+            target,
+            index: self.index,
+            parallel_scheme: self.parallel_scheme,
+            small_match: self.small_match,
+            mismatch_count: self.mismatch_count,
+            long_suffix: self.long_suffix,
+            scoring: self.scoring.clone(),
+            allocation_hook: self.allocation_hook.clone(),
+            memory_limit: self.memory_limit,
+            work_limit: self.work_limit,
+            analyze: self.analyze,
+            target_copy: self.target_copy,
+            compact_seek: self.compact_seek,
+            decoded_sizes: self.decoded_sizes,
+            explicit_sizes: self.explicit_sizes,
+            block_size: self.block_size,
+            max_add: self.max_add,
+            max_copy: self.max_copy,
+            micro: self.micro,
+            dedupe: self.dedupe,
+            line_aware: self.line_aware,
+            anchors: self.anchors.clone(),
+            hints: self.hints.clone(),
+            append_mostly: self.append_mostly,
+            full_fallback: self.full_fallback,
+            metadata: self.metadata,
+            source_checksum: self.source_checksum,
+            checksum: self.checksum,
+            buffer_size: self.buffer_size,
+            format: self.format,
+            codec: self.codec,
+            compression_level: self.compression_level,
+            section_codecs: self.section_codecs,
+            auto_levels: self.auto_levels,
+            auto_codec: self.auto_codec,
+        }
+    }
+
     /// Set parallel searching scheme (default is `ParallelScheme::Never`).
     /// Chunk size or thread number should not be zero, or it would
     /// automatically choose a proper number instead.
@@ -130,15 +501,45 @@ impl<'s, 't> Bsdiff<'s, 't> {
     /// Considering that small chunk size of each parallel job may lead to bad
     /// patch quality, the chunk size is forced to be no less than 256 KiB
     /// internally.
+    ///
+    /// Without feature `parallel`, any scheme is degraded to `Never`, and no
+    /// threads are ever spawned.
     pub fn parallel_scheme(mut self, mut parallel_scheme: ParallelScheme) -> Self {
         use ParallelScheme::*;
-        if parallel_scheme == ChunkSize(0) || parallel_scheme == NumJobs(0) {
+        if !cfg!(feature = "parallel") {
+            parallel_scheme = Never;
+        } else if parallel_scheme == ChunkSize(0) || parallel_scheme == NumJobs(0) {
             parallel_scheme = Auto;
         }
         self.parallel_scheme = parallel_scheme;
         self
     }
 
+    /// Set parallel searching scheme, like `parallel_scheme` but rejecting
+    /// zero chunk size or thread number with `ErrorKind::InvalidInput`.
+    pub fn try_parallel_scheme(self, parallel_scheme: ParallelScheme) -> Result<Self> {
+        use ParallelScheme::*;
+        if matches!(parallel_scheme, ChunkSize(0) | NumJobs(0)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "parallel chunk size or jobs must be positive",
+            ));
+        }
+        Ok(self.parallel_scheme(parallel_scheme))
+    }
+
+    /// Get the effective parallel searching scheme, with chunk size raised to
+    /// the internal minimum.
+    ///
+    /// The chunk size chosen by `ParallelScheme::Auto` depends on the inputs,
+    /// see `DiffReport`.
+    pub fn effective_parallel_scheme(&self) -> ParallelScheme {
+        match self.parallel_scheme {
+            ParallelScheme::ChunkSize(chunk) => ParallelScheme::ChunkSize(Ord::max(chunk, MIN_CHUNK)),
+            scheme => scheme,
+        }
+    }
+
     /// Set the threshold to determine small match (default is `SMALL_MATCH`).
     /// If set to zero, no matches would be skipped.
     pub fn small_match(mut self, small_match: usize) -> Self {
@@ -172,16 +573,632 @@ impl<'s, 't> Bsdiff<'s, 't> {
         self
     }
 
-    /// Set the compression level of bzip2 (in range `0..=9`, default is `COMPRESSION_LEVEL`).
+    /// Set the scoring function for extending exact matches with similar bytes
+    /// (default scores `1` for equal bytes and `-1` otherwise).
+    ///
+    /// The function is called with a target byte and a source byte, and
+    /// similar regions are extended as long as the accumulated score grows.
+    /// This is useful to weight meaningless bytes lower, e.g. the padding of
+    /// firmware images:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let mut patch = Vec::new();
+    ///     Bsdiff::new(source, target)
+    ///         .scoring(|t, s| match (t == s, t) {
+    ///             (true, 0x00 | 0xff) => 0,
+    ///             (true, _) => 1,
+    ///             (false, _) => -1,
+    ///         })
+    ///         .compare(io::Cursor::new(&mut patch))?;
+    ///     Ok(patch)
+    /// }
+    /// ```
+    pub fn scoring<F>(mut self, scoring: F) -> Self
+    where
+        F: Fn(u8, u8) -> isize + Send + Sync + 'static,
+    {
+        self.scoring = Some(Arc::new(scoring));
+        self
+    }
+
+    /// Set the hook called before each large allocation (default is none).
+    ///
+    /// The hook is given the kind and size of the allocation, which lets
+    /// embedders with custom allocators or memory pools track the memory of
+    /// delta compression precisely, and bound it by returning an error, which
+    /// aborts the delta compression with that error.
+    /// The index of source is only reported if built by `Bsdiff` (i.e. not
+    /// given by `with_index`), and the controls are reported once collected.
+    /// The hook is consulted by `compare`, `compare_report`, `compare_to_vec`,
+    /// `compare_reader` and `splice`.
+    ///
+    /// Example:
+    ///
+    /// Bound the large allocations to 1 GiB in total:
+    /// ```
+    /// use std::io;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let used = AtomicUsize::new(0);
+    ///     Bsdiff::new(source, target)
+    ///         .allocation_hook(move |alloc| {
+    ///             if used.fetch_add(alloc.size(), Ordering::Relaxed) + alloc.size() > 1 << 30 {
+    ///                 return Err(io::Error::new(io::ErrorKind::OutOfMemory, "memory budget exceeded"));
+    ///             }
+    ///             Ok(())
+    ///         })
+    ///         .compare_to_vec()
+    /// }
+    /// ```
+    pub fn allocation_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Allocation) -> Result<()> + Send + Sync + 'static,
+    {
+        self.allocation_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Bound the memory of the source index to `limit` bytes (default is
+    /// `MEMORY_LIMIT`, i.e. 1 GiB on 32-bit platforms and unbounded on
+    /// others).
+    ///
+    /// The index takes 4 bytes per source byte, thus the index of a large
+    /// source might not fit in the address space of 32-bit platforms, or the
+    /// memory of embedded devices.
+    /// Sources whose index exceeds the limit are indexed window by window
+    /// instead: the target is split into chunks of half the window size, and
+    /// each chunk is searched against the source window around the
+    /// proportional position, which is a best effort for inputs of similar layout (e.g.
+    /// firmware images), matches out of the window are lost.
+    /// Windows are no smaller than 64 KiB.
+    ///
+    /// Prebuilt indexes (see `with_index`), `line_aware`, `anchors` and
+    /// `append_mostly` are not limited.
+    pub fn memory_limit(mut self, limit: Option<usize>) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Bound the searching work to `factor` steps per target byte (`factor >= 1`,
+    /// default is unbounded).
+    ///
+    /// Steps are counted deterministically (suffix array lookups weighted by
+    /// the match lengths, plus bytes scanned), so the produced patch does not
+    /// depend on the machine load.
+    /// Once a chunk of target exceeds its budget, the rest of the chunk is
+    /// split into small pieces, each aligned by a single bounded lookup and
+    /// emitted as delta data (or extra data if nothing matches).
+    /// Thus pathological inputs are searched in time linear to the target size,
+    /// at the cost of patch size.
+    /// Regular inputs usually take less than 16 steps per byte.
+    pub fn work_limit(mut self, factor: usize) -> Self {
+        self.work_limit = Some(Ord::max(factor, 1));
+        self
+    }
+
+    /// Bound the searching work, like `work_limit` but rejecting zero factor
+    /// with `ErrorKind::InvalidInput`.
+    pub fn try_work_limit(self, factor: usize) -> Result<Self> {
+        if factor == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "work limit must be positive"));
+        }
+        Ok(self.work_limit(factor))
+    }
+
+    /// Get the effective searching steps per target byte, `None` if unbounded.
+    pub fn effective_work_limit(&self) -> Option<usize> {
+        self.work_limit
+    }
+
+    /// Enable analysis of the generated controls (default is disabled).
+    ///
+    /// Suspicious patterns are flagged in `DiffReport::anomalies`, e.g. most
+    /// of the target emitted as extra data despite high similarity to the
+    /// source, which usually indicates a regression of the matcher.
+    /// The analysis estimates the similarity with the source index, thus it
+    /// costs a bit more searching.
+    pub fn analyze(mut self, analyze: bool) -> Self {
+        self.analyze = analyze;
+        self
+    }
+
+    /// Enable target-relative copies (default is disabled).
+    ///
+    /// Controls are extended with copies from the target bytes already
+    /// produced, which might overlap the bytes being produced (like
+    /// self-referential copies of VCDIFF).
+    /// Runs of a short period in extra data (e.g. zero fills or repeated small
+    /// structures) are rewritten as such copies, see also `dedupe`.
+    ///
+    /// Target-relative copies require `Format::Extended`, otherwise `compare`
+    /// would fail.
+    /// Patchers have to keep the recent target bytes, up to the farthest
+    /// distance of copies.
+    pub fn target_copy(mut self, target_copy: bool) -> Self {
+        self.target_copy = target_copy;
+        self
+    }
+
+    /// Encode the seeks of controls compactly (default is disabled).
+    ///
+    /// Seeks are already relative to the source position expected after the
+    /// delta data, and mostly small corrections, thus they are encoded as
+    /// zig-zag varints of one or two bytes rather than 8-byte integers, which
+    /// shrinks the control section significantly for binaries with many small
+    /// displacements.
+    ///
+    /// Compact seeks require `Format::Extended`, otherwise `compare` would
+    /// fail.
+    pub fn compact_seek(mut self, compact_seek: bool) -> Self {
+        self.compact_seek = compact_seek;
+        self
+    }
+
+    /// Record the decoded sizes of the sections in the header (default is
+    /// disabled), see `Bspatch::hint_section_sizes`.
+    ///
+    /// Patchers then allocate their buffers once and no larger than the
+    /// sections need, which makes the memory of patching fully predictable.
+    /// This costs 24 bytes of the patch.
+    ///
+    /// Decoded sizes require `Format::Extended`, otherwise `compare` would
+    /// fail.
+    pub fn decoded_sizes(mut self, decoded_sizes: bool) -> Self {
+        self.decoded_sizes = decoded_sizes;
+        self
+    }
+
+    /// Record the source size explicitly in the header, along with the
+    /// decoded sizes of the sections (default is disabled), see
+    /// `Bspatch::sizes`.
+    ///
+    /// Patchers then reject sources of other sizes before reading any of
+    /// them, and patches whose target size disagrees with the decoded
+    /// sections at parse time.
+    /// This costs 36 bytes of the patch (including the decoded sizes).
+    ///
+    /// Explicit sizes require `Format::Extended`, otherwise `compare` would
+    /// fail.
+    pub fn explicit_sizes(mut self, explicit_sizes: bool) -> Self {
+        self.explicit_sizes = explicit_sizes;
+        self
+    }
+
+    /// Split the sections into independently compressed blocks of `size`
+    /// decoded bytes (default is 0, i.e. disabled), indexed by a block table
+    /// in the header.
+    ///
+    /// Patchers decode the blocks of large patches on worker threads ahead of
+    /// writing the target (see `Bspatch::decode_ahead`), at the cost of
+    /// slightly larger sections as each block is compressed on its own.
+    /// Blocks of 1 MiB or more keep the cost negligible.
+    ///
+    /// Blocks require `Format::Extended`, and must be no larger than
+    /// `format::MAX_BLOCK_SIZE`, otherwise `compare` would fail.
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Limit the delta data of each control to `len` bytes (default is 0, i.e.
+    /// unlimited).
+    ///
+    /// Longer controls are split as they are packed, after matching, thus the
+    /// matches found and the decoded sections are unchanged, only a few more
+    /// controls are encoded.
+    /// Patches stay compatible with any patcher, and patchers working control
+    /// by control (e.g. reporting progress or verifying chunks) see bounded
+    /// steps.
+    pub fn max_add(mut self, len: usize) -> Self {
+        self.max_add = len;
+        self
+    }
+
+    /// Limit the extra data of each control to `len` bytes (default is 0, i.e.
+    /// unlimited), split as `max_add` does.
+    pub fn max_copy(mut self, len: usize) -> Self {
+        self.max_copy = len;
+        self
+    }
+
+    /// Emit micro patches for targets of at most `threshold` bytes (default is
+    /// 0, i.e. disabled), e.g. 4096.
+    ///
+    /// The 48-byte extended header and the bzip2 streams (about 40 bytes
+    /// each) dominate patches of tiny inputs, which could be larger than the
+    /// targets themselves.
+    /// Micro patches have a header of about 10 bytes with varint sizes, and
+    /// controls of varints, see `Format::Extended`.
+    /// Their sections are stored raw unless compressed smaller by the codec
+    /// of the section (probing its level as `auto_levels` does), or by the
+    /// codec chosen by `auto_codec`.
+    /// Patchers recognize micro patches transparently.
+    ///
+    /// Micro patches are only emitted with `Format::Extended` and no other
+    /// feature of it (target-relative copies, decoded sizes, blocks, source
+    /// checksums, metadata or checksums, while seeks are always compact),
+    /// regular extended patches are emitted otherwise, as well as by
+    /// `compare_reader` whose target size is unknown in advance.
+    pub fn micro(mut self, threshold: usize) -> Self {
+        self.micro = threshold;
+        self
+    }
+
+    /// Enable deduplication of repeated target data (default is disabled),
+    /// implies `target_copy`.
+    ///
+    /// Extra data repeating earlier target bytes (e.g. the same new block
+    /// inserted many times) are rewritten as target-relative copies, which
+    /// greatly shrinks patches of highly repetitive targets.
+    /// Only repeats of at least 64 bytes are considered.
+    pub fn dedupe(mut self, dedupe: bool) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// Enable line-aware matching for text data (default is disabled).
+    ///
+    /// Target lines are first matched against identical source lines, and
+    /// only runs of changed lines are searched byte by byte, which produces
+    /// much smaller patches for sorted or append-mostly records such as CSV,
+    /// logs and SQL dumps, where plain matching gets fragmented by the
+    /// similar-looking records.
+    /// The patch is an ordinary patch of the configured format, applied by
+    /// `Bspatch` as usual.
+    /// Searching is not paralleled in this mode, and binary data without line
+    /// breaks simply degrades to the plain matching.
+    ///
+    /// Example:
+    ///
+    /// Diff database dumps:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn diff_dumps(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target).line_aware(true).compare_to_vec()
+    /// }
+    /// ```
+    pub fn line_aware(mut self, line_aware: bool) -> Self {
+        self.line_aware = line_aware;
+        self
+    }
+
+    /// Align source and target on stable markers found by `anchors` (default
+    /// is none), for formats with frequent small insertions such as database
+    /// pages or serialized records.
+    ///
+    /// The finder is called on the source and the target, returning the
+    /// positions of markers (e.g. page headers or field tags) in any order.
+    /// Data is split into segments at the anchors, target segments are first
+    /// matched against identical source segments, and only runs of changed
+    /// segments are searched byte by byte, thus matches get resynchronized at
+    /// every anchor instead of derailed by the insertions.
+    /// Like `line_aware` (which takes precedence), searching is not
+    /// paralleled in this mode.
+    ///
+    /// Example:
+    ///
+    /// Align on the headers of records starting with `0xfe 0xed`:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn diff_records(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target)
+    ///         .anchors(|data| data.windows(2).enumerate().filter(|(_, w)| w == b"\xfe\xed").map(|(i, _)| i).collect())
+    ///         .compare_to_vec()
+    /// }
+    /// ```
+    pub fn anchors<F>(mut self, anchors: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<usize> + Send + Sync + 'static,
+    {
+        self.anchors = Some(Arc::new(anchors));
+        self
+    }
+
+    /// Seed the matcher with pairs of source and target offsets known to
+    /// correspond (default is none), e.g. of relocated sections or symbols
+    /// from symbol tables or build metadata.
+    ///
+    /// Once searching reaches the target offset of a hint whose alignment
+    /// differs from the current one, the hinted source offset is taken as the
+    /// next match, and the following bytes are compared against the source
+    /// from there on, thus relocated code with changed addresses is covered
+    /// by delta data instead of searched piece by piece.
+    /// A wrong hint costs a control or two until searching realigns.
+    /// Hints out of bounds are ignored, and only the first hint of each
+    /// target offset is kept.
+    /// Hints are not used by `line_aware`, `anchors`, `append_mostly` and
+    /// the streaming `compare_reader`.
+    ///
+    /// Example:
+    ///
+    /// Align sections moved by the linker:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn diff_sections(source: &[u8], target: &[u8], moves: &[(usize, usize)]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target).hints(moves.iter().copied()).compare_to_vec()
+    /// }
+    /// ```
+    pub fn hints<I: IntoIterator<Item = (usize, usize)>>(mut self, hints: I) -> Self {
+        let mut hints: Vec<(usize, usize)> = hints
+            .into_iter()
+            .filter(|&(spos, tpos)| spos < self.source.len() && tpos < self.target.len())
+            .collect();
+        hints.sort_by_key(|&(_, tpos)| tpos);
+        hints.dedup_by_key(|&mut (_, tpos)| tpos);
+        self.hints = hints.into();
+        self
+    }
+
+    /// Enable the fast mode for append-mostly data (default is disabled).
+    ///
+    /// The common prefix of source and target is detected first and emitted
+    /// as a single delta control, then only the rest of target is searched
+    /// against the rest of source and the last 1 MiB of the prefix (a
+    /// sliding dictionary of recent records), which are the only part
+    /// indexed.
+    /// Diffing logs and ledgers, where targets are the old content plus
+    /// appended data, takes milliseconds rather than seconds this way.
+    /// Content further before the first change is not matched, so the patch
+    /// might be bigger than the one of plain matching if the target differs
+    /// early.
+    /// Searching is not paralleled in this mode, and `analyze` estimates the
+    /// similarity by the matched bytes instead of sampling the index.
+    ///
+    /// Example:
+    ///
+    /// Diff rotated log files:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn diff_logs(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target).append_mostly(true).compare_to_vec()
+    /// }
+    /// ```
+    pub fn append_mostly(mut self, append_mostly: bool) -> Self {
+        self.append_mostly = append_mostly;
+        self
+    }
+
+    /// Embed checksums of source windows of given size (default is 0, i.e.
+    /// disabled).
+    ///
+    /// Patchers verify each source window as it is read, and report the
+    /// corrupted window by `SourceCorruption` instead of producing a wrong
+    /// target, which helps to detect bit rot on long-lived devices.
+    /// Each window costs 4 bytes of the patch.
+    ///
+    /// Source checksums require `Format::Extended`, otherwise `compare` would
+    /// fail.
+    pub fn source_checksum(mut self, window: usize) -> Self {
+        self.source_checksum = window;
+        self
+    }
+
+    /// Append checksums of the sections computed by the algorithm (default is
+    /// none), which are verified by patchers before patching.
+    ///
+    /// Patchers could reject patches with checksums weaker than their threat
+    /// models require, see `Bspatch::require_checksum`.
+    ///
+    /// Checksums require `Format::Extended` and the algorithm compiled in,
+    /// otherwise `compare` would fail.
+    ///
+    /// Example:
+    ///
+    /// Protect the patch by SHA-256 (requires feature `sha256`):
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, ChecksumKind, Format};
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target)
+    ///         .format(Format::Extended)
+    ///         .checksum(Some(ChecksumKind::Sha256))
+    ///         .compare_to_vec()
+    /// }
+    /// ```
+    pub fn checksum(mut self, kind: Option<ChecksumKind>) -> Self {
+        self.checksum = kind;
+        self
+    }
+
+    /// Fall back to the full patch if the patch is not small enough (default
+    /// is disabled).
+    ///
+    /// The full patch is a single control copying the whole target from the
+    /// extra section, i.e. the compressed target plus headers, like the patch
+    /// of an empty source. With `Some(ratio)`, the patch is replaced by the
+    /// full patch if it is larger than `ratio` times the full patch, e.g.
+    /// `Some(0.9)` keeps the delta only if it saves more than 10%, which
+    /// avoids pathological deltas of unrelated inputs larger than the target.
+    /// `DiffReport::full_fallback` tells whether the patch was replaced,
+    /// callers could ship the target file itself instead.
+    ///
+    /// This applies to `compare`, `compare_report`, `compare_to_vec` and
+    /// `compare_async`, and costs compressing the whole target once more.
+    pub fn full_fallback(mut self, ratio: Option<f64>) -> Self {
+        self.full_fallback = ratio;
+        self
+    }
+
+    /// Record the qbsdiff version and the effective settings in the patch
+    /// (default is disabled), see `Bspatch::metadata`.
+    ///
+    /// The metadata helps to investigate why two builds of a delta pipeline
+    /// produce different patches, it costs a few hundred bytes of the patch
+    /// and is ignored by patchers.
+    /// Recorded keys are `version`, `codecs` (requested codec and level of
+    /// each section), `auto_levels`, `auto_codec`, `small_match`,
+    /// `mismatch_count`, `scoring`, `work_limit`, `parallel_scheme`,
+    /// `chunk_size` (the effective one), `target_copy`, `compact_seek`,
+    /// `decoded_sizes`, `explicit_sizes`, `block_size`, `max_add`,
+    /// `max_copy`, `micro`, `dedupe`, `line_aware`, `anchors`, `hints` (the
+    /// number of hints),
+    /// `append_mostly`, `source_checksum`, `checksum`, `buffer_size` and
+    /// `memory_limit`.
+    ///
+    /// Metadata requires `Format::Extended`, otherwise `compare` would fail.
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set the compression level of bzip2 (in range `1..=9`, default is `COMPRESSION_LEVEL`).
     ///
     /// The fastest/default compression level is usually good enough.
     /// In contrast, patch files produced with the best level appeared slightly
     /// bigger in many test cases.
+    ///
+    /// Levels greater than 9 are clamped for bzip2, but passed as is to zstd.
+    ///
+    /// Level 0 means storing sections uncompressed (`Codec::Stored`) whatever
+    /// the codec is, which is only available in `Format::Extended`, `compare`
+    /// would fail with the classic format.
     pub fn compression_level(mut self, compression_level: u32) -> Self {
-        self.compression_level = Compression::new(u32::min(u32::max(compression_level, 0), 9));
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Set the container format of patch file (default is `Format::Classic`).
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the codec of patch sections (default is `Codec::Bzip2`).
+    ///
+    /// Codecs other than bzip2 require `Format::Extended`, otherwise `compare`
+    /// would fail.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the codec and compression level of a patch section, overriding
+    /// `codec` and `compression_level` for that section.
+    ///
+    /// Sections compress very differently: the delta section is mostly zeros
+    /// and benefits from higher levels, while the extra section is new data
+    /// which often barely compresses, thus a lower level (or level 0, i.e.
+    /// `Codec::Stored`) saves time at little cost.
+    /// Codecs other than bzip2 and level 0 require `Format::Extended`, but
+    /// bzip2 levels could be set per section in the classic format as well.
+    ///
+    /// Example:
+    ///
+    /// Compress delta harder, and extra faster:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, Codec, Section};
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target)
+    ///         .section_codec(Section::Delta, Codec::Bzip2, 9)
+    ///         .section_codec(Section::Extra, Codec::Bzip2, 1)
+    ///         .compare_to_vec()
+    /// }
+    /// ```
+    pub fn section_codec(mut self, section: Section, codec: Codec, level: u32) -> Self {
+        self.section_codecs[section.index()] = Some((codec, level));
+        self
+    }
+
+    /// Probe the compression level of each section on a sample of its
+    /// leading data (default is disabled).
+    ///
+    /// The fastest of level 1, the configured level and the maximum level of
+    /// the section codec is chosen if it compresses the sample no more than 1%
+    /// larger than the others.
+    /// With `Format::Extended`, sections hardly compressible (saving less than
+    /// 2%) are stored uncompressed instead.
+    /// Sections configured with level 0 are always stored.
+    pub fn auto_levels(mut self, auto_levels: bool) -> Self {
+        self.auto_levels = auto_levels;
+        self
+    }
+
+    /// Choose the codec and compression level of each section by priority
+    /// (default is `None`, using the configured codecs).
+    ///
+    /// A sample of the leading data of each section is compressed with every
+    /// codec compiled in, and the choice is recorded by the codec id of the
+    /// section in the patch header, thus patchers need no extra settings.
+    /// Configured codecs and levels (as well as `auto_levels`) are ignored.
+    ///
+    /// Automatic codec selection requires `Format::Extended`, otherwise
+    /// `compare` would fail.
+    ///
+    /// Example:
+    ///
+    /// Favor patching speed on slow devices:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, CodecPriority, Format};
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     Bsdiff::new(source, target)
+    ///         .format(Format::Extended)
+    ///         .auto_codec(Some(CodecPriority::FastestApply))
+    ///         .compare_to_vec()
+    /// }
+    /// ```
+    pub fn auto_codec(mut self, priority: Option<CodecPriority>) -> Self {
+        self.auto_codec = priority;
         self
     }
 
+    /// Apply all settings of the tuning profile.
+    ///
+    /// Settings not covered by profiles (e.g. `scoring`) are kept.
+    pub fn profile(self, profile: &DiffProfile) -> Self {
+        let bsdiff = self
+            .parallel_scheme(profile.parallel_scheme)
+            .small_match(profile.small_match)
+            .buffer_size(profile.buffer_size)
+            .format(profile.format)
+            .codec(profile.codec)
+            .compression_level(profile.compression_level)
+            .auto_levels(profile.auto_levels)
+            .auto_codec(profile.auto_codec)
+            .target_copy(profile.target_copy)
+            .compact_seek(profile.compact_seek)
+            .decoded_sizes(profile.decoded_sizes)
+            .explicit_sizes(profile.explicit_sizes)
+            .block_size(profile.block_size)
+            .max_add(profile.max_add)
+            .max_copy(profile.max_copy)
+            .micro(profile.micro)
+            .dedupe(profile.dedupe)
+            .line_aware(profile.line_aware)
+            .append_mostly(profile.append_mostly)
+            .metadata(profile.metadata)
+            .source_checksum(profile.source_checksum)
+            .checksum(profile.checksum);
+        let bsdiff = Bsdiff {
+            section_codecs: profile.section_codecs,
+            ..bsdiff
+        };
+        match profile.work_limit {
+            Some(factor) => bsdiff.work_limit(factor),
+            None => Bsdiff {
+                work_limit: None,
+                ..bsdiff
+            },
+        }
+    }
+
     /// Set the buffer size for delta calculation (`buffer_size >= 128`, default is `BUFFER_SIZE`).
     pub fn buffer_size(mut self, mut buffer_size: usize) -> Self {
         if buffer_size < 128 {
@@ -191,160 +1208,1592 @@ impl<'s, 't> Bsdiff<'s, 't> {
         self
     }
 
+    /// Set the buffer size for delta calculation, like `buffer_size` but
+    /// rejecting sizes less than 128 with `ErrorKind::InvalidInput`.
+    pub fn try_buffer_size(self, buffer_size: usize) -> Result<Self> {
+        if buffer_size < 128 {
+            return Err(Error::new(ErrorKind::InvalidInput, "buffer size must be at least 128"));
+        }
+        Ok(self.buffer_size(buffer_size))
+    }
+
+    /// Get the effective buffer size for delta calculation.
+    pub fn effective_buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Apply all settings of the tuning profile, like `profile` but rejecting
+    /// invalid settings (see `DiffProfile::validate`).
+    pub fn try_profile(self, profile: &DiffProfile) -> Result<Self> {
+        profile.validate()?;
+        Ok(self.profile(profile))
+    }
+
+    /// Codec and compression level of each section.
+    fn section_codecs(&self) -> [(Codec, u32); 3] {
+        self.section_codecs
+            .map(|codec| codec.unwrap_or((self.codec, self.compression_level)))
+    }
+
+    /// Attach the metadata describing the settings if enabled, `chunk` is the
+    /// effective chunk size.
+    fn describe(&self, packer: &mut Packer, chunk: usize) {
+        if !self.metadata {
+            return;
+        }
+        let mut metadata = String::new();
+        for (key, value) in self.settings(chunk) {
+            metadata.push_str(&format!("{}={}\n", key, value));
+        }
+        packer.metadata(metadata);
+    }
+
+    /// Effective settings recorded by `describe`, in order.
+    fn settings(&self, chunk: usize) -> Vec<(&'static str, String)> {
+        let codecs: Vec<String> = self
+            .section_codecs()
+            .iter()
+            .map(|(codec, level)| format!("{:?}:{}", codec, level))
+            .collect();
+        let work_limit = match self.work_limit {
+            Some(factor) => factor.to_string(),
+            None => String::from("none"),
+        };
+        let memory_limit = match self.memory_limit {
+            Some(limit) => limit.to_string(),
+            None => String::from("none"),
+        };
+        let auto_codec = match self.auto_codec {
+            Some(priority) => format!("{:?}", priority),
+            None => String::from("none"),
+        };
+        let checksum = match self.checksum {
+            Some(kind) => format!("{:?}", kind),
+            None => String::from("none"),
+        };
+        let scoring = if self.scoring.is_some() { "custom" } else { "default" };
+        let entries = [
+            ("version", String::from(env!("CARGO_PKG_VERSION"))),
+            ("codecs", codecs.join(",")),
+            ("auto_levels", self.auto_levels.to_string()),
+            ("auto_codec", auto_codec),
+            ("small_match", self.small_match.to_string()),
+            ("mismatch_count", self.mismatch_count.to_string()),
+            ("scoring", String::from(scoring)),
+            ("work_limit", work_limit),
+            ("parallel_scheme", format!("{:?}", self.parallel_scheme)),
+            ("chunk_size", chunk.to_string()),
+            ("target_copy", self.target_copy.to_string()),
+            ("compact_seek", self.compact_seek.to_string()),
+            ("decoded_sizes", self.decoded_sizes.to_string()),
+            ("explicit_sizes", self.explicit_sizes.to_string()),
+            ("block_size", self.block_size.to_string()),
+            ("max_add", self.max_add.to_string()),
+            ("max_copy", self.max_copy.to_string()),
+            ("micro", self.micro.to_string()),
+            ("dedupe", self.dedupe.to_string()),
+            ("line_aware", self.line_aware.to_string()),
+            (
+                "anchors",
+                String::from(if self.anchors.is_some() { "custom" } else { "none" }),
+            ),
+            ("hints", self.hints.len().to_string()),
+            ("append_mostly", self.append_mostly.to_string()),
+            ("source_checksum", self.source_checksum.to_string()),
+            ("checksum", checksum),
+            ("buffer_size", self.buffer_size.to_string()),
+            ("memory_limit", memory_limit),
+        ];
+        entries.into()
+    }
+
+    /// Report a large allocation to the hook.
+    fn allocate(&self, allocation: Allocation) -> Result<()> {
+        match self.allocation_hook {
+            Some(ref hook) => hook(allocation),
+            None => Ok(()),
+        }
+    }
+
+    /// Build the index of source, reporting its allocations.
+    fn build_index(&self) -> Result<SourceIndex<'s>> {
+        self.allocate(Allocation::SuffixArray((self.source.len() + 1) * 4))?;
+        self.allocate(Allocation::Buckets(BUCKETS * 4))?;
+        Ok(SourceIndex::new(self.source))
+    }
+
+    /// Search matches in target as `compare` does, but only count the lengths
+    /// of exact matches found, without constructing the patch.
+    ///
+    /// Matches of any length are accepted during the search (as if
+    /// `small_match` were 0), so that the histogram tells how many matches and
+    /// delta bytes would be kept by each `small_match`, to tune it
+    /// empirically for a class of data.
+    /// Other settings including the parallel scheme are respected.
+    ///
+    /// Example:
+    ///
+    /// Keep 95% of delta bytes:
+    /// ```
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn tune(source: &[u8], target: &[u8]) -> usize {
+    ///     let histogram = Bsdiff::new(source, target).match_histogram();
+    ///     histogram.suggest_small_match(0.95)
+    /// }
+    /// ```
+    pub fn match_histogram(&self) -> MatchHistogram {
+        if self.source.is_empty() || self.target.is_empty() {
+            return MatchHistogram::default();
+        }
+        let (chunk, workers, _) = self.chunking(available_threads());
+        let chunk = Ord::min(chunk, self.target.len());
+
+        let owned_index;
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                owned_index = SourceIndex::new(self.source);
+                &owned_index
+            }
+        };
+        let match_config = MatchConfig {
+            small_match: 0,
+            ..self.match_config()
+        };
+        let mut par_diff = ParSaDiff::new(self.source, self.target, index, chunk, workers, &match_config);
+        par_diff.compute_histogram()
+    }
+
+    /// Search matches in target chunk by chunk as `compare` does, but return
+    /// the controls of each chunk instead of constructing the patch.
+    ///
+    /// This is an advanced API for experimenting with alternative ways of
+    /// merging or repairing controls around chunk seams; the merged controls
+    /// could be packed by `compare_controls`.
+    /// Chunks are split by the parallel scheme, a single chunk covers the
+    /// whole target if not parallel.
+    ///
+    /// Example:
+    ///
+    /// Concatenate the chunks, which is what `compare` does:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let bsdiff = Bsdiff::new(source, target);
+    ///     let chunks = bsdiff.search_chunks();
+    ///     let ctrls = chunks.into_iter().flat_map(|chunk| chunk.controls);
+    ///     let mut patch = Vec::new();
+    ///     bsdiff.compare_controls(ctrls, io::Cursor::new(&mut patch))?;
+    ///     Ok(patch)
+    /// }
+    /// ```
+    pub fn search_chunks(&self) -> Vec<ChunkControls> {
+        if self.target.is_empty() {
+            return Vec::new();
+        }
+        let (chunk, workers, _) = self.chunking(available_threads());
+        let chunk = Ord::min(chunk, self.target.len());
+
+        let owned_index;
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                owned_index = SourceIndex::new(self.source);
+                &owned_index
+            }
+        };
+        let match_config = self.match_config();
+        let mut par_diff = ParSaDiff::new(self.source, self.target, index, chunk, workers, &match_config);
+        par_diff
+            .compute_chunks()
+            .into_iter()
+            .enumerate()
+            .map(|(i, controls)| ChunkControls {
+                range: i * chunk..Ord::min((i + 1) * chunk, self.target.len()),
+                controls,
+            })
+            .collect()
+    }
+
+    /// Construct the patch file from the given controls, e.g. chunks of
+    /// `search_chunks` merged by a custom strategy, or controls produced by
+    /// an external matcher.
+    ///
+    /// This only packs the controls, the source is not indexed nor searched,
+    /// while all the container settings apply as `compare` does: the format,
+    /// codecs and compression levels, source checksums, target-relative
+    /// copies and the buffer size.
+    /// Controls are checked and packed as they are iterated, and sections are
+    /// compressed in memory, thus nothing is written to `patch` if any control
+    /// is invalid.
+    ///
+    /// Example:
+    ///
+    /// Pack the controls of a matcher aligning the source and target at the
+    /// same offsets, e.g. for fixed-layout records:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, Codec, Control, Format};
+    ///
+    /// fn pack_aligned(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let add = Ord::min(source.len(), target.len()) as u64;
+    ///     let ctrl = Control {
+    ///         add,
+    ///         copy: target.len() as u64 - add,
+    ///         ..Control::default()
+    ///     };
+    ///     let mut patch = Vec::new();
+    ///     Bsdiff::new(source, target)
+    ///         .format(Format::Extended)
+    ///         .codec(Codec::Stored)
+    ///         .source_checksum(4096)
+    ///         .compare_controls(Some(ctrl), io::Cursor::new(&mut patch))?;
+    ///     Ok(patch)
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the controls do not cover the
+    /// target exactly, read beyond the source, or contain target-relative
+    /// copies (these are generated by the patch settings instead).
+    /// The size of patch file would be returned if no error occurs.
+    pub fn compare_controls<I, P>(&self, ctrls: I, patch: P) -> Result<u64>
+    where
+        I: IntoIterator<Item = Control>,
+        P: Write,
+    {
+        let config = self.pack_config()?;
+        let mut ctrls = CheckedControls::new(ctrls.into_iter(), self.source.len(), self.target.len());
+        let mut packer = Packer::new(&config, self.source)?;
+        packer.push(self.source, self.target, &mut ctrls)?;
+        ctrls.finish()?;
+        self.describe(&mut packer, self.target.len());
+        packer.finish(patch)
+    }
+
+    /// Search matches in target as `compare` does, but return the raw control
+    /// stream as a `Sidecar` instead of constructing the patch.
+    ///
+    /// Only the matching settings apply, the sidecar records the effective
+    /// ones (the `version`, `small_match`, `mismatch_count`, `scoring`,
+    /// `work_limit`, `parallel_scheme`, `chunk_size`, `line_aware`,
+    /// `anchors`, `hints`, `append_mostly` and `memory_limit` entries of
+    /// metadata).
+    /// Delta and extra data are not computed nor compressed, the sidecar
+    /// could be packed later by `pack_sidecar` with any container settings.
+    pub fn compare_sidecar(&self) -> Result<Sidecar> {
+        let config = PackConfig {
+            format: Format::Extended,
+            codecs: [(Codec::Stored, 0); 3],
+            auto_levels: false,
+            auto_codec: None,
+            buffer_size: self.buffer_size,
+            target_copy: false,
+            compact_seek: false,
+            decoded_sizes: false,
+            explicit_sizes: false,
+            block_size: 0,
+            max_lengths: [0; 2],
+            micro: false,
+            dedupe: false,
+            source_checksum: 0,
+            checksum: None,
+            controls_only: true,
+        };
+        let mut controls = Vec::new();
+        let report = self.compare_with(config, |packer| {
+            controls = packer.into_controls();
+            Ok(0)
+        })?;
+        let mut parameters = String::new();
+        for (key, value) in self.settings(report.chunk_size()) {
+            if SEARCH_SETTINGS.contains(&key) {
+                parameters.push_str(&format!("{}={}\n", key, value));
+            }
+        }
+        Ok(Sidecar::new(self.source, self.target, parameters, controls))
+    }
+
+    /// Construct the patch file from the controls of a sidecar produced by
+    /// `compare_sidecar`, packing them as `compare_controls` does.
+    ///
+    /// The metadata (if enabled) records the matching parameters of the
+    /// sidecar rather than the current ones.
+    ///
+    /// Return `ErrorKind::InvalidInput` if the source or target is not the one
+    /// searched, or the controls are invalid.
+    /// The size of patch file would be returned if no error occurs.
+    pub fn pack_sidecar<P: Write>(&self, sidecar: &Sidecar, patch: P) -> Result<u64> {
+        sidecar.check(self.source, self.target)?;
+        let config = self.pack_config()?;
+        let ctrls = sidecar.controls().iter().copied();
+        let mut ctrls = CheckedControls::new(ctrls, self.source.len(), self.target.len());
+        let mut packer = Packer::new(&config, self.source)?;
+        packer.push(self.source, self.target, &mut ctrls)?;
+        ctrls.finish()?;
+        if self.metadata {
+            let parameters = sidecar.parameters();
+            let mut metadata = String::new();
+            for (key, value) in self.settings(self.target.len()) {
+                let value = parameters.get(key).unwrap_or(&value);
+                metadata.push_str(&format!("{}={}\n", key, value));
+            }
+            packer.metadata(metadata);
+        }
+        packer.finish(patch)
+    }
+
+    /// Update the patch of an earlier target for the current target, which
+    /// only differs in the range `changed` (of the current target).
+    ///
+    /// Only the changed range is searched, the controls outside it are taken
+    /// from the earlier patch, and the patch is repacked with the current
+    /// settings.
+    /// This is much faster than `compare` for incremental builds where only a
+    /// small region of a large artifact changes, especially with a reused
+    /// `SourceIndex` (see `Bsdiff::index`).
+    /// Target-relative copies of the earlier patch are turned into extra data.
+    ///
+    /// Example:
+    ///
+    /// Rebuild the patch after a region of the target is rewritten:
+    /// ```
+    /// use std::io;
+    /// use std::ops::Range;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn rebuild(source: &[u8], target: &[u8], patch: &[u8], changed: Range<usize>) -> io::Result<Vec<u8>> {
+    ///     let mut updated = Vec::new();
+    ///     Bsdiff::new(source, target).splice(patch, changed, io::Cursor::new(&mut updated))?;
+    ///     Ok(updated)
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the range does not fit the target
+    /// size of the earlier patch.
+    /// The size of patch file would be returned if no error occurs.
+    pub fn splice<P: Write>(&self, patch: &[u8], changed: Range<usize>, out: P) -> Result<u64> {
+        let regions = inspect::regions(patch)?;
+        let old_size = regions.last().map(|r| r.target.end).unwrap_or(0) as i128;
+        let growth = self.target.len() as i128 - old_size;
+        let old_end = changed.end as i128 - growth;
+        if changed.start > changed.end
+            || changed.end > self.target.len()
+            || old_end < changed.start as i128
+            || old_end > old_size
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "changed range does not fit the patch",
+            ));
+        }
+        let old_end = old_end as u64;
+
+        let mut splicer = Splicer::default();
+        let start = changed.start as u64;
+        for region in regions.iter().filter(|r| r.target.start < start) {
+            let len = Ord::min(region.target.end, start) - region.target.start;
+            match (region.kind, &region.source) {
+                (RegionKind::Delta, Some(source)) => splicer.delta(source.start, len),
+                _ => splicer.extra(len),
+            }
+        }
+
+        if !changed.is_empty() {
+            let owned_index;
+            let index = match self.index {
+                Some(index) => index,
+                None => {
+                    owned_index = self.build_index()?;
+                    &owned_index
+                }
+            };
+            let match_config = self.match_config();
+            let target = &self.target[changed];
+            let mut diff = SaDiff::new(self.source, target, index, &match_config);
+            splicer.search(&mut diff);
+        }
+
+        for region in regions.iter().filter(|r| r.target.end > old_end) {
+            let skip = old_end.saturating_sub(region.target.start);
+            let len = region.target.end - region.target.start - skip;
+            match (region.kind, &region.source) {
+                (RegionKind::Delta, Some(source)) => splicer.delta(source.start + skip, len),
+                _ => splicer.extra(len),
+            }
+        }
+
+        self.compare_controls(splicer.ctrls, out)
+    }
+
     /// Start searching matches in target and constructing the patch file.
     ///
+    /// Degenerate inputs are handled without searching, and produce canonical
+    /// patches independent of the parallel scheme and matching settings:
+    /// * empty target: a patch with no controls and empty sections;
+    /// * empty source: a single control copying the whole target from the
+    ///   extra section;
+    /// * identical source and target: a single control adding a delta of
+    ///   zeros to the whole target, recognized by `Bspatch::is_identity`.
+    ///   The size of this patch only depends on the target size and the
+    ///   format settings, e.g. it is exactly `target size + 72` bytes for
+    ///   `Format::Extended` with `Codec::Stored` (without checksums).
+    ///
+    /// Targets shorter than `small_match` and sources shorter than the
+    /// parallel chunk size are searched as usual.
+    ///
     /// The size of patch file would be returned if no error occurs.
     pub fn compare<P: Write>(&self, patch: P) -> Result<u64> {
-        // Determine parallel chunk size.
-        use ParallelScheme::*;
-        let mut chunk = match self.parallel_scheme {
-            Never => self.target.len(),
-            ChunkSize(chunk) => chunk,
-            NumJobs(jobs) => div_ceil(self.target.len(), jobs),
-            Auto => DEFAULT_CHUNK,
+        self.compare_report(patch).map(|report| report.patch_size())
+    }
+
+    /// Start searching matches in target and constructing the patch file,
+    /// returning a summary of the delta compression.
+    ///
+    /// Reuse the chunk size chosen by `ParallelScheme::Auto`:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, ParallelScheme};
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<(Vec<u8>, ParallelScheme)> {
+    ///     let mut patch = Vec::new();
+    ///     let report = Bsdiff::new(source, target)
+    ///         .parallel_scheme(ParallelScheme::Auto)
+    ///         .compare_report(io::Cursor::new(&mut patch))?;
+    ///     Ok((patch, ParallelScheme::ChunkSize(report.chunk_size())))
+    /// }
+    /// ```
+    pub fn compare_report<P: Write>(&self, patch: P) -> Result<DiffReport> {
+        let mut full = false;
+        let mut report = self.compare_with(self.pack_config()?, |packer| {
+            let sections;
+            (sections, full) = self.seal(packer)?;
+            sections.write(patch)
+        })?;
+        report.full_fallback = full;
+        Ok(report)
+    }
+
+    /// Start searching matches in target and constructing the patch in a
+    /// vector.
+    ///
+    /// Sections are compressed in memory before the patch is assembled, thus
+    /// the vector is allocated once with the exact size of patch, rather than
+    /// growing (and copying) repeatedly as writing to `io::Cursor<Vec<u8>>`
+    /// does, which matters for patches of hundreds of megabytes.
+    pub fn compare_to_vec(&self) -> Result<Vec<u8>> {
+        self.compare_to_vec_with_capacity(0)
+    }
+
+    /// Start searching matches in target and constructing the patch in a
+    /// vector, like `compare_to_vec` but reserving `capacity` bytes up front
+    /// (e.g. the size of a previous patch), which is grown to the exact size
+    /// if insufficient.
+    pub fn compare_to_vec_with_capacity(&self, capacity: usize) -> Result<Vec<u8>> {
+        let mut patch = Vec::with_capacity(capacity);
+        self.compare_with(self.pack_config()?, |packer| {
+            let (sections, _) = self.seal(packer)?;
+            self.allocate(Allocation::Patch(sections.size() as usize))?;
+            patch.reserve_exact(sections.size() as usize);
+            sections.write(&mut patch)
+        })?;
+        Ok(patch)
+    }
+
+    /// Finish the sections, replacing them by the full patch if larger than
+    /// `full_fallback` allows, which is told by the flag returned.
+    fn seal(&self, packer: Packer) -> Result<(Sections, bool)> {
+        let sections = packer.seal()?;
+        let ratio = match self.full_fallback {
+            Some(ratio) if !self.source.is_empty() && !self.target.is_empty() => ratio,
+            _ => return Ok((sections, false)),
+        };
+        let mut full = Packer::new(&self.pack_config()?, self.source)?;
+        let ctl = Control {
+            copy: self.target.len() as u64,
+            ..Control::default()
         };
-        chunk = Ord::max(chunk, MIN_CHUNK);
+        full.push(self.source, self.target, Some(ctl).into_iter())?;
+        self.describe(&mut full, self.target.len());
+        let full = full.seal()?;
+        if sections.size() as f64 > ratio * full.size() as f64 {
+            Ok((full, true))
+        } else {
+            Ok((sections, false))
+        }
+    }
+
+    /// Search matches and pack the controls, then finish the patch.
+    fn compare_with<F>(&self, config: PackConfig, finish: F) -> Result<DiffReport>
+    where
+        F: FnOnce(Packer) -> Result<u64>,
+    {
+        let match_config = self.match_config();
+
+        // Fresh install: all target bytes are extra.
+        // Identical inputs: all target bytes are zero delta.
+        let mut packer = Packer::new(&config, self.source)?;
+        let identical = self.source == self.target;
+        if self.source.is_empty() || identical {
+            let size = self.target.len() as u64;
+            let ctl = if identical {
+                Control {
+                    add: size,
+                    ..Control::default()
+                }
+            } else {
+                Control {
+                    copy: size,
+                    ..Control::default()
+                }
+            };
+            let ctrls = Some(ctl).filter(|_| size > 0);
+            packer.push(self.source, self.target, ctrls.into_iter())?;
+            self.describe(&mut packer, self.target.len());
+            let stats = packer.stats();
+            let patch_size = finish(packer)?;
+            let similarity = if identical { 1.0 } else { 0.0 };
+            return Ok(DiffReport {
+                patch_size,
+                source_size: self.source.len() as u64,
+                target_size: size,
+                parallel_scheme: self.parallel_scheme,
+                chunk_size: self.target.len(),
+                jobs: usize::from(!self.target.is_empty()),
+                workers: usize::from(!self.target.is_empty()),
+                threads: available_threads(),
+                target_entropy: None,
+                search_steps: 0,
+                fallback_chunks: 0,
+                controls: stats.controls,
+                extra_size: stats.extra_size,
+                similarity: Some(similarity).filter(|_| self.analyze),
+                anomalies: if self.analyze {
+                    stats.anomalies(similarity)
+                } else {
+                    Vec::new()
+                },
+                full_fallback: false,
+            });
+        }
 
-        let mut suffix_array = SuffixArray::new(self.source);
-        suffix_array.enable_buckets();
-        if chunk >= self.target.len() {
+        if self.append_mostly {
+            return self.compare_appended(packer, finish);
+        }
+        if let Some(window) = self.source_window() {
+            return self.compare_windowed(packer, finish, window);
+        }
+
+        let threads = available_threads();
+        let (mut chunk, workers, entropy) = self.chunking(threads);
+
+        let owned_index;
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                owned_index = self.build_index()?;
+                &owned_index
+            }
+        };
+        let jobs = if chunk == 0 {
+            0
+        } else {
+            div_ceil(self.target.len(), chunk)
+        };
+        let (steps, fallbacks) = if self.line_aware {
+            let (ctrls, work) = search_lines(self.source, self.target, index, &match_config);
+            self.allocate(Allocation::Controls(mem::size_of_val(&ctrls[..])))?;
+            packer.push(self.source, self.target, ctrls.into_iter())?;
+            work
+        } else if let Some(anchors) = &self.anchors {
+            let (ctrls, work) = search_anchored(self.source, self.target, index, &match_config, &**anchors);
+            self.allocate(Allocation::Controls(mem::size_of_val(&ctrls[..])))?;
+            packer.push(self.source, self.target, ctrls.into_iter())?;
+            work
+        } else if chunk >= self.target.len() {
             // Single thread is fine.
-            let diff = SaDiff::new(
-                self.source,
-                self.target,
-                &suffix_array,
-                self.small_match,
-                self.mismatch_count,
-                self.long_suffix,
-            );
-            pack(
-                self.source,
-                self.target,
-                diff,
-                patch,
-                self.compression_level,
-                self.buffer_size,
-            )
+            chunk = self.target.len();
+            let mut diff = SaDiff::new(self.source, self.target, index, &match_config).hinted(&match_config, 0);
+            packer.push(self.source, self.target, &mut diff)?;
+            diff.work()
         } else {
             // Go parallel.
-            let par_diff = ParSaDiff::new(
-                self.source,
-                self.target,
-                &suffix_array,
-                chunk,
-                self.small_match,
-                self.mismatch_count,
-                self.long_suffix,
+            let mut par_diff = ParSaDiff::new(self.source, self.target, index, chunk, workers, &match_config);
+            self.push_parallel(&mut packer, self.target, &mut par_diff)?;
+            par_diff.work()
+        };
+        self.describe(&mut packer, chunk);
+        let stats = packer.stats();
+        let patch_size = finish(packer)?;
+        let similarity = if self.analyze {
+            Some(index.similarity(self.target, SIMILARITY_INTERVAL))
+        } else {
+            None
+        };
+
+        Ok(DiffReport {
+            patch_size,
+            source_size: self.source.len() as u64,
+            target_size: self.target.len() as u64,
+            parallel_scheme: self.parallel_scheme,
+            chunk_size: chunk,
+            jobs,
+            workers: Ord::min(workers, jobs),
+            threads,
+            target_entropy: entropy,
+            search_steps: steps,
+            fallback_chunks: fallbacks,
+            controls: stats.controls,
+            extra_size: stats.extra_size,
+            similarity,
+            anomalies: similarity
+                .map(|similarity| stats.anomalies(similarity))
+                .unwrap_or_default(),
+            full_fallback: false,
+        })
+    }
+
+    /// Pack the controls of parallel chunks as soon as they are searched in
+    /// order, reporting each chunk of controls buffered.
+    fn push_parallel(&self, packer: &mut Packer, target: &[u8], par_diff: &mut ParSaDiff) -> Result<()> {
+        let mut reported = Ok(());
+        let pushed = par_diff.stream(|chunks| {
+            let ctrls = chunks
+                .map_while(|ctrls| {
+                    reported = self.allocate(Allocation::Controls(mem::size_of_val(&ctrls[..])));
+                    reported.is_ok().then_some(ctrls)
+                })
+                .flatten();
+            packer.push(self.source, target, ctrls)
+        });
+        reported?;
+        pushed
+    }
+
+    /// Emit the common prefix as a single delta, then search the rest of
+    /// target against the rest of source and the window before it, see
+    /// `append_mostly`.
+    fn compare_appended<F>(&self, mut packer: Packer, finish: F) -> Result<DiffReport>
+    where
+        F: FnOnce(Packer) -> Result<u64>,
+    {
+        let prefix = common_prefix(self.source, self.target);
+        let base = prefix.saturating_sub(APPEND_WINDOW);
+        let (source, target) = (&self.source[base..], &self.target[prefix..]);
+        self.allocate(Allocation::SuffixArray((source.len() + 1) * 4))?;
+        self.allocate(Allocation::Buckets(BUCKETS * 4))?;
+        let index = SourceIndex::new(source);
+        let match_config = self.match_config();
+        let mut diff = SaDiff::new(source, target, &index, &match_config);
+        let head = Some(Control {
+            add: prefix as u64,
+            seek: (base as i64) - (prefix as i64),
+            ..Control::default()
+        })
+        .filter(|_| prefix > 0);
+        packer.push(self.source, self.target, head.into_iter().chain(&mut diff))?;
+        let (steps, fallbacks) = diff.work();
+        self.describe(&mut packer, self.target.len());
+
+        let stats = packer.stats();
+        let patch_size = finish(packer)?;
+        let similarity = if self.analyze {
+            let unmatched = stats.extra_size as f64 / Ord::max(self.target.len(), 1) as f64;
+            Some(1.0 - unmatched)
+        } else {
+            None
+        };
+        Ok(DiffReport {
+            patch_size,
+            source_size: self.source.len() as u64,
+            target_size: self.target.len() as u64,
+            parallel_scheme: self.parallel_scheme,
+            chunk_size: self.target.len(),
+            jobs: 1,
+            workers: 1,
+            threads: available_threads(),
+            target_entropy: None,
+            search_steps: steps,
+            fallback_chunks: fallbacks,
+            controls: stats.controls,
+            extra_size: stats.extra_size,
+            similarity,
+            anomalies: similarity
+                .map(|similarity| stats.anomalies(similarity))
+                .unwrap_or_default(),
+            full_fallback: false,
+        })
+    }
+
+    /// Size of source windows indexed one by one, if the index of the whole
+    /// source exceeds the memory limit.
+    fn source_window(&self) -> Option<usize> {
+        let limit = self.memory_limit?;
+        if self.index.is_some() || self.line_aware || self.anchors.is_some() {
+            return None;
+        }
+        let window = Ord::max(limit.saturating_sub(BUCKETS * 4) / 4, MIN_SOURCE_WINDOW + 1) - 1;
+        Some(window).filter(|&window| window < self.source.len())
+    }
+
+    /// Search each target chunk against the source window at the
+    /// proportional position, see `memory_limit`.
+    fn compare_windowed<F>(&self, mut packer: Packer, finish: F, window: usize) -> Result<DiffReport>
+    where
+        F: FnOnce(Packer) -> Result<u64>,
+    {
+        let match_config = self.match_config();
+        let (slen, tlen) = (self.source.len(), self.target.len());
+        let chunk = Ord::max(window / 2, 1);
+        let align = Ord::max(window / 8, 1);
+        let mut indexed: Option<(usize, SourceIndex)> = None;
+        let (mut steps, mut fallbacks, mut jobs) = (0, 0, 0);
+        for tpos in (0..tlen).step_by(chunk) {
+            let target = &self.target[tpos..Ord::min(tpos + chunk, tlen)];
+
+            // Center the window at the proportional position of the chunk,
+            // aligned to reuse the index of close chunks, leaving a margin for
+            // shifted data on both sides.
+            let center = (tpos + target.len() / 2) as u128 * slen as u128 / tlen as u128;
+            let base = Ord::min(
+                (center as usize).saturating_sub(window / 2) / align * align,
+                slen - window,
             );
-            let ctrls = par_diff.compute();
-            pack(
-                self.source,
-                self.target,
-                ctrls.into_iter(),
-                patch,
-                self.compression_level,
-                self.buffer_size,
-            )
+            let index = match indexed.take().filter(|&(b, _)| b == base) {
+                Some((_, index)) => index,
+                None => {
+                    self.allocate(Allocation::SuffixArray((window + 1) * 4))?;
+                    self.allocate(Allocation::Buckets(BUCKETS * 4))?;
+                    SourceIndex::new(&self.source[base..base + window])
+                }
+            };
+
+            let mut diff = SaDiff::new(index.source(), target, &index, &match_config);
+            let head = Some(Control {
+                seek: base as i64,
+                ..Control::default()
+            })
+            .filter(|_| base > 0);
+            packer.push(self.source, target, head.into_iter().chain(&mut diff))?;
+            let (s, f) = diff.work();
+            steps += s;
+            fallbacks += f;
+            jobs += 1;
+            indexed = Some((base, index));
+        }
+        self.describe(&mut packer, chunk);
+
+        let stats = packer.stats();
+        let patch_size = finish(packer)?;
+        let similarity = if self.analyze {
+            let unmatched = stats.extra_size as f64 / Ord::max(tlen, 1) as f64;
+            Some(1.0 - unmatched)
+        } else {
+            None
+        };
+        Ok(DiffReport {
+            patch_size,
+            source_size: slen as u64,
+            target_size: tlen as u64,
+            parallel_scheme: self.parallel_scheme,
+            chunk_size: chunk,
+            jobs,
+            workers: 1,
+            threads: available_threads(),
+            target_entropy: None,
+            search_steps: steps,
+            fallback_chunks: fallbacks,
+            controls: stats.controls,
+            extra_size: stats.extra_size,
+            similarity,
+            anomalies: similarity
+                .map(|similarity| stats.anomalies(similarity))
+                .unwrap_or_default(),
+            full_fallback: false,
+        })
+    }
+
+    /// Start searching matches in target read from a stream window by window,
+    /// and construct the patch file.
+    ///
+    /// The target data set by `target` is ignored. Instead, the target stream
+    /// is read in windows of `window` bytes (no less than 256 KiB), each of
+    /// them compared against the whole source and encoded before reading the
+    /// next, thus memory usage is bounded by the window size plus the size of
+    /// compressed sections, rather than the target size.
+    /// Matches across window boundaries are lost, so the patch might be
+    /// slightly bigger than the one produced by `compare`.
+    ///
+    /// Example:
+    ///
+    /// Diff a huge target piped through stdin:
+    /// ```no_run
+    /// use std::io;
+    /// use qbsdiff::Bsdiff;
+    ///
+    /// fn bsdiff_stdin(source: &[u8]) -> io::Result<u64> {
+    ///     Bsdiff::new(source, &[]).compare_reader(io::stdin().lock(), 64 << 20, io::stdout().lock())
+    /// }
+    /// ```
+    ///
+    /// The size of patch file would be returned if no error occurs.
+    pub fn compare_reader<R: Read, P: Write>(&self, mut target: R, window: usize, patch: P) -> Result<u64> {
+        let config = PackConfig {
+            micro: false,
+            ..self.pack_config()?
+        };
+        let match_config = self.match_config();
+        let window = Ord::max(window, MIN_CHUNK);
+
+        let owned_index;
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                owned_index = self.build_index()?;
+                &owned_index
+            }
+        };
+
+        use ParallelScheme::*;
+        let threads = available_threads();
+        let mut packer = Packer::new(&config, self.source)?;
+        self.allocate(Allocation::Window(window))?;
+        let mut buf = Vec::with_capacity(window);
+        loop {
+            buf.clear();
+            (&mut target).take(window as u64).read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+
+            let (chunk, workers) = match self.parallel_scheme {
+                Never => (buf.len(), 1),
+                ChunkSize(chunk) => (chunk, threads),
+                NumJobs(jobs) => (div_ceil(buf.len(), jobs.saturating_mul(JOBS_PER_THREAD)), jobs),
+                Auto => (
+                    auto_chunk(self.source.len(), buf.len(), threads, sample_entropy(&buf)),
+                    threads,
+                ),
+            };
+            let chunk = Ord::max(chunk, MIN_CHUNK);
+            if chunk >= buf.len() {
+                let diff = SaDiff::new(self.source, &buf, index, &match_config);
+                packer.push(self.source, &buf, diff)?;
+            } else {
+                let mut par_diff = ParSaDiff::new(self.source, &buf, index, chunk, workers, &match_config);
+                self.push_parallel(&mut packer, &buf, &mut par_diff)?;
+            }
         }
+        self.describe(&mut packer, window);
+        packer.finish(patch)
     }
+
+    /// Start searching matches in target and write the patch file to an
+    /// asynchronous sink (requires feature `async`).
+    ///
+    /// Target chunks of the parallel scheme are searched one by one on the
+    /// calling task, yielding to the executor after each chunk is packed, then
+    /// the patch is written in chunks of `buffer_size`, yielding between chunks
+    /// as well.
+    /// The patch is the one produced by `compare`, except that target-relative
+    /// copies (see `target_copy`) are only found within each chunk.
+    ///
+    /// Searching is not divided in the other modes (`append_mostly`,
+    /// `line_aware`, `anchors` and the source windows of `memory_limit`), thus
+    /// the whole search blocks the calling task. Run `compare_to_vec` on a
+    /// blocking thread pool (e.g. tokio's `spawn_blocking`) instead for large
+    /// inputs in these modes.
+    ///
+    /// The size of patch file would be returned if no error occurs.
+    #[cfg(feature = "async")]
+    pub async fn compare_async<P: AsyncWrite + Unpin>(&self, mut patch: P) -> Result<u64> {
+        let config = self.pack_config()?;
+        let divided = !(self.source.is_empty()
+            || self.target.is_empty()
+            || self.append_mostly
+            || self.line_aware
+            || self.anchors.is_some()
+            || self.source_window().is_some()
+            || self.source == self.target);
+        let sections = if divided {
+            self.search_async(config).await?
+        } else {
+            let mut sealed = None;
+            self.compare_with(config, |packer| {
+                let (sections, _) = self.seal(packer)?;
+                let size = sections.size();
+                sealed = Some(sections);
+                Ok(size)
+            })?;
+            sealed.ok_or_else(|| Error::other("patch sections not sealed"))?
+        };
+
+        for part in sections.parts() {
+            for chunk in part.chunks(self.buffer_size) {
+                patch.write_all(chunk).await?;
+                yield_now().await;
+            }
+        }
+        patch.flush().await?;
+        Ok(sections.size())
+    }
+
+    /// Search target chunks one by one and pack them, yielding after each
+    /// chunk, see `compare_async`.
+    #[cfg(feature = "async")]
+    async fn search_async(&self, config: PackConfig) -> Result<Sections> {
+        let match_config = self.match_config();
+        let owned_index;
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                owned_index = self.build_index()?;
+                &owned_index
+            }
+        };
+
+        let mut packer = Packer::new(&config, self.source)?;
+        let (chunk, _, _) = self.chunking(available_threads());
+        let chunk = Ord::min(chunk, self.target.len());
+        if chunk == self.target.len() {
+            let mut diff = SaDiff::new(self.source, self.target, index, &match_config).hinted(&match_config, 0);
+            packer.push(self.source, self.target, &mut diff)?;
+            yield_now().await;
+        } else {
+            for (k, target) in self.target.chunks(chunk).enumerate() {
+                let mut diff = SaDiff::new(self.source, target, index, &match_config).hinted(&match_config, k * chunk);
+                let ctrls = search_chunk(&mut diff);
+                self.allocate(Allocation::Controls(mem::size_of_val(&ctrls[..])))?;
+                packer.push(self.source, target, ctrls.into_iter())?;
+                yield_now().await;
+            }
+        }
+        self.describe(&mut packer, chunk);
+        let (sections, _) = self.seal(packer)?;
+        Ok(sections)
+    }
+
+    /// Determine parallel chunk size and number of workers, and the sampled
+    /// target entropy if chosen by `ParallelScheme::Auto`.
+    fn chunking(&self, threads: usize) -> (usize, usize, Option<f64>) {
+        use ParallelScheme::*;
+        let mut workers = threads;
+        let mut entropy = None;
+        let chunk = match self.parallel_scheme {
+            _ if self.line_aware || self.anchors.is_some() => self.target.len(),
+            Never => self.target.len(),
+            ChunkSize(chunk) => chunk,
+            NumJobs(jobs) => {
+                workers = jobs;
+                div_ceil(self.target.len(), jobs.saturating_mul(JOBS_PER_THREAD))
+            }
+            Auto => {
+                let e = sample_entropy(self.target);
+                entropy = Some(e);
+                auto_chunk(self.source.len(), self.target.len(), threads, e)
+            }
+        };
+        (Ord::max(chunk, MIN_CHUNK), workers, entropy)
+    }
+
+    /// Check the format settings and collect them for packing.
+    fn pack_config(&self) -> Result<PackConfig> {
+        let codecs = self.section_codecs();
+        if self.format == Format::Classic && codecs.iter().any(|&(codec, _)| codec != Codec::Bzip2) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "classic bsdiff 4.x format only supports bzip2",
+            ));
+        }
+        if self.format == Format::Classic && codecs.iter().any(|&(_, level)| level == 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "compression level 0 (store) requires the extended format",
+            ));
+        }
+        if self.format == Format::Classic && (self.target_copy || self.dedupe) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "target-relative copies require the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.compact_seek {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "compact seeks require the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.decoded_sizes {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "decoded sizes require the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.explicit_sizes {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "explicit sizes require the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.block_size > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "blocks require the extended format",
+            ));
+        }
+        if self.block_size > MAX_BLOCK_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size is too large"));
+        }
+        if self.format == Format::Classic && self.source_checksum > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source checksums require the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.metadata {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "metadata requires the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.auto_codec.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "automatic codec selection requires the extended format",
+            ));
+        }
+        if self.format == Format::Classic && self.checksum.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "checksums require the extended format",
+            ));
+        }
+        if self.checksum.is_some_and(|kind| !kind.is_supported()) {
+            return Err(Error::new(ErrorKind::Unsupported, "checksum not compiled in"));
+        }
+        let micro = self.format == Format::Extended
+            && self.micro > 0
+            && self.target.len() <= self.micro
+            && !(self.target_copy || self.dedupe || self.decoded_sizes || self.explicit_sizes || self.metadata)
+            && self.block_size == 0
+            && self.source_checksum == 0
+            && self.checksum.is_none();
+        Ok(PackConfig {
+            format: self.format,
+            codecs: codecs.map(|(codec, level)| if level == 0 { (Codec::Stored, 0) } else { (codec, level) }),
+            auto_levels: self.auto_levels || micro,
+            auto_codec: self.auto_codec,
+            buffer_size: self.buffer_size,
+            target_copy: self.target_copy || self.dedupe,
+            compact_seek: self.compact_seek,
+            decoded_sizes: self.decoded_sizes,
+            explicit_sizes: self.explicit_sizes,
+            block_size: self.block_size,
+            max_lengths: [self.max_add, self.max_copy],
+            micro,
+            dedupe: self.dedupe,
+            source_checksum: self.source_checksum,
+            checksum: self.checksum,
+            controls_only: false,
+        })
+    }
+
+    /// Collect the matching settings.
+    fn match_config(&self) -> MatchConfig {
+        MatchConfig {
+            small_match: self.small_match,
+            mismatch_count: self.mismatch_count,
+            long_suffix: self.long_suffix,
+            scoring: self.scoring.clone(),
+            work_limit: self.work_limit,
+            hints: self.hints.clone(),
+        }
+    }
+}
+
+/// Length of the common prefix, compared block by block.
+fn common_prefix(x: &[u8], y: &[u8]) -> usize {
+    const BLOCK: usize = 4096;
+    let blocks = x.chunks(BLOCK).zip(y.chunks(BLOCK)).take_while(|(a, b)| a == b).count();
+    let start = blocks * BLOCK;
+    let (x, y) = (&x[Ord::min(start, x.len())..], &y[Ord::min(start, y.len())..]);
+    start + x.iter().zip(y).take_while(|(a, b)| a == b).count()
 }
 
 /// Calculate `ceil(x/y)`.
 #[inline]
 fn div_ceil(x: usize, y: usize) -> usize {
-    if x % y == 0 {
+    if x.is_multiple_of(y) {
         x / y
     } else {
         x / y + 1
     }
-}
+}
+
+/// Number of threads available for parallel jobs.
+#[cfg(feature = "parallel")]
+pub(crate) fn available_threads() -> usize {
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    Ord::max(Ord::min(threads, rayon::current_num_threads()), 1)
+}
+
+/// Number of threads available for parallel jobs, only the current one
+/// without feature `parallel`.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn available_threads() -> usize {
+    1
+}
+
+/// Estimate Shannon entropy (bits per byte) of data by sampling windows evenly.
+fn sample_entropy(data: &[u8]) -> f64 {
+    let mut freq = [0u64; 256];
+    let window = Ord::min(ENTROPY_PROBE_SIZE, data.len());
+    let probes = Ord::min(ENTROPY_PROBES, div_ceil(data.len(), ENTROPY_PROBE_SIZE));
+    for i in 0..probes {
+        let start = if probes > 1 {
+            (data.len() - window) / (probes - 1) * i
+        } else {
+            0
+        };
+        for &b in data[start..start + window].iter() {
+            freq[b as usize] += 1;
+        }
+    }
+
+    let total = (probes * window) as f64;
+    freq.iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Determine chunk size of `ParallelScheme::Auto`.
+///
+/// Every thread gets a few chunks for load balancing. Chunking breaks long
+/// matches across chunk boundaries, thus redundant (low entropy) targets get
+/// larger chunks. Searching in a small source is cheap, where parallelism
+/// gains less than it costs.
+fn auto_chunk(source_size: usize, target_size: usize, threads: usize, entropy: f64) -> usize {
+    if threads <= 1 || target_size < 2 * MIN_CHUNK {
+        return target_size;
+    }
+    let mut chunk = div_ceil(target_size, threads * JOBS_PER_THREAD);
+    if entropy < 4.0 {
+        chunk = chunk.saturating_mul(4);
+    } else if entropy < 6.0 {
+        chunk = chunk.saturating_mul(2);
+    }
+    if source_size < MIN_CHUNK {
+        chunk = chunk.saturating_mul(2);
+    }
+    Ord::max(chunk, MIN_CHUNK)
+}
+
+/// Patch construction settings.
+pub(crate) struct PackConfig {
+    pub format: Format,
+    pub codecs: [(Codec, u32); 3],
+    pub auto_levels: bool,
+    pub auto_codec: Option<CodecPriority>,
+    pub buffer_size: usize,
+    pub target_copy: bool,
+    pub compact_seek: bool,
+    pub decoded_sizes: bool,
+    pub explicit_sizes: bool,
+    pub block_size: usize,
+    pub max_lengths: [usize; 2],
+    pub micro: bool,
+    pub dedupe: bool,
+    pub source_checksum: usize,
+    pub checksum: Option<ChecksumKind>,
+    pub controls_only: bool,
+}
+
+/// Construct bsdiff 4.x or extended patch file from parts.
+pub(crate) fn pack<D, P>(source: &[u8], target: &[u8], diff: D, patch: P, config: &PackConfig) -> Result<u64>
+where
+    D: Iterator<Item = Control>,
+    P: Write,
+{
+    let mut packer = Packer::new(config, source)?;
+    packer.push(source, target, diff)?;
+    packer.finish(patch)
+}
+
+/// Create the encoder of a patch section.
+fn section(config: &PackConfig, section: Section) -> Result<SectionEncoder> {
+    let (codec, level) = config.codecs[section.index()];
+    SectionEncoder::new(
+        codec,
+        level,
+        config.auto_levels,
+        config.format == Format::Extended,
+        config.auto_codec,
+        config.block_size,
+    )
+}
+
+/// Split the control into controls of at most `max_add` delta bytes and
+/// `max_copy` extra bytes, the last one carrying the seek and the
+/// target-relative copy.
+fn split_control(ctrl: Control, max_add: u64, max_copy: u64) -> impl Iterator<Item = Control> {
+    let mut rest = Some(ctrl);
+    iter::from_fn(move || {
+        let ctrl = rest.as_mut()?;
+        if ctrl.add > max_add {
+            ctrl.add -= max_add;
+            return Some(Control {
+                add: max_add,
+                ..Control::default()
+            });
+        }
+        if ctrl.copy > max_copy {
+            ctrl.copy -= max_copy;
+            return Some(Control {
+                add: mem::take(&mut ctrl.add),
+                copy: max_copy,
+                ..Control::default()
+            });
+        }
+        rest.take()
+    })
+}
+
+/// Compute the bytewise difference of equally sized slices `new - old`.
+///
+/// Slices of equal length let the loop be vectorized.
+fn subtract(dat: &mut [u8], old: &[u8], new: &[u8]) {
+    for ((d, &x), &y) in dat.iter_mut().zip(old).zip(new) {
+        *d = y.wrapping_sub(x);
+    }
+}
+
+/// Incremental constructor of patch files, encoding consecutive target
+/// windows into in-memory sections.
+pub(crate) struct Packer {
+    format: Format,
+    bsize: usize,
+    ctrls: SectionEncoder,
+    delta: SectionEncoder,
+    extra: SectionEncoder,
+    dat: Vec<u8>,
+    layout: ControlLayout,
+    decoded: Option<[u64; 3]>,
+    explicit_sizes: bool,
+    block_size: usize,
+    max_lengths: [u64; 2],
+    dedupe: bool,
+    ssize: u64,
+    swindow: usize,
+    stable: Vec<u8>,
+    spos: u64,
+    tsize: u64,
+    tdist: u64,
+    stats: ControlStats,
+    metadata: Option<Vec<u8>>,
+    checksum: Option<ChecksumKind>,
+    recorded: Option<Vec<Control>>,
+}
+
+impl Packer {
+    /// Create patch constructor.
+    pub fn new(config: &PackConfig, source: &[u8]) -> Result<Self> {
+        Ok(Packer {
+            format: config.format,
+            bsize: config.buffer_size,
+            ctrls: section(config, Section::Control)?,
+            delta: section(config, Section::Delta)?,
+            extra: section(config, Section::Extra)?,
+            dat: vec![0; config.buffer_size],
+            layout: ControlLayout {
+                target_copy: config.target_copy,
+                compact_seek: config.compact_seek && !config.micro,
+                varint: config.micro,
+            },
+            decoded: Some([0; 3]).filter(|_| config.decoded_sizes || config.explicit_sizes),
+            explicit_sizes: config.explicit_sizes,
+            block_size: config.block_size,
+            max_lengths: config
+                .max_lengths
+                .map(|len| if len == 0 { u64::MAX } else { len as u64 }),
+            dedupe: config.dedupe,
+            ssize: source.len() as u64,
+            swindow: config.source_checksum,
+            stable: if config.source_checksum > 0 {
+                Header::encode_source_checksums(source, config.source_checksum)
+            } else {
+                Vec::new()
+            },
+            spos: 0,
+            tsize: 0,
+            tdist: 0,
+            stats: ControlStats::default(),
+            metadata: None,
+            checksum: config.checksum,
+            recorded: Some(Vec::new()).filter(|_| config.controls_only),
+        })
+    }
+
+    /// Attach metadata to the header.
+    pub fn metadata(&mut self, metadata: String) {
+        self.metadata = Some(metadata.into_bytes());
+    }
+
+    /// Encode the controls of the next target window, which are relative to
+    /// the start of source.
+    pub fn push<D>(&mut self, source: &[u8], target: &[u8], diff: D) -> Result<()>
+    where
+        D: Iterator<Item = Control>,
+    {
+        if self.recorded.is_some() {
+            self.record(target, diff);
+            Ok(())
+        } else if self.layout.target_copy {
+            let ctrls = dedupe(target, diff, self.dedupe);
+            self.encode(source, target, ctrls.into_iter())
+        } else {
+            self.encode(source, target, diff)
+        }
+    }
 
-/// Construct bsdiff 4.x patch file from parts.
-fn pack<D, P>(source: &[u8], target: &[u8], diff: D, mut patch: P, level: Compression, bsize: usize) -> Result<u64>
-where
-    D: Iterator<Item = Control>,
-    P: Write,
-{
-    let mut bz_ctrls = Vec::new();
-    let mut bz_delta = Vec::new();
-    let mut bz_extra = Vec::new();
+    /// Statistics of the controls encoded so far.
+    pub fn stats(&self) -> ControlStats {
+        self.stats
+    }
+
+    /// Take the controls recorded so far, see `PackConfig::controls_only`.
+    pub fn into_controls(self) -> Vec<Control> {
+        self.recorded.unwrap_or_default()
+    }
+
+    /// Record the controls as a single stream instead of encoding them.
+    fn record<D>(&mut self, target: &[u8], diff: D)
+    where
+        D: Iterator<Item = Control>,
+    {
+        let Some(recorded) = self.recorded.as_mut() else {
+            return;
+        };
+        if self.spos != 0 {
+            recorded.push(Control {
+                seek: (self.spos as i64).wrapping_neg(),
+                ..Control::default()
+            });
+        }
+        let mut spos = 0u64;
+        for ctrl in diff {
+            self.stats.record(&ctrl);
+            spos = spos.wrapping_add(ctrl.add).wrapping_add(ctrl.seek as u64);
+            recorded.push(ctrl);
+        }
+        self.spos = spos;
+        self.tsize += target.len() as u64;
+    }
 
+    /// Encode the controls as is.
+    fn encode<D>(&mut self, source: &[u8], target: &[u8], diff: D) -> Result<()>
+    where
+        D: Iterator<Item = Control>,
     {
-        let mut ctrls = BzEncoder::new(Cursor::new(&mut bz_ctrls), level);
-        let mut delta = BzEncoder::new(Cursor::new(&mut bz_delta), level);
-        let mut extra = BzEncoder::new(Cursor::new(&mut bz_extra), level);
+        // Rewind the source cursor left by the previous window.
+        if self.spos != 0 {
+            let seek = (self.spos as i64).wrapping_neg();
+            self.control(&Control {
+                seek,
+                ..Control::default()
+            })?;
+        }
 
         let mut spos = 0;
         let mut tpos = 0;
-        let mut cbuf = [0; 24];
-
-        let mut dat = Vec::with_capacity(bsize);
-
-        for ctrl in diff {
-            // Write control data.
-            encode_int(ctrl.add as i64, &mut cbuf[0..8]);
-            encode_int(ctrl.copy as i64, &mut cbuf[8..16]);
-            encode_int(ctrl.seek, &mut cbuf[16..24]);
-            ctrls.write_all(&cbuf[..])?;
+        let [max_add, max_copy] = self.max_lengths;
+        for ctrl in diff.flat_map(|ctrl| split_control(ctrl, max_add, max_copy)) {
+            self.control(&ctrl)?;
+            self.stats.record(&ctrl);
 
-            // Compute and write delta data, using limited buffer `dat`.
+            // Compute and write delta data, in chunks of buffer `dat`.
             if ctrl.add > 0 {
-                let mut n = ctrl.add;
-                while n > 0 {
-                    let k = Ord::min(n, bsize as u64) as usize;
-
-                    dat.extend(
-                        Iterator::zip(source[spos as usize..].iter(), target[tpos as usize..].iter())
-                            .map(|(x, y)| y.wrapping_sub(*x))
-                            .take(k),
-                    );
-
-                    delta.write_all(&dat[..])?;
-                    dat.clear();
-
-                    spos += k as u64;
-                    tpos += k as u64;
-                    n -= k as u64;
+                let add = ctrl.add as usize;
+                let old = &source[spos as usize..spos as usize + add];
+                let new = &target[tpos as usize..tpos as usize + add];
+                for (old, new) in Iterator::zip(old.chunks(self.bsize), new.chunks(self.bsize)) {
+                    let dat = &mut self.dat[..old.len()];
+                    subtract(dat, old, new);
+                    self.delta.write_all(dat)?;
                 }
+                if let Some(decoded) = self.decoded.as_mut() {
+                    decoded[1] += ctrl.add;
+                }
+                spos += ctrl.add;
+                tpos += ctrl.add;
             }
 
             // Write extra data.
             if ctrl.copy > 0 {
-                extra.write_all(&target[tpos as usize..(tpos + ctrl.copy) as usize])?;
+                self.extra
+                    .write_all(&target[tpos as usize..(tpos + ctrl.copy) as usize])?;
+                if let Some(decoded) = self.decoded.as_mut() {
+                    decoded[2] += ctrl.copy;
+                }
                 tpos += ctrl.copy;
             }
 
+            if ctrl.tcopy > 0 {
+                self.tdist = Ord::max(self.tdist, ctrl.tdist);
+                tpos += ctrl.tcopy;
+            }
+
             spos = spos.wrapping_add(ctrl.seek as u64);
         }
-        ctrls.flush()?;
-        delta.flush()?;
-        extra.flush()?;
+        self.spos = spos;
+        self.tsize += target.len() as u64;
+        Ok(())
+    }
+
+    /// Write control data.
+    fn control(&mut self, ctrl: &Control) -> Result<()> {
+        let mut cbuf = [0; ControlLayout::MAX_SIZE];
+        let values = [
+            ctrl.add as i64,
+            ctrl.copy as i64,
+            ctrl.seek,
+            ctrl.tcopy as i64,
+            ctrl.tdist as i64,
+        ];
+        let n = self.layout.encode(values, &mut cbuf);
+        if let Some(decoded) = self.decoded.as_mut() {
+            decoded[0] += n as u64;
+        }
+        self.ctrls.write_all(&cbuf[..n])
+    }
+
+    /// Finish the sections and write the patch file.
+    pub fn finish<P: Write>(self, patch: P) -> Result<u64> {
+        self.seal()?.write(patch)
+    }
+
+    /// Finish the compressed sections and encode the header.
+    pub fn seal(self) -> Result<Sections> {
+        let (ccodec, bz_ctrls, cblocks) = self.ctrls.finish()?;
+        let (dcodec, bz_delta, dblocks) = self.delta.finish()?;
+        let (ecodec, bz_extra, eblocks) = self.extra.finish()?;
+
+        // Encode header (magic, section sizes, target size).
+        let csize = bz_ctrls.len() as u64;
+        let dsize = bz_delta.len() as u64;
+        let esize = bz_extra.len() as u64;
+        let mut header = Header::new(self.format, [ccodec, dcodec, ecodec], csize, dsize, esize, self.tsize);
+        if self.layout.compact_seek {
+            header = header.compact_seek();
+        }
+        if self.layout.varint {
+            header = header.micro();
+        }
+        if let Some(decoded) = self.decoded {
+            header = header.decoded_sizes(decoded);
+        }
+        if self.explicit_sizes {
+            header = header.explicit_sizes(self.ssize);
+        }
+        if self.layout.target_copy {
+            // Smallest window covering all the distances.
+            let window_log = 64 - self.tdist.saturating_sub(1).leading_zeros();
+            header = header.target_copy(window_log as u8);
+        }
+        if self.swindow > 0 {
+            header = header.source_checksum(self.ssize, self.swindow as u64);
+        }
+        let metadata = match self.metadata {
+            Some(ref metadata) => {
+                header = header.metadata(metadata.len() as u32);
+                Header::encode_metadata(&metadata[..])
+            }
+            None => Vec::new(),
+        };
+        let blocks = match (cblocks, dblocks, eblocks) {
+            (Some(cblocks), Some(dblocks), Some(eblocks)) => {
+                let counts = [&cblocks, &dblocks, &eblocks].map(|blocks| blocks.len() as u32);
+                header = header.blocks(self.block_size as u64, counts);
+                Header::encode_block_table(self.block_size as u64, [&cblocks[..], &dblocks[..], &eblocks[..]])
+            }
+            _ => Vec::new(),
+        };
+        let trailer = match self.checksum {
+            Some(kind) => {
+                header = header.checksum(kind);
+                Header::encode_checksums(kind, &bz_ctrls, &bz_delta, &bz_extra)?
+            }
+            None => Vec::new(),
+        };
+        Ok(Sections {
+            header: header.encode(),
+            stable: self.stable,
+            metadata,
+            blocks,
+            ctrls: bz_ctrls,
+            delta: bz_delta,
+            extra: bz_extra,
+            trailer,
+        })
     }
+}
 
-    // Write header (BSDIFF4_MAGIC, control size, delta size, target size).
-    let mut header = [0; 32];
-    let csize = bz_ctrls.len() as u64;
-    let dsize = bz_delta.len() as u64;
-    let esize = bz_extra.len() as u64;
-    let tsize = target.len() as u64;
-    header[0..8].copy_from_slice(BSDIFF4_MAGIC);
-    encode_int(csize as i64, &mut header[8..16]);
-    encode_int(dsize as i64, &mut header[16..24]);
-    encode_int(tsize as i64, &mut header[24..32]);
-    patch.write_all(&header[..])?;
+/// Encoded parts of a patch.
+pub(crate) struct Sections {
+    header: Vec<u8>,
+    stable: Vec<u8>,
+    metadata: Vec<u8>,
+    blocks: Vec<u8>,
+    ctrls: Vec<u8>,
+    delta: Vec<u8>,
+    extra: Vec<u8>,
+    trailer: Vec<u8>,
+}
+
+impl Sections {
+    /// Total size of patch.
+    pub fn size(&self) -> u64 {
+        self.parts().iter().map(|part| part.len() as u64).sum()
+    }
 
-    // Write compressed controls, delta data and extra data.
-    patch.write_all(&bz_ctrls[..])?;
-    patch.write_all(&bz_delta[..])?;
-    patch.write_all(&bz_extra[..])?;
-    patch.flush()?;
+    /// Write header, checksums of source windows, metadata, block table,
+    /// compressed controls, delta data, extra data and checksums of them.
+    pub fn write<P: Write>(self, mut patch: P) -> Result<u64> {
+        for part in self.parts() {
+            patch.write_all(part)?;
+        }
+        patch.flush()?;
+        Ok(self.size())
+    }
 
-    Ok(32 + csize + dsize + esize)
+    /// Parts of patch in order.
+    pub fn parts(&self) -> [&[u8]; 8] {
+        [
+            &self.header,
+            &self.stable,
+            &self.metadata,
+            &self.blocks,
+            &self.ctrls,
+            &self.delta,
+            &self.extra,
+            &self.trailer,
+        ]
+    }
 }
 
 /// Paralleled searching by dividing chunks of target.
+///
+/// Chunks are claimed from a shared queue by a limited number of workers, and
+/// the results are collected or streamed in order, thus the output does not
+/// depend on the scheduling.
 struct ParSaDiff<'s, 't> {
-    jobs: Vec<SaDiff<'s, 't>>,
+    jobs: Vec<Mutex<SaDiff<'s, 't>>>,
+    workers: usize,
 }
 
 impl<'s, 't> ParSaDiff<'s, 't> {
@@ -352,57 +2801,441 @@ impl<'s, 't> ParSaDiff<'s, 't> {
     pub fn new(
         s: &'s [u8],
         t: &'t [u8],
-        sa: &'s SuffixArray<'s>,
+        sa: &'s SourceIndex<'s>,
         chunk: usize,
-        small_match: usize,
-        mismatch_count: usize,
-        long_suffix: usize,
+        workers: usize,
+        config: &MatchConfig,
     ) -> Self {
         let jobs = t
             .chunks(chunk)
-            .map(|ti| SaDiff::new(s, ti, sa, small_match, mismatch_count, long_suffix))
+            .enumerate()
+            .map(|(k, ti)| Mutex::new(SaDiff::new(s, ti, sa, config).hinted(config, k * chunk)))
             .collect();
-        ParSaDiff { jobs }
-    }
-
-    /// Compute all the bsdiff controls in parallel.
-    pub fn compute(mut self) -> Vec<Control> {
-        self.jobs
-            .par_iter_mut()
-            .map(|diff| {
-                // Search current chunk.
-                let mut pos = 0u64;
-                let mut ctrls = Vec::new();
-                for ctl in diff {
-                    pos += ctl.add;
-                    pos = pos.wrapping_add(ctl.seek as u64);
-                    ctrls.push(ctl);
-                }
+        ParSaDiff { jobs, workers }
+    }
 
-                // Reset source cursor (`pos <= MAX_LENGTH` would not overflow).
-                debug_assert!(pos <= i64::MAX as u64);
-                ctrls.push(Control {
-                    add: 0,
-                    copy: 0,
-                    seek: -(pos as i64),
+    /// Compute the bsdiff controls in parallel, feeding the controls of each
+    /// chunk to `consume` in order as soon as all the earlier chunks are done.
+    ///
+    /// Consuming (e.g. packing) overlaps searching the later chunks, and the
+    /// controls of a chunk are dropped once consumed instead of collected for
+    /// the whole target.
+    /// While the next chunk in order is still pending, the consuming thread
+    /// searches unclaimed chunks itself, thus it never waits on a busy pool.
+    pub fn stream<T, F>(&mut self, consume: F) -> T
+    where
+        F: FnOnce(&mut dyn Iterator<Item = Vec<Control>>) -> T,
+    {
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel();
+        #[cfg(feature = "parallel")]
+        return rayon::in_place_scope(|scope| {
+            for _ in 0..Ord::min(self.workers, self.jobs.len()) {
+                let (tx, next, jobs) = (tx.clone(), &next, &self.jobs);
+                scope.spawn(move |_| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= jobs.len() {
+                        break;
+                    }
+                    let ctrls = search_chunk(&mut jobs[i].lock().unwrap());
+                    if tx.send((i, ctrls)).is_err() {
+                        // The consumer gave up.
+                        break;
+                    }
                 });
+            }
+            drop(tx);
+            consume(&mut Ordered::new(&self.jobs, &next, rx))
+        });
+
+        // All chunks are searched by the consumer itself.
+        #[cfg(not(feature = "parallel"))]
+        {
+            drop(tx);
+            consume(&mut Ordered::new(&self.jobs, &next, rx))
+        }
+    }
 
-                ctrls
-            })
-            .flatten()
-            .collect()
+    /// Compute the bsdiff controls of each chunk in parallel.
+    pub fn compute_chunks(&mut self) -> Vec<Vec<Control>> {
+        self.run(search_chunk)
+    }
+
+    /// Count the exact matches of all chunks in parallel.
+    pub fn compute_histogram(&mut self) -> MatchHistogram {
+        let mut histogram = MatchHistogram::default();
+        for h in self.run(histogram_chunk) {
+            histogram.merge(&h);
+        }
+        histogram
+    }
+
+    /// Run the search job on each chunk in parallel.
+    fn run<R, F>(&mut self, job: F) -> Vec<R>
+    where
+        R: Default + Send,
+        F: Fn(&mut SaDiff) -> R + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<R>> = self.jobs.iter().map(|_| Mutex::new(R::default())).collect();
+
+        let worker = || loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            if i >= self.jobs.len() {
+                break;
+            }
+            let mut diff = self.jobs[i].lock().unwrap();
+            *results[i].lock().unwrap() = job(&mut diff);
+        };
+        #[cfg(feature = "parallel")]
+        rayon::scope(|scope| {
+            for _ in 0..Ord::min(self.workers, self.jobs.len()) {
+                scope.spawn(|_| worker());
+            }
+        });
+        #[cfg(not(feature = "parallel"))]
+        worker();
+
+        results.into_iter().map(|r| r.into_inner().unwrap()).collect()
+    }
+
+    /// Total searching steps and number of chunks exceeding the budget.
+    pub fn work(&self) -> (u64, usize) {
+        self.jobs.iter().fold((0, 0), |(steps, fallbacks), diff| {
+            let (s, f) = diff.lock().unwrap().work();
+            (steps + s, fallbacks + f)
+        })
+    }
+}
+
+/// Controls of parallel chunks in order, see `ParSaDiff::stream`.
+struct Ordered<'a, 's, 't> {
+    jobs: &'a [Mutex<SaDiff<'s, 't>>],
+    next: &'a AtomicUsize,
+    results: Receiver<(usize, Vec<Control>)>,
+    pending: BTreeMap<usize, Vec<Control>>,
+    want: usize,
+}
+
+impl<'a, 's, 't> Ordered<'a, 's, 't> {
+    fn new(jobs: &'a [Mutex<SaDiff<'s, 't>>], next: &'a AtomicUsize, results: Receiver<(usize, Vec<Control>)>) -> Self {
+        Ordered {
+            jobs,
+            next,
+            results,
+            pending: BTreeMap::new(),
+            want: 0,
+        }
+    }
+}
+
+impl Iterator for Ordered<'_, '_, '_> {
+    type Item = Vec<Control>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.want >= self.jobs.len() {
+            return None;
+        }
+        loop {
+            if let Some(ctrls) = self.pending.remove(&self.want) {
+                self.want += 1;
+                return Some(ctrls);
+            }
+            if let Ok((i, ctrls)) = self.results.try_recv() {
+                self.pending.insert(i, ctrls);
+                continue;
+            }
+
+            // Help searching instead of waiting, unless all chunks are claimed.
+            let i = self.next.fetch_add(1, Ordering::Relaxed);
+            let (i, ctrls) = if i < self.jobs.len() {
+                (i, search_chunk(&mut self.jobs[i].lock().unwrap()))
+            } else {
+                // Workers are gone only if one of them panicked, which is
+                // propagated by the scope.
+                self.results.recv().ok()?
+            };
+            self.pending.insert(i, ctrls);
+        }
+    }
+}
+
+/// Builder of controls from consecutive target regions.
+#[derive(Default)]
+struct Splicer {
+    ctrls: Vec<Control>,
+    spos: u64,
+}
+
+impl Splicer {
+    /// Append target bytes of source bytes plus delta.
+    fn delta(&mut self, spos: u64, len: u64) {
+        let seek = spos.wrapping_sub(self.spos) as i64;
+        match self.ctrls.last_mut() {
+            Some(last) if seek == 0 && last.copy == 0 && last.seek == 0 => {
+                last.add += len;
+                self.spos = spos + len;
+                return;
+            }
+            Some(last) => last.seek = last.seek.wrapping_add(seek),
+            None if seek != 0 => self.ctrls.push(Control {
+                seek,
+                ..Control::default()
+            }),
+            None => (),
+        }
+        self.ctrls.push(Control {
+            add: len,
+            ..Control::default()
+        });
+        self.spos = spos + len;
+    }
+
+    /// Append target bytes from the extra section.
+    fn extra(&mut self, len: u64) {
+        match self.ctrls.last_mut() {
+            Some(last) if last.seek == 0 => last.copy += len,
+            _ => self.ctrls.push(Control {
+                copy: len,
+                ..Control::default()
+            }),
+        }
+    }
+
+    /// Append target bytes of the searched target chunk.
+    fn search(&mut self, diff: &mut SaDiff) -> (u64, usize) {
+        let mut spos = 0u64;
+        for ctl in search_chunk(diff) {
+            if ctl.add > 0 {
+                self.delta(spos, ctl.add);
+            }
+            if ctl.copy > 0 {
+                self.extra(ctl.copy);
+            }
+            spos = spos.wrapping_add(ctl.add).wrapping_add(ctl.seek as u64);
+        }
+        diff.work()
+    }
+}
+
+/// Controls checked on the fly to cover the target exactly, and only read
+/// the source within bounds.
+///
+/// Iteration stops at the first invalid control, which is reported by
+/// `finish`.
+struct CheckedControls<I> {
+    ctrls: I,
+    ssize: i128,
+    tsize: i128,
+    spos: i128,
+    tpos: i128,
+    error: Option<&'static str>,
+}
+
+impl<I: Iterator<Item = Control>> CheckedControls<I> {
+    fn new(ctrls: I, source_size: usize, target_size: usize) -> Self {
+        CheckedControls {
+            ctrls,
+            ssize: source_size as i128,
+            tsize: target_size as i128,
+            spos: 0,
+            tpos: 0,
+            error: None,
+        }
+    }
+
+    /// Check the control against the cursors.
+    fn check(&self, ctl: &Control) -> std::result::Result<(), &'static str> {
+        if ctl.tcopy != 0 || ctl.tdist != 0 {
+            return Err("target-relative copies are not accepted");
+        }
+        let (add, copy) = (ctl.add as i128, ctl.copy as i128);
+        if add > 0 && (self.spos < 0 || self.spos + add > self.ssize) {
+            return Err("control reads beyond the source");
+        }
+        if self.tpos + add + copy > self.tsize {
+            return Err("controls exceed the target");
+        }
+        Ok(())
+    }
+
+    /// Return error if any control is invalid, or the controls do not cover
+    /// the target.
+    fn finish(self) -> Result<()> {
+        match self.error {
+            Some(msg) => Err(Error::new(ErrorKind::InvalidInput, msg)),
+            None if self.tpos != self.tsize => {
+                Err(Error::new(ErrorKind::InvalidInput, "controls do not cover the target"))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Control>> Iterator for CheckedControls<I> {
+    type Item = Control;
+
+    fn next(&mut self) -> Option<Control> {
+        if self.error.is_some() {
+            return None;
+        }
+        let ctl = self.ctrls.next()?;
+        if let Err(msg) = self.check(&ctl) {
+            self.error = Some(msg);
+            return None;
+        }
+        self.spos += ctl.add as i128 + ctl.seek as i128;
+        self.tpos += ctl.add as i128 + ctl.copy as i128;
+        Some(ctl)
+    }
+}
+
+/// Search a chunk of target, and reset the source cursor at the end.
+fn search_chunk(diff: &mut SaDiff) -> Vec<Control> {
+    let mut pos = 0u64;
+    let mut ctrls = Vec::new();
+    for ctl in diff {
+        pos += ctl.add;
+        pos = pos.wrapping_add(ctl.seek as u64);
+        ctrls.push(ctl);
+    }
+
+    // Reset source cursor (`pos <= MAX_LENGTH` would not overflow).
+    debug_assert!(pos <= i64::MAX as u64);
+    ctrls.push(Control {
+        seek: -(pos as i64),
+        ..Control::default()
+    });
+
+    ctrls
+}
+
+/// Count the exact matches of a target chunk.
+fn histogram_chunk(diff: &mut SaDiff) -> MatchHistogram {
+    let mut histogram = MatchHistogram::default();
+    while let Some((ctl, n)) = diff.next_match() {
+        histogram.record(n, ctl.add);
+    }
+    histogram
+}
+
+/// Search target line by line, see `Bsdiff::line_aware`.
+///
+/// Matched lines are copied from source, and changed lines are diffed
+/// record by record against the source lines they replace, unless mostly
+/// extra in a long run (e.g. a new block of text), which is searched byte by
+/// byte instead.
+fn search_lines(s: &[u8], t: &[u8], sa: &SourceIndex, config: &MatchConfig) -> (Vec<Control>, (u64, usize)) {
+    let mut splicer = Splicer::default();
+    let mut work = (0, 0);
+    let runs = lines::match_lines(s, t);
+    for (i, run) in runs.iter().enumerate() {
+        if let Some(spos) = run.source {
+            splicer.delta(spos as u64, run.target.len() as u64);
+            continue;
+        }
+
+        let target = &t[run.target.clone()];
+        let base = splicer.spos as usize;
+        let next = runs.get(i + 1).and_then(|next| next.source).unwrap_or(s.len());
+        let pieces = lines::diff_changed(s, base..Ord::max(base, next), target);
+        let (steps, fallbacks) = splice_changed(&mut splicer, s, target, pieces, sa, config);
+        work = (work.0 + steps, work.1 + fallbacks);
+    }
+    (splicer.ctrls, work)
+}
+
+/// Search target segment by segment between anchors, see `Bsdiff::anchors`.
+///
+/// Like `search_lines`, matched segments are copied from source, and changed
+/// segments are diffed record by record against the source segments they
+/// replace, unless mostly extra in a long run.
+fn search_anchored(
+    s: &[u8],
+    t: &[u8],
+    sa: &SourceIndex,
+    config: &MatchConfig,
+    anchors: &AnchorFinder,
+) -> (Vec<Control>, (u64, usize)) {
+    let mut splicer = Splicer::default();
+    let mut work = (0, 0);
+    let ssegments = lines::split_anchors(s.len(), anchors(s));
+    let tsegments = lines::split_anchors(t.len(), anchors(t));
+    let runs = lines::match_segments(s, &ssegments, t, &tsegments);
+    for (i, run) in runs.iter().enumerate() {
+        if let Some(spos) = run.source {
+            splicer.delta(spos as u64, run.target.len() as u64);
+            continue;
+        }
+
+        let base = splicer.spos as usize;
+        let next = runs.get(i + 1).and_then(|next| next.source).unwrap_or(s.len());
+        let gap = base..Ord::max(base, next);
+        let pieces = lines::diff_changed_segments(s, &ssegments, gap, t, &tsegments, run.target.clone());
+        let (steps, fallbacks) = splice_changed(&mut splicer, s, &t[run.target.clone()], pieces, sa, config);
+        work = (work.0 + steps, work.1 + fallbacks);
+    }
+    (splicer.ctrls, work)
+}
+
+/// Append the pieces of changed target lines or segments, or search them
+/// byte by byte instead if mostly extra in a long run (e.g. new data).
+fn splice_changed(
+    splicer: &mut Splicer,
+    s: &[u8],
+    target: &[u8],
+    pieces: Vec<Piece>,
+    sa: &SourceIndex,
+    config: &MatchConfig,
+) -> (u64, usize) {
+    let extra: usize = pieces
+        .iter()
+        .map(|piece| match *piece {
+            Piece::Extra(len) => len,
+            Piece::Delta(..) => 0,
+        })
+        .sum();
+    if target.len() >= LINE_SEARCH && extra * 2 > target.len() {
+        let mut diff = SaDiff::new(s, target, sa, config);
+        return splicer.search(&mut diff);
     }
+    for piece in pieces {
+        match piece {
+            Piece::Delta(spos, len) => splicer.delta(spos as u64, len as u64),
+            Piece::Extra(len) => splicer.extra(len as u64),
+        }
+    }
+    (0, 0)
+}
+
+/// Matching settings.
+#[derive(Clone)]
+struct MatchConfig {
+    small_match: usize,
+    mismatch_count: usize,
+    long_suffix: usize,
+    scoring: Option<Arc<Scoring>>,
+    work_limit: Option<usize>,
+    /// Pairs of source and target offsets, sorted by target offsets.
+    hints: Arc<[(usize, usize)]>,
 }
 
 /// The delta compression algorithm based on suffix array (a variant of bsdiff 4.x).
 struct SaDiff<'s, 't> {
     s: &'s [u8],
     t: &'t [u8],
-    sa: &'s SuffixArray<'s>,
+    sa: &'s SourceIndex<'s>,
 
     small_match: usize,
     mismatch_count: usize,
     long_suffix: usize,
+    scoring: Option<Arc<Scoring>>,
+
+    steps: u64,
+    budget: u64,
+
+    /// Hints not reached yet, relative to `t`, in reversed order.
+    hints: Vec<(usize, usize)>,
 
     i0: usize,
     j0: usize,
@@ -412,21 +3245,21 @@ struct SaDiff<'s, 't> {
 
 impl<'s, 't> SaDiff<'s, 't> {
     /// Creates new search context.
-    pub fn new(
-        s: &'s [u8],
-        t: &'t [u8],
-        sa: &'s SuffixArray<'s>,
-        small_match: usize,
-        mismatch_count: usize,
-        long_suffix: usize,
-    ) -> Self {
+    pub fn new(s: &'s [u8], t: &'t [u8], sa: &'s SourceIndex<'s>, config: &MatchConfig) -> Self {
         SaDiff {
             s,
             t,
             sa,
-            small_match,
-            mismatch_count,
-            long_suffix,
+            small_match: config.small_match,
+            mismatch_count: config.mismatch_count,
+            long_suffix: config.long_suffix,
+            scoring: config.scoring.clone(),
+            steps: 0,
+            budget: match config.work_limit {
+                Some(factor) => (t.len() as u64).saturating_mul(factor as u64),
+                None => u64::MAX,
+            },
+            hints: Vec::new(),
             i0: 0,
             j0: 0,
             n0: 0,
@@ -434,6 +3267,25 @@ impl<'s, 't> SaDiff<'s, 't> {
         }
     }
 
+    /// Use the hints of the config within `t`, which starts at `offset` of the
+    /// whole target.
+    pub fn hinted(mut self, config: &MatchConfig, offset: usize) -> Self {
+        let end = offset + self.t.len();
+        self.hints = config
+            .hints
+            .iter()
+            .rev()
+            .filter(|&&(_, tpos)| offset <= tpos && tpos < end)
+            .map(|&(spos, tpos)| (spos, tpos - offset))
+            .collect();
+        self
+    }
+
+    /// Searching steps taken and whether the budget is exceeded (0 or 1).
+    pub fn work(&self) -> (u64, usize) {
+        (self.steps, usize::from(self.steps > self.budget))
+    }
+
     #[inline]
     fn previous_state(&self) -> (usize, usize, usize, usize) {
         (self.i0, self.j0, self.n0, self.b0)
@@ -447,6 +3299,37 @@ impl<'s, 't> SaDiff<'s, 't> {
         self.b0 = b0;
     }
 
+    /// Search the next control, with the length of the exact match seeding
+    /// its delta region.
+    fn next_match(&mut self) -> Option<(Control, usize)> {
+        if let Some((i, j, n)) = self.search_next() {
+            let (i0, j0, n0, b0) = self.previous_state();
+            let (a0, b) = self.shrink_gap(i, j);
+
+            // source:
+            //     ...(   b0   ,   n0   ,   a0   )...(   b   ,...
+            //        ^ spos   ^ i0                ^         ^ i
+            //                                     | distance can be negative
+            // target:
+            //     ...(   b0   ,   n0   ,   a0   ;   copy   )(   b   ,...
+            //        ^ tpos   ^ j0              ^ tpos+add          ^ j
+            let add = (b0 + n0 + a0) as u64;
+            let copy = ((j - b) - (j0 + n0 + a0)) as u64;
+            let seek = (i - b).wrapping_sub(i0 + n0 + a0) as isize as i64;
+
+            self.update_state(i, j, n, b);
+            let ctl = Control {
+                add,
+                copy,
+                seek,
+                ..Control::default()
+            };
+            Some((ctl, n0))
+        } else {
+            None
+        }
+    }
+
     /// Searches for the next exact match (i, j, n).
     #[inline]
     fn search_next(&mut self) -> Option<(usize, usize, usize)> {
@@ -459,8 +3342,16 @@ impl<'s, 't> SaDiff<'s, 't> {
         let mut k = j;
         let mut m = 0;
         while j < self.t.len().saturating_sub(self.small_match) {
+            if let Some(hint) = self.next_hint(j) {
+                return Some(hint);
+            }
+            if self.steps > self.budget {
+                return Some(self.search_fallback(j));
+            }
+
             // Finds out a possible exact match.
-            let (i, n) = range_to_extent(self.sa.search_lcp(&self.t[j..]));
+            let (i, n) = self.sa.search_lcp(&self.t[j..]);
+            self.steps += 1 + n as u64 + (j + n).saturating_sub(k) as u64;
 
             // Counts the matched bytes, and determine whether these bytes
             // should be treated as possible similar bytes, or simply as the
@@ -502,7 +3393,8 @@ impl<'s, 't> SaDiff<'s, 't> {
                     let mut y = n;
                     while x < y {
                         let z = x + (y - x) / 2;
-                        let (iz, nz) = range_to_extent(self.sa.search_lcp(&self.t[j + z..]));
+                        let (iz, nz) = self.sa.search_lcp(&self.t[j + z..]);
+                        self.steps += 1 + nz as u64;
                         if i + n == iz + nz && j + n == j + z + nz {
                             x = z + 1;
                         } else {
@@ -512,6 +3404,7 @@ impl<'s, 't> SaDiff<'s, 't> {
                     j + Ord::max(x, 1)
                 };
                 let mut i = self.i0.saturating_add(j - self.j0);
+                self.steps += (next - j) as u64;
                 while j < next {
                     if i < self.s.len() && self.s[i] == self.t[j] {
                         m -= 1;
@@ -529,6 +3422,50 @@ impl<'s, 't> SaDiff<'s, 't> {
         Some((self.s.len(), self.t.len(), 0))
     }
 
+    /// Take the hint at or before the scanning position `j` as the next exact
+    /// match (i, j, n), if it changes the alignment.
+    fn next_hint(&mut self, j: usize) -> Option<(usize, usize, usize)> {
+        while let Some(&(i, tpos)) = self.hints.last() {
+            if tpos > j {
+                return None;
+            }
+            self.hints.pop();
+            let aligned = i.wrapping_sub(tpos) == self.i0.wrapping_sub(self.j0);
+            if tpos < self.j0 + self.n0 || (aligned && self.n0 > 0) {
+                continue;
+            }
+            let n = self.s[i..]
+                .iter()
+                .zip(&self.t[tpos..])
+                .take_while(|(x, y)| x == y)
+                .count();
+            self.steps += 1 + n as u64;
+            return Some((i, tpos, n));
+        }
+        None
+    }
+
+    /// Searches for the next aligned piece starting from `j` cheaply, once the
+    /// searching work exceeds the budget.
+    ///
+    /// Each piece is aligned by the longest match of its head, and treated as
+    /// an exact match (delta data would cover the differences).
+    /// Pieces matching nothing are left in the gap (as extra data).
+    fn search_fallback(&mut self, mut j: usize) -> (usize, usize, usize) {
+        while j < self.t.len() {
+            let end = Ord::min(j + FALLBACK_PIECE, self.t.len());
+            let (i, n) = self.sa.search_lcp(&self.t[j..end]);
+            if n > self.small_match {
+                let n = Ord::min(end - j, self.s.len() - i);
+                return (i, j, n);
+            }
+            j = end;
+        }
+
+        // EOF should be treated as the last exact match.
+        (self.s.len(), self.t.len(), 0)
+    }
+
     /// Shrinks the gap region between the previous and current exact match by
     /// determining similar bytes. Returns the lengths (a0, b) of similar bytes.
     #[inline]
@@ -537,8 +3474,16 @@ impl<'s, 't> SaDiff<'s, 't> {
         let suffix = &self.s[self.i0 + self.n0..];
         let prefix = &self.s[..i];
 
-        let mut a0 = scan_similar(gap.iter(), suffix.iter());
-        let mut b = scan_similar(gap.iter().rev(), prefix.iter().rev());
+        let (mut a0, mut b) = match self.scoring.as_deref() {
+            None => (
+                scan_similar(gap.iter(), suffix.iter()),
+                scan_similar(gap.iter().rev(), prefix.iter().rev()),
+            ),
+            Some(score) => (
+                scan_similar_by(gap.iter(), suffix.iter(), score),
+                scan_similar_by(gap.iter().rev(), prefix.iter().rev(), score),
+            ),
+        };
 
         // Overlapped.
         if a0 + b > gap.len() {
@@ -547,7 +3492,10 @@ impl<'s, 't> SaDiff<'s, 't> {
             let ys = suffix[gap.len() - b..a0].iter();
             let zs = prefix[prefix.len() - b..prefix.len() - b + n].iter();
 
-            let i = scan_divide(xs, ys, zs);
+            let i = match self.scoring.as_deref() {
+                None => scan_divide(xs, ys, zs),
+                Some(score) => scan_divide_by(xs, ys, zs, score),
+            };
             a0 -= n - i;
             b -= i;
         }
@@ -560,36 +3508,10 @@ impl<'s, 't> Iterator for SaDiff<'s, 't> {
     type Item = Control;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some((i, j, n)) = self.search_next() {
-            let (i0, j0, n0, b0) = self.previous_state();
-            let (a0, b) = self.shrink_gap(i, j);
-
-            // source:
-            //     ...(   b0   ,   n0   ,   a0   )...(   b   ,...
-            //        ^ spos   ^ i0                ^         ^ i
-            //                                     | distance can be negative
-            // target:
-            //     ...(   b0   ,   n0   ,   a0   ;   copy   )(   b   ,...
-            //        ^ tpos   ^ j0              ^ tpos+add          ^ j
-            let add = (b0 + n0 + a0) as u64;
-            let copy = ((j - b) - (j0 + n0 + a0)) as u64;
-            let seek = (i - b).wrapping_sub(i0 + n0 + a0) as isize as i64;
-
-            self.update_state(i, j, n, b);
-            Some(Control { add, copy, seek })
-        } else {
-            None
-        }
+        self.next_match().map(|(ctl, _)| ctl)
     }
 }
 
-/// Converts Range<usize> to extent (i, n).
-#[inline]
-fn range_to_extent(range: Range<usize>) -> (usize, usize) {
-    let Range { start, end } = range;
-    (start, end.saturating_sub(start))
-}
-
 /// Scans for the data length of the max similarity.
 #[inline]
 fn scan_similar<T: Eq, I: Iterator<Item = T>>(xs: I, ys: I) -> usize {
@@ -631,3 +3553,39 @@ fn scan_divide<T: Eq, I: Iterator<Item = T>>(xs: I, ys: I, zs: I) -> usize {
 
     i
 }
+
+/// Scans for the data length of the max similarity using custom scoring.
+#[inline]
+fn scan_similar_by<'a, I: Iterator<Item = &'a u8>>(xs: I, ys: I, score: &Scoring) -> usize {
+    let mut i = 0;
+    let mut total = 0;
+    let mut max_score = 0;
+
+    for (n, (&x, &y)) in (1..).zip(xs.zip(ys)) {
+        total += score(x, y);
+        if total > max_score {
+            i = n;
+            max_score = total;
+        }
+    }
+
+    i
+}
+
+/// Scans for the dividing point of the overlapping using custom scoring.
+#[inline]
+fn scan_divide_by<'a, I: Iterator<Item = &'a u8>>(xs: I, ys: I, zs: I, score: &Scoring) -> usize {
+    let mut i = 0;
+    let mut total = 0;
+    let mut max_score = 0;
+
+    for (n, ((&x, &y), &z)) in (1..).zip(xs.zip(ys).zip(zs)) {
+        total += score(x, y) - score(x, z);
+        if total > max_score {
+            i = n;
+            max_score = total;
+        }
+    }
+
+    i
+}
@@ -1,20 +1,126 @@
 #![forbid(unsafe_code)]
 
-use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::error;
+use std::fmt;
+use std::io::{self, Error, ErrorKind, IoSlice, Read, Result, Write};
+use std::ops::Range;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use bzip2::read::BzDecoder;
+use byteorder::{ByteOrder, LE};
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
 
+use super::bsdiff::available_threads;
+use super::checksum::{ChecksumKind, Strength};
+use super::codec::Decoder;
+#[cfg(feature = "mmap")]
+use super::files::TempFile;
+use super::format::{Format, Header, PatchError, PatchMetadata, PatchSizes, Section};
+#[cfg(feature = "mmap")]
+use super::input::InputFile;
+use super::profile::PatchProfile;
+use super::source::{preload, SourcePreload, SourceRead, WindowCache};
 use super::utils::*;
 
 /// Default buffer size.
 pub const BUFFER_SIZE: usize = 131072;
 
-/// Default initial size of the delta calculation buffer.
+/// Default size of the delta cache.
 pub const DELTA_MIN: usize = 32768;
 
+/// Strategy of waiting for the target stream to accept more bytes, see
+/// `Bspatch::wait_strategy`.
+pub type WaitStrategy<'a> = dyn FnMut(&Stall) -> Result<()> + Send + 'a;
+
+/// Hook called with the target size once the target is completely written
+/// and flushed, see `Bspatch::on_complete`.
+pub type CompleteHook<'a> = dyn FnOnce(u64) -> Result<()> + Send + 'a;
+
+/// Hook called with the statistics of patching once it ends, see
+/// `Bspatch::on_stats`.
+pub type StatsHook<'a> = dyn FnOnce(&ApplyStats) + Send + 'a;
+
+/// Statistics of patching, derived from the controls and sections as they
+/// are applied, see `Bspatch::on_stats`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApplyStats {
+    /// Number of controls applied.
+    pub controls: u64,
+
+    /// Number of target bytes added from source and delta data.
+    pub added: u64,
+
+    /// Number of target bytes copied from extra data.
+    pub copied: u64,
+
+    /// Number of target bytes copied from earlier target (see
+    /// `Bsdiff::target_copy`).
+    pub repeated: u64,
+
+    /// End of the furthest source byte read, i.e. the size of the source
+    /// prefix touched.
+    pub source_span: u64,
+
+    /// Encoded sizes of the control, delta and extra sections.
+    pub encoded_sizes: [u64; 3],
+
+    /// Number of bytes decoded from the control, delta and extra sections.
+    pub decoded_sizes: [u64; 3],
+}
+
+impl ApplyStats {
+    /// Number of target bytes written.
+    pub fn target_size(&self) -> u64 {
+        self.added + self.copied + self.repeated
+    }
+
+    /// Decompression ratios (decoded by encoded sizes) of the control,
+    /// delta and extra sections, 0 for empty sections.
+    pub fn decompression_ratios(&self) -> [f64; 3] {
+        [0, 1, 2].map(|i| match self.encoded_sizes[i] {
+            0 => 0.0,
+            size => self.decoded_sizes[i] as f64 / size as f64,
+        })
+    }
+}
+
+/// Stall of the target stream, i.e. writes failed with
+/// `ErrorKind::WouldBlock`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Stall {
+    /// Number of bytes accepted by the target stream so far.
+    pub position: u64,
+
+    /// Number of consecutive attempts blocked, starting from 1.
+    pub attempts: u32,
+
+    /// Time elapsed since the first blocked attempt.
+    pub elapsed: Duration,
+}
+
+/// Wait strategy sleeping with exponential backoff (1ms up to 100ms), failing
+/// with `ErrorKind::TimedOut` once a stall lasts longer than `timeout`.
+pub fn backoff(timeout: Duration) -> impl FnMut(&Stall) -> Result<()> + Send {
+    move |stall| {
+        if stall.elapsed > timeout {
+            return Err(Error::new(ErrorKind::TimedOut, "target stream stalled"));
+        }
+        let millis = 1u64 << Ord::min(stall.attempts - 1, 7);
+        thread::sleep(Duration::from_millis(Ord::min(millis, 100)));
+        Ok(())
+    }
+}
+
 /// Fast and memory saving patcher compatible with bspatch.
 ///
-/// Apply patch with a 4k copy buffer and a 1k-4k delta cache buffer:
+/// Both bsdiff 4.x patches and qbsdiff extended patches are accepted, the
+/// format and section codecs are detected automatically.
+///
+/// Apply patch with a 4k copy buffer and a 1k delta cache:
 /// ```
 /// use std::io;
 /// use qbsdiff::Bspatch;
@@ -48,9 +154,306 @@ pub const DELTA_MIN: usize = 32768;
 /// }
 /// ```
 pub struct Bspatch<'p> {
+    data: &'p [u8],
     patch: PatchFile<'p>,
     buffer_size: usize,
     delta_min: usize,
+    vectored: bool,
+    trailing: u64,
+    wait: Option<Box<WaitStrategy<'p>>>,
+    flush_every: u64,
+    on_complete: Option<Box<CompleteHook<'p>>>,
+    on_stats: Option<Box<StatsHook<'p>>>,
+    #[cfg(feature = "mmap")]
+    decompress_source: bool,
+    fuzzy_radius: usize,
+    strict_size: bool,
+    preload: SourcePreload,
+}
+
+/// Tolerance of trailing bytes after the patch payload.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tolerance {
+    /// Bytes beyond the declared section sizes are errors.
+    ///
+    /// The extra section of bsdiff 4.x patches is not sized in the header and
+    /// always extends to the end of patch, thus trailing bytes are not
+    /// detected for them (same as bspatch).
+    Strict,
+
+    /// Bytes beyond the declared section sizes are ignored.
+    ///
+    /// This is useful for patches padded to block boundaries, e.g. those
+    /// embedded in firmware containers.
+    /// For bsdiff 4.x patches, the extra section is decompressed once more to
+    /// find where its bzip2 stream ends.
+    Lenient,
+}
+
+/// Policy of adding delta to source bytes beyond the end of source, see
+/// `Bspatch::source_overrun`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SourceOverrun {
+    /// Controls reading beyond the end of source are rejected by
+    /// `CorruptControl` with `ControlFault::SourceOutOfBounds`.
+    #[default]
+    Error,
+
+    /// Source bytes beyond the end of source are read as zeros.
+    ///
+    /// Some forks of bsdiff patch this way, which is useful for images grown
+    /// in place (e.g. partitions or disk images extended by zeros).
+    ZeroFill,
+}
+
+/// Corrupted source window detected by the checksums embedded in the patch,
+/// see `Bsdiff::source_checksum`.
+///
+/// This is wrapped in `io::Error` of kind `InvalidData`, see
+/// `SourceCorruption::of`.
+///
+/// Example:
+///
+/// Locate bit rot of the source:
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bspatch, SourceCorruption};
+///
+/// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut target = Vec::new();
+///     let result = Bspatch::new(patch)?.apply(source, io::Cursor::new(&mut target));
+///     if let Some(corruption) = result.as_ref().err().and_then(SourceCorruption::of) {
+///         eprintln!("source bytes {:?} corrupted", corruption.range);
+///     }
+///     result.map(|_| target)
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceCorruption {
+    /// Byte range of the corrupted source window.
+    pub range: Range<u64>,
+}
+
+impl SourceCorruption {
+    /// Get the corrupted source window of the error, if any.
+    pub fn of(err: &Error) -> Option<SourceCorruption> {
+        err.get_ref()?.downcast_ref::<SourceCorruption>().cloned()
+    }
+}
+
+impl fmt::Display for SourceCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "source corrupted at bytes {}..{}", self.range.start, self.range.end)
+    }
+}
+
+impl error::Error for SourceCorruption {}
+
+impl From<SourceCorruption> for Error {
+    fn from(err: SourceCorruption) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+/// Control of the patch rejected before applied, as its lengths, the cursors
+/// or the target size would go out of range.
+///
+/// All control arithmetic of patching is checked, so corrupted patches fail
+/// deterministically at the first bad control instead of producing garbage
+/// targets.
+/// It is wrapped in `io::Error` of kind `InvalidData`, see
+/// `CorruptControl::of`.
+///
+/// Example:
+///
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bspatch, CorruptControl};
+///
+/// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut target = Vec::new();
+///     let result = Bspatch::new(patch)?.apply(source, io::Cursor::new(&mut target));
+///     if let Some(corrupt) = result.as_ref().err().and_then(CorruptControl::of) {
+///         eprintln!("control #{} corrupted: {:?}", corrupt.index, corrupt.fault);
+///     }
+///     result.map(|_| target)
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CorruptControl {
+    /// Index of the control in the patch, starting from 0.
+    pub index: u64,
+
+    /// What is wrong with the control.
+    pub fault: ControlFault,
+}
+
+/// Fault of a corrupted control, see `CorruptControl`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ControlFault {
+    /// A length (or the target-relative distance) is negative.
+    NegativeLength,
+
+    /// The control produces bytes beyond the target size.
+    TargetOverflow,
+
+    /// The delta is added to bytes out of bounds of the source.
+    SourceOutOfBounds,
+
+    /// The source cursor seeks before the start of source, or overflows.
+    SeekOutOfRange,
+
+    /// The target-relative copy reaches before the start of target or beyond
+    /// the target window.
+    InvalidDistance,
+}
+
+impl CorruptControl {
+    /// Get the corrupted control of the error, if any.
+    pub fn of(err: &Error) -> Option<CorruptControl> {
+        err.get_ref()?.downcast_ref::<CorruptControl>().copied()
+    }
+}
+
+impl fmt::Display for CorruptControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fault = match self.fault {
+            ControlFault::NegativeLength => "negative length",
+            ControlFault::TargetOverflow => "exceeding the target size",
+            ControlFault::SourceOutOfBounds => "out of bounds of source",
+            ControlFault::SeekOutOfRange => "seeking out of range",
+            ControlFault::InvalidDistance => "invalid target distance",
+        };
+        write!(f, "patch corrupted: control #{} {}", self.index, fault)
+    }
+}
+
+impl error::Error for CorruptControl {}
+
+impl From<CorruptControl> for Error {
+    fn from(err: CorruptControl) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+/// Target produced by a patch whose controls end before the target size in
+/// its header, see `Bspatch::strict_size`.
+///
+/// It is wrapped in `io::Error` of kind `InvalidData`, see
+/// `TargetSizeMismatch::of`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TargetSizeMismatch {
+    /// Target size in the header, see `Bspatch::hint_target_size`.
+    pub expected: u64,
+
+    /// Number of target bytes written.
+    pub actual: u64,
+}
+
+impl TargetSizeMismatch {
+    /// Get the target size mismatch of the error, if any.
+    pub fn of(err: &Error) -> Option<TargetSizeMismatch> {
+        err.get_ref()?.downcast_ref::<TargetSizeMismatch>().copied()
+    }
+}
+
+impl fmt::Display for TargetSizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "patch corrupted: {} target bytes written, {} expected",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl error::Error for TargetSizeMismatch {}
+
+impl From<TargetSizeMismatch> for Error {
+    fn from(err: TargetSizeMismatch) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+/// Section of the patch failed to decode partway through patching, e.g.
+/// truncated or corrupted in transfer, with the progress made before.
+///
+/// The target bytes produced before the failure are written out, so callers
+/// could re-download the patch bytes of `range` and resume patching from
+/// `written` (see `Bspatch::apply_range`) instead of restarting.
+/// It is wrapped in `io::Error` of the kind of the decoding error, see
+/// `SectionCorruption::of`.
+///
+/// Example:
+///
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bspatch, SectionCorruption};
+///
+/// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut target = Vec::new();
+///     let result = Bspatch::new(patch)?.apply(source, io::Cursor::new(&mut target));
+///     if let Some(corruption) = result.as_ref().err().and_then(SectionCorruption::of) {
+///         eprintln!(
+///             "{} target bytes written, patch bytes {:?} to re-download",
+///             corruption.written, corruption.range
+///         );
+///     }
+///     result.map(|_| target)
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SectionCorruption {
+    /// Section failed to decode.
+    pub section: Section,
+
+    /// Index of the control being applied, starting from 0.
+    pub control: u64,
+
+    /// Number of target bytes written before the failure.
+    pub written: u64,
+
+    /// Number of bytes decoded from the control, delta and extra sections
+    /// before the failure.
+    pub decoded: [u64; 3],
+
+    /// Byte range of the patch to re-download: the failed block if the
+    /// section is split into blocks (see `Bsdiff::block_size`), the whole
+    /// section otherwise.
+    pub range: Range<u64>,
+
+    /// Kind of the decoding error, e.g. `UnexpectedEof` if truncated.
+    pub kind: ErrorKind,
+}
+
+impl SectionCorruption {
+    /// Get the failed section of the error, if any.
+    pub fn of(err: &Error) -> Option<SectionCorruption> {
+        err.get_ref()?.downcast_ref::<SectionCorruption>().cloned()
+    }
+}
+
+impl fmt::Display for SectionCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let section = match self.section {
+            Section::Control => "control",
+            Section::Delta => "delta",
+            Section::Extra => "extra",
+        };
+        write!(
+            f,
+            "patch corrupted: {} section failed at control #{} (patch bytes {}..{}), {} target bytes written",
+            section, self.control, self.range.start, self.range.end, self.written
+        )
+    }
+}
+
+impl error::Error for SectionCorruption {}
+
+impl From<SectionCorruption> for Error {
+    fn from(err: SectionCorruption) -> Self {
+        Error::new(err.kind, err)
+    }
 }
 
 impl<'p> Bspatch<'p> {
@@ -58,13 +461,55 @@ impl<'p> Bspatch<'p> {
     ///
     /// Return error if failed to parse the patch header.
     pub fn new(patch: &'p [u8]) -> Result<Self> {
+        Bspatch::with_tolerance(patch, Tolerance::Strict)
+    }
+
+    /// Parse the patch file with given tolerance of trailing bytes, and create
+    /// new patcher configuration.
+    ///
+    /// Accept patches padded with zeros:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bspatch, Tolerance};
+    ///
+    /// fn bspatch(source: &[u8], padded_patch: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let patcher = Bspatch::with_tolerance(padded_patch, Tolerance::Lenient)?;
+    ///     eprintln!("{} padding bytes skipped", patcher.trailing_bytes());
+    ///     let mut target = Vec::new();
+    ///     patcher.apply(source, io::Cursor::new(&mut target))?;
+    ///     Ok(target)
+    /// }
+    /// ```
+    ///
+    /// Return error if failed to parse the patch header.
+    pub fn with_tolerance(data: &'p [u8], tolerance: Tolerance) -> Result<Self> {
+        let (patch, trailing) = parse(data, tolerance)?;
         Ok(Bspatch {
-            patch: parse(patch)?,
+            data,
+            patch,
             buffer_size: BUFFER_SIZE,
             delta_min: DELTA_MIN,
+            vectored: false,
+            trailing,
+            wait: None,
+            flush_every: 0,
+            on_complete: None,
+            on_stats: None,
+            #[cfg(feature = "mmap")]
+            decompress_source: false,
+            fuzzy_radius: 0,
+            strict_size: true,
+            preload: SourcePreload::Auto,
         })
     }
 
+    /// Number of trailing bytes skipped after the patch payload.
+    ///
+    /// This is always zero in strict mode.
+    pub fn trailing_bytes(&self) -> u64 {
+        self.trailing
+    }
+
     /// Set the main copy buffer size, (`bs > 128`, default is `BUFFER_SIZE`).
     ///
     /// This is also the write buffer to target stream.
@@ -78,12 +523,26 @@ impl<'p> Bspatch<'p> {
         self
     }
 
-    /// Sets the initial delta cache size, (`dm > 128`, default is `DELTA_MIN`).
-    ///
-    /// The delta cache is dynamic and can grow up when needed (but keeps not
-    /// greater than the size of main copy buffer).
+    /// Set the main copy buffer size, like `buffer_size` but rejecting sizes
+    /// less than 128 with `ErrorKind::InvalidInput`.
+    pub fn try_buffer_size(self, bs: usize) -> Result<Self> {
+        if bs < 128 {
+            return Err(Error::new(ErrorKind::InvalidInput, "buffer size must be at least 128"));
+        }
+        Ok(self.buffer_size(bs))
+    }
+
+    /// Get the effective main copy buffer size.
+    pub fn effective_buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Sets the delta cache size, (`dm > 128`, default is `DELTA_MIN`).
     ///
-    /// This might be deprecated in later version.
+    /// The delta cache is allocated once with this size (but not greater than
+    /// the size of main copy buffer), and delta data are decoded through it
+    /// chunk by chunk, thus the memory usage does not depend on the controls
+    /// of patch.
     pub fn delta_min(mut self, mut dm: usize) -> Self {
         if dm < 128 {
             dm = 128;
@@ -92,11 +551,368 @@ impl<'p> Bspatch<'p> {
         self
     }
 
+    /// Set the delta cache size, like `delta_min` but rejecting sizes
+    /// less than 128 with `ErrorKind::InvalidInput`.
+    pub fn try_delta_min(self, dm: usize) -> Result<Self> {
+        if dm < 128 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "delta cache size must be at least 128",
+            ));
+        }
+        Ok(self.delta_min(dm))
+    }
+
+    /// Get the effective delta cache size, which is no greater than the main
+    /// copy buffer size.
+    pub fn effective_delta_min(&self) -> usize {
+        Ord::min(self.delta_min, self.buffer_size)
+    }
+
+    /// Write large extra data along with the pending bytes of the main copy
+    /// buffer by vectored writes (default is disabled).
+    ///
+    /// Extra data is then read into a second buffer of the main copy buffer
+    /// size, and both buffers are passed to `Write::write_vectored` at once,
+    /// which saves system calls for targets supporting vectored IO (e.g. files
+    /// and sockets).
+    /// Targets without efficient vectored IO fall back to writing the buffers
+    /// one by one, which is still correct but slightly slower.
+    pub fn vectored_writes(mut self, vectored: bool) -> Self {
+        self.vectored = vectored;
+        self
+    }
+
+    /// Set the strategy of waiting for the target stream (default is none).
+    ///
+    /// Targets backed by non-blocking pipes or sockets, or by adapters of
+    /// asynchronous object stores, might fail writes with
+    /// `ErrorKind::WouldBlock` when their buffers are full.
+    /// Without a strategy such errors abort patching, otherwise the strategy is
+    /// called on each blocked attempt to wait (e.g. sleep, or poll the file
+    /// descriptor), then the write is retried.
+    /// Errors returned by the strategy abort patching.
+    ///
+    /// The strategy also observes every stall, e.g. for logging or metrics:
+    /// ```
+    /// use std::io;
+    /// use std::time::Duration;
+    /// use qbsdiff::{bspatch, Bspatch};
+    ///
+    /// fn bspatch<W: io::Write>(source: &[u8], patch: &[u8], pipe: W) -> io::Result<u64> {
+    ///     let mut wait = bspatch::backoff(Duration::from_secs(30));
+    ///     Bspatch::new(patch)?
+    ///         .wait_strategy(move |stall| {
+    ///             if stall.attempts == 1 {
+    ///                 eprintln!("target stalled at byte {}", stall.position);
+    ///             }
+    ///             wait(stall)
+    ///         })
+    ///         .apply(source, pipe)
+    /// }
+    /// ```
+    pub fn wait_strategy<F>(mut self, wait: F) -> Self
+    where
+        F: FnMut(&Stall) -> Result<()> + Send + 'p,
+    {
+        self.wait = Some(Box::new(wait));
+        self
+    }
+
+    /// Flush the target stream every time at least `bytes` more bytes are
+    /// written (default is 0 for flushing only once at the end).
+    ///
+    /// This bounds the amount of target data buffered by the stream (e.g. a
+    /// `BufWriter`, or a network sink acknowledging flushed data), at the
+    /// cost of more flushes.
+    pub fn flush_every(mut self, bytes: u64) -> Self {
+        self.flush_every = bytes;
+        self
+    }
+
+    /// Call `hook` with the target size once the target is completely
+    /// written and flushed (default is none).
+    ///
+    /// This is where callers make the target durable, e.g. `fsync` it and
+    /// rename it into place, without wrapping the target stream.
+    /// Errors returned by the hook fail patching.
+    /// The hook is not called if patching fails.
+    /// For `apply_to_path` and friends, the hook is called before the
+    /// temporary file is persisted.
+    ///
+    /// Example:
+    ///
+    /// Write the target crash-consistently:
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use std::io;
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<u64> {
+    ///     let file = File::create("target.tmp")?;
+    ///     let sync = file.try_clone()?;
+    ///     Bspatch::new(patch)?
+    ///         .on_complete(move |_| {
+    ///             sync.sync_all()?;
+    ///             fs::rename("target.tmp", "target")
+    ///         })
+    ///         .apply(source, file)
+    /// }
+    /// ```
+    pub fn on_complete<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(u64) -> Result<()> + Send + 'p,
+    {
+        self.on_complete = Some(Box::new(hook));
+        self
+    }
+
+    /// Call the hook with the statistics of patching once it ends, whether
+    /// it succeeds or fails.
+    ///
+    /// The statistics are derived as the patch is applied, without an extra
+    /// pass over it.
+    /// If patching fails, the counters tell how far it went, e.g. to
+    /// correlate the characteristics of patches with failures in the field.
+    /// The hook is called before `on_complete`.
+    ///
+    /// Example:
+    ///
+    /// Report the telemetry of updates:
+    /// ```
+    /// use std::io;
+    /// use std::sync::mpsc::Sender;
+    /// use qbsdiff::{ApplyStats, Bspatch};
+    ///
+    /// fn update(source: &[u8], patch: &[u8], telemetry: Sender<ApplyStats>) -> io::Result<Vec<u8>> {
+    ///     let mut target = Vec::new();
+    ///     Bspatch::new(patch)?
+    ///         .on_stats(move |stats| {
+    ///             let _ = telemetry.send(*stats);
+    ///         })
+    ///         .apply(source, io::Cursor::new(&mut target))?;
+    ///     Ok(target)
+    /// }
+    /// ```
+    pub fn on_stats<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(&ApplyStats) + Send + 'p,
+    {
+        self.on_stats = Some(Box::new(hook));
+        self
+    }
+
+    /// Decompress the source file of `apply_file` transparently if it is
+    /// compressed with gzip or zstd (requires feature `mmap`, default is
+    /// false), see `InputFile::open_decompressed`.
+    #[cfg(feature = "mmap")]
+    pub fn decompress_source(mut self, decompress: bool) -> Self {
+        self.decompress_source = decompress;
+        self
+    }
+
+    /// Tolerate sources slightly different from the one the patch was made
+    /// from (experimental, default is 0 for disabled).
+    ///
+    /// If the patch carries checksums of source windows (see
+    /// `Bsdiff::source_checksum`), each mismatching window is searched within
+    /// `radius` bytes around its offset for the expected content, nearest
+    /// first, and the content found is used instead.
+    /// This is useful for files differing only by shifted regions, e.g. of
+    /// varying padding or timestamps at known region boundaries.
+    /// The source is also allowed to be of different size.
+    ///
+    /// All the windows are verified before patching, and the source is copied
+    /// into memory if any window is relocated.
+    /// Windows not found are left as is, and reported by `SourceCorruption`
+    /// once read.
+    /// Patches without checksums of source windows are not affected.
+    pub fn fuzzy_source(mut self, radius: usize) -> Self {
+        self.fuzzy_radius = radius;
+        self
+    }
+
+    /// Require the target written to be exactly of the target size in the
+    /// header (default is enabled).
+    ///
+    /// Controls never produce bytes beyond the target size (see
+    /// `CorruptControl`), but a corrupted patch could end its controls early.
+    /// In strict mode, such a short target is reported by
+    /// `TargetSizeMismatch` (after written and flushed, but before
+    /// `on_complete` is called), in lenient mode the number of bytes written
+    /// is returned as is, like bsdiff 4.x patchers relying on external
+    /// checksums do.
+    pub fn strict_size(mut self, strict: bool) -> Self {
+        self.strict_size = strict;
+        self
+    }
+
+    /// Set the policy of adding delta to source bytes beyond the end of source
+    /// (default is `SourceOverrun::Error`).
+    ///
+    /// With `SourceOverrun::ZeroFill`, the missing source bytes are read as
+    /// zeros instead of rejecting the control, like some forks of bsdiff do.
+    /// Source checksums of the patch (see `Bsdiff::source_checksum`) only
+    /// cover the source within its size.
+    pub fn source_overrun(mut self, policy: SourceOverrun) -> Self {
+        self.patch.overrun = policy;
+        self
+    }
+
+    /// Set the policy of reading the source of `apply_source` (default is
+    /// `SourcePreload::Auto`).
+    ///
+    /// Sources read by seeks (e.g. files on Unix-like systems without
+    /// positioned reads, or remote backends) are better preloaded as a whole
+    /// or through a cache of windows when the controls jump back and forth,
+    /// see `effective_source_preload` for the policy chosen automatically.
+    /// Other `apply` methods read sources in memory and are not affected.
+    pub fn source_preload(mut self, policy: SourcePreload) -> Self {
+        self.preload = policy;
+        self
+    }
+
+    /// Get the policy of reading a source of given size by `apply_source`,
+    /// with `SourcePreload::Auto` resolved by scanning the controls.
+    pub fn effective_source_preload(&self, source_size: u64) -> SourcePreload {
+        match self.preload {
+            SourcePreload::Auto => self.preload.resolve(source_size, source_access(self.data).ok()),
+            policy => policy,
+        }
+    }
+
+    /// Set the number of blocks decoded ahead of patching on worker threads
+    /// (default is the available parallelism), 0 to decode blocks in the
+    /// calling thread as read.
+    ///
+    /// Only effective for patches split into blocks (see
+    /// `Bsdiff::block_size`), and without feature `parallel` where blocks
+    /// are always decoded in the calling thread.
+    pub fn decode_ahead(mut self, blocks: usize) -> Self {
+        self.patch.ctrls.decode_ahead(blocks);
+        self.patch.delta.decode_ahead(blocks);
+        self.patch.extra.decode_ahead(blocks);
+        self
+    }
+
+    /// Apply all settings of the tuning profile.
+    pub fn profile(self, profile: &PatchProfile) -> Self {
+        self.buffer_size(profile.buffer_size)
+            .delta_min(profile.delta_min)
+            .fuzzy_source(profile.fuzzy_radius)
+    }
+
+    /// Apply all settings of the tuning profile, like `profile` but rejecting
+    /// invalid settings (see `PatchProfile::validate`).
+    pub fn try_profile(self, profile: &PatchProfile) -> Result<Self> {
+        profile.validate()?;
+        Ok(self.profile(profile))
+    }
+
     /// Hint the final target file size.
     pub fn hint_target_size(&self) -> u64 {
         self.patch.tsize
     }
 
+    /// Hint the decoded sizes of the control, delta and extra sections,
+    /// `None` unless recorded by the patch (see `Bsdiff::decoded_sizes`).
+    ///
+    /// If recorded, the buffers of patching are allocated once and capped by
+    /// these sizes.
+    pub fn hint_section_sizes(&self) -> Option<[u64; 3]> {
+        self.patch.decoded
+    }
+
+    /// Get the target and source sizes declared by the patch, and whether the
+    /// target size is verified against the decoded sections (see
+    /// `Bsdiff::explicit_sizes`).
+    ///
+    /// Sources of other sizes than the declared one are rejected before being
+    /// read.
+    pub fn sizes(&self) -> PatchSizes {
+        self.patch.sizes
+    }
+
+    /// Get the algorithm of the section checksums (see `Bsdiff::checksum`),
+    /// `None` if absent.
+    ///
+    /// The checksums are already verified once the patcher is created.
+    pub fn checksum(&self) -> Option<ChecksumKind> {
+        self.patch.checksum
+    }
+
+    /// Require the section checksums to be at least as strong as `strength`.
+    ///
+    /// This lets integrators enforce their threat model on patches from
+    /// outside, e.g. refuse patches without checksums, or require
+    /// cryptographic ones.
+    ///
+    /// Example:
+    ///
+    /// Only accept patches protected by a 64-bit hash at least:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bspatch, Strength};
+    ///
+    /// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let mut target = Vec::new();
+    ///     Bspatch::new(patch)?
+    ///         .require_checksum(Strength::Strong)?
+    ///         .apply(source, io::Cursor::new(&mut target))?;
+    ///     Ok(target)
+    /// }
+    /// ```
+    ///
+    /// Return `PatchError::WeakChecksum` (of `ErrorKind::InvalidData`) if the
+    /// checksums are weaker or absent.
+    pub fn require_checksum(self, strength: Strength) -> Result<Self> {
+        let actual = self.patch.checksum.map_or(Strength::None, ChecksumKind::strength);
+        if actual < strength {
+            return Err(PatchError::WeakChecksum.into());
+        }
+        Ok(self)
+    }
+
+    /// Get the metadata recorded by `Bsdiff::metadata`, `None` if absent.
+    pub fn metadata(&self) -> Option<PatchMetadata> {
+        self.patch.metadata.map(PatchMetadata::parse)
+    }
+
+    /// Check whether the patch reproduces the source unchanged, i.e. it is
+    /// the canonical patch of identical inputs (see `Bsdiff::compare`):
+    /// either no controls for empty target, or a single control adding zero
+    /// delta to the whole target.
+    ///
+    /// Only the control and delta sections are decoded, no source is needed.
+    /// Return error if the patch is corrupted.
+    pub fn is_identity(mut self) -> Result<bool> {
+        let layout = self.patch.ctl_layout;
+        let mut ctl = [0; 40];
+        if !layout.read(&mut self.patch.ctrls, &mut ctl)? {
+            return Ok(self.patch.tsize == 0);
+        }
+        if decode_int(&ctl[0..]) as u64 != self.patch.tsize || ctl[8..].iter().any(|&b| b != 0) {
+            return Ok(false);
+        }
+        if layout.read(&mut self.patch.ctrls, &mut ctl)? {
+            return Ok(false);
+        }
+
+        let mut buf = vec![0; self.buffer_size];
+        let mut total = 0;
+        loop {
+            let n = self.patch.delta.read(&mut buf[..])?;
+            if n == 0 {
+                break;
+            }
+            if buf[..n].iter().any(|&b| b != 0) {
+                return Ok(false);
+            }
+            total += n as u64;
+        }
+        Ok(total == self.patch.tsize)
+    }
+
     /// Apply patch to the source data and output the stream of target.
     ///
     /// Parameter `source` is designed to be a low-level `&[u8]` binary, rather than a `Seek + Read` random accessing data.
@@ -104,89 +920,655 @@ impl<'p> Bspatch<'p> {
     /// which could result in unwanted performance loss due to heavy syscall overheads of `seek` + `read` (by the way, `pread`/`read_at` is not cross-platform).
     ///
     /// For those who in search of loading the source file lazily, simply [memmap](https://crates.io/crates/memmap2) source file to memory is recommended.
+    /// Sources that could not be mapped (e.g. block caches or remote
+    /// backends) are read by `apply_source` instead.
+    ///
+    /// If the patch carries checksums of source windows (see
+    /// `Bsdiff::source_checksum`), each source window is verified once read,
+    /// and a corrupted window is reported by `SourceCorruption`.
     ///
     /// The target data size would be returned if no error occurs.
     pub fn apply<T: Write>(self, source: &[u8], target: T) -> Result<u64> {
+        let relaxed = self.relax_source(source)?;
+        let source = relaxed.as_deref().unwrap_or(source);
+        self.apply_checked(source, target)
+    }
+
+    /// Apply patch to a random accessible source and output the stream of
+    /// target.
+    ///
+    /// The source is read by `SourceRead::read_at` with the lengths of add
+    /// controls (capped by the buffer size), preloaded or cached as set by
+    /// `source_preload`, prefer `apply` for sources already in memory.
+    ///
+    /// Example:
+    ///
+    /// Patch a source file without reading or mapping it as a whole:
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io;
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn bspatch_file(source: &str, patch: &[u8], target: &str) -> io::Result<u64> {
+    ///     Bspatch::new(patch)?.apply_source(File::open(source)?, File::create(target)?)
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::Unsupported` if `fuzzy_source` is enabled for a
+    /// patch carrying source checksums, which relocates windows in memory.
+    /// The target data size would be returned if no error occurs.
+    pub fn apply_source<S: SourceRead, T: Write>(self, source: S, target: T) -> Result<u64> {
+        if self.fuzzy_radius > 0 && self.patch.source_check.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "fuzzy source requires in-memory source",
+            ));
+        }
+        let size = source.size()?;
+        self.patch.check_source(size)?;
+        match self.effective_source_preload(size) {
+            SourcePreload::Whole => {
+                let source = preload(source)?;
+                self.apply_checked(&source[..], target)
+            }
+            SourcePreload::Windows { size, count } => {
+                let source = WindowCache::new(source, size, count)?;
+                self.apply_checked(source, target)
+            }
+            _ => self.apply_checked(source, target),
+        }
+    }
+
+    /// Apply patch to the source data and write the target into a
+    /// caller-provided buffer, of at least `hint_target_size` bytes.
+    ///
+    /// The target is written in place without allocating on the output path,
+    /// the patcher only allocates its buffers, bounded by `buffer_size` and
+    /// `delta_min`, which suits bootloaders and other memory-constrained
+    /// environments patching into a fixed region.
+    /// Bytes of the buffer beyond the target are left untouched.
+    ///
+    /// Example:
+    ///
+    /// Patch into a statically sized staging area:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn stage<'a>(source: &[u8], patch: &[u8], staging: &'a mut [u8; 65536]) -> io::Result<&'a [u8]> {
+    ///     let n = Bspatch::new(patch)?
+    ///         .buffer_size(4096)
+    ///         .delta_min(1024)
+    ///         .apply_into(source, &mut staging[..])?;
+    ///     Ok(&staging[..n])
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the buffer is smaller than the
+    /// target size.
+    /// The target data size would be returned if no error occurs.
+    pub fn apply_into(self, source: &[u8], target: &mut [u8]) -> Result<usize> {
+        let tsize = usize::try_from(self.patch.tsize)
+            .ok()
+            .filter(|&tsize| tsize <= target.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "target buffer is too small"))?;
+        let size = self
+            .apply(source, io::Cursor::new(&mut target[..tsize]))
+            .map_err(|e| match e.kind() {
+                ErrorKind::WriteZero => Error::new(ErrorKind::InvalidData, "patch corrupted"),
+                _ => e,
+            })?;
+        Ok(size as usize)
+    }
+
+    /// Apply patch to the checked source.
+    fn apply_checked<S: SourceRead, T: Write>(self, source: S, target: T) -> Result<u64> {
+        let delta_min =
+            if source.size()? == 0 && self.patch.window.is_none() && self.patch.overrun == SourceOverrun::Error {
+                0
+            } else {
+                Ord::min(self.delta_min, self.buffer_size)
+            };
+        let expected = self.patch.tsize;
+        let target = Retry::new(target, self.wait, self.flush_every);
+        let mut ctx = Context::new(self.patch, source, target, self.buffer_size, delta_min, self.vectored)?;
+        let result = ctx.apply();
+        let size = report(self.on_stats, &ctx.stats, result)?;
+        if self.strict_size && size != expected {
+            return Err(TargetSizeMismatch { expected, actual: size }.into());
+        }
+        complete(self.on_complete, size)
+    }
+
+    /// Apply patch to the source data and output the stream of target, feeding
+    /// the target bytes into `hasher` by `update` meanwhile.
+    ///
+    /// This saves a second pass over a large target to compute its checksum,
+    /// `hasher` would be returned with the target data size if no error
+    /// occurs.
+    ///
+    /// Example:
+    ///
+    /// Verify the target against an expected checksum while patching:
+    /// ```
+    /// use std::io;
+    /// use std::hash::{DefaultHasher, Hasher};
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn bspatch_verified(source: &[u8], patch: &[u8], expected: u64) -> io::Result<Vec<u8>> {
+    ///     let mut target = Vec::new();
+    ///     let (hasher, _) = Bspatch::new(patch)?.apply_hashed(
+    ///         source,
+    ///         io::Cursor::new(&mut target),
+    ///         DefaultHasher::new(),
+    ///         |hasher, data| hasher.write(data),
+    ///     )?;
+    ///     if hasher.finish() != expected {
+    ///         return Err(io::Error::new(io::ErrorKind::InvalidData, "target checksum mismatch"));
+    ///     }
+    ///     Ok(target)
+    /// }
+    /// ```
+    ///
+    /// With a `digest` hasher, pass `|hasher, data| hasher.update(data)`.
+    pub fn apply_hashed<T, H, F>(self, source: &[u8], target: T, mut hasher: H, update: F) -> Result<(H, u64)>
+    where
+        T: Write,
+        F: FnMut(&mut H, &[u8]),
+    {
+        let tee = Tee {
+            inner: target,
+            hasher: &mut hasher,
+            update,
+        };
+        let size = self.apply(source, tee)?;
+        Ok((hasher, size))
+    }
+
+    /// Apply patch to the source data and output only the given range of
+    /// target.
+    ///
+    /// Controls before the range are skipped, only the prefixes of delta and
+    /// extra sections up to the end of the range are decompressed, which is
+    /// useful for serving range requests of a patched file on the fly.
+    /// See `PatchedReader` for repeated random access.
+    ///
+    /// Serve an HTTP range request:
+    /// ```
+    /// use std::io;
+    /// use std::ops::Range;
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn serve(source: &[u8], patch: &[u8], range: Range<u64>) -> io::Result<Vec<u8>> {
+    ///     let mut body = Vec::new();
+    ///     Bspatch::new(patch)?.apply_range(source, range, io::Cursor::new(&mut body))?;
+    ///     Ok(body)
+    /// }
+    /// ```
+    ///
+    /// Patches carrying target-relative copies (see `Bsdiff::target_copy`)
+    /// have to be applied from the beginning of target, although only the
+    /// range is written.
+    ///
+    /// Return error if the range exceeds the target size.
+    /// The size of the range would be returned if no error occurs.
+    pub fn apply_range<T: Write>(self, source: &[u8], range: Range<u64>, target: T) -> Result<u64> {
+        if range.start > range.end || range.end > self.patch.tsize {
+            return Err(Error::new(ErrorKind::InvalidInput, "target range out of bounds"));
+        }
+        let relaxed = self.relax_source(source)?;
+        let source = relaxed.as_deref().unwrap_or(source);
         let delta_min = Ord::min(self.delta_min, self.buffer_size);
-        let ctx = Context::new(self.patch, source, target, self.buffer_size, delta_min);
-        ctx.apply()
+        let target = Retry::new(target, self.wait, self.flush_every);
+        if self.patch.window.is_some() {
+            let target = Clip::new(target, range.clone());
+            let mut ctx = Context::new(self.patch, source, target, self.buffer_size, delta_min, self.vectored)?;
+            let result = ctx.apply_until(range.end);
+            report(self.on_stats, &ctx.stats, result)?;
+            return complete(self.on_complete, range.end - range.start);
+        }
+        let mut ctx = Context::new(self.patch, source, target, self.buffer_size, delta_min, self.vectored)?;
+        let result = ctx.apply_range(range);
+        let size = report(self.on_stats, &ctx.stats, result)?;
+        complete(self.on_complete, size)
+    }
+
+    /// Check the source, and relocate the mismatching windows if
+    /// `fuzzy_source` is enabled.
+    fn relax_source(&self, source: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.patch.source_check {
+            Some(ref check) if self.fuzzy_radius > 0 && check.is_near(source.len(), self.fuzzy_radius) => {
+                Ok(check.relax(source, self.fuzzy_radius))
+            }
+            _ => self.patch.check_source(source.len() as u64).map(|_| None),
+        }
+    }
+
+    /// Apply patch to the source data and write the target file (requires
+    /// feature `mmap`).
+    ///
+    /// The target is written to a temporary file in the same directory first,
+    /// then synced to disk and atomically renamed to `target`, thus the target
+    /// file is either left untouched or completely replaced.
+    /// The temporary file is created exclusively under an unpredictable name,
+    /// and gets the permissions of the original target before renaming.
+    ///
+    /// The target data size would be returned if no error occurs.
+    #[cfg(feature = "mmap")]
+    pub fn apply_to_path<P: AsRef<Path>>(self, source: &[u8], target: P) -> Result<u64> {
+        let target = target.as_ref();
+        let mut temp = TempFile::create(target)?;
+        temp.preallocate(self.hint_target_size());
+        let size = self.apply(source, temp.file())?;
+        temp.persist(target)?;
+        Ok(size)
+    }
+
+    /// Apply patch to the source data and write the target file through a
+    /// writable memory mapping (requires feature `mmap`).
+    ///
+    /// The temporary target file is resized to the target size up front and
+    /// memory-mapped, so target data is copied straight into the page cache
+    /// without write calls, and written back by the OS, which is faster for
+    /// very large targets on fast disks.
+    /// The target file is replaced atomically as `apply_to_path` does, after
+    /// the mapping is flushed.
+    ///
+    /// Example:
+    ///
+    /// Apply a patch of a multi-gigabyte image:
+    /// ```no_run
+    /// use std::{fs, io};
+    /// use qbsdiff::Bspatch;
+    ///
+    /// fn patch_image(patch: &[u8]) -> io::Result<u64> {
+    ///     let source = fs::read("disk.img")?;
+    ///     Bspatch::new(patch)?.apply_to_mmap(&source, "disk.img.new")
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidData` if the patch does not produce exactly
+    /// as many bytes as the target size in its header.
+    /// The target data size would be returned if no error occurs.
+    #[cfg(feature = "mmap")]
+    pub fn apply_to_mmap<P: AsRef<Path>>(mut self, source: &[u8], target: P) -> Result<u64> {
+        let target = target.as_ref();
+        let mut temp = TempFile::create(target)?;
+        let tsize = self.hint_target_size();
+        if tsize == 0 {
+            let size = self.apply(source, temp.file())?;
+            temp.persist(target)?;
+            return Ok(size);
+        }
+
+        let hook = self.on_complete.take();
+        let mut map = temp.map_mut(tsize)?;
+        let size = self
+            .apply(source, io::Cursor::new(&mut map[..]))
+            .map_err(|e| match e.kind() {
+                ErrorKind::WriteZero => Error::new(ErrorKind::InvalidData, "patch corrupted"),
+                _ => e,
+            })?;
+        if size != tsize {
+            return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+        }
+        map.flush()?;
+        complete(hook, size)?;
+        // Windows cannot rename files still mapped.
+        drop(map);
+        temp.persist(target)?;
+        Ok(size)
+    }
+
+    /// Apply patch to the source file and write the target file (requires
+    /// feature `mmap`).
+    ///
+    /// The source file is memory-mapped read-only rather than loaded into
+    /// memory, and must not be modified during patching.
+    /// The target file is replaced atomically as `apply_to_path` does.
+    /// It is fine for `source` and `target` to be the same file.
+    /// Compressed sources could be decompressed with `decompress_source`.
+    ///
+    /// The target data size would be returned if no error occurs.
+    #[cfg(feature = "mmap")]
+    pub fn apply_file<S: AsRef<Path>, T: AsRef<Path>>(self, source: S, target: T) -> Result<u64> {
+        let target = target.as_ref();
+        let source = if self.decompress_source {
+            InputFile::open_decompressed(source)?
+        } else {
+            InputFile::open(source)?
+        };
+        let mut temp = TempFile::create(target)?;
+        temp.preallocate(self.hint_target_size());
+        let size = self.apply(&source[..], temp.file())?;
+        // Windows cannot replace files still mapped.
+        drop(source);
+        temp.persist(target)?;
+        Ok(size)
+    }
+}
+
+/// Patcher owning the patch as `Bytes` (requires feature `bytes`).
+///
+/// Unlike `Bspatch`, it borrows nothing, thus it is `'static` and could be
+/// moved into spawned tasks of async services passing `Bytes` around, and
+/// cloned cheaply.
+/// The settings are given as a `PatchProfile`.
+///
+/// Example:
+///
+/// Patch on a blocking thread of a tokio-like runtime:
+/// ```
+/// use std::{io, thread};
+/// use bytes::Bytes;
+/// use qbsdiff::bspatch::OwnedBspatch;
+///
+/// fn spawn_bspatch(source: Bytes, patch: Bytes) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+///     thread::spawn(move || {
+///         let mut target = Vec::new();
+///         OwnedBspatch::new(patch)?.apply_owned(source, io::Cursor::new(&mut target))?;
+///         Ok(target)
+///     })
+/// }
+/// ```
+#[cfg(feature = "bytes")]
+#[derive(Clone, Debug)]
+pub struct OwnedBspatch {
+    patch: Bytes,
+    tsize: u64,
+    profile: PatchProfile,
+}
+
+#[cfg(feature = "bytes")]
+impl OwnedBspatch {
+    /// Parse the patch header and create new patcher configuration.
+    ///
+    /// Return error if failed to parse the patch header.
+    pub fn new(patch: Bytes) -> Result<Self> {
+        let tsize = Bspatch::new(&patch)?.hint_target_size();
+        Ok(OwnedBspatch {
+            patch,
+            tsize,
+            profile: PatchProfile::default(),
+        })
+    }
+
+    /// Apply all settings of the tuning profile, see `Bspatch::profile`.
+    pub fn profile(mut self, profile: &PatchProfile) -> Self {
+        self.profile = profile.clone();
+        self
+    }
+
+    /// Hint the final target file size.
+    pub fn hint_target_size(&self) -> u64 {
+        self.tsize
+    }
+
+    /// Apply patch to the source data and output the stream of target, as
+    /// `Bspatch::apply` does.
+    ///
+    /// The target data size would be returned if no error occurs.
+    pub fn apply_owned<T: Write>(self, source: Bytes, target: T) -> Result<u64> {
+        Bspatch::new(&self.patch)?.profile(&self.profile).apply(&source, target)
     }
 }
 
 /// Patch file content.
 struct PatchFile<'a> {
     tsize: u64,
-    ctrls: BzDecoder<Cursor<&'a [u8]>>,
-    delta: BzDecoder<Cursor<&'a [u8]>>,
-    extra: BzDecoder<Cursor<&'a [u8]>>,
+    sizes: PatchSizes,
+    decoded: Option<[u64; 3]>,
+    ctl_layout: ControlLayout,
+    window: Option<u64>,
+    source_check: Option<SourceCheck<'a>>,
+    metadata: Option<&'a [u8]>,
+    checksum: Option<ChecksumKind>,
+    overrun: SourceOverrun,
+    ranges: [Range<u64>; 3],
+    ctrls: Decoder<'a>,
+    delta: Decoder<'a>,
+    extra: Decoder<'a>,
 }
 
-/// Parse the bsdiff 4.x patch file.
-fn parse(patch: &[u8]) -> Result<PatchFile> {
-    if patch.len() < 32 || &patch[..8] != b"BSDIFF40" {
-        return Err(Error::new(ErrorKind::InvalidData, "not a valid patch"));
+impl<'a> PatchFile<'a> {
+    /// Check the size of source if the patch declares it.
+    fn check_source(&self, size: u64) -> Result<()> {
+        match self.sizes.declared_source {
+            Some(declared) if declared != size => Err(Error::new(ErrorKind::InvalidInput, "source size mismatch")),
+            _ => Ok(()),
+        }
     }
+}
+
+/// Checksums of source windows, verified lazily.
+struct SourceCheck<'a> {
+    size: u64,
+    window: u64,
+    checksums: &'a [u8],
+    verified: Vec<bool>,
+    scratch: Vec<u8>,
+}
 
-    let csize = decode_int(&patch[8..16]) as u64;
-    let dsize = decode_int(&patch[16..24]) as u64;
-    let tsize = decode_int(&patch[24..32]) as u64;
-    if 32 + csize + dsize > patch.len() as u64 {
-        return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+impl<'a> SourceCheck<'a> {
+    /// Search the mismatching windows around their offsets, and return the
+    /// relocated source if any window is found or the size mismatches.
+    ///
+    /// The declared size must be near the source size (see `is_near`).
+    fn relax(&self, source: &[u8], radius: usize) -> Option<Vec<u8>> {
+        let windows = self.checksums.len() / 4;
+        let window = usize::try_from(self.window).unwrap_or(usize::MAX);
+        let size = usize::try_from(self.size).ok()?;
+        let expected = |i: usize| LE::read_u32(&self.checksums[i * 4..]);
+        let range = |i: usize| {
+            let start = Ord::min(i.saturating_mul(window), size);
+            start..Ord::min(start.saturating_add(window), size)
+        };
+        let matches = |start: usize, len: usize, i: usize| {
+            let window = start.checked_add(len).and_then(|end| source.get(start..end));
+            window.map(|w| crc32(0, w)) == Some(expected(i))
+        };
+
+        let mismatches: Vec<usize> = (0..windows)
+            .filter(|&i| !matches(range(i).start, range(i).len(), i))
+            .collect();
+        if mismatches.is_empty() && source.len() == size {
+            return None;
+        }
+
+        let mut relaxed = source[..Ord::min(source.len(), size)].to_vec();
+        relaxed.resize(size, 0);
+        for i in mismatches {
+            let r = range(i);
+            let found = (1..=radius)
+                .flat_map(|d| [r.start.checked_add(d), r.start.checked_sub(d)])
+                .flatten()
+                .find(|&start| matches(start, r.len(), i));
+            if let Some(start) = found {
+                relaxed[r.clone()].copy_from_slice(&source[start..start + r.len()]);
+            }
+        }
+        Some(relaxed)
+    }
+
+    /// Check if the declared size differs from the source size by no more
+    /// than the search radius, otherwise the source is not relaxed at all,
+    /// since the declared size of a malicious patch could be huge.
+    fn is_near(&self, len: usize, radius: usize) -> bool {
+        self.size.abs_diff(len as u64) <= Ord::min(radius, len) as u64
+    }
+
+    /// Verify the windows overlapping the source range not verified yet.
+    fn verify<S: SourceRead>(&mut self, source: &mut S, pos: u64, len: usize) -> Result<()> {
+        let end = Ord::min(pos.saturating_add(len as u64), self.size);
+        if pos >= end {
+            return Ok(());
+        }
+        for i in (pos / self.window)..=((end - 1) / self.window) {
+            let i = i as usize;
+            if self.verified[i] {
+                continue;
+            }
+            let start = i as u64 * self.window;
+            let range = start..Ord::min(start + self.window, self.size);
+            self.scratch.resize((range.end - range.start) as usize, 0);
+            source.read_at(range.start, &mut self.scratch)?;
+            let checksum = crc32(0, &self.scratch);
+            if checksum != LE::read_u32(&self.checksums[i * 4..]) {
+                return Err(SourceCorruption { range }.into());
+            }
+            self.verified[i] = true;
+        }
+        Ok(())
     }
+}
 
-    let (_, remain) = patch.split_at(32);
-    let (bz_ctrls, remain) = remain.split_at(csize as usize);
-    let (bz_delta, bz_extra) = remain.split_at(dsize as usize);
+/// Parse the bsdiff 4.x or extended patch file, return the count of trailing
+/// bytes skipped as well.
+fn parse(patch: &[u8], tolerance: Tolerance) -> Result<(PatchFile<'_>, u64)> {
+    let header = match tolerance {
+        Tolerance::Strict => Header::parse(patch)?,
+        Tolerance::Lenient => Header::parse_prefix(patch)?,
+    };
+    let (ctrls, delta, mut extra) = header.sections(patch);
+    let [ccodec, dcodec, ecodec] = header.codecs;
 
-    let ctrls = BzDecoder::new(Cursor::new(bz_ctrls));
-    let delta = BzDecoder::new(Cursor::new(bz_delta));
-    let extra = BzDecoder::new(Cursor::new(bz_extra));
+    let mut trailing = patch.len() as u64 - header.total_size().unwrap_or_default();
+    if tolerance == Tolerance::Lenient && header.format == Format::Classic {
+        let mut decoder = bzip2::bufread::BzDecoder::new(extra);
+        io::copy(&mut decoder, &mut io::sink())?;
+        let rest = decoder.into_inner().len();
+        extra = &extra[..extra.len() - rest];
+        trailing += rest as u64;
+    }
 
-    Ok(PatchFile {
-        tsize,
+    let start = header.size() as u64;
+    let (delta_start, extra_start) = (start + ctrls.len() as u64, start + (ctrls.len() + delta.len()) as u64);
+    let ranges = [
+        start..delta_start,
+        delta_start..extra_start,
+        extra_start..extra_start + extra.len() as u64,
+    ];
+    let (ctrls, delta, extra) = match header.block_sizes(patch)? {
+        Some([csizes, dsizes, esizes]) => {
+            let ahead = available_threads();
+            (
+                Decoder::blocks(ccodec, ctrls, header.block_size, csizes, ahead)?,
+                Decoder::blocks(dcodec, delta, header.block_size, dsizes, ahead)?,
+                Decoder::blocks(ecodec, extra, header.block_size, esizes, ahead)?,
+            )
+        }
+        None => (
+            Decoder::new(ccodec, ctrls)?,
+            Decoder::new(dcodec, delta)?,
+            Decoder::new(ecodec, extra)?,
+        ),
+    };
+    let patch = PatchFile {
+        tsize: header.tsize,
+        sizes: header.sizes(),
+        decoded: header.decoded_size_hint(),
+        ctl_layout: header.control_layout(),
+        window: Some(1 << header.window_log).filter(|_| header.has_target_copy()),
+        source_check: Some(SourceCheck {
+            size: header.ssize,
+            window: header.swindow,
+            checksums: header.source_checksums(patch),
+            verified: vec![false; header.source_checksums(patch).len() / 4],
+            scratch: Vec::new(),
+        })
+        .filter(|_| header.has_source_checksum()),
+        metadata: Some(header.metadata_bytes(patch)).filter(|_| header.has_metadata()),
+        checksum: header.checksum_kind(),
+        overrun: SourceOverrun::Error,
+        ranges,
         ctrls,
         delta,
         extra,
-    })
+    };
+    Ok((patch, trailing))
 }
 
 /// Bspatch context.
-struct Context<'s, 'p, T: Write> {
-    source: Cursor<&'s [u8]>,
+struct Context<'p, S: SourceRead, T: Write> {
+    source: S,
+    ssize: u64,
+    spos: u64,
     target: T,
 
     patch: PatchFile<'p>,
 
     n: usize,
     buf: Vec<u8>,
+    ext: Vec<u8>,
     dlt: Vec<u8>,
-    ctl: [u8; 24],
+    ctl: [u8; 40],
+    history: History,
 
+    index: u64,
+    tpos: u64,
     total: u64,
+    stats: ApplyStats,
 }
 
-impl<'s, 'p, T: Write> Context<'s, 'p, T> {
-    /// Create context.
-    pub fn new(patch: PatchFile<'p>, source: &'s [u8], target: T, bsize: usize, dsize: usize) -> Self {
-        Context {
-            source: Cursor::new(source),
+impl<'p, S: SourceRead, T: Write> Context<'p, S, T> {
+    /// Create context, allocating the buffer of extra data if `vectored`.
+    ///
+    /// The buffers are capped by the target size and the decoded section
+    /// sizes if recorded by the patch.
+    pub fn new(patch: PatchFile<'p>, source: S, target: T, bsize: usize, dsize: usize, vectored: bool) -> Result<Self> {
+        let history = match patch.window {
+            Some(window) => usize::try_from(Ord::min(window, patch.tsize)).unwrap_or(usize::MAX),
+            None => 0,
+        };
+        let cap = |size: usize, hint: u64| usize::try_from(hint).map_or(size, |hint| Ord::min(size, hint));
+        let (bsize, dsize, esize) = match patch.decoded {
+            Some([_, delta, extra]) => (
+                Ord::max(cap(bsize, patch.tsize), 1),
+                cap(dsize, delta),
+                cap(bsize, extra),
+            ),
+            None => (bsize, dsize, bsize),
+        };
+        let patch_sizes = patch.ranges.clone().map(|range| range.end - range.start);
+        Ok(Context {
+            ssize: source.size()?,
+            spos: 0,
+            source,
             target,
             patch,
             n: 0,
             buf: vec![0; bsize],
+            ext: if vectored { vec![0; esize] } else { Vec::new() },
             dlt: vec![0; dsize],
-            ctl: [0; 24],
+            ctl: [0; 40],
+            history: History::new(history),
+            index: 0,
+            tpos: 0,
             total: 0,
-        }
+            stats: ApplyStats {
+                encoded_sizes: patch_sizes,
+                ..ApplyStats::default()
+            },
+        })
     }
 
     /// Apply the patch file.
-    pub fn apply(mut self) -> Result<u64> {
+    pub fn apply(&mut self) -> Result<u64> {
+        if self.ssize == 0 && self.patch.window.is_none() && self.patch.overrun == SourceOverrun::Error {
+            return self.apply_extra();
+        }
         while let Some(result) = self.next() {
             match result {
-                Ok(Control { add, copy, seek }) => {
+                Ok(Control {
+                    add,
+                    copy,
+                    seek,
+                    tcopy,
+                    tdist,
+                }) => {
                     self.add(add)?;
                     self.copy(copy)?;
+                    self.tcopy(tcopy, tdist)?;
                     self.seek(seek)?;
                 }
                 Err(e) => return Err(e),
@@ -199,33 +1581,229 @@ impl<'s, 'p, T: Write> Context<'s, 'p, T> {
         Ok(self.total)
     }
 
-    /// Read the next control.
+    /// Apply the patch file to empty source, where all target bytes are
+    /// copied from the extra section straight through the main buffer.
+    fn apply_extra(&mut self) -> Result<u64> {
+        while let Some(result) = self.next() {
+            // Delta of empty source is rejected by `next`.
+            let Control { copy, seek, .. } = result?;
+            let mut count = copy;
+            while count > 0 {
+                let k = Ord::min(count, self.buf.len() as u64) as usize;
+                self.patch
+                    .extra
+                    .read_exact(&mut self.buf[..k])
+                    .map_err(|e| self.corrupted(Section::Extra, e))?;
+                self.stats.decoded_sizes[2] += k as u64;
+                self.target.write_all(&self.buf[..k])?;
+                self.stats.copied += k as u64;
+                self.total += k as u64;
+                count -= k as u64;
+            }
+            self.seek(seek)?;
+        }
+        self.target.flush()?;
+        Ok(self.total)
+    }
+
+    /// Apply the patch file, output only the given range of target.
+    pub fn apply_range(&mut self, range: Range<u64>) -> Result<u64> {
+        let mut tpos = 0u64;
+        while tpos < range.end {
+            let Control { add, copy, seek, .. } = match self.next() {
+                Some(result) => result?,
+                None => return Err(Error::new(ErrorKind::UnexpectedEof, "target range not reached")),
+            };
+
+            let (skip, take) = overlap(tpos, add, &range);
+            self.spos += skip;
+            discard(&mut self.patch.delta, skip).map_err(|e| self.corrupted(Section::Delta, e))?;
+            self.stats.decoded_sizes[1] += skip;
+            self.add(take)?;
+            tpos += skip + take;
+            if skip + take < add {
+                break;
+            }
+
+            let (skip, take) = overlap(tpos, copy, &range);
+            discard(&mut self.patch.extra, skip).map_err(|e| self.corrupted(Section::Extra, e))?;
+            self.stats.decoded_sizes[2] += skip;
+            self.copy(take)?;
+            tpos += skip + take;
+            if skip + take < copy {
+                break;
+            }
+
+            self.seek(seek)?;
+        }
+        if self.n > 0 {
+            self.target.write_all(&self.buf[..self.n])?;
+        }
+        self.target.flush()?;
+        Ok(self.total)
+    }
+
+    /// Apply the patch file up to the given target position.
+    pub fn apply_until(&mut self, end: u64) -> Result<()> {
+        while self.total < end {
+            let Control {
+                add,
+                copy,
+                seek,
+                tcopy,
+                tdist,
+            } = match self.next() {
+                Some(result) => result?,
+                None => return Err(Error::new(ErrorKind::UnexpectedEof, "target range not reached")),
+            };
+            self.add(add)?;
+            self.copy(copy)?;
+            self.tcopy(tcopy, tdist)?;
+            self.seek(seek)?;
+        }
+        if self.n > 0 {
+            self.target.write_all(&self.buf[..self.n])?;
+        }
+        self.target.flush()
+    }
+
+    /// Read the next control, checked against the cursors and the target
+    /// size.
     fn next(&mut self) -> Option<Result<Control>> {
-        match read_exact_or_eof(&mut self.patch.ctrls, &mut self.ctl[..]) {
-            Ok(0) => return None,
-            Err(e) => return Some(Err(e)),
+        match self.patch.ctl_layout.read(&mut self.patch.ctrls, &mut self.ctl) {
+            Ok(false) => return None,
+            Err(e) => return Some(Err(self.corrupted(Section::Control, e))),
             _ => (),
         }
 
-        let add = decode_int(&self.ctl[0..]) as u64;
-        let copy = decode_int(&self.ctl[8..]) as u64;
+        let add = decode_int(&self.ctl[0..]);
+        let copy = decode_int(&self.ctl[8..]);
         let seek = decode_int(&self.ctl[16..]);
-        Some(Ok(Control { add, copy, seek }))
+        let (tcopy, tdist) = if self.patch.ctl_layout.target_copy {
+            (decode_int(&self.ctl[24..]), decode_int(&self.ctl[32..]))
+        } else {
+            (0, 0)
+        };
+        self.stats.controls += 1;
+        self.stats.decoded_sizes[0] +=
+            self.patch
+                .ctl_layout
+                .encode([add, copy, seek, tcopy, tdist], &mut [0; ControlLayout::MAX_SIZE]) as u64;
+        let checked = self.check([add, copy, tcopy, tdist], seek).map_err(|fault| {
+            CorruptControl {
+                index: self.index,
+                fault,
+            }
+            .into()
+        });
+        self.index += 1;
+        Some(checked.map(|()| Control {
+            add: add as u64,
+            copy: copy as u64,
+            seek,
+            tcopy: tcopy as u64,
+            tdist: tdist as u64,
+        }))
+    }
+
+    /// Wrap the failure of decoding a section with the progress of patching,
+    /// writing out the pending target bytes first.
+    fn corrupted(&mut self, section: Section, err: Error) -> Error {
+        if self.n == 0 || self.target.write_all(&self.buf[..self.n]).is_ok() {
+            self.n = 0;
+        }
+        let _ = self.target.flush();
+
+        let decoder = match section {
+            Section::Control => &self.patch.ctrls,
+            Section::Delta => &self.patch.delta,
+            Section::Extra => &self.patch.extra,
+        };
+        let range = self.patch.ranges[section.index()].clone();
+        let range = match decoder.block_range() {
+            Some(block) if !block.is_empty() => range.start + block.start as u64..range.start + block.end as u64,
+            _ => range,
+        };
+        SectionCorruption {
+            section,
+            control: match section {
+                Section::Control => self.index,
+                _ => self.index.saturating_sub(1),
+            },
+            written: self.total - self.n as u64,
+            decoded: self.stats.decoded_sizes,
+            range,
+            kind: err.kind(),
+        }
+        .into()
+    }
+
+    /// Check the lengths and the seek of a control, and advance the target
+    /// cursor.
+    fn check(&mut self, lengths: [i64; 4], seek: i64) -> std::result::Result<(), ControlFault> {
+        if lengths.iter().any(|&x| x < 0) {
+            return Err(ControlFault::NegativeLength);
+        }
+        let [add, copy, tcopy, tdist] = lengths.map(|x| x as u64);
+        let tpos = self
+            .tpos
+            .checked_add(add)
+            .and_then(|tpos| tpos.checked_add(copy))
+            .filter(|&tpos| tpos <= self.patch.tsize)
+            .ok_or(ControlFault::TargetOverflow)?;
+        let tend = tpos
+            .checked_add(tcopy)
+            .filter(|&tend| tend <= self.patch.tsize)
+            .ok_or(ControlFault::TargetOverflow)?;
+
+        let spos = self.spos;
+        let overrun = self.patch.overrun == SourceOverrun::Error;
+        if add > 0 && spos.checked_add(add).is_none_or(|send| send > self.ssize && overrun) {
+            return Err(ControlFault::SourceOutOfBounds);
+        }
+        if tcopy > 0 && (tdist == 0 || tdist > tpos || Some(tdist) > self.patch.window) {
+            return Err(ControlFault::InvalidDistance);
+        }
+        i64::try_from(spos + add)
+            .ok()
+            .and_then(|spos| spos.checked_add(seek))
+            .filter(|&spos| spos >= 0)
+            .ok_or(ControlFault::SeekOutOfRange)?;
+        self.tpos = tend;
+        Ok(())
     }
 
     /// Add delta to source and write the result to target.
+    ///
+    /// Delta data are decoded through the fixed delta cache, chunk by chunk.
     fn add(&mut self, mut count: u64) -> Result<()> {
+        if count > 0 && self.dlt.is_empty() {
+            let e = Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+            return Err(self.corrupted(Section::Delta, e));
+        }
         while count > 0 {
-            let k = Ord::min(count, (self.buf.len() - self.n) as u64) as usize;
+            let space = Ord::min(self.buf.len() - self.n, self.dlt.len());
+            let k = Ord::min(count, space as u64) as usize;
 
-            if k > self.dlt.len() {
-                self.dlt.resize(k, 0);
+            if let Some(ref mut check) = self.patch.source_check {
+                check.verify(&mut self.source, self.spos, k)?;
             }
-
-            self.source.read_exact(&mut self.buf[self.n..self.n + k])?;
-            self.patch.delta.read_exact(&mut self.dlt[..k])?;
+            let m = Ord::min(self.ssize.saturating_sub(self.spos), k as u64) as usize;
+            if m > 0 {
+                self.source.read_at(self.spos, &mut self.buf[self.n..self.n + m])?;
+            }
+            self.buf[self.n + m..self.n + k].fill(0);
+            self.spos += k as u64;
+            self.stats.source_span = Ord::max(self.stats.source_span, self.spos);
+            self.patch
+                .delta
+                .read_exact(&mut self.dlt[..k])
+                .map_err(|e| self.corrupted(Section::Delta, e))?;
+            self.stats.decoded_sizes[1] += k as u64;
             Iterator::zip(self.buf[self.n..self.n + k].iter_mut(), self.dlt[..k].iter())
                 .for_each(|(x, y)| *x = x.wrapping_add(*y));
+            self.history.push(&self.buf[self.n..self.n + k]);
+            self.stats.added += k as u64;
 
             self.n += k;
             if self.n >= self.buf.len() {
@@ -240,11 +1818,60 @@ impl<'s, 'p, T: Write> Context<'s, 'p, T> {
     }
 
     /// Copy extra data to target.
+    ///
+    /// Extra data overflowing the main buffer is written along with the
+    /// pending bytes by a single vectored write, if enabled.
     fn copy(&mut self, mut count: u64) -> Result<()> {
+        while !self.ext.is_empty() && count > 0 && count >= (self.buf.len() - self.n) as u64 {
+            let k = Ord::min(count, self.ext.len() as u64) as usize;
+
+            self.patch
+                .extra
+                .read_exact(&mut self.ext[..k])
+                .map_err(|e| self.corrupted(Section::Extra, e))?;
+            self.stats.decoded_sizes[2] += k as u64;
+            self.history.push(&self.ext[..k]);
+
+            let mut bufs = [IoSlice::new(&self.buf[..self.n]), IoSlice::new(&self.ext[..k])];
+            write_all_vectored(&mut self.target, &mut bufs[..])?;
+            self.stats.copied += k as u64;
+            self.n = 0;
+
+            self.total += k as u64;
+            count -= k as u64;
+        }
         while count > 0 {
             let k = Ord::min(count, (self.buf.len() - self.n) as u64) as usize;
 
-            self.patch.extra.read_exact(&mut self.buf[self.n..self.n + k])?;
+            self.patch
+                .extra
+                .read_exact(&mut self.buf[self.n..self.n + k])
+                .map_err(|e| self.corrupted(Section::Extra, e))?;
+            self.stats.decoded_sizes[2] += k as u64;
+            self.history.push(&self.buf[self.n..self.n + k]);
+            self.stats.copied += k as u64;
+
+            self.n += k;
+            if self.n >= self.buf.len() {
+                self.target.write_all(self.buf.as_ref())?;
+                self.n = 0;
+            }
+
+            self.total += k as u64;
+            count -= k as u64;
+        }
+        Ok(())
+    }
+
+    /// Copy target bytes `dist` bytes back to target, the copied bytes might
+    /// overlap the bytes being produced (the distance is checked by `next`).
+    fn tcopy(&mut self, mut count: u64, dist: u64) -> Result<()> {
+        while count > 0 {
+            let k = Ord::min(Ord::min(count, dist), (self.buf.len() - self.n) as u64) as usize;
+
+            self.history.get(dist as usize, &mut self.buf[self.n..self.n + k]);
+            self.history.push(&self.buf[self.n..self.n + k]);
+            self.stats.repeated += k as u64;
 
             self.n += k;
             if self.n >= self.buf.len() {
@@ -260,25 +1887,217 @@ impl<'s, 'p, T: Write> Context<'s, 'p, T> {
 
     /// Move the cursor on source.
     fn seek(&mut self, offset: i64) -> Result<()> {
-        self.source.seek(SeekFrom::Current(offset)).map(drop)
+        self.spos = self
+            .spos
+            .checked_add_signed(offset)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "patch corrupted"))?;
+        Ok(())
     }
 }
 
-// Read exact buf.len() bytes or reads an EOF, return read bytes count.
-#[inline]
-fn read_exact_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<usize> {
-    let mut cnt = 0;
-    while cnt < buf.len() {
-        match r.read(&mut buf[cnt..]) {
-            Ok(0) => break,
-            Ok(n) => cnt += n,
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+/// Scan the controls of the patch for the number of source bytes read and the
+/// number of reads jumping backward.
+fn source_access(patch: &[u8]) -> Result<(u64, u64)> {
+    let header = Header::parse_prefix(patch)?;
+    let (ctrls, _, _) = header.sections(patch);
+    let mut ctrls = match header.block_sizes(patch)? {
+        Some([csizes, _, _]) => Decoder::blocks(header.codecs[0], ctrls, header.block_size, csizes, 0)?,
+        None => Decoder::new(header.codecs[0], ctrls)?,
+    };
+    let layout = header.control_layout();
+    let mut ctl = [0; 40];
+    let (mut spos, mut end) = (0i64, 0i64);
+    let (mut bytes, mut backward) = (0u64, 0u64);
+    while layout.read(&mut ctrls, &mut ctl)? {
+        let add = decode_int(&ctl[0..]);
+        let seek = decode_int(&ctl[16..]);
+        if add > 0 {
+            if spos < end {
+                backward += 1;
+            }
+            bytes = bytes.saturating_add(add as u64);
+            end = spos.saturating_add(add);
+        }
+        spos = spos.saturating_add(add).saturating_add(seek);
+    }
+    Ok((bytes, backward))
+}
+
+/// Call the statistics hook if any, passing the result through.
+fn report<R>(hook: Option<Box<StatsHook<'_>>>, stats: &ApplyStats, result: Result<R>) -> Result<R> {
+    if let Some(hook) = hook {
+        hook(stats);
+    }
+    result
+}
+
+/// Call the completion hook if any.
+fn complete(hook: Option<Box<CompleteHook<'_>>>, size: u64) -> Result<u64> {
+    if let Some(hook) = hook {
+        hook(size)?;
+    }
+    Ok(size)
+}
+
+/// Writer retrying blocked writes with the wait strategy, and flushing at
+/// the requested cadence.
+struct Retry<'a, W: Write> {
+    inner: W,
+    wait: Option<Box<WaitStrategy<'a>>>,
+    position: u64,
+    flush_every: u64,
+    flushed: u64,
+}
+
+impl<'a, W: Write> Retry<'a, W> {
+    fn new(inner: W, wait: Option<Box<WaitStrategy<'a>>>, flush_every: u64) -> Self {
+        Retry {
+            inner,
+            wait,
+            position: 0,
+            flush_every,
+            flushed: 0,
+        }
+    }
+
+    /// Flush if enough bytes are written since the last flush.
+    fn written(&mut self, n: usize) -> Result<()> {
+        self.position += n as u64;
+        if self.flush_every > 0 && self.position - self.flushed >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Run the operation until it is not blocked.
+    fn retry<R, F: FnMut(&mut W) -> Result<R>>(&mut self, mut op: F) -> Result<R> {
+        let mut stall = None;
+        loop {
+            match op(&mut self.inner) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    let wait = match self.wait {
+                        Some(ref mut wait) => wait,
+                        None => return Err(e),
+                    };
+                    let (attempts, since) = stall.get_or_insert((0, Instant::now()));
+                    *attempts += 1;
+                    wait(&Stall {
+                        position: self.position,
+                        attempts: *attempts,
+                        elapsed: since.elapsed(),
+                    })?;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<'a, W: Write> Write for Retry<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.retry(|w| w.write(buf))?;
+        self.written(n)?;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        let n = self.retry(|w| w.write_vectored(bufs))?;
+        self.written(n)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.retry(|w| w.flush())?;
+        self.flushed = self.position;
+        Ok(())
+    }
+}
+
+/// Writer feeding the bytes written into a hasher.
+struct Tee<'h, W: Write, H, F: FnMut(&mut H, &[u8])> {
+    inner: W,
+    hasher: &'h mut H,
+    update: F,
+}
+
+impl<'h, W: Write, H, F: FnMut(&mut H, &[u8])> Write for Tee<'h, W, H, F> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        (self.update)(self.hasher, &buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut rest = n;
+        for buf in bufs {
+            let k = Ord::min(rest, buf.len());
+            (self.update)(self.hasher, &buf[..k]);
+            rest -= k;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer dropping bytes out of the target range.
+struct Clip<W: Write> {
+    inner: W,
+    pos: u64,
+    range: Range<u64>,
+}
+
+impl<W: Write> Clip<W> {
+    fn new(inner: W, range: Range<u64>) -> Self {
+        Clip { inner, pos: 0, range }
+    }
+}
+
+impl<W: Write> Write for Clip<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let (skip, take) = overlap(self.pos, buf.len() as u64, &self.range);
+        self.inner.write_all(&buf[skip as usize..(skip + take) as usize])?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write all buffers by vectored writes, like the unstable
+/// `Write::write_all_vectored`.
+fn write_all_vectored<W: Write>(w: &mut W, mut bufs: &mut [IoSlice<'_>]) -> Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
             Err(e) => return Err(e),
         }
     }
-    if cnt != 0 && cnt != buf.len() {
-        Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"))
-    } else {
-        Ok(cnt)
+    Ok(())
+}
+
+/// Split `len` bytes at target position `tpos` into the count of bytes to skip
+/// before the range and the count of bytes to take within the range.
+#[inline]
+fn overlap(tpos: u64, len: u64, range: &Range<u64>) -> (u64, u64) {
+    let skip = Ord::min(range.start.saturating_sub(tpos), len);
+    let take = Ord::min(len - skip, range.end.saturating_sub(tpos + skip));
+    (skip, take)
+}
+
+/// Read and drop exact `n` bytes.
+#[inline]
+fn discard<R: Read>(r: &mut R, n: u64) -> Result<()> {
+    if io::copy(&mut r.take(n), &mut io::sink())? < n {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
     }
+    Ok(())
 }
@@ -0,0 +1,453 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+#[cfg(feature = "mmap")]
+use std::fs;
+#[cfg(feature = "zstd")]
+use std::io::Read;
+use std::io::{Error, ErrorKind, Result, Write};
+#[cfg(feature = "mmap")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "mmap")]
+use std::process;
+
+use byteorder::{ByteOrder, LE};
+
+#[cfg(feature = "mmap")]
+use super::bspatch::Bspatch;
+#[cfg(feature = "mmap")]
+use super::files::{MappedFile, TempFile};
+use super::format::Header;
+
+/// Magic number bytes of patch bundles.
+pub const BUNDLE_MAGIC: &[u8] = b"QBSBNDL1";
+
+/// Size of the bundle header.
+const BUNDLE_HEADER_SIZE: usize = 24;
+
+/// Size of the fixed part of entry headers.
+const ENTRY_HEADER_SIZE: usize = 28;
+
+/// Flag of bundles having a shared compression dictionary.
+const FLAG_DICTIONARY: u32 = 1;
+
+/// Entry payload is the plain patch.
+const PAYLOAD_PLAIN: u8 = 0;
+
+/// Entry payload is the patch compressed with zstd and the shared dictionary.
+const PAYLOAD_ZSTD_DICT: u8 = 1;
+
+/// Writer of patch bundles, a single artifact holding patches of multiple files.
+///
+/// The bundle consists of magic `QBSBNDL1`, flags (u32), entry count (u32),
+/// the size of the shared dictionary (u64) and the dictionary itself, followed
+/// by the entries.
+/// Each entry consists of the length of its path (u16), payload kind (u8,
+/// plus a reserved byte), the patch size, the target size and the payload
+/// size (u64 each), the UTF-8 path and the payload.
+/// All integers are in little endian.
+///
+/// Paths are relative, separated by `/` and must not contain `.` or `..`
+/// components.
+///
+/// Example:
+///
+/// Bundle patches of an executable and its assets:
+/// ```
+/// use std::io;
+/// use qbsdiff::Bsdiff;
+/// use qbsdiff::bundle::BundleWriter;
+///
+/// fn bundle(files: &[(&str, &[u8], &[u8])]) -> io::Result<Vec<u8>> {
+///     let mut writer = BundleWriter::new();
+///     for &(path, source, target) in files {
+///         let mut patch = Vec::new();
+///         Bsdiff::new(source, target).compare(io::Cursor::new(&mut patch))?;
+///         writer.add(path, patch)?;
+///     }
+///     let mut bundle = Vec::new();
+///     writer.write_to(&mut bundle)?;
+///     Ok(bundle)
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BundleWriter {
+    entries: Vec<(String, Vec<u8>)>,
+    dictionary: Option<Vec<u8>>,
+    level: i32,
+}
+
+impl BundleWriter {
+    /// Create an empty bundle writer.
+    pub fn new() -> Self {
+        BundleWriter {
+            entries: Vec::new(),
+            dictionary: None,
+            level: 3,
+        }
+    }
+
+    /// Compress all entries with zstd and a shared dictionary (requires feature
+    /// `zstd`).
+    ///
+    /// Small patches of similar files benefit from the shared dictionary (e.g.
+    /// trained by `zstd::dict::from_samples`) a lot.
+    pub fn dictionary(mut self, dict: Vec<u8>) -> Self {
+        self.dictionary = Some(dict);
+        self
+    }
+
+    /// Set the zstd compression level of entries (default is 3).
+    ///
+    /// This only takes effect if a shared dictionary is set.
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Add patch of the file at the relative `path`.
+    ///
+    /// Return error if the path is invalid or duplicated, or the patch header
+    /// is invalid.
+    pub fn add<P: Into<String>>(&mut self, path: P, patch: Vec<u8>) -> Result<()> {
+        let path = path.into();
+        check_path(&path)?;
+        if self.entries.iter().any(|(p, _)| *p == path) {
+            return Err(Error::new(ErrorKind::InvalidInput, "duplicated bundle entry path"));
+        }
+        Header::parse(&patch)?;
+        self.entries.push((path, patch));
+        Ok(())
+    }
+
+    /// Number of entries added.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether no entry is added.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the bundle and return its size.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<u64> {
+        if self.entries.len() > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many bundle entries"));
+        }
+        let dict = self.dictionary.as_deref().unwrap_or(&[]);
+        let mut header = [0; BUNDLE_HEADER_SIZE];
+        header[0..8].copy_from_slice(BUNDLE_MAGIC);
+        let flags = if self.dictionary.is_some() { FLAG_DICTIONARY } else { 0 };
+        LE::write_u32(&mut header[8..12], flags);
+        LE::write_u32(&mut header[12..16], self.entries.len() as u32);
+        LE::write_u64(&mut header[16..24], dict.len() as u64);
+        w.write_all(&header[..])?;
+        w.write_all(dict)?;
+        let mut size = (BUNDLE_HEADER_SIZE + dict.len()) as u64;
+
+        for (path, patch) in self.entries.iter() {
+            let (kind, payload) = match self.dictionary {
+                Some(ref dict) => (PAYLOAD_ZSTD_DICT, compress(patch, dict, self.level)?),
+                None => (PAYLOAD_PLAIN, Cow::Borrowed(&patch[..])),
+            };
+            let tsize = Header::parse(patch)?.tsize;
+            let mut entry = [0; ENTRY_HEADER_SIZE];
+            LE::write_u16(&mut entry[0..2], path.len() as u16);
+            entry[2] = kind;
+            LE::write_u64(&mut entry[4..12], patch.len() as u64);
+            LE::write_u64(&mut entry[12..20], tsize);
+            LE::write_u64(&mut entry[20..28], payload.len() as u64);
+            w.write_all(&entry[..])?;
+            w.write_all(path.as_bytes())?;
+            w.write_all(&payload[..])?;
+            size += (ENTRY_HEADER_SIZE + path.len() + payload.len()) as u64;
+        }
+        w.flush()?;
+        Ok(size)
+    }
+}
+
+/// Parsed patch bundle.
+///
+/// Example:
+///
+/// Apply all patches of the bundle to files in memory:
+/// ```
+/// use std::io;
+/// use qbsdiff::Bspatch;
+/// use qbsdiff::bundle::Bundle;
+///
+/// fn apply(bundle: &[u8], source: impl Fn(&str) -> Vec<u8>) -> io::Result<Vec<(String, Vec<u8>)>> {
+///     let mut files = Vec::new();
+///     for entry in Bundle::parse(bundle)?.entries() {
+///         let patch = entry.patch()?;
+///         let mut target = Vec::new();
+///         Bspatch::new(&patch)?.apply(&source(entry.path()), &mut target)?;
+///         files.push((entry.path().to_string(), target));
+///     }
+///     Ok(files)
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Bundle<'a> {
+    entries: Vec<BundleEntry<'a>>,
+}
+
+impl<'a> Bundle<'a> {
+    /// Parse the patch bundle.
+    ///
+    /// Entries are checked for valid paths, but the patches would not be
+    /// decompressed until accessed.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < BUNDLE_HEADER_SIZE || &data[..8] != BUNDLE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a valid patch bundle"));
+        }
+        let flags = LE::read_u32(&data[8..12]);
+        if flags & !FLAG_DICTIONARY != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "unsupported bundle features"));
+        }
+        let count = LE::read_u32(&data[12..16]) as usize;
+        let (dict, mut remain) = split(&data[BUNDLE_HEADER_SIZE..], LE::read_u64(&data[16..24]))?;
+        let dictionary = if flags & FLAG_DICTIONARY != 0 { Some(dict) } else { None };
+
+        let mut paths = HashSet::new();
+        let mut entries = Vec::with_capacity(Ord::min(count, remain.len() / ENTRY_HEADER_SIZE));
+        for _ in 0..count {
+            let (entry, rest) = split(remain, ENTRY_HEADER_SIZE as u64)?;
+            let (path, rest) = split(rest, LE::read_u16(&entry[0..2]) as u64)?;
+            let (payload, rest) = split(rest, LE::read_u64(&entry[20..28]))?;
+            remain = rest;
+
+            let path = std::str::from_utf8(path).map_err(|_| corrupted())?;
+            check_path(path).map_err(|_| corrupted())?;
+            if !paths.insert(path) {
+                return Err(corrupted());
+            }
+            let kind = entry[2];
+            match kind {
+                PAYLOAD_PLAIN if payload.len() as u64 == LE::read_u64(&entry[4..12]) => {}
+                PAYLOAD_ZSTD_DICT if dictionary.is_some() => {}
+                PAYLOAD_PLAIN | PAYLOAD_ZSTD_DICT => return Err(corrupted()),
+                _ => return Err(Error::new(ErrorKind::Unsupported, "unsupported bundle entry")),
+            }
+            entries.push(BundleEntry {
+                path,
+                kind,
+                patch_size: LE::read_u64(&entry[4..12]),
+                target_size: LE::read_u64(&entry[12..20]),
+                payload,
+                dictionary,
+            });
+        }
+        if !remain.is_empty() {
+            return Err(corrupted());
+        }
+
+        Ok(Bundle { entries })
+    }
+
+    /// Get all entries in the bundle.
+    pub fn entries(&self) -> &[BundleEntry<'a>] {
+        &self.entries[..]
+    }
+
+    /// Find the entry of given path.
+    pub fn get(&self, path: &str) -> Option<&BundleEntry<'a>> {
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the bundle is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Apply all patches to files under directory `dir` atomically (requires
+    /// feature `mmap`).
+    ///
+    /// Missing source files are treated as empty, and missing parent
+    /// directories are created (and kept even if failed).
+    /// All targets are written to temporary files first, then the original
+    /// files are replaced one by one.
+    /// If any entry fails, all replaced files are rolled back and the error is
+    /// returned, thus the files are either all updated or left untouched
+    /// (unless the rollback itself fails).
+    ///
+    /// The total target data size would be returned if no error occurs.
+    #[cfg(feature = "mmap")]
+    pub fn apply<P: AsRef<Path>>(&self, dir: P) -> Result<u64> {
+        let dir = dir.as_ref();
+
+        // Stage all targets.
+        let mut staged = Vec::with_capacity(self.entries.len());
+        let mut total = 0;
+        for entry in self.entries.iter() {
+            let dest = entry_path(dir, entry.path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let source = match MappedFile::open(&dest) {
+                Ok(source) => Some(source),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            let patch = entry.patch()?;
+            let patcher = Bspatch::new(&patch)?;
+            let mut temp = TempFile::create(&dest)?;
+            temp.preallocate(patcher.hint_target_size());
+            total += patcher.apply(source.as_deref().unwrap_or(&[]), temp.file())?;
+            staged.push((dest, temp));
+        }
+
+        // Replace original files, keeping backups for rollback.
+        let mut committed: Vec<(PathBuf, Option<PathBuf>)> = Vec::with_capacity(staged.len());
+        for (dest, temp) in staged.into_iter() {
+            match commit(&dest, temp) {
+                Ok(backup) => committed.push((dest, backup)),
+                Err(e) => {
+                    for (dest, backup) in committed.into_iter().rev() {
+                        let _ = match backup {
+                            Some(backup) => fs::rename(backup, &dest),
+                            None => fs::remove_file(&dest),
+                        };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        for (_, backup) in committed.into_iter() {
+            if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// Entry of patch bundles.
+#[derive(Clone, Debug)]
+pub struct BundleEntry<'a> {
+    path: &'a str,
+    kind: u8,
+    patch_size: u64,
+    target_size: u64,
+    payload: &'a [u8],
+    dictionary: Option<&'a [u8]>,
+}
+
+impl<'a> BundleEntry<'a> {
+    /// Relative path of the file.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// Size of the (decompressed) patch.
+    pub fn patch_size(&self) -> u64 {
+        self.patch_size
+    }
+
+    /// Size of the target file.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Get the patch data, decompressing it if needed.
+    pub fn patch(&self) -> Result<Cow<'a, [u8]>> {
+        match (self.kind, self.dictionary) {
+            (PAYLOAD_ZSTD_DICT, Some(dict)) => decompress(self.payload, dict, self.patch_size).map(Cow::Owned),
+            _ => Ok(Cow::Borrowed(self.payload)),
+        }
+    }
+}
+
+/// Check whether the entry path is relative and normalized.
+fn check_path(path: &str) -> Result<()> {
+    let valid = path.len() <= u16::MAX as usize
+        && !path.contains('\\')
+        && !path.contains('\0')
+        && path.split('/').all(|c| !c.is_empty() && c != "." && c != "..");
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, "invalid bundle entry path"))
+    }
+}
+
+/// Split data at `n` bytes, return error if out of range.
+fn split(data: &[u8], n: u64) -> Result<(&[u8], &[u8])> {
+    if n > data.len() as u64 {
+        return Err(corrupted());
+    }
+    Ok(data.split_at(n as usize))
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch bundle corrupted")
+}
+
+#[cfg(feature = "mmap")]
+fn entry_path(dir: &Path, path: &str) -> PathBuf {
+    let mut dest = dir.to_path_buf();
+    dest.extend(path.split('/'));
+    dest
+}
+
+/// Persist the staged target, moving the original file aside as backup.
+#[cfg(feature = "mmap")]
+fn commit(dest: &Path, temp: TempFile) -> Result<Option<PathBuf>> {
+    let backup = if dest.exists() {
+        let name = dest.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let backup = dest.with_file_name(format!(".{}.{}.qbsdiff-backup", name, process::id()));
+        fs::rename(dest, &backup)?;
+        Some(backup)
+    } else {
+        None
+    };
+    if let Err(e) = temp.persist(dest) {
+        if let Some(ref backup) = backup {
+            let _ = fs::rename(backup, dest);
+        }
+        return Err(e);
+    }
+    Ok(backup)
+}
+
+#[cfg(feature = "zstd")]
+fn compress<'a>(patch: &[u8], dict: &[u8], level: i32) -> Result<Cow<'a, [u8]>> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(level, dict)?;
+    compressor.compress(patch).map(Cow::Owned)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(payload: &[u8], dict: &[u8], size: u64) -> Result<Vec<u8>> {
+    let mut patch = Vec::new();
+    zstd::stream::read::Decoder::with_dictionary(payload, dict)?
+        .take(size.saturating_add(1))
+        .read_to_end(&mut patch)?;
+    if patch.len() as u64 != size {
+        return Err(corrupted());
+    }
+    Ok(patch)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress<'a>(_patch: &[u8], _dict: &[u8], _level: i32) -> Result<Cow<'a, [u8]>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "bundle dictionary is not supported in this build",
+    ))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_payload: &[u8], _dict: &[u8], _size: u64) -> Result<Vec<u8>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "bundle dictionary is not supported in this build",
+    ))
+}
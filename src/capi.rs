@@ -0,0 +1,317 @@
+#![allow(unsafe_code)]
+/*!
+Stable C ABI with streaming callbacks (feature `capi`), for embedding qbsdiff
+as the delta engine of existing C/C++ updater frameworks.
+
+Patches are produced from source and target in memory (as `Bsdiff` does) and
+streamed to a write callback, and applied to a source read by read and seek
+callbacks with the target streamed to a write callback, without intermediate
+buffers of the whole source or target.
+Callbacks are always called on the calling thread, with the `user_data`
+pointer given alongside them.
+
+The declarations are in `capi/qbsdiff.h`, and the library is built by:
+```shell
+$ cargo rustc --release --lib --features capi --crate-type staticlib
+```
+or `--crate-type cdylib` for a shared library.
+
+All entry points return `QBS_OK` (zero) on success, or a negative error code
+(see `qbs_strerror`).
+Entries and structs are only ever appended, `qbs_abi_version` is bumped once
+anything is appended.
+ */
+
+use std::ffi::{c_char, c_int, c_void};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use super::bsdiff::Bsdiff;
+use super::bspatch::Bspatch;
+use super::source::{SourcePreload, SourceRead};
+
+/// Version of the C ABI.
+pub const QBS_ABI_VERSION: u32 = 1;
+
+/// Success.
+pub const QBS_OK: c_int = 0;
+/// Invalid arguments, e.g. null pointers or missing callbacks.
+pub const QBS_EINVAL: c_int = -1;
+/// Corrupted patch or source.
+pub const QBS_ECORRUPT: c_int = -2;
+/// Patch features not supported by this build.
+pub const QBS_EUNSUPPORTED: c_int = -3;
+/// A callback failed, or other I/O error.
+pub const QBS_EIO: c_int = -4;
+/// Memory exhausted or limits exceeded.
+pub const QBS_ENOMEM: c_int = -5;
+/// Unexpected internal failure.
+pub const QBS_EINTERNAL: c_int = -6;
+
+/// Write callback, returning the number of bytes written (at least one for a
+/// non-empty buffer), or a negative value on failure.
+pub type QbsWriteFn = unsafe extern "C" fn(user_data: *mut c_void, buf: *const u8, len: usize) -> isize;
+
+/// Flush callback, returning zero on success.
+pub type QbsFlushFn = unsafe extern "C" fn(user_data: *mut c_void) -> c_int;
+
+/// Read callback, returning the number of bytes read (zero at the end), or a
+/// negative value on failure.
+pub type QbsReadFn = unsafe extern "C" fn(user_data: *mut c_void, buf: *mut u8, len: usize) -> isize;
+
+/// Seek callback with `whence` of `SEEK_SET`, `SEEK_CUR` or `SEEK_END` (0, 1
+/// and 2), returning the new offset from the start, or a negative value on
+/// failure.
+pub type QbsSeekFn = unsafe extern "C" fn(user_data: *mut c_void, offset: i64, whence: c_int) -> i64;
+
+/// Sink of a stream, the flush callback is optional.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct QbsWriter {
+    pub write: Option<QbsWriteFn>,
+    pub flush: Option<QbsFlushFn>,
+    pub user_data: *mut c_void,
+}
+
+/// Random accessible source, read by seeking and reading.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct QbsSource {
+    pub read: Option<QbsReadFn>,
+    pub seek: Option<QbsSeekFn>,
+    pub user_data: *mut c_void,
+}
+
+/// Get the version of the C ABI.
+#[no_mangle]
+pub extern "C" fn qbs_abi_version() -> u32 {
+    QBS_ABI_VERSION
+}
+
+/// Get the static, nul-terminated description of an error code.
+#[no_mangle]
+pub extern "C" fn qbs_strerror(code: c_int) -> *const c_char {
+    let message: &'static [u8] = match code {
+        QBS_OK => b"success\0",
+        QBS_EINVAL => b"invalid argument\0",
+        QBS_ECORRUPT => b"patch or source corrupted\0",
+        QBS_EUNSUPPORTED => b"unsupported patch\0",
+        QBS_EIO => b"callback or I/O failure\0",
+        QBS_ENOMEM => b"out of memory\0",
+        QBS_EINTERNAL => b"internal error\0",
+        _ => b"unknown error\0",
+    };
+    message.as_ptr().cast()
+}
+
+/// Compare source with target, and stream the patch to `patch`.
+///
+/// If `patch_size` is not null, the size of the patch is stored there.
+///
+/// # Safety
+///
+/// `source` and `target` must point to `source_len` and `target_len`
+/// readable bytes (or be null if the length is zero), `patch` must point to
+/// a valid writer whose callbacks accept its `user_data`, and `patch_size`
+/// must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn qbs_diff(
+    source: *const u8,
+    source_len: usize,
+    target: *const u8,
+    target_len: usize,
+    patch: *const QbsWriter,
+    patch_size: *mut u64,
+) -> c_int {
+    guard(|| {
+        let source = bytes(source, source_len)?;
+        let target = bytes(target, target_len)?;
+        let patch = Writer::new(patch)?;
+        let size = Bsdiff::try_new(source, target)?.compare(patch)?;
+        if !patch_size.is_null() {
+            *patch_size = size;
+        }
+        Ok(())
+    })
+}
+
+/// Get the target size declared by the patch header.
+///
+/// # Safety
+///
+/// `patch` must point to `patch_len` readable bytes, and `target_size` must
+/// be writable.
+#[no_mangle]
+pub unsafe extern "C" fn qbs_patch_target_size(patch: *const u8, patch_len: usize, target_size: *mut u64) -> c_int {
+    guard(|| {
+        let patch = bytes(patch, patch_len)?;
+        if target_size.is_null() {
+            return Err(invalid());
+        }
+        *target_size = Bspatch::new(patch)?.hint_target_size();
+        Ok(())
+    })
+}
+
+/// Apply the patch to the source read by `source`, and stream the target to
+/// `target`.
+///
+/// The source is read by seeking and reading as required by each control,
+/// memory usage is bounded by the buffers of `Bspatch`.
+///
+/// If `target_size` is not null, the size of the target is stored there.
+///
+/// # Safety
+///
+/// `patch` must point to `patch_len` readable bytes, `source` and `target`
+/// must point to valid source and writer whose callbacks accept their
+/// `user_data`, and `target_size` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn qbs_patch(
+    patch: *const u8,
+    patch_len: usize,
+    source: *const QbsSource,
+    target: *const QbsWriter,
+    target_size: *mut u64,
+) -> c_int {
+    guard(|| {
+        let patch = bytes(patch, patch_len)?;
+        let source = Source::new(source)?;
+        let target = Writer::new(target)?;
+        let size = Bspatch::new(patch)?
+            .source_preload(SourcePreload::Seek)
+            .apply_source(source, target)?;
+        if !target_size.is_null() {
+            *target_size = size;
+        }
+        Ok(())
+    })
+}
+
+/// Run the entry point, mapping errors and panics to error codes.
+fn guard<F: FnOnce() -> Result<()>>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => QBS_OK,
+        Ok(Err(e)) => match e.kind() {
+            ErrorKind::InvalidInput => QBS_EINVAL,
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => QBS_ECORRUPT,
+            ErrorKind::Unsupported => QBS_EUNSUPPORTED,
+            ErrorKind::OutOfMemory => QBS_ENOMEM,
+            _ => QBS_EIO,
+        },
+        Err(_) => QBS_EINTERNAL,
+    }
+}
+
+/// Borrow the bytes given by pointer and length.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(invalid()),
+        // SAFETY: guaranteed by the caller of the entry point.
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Writer calling back.
+struct Writer(QbsWriter, QbsWriteFn);
+
+impl Writer {
+    /// Copy the callbacks given by pointer.
+    unsafe fn new(writer: *const QbsWriter) -> Result<Self> {
+        // SAFETY: null or valid, guaranteed by the caller of the entry point.
+        let writer = writer.as_ref().ok_or_else(invalid)?;
+        let write = writer.write.ok_or_else(invalid)?;
+        Ok(Writer(*writer, write))
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // SAFETY: the callback accepts its user data and a readable buffer.
+        let n = unsafe { (self.1)(self.0.user_data, buf.as_ptr(), buf.len()) };
+        match usize::try_from(n) {
+            Ok(n) if n <= buf.len() => Ok(n),
+            _ => Err(callback_failed("write")),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.0.flush {
+            // SAFETY: the callback accepts its user data.
+            Some(flush) if unsafe { flush(self.0.user_data) } != 0 => Err(callback_failed("flush")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Source calling back.
+struct Source {
+    user_data: *mut c_void,
+    read: QbsReadFn,
+    seek: QbsSeekFn,
+    size: u64,
+}
+
+impl Source {
+    /// Copy the callbacks given by pointer, and seek to the end for the size.
+    unsafe fn new(source: *const QbsSource) -> Result<Self> {
+        // SAFETY: null or valid, guaranteed by the caller of the entry point.
+        let source = source.as_ref().ok_or_else(invalid)?;
+        let mut source = Source {
+            user_data: source.user_data,
+            read: source.read.ok_or_else(invalid)?,
+            seek: source.seek.ok_or_else(invalid)?,
+            size: 0,
+        };
+        source.size = source.seek(SeekFrom::End(0))?;
+        Ok(source)
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // SAFETY: the callback accepts its user data and a writable buffer.
+        let n = unsafe { (self.read)(self.user_data, buf.as_mut_ptr(), buf.len()) };
+        match usize::try_from(n) {
+            Ok(n) if n <= buf.len() => Ok(n),
+            _ => Err(callback_failed("read")),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (i64::try_from(offset).map_err(|_| invalid())?, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        // SAFETY: the callback accepts its user data.
+        let n = unsafe { (self.seek)(self.user_data, offset, whence) };
+        u64::try_from(n).map_err(|_| callback_failed("seek"))
+    }
+}
+
+impl SourceRead for Source {
+    fn size(&self) -> Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+}
+
+fn invalid() -> Error {
+    Error::new(ErrorKind::InvalidInput, "invalid argument")
+}
+
+fn callback_failed(name: &str) -> Error {
+    Error::other(format!("{} callback failed", name))
+}
@@ -0,0 +1,391 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use byteorder::{ByteOrder, LE};
+use sha2::{Digest, Sha256};
+
+/// Magic number bytes of chunk manifests.
+pub const MANIFEST_MAGIC: &[u8] = b"QBSCDC01";
+
+/// Default minimal chunk size.
+pub const MIN_SIZE: usize = 16 * 1024;
+
+/// Default average chunk size.
+pub const AVG_SIZE: usize = 64 * 1024;
+
+/// Default maximal chunk size.
+pub const MAX_SIZE: usize = 256 * 1024;
+
+/// Size of chunk hashes (SHA-256).
+pub const HASH_SIZE: usize = 32;
+
+/// Gear table of the rolling hash.
+const GEAR: [u64; 256] = gear_table();
+
+/// Content-defined chunking parameters (FastCDC with normalized chunking).
+///
+/// Example:
+///
+/// Reconstruct the new version from the old one, and fetch missing chunks
+/// elsewhere (e.g. via HTTP range requests of the new version):
+/// ```
+/// use std::io;
+/// use qbsdiff::cdc::{ChunkerConfig, Manifest};
+///
+/// fn sync(old: &[u8], new: &[u8]) -> io::Result<Vec<u8>> {
+///     let config = ChunkerConfig::default();
+///     let old_manifest = Manifest::build(old, config);
+///     let new_manifest = Manifest::build(new, config);
+///
+///     let delta = new_manifest.diff(&old_manifest);
+///     let mut target = Vec::new();
+///     delta.reconstruct(old, |chunk| Ok(new[chunk.range()].to_vec()), &mut target)?;
+///     Ok(target)
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ChunkerConfig {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        ChunkerConfig {
+            min_size: MIN_SIZE,
+            avg_size: AVG_SIZE,
+            max_size: MAX_SIZE,
+        }
+    }
+}
+
+impl ChunkerConfig {
+    /// Create chunking parameters.
+    ///
+    /// The average size is rounded to a power of two (in range `256..=1 GiB`),
+    /// and the sizes are adjusted to keep `min_size <= avg_size <= max_size`
+    /// and `max_size < 4 GiB`.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let avg_size = avg_size.clamp(256, 1 << 30).next_power_of_two();
+        let min_size = min_size.clamp(64, avg_size);
+        let max_size = max_size.clamp(avg_size, u32::MAX as usize);
+        ChunkerConfig {
+            min_size,
+            avg_size,
+            max_size,
+        }
+    }
+
+    /// Get the minimal chunk size.
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Get the average chunk size.
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    /// Get the maximal chunk size.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Split data into content-defined chunks.
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Chunker<'a> {
+        Chunker::new(data, *self)
+    }
+}
+
+/// Iterator over content-defined chunks, returning `(offset, length)`.
+pub struct Chunker<'a> {
+    data: &'a [u8],
+    pos: usize,
+    config: ChunkerConfig,
+    mask_s: u64,
+    mask_l: u64,
+}
+
+impl<'a> Chunker<'a> {
+    /// Create chunker over the data.
+    pub fn new(data: &'a [u8], config: ChunkerConfig) -> Self {
+        let bits = config.avg_size.trailing_zeros();
+        Chunker {
+            data,
+            pos: 0,
+            config,
+            mask_s: high_bits_mask(bits + 2),
+            mask_l: high_bits_mask(bits - 2),
+        }
+    }
+
+    /// Find the next cut point in `data`.
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.config.min_size {
+            return data.len();
+        }
+        let max = Ord::min(data.len(), self.config.max_size);
+        let normal = Ord::min(self.config.avg_size, max);
+
+        let mut hash = 0u64;
+        let mut i = self.config.min_size;
+        while i < normal {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_s == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        while i < max {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_l == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        max
+    }
+}
+
+impl<'a> Iterator for Chunker<'a> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let offset = self.pos;
+        let n = self.cut(&self.data[offset..]);
+        self.pos += n;
+        Some((offset, n))
+    }
+}
+
+/// Chunk described in manifests.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Chunk {
+    pub offset: u64,
+    pub length: u64,
+    pub hash: [u8; HASH_SIZE],
+}
+
+impl Chunk {
+    /// Get the byte range of the chunk.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset as usize..(self.offset + self.length) as usize
+    }
+}
+
+/// Chunk manifest (list of chunk hashes) of some data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Manifest {
+    pub config: ChunkerConfig,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+impl Manifest {
+    /// Chunk the data and build the manifest.
+    pub fn build(data: &[u8], config: ChunkerConfig) -> Self {
+        let chunks = config
+            .chunks(data)
+            .map(|(offset, length)| Chunk {
+                offset: offset as u64,
+                length: length as u64,
+                hash: chunk_hash(&data[offset..offset + length]),
+            })
+            .collect();
+        Manifest {
+            config,
+            size: data.len() as u64,
+            chunks,
+        }
+    }
+
+    /// Serialize the manifest.
+    ///
+    /// Layout: magic `QBSCDC01`, data size (u64), min/avg/max chunk size (u32
+    /// each), chunk count (u64), then the length (u32) and hash of each chunk.
+    /// All integers are in little endian.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        let mut header = [0; 36];
+        header[0..8].copy_from_slice(MANIFEST_MAGIC);
+        LE::write_u64(&mut header[8..16], self.size);
+        LE::write_u32(&mut header[16..20], self.config.min_size as u32);
+        LE::write_u32(&mut header[20..24], self.config.avg_size as u32);
+        LE::write_u32(&mut header[24..28], self.config.max_size as u32);
+        LE::write_u64(&mut header[28..36], self.chunks.len() as u64);
+        w.write_all(&header[..])?;
+
+        let mut buf = [0; 4 + HASH_SIZE];
+        for chunk in self.chunks.iter() {
+            LE::write_u32(&mut buf[0..4], chunk.length as u32);
+            buf[4..].copy_from_slice(&chunk.hash[..]);
+            w.write_all(&buf[..])?;
+        }
+        w.flush()
+    }
+
+    /// Parse the serialized manifest.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 36 || &data[..8] != MANIFEST_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a valid chunk manifest"));
+        }
+        let size = LE::read_u64(&data[8..16]);
+        let config = ChunkerConfig::new(
+            LE::read_u32(&data[16..20]) as usize,
+            LE::read_u32(&data[20..24]) as usize,
+            LE::read_u32(&data[24..28]) as usize,
+        );
+        let count = LE::read_u64(&data[28..36]);
+        let records = &data[36..];
+        if count.checked_mul((4 + HASH_SIZE) as u64) != Some(records.len() as u64) {
+            return Err(Error::new(ErrorKind::InvalidData, "chunk manifest corrupted"));
+        }
+
+        let mut offset = 0u64;
+        let mut chunks = Vec::with_capacity(count as usize);
+        for record in records.chunks(4 + HASH_SIZE) {
+            let length = LE::read_u32(&record[0..4]) as u64;
+            let mut hash = [0; HASH_SIZE];
+            hash.copy_from_slice(&record[4..]);
+            chunks.push(Chunk { offset, length, hash });
+            offset += length;
+        }
+        if offset != size {
+            return Err(Error::new(ErrorKind::InvalidData, "chunk manifest corrupted"));
+        }
+
+        Ok(Manifest { config, size, chunks })
+    }
+
+    /// Compare with the manifest of old data, determine which chunks could be
+    /// reused from the old data.
+    pub fn diff(&self, old: &Manifest) -> ManifestDiff {
+        let known: HashMap<&[u8; HASH_SIZE], &Chunk> =
+            old.chunks.iter().rev().map(|chunk| (&chunk.hash, chunk)).collect();
+        let entries = self
+            .chunks
+            .iter()
+            .map(|chunk| ChunkEntry {
+                chunk: *chunk,
+                source_offset: known
+                    .get(&chunk.hash)
+                    .filter(|c| c.length == chunk.length)
+                    .map(|c| c.offset),
+            })
+            .collect();
+        ManifestDiff {
+            size: self.size,
+            entries,
+        }
+    }
+}
+
+/// Chunk of the new data, and where to find it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ChunkEntry {
+    /// Chunk of the new data.
+    pub chunk: Chunk,
+
+    /// Offset of the same chunk in the old data, or `None` if it should be
+    /// fetched elsewhere.
+    pub source_offset: Option<u64>,
+}
+
+/// Difference between chunk manifests.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestDiff {
+    pub size: u64,
+    pub entries: Vec<ChunkEntry>,
+}
+
+impl ManifestDiff {
+    /// Iterate the chunks missing in the old data.
+    pub fn missing(&self) -> impl Iterator<Item = &Chunk> {
+        self.entries
+            .iter()
+            .filter(|e| e.source_offset.is_none())
+            .map(|e| &e.chunk)
+    }
+
+    /// Total size of the chunks missing in the old data.
+    pub fn missing_size(&self) -> u64 {
+        self.missing().map(|c| c.length).sum()
+    }
+
+    /// Reconstruct the new data, fetching missing chunks with `fetch`.
+    ///
+    /// Every chunk is verified against its hash before being written.
+    /// To resume an interrupted reconstruction, call `reconstruct_from` with
+    /// the index of the first chunk not written yet.
+    ///
+    /// The size of new data would be returned if no error occurs.
+    pub fn reconstruct<F, W>(&self, source: &[u8], fetch: F, target: W) -> Result<u64>
+    where
+        F: FnMut(&Chunk) -> Result<Vec<u8>>,
+        W: Write,
+    {
+        self.reconstruct_from(0, source, fetch, target)
+    }
+
+    /// Reconstruct the new data starting from the chunk `start`.
+    ///
+    /// The number of bytes written would be returned if no error occurs.
+    pub fn reconstruct_from<F, W>(&self, start: usize, source: &[u8], mut fetch: F, mut target: W) -> Result<u64>
+    where
+        F: FnMut(&Chunk) -> Result<Vec<u8>>,
+        W: Write,
+    {
+        let mut total = 0;
+        for entry in self.entries.iter().skip(start) {
+            let chunk = &entry.chunk;
+            let fetched;
+            let data = match entry.source_offset {
+                Some(offset) => source
+                    .get(offset as usize..(offset + chunk.length) as usize)
+                    .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "source too short"))?,
+                None => {
+                    fetched = fetch(chunk)?;
+                    &fetched[..]
+                }
+            };
+            if data.len() as u64 != chunk.length || chunk_hash(data) != chunk.hash {
+                return Err(Error::new(ErrorKind::InvalidData, "chunk hash mismatch"));
+            }
+            target.write_all(data)?;
+            total += chunk.length;
+        }
+        target.flush()?;
+        Ok(total)
+    }
+}
+
+/// Hash of chunk data.
+fn chunk_hash(data: &[u8]) -> [u8; HASH_SIZE] {
+    Sha256::digest(data).into()
+}
+
+/// Mask of the highest `bits` bits.
+const fn high_bits_mask(bits: u32) -> u64 {
+    (u64::MAX >> (64 - bits)) << (64 - bits)
+}
+
+/// Generate the gear table with splitmix64.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = 0x7162_7364_6966_6663u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
@@ -0,0 +1,172 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Result};
+
+#[cfg(feature = "sha256")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "xxh3")]
+use xxhash_rust::xxh3::Xxh3;
+
+use super::utils::{crc32, crc32c};
+
+/// Checksum algorithm of the sections of extended patches, see
+/// `Bsdiff::checksum`.
+///
+/// The algorithm is recorded in the patch header, and the checksums are
+/// verified by patchers before patching.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumKind {
+    /// CRC-32 (IEEE 802.3), understood by all qbsdiff versions with checksums.
+    Crc32,
+
+    /// CRC-32C (Castagnoli), cheap on embedded targets with hardware support.
+    Crc32c,
+
+    /// 64-bit XXH3, the fastest for large patches (requires feature `xxh3`).
+    Xxh3,
+
+    /// SHA-256, the only one resisting deliberate collisions (requires
+    /// feature `sha256`).
+    Sha256,
+}
+
+/// Strength of checksums, ordered from the weakest, see
+/// `Bspatch::require_checksum`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Strength {
+    /// No checksums at all.
+    None,
+
+    /// 32-bit checksums detecting accidental corruption.
+    Weak,
+
+    /// 64-bit hashes, missing accidental corruption with negligible chance.
+    Strong,
+
+    /// Cryptographic hashes, which could not be forged to match a tampered
+    /// patch, as long as the checksums themselves are authenticated (e.g. the
+    /// patch header is signed).
+    Cryptographic,
+}
+
+/// Incremental computation of a checksum, see `ChecksumKind::hasher`.
+pub trait Checksum {
+    /// Update the checksum with bytes.
+    fn update(&mut self, data: &[u8]);
+
+    /// Get the digest of all bytes so far, in `ChecksumKind::size` bytes.
+    fn digest(&self) -> Vec<u8>;
+}
+
+impl ChecksumKind {
+    /// Check whether the support of this algorithm is compiled in.
+    pub fn is_supported(self) -> bool {
+        match self {
+            ChecksumKind::Crc32 | ChecksumKind::Crc32c => true,
+            ChecksumKind::Xxh3 => cfg!(feature = "xxh3"),
+            ChecksumKind::Sha256 => cfg!(feature = "sha256"),
+        }
+    }
+
+    /// Strength of the algorithm.
+    pub fn strength(self) -> Strength {
+        match self {
+            ChecksumKind::Crc32 | ChecksumKind::Crc32c => Strength::Weak,
+            ChecksumKind::Xxh3 => Strength::Strong,
+            ChecksumKind::Sha256 => Strength::Cryptographic,
+        }
+    }
+
+    /// Size of the digest in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumKind::Crc32 | ChecksumKind::Crc32c => 4,
+            ChecksumKind::Xxh3 => 8,
+            ChecksumKind::Sha256 => 32,
+        }
+    }
+
+    /// Create the incremental checksum.
+    ///
+    /// Return `ErrorKind::Unsupported` if the algorithm is not compiled in.
+    pub fn hasher(self) -> Result<Box<dyn Checksum + Send>> {
+        match self {
+            ChecksumKind::Crc32 => Ok(Box::new(Crc(0, crc32))),
+            ChecksumKind::Crc32c => Ok(Box::new(Crc(0, crc32c))),
+            #[cfg(feature = "xxh3")]
+            ChecksumKind::Xxh3 => Ok(Box::new(Xxh3::new())),
+            #[cfg(feature = "sha256")]
+            ChecksumKind::Sha256 => Ok(Box::new(Sha256::new())),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::new(ErrorKind::Unsupported, "checksum not compiled in")),
+        }
+    }
+
+    /// Compute the digest of bytes.
+    ///
+    /// Return `ErrorKind::Unsupported` if the algorithm is not compiled in.
+    pub fn digest(self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut hasher = self.hasher()?;
+        hasher.update(data);
+        Ok(hasher.digest())
+    }
+
+    /// Algorithm identifier used in the extended patch header.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            ChecksumKind::Crc32 => 0,
+            ChecksumKind::Crc32c => 1,
+            ChecksumKind::Xxh3 => 2,
+            ChecksumKind::Sha256 => 3,
+        }
+    }
+
+    /// Get algorithm from the identifier used in the extended patch header.
+    pub(crate) fn from_id(id: u8) -> Option<ChecksumKind> {
+        match id {
+            0 => Some(ChecksumKind::Crc32),
+            1 => Some(ChecksumKind::Crc32c),
+            2 => Some(ChecksumKind::Xxh3),
+            3 => Some(ChecksumKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// CRC-32 of either polynomial, digest in little endian.
+struct Crc(u32, fn(u32, &[u8]) -> u32);
+
+impl Checksum for Crc {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = (self.1)(self.0, data);
+    }
+
+    fn digest(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+}
+
+/// XXH3 digest in little endian.
+#[cfg(feature = "xxh3")]
+impl Checksum for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn digest(&self) -> Vec<u8> {
+        Xxh3::digest(self).to_le_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "sha256")]
+impl Checksum for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn digest(&self) -> Vec<u8> {
+        self.clone().finalize().to_vec()
+    }
+}
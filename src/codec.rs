@@ -0,0 +1,606 @@
+#![forbid(unsafe_code)]
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::mem;
+use std::ops::Range;
+#[cfg(feature = "parallel")]
+use std::sync::mpsc::{self, Receiver};
+#[cfg(feature = "parallel")]
+use std::thread;
+
+use bzip2::read::{BzDecoder, MultiBzDecoder};
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+
+/// Compression codec of patch sections.
+///
+/// Classic bsdiff 4.x patches always use `Codec::Bzip2`, other codecs are
+/// only available in the extended patch format.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Codec {
+    /// Store section data uncompressed, fastest to apply.
+    Stored,
+
+    /// Compress section data with bzip2.
+    Bzip2,
+
+    /// Compress section data with zstd (requires feature `zstd`).
+    Zstd,
+}
+
+impl Codec {
+    /// Check whether the support of this codec is compiled in.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Codec::Stored | Codec::Bzip2 => true,
+            Codec::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Codec identifier used in the extended patch header.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Codec::Stored => 0,
+            Codec::Bzip2 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    /// Get codec from the identifier used in the extended patch header.
+    pub(crate) fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::Stored),
+            1 => Some(Codec::Bzip2),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Priority of the automatic codec selection, see `Bsdiff::auto_codec`.
+///
+/// Codecs compiled in are ranked by their speed of decompression, `Stored`
+/// being the fastest, then `Zstd`, then `Bzip2`.
+/// The fastest codec compressing the sample of a section no more than the
+/// tolerance of the priority larger than the smallest is chosen.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CodecPriority {
+    /// The smallest patch, each codec at its maximum level.
+    Smallest,
+
+    /// The fastest patching, tolerating 50% larger sections.
+    FastestApply,
+
+    /// Balanced between both, tolerating 10% larger sections.
+    Balanced,
+}
+
+impl CodecPriority {
+    /// Candidate codecs and levels, from the fastest to decompress to the
+    /// slowest.
+    fn candidates(self) -> Vec<(Codec, u32)> {
+        let (bzip2, zstd) = match self {
+            CodecPriority::Smallest => (9, 19),
+            CodecPriority::FastestApply | CodecPriority::Balanced => (6, 3),
+        };
+        [(Codec::Stored, 0), (Codec::Zstd, zstd), (Codec::Bzip2, bzip2)]
+            .into_iter()
+            .filter(|&(codec, _)| codec.is_supported())
+            .collect()
+    }
+
+    /// Tolerance of larger sections, in percent of the smallest.
+    fn tolerance(self) -> usize {
+        match self {
+            CodecPriority::Smallest => 0,
+            CodecPriority::FastestApply => 50,
+            CodecPriority::Balanced => 10,
+        }
+    }
+}
+
+/// Section encoder.
+pub(crate) enum Encoder<W: Write> {
+    Stored(W),
+    Bzip2(BzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Create encoder of given codec and compression level.
+    pub fn new(codec: Codec, level: u32, w: W) -> Result<Self> {
+        match codec {
+            Codec::Stored => Ok(Encoder::Stored(w)),
+            Codec::Bzip2 => Ok(Encoder::Bzip2(BzEncoder::new(w, Compression::new(level.clamp(1, 9))))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(w, level as i32)?)),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(unsupported(codec)),
+        }
+    }
+
+    /// Finish the compressed stream and return the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        match self {
+            Encoder::Stored(w) => Ok(w),
+            Encoder::Bzip2(enc) => enc.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(enc) => enc.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Encoder::Stored(w) => w.write(buf),
+            Encoder::Bzip2(enc) => enc.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(enc) => enc.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Encoder::Stored(w) => w.flush(),
+            Encoder::Bzip2(enc) => enc.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(enc) => enc.flush(),
+        }
+    }
+}
+
+/// Size of leading section data sampled by `SectionEncoder` to probe the
+/// compression level.
+const PROBE_SIZE: usize = 256 * 1024;
+
+/// Section encoder, optionally probing the codec and compression level on a
+/// sample of the leading data first, and splitting the section into
+/// independently compressed blocks.
+pub(crate) enum SectionEncoder {
+    Probing {
+        codec: Codec,
+        level: u32,
+        stored: bool,
+        priority: Option<CodecPriority>,
+        block_size: usize,
+        sample: Vec<u8>,
+    },
+    Encoding {
+        codec: Codec,
+        level: u32,
+        encoder: Encoder<Vec<u8>>,
+        blocks: Option<Blocks>,
+    },
+}
+
+/// Blocks finished so far, and the decoded size of the current block.
+pub(crate) struct Blocks {
+    size: usize,
+    filled: usize,
+    data: Vec<u8>,
+    sizes: Vec<u32>,
+}
+
+impl SectionEncoder {
+    /// Create section encoder of given codec and compression level, or probe
+    /// them if `auto` (`stored` allows falling back to `Codec::Stored`), or
+    /// choose the codec by `priority` if any.
+    ///
+    /// The section is split into blocks of `block_size` decoded bytes unless
+    /// zero.
+    pub fn new(
+        codec: Codec,
+        level: u32,
+        auto: bool,
+        stored: bool,
+        priority: Option<CodecPriority>,
+        block_size: usize,
+    ) -> Result<Self> {
+        if priority.is_some() {
+            return Ok(SectionEncoder::Probing {
+                codec,
+                level,
+                stored,
+                priority,
+                block_size,
+                sample: Vec::new(),
+            });
+        }
+        if auto && codec != Codec::Stored {
+            if !codec.is_supported() {
+                return Err(unsupported(codec));
+            }
+            return Ok(SectionEncoder::Probing {
+                codec,
+                level,
+                stored,
+                priority: None,
+                block_size,
+                sample: Vec::new(),
+            });
+        }
+        SectionEncoder::encoding(codec, level, block_size)
+    }
+
+    /// Start encoding with the codec and compression level.
+    fn encoding(codec: Codec, level: u32, block_size: usize) -> Result<Self> {
+        Ok(SectionEncoder::Encoding {
+            codec,
+            level,
+            encoder: Encoder::new(codec, level, Vec::new())?,
+            blocks: Some(Blocks {
+                size: block_size,
+                filled: 0,
+                data: Vec::new(),
+                sizes: Vec::new(),
+            })
+            .filter(|_| block_size > 0),
+        })
+    }
+
+    /// Finish the compressed section, returning the codec chosen, the
+    /// section data and the encoded sizes of blocks if split.
+    pub fn finish(mut self) -> Result<(Codec, Vec<u8>, Option<Vec<u32>>)> {
+        self.decide()?;
+        let SectionEncoder::Encoding {
+            codec, encoder, blocks, ..
+        } = self
+        else {
+            unreachable!("section encoder not started");
+        };
+        let data = encoder.finish()?;
+        match blocks {
+            Some(mut blocks) => {
+                // Empty sections consist of no blocks.
+                if blocks.filled > 0 {
+                    blocks.sizes.push(block_len(data.len())?);
+                    blocks.data.extend_from_slice(&data[..]);
+                }
+                Ok((codec, blocks.data, Some(blocks.sizes)))
+            }
+            None => Ok((codec, data, None)),
+        }
+    }
+
+    /// Probe on the sample and start encoding.
+    fn decide(&mut self) -> Result<()> {
+        if let SectionEncoder::Probing {
+            codec,
+            level,
+            stored,
+            priority,
+            block_size,
+            ref mut sample,
+        } = *self
+        {
+            let (codec, level) = match priority {
+                Some(priority) => choose(priority, &sample[..])?,
+                None => probe(codec, level, stored, &sample[..])?,
+            };
+            let sample = mem::take(sample);
+            *self = SectionEncoder::encoding(codec, level, block_size)?;
+            self.write_all(&sample[..])?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for SectionEncoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            SectionEncoder::Probing { sample, .. } if sample.len() < PROBE_SIZE => {
+                let n = Ord::min(buf.len(), PROBE_SIZE - sample.len());
+                sample.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            SectionEncoder::Probing { .. } => {
+                self.decide()?;
+                self.write(buf)
+            }
+            SectionEncoder::Encoding {
+                codec,
+                level,
+                encoder,
+                blocks: Some(blocks),
+            } => {
+                if blocks.filled == blocks.size {
+                    let block = mem::replace(encoder, Encoder::new(*codec, *level, Vec::new())?).finish()?;
+                    blocks.sizes.push(block_len(block.len())?);
+                    blocks.data.extend_from_slice(&block[..]);
+                    blocks.filled = 0;
+                }
+                let n = Ord::min(buf.len(), blocks.size - blocks.filled);
+                let n = encoder.write(&buf[..n])?;
+                blocks.filled += n;
+                Ok(n)
+            }
+            SectionEncoder::Encoding { encoder, .. } => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            SectionEncoder::Probing { .. } => Ok(()),
+            SectionEncoder::Encoding { encoder, .. } => encoder.flush(),
+        }
+    }
+}
+
+/// Encoded size of a block, which is recorded as u32.
+fn block_len(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| Error::new(ErrorKind::InvalidInput, "encoded block is too large"))
+}
+
+/// Choose the compression level of the codec for the sample: the fastest one
+/// of level 1, the given level and the maximum level, producing no more than
+/// 1% larger data than the best of them, or storing data uncompressed if
+/// allowed and saving less than 2%.
+fn probe(codec: Codec, level: u32, stored: bool, sample: &[u8]) -> Result<(Codec, u32)> {
+    if sample.is_empty() {
+        return Ok(if stored { (Codec::Stored, 0) } else { (codec, level) });
+    }
+    let max = match codec {
+        Codec::Bzip2 => 9,
+        _ => 19,
+    };
+    let mut levels = vec![1, Ord::max(level, 1), max];
+    levels.sort_unstable();
+    levels.dedup();
+
+    let mut sizes = Vec::with_capacity(levels.len());
+    for &level in levels.iter() {
+        let mut encoder = Encoder::new(codec, level, Vec::new())?;
+        encoder.write_all(sample)?;
+        sizes.push(encoder.finish()?.len());
+    }
+    let best = sizes.iter().copied().min().unwrap_or(0);
+    let (level, size) = Iterator::zip(levels.into_iter(), sizes)
+        .find(|&(_, size)| size <= best + best / 100)
+        .unwrap_or((level, best));
+    if stored && size >= sample.len() - sample.len() / 50 {
+        return Ok((Codec::Stored, 0));
+    }
+    Ok((codec, level))
+}
+
+/// Choose the codec and compression level for the sample by priority.
+fn choose(priority: CodecPriority, sample: &[u8]) -> Result<(Codec, u32)> {
+    let candidates = priority.candidates();
+    let mut sizes = Vec::with_capacity(candidates.len());
+    for &(codec, level) in candidates.iter() {
+        let mut encoder = Encoder::new(codec, level, Vec::new())?;
+        encoder.write_all(sample)?;
+        sizes.push(encoder.finish()?.len());
+    }
+    let best = sizes.iter().copied().min().unwrap_or(0);
+    let tolerance = best * priority.tolerance() / 100;
+    Ok(Iterator::zip(candidates.into_iter(), sizes)
+        .find(|&(_, size)| size <= best + tolerance)
+        .map_or((Codec::Stored, 0), |(candidate, _)| candidate))
+}
+
+/// Section decoder.
+///
+/// Sections split into blocks are concatenated compressed streams, decoded
+/// sequentially unless created by `Decoder::blocks`.
+pub(crate) enum Decoder<'a> {
+    Stored(&'a [u8]),
+    Bzip2(MultiBzDecoder<&'a [u8]>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, &'a [u8]>),
+    Blocks(BlockDecoder<'a>),
+}
+
+impl<'a> Decoder<'a> {
+    /// Create decoder of given codec over the section data.
+    pub fn new(codec: Codec, data: &'a [u8]) -> Result<Self> {
+        match codec {
+            Codec::Stored => Ok(Decoder::Stored(data)),
+            Codec::Bzip2 => Ok(Decoder::Bzip2(MultiBzDecoder::new(data))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Decoder::Zstd(zstd::stream::read::Decoder::with_buffer(data)?)),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(unsupported(codec)),
+        }
+    }
+
+    /// Create decoder of a section split into blocks of `block_size` decoded
+    /// bytes, with the encoded size of each block.
+    ///
+    /// Blocks are decoded one by one as read, or up to `ahead` blocks ahead
+    /// of the reader on worker threads (feature `parallel`).
+    pub fn blocks(codec: Codec, data: &'a [u8], block_size: u64, sizes: Vec<u32>, ahead: usize) -> Result<Self> {
+        if !codec.is_supported() {
+            return Err(unsupported(codec));
+        }
+        Ok(Decoder::Blocks(BlockDecoder {
+            codec,
+            data,
+            block_size,
+            sizes,
+            ahead,
+            next: 0,
+            offset: 0,
+            block: 0,
+            pending: VecDeque::new(),
+            current: Vec::new(),
+            pos: 0,
+        }))
+    }
+
+    /// Set the number of blocks decoded ahead of the reader, if split.
+    pub fn decode_ahead(&mut self, ahead: usize) {
+        if let Decoder::Blocks(dec) = self {
+            dec.ahead = ahead;
+        }
+    }
+
+    /// Encoded byte range of the block being read within the section data,
+    /// if split.
+    pub fn block_range(&self) -> Option<Range<usize>> {
+        match self {
+            Decoder::Blocks(dec) => {
+                let start: usize = dec.sizes[..dec.block].iter().map(|&n| n as usize).sum();
+                let end = start + dec.sizes.get(dec.block).map_or(0, |&n| n as usize);
+                Some(Ord::min(start, dec.data.len())..Ord::min(end, dec.data.len()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Read for Decoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Decoder::Stored(data) => data.read(buf),
+            Decoder::Bzip2(dec) => dec.read(buf),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(dec) => dec.read(buf),
+            Decoder::Blocks(dec) => dec.read(buf),
+        }
+    }
+}
+
+/// Decoder of a section split into independently compressed blocks.
+pub(crate) struct BlockDecoder<'a> {
+    codec: Codec,
+    data: &'a [u8],
+    block_size: u64,
+    sizes: Vec<u32>,
+    ahead: usize,
+    next: usize,
+    offset: usize,
+    block: usize,
+    pending: VecDeque<Pending<'a>>,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+/// Block dispatched, but not consumed yet.
+enum Pending<'a> {
+    Lazy(&'a [u8], bool),
+    #[cfg(feature = "parallel")]
+    Spawned(Receiver<Result<Vec<u8>>>),
+}
+
+impl<'a> BlockDecoder<'a> {
+    /// Dispatch the following blocks, at least the next one.
+    fn dispatch(&mut self) -> Result<()> {
+        while self.pending.len() < Ord::max(self.ahead, 1) && self.next < self.sizes.len() {
+            let end = self
+                .offset
+                .checked_add(self.sizes[self.next] as usize)
+                .filter(|&end| end <= self.data.len())
+                .ok_or_else(corrupted)?;
+            let block = &self.data[self.offset..end];
+            self.next += 1;
+            self.offset = end;
+            let last = self.next == self.sizes.len();
+            self.pending.push_back(self.spawn(block, last));
+        }
+        Ok(())
+    }
+
+    /// Decode the block on a worker thread if decoding ahead.
+    #[cfg(feature = "parallel")]
+    fn spawn(&self, block: &'a [u8], last: bool) -> Pending<'a> {
+        if self.ahead == 0 {
+            return Pending::Lazy(block, last);
+        }
+        let (codec, block_size, block) = (self.codec, self.block_size, block.to_vec());
+        let (result, received) = mpsc::channel();
+        thread::spawn(move || result.send(decode_block(codec, &block[..], block_size, last)));
+        Pending::Spawned(received)
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn spawn(&self, block: &'a [u8], last: bool) -> Pending<'a> {
+        Pending::Lazy(block, last)
+    }
+}
+
+impl<'a> Read for BlockDecoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.pos == self.current.len() {
+            self.dispatch()?;
+            self.block = self.next - self.pending.len();
+            self.current = match self.pending.pop_front() {
+                None => return Ok(0),
+                Some(Pending::Lazy(block, last)) => decode_block(self.codec, block, self.block_size, last)?,
+                #[cfg(feature = "parallel")]
+                Some(Pending::Spawned(received)) => received
+                    .recv()
+                    .unwrap_or_else(|_| Err(Error::other("block decoder exited")))?,
+            };
+            self.pos = 0;
+            self.dispatch()?;
+        }
+        let n = Ord::min(buf.len(), self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decode a block, which is of exactly `block_size` bytes unless the last.
+fn decode_block(codec: Codec, block: &[u8], block_size: u64, last: bool) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    Decoder::new(codec, block)?
+        .take(block_size + 1)
+        .read_to_end(&mut decoded)?;
+    let size = decoded.len() as u64;
+    if size > block_size || size == 0 || (!last && size != block_size) {
+        return Err(corrupted());
+    }
+    Ok(decoded)
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch corrupted")
+}
+
+/// Decoder of a compressed stream read from any reader.
+pub(crate) enum StreamDecoder<R: Read> {
+    Stored(R),
+    Bzip2(BzDecoder<R>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, std::io::BufReader<R>>),
+}
+
+impl<R: Read> StreamDecoder<R> {
+    /// Create decoder of given codec over the reader.
+    pub fn new(codec: Codec, r: R) -> Result<Self> {
+        match codec {
+            Codec::Stored => Ok(StreamDecoder::Stored(r)),
+            Codec::Bzip2 => Ok(StreamDecoder::Bzip2(BzDecoder::new(r))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(StreamDecoder::Zstd(zstd::stream::read::Decoder::new(r)?)),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(unsupported(codec)),
+        }
+    }
+}
+
+impl<R: Read> Read for StreamDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            StreamDecoder::Stored(r) => r.read(buf),
+            StreamDecoder::Bzip2(dec) => dec.read(buf),
+            #[cfg(feature = "zstd")]
+            StreamDecoder::Zstd(dec) => dec.read(buf),
+        }
+    }
+}
+
+/// Error of codecs not compiled in.
+fn unsupported(codec: Codec) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("codec {:?} is not supported in this build", codec),
+    )
+}
@@ -0,0 +1,294 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Result};
+
+use super::format::Format;
+
+/// Canonical test vector of the patch format.
+///
+/// Vectors without `target` are invalid patches, which must be rejected by
+/// conforming patchers.
+#[derive(Copy, Clone, Debug)]
+pub struct Vector {
+    /// Unique name of the vector.
+    pub name: &'static str,
+    /// What the vector covers.
+    pub description: &'static str,
+    /// Container format of the patch.
+    pub format: Format,
+    /// Source data.
+    pub source: &'static [u8],
+    /// Expected target data, or `None` if the patch must be rejected.
+    pub target: Option<&'static [u8]>,
+    /// Patch data.
+    pub patch: &'static [u8],
+}
+
+/// Verify the patcher against all test vectors.
+///
+/// The patcher is called with the source and the patch, and should return the
+/// target data or an error.
+/// Patchers only supporting the classic bsdiff 4.x format could filter
+/// `VECTORS` by `Vector::format` and check them one by one instead.
+///
+/// Return error naming all failed vectors.
+///
+/// Example:
+///
+/// ```
+/// use std::io;
+/// use qbsdiff::{conformance, Bspatch};
+///
+/// conformance::verify(|source, patch| {
+///     let mut target = Vec::new();
+///     Bspatch::new(patch)?.apply(source, io::Cursor::new(&mut target))?;
+///     Ok(target)
+/// })
+/// .unwrap();
+/// ```
+pub fn verify<F>(mut patcher: F) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8]) -> Result<Vec<u8>>,
+{
+    let failed: Vec<&str> = VECTORS
+        .iter()
+        .filter(|v| !check(v, &mut patcher))
+        .map(|v| v.name)
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("conformance vectors failed: {}", failed.join(", ")),
+        ))
+    }
+}
+
+/// Check the patcher against a single test vector.
+pub fn check<F>(vector: &Vector, mut patcher: F) -> bool
+where
+    F: FnMut(&[u8], &[u8]) -> Result<Vec<u8>>,
+{
+    match (patcher(vector.source, vector.patch), vector.target) {
+        (Ok(target), Some(expected)) => target == expected,
+        (Err(_), None) => true,
+        _ => false,
+    }
+}
+
+/// All test vectors.
+///
+/// Each patch in the classic format is compressed by the reference bzip2
+/// implementation, rather than produced by qbsdiff itself.
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        name: "empty",
+        description: "Both source and target are empty, no controls.",
+        format: Format::Classic,
+        source: b"",
+        target: Some(b""),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38,
+            0x50, 0x90, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00,
+            0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "empty-source",
+        description: "Empty source, the target comes from extra data only.",
+        format: Format::Classic,
+        source: b"",
+        target: Some(b"hello world"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x27, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x0d, 0xf7, 0xa6, 0xec, 0x00, 0x00, 0x00, 0x40, 0x00, 0x44, 0x08, 0x20,
+            0x00, 0x20, 0xa8, 0x06, 0x63, 0x28, 0xdc, 0x5d, 0xc9, 0x14, 0xe1, 0x42, 0x40, 0x37, 0xde, 0x9b, 0xb0, 0x42,
+            0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39, 0x31,
+            0x41, 0x59, 0x26, 0x53, 0x59, 0x44, 0xf7, 0x13, 0x78, 0x00, 0x00, 0x01, 0x91, 0x80, 0x40, 0x00, 0x06, 0x44,
+            0x90, 0x80, 0x20, 0x00, 0x22, 0x03, 0x34, 0x84, 0x30, 0x21, 0xb6, 0x81, 0x54, 0x27, 0x8b, 0xb9, 0x22, 0x9c,
+            0x28, 0x48, 0x22, 0x7b, 0x89, 0xbc, 0x00,
+        ],
+    },
+    Vector {
+        name: "identity",
+        description: "Target equals to source, a single control with zero delta.",
+        format: Format::Classic,
+        source: b"abcdefgh",
+        target: Some(b"abcdefgh"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xb8, 0xad, 0x55, 0x3b, 0x00, 0x00, 0x02, 0x60, 0x00, 0x40, 0x40, 0x08,
+            0x00, 0x20, 0x00, 0x30, 0xcc, 0x0c, 0xf5, 0x05, 0xce, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x21, 0x71, 0x5a, 0xaa,
+            0x76, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x96, 0xfb, 0x44, 0xa6, 0x00, 0x00, 0x00,
+            0x40, 0x00, 0x44, 0x00, 0x20, 0x00, 0x21, 0x00, 0x82, 0x83, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x96, 0xfb,
+            0x44, 0xa6, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "delta-wrapping",
+        description: "Delta bytes added to source bytes wrap around.",
+        format: Format::Classic,
+        source: b"\x00\x7f\x80\xff",
+        target: Some(b"\xff\x80\x7f\x00"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x27, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x5a, 0x2c, 0xe8, 0xba, 0x00, 0x00, 0x02, 0x60, 0x00, 0x44, 0x00, 0x08,
+            0x00, 0x20, 0x00, 0x30, 0xcc, 0x0c, 0xf5, 0x05, 0xce, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x20, 0xb4, 0x59, 0xd1,
+            0x74, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xfc, 0x3d, 0x17, 0x06, 0x00, 0x00, 0x01,
+            0xc0, 0x00, 0xa0, 0x00, 0x00, 0x00, 0xa0, 0x00, 0x21, 0x00, 0x82, 0x23, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90,
+            0xfc, 0x3d, 0x17, 0x06, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "truncate",
+        description: "Target is a prefix of source.",
+        format: Format::Classic,
+        source: b"0123456789",
+        target: Some(b"0123"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x87, 0x29, 0x3f, 0xb1, 0x00, 0x00, 0x04, 0xc0, 0x00, 0x4d, 0x08, 0x20,
+            0x00, 0x21, 0x86, 0x81, 0x9a, 0x00, 0xad, 0x9a, 0xf1, 0x77, 0x24, 0x53, 0x85, 0x09, 0x08, 0x72, 0x93, 0xfb,
+            0x10, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x38, 0xfb, 0x22, 0x84, 0x00, 0x00, 0x02,
+            0x40, 0x00, 0x40, 0x00, 0x20, 0x00, 0x21, 0x18, 0x46, 0xb0, 0xbb, 0x92, 0x29, 0xc2, 0x84, 0x81, 0xc7, 0xd9,
+            0x14, 0x20, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "negative-seek",
+        description: "Seek backwards in source to reuse data.",
+        format: Format::Classic,
+        source: b"0123456789",
+        target: Some(b"6789012345"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x3f, 0x47, 0x62, 0xb3, 0x00, 0x00, 0x01, 0xe0, 0x40, 0x5d, 0x1c, 0x08,
+            0x00, 0x40, 0x00, 0x20, 0x00, 0x31, 0x00, 0x30, 0x19, 0x06, 0x86, 0x89, 0x2e, 0x9b, 0x20, 0x0c, 0xe6, 0xec,
+            0xbf, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x3f, 0x47, 0x62, 0xb3, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59,
+            0x26, 0x53, 0x59, 0x6e, 0x16, 0x51, 0xc7, 0x00, 0x00, 0x00, 0x40, 0x00, 0x41, 0x00, 0x20, 0x00, 0x21, 0x00,
+            0x82, 0x83, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x6e, 0x16, 0x51, 0xc7, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72,
+            0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "zero-length-control",
+        description: "Controls adding and copying nothing, only seeking.",
+        format: Format::Classic,
+        source: b"abcdef",
+        target: Some(b"abefX"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x8c, 0x13, 0x41, 0xff, 0x00, 0x00, 0x0d, 0xe8, 0x40, 0x7c, 0x00, 0x04,
+            0x00, 0x00, 0x08, 0x40, 0x00, 0x20, 0x00, 0x21, 0x28, 0x64, 0x68, 0x43, 0x02, 0x1a, 0x14, 0xc6, 0x50, 0x69,
+            0x22, 0x05, 0x63, 0xf1, 0x77, 0x24, 0x53, 0x85, 0x09, 0x08, 0xc1, 0x34, 0x1f, 0xf0, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x38, 0xfb, 0x22, 0x84, 0x00, 0x00, 0x02, 0x40, 0x00, 0x40, 0x00, 0x20,
+            0x00, 0x21, 0x18, 0x46, 0xb0, 0xbb, 0x92, 0x29, 0xc2, 0x84, 0x81, 0xc7, 0xd9, 0x14, 0x20, 0x42, 0x5a, 0x68,
+            0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xef, 0x68, 0x06, 0xf4, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x40,
+            0x20, 0x00, 0x21, 0x18, 0x46, 0x82, 0xee, 0x48, 0xa7, 0x0a, 0x12, 0x1d, 0xed, 0x00, 0xde, 0x80,
+        ],
+    },
+    Vector {
+        name: "mixed",
+        description: "Interleaved delta and extra data.",
+        format: Format::Classic,
+        source: b"the quick brown fox",
+        target: Some(b"the quick cat brown!!!"),
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2e, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x87, 0xb4, 0x39, 0xf9, 0x00, 0x00, 0x0e, 0x40, 0x40, 0x5e, 0x18, 0x40,
+            0x00, 0x20, 0x00, 0x21, 0x29, 0xa1, 0xa6, 0x84, 0x30, 0x23, 0x35, 0x37, 0xa0, 0x09, 0x52, 0x24, 0xc9, 0xe2,
+            0xee, 0x48, 0xa7, 0x0a, 0x12, 0x10, 0xf6, 0x87, 0x3f, 0x20, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26,
+            0x53, 0x59, 0xd5, 0x13, 0x67, 0x1d, 0x00, 0x00, 0x00, 0x42, 0x00, 0xc5, 0x04, 0x80, 0x80, 0x00, 0x20, 0x20,
+            0x00, 0x21, 0x89, 0x84, 0x21, 0x80, 0x68, 0xc5, 0x75, 0xde, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x21, 0xaa, 0x26,
+            0xce, 0x3a, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x48, 0xb4, 0xbb, 0x1a, 0x00, 0x00,
+            0x02, 0x91, 0x80, 0x60, 0x00, 0x28, 0x00, 0x04, 0x00, 0x20, 0x00, 0x21, 0x83, 0x41, 0x9a, 0x02, 0x54, 0x43,
+            0x8b, 0xb9, 0x22, 0x9c, 0x28, 0x48, 0x24, 0x5a, 0x5d, 0x8d, 0x00,
+        ],
+    },
+    Vector {
+        name: "extended-stored",
+        description: "Extended format with uncompressed sections.",
+        format: Format::Extended,
+        source: b"0123456789",
+        target: Some(b"6789abc01"),
+        patch: &[
+            0x51, 0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x61, 0x62, 0x63,
+        ],
+    },
+    Vector {
+        name: "bad-magic",
+        description: "Unknown magic number.",
+        format: Format::Classic,
+        source: b"abcdefgh",
+        target: None,
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x31, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xb8, 0xad, 0x55, 0x3b, 0x00, 0x00, 0x02, 0x60, 0x00, 0x40, 0x40, 0x08,
+            0x00, 0x20, 0x00, 0x30, 0xcc, 0x0c, 0xf5, 0x05, 0xce, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x21, 0x71, 0x5a, 0xaa,
+            0x76, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x96, 0xfb, 0x44, 0xa6, 0x00, 0x00, 0x00,
+            0x40, 0x00, 0x44, 0x00, 0x20, 0x00, 0x21, 0x00, 0x82, 0x83, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x96, 0xfb,
+            0x44, 0xa6, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "truncated",
+        description: "Sections are truncated.",
+        format: Format::Classic,
+        source: b"abcdefgh",
+        target: None,
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26,
+        ],
+    },
+    Vector {
+        name: "source-overrun",
+        description: "Controls read beyond the end of source.",
+        format: Format::Classic,
+        source: b"abcd",
+        target: None,
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xb8, 0xad, 0x55, 0x3b, 0x00, 0x00, 0x02, 0x60, 0x00, 0x40, 0x40, 0x08,
+            0x00, 0x20, 0x00, 0x30, 0xcc, 0x0c, 0xf5, 0x05, 0xce, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x21, 0x71, 0x5a, 0xaa,
+            0x76, 0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x96, 0xfb, 0x44, 0xa6, 0x00, 0x00, 0x00,
+            0x40, 0x00, 0x44, 0x00, 0x20, 0x00, 0x21, 0x00, 0x82, 0x83, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x96, 0xfb,
+            0x44, 0xa6, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "negative-length",
+        description: "Negative add length in controls.",
+        format: Format::Classic,
+        source: b"abcdefgh",
+        target: None,
+        patch: &[
+            0x42, 0x53, 0x44, 0x49, 0x46, 0x46, 0x34, 0x30, 0x2b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x5a, 0x68, 0x39,
+            0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x90, 0x2f, 0x7b, 0x96, 0x00, 0x00, 0x04, 0x40, 0x40, 0x70, 0x04, 0x40,
+            0x00, 0x20, 0x00, 0x21, 0x83, 0x41, 0x9a, 0x08, 0x54, 0xc8, 0x8e, 0x2e, 0xe4, 0x8a, 0x70, 0xa1, 0x21, 0x20,
+            0x5e, 0xf7, 0x2c, 0x42, 0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00, 0x42,
+            0x5a, 0x68, 0x39, 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+];
@@ -0,0 +1,141 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+
+use super::utils::Control;
+
+/// Length of blocks indexing the target history.
+const BLOCK: usize = 64;
+
+/// Max period of runs.
+const MAX_PERIOD: usize = 16;
+
+/// Min length of runs.
+const MIN_RUN: usize = 32;
+
+/// Base of the rolling polynomial hash.
+const BASE: u64 = 0x100000001b3;
+
+/// Rewrite extra data repeating earlier target bytes as target-relative
+/// copies.
+///
+/// Runs of a short period (e.g. zero fills or repeated small structures) are
+/// always rewritten as overlapping copies.
+/// With `blocks`, target history is also indexed by aligned blocks, and every
+/// position of extra data is looked up with a rolling hash, thus repeats of at
+/// least two blocks are always found, and repeats of at least one block are
+/// usually found.
+/// Found repeats are extended greedily in both directions within the extra
+/// data.
+pub(crate) fn dedupe<D>(target: &[u8], diff: D, blocks: bool) -> Vec<Control>
+where
+    D: Iterator<Item = Control>,
+{
+    // B^(BLOCK-1), to drop the leading byte of rolling hash.
+    let lead = (1..BLOCK).fold(1u64, |h, _| h.wrapping_mul(BASE));
+
+    let mut table: HashMap<u64, usize> = HashMap::new();
+    let mut indexed = 0;
+    let mut index = |table: &mut HashMap<u64, usize>, end: usize| {
+        while indexed + BLOCK <= end {
+            table.insert(hash(&target[indexed..indexed + BLOCK]), indexed);
+            indexed += BLOCK;
+        }
+    };
+
+    let mut ctrls = Vec::new();
+    let mut tpos = 0;
+    for ctrl in diff {
+        let start = tpos + ctrl.add as usize;
+        let end = start + ctrl.copy as usize;
+        tpos = end;
+
+        // Find repeats (position, length, distance) in extra data.
+        let mut repeats = Vec::new();
+        let mut literal = start;
+        let mut i = start;
+        let mut h = None;
+        while i + MIN_RUN <= end {
+            let mut found = (1..=Ord::min(MAX_PERIOD, i))
+                .map(|p| i - p)
+                .find(|&j| target[j] == target[i] && target[j..j + MIN_RUN] == target[i..i + MIN_RUN]);
+
+            let mut next = None;
+            if blocks && i + BLOCK <= end {
+                index(&mut table, i);
+                let x = h.unwrap_or_else(|| hash(&target[i..i + BLOCK]));
+                if found.is_none() {
+                    found = table
+                        .get(&x)
+                        .copied()
+                        .filter(|&j| target[j..j + BLOCK] == target[i..i + BLOCK]);
+                }
+                if i + BLOCK < end {
+                    next = Some(
+                        x.wrapping_sub((target[i] as u64).wrapping_mul(lead))
+                            .wrapping_mul(BASE)
+                            .wrapping_add(target[i + BLOCK] as u64),
+                    );
+                }
+            }
+            h = next;
+
+            if let Some(mut j) = found {
+                let mut k = i;
+                let mut n = 0;
+                while k + n < end && target[j + n] == target[k + n] {
+                    n += 1;
+                }
+                while k > literal && j > 0 && target[j - 1] == target[k - 1] {
+                    j -= 1;
+                    k -= 1;
+                    n += 1;
+                }
+                repeats.push((k, n, k - j));
+                i = k + n;
+                literal = i;
+                h = None;
+                continue;
+            }
+            i += 1;
+        }
+
+        if repeats.is_empty() {
+            ctrls.push(ctrl);
+            continue;
+        }
+
+        // Split the control at repeats.
+        let mut add = ctrl.add;
+        let mut literal = start;
+        for (k, n, dist) in repeats {
+            ctrls.push(Control {
+                add,
+                copy: (k - literal) as u64,
+                seek: 0,
+                tcopy: n as u64,
+                tdist: dist as u64,
+            });
+            add = 0;
+            literal = k + n;
+        }
+        if literal < end {
+            ctrls.push(Control {
+                copy: (end - literal) as u64,
+                seek: ctrl.seek,
+                ..Control::default()
+            });
+        } else if let Some(last) = ctrls.last_mut() {
+            last.seek = ctrl.seek;
+        }
+    }
+    ctrls
+}
+
+/// Polynomial hash of a block.
+#[inline]
+fn hash(block: &[u8]) -> u64 {
+    block
+        .iter()
+        .fold(0u64, |h, &b| h.wrapping_mul(BASE).wrapping_add(b as u64))
+}
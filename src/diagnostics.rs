@@ -0,0 +1,248 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Cursor, Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+use super::bsdiff::Bsdiff;
+use super::bspatch::Bspatch;
+
+/// Size of the built-in samples.
+const SAMPLE_SIZE: usize = 1 << 20;
+
+/// Result of a built-in sample of `self_test`.
+#[derive(Clone, Debug)]
+pub struct SampleResult {
+    /// Name of the sample.
+    pub name: &'static str,
+
+    /// Size of the source.
+    pub source_size: u64,
+
+    /// Size of the target.
+    pub target_size: u64,
+
+    /// Size of the patch.
+    pub patch_size: u64,
+
+    /// Time spent on delta compression.
+    pub diff_time: Duration,
+
+    /// Time spent on patching.
+    pub patch_time: Duration,
+
+    /// Growth of the peak resident set size during delta compression, if
+    /// measurable on the platform (Linux only).
+    pub diff_peak_rss: Option<u64>,
+
+    /// Growth of the peak resident set size during patching, if measurable
+    /// on the platform (Linux only).
+    pub patch_peak_rss: Option<u64>,
+}
+
+impl SampleResult {
+    /// Target bytes produced per second by delta compression.
+    pub fn diff_throughput(&self) -> f64 {
+        throughput(self.target_size, self.diff_time)
+    }
+
+    /// Target bytes produced per second by patching.
+    pub fn patch_throughput(&self) -> f64 {
+        throughput(self.target_size, self.patch_time)
+    }
+}
+
+/// Results of `self_test`.
+#[derive(Clone, Debug)]
+pub struct SelfTest {
+    /// Results of each built-in sample.
+    pub samples: Vec<SampleResult>,
+}
+
+impl SelfTest {
+    /// The lowest throughput of delta compression among the samples.
+    pub fn min_diff_throughput(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|s| s.diff_throughput())
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// The lowest throughput of patching among the samples.
+    pub fn min_patch_throughput(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|s| s.patch_throughput())
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// The largest growth of the peak resident set size among the samples,
+    /// if measurable on the platform.
+    pub fn max_peak_rss(&self) -> Option<u64> {
+        self.samples
+            .iter()
+            .flat_map(|s| [s.diff_peak_rss, s.patch_peak_rss])
+            .collect::<Option<Vec<u64>>>()
+            .map(|rss| rss.into_iter().max().unwrap_or(0))
+    }
+}
+
+/// Run delta compression and patching with the default settings on a fixed
+/// set of built-in synthetic samples (1 MiB each), measuring the throughput
+/// and peak memory on the current machine.
+///
+/// The samples cover small edits of text, shifted binary data, random data
+/// and an empty source, each patch is verified after applied.
+/// Peak memory is measured by resetting the high-water mark of the process,
+/// which is also affected by other threads running meanwhile.
+///
+/// Example:
+///
+/// Assert the host meets the performance expectations at startup:
+/// ```no_run
+/// use std::io;
+/// use qbsdiff::diagnostics;
+///
+/// fn check_host() -> io::Result<()> {
+///     let report = diagnostics::self_test()?;
+///     if report.min_diff_throughput() < 1e6 || report.max_peak_rss().unwrap_or(0) > 256 << 20 {
+///         return Err(io::Error::other("host too slow"));
+///     }
+///     Ok(())
+/// }
+/// ```
+///
+/// Return error if any patch does not reproduce its target.
+pub fn self_test() -> Result<SelfTest> {
+    let samples = samples()
+        .into_iter()
+        .map(|(name, source, target)| run_sample(name, &source[..], &target[..]))
+        .collect::<Result<_>>()?;
+    Ok(SelfTest { samples })
+}
+
+fn run_sample(name: &'static str, source: &[u8], target: &[u8]) -> Result<SampleResult> {
+    let mut patch = Vec::new();
+    reset_peak_rss();
+    let rss = peak_rss();
+    let start = Instant::now();
+    Bsdiff::new(source, target).compare(Cursor::new(&mut patch))?;
+    let diff_time = start.elapsed();
+    let diff_peak_rss = Option::zip(rss, peak_rss()).map(|(before, after)| after.saturating_sub(before));
+
+    let mut patched = Vec::with_capacity(target.len());
+    reset_peak_rss();
+    let rss = peak_rss();
+    let start = Instant::now();
+    Bspatch::new(&patch[..])?.apply(source, Cursor::new(&mut patched))?;
+    let patch_time = start.elapsed();
+    let patch_peak_rss = Option::zip(rss, peak_rss()).map(|(before, after)| after.saturating_sub(before));
+
+    if patched != target {
+        return Err(Error::new(ErrorKind::InvalidData, "self test patch mismatch"));
+    }
+    Ok(SampleResult {
+        name,
+        source_size: source.len() as u64,
+        target_size: target.len() as u64,
+        patch_size: patch.len() as u64,
+        diff_time,
+        patch_time,
+        diff_peak_rss,
+        patch_peak_rss,
+    })
+}
+
+/// The built-in samples: name, source and target.
+fn samples() -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+
+    // Text with words replaced and lines inserted.
+    let words = [
+        &b"delta "[..],
+        b"patch ",
+        b"source ",
+        b"target ",
+        b"\n",
+        b"bsdiff ",
+        b"chunk ",
+    ];
+    let mut text = Vec::with_capacity(SAMPLE_SIZE);
+    while text.len() < SAMPLE_SIZE {
+        text.extend_from_slice(words[rng.next_u64() as usize % words.len()]);
+    }
+    text.truncate(SAMPLE_SIZE);
+    let mut edited = text.clone();
+    for _ in 0..64 {
+        let i = rng.next_u64() as usize % (edited.len() - 16);
+        edited[i..i + 6].copy_from_slice(b"EDITED");
+        edited.splice(i..i, b"inserted line\n".iter().copied());
+    }
+
+    // Binary data with sections shifted and patched.
+    let binary: Vec<u8> = (0..SAMPLE_SIZE)
+        .map(|i| (rng.next_u64() % 16) as u8 * (i % 7) as u8)
+        .collect();
+    let mut shifted = binary[4096..].to_vec();
+    shifted.extend_from_slice(&binary[..4096]);
+    for i in (0..shifted.len()).step_by(8191) {
+        shifted[i] = shifted[i].wrapping_add(1);
+    }
+
+    // Incompressible data.
+    let random: Vec<u8> = (0..SAMPLE_SIZE).map(|_| rng.next_u64() as u8).collect();
+    let other: Vec<u8> = (0..SAMPLE_SIZE).map(|_| rng.next_u64() as u8).collect();
+
+    vec![
+        ("text", text, edited),
+        ("binary", binary, shifted),
+        ("random", random, other.clone()),
+        ("fresh", Vec::new(), other),
+    ]
+}
+
+/// Fixed pseudo-random generator, so that samples are the same everywhere.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 >> 32
+    }
+}
+
+fn throughput(size: u64, time: Duration) -> f64 {
+    size as f64 / Ord::max(time, Duration::from_nanos(1)).as_secs_f64()
+}
+
+/// Peak resident set size of the process, if measurable on the platform
+/// (Linux only).
+#[cfg(target_os = "linux")]
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line["VmHWM:".len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Peak resident set size of the process, if measurable on the platform
+/// (Linux only).
+#[cfg(not(target_os = "linux"))]
+pub fn peak_rss() -> Option<u64> {
+    None
+}
+
+/// Reset the peak resident set size to the current one, if supported.
+#[cfg(target_os = "linux")]
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reset_peak_rss() {}
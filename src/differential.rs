@@ -0,0 +1,173 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::io::{Cursor, Error, ErrorKind, Result, Write};
+
+use super::bsdiff::{self, pack, Bsdiff, PackConfig, ParallelScheme};
+use super::bspatch::Bspatch;
+use super::codec::Codec;
+use super::format::Format;
+use super::utils::*;
+
+/// Length of keys indexing the source in the reference differ.
+const KEY: usize = 4;
+
+/// Min length of matches accepted by the reference differ.
+const MIN_MATCH: usize = 8;
+
+/// Max number of candidates examined per key by the reference differ.
+const CANDIDATES: usize = 64;
+
+/// Slow but obviously correct reference differ.
+///
+/// Every source position is indexed by a hash map of its first bytes, and the
+/// target is matched greedily by the longest exact match among candidates.
+/// Matched bytes are emitted as delta data, the others as extra data.
+/// The patch is in the classic bsdiff 4.x format.
+///
+/// The size of patch file would be returned if no error occurs.
+pub fn reference_diff<P: Write>(source: &[u8], target: &[u8], patch: P) -> Result<u64> {
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for i in 0..source.len().saturating_sub(KEY - 1) {
+        index.entry(&source[i..i + KEY]).or_default().push(i);
+    }
+
+    let mut ctrls = Vec::new();
+    let (mut spos, mut tpos, mut j) = (0usize, 0usize, 0usize);
+    while j < target.len() {
+        let candidates = match target.get(j..j + KEY) {
+            Some(key) => index.get(key).map(|c| &c[..]).unwrap_or(&[]),
+            None => &[],
+        };
+        let (i, n) = candidates
+            .iter()
+            .take(CANDIDATES)
+            .map(|&i| {
+                let n = Iterator::zip(source[i..].iter(), target[j..].iter())
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                (i, n)
+            })
+            .max_by_key(|&(i, n)| (n, usize::MAX - i))
+            .unwrap_or((0, 0));
+        if n < MIN_MATCH {
+            j += 1;
+            continue;
+        }
+
+        // Bytes between matches are extra data, then seek to the match.
+        ctrls.push(Control {
+            copy: (j - tpos) as u64,
+            seek: i as i64 - spos as i64,
+            ..Control::default()
+        });
+        ctrls.push(Control {
+            add: n as u64,
+            ..Control::default()
+        });
+        spos = i + n;
+        j += n;
+        tpos = j;
+    }
+    if tpos < target.len() {
+        ctrls.push(Control {
+            copy: (target.len() - tpos) as u64,
+            ..Control::default()
+        });
+    }
+
+    let config = PackConfig {
+        format: Format::Classic,
+        codecs: [(Codec::Bzip2, bsdiff::COMPRESSION_LEVEL); 3],
+        auto_levels: false,
+        auto_codec: None,
+        buffer_size: bsdiff::BUFFER_SIZE,
+        target_copy: false,
+        compact_seek: false,
+        decoded_sizes: false,
+        explicit_sizes: false,
+        block_size: 0,
+        max_lengths: [0; 2],
+        micro: false,
+        dedupe: false,
+        source_checksum: 0,
+        checksum: None,
+        controls_only: false,
+    };
+    pack(source, target, ctrls.into_iter(), patch, &config)
+}
+
+/// Check that patches produced by `Bsdiff` with various settings, as well as
+/// the reference differ, reproduce the target once applied by `Bspatch`.
+///
+/// This is the entry point of differential property tests and fuzzers, to
+/// make sure that optimizations of the matcher never break patches.
+///
+/// Example:
+///
+/// A fuzz target:
+/// ```
+/// use qbsdiff::differential;
+///
+/// fn fuzz(data: &[u8]) {
+///     let (source, target) = data.split_at(data.len() / 2);
+///     differential::check(source, target).unwrap();
+/// }
+/// # fuzz(b"the quick brown fox jumps over the lazy dog");
+/// ```
+///
+/// Return error naming the settings that failed.
+pub fn check(source: &[u8], target: &[u8]) -> Result<()> {
+    let configs = [
+        ("default", Bsdiff::new(source, target)),
+        (
+            "never-parallel",
+            Bsdiff::new(source, target).parallel_scheme(ParallelScheme::Never),
+        ),
+        (
+            "num-jobs",
+            Bsdiff::new(source, target).parallel_scheme(ParallelScheme::NumJobs(3)),
+        ),
+        ("no-small-match", Bsdiff::new(source, target).small_match(0)),
+        ("work-limit", Bsdiff::new(source, target).work_limit(1)),
+        ("max-lengths", Bsdiff::new(source, target).max_add(7).max_copy(5)),
+        (
+            "extended-stored",
+            Bsdiff::new(source, target)
+                .format(Format::Extended)
+                .codec(Codec::Stored),
+        ),
+    ];
+
+    let mut failed = Vec::new();
+    for (name, bsdiff) in configs.iter() {
+        let mut patch = Vec::new();
+        let result = bsdiff.compare(Cursor::new(&mut patch));
+        if result.is_err() || !reproduces(source, target, &patch[..]) {
+            failed.push(*name);
+        }
+    }
+    let mut patch = Vec::new();
+    let result = reference_diff(source, target, Cursor::new(&mut patch));
+    if result.is_err() || !reproduces(source, target, &patch[..]) {
+        failed.push("reference");
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("patches not reproducing the target: {}", failed.join(", ")),
+        ))
+    }
+}
+
+/// Check if the patch reproduces the target.
+fn reproduces(source: &[u8], target: &[u8], patch: &[u8]) -> bool {
+    let mut output = Vec::with_capacity(target.len());
+    match Bspatch::new(patch).and_then(|patcher| patcher.apply(source, Cursor::new(&mut output))) {
+        Ok(_) => output == target,
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,148 @@
+#![forbid(unsafe_code)]
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Error, ErrorKind, Result};
+use std::ops::Range;
+
+use super::bsdiff::Bsdiff;
+use super::index::SourceIndex;
+
+/// Patch of a range of the target.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fragment {
+    /// Target range reconstructed by the patch.
+    pub target: Range<u64>,
+
+    /// Patch data reconstructing the target range from the whole source.
+    pub patch: Vec<u8>,
+}
+
+/// Incremental delta compressor of a target arriving in ranges.
+///
+/// Target ranges could be fed in any order (e.g. as they are received over
+/// network), each range is compared against the indexed source as soon as it
+/// is fed, emitting a patch fragment.
+/// Applying the fragment to the whole source yields the target bytes of the
+/// range.
+///
+/// The source index is shared by all ranges, and the source itself could be
+/// a read-only memory mapped file.
+///
+/// Example:
+///
+/// Generate fragments while the target is being assembled:
+/// ```
+/// use std::io;
+/// use qbsdiff::{SourceIndex, TargetFeeder};
+/// use qbsdiff::feeder::Fragment;
+///
+/// fn generate(source: &[u8], target_size: u64, ranges: &[(u64, &[u8])]) -> io::Result<Vec<Fragment>> {
+///     let index = SourceIndex::new(source);
+///     let mut feeder = TargetFeeder::new(&index, target_size);
+///     let mut fragments = Vec::new();
+///     for &(offset, bytes) in ranges.iter() {
+///         fragments.push(feeder.feed(offset, bytes)?);
+///     }
+///     assert!(feeder.is_complete());
+///     Ok(fragments)
+/// }
+/// ```
+pub struct TargetFeeder<'s> {
+    bsdiff: Bsdiff<'s, 'static>,
+    target_size: u64,
+    fed: BTreeMap<u64, u64>,
+}
+
+impl<'s> TargetFeeder<'s> {
+    /// Create new feeder of the target with given size, using the default
+    /// delta compression configuration.
+    pub fn new(index: &'s SourceIndex<'s>, target_size: u64) -> Self {
+        TargetFeeder::with_config(&Bsdiff::with_index(index, &[]), target_size)
+    }
+
+    /// Create new feeder of the target with given size, using the delta
+    /// compression configuration (the target data of which is ignored).
+    ///
+    /// The configuration should be created by `Bsdiff::with_index`, or the
+    /// source would be indexed again for every range.
+    pub fn with_config(bsdiff: &Bsdiff<'s, '_>, target_size: u64) -> Self {
+        TargetFeeder {
+            bsdiff: bsdiff.retarget(&[]),
+            target_size,
+            fed: BTreeMap::new(),
+        }
+    }
+
+    /// Feed target bytes at given offset and emit the patch fragment.
+    ///
+    /// Returns error if the range exceeds the target size or overlaps fed
+    /// ranges.
+    pub fn feed(&mut self, offset: u64, bytes: &[u8]) -> Result<Fragment> {
+        let end = offset
+            .checked_add(bytes.len() as u64)
+            .filter(|&end| end <= self.target_size)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "target range out of bounds"))?;
+        if !bytes.is_empty() {
+            if let Some((_, &prev_end)) = self.fed.range(..end).next_back() {
+                if prev_end > offset {
+                    return Err(Error::new(ErrorKind::InvalidInput, "target range already fed"));
+                }
+            }
+        }
+
+        let mut patch = Vec::new();
+        self.bsdiff.retarget(bytes).compare(Cursor::new(&mut patch))?;
+        if !bytes.is_empty() {
+            self.insert(offset, end);
+        }
+        Ok(Fragment {
+            target: offset..end,
+            patch,
+        })
+    }
+
+    /// Size of the target.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Number of target bytes fed.
+    pub fn fed_size(&self) -> u64 {
+        self.fed.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// Check if the whole target is fed.
+    pub fn is_complete(&self) -> bool {
+        self.fed_size() == self.target_size
+    }
+
+    /// Target ranges not fed yet, in ascending order.
+    pub fn missing(&self) -> Vec<Range<u64>> {
+        let mut missing = Vec::new();
+        let mut pos = 0;
+        for (&start, &end) in self.fed.iter() {
+            if start > pos {
+                missing.push(pos..start);
+            }
+            pos = end;
+        }
+        if pos < self.target_size {
+            missing.push(pos..self.target_size);
+        }
+        missing
+    }
+
+    /// Record a fed range, merging adjacent ranges.
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if let Some((&prev_start, &prev_end)) = self.fed.range(..start).next_back() {
+            if prev_end == start {
+                self.fed.remove(&prev_start);
+                start = prev_start;
+            }
+        }
+        if let Some(next_end) = self.fed.remove(&end) {
+            end = next_end;
+        }
+        self.fed.insert(start, end);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use fs2::FileExt;
+use memmap2::{Mmap, MmapMut};
+
+/// Read-only memory-mapped file.
+pub(crate) struct MappedFile(Option<Mmap>);
+
+impl MappedFile {
+    /// Memory-map the file read-only.
+    ///
+    /// Empty files are not mapped at all, since mapping zero bytes fails on
+    /// some platforms.
+    #[allow(unsafe_code)]
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedFile(None));
+        }
+        // SAFETY: the mapping is read-only. Modifying the file by another
+        // process while mapped is undefined, which is documented on the public
+        // APIs using this mapping.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedFile(Some(map)))
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_deref().unwrap_or(&[])
+    }
+}
+
+/// Temporary file in the same directory of its destination, which would be
+/// removed unless persisted.
+pub(crate) struct TempFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl TempFile {
+    /// Create temporary file for the destination path.
+    pub fn create(dest: &Path) -> Result<Self> {
+        let name = dest.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        Self::create_unique(|unique| dest.with_file_name(format!(".{}.{}.qbsdiff", name, unique)))
+    }
+
+    /// Create temporary file in `std::env::temp_dir()`, for scratch data
+    /// that is never persisted.
+    pub fn scratch() -> Result<Self> {
+        let dir = env::temp_dir();
+        Self::create_unique(|unique| dir.join(format!(".qbsdiff.{}.scratch", unique)))
+    }
+
+    /// Create a new file exclusively at the path of an unpredictable unique
+    /// name, retrying on collisions.
+    ///
+    /// Existing files (or symbolic links planted at the path) are never
+    /// opened, thus never truncated or written through.
+    fn create_unique<F: Fn(&str) -> PathBuf>(path_of: F) -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        for _ in 0..CREATE_ATTEMPTS {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = path_of(&format!("{}.{}.{:016x}", process::id(), n, random_bits(n)));
+            // Readable as well, for writable memory mapping.
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(TempFile { path, file: Some(file) }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "failed to create a unique temporary file",
+        ))
+    }
+
+    /// Path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Preallocate disk space, this is only a hint.
+    pub fn preallocate(&mut self, size: u64) {
+        if size > 0 {
+            let _ = self.file().allocate(size);
+        }
+    }
+
+    /// Resize the file and memory-map it writable.
+    ///
+    /// Size should not be zero, since mapping zero bytes fails on some
+    /// platforms.
+    #[allow(unsafe_code)]
+    pub fn map_mut(&mut self, size: u64) -> Result<MmapMut> {
+        let file = self.file();
+        file.set_len(size)?;
+        // SAFETY: the temporary file is created by us with a unique name, and
+        // is not exposed to any other code until persisted.
+        unsafe { MmapMut::map_mut(&*file) }
+    }
+
+    /// Get the underlying file.
+    pub fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("temporary file already persisted")
+    }
+
+    /// Flush file content to disk and atomically replace the destination,
+    /// keeping the permissions of the original destination (if any).
+    pub fn persist(mut self, dest: &Path) -> Result<()> {
+        if let Some(file) = self.file.take() {
+            keep_permissions(&file, dest)?;
+            file.sync_all()?;
+            // Windows cannot rename files still opened.
+            drop(file);
+        }
+        fs::rename(&self.path, dest)?;
+        self.path = PathBuf::new();
+        sync_parent_dir(dest)
+    }
+
+    /// Replace the destination as `persist` does, but restore the original
+    /// destination (if any) unless the replacement is made durable.
+    pub fn replace(mut self, dest: &Path) -> Result<()> {
+        if let Some(file) = self.file.take() {
+            keep_permissions(&file, dest)?;
+            file.sync_all()?;
+            drop(file);
+        }
+
+        // Keep the original by another link, the destination is never absent.
+        let backup = self.path.with_extension("orig");
+        let backed_up = match fs::hard_link(dest, &backup) {
+            Ok(()) => true,
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(_) => {
+                fs::copy(dest, &backup)?;
+                true
+            }
+        };
+        let result = fs::rename(&self.path, dest).and_then(|_| sync_parent_dir(dest));
+        match result {
+            Ok(()) => {
+                self.path = PathBuf::new();
+                if backed_up {
+                    let _ = fs::remove_file(&backup);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if backed_up {
+                    let _ = fs::rename(&backup, dest);
+                } else if !self.path.exists() {
+                    let _ = fs::remove_file(dest);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            self.file.take();
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Number of attempts to create a temporary file of a unique name.
+const CREATE_ATTEMPTS: usize = 64;
+
+/// Unpredictable bits from the randomly seeded keys of `RandomState`, mixed
+/// with the clock and the sequence number.
+fn random_bits(n: usize) -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(n);
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.finish()
+}
+
+/// Copy the permissions of the destination to be replaced, if it exists.
+fn keep_permissions(file: &File, dest: &Path) -> Result<()> {
+    match fs::metadata(dest) {
+        Ok(meta) => file.set_permissions(meta.permissions()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Make the renaming durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories could not be opened for syncing on other platforms.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,1555 @@
+#![forbid(unsafe_code)]
+
+use std::error;
+use std::fmt;
+use std::io::{self, Error, ErrorKind, Result};
+use std::ops::Range;
+
+use byteorder::{ByteOrder, LE};
+
+use super::bsdiff::COMPRESSION_LEVEL;
+use super::checksum::ChecksumKind;
+use super::codec::Codec;
+use super::profile::DiffProfile;
+use super::utils::*;
+
+/// Magic number bytes of bsdiff 4.x patch files.
+pub const BSDIFF4_MAGIC: &[u8] = b"BSDIFF40";
+
+/// Magic number bytes of qbsdiff extended patch files.
+pub const QBSDIFF2_MAGIC: &[u8] = b"QBSDIFF2";
+
+/// Magic number bytes of qbsdiff micro patch files, see `Format::Extended`.
+pub const QBSMICRO_MAGIC: &[u8] = b"QBSM";
+
+/// Magic number bytes of encoded capabilities, see `Capabilities::encode`.
+pub const CAPABILITIES_MAGIC: &[u8] = b"QBSCAPS1";
+
+/// Size of encoded capabilities.
+const CAPABILITIES_SIZE: usize = 16;
+
+/// Header size of bsdiff 4.x patch files.
+const CLASSIC_HEADER_SIZE: usize = 32;
+
+/// Header size of extended patch files.
+const EXTENDED_HEADER_SIZE: usize = 48;
+
+/// Minimum header size of micro patch files.
+const MICRO_HEADER_SIZE: usize = 9;
+
+/// Feature flag of extended patch files: controls carry target-relative
+/// copies.
+pub(crate) const FLAG_TARGET_COPY: u32 = 1;
+
+/// Feature flag of extended patch files: sections are followed by checksums.
+pub(crate) const FLAG_CHECKSUM: u32 = 2;
+
+/// Feature flag of extended patch files: the header is followed by
+/// checksums of source windows.
+pub(crate) const FLAG_SOURCE_CHECKSUM: u32 = 4;
+
+/// Feature flag of extended patch files: the header is followed by metadata
+/// describing how the patch was produced.
+pub(crate) const FLAG_METADATA: u32 = 8;
+
+/// Feature flags of extended patch files: checksum algorithm of the sections.
+const FLAG_CHECKSUM_KIND: u32 = 0xf0;
+
+/// Feature flag of extended patch files: seeks of controls are encoded as
+/// zig-zag varints.
+pub(crate) const FLAG_COMPACT_SEEK: u32 = 0x100;
+
+/// Feature flag of extended patch files: the header is followed by the
+/// decoded sizes of the sections.
+const FLAG_DECODED_SIZES: u32 = 0x200;
+
+/// Size of the decoded sizes of the sections.
+const DECODED_SIZES_SIZE: usize = 24;
+
+/// Feature flag of extended patch files: the header (and the decoded sizes)
+/// is followed by the explicit source size and the size flags.
+const FLAG_SIZES: u32 = 0x800;
+
+/// Size of the explicit source size and the size flags.
+const SIZES_SIZE: usize = 12;
+
+/// Max sizes of sources, targets and decoded sections declared by patches,
+/// since controls address them by signed 64-bit offsets.
+pub const MAX_DECLARED_SIZE: u64 = i64::MAX as u64;
+
+/// Feature flag of extended patch files: sections are split into
+/// independently compressed blocks, indexed by the block table following the
+/// metadata.
+const FLAG_BLOCKS: u32 = 0x400;
+
+/// Pseudo feature flag of extended patch files: the patch has the micro
+/// header, never encoded in headers.
+pub(crate) const FLAG_MICRO: u32 = 1 << 31;
+
+/// Size of the block size and the numbers of blocks preceding the encoded
+/// sizes of blocks.
+const BLOCK_TABLE_HEADER_SIZE: usize = 20;
+
+/// Max decoded size of blocks, see `Bsdiff::block_size`.
+pub const MAX_BLOCK_SIZE: usize = 1 << 30;
+
+/// Shift of the checksum algorithm in feature flags.
+const CHECKSUM_KIND_SHIFT: u32 = 4;
+
+/// Size of the source size and window preceding the source checksums.
+const SOURCE_TABLE_HEADER_SIZE: usize = 16;
+
+/// Max target window (log2) of target-relative copies.
+pub(crate) const MAX_WINDOW_LOG: u8 = 40;
+
+/// Container format of patch files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Format {
+    /// Classic bsdiff 4.x format, compatible with bsdiff(1)/bspatch(1).
+    ///
+    /// All sections are compressed with bzip2.
+    Classic,
+
+    /// Extended qbsdiff format, allowing different codecs for each section.
+    ///
+    /// The header consists of magic `QBSDIFF2`, feature flags (u32), codecs of
+    /// the control/delta/extra sections (one byte each), the target window
+    /// (one byte), the encoded sizes of the three sections and the target size
+    /// (u64 each).
+    /// All integers are in little endian.
+    ///
+    /// With feature flag bit 0 set, each control is followed by a
+    /// target-relative copy: the length and the distance back from the current
+    /// target position (encoded like control fields).
+    /// Copied bytes might overlap the bytes being produced, as LZ77 does.
+    /// Distances never exceed `2^window`, the amount of target history patchers
+    /// should keep.
+    ///
+    /// With feature flag bit 1 set, the sections are followed by the
+    /// checksums of the encoded control, delta and extra sections, which are
+    /// verified before patching.
+    /// Feature flag bits 4-7 hold the checksum algorithm: 0 for CRC-32 (IEEE
+    /// 802.3, u32), 1 for CRC-32C (u32), 2 for XXH3 (u64) and 3 for SHA-256
+    /// (32 bytes), see `ChecksumKind`.
+    ///
+    /// With feature flag bit 9 set, the header is followed by the decoded
+    /// sizes of the control, delta and extra sections (u64 each), which let
+    /// patchers size their buffers exactly.
+    ///
+    /// With feature flag bit 11 set (see `Bsdiff::explicit_sizes`), which
+    /// requires bit 9, the header (and the decoded sizes) is followed by the
+    /// source size (u64) and the size flags (u32, reserved as zero).
+    /// Patchers reject sources of other sizes, and target sizes disagreeing
+    /// with the decoded sizes of the delta and extra sections.
+    ///
+    /// With feature flag bit 2 set, the header (and the explicit sizes if any)
+    /// is followed by the source size and the source window size (u64 each),
+    /// then the CRC-32 checksum of each source window (u32 each, the last
+    /// window might be shorter), which are verified as the source is read by
+    /// patchers.
+    ///
+    /// With feature flag bit 3 set, the header (and the source checksums if
+    /// any) is followed by the size of metadata (u32) and the metadata, UTF-8
+    /// lines of `key=value` describing how the patch was produced, which are
+    /// ignored by patchers.
+    ///
+    /// With feature flag bit 8 set, the seek of each control is encoded as a
+    /// zig-zag LEB128 varint (at most 10 bytes) instead of 8 bytes.
+    ///
+    /// With feature flag bit 10 set, the header (and the metadata if any) is
+    /// followed by the block table: the decoded size of blocks (u64), the
+    /// number of blocks of the control, delta and extra sections (u32 each),
+    /// then the encoded size of each block (u32 each) in order.
+    /// Each section is the concatenation of its blocks, compressed
+    /// independently with the codec of the section, and decoded to exactly the
+    /// block size except the last one, thus patchers could decode blocks
+    /// concurrently, or simply decode the concatenated streams in order.
+    ///
+    /// Tiny patches might have the micro header instead (see
+    /// `Bsdiff::micro`): magic `QBSM`, the codecs of the control/delta/extra
+    /// sections (two bits each, from the lowest bits of one byte), then the
+    /// encoded sizes of the three sections and the target size as LEB128
+    /// varints of minimal length.
+    /// Controls of micro patches are three zig-zag LEB128 varints each, and no
+    /// feature is available.
+    Extended,
+}
+
+/// Section of patch files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Section {
+    /// Control section.
+    Control,
+
+    /// Delta section.
+    Delta,
+
+    /// Extra section.
+    Extra,
+}
+
+impl Section {
+    /// Index of the section in the patch, i.e. the order of sections.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Section::Control => 0,
+            Section::Delta => 1,
+            Section::Extra => 2,
+        }
+    }
+}
+
+/// Specific reasons of rejected patch headers.
+///
+/// These are wrapped in `io::Error` of kind `InvalidData`, see
+/// `PatchError::of`.
+///
+/// Example:
+///
+/// ```
+/// use qbsdiff::{Bspatch, PatchError};
+///
+/// let mut patch = b"BSDIFF40".to_vec();
+/// patch.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80 | 1]);
+/// patch.resize(32, 0);
+/// let err = Bspatch::new(&patch).err().unwrap();
+/// assert_eq!(PatchError::of(&err), Some(PatchError::NegativeSectionSize));
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PatchError {
+    /// A section size or the target size in bsdiff 4.x header is negative.
+    NegativeSectionSize,
+
+    /// Section sizes overflow, or the sections exceed the patch.
+    SectionOverflow,
+
+    /// Section checksums are weaker than required, or absent, see
+    /// `Bspatch::require_checksum`.
+    WeakChecksum,
+
+    /// A declared size exceeds `MAX_DECLARED_SIZE`, or the declared sizes
+    /// disagree with each other.
+    InvalidSize,
+}
+
+impl PatchError {
+    /// Get the specific reason of the error, if any.
+    pub fn of(err: &Error) -> Option<PatchError> {
+        err.get_ref()?.downcast_ref::<PatchError>().copied()
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::NegativeSectionSize => f.write_str("patch corrupted: negative section size"),
+            PatchError::SectionOverflow => f.write_str("patch corrupted: section size overflow"),
+            PatchError::WeakChecksum => f.write_str("patch checksum weaker than required"),
+            PatchError::InvalidSize => f.write_str("patch corrupted: invalid declared size"),
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+impl From<PatchError> for Error {
+    fn from(err: PatchError) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+/// Sizes declared by a patch file, see `Bspatch::sizes`.
+///
+/// Declared sizes are hints from the header, suitable for preallocation but
+/// not trusted until the patch is applied, except the target size verified
+/// against the decoded sizes of the sections at parse time.
+///
+/// Example:
+///
+/// Preallocate only sizes vouched for by the patch:
+/// ```
+/// use std::io;
+/// use qbsdiff::Bspatch;
+///
+/// fn bspatch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let patcher = Bspatch::new(patch)?;
+///     let capacity = patcher.sizes().verified_target.unwrap_or(0);
+///     let mut target = Vec::with_capacity(capacity as usize);
+///     patcher.apply(source, io::Cursor::new(&mut target))?;
+///     Ok(target)
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PatchSizes {
+    /// Target size of the header.
+    pub declared_target: u64,
+
+    /// Target size verified against the decoded sizes of the delta and extra
+    /// sections (see `Bsdiff::explicit_sizes`), `None` if not recorded or
+    /// target-relative copies are carried.
+    pub verified_target: Option<u64>,
+
+    /// Source size recorded by `Bsdiff::explicit_sizes` or
+    /// `Bsdiff::source_checksum`, sources of other sizes are rejected.
+    pub declared_source: Option<u64>,
+}
+
+/// Validation result of a partially received patch file.
+///
+/// Tells whether the header is complete, how many bytes the whole patch is
+/// expected to have and which byte range is still missing, so that download
+/// managers could resume with range requests.
+///
+/// The total size of extended patch files is known once the header is
+/// received.
+/// Since the extra section of bsdiff 4.x patch files is not sized in the
+/// header, the total size is known only after the end of the extra section
+/// (bzip2 stream) is received.
+///
+/// Example:
+///
+/// Compute the next range request of a download:
+/// ```
+/// use std::io;
+/// use std::ops::Range;
+/// use qbsdiff::PartialPatch;
+///
+/// fn next_request(received: &[u8]) -> io::Result<Option<Range<u64>>> {
+///     let partial = PartialPatch::check(received)?;
+///     if partial.is_complete() {
+///         return Ok(None);
+///     }
+///     // `missing()` is open-ended (up to `u64::MAX`) while the size is unknown.
+///     Ok(partial.missing())
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PartialPatch {
+    received: u64,
+    format: Option<Format>,
+    header: Option<Header>,
+    header_size: usize,
+    total: Option<u64>,
+}
+
+impl PartialPatch {
+    /// Validate the first bytes of a patch file.
+    ///
+    /// Returns error if the received bytes could not be the prefix of any
+    /// valid patch file.
+    pub fn check(prefix: &[u8]) -> Result<Self> {
+        let n = Ord::min(prefix.len(), 8);
+        let micro = n > 0 && prefix[..Ord::min(n, 4)] == QBSMICRO_MAGIC[..Ord::min(n, 4)];
+        let format = if n == 0 {
+            None
+        } else if prefix[..n] == BSDIFF4_MAGIC[..n] {
+            Some(Format::Classic)
+        } else if prefix[..n] == QBSDIFF2_MAGIC[..n] || micro {
+            Some(Format::Extended)
+        } else {
+            return Err(Error::new(ErrorKind::InvalidData, "not a valid patch"));
+        };
+
+        let mut partial = PartialPatch {
+            received: prefix.len() as u64,
+            format,
+            header: None,
+            header_size: match format {
+                _ if micro => MICRO_HEADER_SIZE,
+                Some(Format::Extended) => EXTENDED_HEADER_SIZE,
+                _ => CLASSIC_HEADER_SIZE,
+            },
+            total: None,
+        };
+        if prefix.len() < partial.header_size {
+            return Ok(partial);
+        }
+        if format == Some(Format::Extended) {
+            partial.header_size = if micro {
+                Header::micro_size(prefix)?
+            } else {
+                Header::extended_size(prefix)?
+            };
+            if prefix.len() < partial.header_size {
+                return Ok(partial);
+            }
+        }
+
+        let header = Header::parse_header(prefix)?;
+        let known = (header.size() as u64)
+            .checked_add(header.csize)
+            .and_then(|size| size.checked_add(header.dsize))
+            .ok_or(PatchError::SectionOverflow)?;
+        partial.header = Some(header);
+        partial.total = match header.format {
+            Format::Classic => {
+                if known > partial.received {
+                    return Ok(partial);
+                }
+                let extra = &prefix[known as usize..];
+                let mut decoder = bzip2::bufread::BzDecoder::new(extra);
+                match io::copy(&mut decoder, &mut io::sink()) {
+                    Ok(_) => Some(known + (extra.len() - decoder.into_inner().len()) as u64),
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                    Err(_) => return Err(Error::new(ErrorKind::InvalidData, "patch corrupted")),
+                }
+            }
+            Format::Extended => Some(header.total_size().ok_or(PatchError::SectionOverflow)?),
+        };
+        Ok(partial)
+    }
+
+    /// Number of bytes received.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Format of the patch file, `None` if no byte is received.
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// Size of the header (the smaller one if the format is unknown).
+    ///
+    /// Headers of extended patch files with source checksums or metadata are
+    /// larger, this is a lower bound until the header is complete.
+    pub fn header_size(&self) -> usize {
+        self.header_size
+    }
+
+    /// Check if the header is completely received.
+    pub fn is_header_complete(&self) -> bool {
+        self.header.is_some()
+    }
+
+    /// Size of the target declared in the header.
+    pub fn target_size(&self) -> Option<u64> {
+        self.header.map(|header| header.tsize)
+    }
+
+    /// Expected size of the whole patch file, `None` if not known yet.
+    pub fn total_size(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Lower bound of the size of the whole patch file.
+    pub fn min_total_size(&self) -> u64 {
+        if let Some(total) = self.total {
+            return total;
+        }
+        let known = match self.header {
+            Some(header) => (header.size() as u64)
+                .saturating_add(header.csize)
+                .saturating_add(header.dsize),
+            None => self.header_size() as u64,
+        };
+        Ord::max(known, self.received)
+    }
+
+    /// Check if the whole patch file is received.
+    ///
+    /// Bytes received beyond the expected total size are trailing garbage.
+    pub fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| self.received >= total)
+    }
+
+    /// Byte range still needed, `None` if complete.
+    ///
+    /// The range ends at `u64::MAX` while the total size is unknown.
+    pub fn missing(&self) -> Option<Range<u64>> {
+        match self.total {
+            Some(total) if self.received >= total => None,
+            Some(total) => Some(self.received..total),
+            None => Some(self.received..u64::MAX),
+        }
+    }
+}
+
+/// Metadata recorded in extended patch files, describing how the patch was
+/// produced, see `Bsdiff::metadata`.
+///
+/// Example:
+///
+/// Compare the settings of two patches:
+/// ```
+/// use std::io;
+/// use qbsdiff::Bspatch;
+///
+/// fn explain(patch1: &[u8], patch2: &[u8]) -> io::Result<()> {
+///     let (meta1, meta2) = (Bspatch::new(patch1)?.metadata(), Bspatch::new(patch2)?.metadata());
+///     if let (Some(meta1), Some(meta2)) = (meta1, meta2) {
+///         for (key, value) in meta1.entries() {
+///             if meta2.get(key) != Some(value) {
+///                 eprintln!("{}: {} vs {:?}", key, value, meta2.get(key));
+///             }
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatchMetadata {
+    entries: Vec<(String, String)>,
+}
+
+impl PatchMetadata {
+    /// Parse lines of `key=value`, other lines are skipped.
+    pub(crate) fn parse(metadata: &[u8]) -> Self {
+        let entries = String::from_utf8_lossy(metadata)
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        PatchMetadata { entries }
+    }
+
+    /// Get the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Version of qbsdiff producing the patch.
+    pub fn version(&self) -> Option<&str> {
+        self.get("version")
+    }
+
+    /// All entries in order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+/// Optional features of extended patch files, see `Format::Extended`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Feature {
+    /// Target-relative copies carried by controls, see `Bsdiff::target_copy`.
+    TargetCopy,
+
+    /// Checksums of source windows, see `Bsdiff::source_checksum`.
+    SourceChecksum,
+
+    /// Metadata describing how the patch was produced, see
+    /// `Bsdiff::metadata`.
+    Metadata,
+
+    /// Seeks of controls encoded as varints, see `Bsdiff::compact_seek`.
+    CompactSeek,
+
+    /// Decoded sizes of the sections, see `Bsdiff::decoded_sizes`.
+    DecodedSizes,
+
+    /// Sections split into blocks, see `Bsdiff::block_size`.
+    Blocks,
+
+    /// Micro header of tiny patches, see `Bsdiff::micro`.
+    Micro,
+
+    /// Explicit source size, see `Bsdiff::explicit_sizes`.
+    ExplicitSizes,
+}
+
+impl Feature {
+    /// All features in the order of their feature flags.
+    const ALL: [Feature; 8] = [
+        Feature::TargetCopy,
+        Feature::SourceChecksum,
+        Feature::Metadata,
+        Feature::CompactSeek,
+        Feature::DecodedSizes,
+        Feature::Blocks,
+        Feature::Micro,
+        Feature::ExplicitSizes,
+    ];
+
+    /// Feature flag in the extended patch header.
+    fn flag(self) -> u32 {
+        match self {
+            Feature::TargetCopy => FLAG_TARGET_COPY,
+            Feature::SourceChecksum => FLAG_SOURCE_CHECKSUM,
+            Feature::Metadata => FLAG_METADATA,
+            Feature::CompactSeek => FLAG_COMPACT_SEEK,
+            Feature::DecodedSizes => FLAG_DECODED_SIZES,
+            Feature::Blocks => FLAG_BLOCKS,
+            Feature::Micro => FLAG_MICRO,
+            Feature::ExplicitSizes => FLAG_SIZES,
+        }
+    }
+}
+
+/// Patch container features supported by a patcher, for negotiating the
+/// patch format between patch producers and consumers.
+///
+/// Clients advertise what they could apply (e.g. `Capabilities::current()`
+/// of the qbsdiff version they ship, or `Capabilities::classic()` for
+/// bspatch(1)), and servers downgrade their preferred settings to the best
+/// patch both sides support by `negotiate`, so older clients keep working
+/// as newer features are rolled out.
+///
+/// The encoded capabilities consist of magic `QBSCAPS1`, the feature flags of
+/// extended patches (u32 in little endian, see `Format::Extended`), then one
+/// byte each of the bits of formats (bit 0 classic, bit 1 extended), codec
+/// identifiers and checksum identifiers, and a reserved byte.
+/// Bits unknown to the decoding side are ignored, thus newer clients could
+/// talk to older servers.
+///
+/// Example:
+///
+/// Serve the best patch the client could apply:
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bsdiff, Capabilities, Codec, DiffProfile, Format};
+///
+/// // client side
+/// fn advertise() -> Vec<u8> {
+///     Capabilities::current().encode()
+/// }
+///
+/// // server side
+/// fn serve(source: &[u8], target: &[u8], advertised: &[u8]) -> io::Result<Vec<u8>> {
+///     let preferred = DiffProfile {
+///         format: Format::Extended,
+///         codec: Codec::Zstd,
+///         target_copy: true,
+///         ..DiffProfile::default()
+///     };
+///     let profile = Capabilities::decode(advertised)?.negotiate(&preferred)?;
+///     Bsdiff::new(source, target).profile(&profile).compare_to_vec()
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    formats: u8,
+    flags: u32,
+    codecs: u8,
+    checksums: u8,
+}
+
+impl Capabilities {
+    /// Capabilities of `Bspatch` in this build, i.e. both formats, all
+    /// features, and the codecs and checksums compiled in.
+    pub fn current() -> Self {
+        let flags = Feature::ALL.iter().fold(0, |flags, feature| flags | feature.flag());
+        let codecs = [Codec::Stored, Codec::Bzip2, Codec::Zstd]
+            .into_iter()
+            .filter(|codec| codec.is_supported())
+            .fold(0, |bits, codec| bits | 1 << codec.id());
+        let checksums = [
+            ChecksumKind::Crc32,
+            ChecksumKind::Crc32c,
+            ChecksumKind::Xxh3,
+            ChecksumKind::Sha256,
+        ]
+        .into_iter()
+        .filter(|kind| kind.is_supported())
+        .fold(0, |bits, kind| bits | 1 << kind.id());
+        Capabilities {
+            formats: 1 << Capabilities::format_bit(Format::Classic) | 1 << Capabilities::format_bit(Format::Extended),
+            flags: flags | if checksums != 0 { FLAG_CHECKSUM } else { 0 },
+            codecs,
+            checksums,
+        }
+    }
+
+    /// Capabilities of bsdiff 4.x patchers such as bspatch(1), i.e. the
+    /// classic format only.
+    pub fn classic() -> Self {
+        Capabilities {
+            formats: 1 << Capabilities::format_bit(Format::Classic),
+            flags: 0,
+            codecs: 1 << Codec::Bzip2.id(),
+            checksums: 0,
+        }
+    }
+
+    /// Remove the support of the codec, e.g. for clients with too little
+    /// memory for zstd windows.
+    pub fn without_codec(mut self, codec: Codec) -> Self {
+        self.codecs &= !(1 << codec.id());
+        self
+    }
+
+    /// Remove the support of the checksum algorithm.
+    pub fn without_checksum(mut self, kind: ChecksumKind) -> Self {
+        self.checksums &= !(1 << kind.id());
+        if self.checksums == 0 {
+            self.flags &= !FLAG_CHECKSUM;
+        }
+        self
+    }
+
+    /// Remove the support of the feature, e.g. target-relative copies for
+    /// clients unable to keep the target history.
+    pub fn without(mut self, feature: Feature) -> Self {
+        self.flags &= !feature.flag();
+        self
+    }
+
+    /// Get the capabilities supported by both.
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            formats: self.formats & other.formats,
+            flags: self.flags & other.flags,
+            codecs: self.codecs & other.codecs,
+            checksums: self.checksums & other.checksums,
+        }
+    }
+
+    /// Check whether the format is supported.
+    pub fn supports_format(&self, format: Format) -> bool {
+        self.formats & 1 << Capabilities::format_bit(format) != 0
+    }
+
+    /// Check whether the feature of extended patches is supported.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.supports_format(Format::Extended) && self.flags & feature.flag() != 0
+    }
+
+    /// Check whether the codec is supported.
+    ///
+    /// Codecs other than bzip2 are only available in the extended format.
+    pub fn supports_codec(&self, codec: Codec) -> bool {
+        self.codecs & 1 << codec.id() != 0
+    }
+
+    /// Check whether the checksum algorithm is supported.
+    ///
+    /// Checksums are only available in the extended format.
+    pub fn supports_checksum(&self, kind: ChecksumKind) -> bool {
+        self.flags & FLAG_CHECKSUM != 0 && self.checksums & 1 << kind.id() != 0
+    }
+
+    /// Encode the capabilities in 16 bytes, see `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0; CAPABILITIES_SIZE];
+        data[..8].copy_from_slice(CAPABILITIES_MAGIC);
+        LE::write_u32(&mut data[8..12], self.flags);
+        data[12] = self.formats;
+        data[13] = self.codecs;
+        data[14] = self.checksums;
+        data
+    }
+
+    /// Decode the capabilities encoded by `encode`, possibly of other
+    /// qbsdiff versions.
+    ///
+    /// Return `ErrorKind::InvalidData` if the data are not encoded
+    /// capabilities.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < CAPABILITIES_SIZE || &data[..8] != CAPABILITIES_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not valid capabilities"));
+        }
+        // Codecs and checksums known to this version, compiled in or not.
+        let known = Capabilities {
+            codecs: 0x7,
+            checksums: 0xf,
+            ..Capabilities::current()
+        };
+        let decoded = Capabilities {
+            flags: LE::read_u32(&data[8..12]),
+            formats: data[12],
+            codecs: data[13],
+            checksums: data[14],
+        };
+        Ok(decoded.intersect(&known))
+    }
+
+    /// Check whether the patch could be applied with the capabilities, by
+    /// its header.
+    ///
+    /// Return `ErrorKind::InvalidData` if the patch header is corrupted, or
+    /// `ErrorKind::Unsupported` if it is unknown to this build.
+    pub fn accepts(&self, patch: &[u8]) -> Result<bool> {
+        let header = Header::parse_header(patch)?;
+        let flags = header.flags & !FLAG_CHECKSUM_KIND;
+        Ok(self.supports_format(header.format)
+            && header.codecs.iter().all(|&codec| self.supports_codec(codec))
+            && flags & !self.flags == 0
+            && header.checksum_kind().is_none_or(|kind| self.supports_checksum(kind)))
+    }
+
+    /// Downgrade the profile to produce the best patch supported by both the
+    /// capabilities and this build.
+    ///
+    /// Features unsupported are disabled, unsupported codecs are replaced by
+    /// bzip2 (or stored if bzip2 is unsupported either), unsupported
+    /// checksums by the strongest one supported, and the extended format by
+    /// the classic one, with all its features disabled.
+    /// Automatic codec selection is disabled unless all codecs compiled in
+    /// are supported.
+    /// Other settings are kept as is.
+    ///
+    /// Return `ErrorKind::Unsupported` if no patch could be applied with the
+    /// capabilities.
+    pub fn negotiate(&self, profile: &DiffProfile) -> Result<DiffProfile> {
+        let both = self.intersect(&Capabilities::current());
+        let unsupported = || Error::new(ErrorKind::Unsupported, "no patch format supported by both sides");
+        let mut profile = profile.clone();
+
+        let classic = both.supports_format(Format::Classic) && both.supports_codec(Codec::Bzip2);
+        profile.format = match profile.format {
+            Format::Extended if both.supports_format(Format::Extended) => Format::Extended,
+            _ if classic => Format::Classic,
+            _ if both.supports_format(Format::Extended) => Format::Extended,
+            _ => return Err(unsupported()),
+        };
+        let extended = profile.format == Format::Extended;
+
+        let fallback = [Codec::Bzip2, Codec::Stored]
+            .into_iter()
+            .find(|&codec| both.supports_codec(codec) && (extended || codec == Codec::Bzip2))
+            .ok_or_else(unsupported)?;
+        let downgrade = |(codec, level): (Codec, u32)| match codec {
+            _ if !extended && level == 0 => (Codec::Bzip2, COMPRESSION_LEVEL),
+            _ if both.supports_codec(codec) && (extended || codec == Codec::Bzip2) => (codec, level),
+            _ if fallback == Codec::Bzip2 => (Codec::Bzip2, Ord::min(level, 9)),
+            _ => (fallback, level),
+        };
+        (profile.codec, profile.compression_level) = downgrade((profile.codec, profile.compression_level));
+        profile.section_codecs = profile.section_codecs.map(|codec| codec.map(downgrade));
+        let all_codecs = [Codec::Stored, Codec::Bzip2, Codec::Zstd]
+            .into_iter()
+            .all(|codec| !codec.is_supported() || both.supports_codec(codec));
+        if !extended || !all_codecs {
+            profile.auto_codec = None;
+        }
+
+        let supports = |feature| extended && both.supports(feature);
+        if !supports(Feature::TargetCopy) {
+            profile.target_copy = false;
+            profile.dedupe = false;
+        }
+        if !supports(Feature::SourceChecksum) {
+            profile.source_checksum = 0;
+        }
+        if !supports(Feature::Metadata) {
+            profile.metadata = false;
+        }
+        if !supports(Feature::CompactSeek) {
+            profile.compact_seek = false;
+        }
+        if !supports(Feature::DecodedSizes) {
+            profile.decoded_sizes = false;
+        }
+        if !supports(Feature::Blocks) {
+            profile.block_size = 0;
+        }
+        if !supports(Feature::Micro) {
+            profile.micro = 0;
+        }
+        if !supports(Feature::ExplicitSizes) || !supports(Feature::DecodedSizes) {
+            profile.explicit_sizes = false;
+        }
+        if profile
+            .checksum
+            .is_some_and(|kind| !extended || !both.supports_checksum(kind))
+        {
+            profile.checksum = [
+                ChecksumKind::Sha256,
+                ChecksumKind::Xxh3,
+                ChecksumKind::Crc32c,
+                ChecksumKind::Crc32,
+            ]
+            .into_iter()
+            .find(|&kind| extended && both.supports_checksum(kind));
+        }
+        Ok(profile)
+    }
+
+    /// Bit of the format in the encoded formats.
+    fn format_bit(format: Format) -> u8 {
+        match format {
+            Format::Classic => 0,
+            Format::Extended => 1,
+        }
+    }
+}
+
+/// Header of patch files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Header {
+    pub format: Format,
+    pub codecs: [Codec; 3],
+    pub csize: u64,
+    pub dsize: u64,
+    pub esize: u64,
+    pub tsize: u64,
+    pub flags: u32,
+    pub window_log: u8,
+    pub ssize: u64,
+    pub swindow: u64,
+    pub source_size: u64,
+    pub msize: u32,
+    pub checksum: ChecksumKind,
+    pub decoded: [u64; 3],
+    pub block_size: u64,
+    pub blocks: [u32; 3],
+}
+
+impl Header {
+    /// Create header with sizes of the encoded sections and the target.
+    pub fn new(format: Format, codecs: [Codec; 3], csize: u64, dsize: u64, esize: u64, tsize: u64) -> Self {
+        Header {
+            format,
+            codecs,
+            csize,
+            dsize,
+            esize,
+            tsize,
+            flags: 0,
+            window_log: 0,
+            ssize: 0,
+            swindow: 0,
+            source_size: 0,
+            msize: 0,
+            checksum: ChecksumKind::Crc32,
+            decoded: [0; 3],
+            block_size: 0,
+            blocks: [0; 3],
+        }
+    }
+
+    /// Record the decoded sizes of the control, delta and extra sections.
+    pub fn decoded_sizes(mut self, decoded: [u64; 3]) -> Self {
+        self.flags |= FLAG_DECODED_SIZES;
+        self.decoded = decoded;
+        self
+    }
+
+    /// Get the decoded sizes of the sections, if recorded.
+    pub fn decoded_size_hint(&self) -> Option<[u64; 3]> {
+        Some(self.decoded).filter(|_| self.flags & FLAG_DECODED_SIZES != 0)
+    }
+
+    /// Offset of the source table, i.e. the size of the header before it.
+    fn source_table_offset(flags: u32) -> usize {
+        let mut offset = EXTENDED_HEADER_SIZE;
+        if flags & FLAG_DECODED_SIZES != 0 {
+            offset += DECODED_SIZES_SIZE;
+        }
+        if flags & FLAG_SIZES != 0 {
+            offset += SIZES_SIZE;
+        }
+        offset
+    }
+
+    /// Record the explicit source size, with the decoded sizes of sections.
+    pub fn explicit_sizes(mut self, ssize: u64) -> Self {
+        self.flags |= FLAG_SIZES;
+        self.source_size = ssize;
+        self
+    }
+
+    /// Check if the header records the explicit source size.
+    pub fn has_explicit_sizes(&self) -> bool {
+        self.flags & FLAG_SIZES != 0
+    }
+
+    /// Get the sizes declared by the header.
+    pub fn sizes(&self) -> PatchSizes {
+        // Checked against the decoded sizes at parse time.
+        let verified_target = Some(self.tsize).filter(|_| self.has_explicit_sizes() && !self.has_target_copy());
+        let declared_source = match () {
+            _ if self.has_explicit_sizes() => Some(self.source_size),
+            _ if self.has_source_checksum() => Some(self.ssize),
+            _ => None,
+        };
+        PatchSizes {
+            declared_target: self.tsize,
+            verified_target,
+            declared_source,
+        }
+    }
+
+    /// Check the declared sizes against the limits and each other.
+    fn check_sizes(&self) -> Result<()> {
+        let sizes = [self.tsize, self.ssize, self.source_size];
+        if sizes.iter().any(|&size| size > MAX_DECLARED_SIZE) {
+            return Err(PatchError::InvalidSize.into());
+        }
+        if let Some(decoded) = self.decoded_size_hint().filter(|_| self.has_explicit_sizes()) {
+            // Target-relative copies produce the rest of target.
+            let produced = decoded[1].saturating_add(decoded[2]);
+            let consistent = match self.has_target_copy() {
+                true => produced <= self.tsize,
+                false => produced == self.tsize,
+            };
+            if decoded.iter().any(|&size| size > MAX_DECLARED_SIZE) || !consistent {
+                return Err(PatchError::InvalidSize.into());
+            }
+        }
+        if self.has_explicit_sizes() && self.has_source_checksum() && self.ssize != self.source_size {
+            return Err(PatchError::InvalidSize.into());
+        }
+        Ok(())
+    }
+
+    /// Enable checksums of source windows, the source has `ssize` bytes.
+    pub fn source_checksum(mut self, ssize: u64, swindow: u64) -> Self {
+        self.flags |= FLAG_SOURCE_CHECKSUM;
+        self.ssize = ssize;
+        self.swindow = swindow;
+        self
+    }
+
+    /// Check if the header is followed by checksums of source windows.
+    pub fn has_source_checksum(&self) -> bool {
+        self.flags & FLAG_SOURCE_CHECKSUM != 0
+    }
+
+    /// Encode the checksums of source windows.
+    pub fn encode_source_checksums(source: &[u8], swindow: usize) -> Vec<u8> {
+        let mut table = Vec::with_capacity(source.len().div_ceil(swindow) * 4);
+        for window in source.chunks(swindow) {
+            let mut b = [0; 4];
+            LE::write_u32(&mut b, crc32(0, window));
+            table.extend_from_slice(&b);
+        }
+        table
+    }
+
+    /// Split the checksums of source windows, empty if absent.
+    pub fn source_checksums<'a>(&self, patch: &'a [u8]) -> &'a [u8] {
+        if !self.has_source_checksum() {
+            return &[];
+        }
+        &patch[Header::source_table_offset(self.flags) + SOURCE_TABLE_HEADER_SIZE..self.metadata_offset()]
+    }
+
+    /// Attach metadata of `msize` bytes.
+    pub fn metadata(mut self, msize: u32) -> Self {
+        self.flags |= FLAG_METADATA;
+        self.msize = msize;
+        self
+    }
+
+    /// Check if the header is followed by metadata.
+    pub fn has_metadata(&self) -> bool {
+        self.flags & FLAG_METADATA != 0
+    }
+
+    /// Encode the size and the metadata.
+    pub fn encode_metadata(metadata: &[u8]) -> Vec<u8> {
+        let mut block = vec![0; 4];
+        LE::write_u32(&mut block[..], metadata.len() as u32);
+        block.extend_from_slice(metadata);
+        block
+    }
+
+    /// Split the metadata, empty if absent.
+    pub fn metadata_bytes<'a>(&self, patch: &'a [u8]) -> &'a [u8] {
+        if !self.has_metadata() {
+            return &[];
+        }
+        &patch[self.metadata_offset() + 4..self.block_table_offset()]
+    }
+
+    /// Offset of the metadata block, i.e. the size of the header before it.
+    fn metadata_offset(&self) -> usize {
+        let offset = Header::source_table_offset(self.flags);
+        if self.has_source_checksum() {
+            let windows = self.ssize.div_ceil(self.swindow) as usize;
+            offset + SOURCE_TABLE_HEADER_SIZE + windows * 4
+        } else {
+            offset
+        }
+    }
+
+    /// Split the sections into blocks of `block_size` decoded bytes, with the
+    /// number of blocks of each section.
+    pub fn blocks(mut self, block_size: u64, blocks: [u32; 3]) -> Self {
+        self.flags |= FLAG_BLOCKS;
+        self.block_size = block_size;
+        self.blocks = blocks;
+        self
+    }
+
+    /// Check if the sections are split into blocks.
+    pub fn has_blocks(&self) -> bool {
+        self.flags & FLAG_BLOCKS != 0
+    }
+
+    /// Encode the block table of the encoded sizes of blocks of each
+    /// section.
+    pub fn encode_block_table(block_size: u64, sizes: [&[u32]; 3]) -> Vec<u8> {
+        let count: usize = sizes.iter().map(|sizes| sizes.len()).sum();
+        let mut table = Vec::with_capacity(BLOCK_TABLE_HEADER_SIZE + count * 4);
+        table.extend_from_slice(&block_size.to_le_bytes());
+        for section in sizes {
+            table.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        }
+        for size in sizes.into_iter().flatten() {
+            table.extend_from_slice(&size.to_le_bytes());
+        }
+        table
+    }
+
+    /// Split the block table, empty if absent.
+    pub fn block_table<'a>(&self, patch: &'a [u8]) -> &'a [u8] {
+        if !self.has_blocks() {
+            return &[];
+        }
+        &patch[self.block_table_offset()..self.size()]
+    }
+
+    /// Get the encoded sizes of blocks of each section, `None` if the
+    /// sections are not split.
+    ///
+    /// Return error if the blocks do not cover the sections exactly.
+    pub fn block_sizes(&self, patch: &[u8]) -> Result<Option<[Vec<u32>; 3]>> {
+        if !self.has_blocks() {
+            return Ok(None);
+        }
+        let mut sizes = self.block_table(patch)[BLOCK_TABLE_HEADER_SIZE..]
+            .chunks(4)
+            .map(LE::read_u32);
+        let blocks = self
+            .blocks
+            .map(|n| sizes.by_ref().take(n as usize).collect::<Vec<u32>>());
+        for (blocks, size) in blocks.iter().zip([self.csize, self.dsize, self.esize]) {
+            if blocks.iter().map(|&n| n as u64).sum::<u64>() != size {
+                return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+            }
+        }
+        Ok(Some(blocks))
+    }
+
+    /// Offset of the block table, i.e. the size of the header before it.
+    fn block_table_offset(&self) -> usize {
+        if self.has_metadata() {
+            self.metadata_offset() + 4 + self.msize as usize
+        } else {
+            self.metadata_offset()
+        }
+    }
+
+    /// Size of the extended header, the prefix should contain the fixed part.
+    ///
+    /// Return a lower bound if the source window size or the metadata size is
+    /// not received yet.
+    fn extended_size(prefix: &[u8]) -> Result<usize> {
+        let flags = LE::read_u32(&prefix[8..12]);
+        let mut size = Header::source_table_offset(flags);
+        if flags & FLAG_SOURCE_CHECKSUM != 0 {
+            let offset = size;
+            size += SOURCE_TABLE_HEADER_SIZE;
+            if prefix.len() < size {
+                return Ok(size);
+            }
+            let ssize = LE::read_u64(&prefix[offset..offset + 8]);
+            let swindow = LE::read_u64(&prefix[offset + 8..offset + 16]);
+            if swindow == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+            }
+            size = usize::try_from(ssize.div_ceil(swindow))
+                .ok()
+                .and_then(|n| n.checked_mul(4))
+                .and_then(|n| n.checked_add(size))
+                .ok_or(PatchError::SectionOverflow)?;
+        }
+        if flags & FLAG_METADATA != 0 {
+            let msize_end = size.checked_add(4).ok_or(PatchError::SectionOverflow)?;
+            if prefix.len() < msize_end {
+                return Ok(msize_end);
+            }
+            size = msize_end
+                .checked_add(LE::read_u32(&prefix[size..msize_end]) as usize)
+                .ok_or(PatchError::SectionOverflow)?;
+        }
+        if flags & FLAG_BLOCKS != 0 {
+            let table = size
+                .checked_add(BLOCK_TABLE_HEADER_SIZE)
+                .ok_or(PatchError::SectionOverflow)?;
+            if prefix.len() < table {
+                return Ok(table);
+            }
+            let count: u64 = prefix[size + 8..table].chunks(4).map(|n| LE::read_u32(n) as u64).sum();
+            size = usize::try_from(count)
+                .ok()
+                .and_then(|n| n.checked_mul(4))
+                .and_then(|n| n.checked_add(table))
+                .ok_or(PatchError::SectionOverflow)?;
+        }
+        Ok(size)
+    }
+
+    /// Enable target-relative copies with history of `2^window_log` bytes.
+    pub fn target_copy(mut self, window_log: u8) -> Self {
+        self.flags |= FLAG_TARGET_COPY;
+        self.window_log = window_log;
+        self
+    }
+
+    /// Enable checksums of the sections computed by the algorithm.
+    pub fn checksum(mut self, kind: ChecksumKind) -> Self {
+        self.flags &= !FLAG_CHECKSUM_KIND;
+        self.flags |= FLAG_CHECKSUM | (kind.id() as u32) << CHECKSUM_KIND_SHIFT;
+        self.checksum = kind;
+        self
+    }
+
+    /// Check if the sections are followed by checksums.
+    pub fn has_checksum(&self) -> bool {
+        self.flags & FLAG_CHECKSUM != 0
+    }
+
+    /// Checksum algorithm of the sections, if any.
+    pub fn checksum_kind(&self) -> Option<ChecksumKind> {
+        Some(self.checksum).filter(|_| self.has_checksum())
+    }
+
+    /// Encode the checksums of the sections.
+    pub fn encode_checksums(kind: ChecksumKind, ctrls: &[u8], delta: &[u8], extra: &[u8]) -> Result<Vec<u8>> {
+        let mut trailer = Vec::with_capacity(kind.size() * 3);
+        for section in [ctrls, delta, extra] {
+            trailer.extend_from_slice(&kind.digest(section)?);
+        }
+        Ok(trailer)
+    }
+
+    /// Size of the checksums of the sections, zero if absent.
+    fn checksums_size(&self) -> usize {
+        self.checksum_kind().map_or(0, |kind| kind.size() * 3)
+    }
+
+    /// Check if controls carry target-relative copies.
+    pub fn has_target_copy(&self) -> bool {
+        self.flags & FLAG_TARGET_COPY != 0
+    }
+
+    /// Encode seeks of controls as zig-zag varints.
+    pub fn compact_seek(mut self) -> Self {
+        self.flags |= FLAG_COMPACT_SEEK;
+        self
+    }
+
+    /// Check if seeks of controls are encoded as zig-zag varints.
+    pub fn has_compact_seek(&self) -> bool {
+        self.flags & FLAG_COMPACT_SEEK != 0
+    }
+
+    /// Encode the header as a micro header.
+    pub fn micro(mut self) -> Self {
+        self.flags |= FLAG_MICRO;
+        self
+    }
+
+    /// Check if the header is a micro header.
+    pub fn has_micro(&self) -> bool {
+        self.flags & FLAG_MICRO != 0
+    }
+
+    /// Size of the micro header, the prefix should contain the fixed part.
+    ///
+    /// Return a lower bound if the sizes are not received yet.
+    fn micro_size(prefix: &[u8]) -> Result<usize> {
+        let mut size = MICRO_HEADER_SIZE - 4;
+        for i in 0..4 {
+            match read_uvarint(&prefix[size..]) {
+                Some((_, n)) => size += n,
+                None if prefix.len() - size < 10 => return Ok(prefix.len() + 4 - i),
+                None => return Err(Error::new(ErrorKind::InvalidData, "patch corrupted")),
+            }
+        }
+        Ok(size)
+    }
+
+    /// Encoding of controls.
+    pub fn control_layout(&self) -> ControlLayout {
+        ControlLayout {
+            target_copy: self.has_target_copy(),
+            compact_seek: self.has_compact_seek(),
+            varint: self.has_micro(),
+        }
+    }
+
+    /// Parse the header of bsdiff 4.x or extended patch file.
+    pub fn parse(patch: &[u8]) -> Result<Self> {
+        let header = Header::parse_prefix(patch)?;
+        if header.total_size() != Some(patch.len() as u64) {
+            return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+        }
+        Ok(header)
+    }
+
+    /// Verify the checksums of the sections if any.
+    fn verify(&self, patch: &[u8]) -> Result<()> {
+        if !self.has_checksum() {
+            return Ok(());
+        }
+        let (ctrls, delta, extra) = self.sections(patch);
+        let offset = self.size() + ctrls.len() + delta.len() + extra.len();
+        let trailer = Header::encode_checksums(self.checksum, ctrls, delta, extra)?;
+        if patch[offset..offset + trailer.len()] != trailer[..] {
+            return Err(Error::new(ErrorKind::InvalidData, "patch checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Parse the header of bsdiff 4.x or extended patch file, which may be
+    /// followed by trailing bytes.
+    ///
+    /// The extra section of bsdiff 4.x patch files always extends to the end.
+    pub fn parse_prefix(patch: &[u8]) -> Result<Self> {
+        let header = Header::parse_header(patch)?;
+        if header.total_size().is_none_or(|size| size > patch.len() as u64) {
+            return Err(PatchError::SectionOverflow.into());
+        }
+        header.verify(patch)?;
+        Ok(header)
+    }
+
+    /// Parse the header only, the sizes of sections are not checked.
+    ///
+    /// The extra section size of bsdiff 4.x patch files is the remainder of
+    /// the patch (or zero if the patch is shorter).
+    pub fn parse_header(patch: &[u8]) -> Result<Self> {
+        if patch.len() >= CLASSIC_HEADER_SIZE && &patch[..8] == BSDIFF4_MAGIC {
+            let sizes = [&patch[8..16], &patch[16..24], &patch[24..32]].map(decode_int);
+            if sizes.iter().any(|&size| size < 0) {
+                return Err(PatchError::NegativeSectionSize.into());
+            }
+            let [csize, dsize, tsize] = sizes.map(|size| size as u64);
+            let esize = (patch.len() as u64)
+                .saturating_sub(CLASSIC_HEADER_SIZE as u64)
+                .saturating_sub(csize)
+                .saturating_sub(dsize);
+            Ok(Header::new(
+                Format::Classic,
+                [Codec::Bzip2; 3],
+                csize,
+                dsize,
+                esize,
+                tsize,
+            ))
+        } else if patch.len() >= EXTENDED_HEADER_SIZE && &patch[..8] == QBSDIFF2_MAGIC {
+            let flags = LE::read_u32(&patch[8..12]);
+            let known = FLAG_TARGET_COPY
+                | FLAG_CHECKSUM
+                | FLAG_SOURCE_CHECKSUM
+                | FLAG_METADATA
+                | FLAG_CHECKSUM_KIND
+                | FLAG_COMPACT_SEEK
+                | FLAG_DECODED_SIZES
+                | FLAG_BLOCKS
+                | FLAG_SIZES;
+            let kind = ChecksumKind::from_id(((flags & FLAG_CHECKSUM_KIND) >> CHECKSUM_KIND_SHIFT) as u8)
+                .filter(|kind| flags & FLAG_CHECKSUM != 0 || *kind == ChecksumKind::Crc32);
+            let sizes_alone = flags & FLAG_SIZES != 0 && flags & FLAG_DECODED_SIZES == 0;
+            if flags & !known != 0 || kind.is_none() || patch[15] > MAX_WINDOW_LOG || sizes_alone {
+                return Err(Error::new(ErrorKind::Unsupported, "unsupported patch features"));
+            }
+            let mut codecs = [Codec::Bzip2; 3];
+            for (codec, &id) in codecs.iter_mut().zip(patch[12..15].iter()) {
+                *codec = Codec::from_id(id).ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown codec"))?;
+            }
+            let csize = LE::read_u64(&patch[16..24]);
+            let dsize = LE::read_u64(&patch[24..32]);
+            let esize = LE::read_u64(&patch[32..40]);
+            let tsize = LE::read_u64(&patch[40..48]);
+            let mut header = Header::new(Format::Extended, codecs, csize, dsize, esize, tsize);
+            if flags & FLAG_TARGET_COPY != 0 {
+                header = header.target_copy(patch[15]);
+            }
+            if flags & FLAG_COMPACT_SEEK != 0 {
+                header = header.compact_seek();
+            }
+            if let Some(kind) = kind.filter(|_| flags & FLAG_CHECKSUM != 0) {
+                header = header.checksum(kind);
+            }
+            if patch.len() < Header::extended_size(patch)? {
+                return Err(PatchError::SectionOverflow.into());
+            }
+            if flags & FLAG_DECODED_SIZES != 0 {
+                let sizes = &patch[EXTENDED_HEADER_SIZE..EXTENDED_HEADER_SIZE + DECODED_SIZES_SIZE];
+                header = header.decoded_sizes([&sizes[0..8], &sizes[8..16], &sizes[16..24]].map(LE::read_u64));
+            }
+            if flags & FLAG_SIZES != 0 {
+                let offset = EXTENDED_HEADER_SIZE + DECODED_SIZES_SIZE;
+                if LE::read_u32(&patch[offset + 8..offset + 12]) != 0 {
+                    return Err(Error::new(ErrorKind::Unsupported, "unsupported patch features"));
+                }
+                header = header.explicit_sizes(LE::read_u64(&patch[offset..offset + 8]));
+            }
+            if flags & FLAG_SOURCE_CHECKSUM != 0 {
+                let offset = Header::source_table_offset(flags);
+                header = header.source_checksum(
+                    LE::read_u64(&patch[offset..offset + 8]),
+                    LE::read_u64(&patch[offset + 8..offset + 16]),
+                );
+            }
+            if flags & FLAG_METADATA != 0 {
+                let offset = header.metadata_offset();
+                header = header.metadata(LE::read_u32(&patch[offset..offset + 4]));
+            }
+            if flags & FLAG_BLOCKS != 0 {
+                let offset = header.block_table_offset();
+                let table = &patch[offset..offset + BLOCK_TABLE_HEADER_SIZE];
+                let block_size = LE::read_u64(&table[0..8]);
+                if block_size == 0 || block_size > MAX_BLOCK_SIZE as u64 {
+                    return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+                }
+                header = header.blocks(
+                    block_size,
+                    [&table[8..12], &table[12..16], &table[16..20]].map(LE::read_u32),
+                );
+            }
+            header.check_sizes()?;
+            Ok(header)
+        } else if patch.len() >= MICRO_HEADER_SIZE && &patch[..4] == QBSMICRO_MAGIC {
+            let mut codecs = [Codec::Stored; 3];
+            for (i, codec) in codecs.iter_mut().enumerate() {
+                let id = patch[4] >> (2 * i) & 0x3;
+                *codec = Codec::from_id(id).ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown codec"))?;
+            }
+            if patch[4] >> 6 != 0 {
+                return Err(Error::new(ErrorKind::Unsupported, "unsupported patch features"));
+            }
+            let mut sizes = [0; 4];
+            let mut offset = MICRO_HEADER_SIZE - 4;
+            for size in sizes.iter_mut() {
+                let (value, n) = read_uvarint(&patch[offset..]).ok_or(PatchError::SectionOverflow)?;
+                *size = value;
+                offset += n;
+            }
+            let [csize, dsize, esize, tsize] = sizes;
+            let header = Header::new(Format::Extended, codecs, csize, dsize, esize, tsize).micro();
+            if header.size() != offset {
+                return Err(Error::new(ErrorKind::InvalidData, "patch corrupted"));
+            }
+            header.check_sizes()?;
+            Ok(header)
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, "not a valid patch"))
+        }
+    }
+
+    /// Encode the header, not including the checksums of source windows, the
+    /// metadata and the block table.
+    pub fn encode(&self) -> Vec<u8> {
+        match self.format {
+            Format::Classic => {
+                let mut header = vec![0; CLASSIC_HEADER_SIZE];
+                header[0..8].copy_from_slice(BSDIFF4_MAGIC);
+                encode_int(self.csize as i64, &mut header[8..16]);
+                encode_int(self.dsize as i64, &mut header[16..24]);
+                encode_int(self.tsize as i64, &mut header[24..32]);
+                header
+            }
+            Format::Extended if self.has_micro() => {
+                let mut header = QBSMICRO_MAGIC.to_vec();
+                header.push(
+                    self.codecs
+                        .iter()
+                        .enumerate()
+                        .fold(0, |bits, (i, codec)| bits | codec.id() << (2 * i)),
+                );
+                for size in [self.csize, self.dsize, self.esize, self.tsize] {
+                    encode_uvarint(size, &mut header);
+                }
+                header
+            }
+            Format::Extended => {
+                let mut header = vec![0; EXTENDED_HEADER_SIZE];
+                header[0..8].copy_from_slice(QBSDIFF2_MAGIC);
+                LE::write_u32(&mut header[8..12], self.flags);
+                for (b, codec) in header[12..15].iter_mut().zip(self.codecs.iter()) {
+                    *b = codec.id();
+                }
+                header[15] = self.window_log;
+                LE::write_u64(&mut header[16..24], self.csize);
+                LE::write_u64(&mut header[24..32], self.dsize);
+                LE::write_u64(&mut header[32..40], self.esize);
+                LE::write_u64(&mut header[40..48], self.tsize);
+                if let Some(decoded) = self.decoded_size_hint() {
+                    for size in decoded {
+                        header.extend_from_slice(&size.to_le_bytes());
+                    }
+                }
+                if self.has_explicit_sizes() {
+                    header.extend_from_slice(&self.source_size.to_le_bytes());
+                    header.extend_from_slice(&0u32.to_le_bytes());
+                }
+                if self.has_source_checksum() {
+                    let mut table = [0; SOURCE_TABLE_HEADER_SIZE];
+                    LE::write_u64(&mut table[0..8], self.ssize);
+                    LE::write_u64(&mut table[8..16], self.swindow);
+                    header.extend_from_slice(&table);
+                }
+                header
+            }
+        }
+    }
+
+    /// Size of the encoded header, including the checksums of source windows,
+    /// the metadata and the block table.
+    pub fn size(&self) -> usize {
+        match self.format {
+            Format::Classic => CLASSIC_HEADER_SIZE,
+            Format::Extended if self.has_micro() => [self.csize, self.dsize, self.esize, self.tsize]
+                .iter()
+                .fold(MICRO_HEADER_SIZE - 4, |size, &x| size + uvarint_size(x)),
+            Format::Extended if self.has_blocks() => {
+                let count: usize = self.blocks.iter().map(|&n| n as usize).sum();
+                self.block_table_offset() + BLOCK_TABLE_HEADER_SIZE + count * 4
+            }
+            Format::Extended => self.block_table_offset(),
+        }
+    }
+
+    /// Total size of the header, all sections and checksums, `None` on
+    /// overflow.
+    pub fn total_size(&self) -> Option<u64> {
+        let trailer = self.checksums_size();
+        (self.size() as u64)
+            .checked_add(self.csize)?
+            .checked_add(self.dsize)?
+            .checked_add(self.esize)?
+            .checked_add(trailer as u64)
+    }
+
+    /// Split the control, delta and extra sections of the patch.
+    pub fn sections<'a>(&self, patch: &'a [u8]) -> (&'a [u8], &'a [u8], &'a [u8]) {
+        let (_, remain) = patch.split_at(self.size());
+        let (ctrls, remain) = remain.split_at(self.csize as usize);
+        let (delta, remain) = remain.split_at(self.dsize as usize);
+        let (extra, _) = remain.split_at(self.esize as usize);
+        (ctrls, delta, extra)
+    }
+}
+
+/// Encode integer as LEB128 varint.
+fn encode_uvarint(mut x: u64, out: &mut Vec<u8>) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+/// Size of integer encoded as LEB128 varint.
+fn uvarint_size(x: u64) -> usize {
+    Ord::max(64 - x.leading_zeros() as usize, 1).div_ceil(7)
+}
+
+/// Decode LEB128 varint, return the integer and the encoded size, `None` if
+/// incomplete or overflowing.
+fn read_uvarint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut x = 0u64;
+    for (i, &b) in data.iter().take(10).enumerate() {
+        if i == 9 && b > 1 {
+            return None;
+        }
+        x |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((x, i + 1));
+        }
+    }
+    None
+}
@@ -0,0 +1,414 @@
+#![forbid(unsafe_code)]
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::bsdiff::Bsdiff;
+use super::codec::Decoder;
+use super::format::Header;
+use super::profile::DiffProfile;
+
+/// Environment variable rewriting baseline files in `Baseline::check_file`
+/// instead of checking them, if set to `1`.
+pub const UPDATE_VAR: &str = "QBSDIFF_UPDATE_BASELINE";
+
+/// Patch sizes of a corpus, tracked across code changes to catch size
+/// regressions of the matcher.
+///
+/// Baseline files are plain text, one `name size` pair per line sorted by
+/// name, blank lines and lines starting with `#` are ignored.
+/// Names must not contain whitespace.
+///
+/// Example:
+///
+/// Check the patch sizes of a corpus against the committed baseline, allowing
+/// 1% larger patches (run with `QBSDIFF_UPDATE_BASELINE=1` to accept the new
+/// sizes):
+/// ```no_run
+/// use std::io;
+/// use qbsdiff::golden::Baseline;
+/// use qbsdiff::Bsdiff;
+///
+/// fn check(corpus: &[(&str, Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+///     let sizes = Baseline::measure(corpus.iter().map(|(n, s, t)| (*n, &s[..], &t[..])), |s, t| {
+///         Bsdiff::new(s, t).compare_to_vec()
+///     })?;
+///     sizes.check_file("tests/sizes.baseline", 0.01)
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Baseline {
+    sizes: BTreeMap<String, u64>,
+}
+
+/// Patch size of a corpus entry changed between baselines.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SizeChange {
+    /// Name of the corpus entry.
+    pub name: String,
+    /// Size in the baseline, or `None` if the entry is new.
+    pub baseline: Option<u64>,
+    /// Current size, or `None` if the entry is gone.
+    pub current: Option<u64>,
+}
+
+impl SizeChange {
+    /// Relative change of size (e.g. `0.1` for 10% larger), or `None` unless
+    /// both sizes are known.
+    pub fn ratio(&self) -> Option<f64> {
+        match (self.baseline, self.current) {
+            (Some(0), Some(0)) => Some(0.0),
+            (Some(0), Some(_)) => Some(f64::INFINITY),
+            (Some(base), Some(cur)) => Some(cur as f64 / base as f64 - 1.0),
+            _ => None,
+        }
+    }
+
+    /// Check whether the size regressed beyond the tolerance, or the entry is
+    /// gone.
+    pub fn is_regression(&self, tolerance: f64) -> bool {
+        match self.ratio() {
+            Some(ratio) => ratio > tolerance,
+            None => self.current.is_none(),
+        }
+    }
+}
+
+impl fmt::Display for SizeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.baseline, self.current, self.ratio()) {
+            (Some(base), Some(cur), Some(ratio)) => {
+                write!(f, "{}: {} -> {} ({:+.2}%)", self.name, base, cur, ratio * 100.0)
+            }
+            (Some(base), None, _) => write!(f, "{}: {} -> missing", self.name, base),
+            (_, Some(cur), _) => write!(f, "{}: new -> {}", self.name, cur),
+            _ => write!(f, "{}: missing", self.name),
+        }
+    }
+}
+
+impl Baseline {
+    /// Create empty baseline.
+    pub fn new() -> Self {
+        Baseline::default()
+    }
+
+    /// Measure the patch size of each corpus entry, of name, source and
+    /// target, by the differ.
+    pub fn measure<'a, C, F>(corpus: C, mut differ: F) -> Result<Self>
+    where
+        C: IntoIterator<Item = (&'a str, &'a [u8], &'a [u8])>,
+        F: FnMut(&[u8], &[u8]) -> Result<Vec<u8>>,
+    {
+        let mut baseline = Baseline::new();
+        for (name, source, target) in corpus {
+            let patch = differ(source, target)?;
+            baseline.record(name, patch.len() as u64)?;
+        }
+        Ok(baseline)
+    }
+
+    /// Record the patch size of a corpus entry, replacing the previous one.
+    ///
+    /// Return error if the name is empty or contains whitespace.
+    pub fn record(&mut self, name: &str, size: u64) -> Result<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid baseline entry name"));
+        }
+        self.sizes.insert(name.to_string(), size);
+        Ok(())
+    }
+
+    /// Get the patch size of a corpus entry.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.sizes.get(name).copied()
+    }
+
+    /// Iterate over the entries sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> + '_ {
+        self.sizes.iter().map(|(name, &size)| (&name[..], size))
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Check whether the baseline is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// Parse the text of a baseline file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut baseline = Baseline::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let entry = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(size), None) => size.parse().ok().map(|size| (name, size)),
+                _ => None,
+            };
+            let (name, size) =
+                entry.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("invalid baseline line {}", i + 1)))?;
+            baseline.record(name, size)?;
+        }
+        Ok(baseline)
+    }
+
+    /// Load the baseline file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Baseline::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Save the baseline file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Get all entries whose size differs from the earlier `baseline`, sorted
+    /// by name.
+    pub fn diff(&self, baseline: &Baseline) -> Vec<SizeChange> {
+        let mut names: Vec<&String> = self.sizes.keys().chain(baseline.sizes.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| SizeChange {
+                name: name.clone(),
+                baseline: baseline.get(name),
+                current: self.get(name),
+            })
+            .filter(|change| change.baseline != change.current)
+            .collect()
+    }
+
+    /// Check the sizes against the earlier `baseline`, allowing sizes larger
+    /// by the relative `tolerance` (e.g. `0.01` for 1%).
+    ///
+    /// Return `ErrorKind::InvalidData` listing all changed entries if any
+    /// size regressed beyond the tolerance or any entry is gone.
+    pub fn check(&self, baseline: &Baseline, tolerance: f64) -> Result<()> {
+        let changes = self.diff(baseline);
+        let regressed = changes.iter().filter(|c| c.is_regression(tolerance)).count();
+        if regressed == 0 {
+            return Ok(());
+        }
+        let mut message = format!(
+            "{} of {} patch sizes regressed beyond {:.2}%:",
+            regressed,
+            baseline.len(),
+            tolerance * 100.0
+        );
+        for change in changes.iter() {
+            let mark = if change.is_regression(tolerance) { '!' } else { ' ' };
+            message.push_str(&format!("\n{} {}", mark, change));
+        }
+        Err(Error::new(ErrorKind::InvalidData, message))
+    }
+
+    /// Check the sizes against the baseline file like `check`.
+    ///
+    /// If the file does not exist, or `UPDATE_VAR` is set to `1`, the file is
+    /// written with the current sizes instead.
+    pub fn check_file<P: AsRef<Path>>(&self, path: P, tolerance: f64) -> Result<()> {
+        let path = path.as_ref();
+        if !path.exists() || env::var(UPDATE_VAR).is_ok_and(|v| v == "1") {
+            return self.save(path);
+        }
+        self.check(&Baseline::load(path)?, tolerance)
+    }
+}
+
+impl fmt::Display for Baseline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, size) in self.iter() {
+            writeln!(f, "{} {}", name, size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes of the phases of patch generation, see `check_determinism`.
+///
+/// Fingerprints are written as two 16-digit hexadecimal hashes separated by
+/// `:`, the hash of the controls followed by the hash of the patch file, e.g.
+/// to be embedded in tests as the expected value.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Fingerprint {
+    /// Hash of the decoded sections (controls, delta and extra data), which
+    /// only depend on the matcher.
+    pub controls: u64,
+
+    /// Hash of the whole patch file, which also depends on the container and
+    /// codecs.
+    pub patch: u64,
+}
+
+/// Phase of patch generation, see `Divergence`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Searching controls, e.g. nondeterministic parallel matching.
+    Controls,
+
+    /// Compressing sections and assembling the patch file, e.g. a different
+    /// codec version.
+    Compression,
+}
+
+/// Structured error of `check_determinism`, telling which phase of patch
+/// generation diverged from the expected fingerprint.
+///
+/// It is carried by `ErrorKind::InvalidData` errors and could be extracted by
+/// `Divergence::of`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// The earliest phase diverged.
+    pub phase: Phase,
+
+    /// Expected fingerprint.
+    pub expected: Fingerprint,
+
+    /// Actual fingerprint.
+    pub actual: Fingerprint,
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of a patch.
+    ///
+    /// Return error if the patch is corrupted.
+    pub fn of(patch: &[u8]) -> Result<Self> {
+        let header = Header::parse(patch)?;
+        let (ctrls, delta, extra) = header.sections(patch);
+        let mut decoded = Vec::new();
+        for (codec, data) in header.codecs.iter().zip([ctrls, delta, extra]) {
+            Decoder::new(*codec, data)?.read_to_end(&mut decoded)?;
+        }
+        Ok(Fingerprint {
+            controls: fnv1a(&decoded),
+            patch: fnv1a(patch),
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}:{:016x}", self.controls, self.patch)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = |h: &str| {
+            Some(h)
+                .filter(|h| h.len() == 16)
+                .and_then(|h| u64::from_str_radix(h, 16).ok())
+        };
+        match s.trim().split_once(':') {
+            Some((controls, patch)) => match (hex(controls), hex(patch)) {
+                (Some(controls), Some(patch)) => Ok(Fingerprint { controls, patch }),
+                _ => Err(Error::new(ErrorKind::InvalidInput, "invalid fingerprint")),
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput, "invalid fingerprint")),
+        }
+    }
+}
+
+impl Divergence {
+    /// Get the divergence reported by `check_determinism`, if any.
+    pub fn of(err: &Error) -> Option<Divergence> {
+        err.get_ref()?.downcast_ref::<Divergence>().copied()
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self.phase {
+            Phase::Controls => "controls",
+            Phase::Compression => "compression",
+        };
+        write!(
+            f,
+            "patch generation diverged in {}: expected {}, got {}",
+            phase, self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for Divergence {}
+
+impl From<Divergence> for Error {
+    fn from(err: Divergence) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+/// Produce the patch of fixed inputs by the profile and check its fingerprint
+/// against the expected one, to track down nondeterminism introduced by
+/// parallelism or codec versions.
+///
+/// Note that `DiffProfile::metadata` records the qbsdiff version in patches,
+/// thus changes the patch hash across versions.
+///
+/// Example:
+///
+/// Pin the patch of a test case (the fingerprint is printed by the first run
+/// with a placeholder):
+/// ```no_run
+/// use std::io;
+/// use qbsdiff::golden::{check_determinism, Divergence};
+/// use qbsdiff::DiffProfile;
+///
+/// fn pinned(source: &[u8], target: &[u8]) -> io::Result<()> {
+///     let expected = "0123456789abcdef:0123456789abcdef".parse()?;
+///     match check_determinism(source, target, &DiffProfile::default(), &expected) {
+///         Err(err) if Divergence::of(&err).is_some() => panic!("{}", err),
+///         result => result.map(|_| ()),
+///     }
+/// }
+/// ```
+///
+/// Return `ErrorKind::InvalidData` carrying a `Divergence` if the
+/// fingerprints differ, or error if the profile is invalid.
+/// The patch would be returned if no error occurs.
+pub fn check_determinism(
+    source: &[u8],
+    target: &[u8],
+    profile: &DiffProfile,
+    expected: &Fingerprint,
+) -> Result<Vec<u8>> {
+    let patch = Bsdiff::try_new(source, target)?
+        .try_profile(profile)?
+        .compare_to_vec()?;
+    let actual = Fingerprint::of(&patch)?;
+    let phase = if actual.controls != expected.controls {
+        Phase::Controls
+    } else if actual.patch != expected.patch {
+        Phase::Compression
+    } else {
+        return Ok(patch);
+    };
+    Err(Divergence {
+        phase,
+        expected: *expected,
+        actual,
+    }
+    .into())
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
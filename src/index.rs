@@ -0,0 +1,684 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Result, Write};
+
+use std::ops::Range;
+
+use byteorder::{ByteOrder, LE};
+use suffix_array::{SuffixArray, MAX_LENGTH};
+
+use super::sais;
+
+/// Magic number bytes of serialized source indexes.
+pub const INDEX_MAGIC: &[u8] = b"QBSINDX1";
+
+/// Size of the header of serialized source indexes.
+const INDEX_HEADER_SIZE: usize = 24;
+
+/// Length of k-mers sampled by similarity estimation.
+const SIMILARITY_KMER: usize = 16;
+
+/// Default interval of k-mers sampled by similarity estimation.
+pub const SIMILARITY_INTERVAL: usize = 64;
+
+/// Estimate how similar the target is to the source, in range `[0, 1]`.
+///
+/// The estimation is the fraction of k-mers sampled from the target that
+/// also appear in the source, with the default interval
+/// `SIMILARITY_INTERVAL`.
+/// See `SourceIndex::similarity()` to reuse the index or to configure the
+/// sampling rate.
+///
+/// Example:
+///
+/// Decide whether to bother generating a delta:
+/// ```
+/// use std::io;
+/// use qbsdiff::{similarity, Bsdiff};
+///
+/// fn make_update(source: &[u8], target: &[u8]) -> io::Result<Option<Vec<u8>>> {
+///     if similarity(source, target) < 0.25 {
+///         // just ship the full file
+///         return Ok(None);
+///     }
+///     let mut patch = Vec::new();
+///     Bsdiff::new(source, target).compare(io::Cursor::new(&mut patch))?;
+///     Ok(Some(patch))
+/// }
+/// ```
+pub fn similarity(source: &[u8], target: &[u8]) -> f64 {
+    SourceIndex::new(source).similarity(target, SIMILARITY_INTERVAL)
+}
+
+/// Prebuilt index (suffix array) of the source data.
+///
+/// Besides delta compression, the index answers substring queries on the
+/// source, e.g. for binary similarity scanners or clone detectors, see
+/// `search_lcp` and `search_all`.
+///
+/// Building the index is the most expensive part of delta compression on
+/// large sources.
+/// An index could be shared by multiple `Bsdiff` configurations comparing
+/// different targets against the same source, and by all parallel jobs of
+/// each comparison.
+///
+/// Example:
+///
+/// Produce patches of multiple targets against the same source:
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bsdiff, SourceIndex};
+///
+/// fn bsdiff_all(source: &[u8], targets: &[&[u8]]) -> io::Result<Vec<Vec<u8>>> {
+///     let index = SourceIndex::new(source);
+///     let mut patches = Vec::new();
+///     for target in targets.iter() {
+///         let mut patch = Vec::new();
+///         Bsdiff::with_index(&index, target).compare(io::Cursor::new(&mut patch))?;
+///         patches.push(patch);
+///     }
+///     Ok(patches)
+/// }
+/// ```
+pub struct SourceIndex<'s> {
+    source: &'s [u8],
+    sa: Suffixes<'s>,
+}
+
+/// Suffix array, either built in memory or borrowed from the serialized form.
+enum Suffixes<'s> {
+    Built(SuffixArray<'s>),
+    Owned(Owned),
+    Shared(&'s [u8]),
+    Enhanced(Enhanced),
+}
+
+/// Suffix array built by `SourceIndex::build_with_progress`, with the bucket
+/// boundaries of 2-byte prefixes, searched the same way as `SuffixArray`.
+struct Owned {
+    sa: Vec<u32>,
+    buckets: Vec<u32>,
+}
+
+/// Enhanced suffix array built by `SourceIndex::build_enhanced`, with the
+/// longest common prefixes of the bounds of the intervals visited by binary
+/// search, searched as by Manber and Myers (1993).
+///
+/// The intervals form a fixed tree, the root is `(0, n)` and the children of
+/// `(lo, hi)` are `(lo, mid)` and `(mid, hi)` with `mid = (lo + hi) / 2`.
+/// `llcp[mid]` and `rlcp[mid]` are the longest common prefixes of the suffix
+/// at `mid` and the ones at `lo` and `hi`, where the virtual suffix at `n`
+/// shares nothing.
+struct Enhanced {
+    sa: Vec<u32>,
+    llcp: Vec<u32>,
+    rlcp: Vec<u32>,
+}
+
+impl<'s> SourceIndex<'s> {
+    /// Build the index of source data.
+    ///
+    /// Panics if the length of source data is greater than MAX_LENGTH, see
+    /// `try_new` for the fallible variant.
+    pub fn new(source: &'s [u8]) -> Self {
+        match SourceIndex::try_new(source) {
+            Ok(index) => index,
+            Err(_) => panic!("source data is too large to be indexed"),
+        }
+    }
+
+    /// Build the index of source data, like `new` but returning
+    /// `ErrorKind::InvalidInput` if the length of source data is greater
+    /// than MAX_LENGTH.
+    pub fn try_new(source: &'s [u8]) -> Result<Self> {
+        if source.len() > MAX_LENGTH {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source data is too large to be indexed",
+            ));
+        }
+
+        let mut sa = SuffixArray::new(source);
+        sa.enable_buckets();
+        Ok(SourceIndex {
+            source,
+            sa: Suffixes::Built(sa),
+        })
+    }
+
+    /// Build the index of source data like `try_new`, calling `progress`
+    /// with the fraction done (in range `[0, 1]`) as it goes.
+    ///
+    /// Building the index of a large source takes minutes, the progress
+    /// keeps interactive tools from appearing frozen.
+    /// Building is cancelled once `progress` returns an error, which is
+    /// returned as is (e.g. `ErrorKind::Interrupted`).
+    /// The fraction is weighted by the phases of induced sorting and reported
+    /// every million suffixes or so, it never decreases.
+    ///
+    /// The index is the same as built by `try_new`, and so are the patches
+    /// produced with it, but building takes more time and up to about twice
+    /// the memory.
+    ///
+    /// Example:
+    ///
+    /// Index the source with a progress bar, cancelled by Ctrl-C:
+    /// ```
+    /// use std::io::{self, Write};
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use qbsdiff::SourceIndex;
+    ///
+    /// fn index<'s>(source: &'s [u8], cancelled: &AtomicBool) -> io::Result<SourceIndex<'s>> {
+    ///     SourceIndex::build_with_progress(source, |fraction| {
+    ///         if cancelled.load(Ordering::Relaxed) {
+    ///             return Err(io::ErrorKind::Interrupted.into());
+    ///         }
+    ///         eprint!("\rindexing {:.0}%", fraction * 100.0);
+    ///         io::stderr().flush()
+    ///     })
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the length of source data is
+    /// greater than MAX_LENGTH.
+    pub fn build_with_progress<F>(source: &'s [u8], mut progress: F) -> Result<Self>
+    where
+        F: FnMut(f64) -> Result<()>,
+    {
+        if source.len() > MAX_LENGTH {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source data is too large to be indexed",
+            ));
+        }
+
+        let sa = sais::suffix_array(source, &mut progress)?;
+        Ok(SourceIndex {
+            source,
+            sa: Suffixes::Owned(Owned {
+                sa,
+                buckets: buckets(source),
+            }),
+        })
+    }
+
+    /// Build the enhanced index of source data, augmenting the suffix array
+    /// with the longest common prefixes of the intervals of binary search.
+    ///
+    /// Searching a plain suffix array compares the whole common prefix of the
+    /// pattern against a suffix at every step of binary search, which costs
+    /// `O(m log n)` for sources with massive internal repetition, where the
+    /// suffixes around the pattern share long prefixes with it.
+    /// The enhanced index skips the prefix known to be shared with both
+    /// bounds of the interval, so `search_lcp` compares each byte of the
+    /// pattern about once, in `O(m + log n)` time.
+    /// E.g. searching the `qemu-m68k` pathological sample is about 30 times
+    /// faster.
+    ///
+    /// The patches produced with it are as good as with `try_new`, but might
+    /// differ in the choice among equally long matches.
+    /// It costs three times the memory of the suffix array (12 bytes per
+    /// source byte) and takes about twice as long to build, so it only pays
+    /// off for repeated queries on sources known to be repetitive.
+    /// The serialized form holds the suffix array only, see `serialize`.
+    ///
+    /// Example:
+    ///
+    /// Diff against a highly repetitive source:
+    /// ```
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, SourceIndex};
+    ///
+    /// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let index = SourceIndex::build_enhanced(source)?;
+    ///     Bsdiff::with_index(&index, target).compare_to_vec()
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the length of source data is
+    /// greater than MAX_LENGTH.
+    pub fn build_enhanced(source: &'s [u8]) -> Result<Self> {
+        if source.len() > MAX_LENGTH {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source data is too large to be indexed",
+            ));
+        }
+
+        let (_, sa) = SuffixArray::new(source).into_parts();
+        let lcp = lcp_array(source, &sa);
+        let mut llcp = vec![0; sa.len()];
+        let mut rlcp = vec![0; sa.len()];
+        interval_lcp(&lcp, 0, sa.len(), &mut llcp, &mut rlcp);
+        Ok(SourceIndex {
+            source,
+            sa: Suffixes::Enhanced(Enhanced { sa, llcp, rlcp }),
+        })
+    }
+
+    /// Load the index of source data from its serialized form without
+    /// copying, see `serialize`.
+    ///
+    /// The serialized index could be placed in memory shared by processes,
+    /// or memory mapped from a file (e.g. `memmap2::Mmap`), thus the multi-GB
+    /// suffix array of a large source is held once by all processes of a
+    /// delta farm, and loading it costs one pass of bounds checks only.
+    /// Searches on loaded indexes are a bit slower than on built ones, and
+    /// their patches might differ in the choice among equally long matches.
+    ///
+    /// Example:
+    ///
+    /// Index the source once, and compare targets in other processes:
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use std::io;
+    /// use qbsdiff::{Bsdiff, SourceIndex};
+    ///
+    /// fn publish(source: &[u8], path: &str) -> io::Result<u64> {
+    ///     SourceIndex::try_new(source)?.serialize(io::BufWriter::new(File::create(path)?))
+    /// }
+    ///
+    /// fn bsdiff(source: &[u8], path: &str, target: &[u8]) -> io::Result<Vec<u8>> {
+    ///     let data = fs::read(path)?;
+    ///     let index = SourceIndex::from_bytes(source, &data[..])?;
+    ///     Bsdiff::with_index(&index, target).compare_to_vec()
+    /// }
+    /// ```
+    ///
+    /// Return `ErrorKind::InvalidInput` if the index belongs to a source of
+    /// different length, or `ErrorKind::InvalidData` if it is corrupted.
+    /// An index of another source of the same length is not detected, the
+    /// patches would be valid but poor.
+    pub fn from_bytes(source: &'s [u8], data: &'s [u8]) -> Result<Self> {
+        let corrupted = || Error::new(ErrorKind::InvalidData, "source index corrupted");
+        if data.len() < INDEX_HEADER_SIZE || &data[..8] != INDEX_MAGIC {
+            return Err(corrupted());
+        }
+        if LE::read_u64(&data[8..16]) != source.len() as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "source index does not match the source",
+            ));
+        }
+        let count = LE::read_u64(&data[16..24]);
+        let sa = &data[INDEX_HEADER_SIZE..];
+        if count.checked_mul(4) != Some(sa.len() as u64) {
+            return Err(corrupted());
+        }
+        if sa.chunks_exact(4).any(|i| LE::read_u32(i) as usize > source.len()) {
+            return Err(corrupted());
+        }
+        Ok(SourceIndex {
+            source,
+            sa: Suffixes::Shared(sa),
+        })
+    }
+
+    /// Write the serialized form of the index, see `from_bytes`.
+    ///
+    /// The index is consumed to avoid holding the suffix array twice.
+    /// The serialized index consists of magic `QBSINDX1`, the source length
+    /// and the suffix count (u64 each), and the suffix array (u32 each), all
+    /// integers in little endian.
+    ///
+    /// The size of serialized index would be returned if no error occurs.
+    pub fn serialize<W: Write>(self, mut writer: W) -> Result<u64> {
+        let mut header = [0; INDEX_HEADER_SIZE];
+        header[..8].copy_from_slice(INDEX_MAGIC);
+        LE::write_u64(&mut header[8..16], self.source.len() as u64);
+        let count = match self.sa {
+            Suffixes::Built(sa) => {
+                let (_, sa) = sa.into_parts();
+                LE::write_u64(&mut header[16..24], sa.len() as u64);
+                writer.write_all(&header[..])?;
+                let mut buf = vec![0; 4 * 4096];
+                for chunk in sa.chunks(4096) {
+                    LE::write_u32_into(chunk, &mut buf[..4 * chunk.len()]);
+                    writer.write_all(&buf[..4 * chunk.len()])?;
+                }
+                sa.len()
+            }
+            Suffixes::Owned(Owned { sa, .. }) | Suffixes::Enhanced(Enhanced { sa, .. }) => {
+                LE::write_u64(&mut header[16..24], sa.len() as u64);
+                writer.write_all(&header[..])?;
+                let mut buf = vec![0; 4 * 4096];
+                for chunk in sa.chunks(4096) {
+                    LE::write_u32_into(chunk, &mut buf[..4 * chunk.len()]);
+                    writer.write_all(&buf[..4 * chunk.len()])?;
+                }
+                sa.len()
+            }
+            Suffixes::Shared(sa) => {
+                LE::write_u64(&mut header[16..24], (sa.len() / 4) as u64);
+                writer.write_all(&header[..])?;
+                writer.write_all(sa)?;
+                sa.len() / 4
+            }
+        };
+        writer.flush()?;
+        Ok((INDEX_HEADER_SIZE + 4 * count) as u64)
+    }
+
+    /// Get the indexed source data.
+    pub fn source(&self) -> &'s [u8] {
+        self.source
+    }
+
+    /// Estimate how similar the target is to the indexed source, in range
+    /// `[0, 1]`.
+    ///
+    /// One k-mer (16 bytes) is sampled every `interval` bytes of the target,
+    /// the estimation is the fraction of sampled k-mers that also appear in
+    /// the source.
+    /// Sample positions are fixed, so the result is stable across runs.
+    /// Smaller interval is more accurate but slower, interval 0 is treated as
+    /// 1 (every k-mer sampled).
+    pub fn similarity(&self, target: &[u8], interval: usize) -> f64 {
+        if target.len() < SIMILARITY_KMER {
+            let (_, n) = self.search_lcp(target);
+            return if n == target.len() { 1.0 } else { 0.0 };
+        }
+
+        let mut samples = 0u64;
+        let mut found = 0u64;
+        for i in (0..=target.len() - SIMILARITY_KMER).step_by(Ord::max(interval, 1)) {
+            samples += 1;
+            if self.contains(&target[i..i + SIMILARITY_KMER]) {
+                found += 1;
+            }
+        }
+        found as f64 / samples as f64
+    }
+
+    /// Search the longest common prefix of the pattern in the source.
+    ///
+    /// Return the offset in source and the length of the longest prefix of
+    /// pattern appearing in the source, which is the primitive of match
+    /// searching in `Bsdiff`.
+    /// The length is zero if even the first byte of pattern does not appear.
+    ///
+    /// Example:
+    ///
+    /// Find source regions cloned into a binary, greedily:
+    /// ```
+    /// use std::ops::Range;
+    /// use qbsdiff::SourceIndex;
+    ///
+    /// fn clones(index: &SourceIndex, binary: &[u8], min_len: usize) -> Vec<(Range<usize>, usize)> {
+    ///     let mut clones = Vec::new();
+    ///     let mut i = 0;
+    ///     while i < binary.len() {
+    ///         let (offset, len) = index.search_lcp(&binary[i..]);
+    ///         if len >= min_len {
+    ///             clones.push((i..i + len, offset));
+    ///         }
+    ///         i += Ord::max(len, 1);
+    ///     }
+    ///     clones
+    /// }
+    /// ```
+    pub fn search_lcp(&self, pattern: &[u8]) -> (usize, usize) {
+        let sa = match self.sa {
+            Suffixes::Built(ref sa) => {
+                let range = sa.search_lcp(pattern);
+                return (range.start, range.len());
+            }
+            Suffixes::Owned(ref owned) => {
+                let range = owned.search_lcp(self.source, pattern);
+                return (range.start, range.len());
+            }
+            Suffixes::Enhanced(ref enhanced) => return enhanced.search_lcp(self.source, pattern),
+            Suffixes::Shared(sa) => sa,
+        };
+
+        // The longest common prefixes are next to where the pattern would be
+        // inserted.
+        let k = self.partition(sa, |suffix| suffix < pattern);
+        let mut best = (0, 0);
+        for k in [k.checked_sub(1), Some(k)].into_iter().flatten() {
+            if let Some(i) = sa.get(4 * k..4 * k + 4).map(|i| LE::read_u32(i) as usize) {
+                let n = Iterator::zip(self.source[i..].iter(), pattern.iter())
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                if n > best.1 {
+                    best = (i, n);
+                }
+            }
+        }
+        best
+    }
+
+    /// Search all the occurrences of the pattern in the source.
+    ///
+    /// Return the offsets in source, in the lexicographical order of the
+    /// suffixes starting there (not in the order of offsets).
+    pub fn search_all(&self, pattern: &[u8]) -> Vec<u32> {
+        let sa = match self.sa {
+            Suffixes::Built(ref sa) => return sa.search_all(pattern).to_vec(),
+            Suffixes::Owned(ref owned) => return owned.search_all(self.source, pattern).to_vec(),
+            Suffixes::Enhanced(ref enhanced) => return enhanced.search_all(self.source, pattern).to_vec(),
+            Suffixes::Shared(sa) => sa,
+        };
+        let prefix = |suffix: &'s [u8]| &suffix[..Ord::min(suffix.len(), pattern.len())];
+        let start = self.partition(sa, |suffix| prefix(suffix) < pattern);
+        let end = self.partition(sa, |suffix| prefix(suffix) <= pattern);
+        sa[4 * start..4 * end].chunks_exact(4).map(LE::read_u32).collect()
+    }
+
+    /// Check whether the pattern appears in the source.
+    pub fn contains(&self, pattern: &[u8]) -> bool {
+        match self.sa {
+            Suffixes::Built(ref sa) => sa.contains(pattern),
+            Suffixes::Owned(ref owned) => owned.contains(self.source, pattern),
+            Suffixes::Shared(_) | Suffixes::Enhanced(_) => self.search_lcp(pattern).1 == pattern.len(),
+        }
+    }
+
+    /// Find the first suffix of the serialized suffix array not satisfying
+    /// the predicate, which holds for a prefix of the suffixes.
+    fn partition<F: Fn(&'s [u8]) -> bool>(&self, sa: &[u8], pred: F) -> usize {
+        let (mut lo, mut hi) = (0, sa.len() / 4);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let i = LE::read_u32(&sa[4 * mid..]) as usize;
+            if pred(&self.source[i..]) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+impl Owned {
+    /// Get the range of suffixes starting with the first two bytes of the
+    /// pattern, or the first byte if shorter.
+    fn bucket(&self, pattern: &[u8]) -> Range<usize> {
+        match *pattern {
+            [] => 0..1,
+            [c0] => self.top_bucket(c0),
+            [c0, c1, ..] => {
+                let i = c0 as usize * 257 + c1 as usize + 2;
+                self.buckets[i - 1] as usize..self.buckets[i] as usize
+            }
+        }
+    }
+
+    /// Get the range of suffixes starting with the byte.
+    fn top_bucket(&self, c0: u8) -> Range<usize> {
+        let i = c0 as usize * 257;
+        self.buckets[i] as usize..self.buckets[i + 257] as usize
+    }
+
+    fn contains(&self, s: &[u8], pattern: &[u8]) -> bool {
+        self.sa[self.bucket(pattern)]
+            .binary_search_by_key(&pattern, |&i| {
+                &s[i as usize..Ord::min(s.len(), i as usize + pattern.len())]
+            })
+            .is_ok()
+    }
+
+    fn search_all(&self, s: &[u8], pattern: &[u8]) -> &[u32] {
+        let sa = match pattern {
+            [] => &self.sa[..],
+            _ => &self.sa[self.bucket(pattern)],
+        };
+        let start = sa.partition_point(|&i| pattern > &s[i as usize..]);
+        let end = start + sa[start..].partition_point(|&i| s[i as usize..].starts_with(pattern));
+        &sa[start..end]
+    }
+
+    fn search_lcp(&self, s: &[u8], pattern: &[u8]) -> Range<usize> {
+        let sa = &self.sa[self.bucket(pattern)];
+        if sa.is_empty() {
+            // The pattern is not empty, no suffix shares more than one byte.
+            return match self.sa[self.top_bucket(pattern[0])].first() {
+                Some(&i) => i as usize..i as usize + 1,
+                None => s.len()..s.len(),
+            };
+        }
+
+        let lcp = |i: u32| {
+            let n = Iterator::zip(s[i as usize..].iter(), pattern.iter())
+                .take_while(|(x, y)| x == y)
+                .count();
+            i as usize..i as usize + n
+        };
+        match sa.binary_search_by(|&i| s[i as usize..].cmp(pattern)) {
+            Ok(k) => sa[k] as usize..s.len(),
+            Err(0) => lcp(sa[0]),
+            Err(k) if k == sa.len() => lcp(sa[k - 1]),
+            Err(k) => {
+                let (a, b) = (lcp(sa[k - 1]), lcp(sa[k]));
+                if a.len() > b.len() {
+                    a
+                } else {
+                    b
+                }
+            }
+        }
+    }
+}
+
+impl Enhanced {
+    /// Search the longest common prefix of the pattern, skipping the prefix
+    /// known to be shared with both bounds of the interval, so that each byte
+    /// of the pattern is compared about once.
+    fn search_lcp(&self, s: &[u8], pattern: &[u8]) -> (usize, usize) {
+        let n = self.sa.len();
+        let suffix = |k: usize| &s[self.sa[k] as usize..];
+
+        // The empty suffix at 0 is smaller than the pattern, and the virtual
+        // suffix at `n` is greater.
+        let (mut lo, mut hi) = (0, n);
+        let (mut l, mut r) = (0, 0);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let (m, shared) = match l >= r {
+                true => (self.llcp[mid] as usize, l),
+                false => (self.rlcp[mid] as usize, r),
+            };
+            if m != shared {
+                // The suffix at mid lies on the side of the nearer bound if it
+                // agrees with the bound beyond the shared prefix, or on the
+                // other side if it differs from the bound within.
+                match (l >= r, m > shared) {
+                    (true, true) => lo = mid,
+                    (true, false) => (hi, r) = (mid, m),
+                    (false, true) => hi = mid,
+                    (false, false) => (lo, l) = (mid, m),
+                }
+                continue;
+            }
+            let h = shared + common_prefix(&suffix(mid)[shared..], &pattern[shared..]);
+            if h == pattern.len() {
+                return (self.sa[mid] as usize, h);
+            }
+            match suffix(mid).get(h) {
+                Some(&c) if c > pattern[h] => (hi, r) = (mid, h),
+                _ => (lo, l) = (mid, h),
+            }
+        }
+
+        match l >= r {
+            true => (self.sa[lo] as usize, l),
+            false => (self.sa[hi] as usize, r),
+        }
+    }
+
+    fn search_all(&self, s: &[u8], pattern: &[u8]) -> &[u32] {
+        let start = self.sa.partition_point(|&i| pattern > &s[i as usize..]);
+        let end = start + self.sa[start..].partition_point(|&i| s[i as usize..].starts_with(pattern));
+        &self.sa[start..end]
+    }
+}
+
+/// Get the length of the common prefix, comparing by chunks first.
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let n = Ord::min(a.len(), b.len());
+    let chunks = Iterator::zip(a[..n].chunks(64), b[..n].chunks(64))
+        .take_while(|(x, y)| x == y)
+        .count();
+    let k = Ord::min(64 * chunks, n);
+    k + Iterator::zip(a[k..n].iter(), b[k..n].iter())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Compute the LCP array of the suffix array by Kasai's algorithm, with the
+/// sentinel 0 at the end.
+fn lcp_array(s: &[u8], sa: &[u32]) -> Vec<u32> {
+    let mut rank = vec![0u32; sa.len()];
+    for (k, &i) in sa.iter().enumerate() {
+        rank[i as usize] = k as u32;
+    }
+
+    let mut lcp = vec![0u32; sa.len() + 1];
+    let mut h = 0;
+    for i in 0..s.len() {
+        let k = rank[i] as usize;
+        let j = sa[k - 1] as usize;
+        while i + h < s.len() && j + h < s.len() && s[i + h] == s[j + h] {
+            h += 1;
+        }
+        lcp[k] = h as u32;
+        h = h.saturating_sub(1);
+    }
+    lcp
+}
+
+/// Fill the longest common prefixes of the bounds of the interval `(lo, hi)`
+/// and its descendants in the layout of `Enhanced`, return the longest common
+/// prefix of the suffixes at `lo` and `hi`.
+fn interval_lcp(lcp: &[u32], lo: usize, hi: usize, llcp: &mut [u32], rlcp: &mut [u32]) -> u32 {
+    if hi - lo == 1 {
+        return lcp[hi];
+    }
+    let mid = lo + (hi - lo) / 2;
+    llcp[mid] = interval_lcp(lcp, lo, mid, llcp, rlcp);
+    rlcp[mid] = interval_lcp(lcp, mid, hi, llcp, rlcp);
+    Ord::min(llcp[mid], rlcp[mid])
+}
+
+/// Count the suffixes by the first two bytes (or the first byte of the last
+/// suffix, or none of the empty suffix), return the cumulative counts.
+///
+/// The layout is `[$; (0, $), (0, 0), ..., (0, 255); ...; (255, $), ...,
+/// (255, 255)]`, the same as the buckets of `SuffixArray`.
+fn buckets(s: &[u8]) -> Vec<u32> {
+    let mut buckets = vec![0u32; 256 * 257 + 1];
+    buckets[0] = 1;
+    for w in s.windows(2) {
+        buckets[w[0] as usize * 257 + w[1] as usize + 2] += 1;
+    }
+    if let Some(&c0) = s.last() {
+        buckets[c0 as usize * 257 + 1] += 1;
+    }
+    let mut sum = 0;
+    for count in buckets.iter_mut() {
+        sum += *count;
+        *count = sum;
+    }
+    buckets
+}
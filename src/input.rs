@@ -0,0 +1,158 @@
+#![forbid(unsafe_code)]
+
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read, Result, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use super::files::{MappedFile, TempFile};
+
+/// Compression of input files, detected by magic numbers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Gzip stream (decompression requires feature `gzip`).
+    Gzip,
+
+    /// Zstandard frames (decompression requires feature `zstd`).
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression by the leading bytes of data.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Whether decompression is compiled in.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Wrap the compressed stream with a decompressor.
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(io::BufReader::new(reader)))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = reader;
+                Err(Error::new(ErrorKind::Unsupported, "decompression not compiled in"))
+            }
+        }
+    }
+}
+
+/// Decompress the data if compressed, see `InputFile::open_decompressed`.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    match Compression::detect(&data) {
+        Some(compression) => {
+            let mut decoded = Vec::new();
+            compression.decoder(&data[..])?.read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        None => Ok(data),
+    }
+}
+
+/// Read-only input file for delta compression or patching (requires feature
+/// `mmap`).
+///
+/// Files are memory-mapped rather than loaded into memory, and must not be
+/// modified while opened.
+/// Compressed files could be decompressed transparently, so that the deltas
+/// are always computed on the uncompressed contents.
+///
+/// Example:
+///
+/// Diff two gzipped releases:
+/// ```no_run
+/// use std::io;
+/// use qbsdiff::Bsdiff;
+/// use qbsdiff::input::InputFile;
+///
+/// fn bsdiff(source: &str, target: &str) -> io::Result<Vec<u8>> {
+///     let source = InputFile::open_decompressed(source)?;
+///     let target = InputFile::open_decompressed(target)?;
+///     let mut patch = Vec::new();
+///     Bsdiff::new(&source, &target).compare(io::Cursor::new(&mut patch))?;
+///     Ok(patch)
+/// }
+/// ```
+pub struct InputFile {
+    // Unmapped before the temporary file is removed.
+    data: MappedFile,
+    _temp: Option<TempFile>,
+    compression: Option<Compression>,
+}
+
+impl InputFile {
+    /// Open the file as is.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(InputFile {
+            data: MappedFile::open(path.as_ref())?,
+            _temp: None,
+            compression: None,
+        })
+    }
+
+    /// Open the file, decompressing it if compressed with gzip or zstd.
+    ///
+    /// Compressed files are decompressed into a temporary file in
+    /// `std::env::temp_dir()`, which is removed as dropped.
+    ///
+    /// Return `ErrorKind::Unsupported` if the compression is detected but not
+    /// compiled in.
+    pub fn open_decompressed<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let mut magic = Vec::with_capacity(4);
+        (&mut file).take(4).read_to_end(&mut magic)?;
+        let compression = match Compression::detect(&magic) {
+            Some(compression) => compression,
+            None => return Self::open(path),
+        };
+        if !compression.is_supported() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{:?} decompression not compiled in", compression),
+            ));
+        }
+
+        let reader = io::Cursor::new(magic).chain(file);
+        let mut temp = TempFile::scratch()?;
+        {
+            let mut writer = io::BufWriter::new(temp.file());
+            io::copy(&mut compression.decoder(reader)?, &mut writer)?;
+            writer.flush()?;
+        }
+        Ok(InputFile {
+            data: MappedFile::open(temp.path())?,
+            _temp: Some(temp),
+            compression: Some(compression),
+        })
+    }
+
+    /// The compression of file, `None` if not decompressed.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+}
+
+impl Deref for InputFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
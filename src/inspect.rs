@@ -0,0 +1,423 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Error, ErrorKind, Read, Result, Write};
+use std::ops::Range;
+
+use super::bspatch::Bspatch;
+use super::codec::Decoder;
+use super::format::{Format, Header};
+use super::utils::*;
+
+/// Kind of target regions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionKind {
+    /// Target bytes are source bytes plus delta.
+    Delta,
+
+    /// Target bytes are copied from the extra section, i.e. new data.
+    Extra,
+
+    /// Target bytes are copied from earlier target bytes, see
+    /// `Bsdiff::target_copy`.
+    Repeat,
+}
+
+impl RegionKind {
+    /// Name of the kind used in exports.
+    pub fn name(self) -> &'static str {
+        match self {
+            RegionKind::Delta => "delta",
+            RegionKind::Extra => "extra",
+            RegionKind::Repeat => "repeat",
+        }
+    }
+}
+
+/// Mapping of a target region to where it comes from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    /// Kind of the region.
+    pub kind: RegionKind,
+
+    /// Source range of delta regions, `None` for extra and repeat regions.
+    pub source: Option<Range<u64>>,
+
+    /// Target range.
+    pub target: Range<u64>,
+
+    /// Number of target bytes differing from the source bytes (always the
+    /// length of extra regions, and zero for repeat regions).
+    pub changed: u64,
+}
+
+/// Summary of a patch checked by `validate`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Validation {
+    /// Container format of the patch.
+    pub format: Format,
+
+    /// Size of the target.
+    pub target_size: u64,
+
+    /// Number of controls.
+    pub controls: u64,
+
+    /// Decompressed sizes of the control, delta and extra sections.
+    pub section_sizes: [u64; 3],
+
+    /// End of the source range read by the patch, i.e. the minimum size of
+    /// source.
+    pub source_span: u64,
+
+    /// Whether the checksums of the sections were verified (extended patches
+    /// carrying checksums only).
+    pub section_checksums: bool,
+
+    /// Whether the patch was applied to the source, verifying the checksums
+    /// of source windows if any.
+    pub source_checked: bool,
+}
+
+/// Check the patch deeply without producing the target.
+///
+/// The header is validated and the section checksums (if any) verified, then
+/// all sections are decompressed as a whole, and every control is checked to
+/// stay within the target size and the sections, with no trailing data left
+/// (matching the decoded section sizes if recorded). If the source is given, controls are bounds checked against it, and the
+/// patch is applied to it, verifying the checksums of source windows (see
+/// `Bsdiff::source_checksum`).
+///
+/// Example:
+///
+/// Reject a broken patch before it is shipped:
+/// ```
+/// use std::io;
+/// use qbsdiff::{inspect, Bsdiff};
+///
+/// fn release(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let patch = Bsdiff::new(source, target).compare_to_vec()?;
+///     let summary = inspect::validate(&patch, Some(source))?;
+///     assert_eq!(summary.target_size, target.len() as u64);
+///     Ok(patch)
+/// }
+/// ```
+///
+/// Return `ErrorKind::InvalidData` if the patch is corrupted, or
+/// `ErrorKind::InvalidInput` if the controls read beyond the source.
+/// Errors of mismatching source windows are `SourceCorruption`.
+pub fn validate(patch: &[u8], source: Option<&[u8]>) -> Result<Validation> {
+    let header = Header::parse(patch)?;
+    let (ctrls, delta, extra) = header.sections(patch);
+    let [ccodec, dcodec, ecodec] = header.codecs;
+    let (mut ctrls, mut delta, mut extra) = match header.block_sizes(patch)? {
+        Some([csizes, dsizes, esizes]) => (
+            Decoder::blocks(ccodec, ctrls, header.block_size, csizes, 0)?,
+            Decoder::blocks(dcodec, delta, header.block_size, dsizes, 0)?,
+            Decoder::blocks(ecodec, extra, header.block_size, esizes, 0)?,
+        ),
+        None => (
+            Decoder::new(ccodec, ctrls)?,
+            Decoder::new(dcodec, delta)?,
+            Decoder::new(ecodec, extra)?,
+        ),
+    };
+    let window = Some(1u64 << header.window_log).filter(|_| header.has_target_copy());
+
+    let mut summary = Validation {
+        format: header.format,
+        target_size: header.tsize,
+        controls: 0,
+        section_sizes: [0; 3],
+        source_span: 0,
+        section_checksums: header.has_checksum(),
+        source_checked: false,
+    };
+    let mut ctl = [0; 40];
+    let (mut spos, mut tpos) = (0i64, 0u64);
+    while header
+        .control_layout()
+        .read(&mut ctrls, &mut ctl)
+        .map_err(eof_corrupted)?
+    {
+        let add = decode_int(&ctl[0..]);
+        let copy = decode_int(&ctl[8..]);
+        let seek = decode_int(&ctl[16..]);
+        let (tcopy, tdist) = match window {
+            Some(_) => (decode_int(&ctl[24..]), decode_int(&ctl[32..])),
+            None => (0, 0),
+        };
+        if add < 0 || copy < 0 || tcopy < 0 || (add > 0 && spos < 0) {
+            return Err(corrupted());
+        }
+        let len = (add as u64).saturating_add(copy as u64).saturating_add(tcopy as u64);
+        if len > header.tsize - tpos {
+            return Err(corrupted());
+        }
+
+        let send = spos.checked_add(add).ok_or_else(corrupted)?;
+        if add > 0 {
+            summary.source_span = Ord::max(summary.source_span, send as u64);
+            if source.is_some_and(|source| send as u64 > source.len() as u64) {
+                return Err(Error::new(ErrorKind::InvalidInput, "control out of bounds of source"));
+            }
+        }
+        for (data, n) in [(&mut delta, add as u64), (&mut extra, copy as u64)] {
+            if io::copy(&mut data.take(n), &mut io::sink())? != n {
+                return Err(corrupted());
+            }
+        }
+        tpos += add as u64 + copy as u64;
+        if tcopy > 0 && (tdist <= 0 || tdist as u64 > tpos || Some(tdist as u64) > window) {
+            return Err(corrupted());
+        }
+        tpos += tcopy as u64;
+
+        spos = send.checked_add(seek).ok_or_else(corrupted)?;
+        summary.controls += 1;
+        let values = [add, copy, seek, tcopy, tdist];
+        summary.section_sizes[0] += header
+            .control_layout()
+            .encode(values, &mut [0; ControlLayout::MAX_SIZE]) as u64;
+        summary.section_sizes[1] += add as u64;
+        summary.section_sizes[2] += copy as u64;
+    }
+    if tpos != header.tsize || delta.read(&mut [0])? > 0 || extra.read(&mut [0])? > 0 {
+        return Err(corrupted());
+    }
+    if header
+        .decoded_size_hint()
+        .is_some_and(|decoded| decoded != summary.section_sizes)
+    {
+        return Err(corrupted());
+    }
+
+    if let Some(source) = source {
+        Bspatch::new(patch)?.apply(source, io::sink())?;
+        summary.source_checked = true;
+    }
+    Ok(summary)
+}
+
+/// Map each control of the patch to target regions.
+///
+/// Every control produces a delta region, an extra region and a repeat
+/// region, empty regions are omitted.
+///
+/// Example:
+///
+/// Export the region mapping for visualization tools:
+/// ```
+/// use std::io;
+/// use qbsdiff::inspect;
+///
+/// fn export(patch: &[u8]) -> io::Result<String> {
+///     let regions = inspect::regions(patch)?;
+///     let mut json = Vec::new();
+///     inspect::write_json(&regions, &mut json)?;
+///     Ok(String::from_utf8(json).unwrap())
+/// }
+/// ```
+pub fn regions(patch: &[u8]) -> Result<Vec<Region>> {
+    let header = Header::parse(patch)?;
+    let (ctrls, delta, _) = header.sections(patch);
+    let [ccodec, dcodec, _] = header.codecs;
+    let mut ctrls = Decoder::new(ccodec, ctrls)?;
+    let mut delta = Decoder::new(dcodec, delta)?;
+
+    let mut regions = Vec::new();
+    let mut ctl = [0; 40];
+    let mut buf = vec![0; 4096];
+    let (mut spos, mut tpos) = (0i64, 0u64);
+    while header.control_layout().read(&mut ctrls, &mut ctl)? {
+        let add = decode_int(&ctl[0..]);
+        let copy = decode_int(&ctl[8..]);
+        let seek = decode_int(&ctl[16..]);
+        let tcopy = if header.has_target_copy() {
+            decode_int(&ctl[24..])
+        } else {
+            0
+        };
+        if add < 0 || copy < 0 || tcopy < 0 || spos < 0 {
+            return Err(corrupted());
+        }
+
+        if add > 0 {
+            let mut changed = 0;
+            let mut n = add as u64;
+            while n > 0 {
+                let k = Ord::min(n, buf.len() as u64) as usize;
+                delta.read_exact(&mut buf[..k])?;
+                changed += buf[..k].iter().filter(|&&d| d != 0).count() as u64;
+                n -= k as u64;
+            }
+            let send = spos.checked_add(add).ok_or_else(corrupted)?;
+            let tend = tpos.checked_add(add as u64).ok_or_else(corrupted)?;
+            regions.push(Region {
+                kind: RegionKind::Delta,
+                source: Some(spos as u64..send as u64),
+                target: tpos..tend,
+                changed,
+            });
+            spos = send;
+            tpos = tend;
+        }
+        if copy > 0 {
+            let tend = tpos.checked_add(copy as u64).ok_or_else(corrupted)?;
+            regions.push(Region {
+                kind: RegionKind::Extra,
+                source: None,
+                target: tpos..tend,
+                changed: copy as u64,
+            });
+            tpos = tend;
+        }
+        if tcopy > 0 {
+            let tend = tpos.checked_add(tcopy as u64).ok_or_else(corrupted)?;
+            regions.push(Region {
+                kind: RegionKind::Repeat,
+                source: None,
+                target: tpos..tend,
+                changed: 0,
+            });
+            tpos = tend;
+        }
+        spos = spos.checked_add(seek).ok_or_else(corrupted)?;
+    }
+    Ok(regions)
+}
+
+/// Compute the source ranges read by patching, sorted and merged, from the
+/// patch alone.
+///
+/// This is the union of the source ranges of delta regions (see `regions`),
+/// thus updaters could fetch only the needed parts of a remote source by
+/// range requests, then apply the patch to a sparse copy of it.
+/// Only the control section is decoded.
+///
+/// If the patch carries checksums of source windows (see
+/// `Bsdiff::source_checksum`), each window touched is verified as a whole,
+/// thus the ranges are widened to whole windows.
+///
+/// Example:
+///
+/// Count the source bytes to download:
+/// ```
+/// use std::io;
+/// use qbsdiff::inspect;
+///
+/// fn needed(patch: &[u8]) -> io::Result<u64> {
+///     let ranges = inspect::source_ranges(patch)?;
+///     Ok(ranges.iter().map(|range| range.end - range.start).sum())
+/// }
+/// ```
+///
+/// Return error if the patch is corrupted.
+pub fn source_ranges(patch: &[u8]) -> Result<Vec<Range<u64>>> {
+    let header = Header::parse(patch)?;
+    let (ctrls, _, _) = header.sections(patch);
+    let mut ctrls = Decoder::new(header.codecs[0], ctrls)?;
+
+    let mut ranges = Vec::new();
+    let mut ctl = [0; 40];
+    let mut spos = 0i64;
+    while header
+        .control_layout()
+        .read(&mut ctrls, &mut ctl)
+        .map_err(eof_corrupted)?
+    {
+        let add = decode_int(&ctl[0..]);
+        let seek = decode_int(&ctl[16..]);
+        if add < 0 || (add > 0 && spos < 0) {
+            return Err(corrupted());
+        }
+        let send = spos.checked_add(add).ok_or_else(corrupted)?;
+        if add > 0 {
+            ranges.push(spos as u64..send as u64);
+        }
+        spos = send.checked_add(seek).ok_or_else(corrupted)?;
+    }
+
+    if header.has_source_checksum() && header.swindow > 0 {
+        let window = header.swindow;
+        for range in ranges.iter_mut() {
+            let end = range.end.div_ceil(window).saturating_mul(window);
+            *range = range.start / window * window..Ord::max(Ord::min(end, header.ssize), range.end);
+        }
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = Ord::max(last.end, range.end),
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+/// Serialize regions as a JSON array of objects with fields `kind`,
+/// `source_start`, `source_end` (`null` for extra and repeat regions), `target_start`,
+/// `target_end` and `changed`.
+pub fn write_json<W: Write>(regions: &[Region], mut w: W) -> Result<()> {
+    w.write_all(b"[")?;
+    for (i, region) in regions.iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        let (start, end) = match region.source {
+            Some(ref source) => (source.start.to_string(), source.end.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        write!(
+            w,
+            "\n  {{\"kind\": \"{}\", \"source_start\": {}, \"source_end\": {}, \"target_start\": {}, \"target_end\": {}, \"changed\": {}}}",
+            region.kind.name(),
+            start,
+            end,
+            region.target.start,
+            region.target.end,
+            region.changed,
+        )?;
+    }
+    w.write_all(b"\n]\n")?;
+    w.flush()
+}
+
+/// Serialize regions as CSV with header
+/// `kind,source_start,source_end,target_start,target_end,changed`, source
+/// fields are empty for extra and repeat regions.
+pub fn write_csv<W: Write>(regions: &[Region], mut w: W) -> Result<()> {
+    writeln!(w, "kind,source_start,source_end,target_start,target_end,changed")?;
+    for region in regions.iter() {
+        let (start, end) = match region.source {
+            Some(ref source) => (source.start.to_string(), source.end.to_string()),
+            None => (String::new(), String::new()),
+        };
+        writeln!(
+            w,
+            "{},{},{},{},{},{}",
+            region.kind.name(),
+            start,
+            end,
+            region.target.start,
+            region.target.end,
+            region.changed,
+        )?;
+    }
+    w.flush()
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch corrupted")
+}
+
+fn eof_corrupted(e: Error) -> Error {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        corrupted()
+    } else {
+        e
+    }
+}
@@ -0,0 +1,209 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Error, ErrorKind, Read, Result, Write};
+
+use byteorder::{ByteOrder, LE};
+
+use super::bspatch::BUFFER_SIZE;
+use super::codec::{Codec, Decoder, Encoder, StreamDecoder};
+use super::format::Header;
+use super::utils::*;
+
+/// Magic number bytes of interleaved patch files.
+pub const INTERLEAVED_MAGIC: &[u8] = b"QBSDIFF3";
+
+/// Size of the header of interleaved patch files.
+const INTERLEAVED_HEADER_SIZE: usize = 24;
+
+/// Size of controls in interleaved patch files.
+const CONTROL_SIZE: usize = 24;
+
+/// Convert a classic bsdiff 4.x or extended patch into the interleaved
+/// layout, compressed with the codec and compression level.
+///
+/// The interleaved layout consists of magic `QBSDIFF3`, feature flags (u32,
+/// always zero for now), the codec (one byte), three reserved zero bytes and
+/// the target size (u64), all integers in little endian, followed by a single
+/// compressed stream of records.
+/// Each record is a control (add, copy and seek, encoded as in the classic
+/// format), followed by the delta data of `add` bytes and the extra data of
+/// `copy` bytes.
+///
+/// Unlike the classic layout holding controls, delta and extra data in three
+/// sections, interleaved patches are applied by `apply_interleaved` in a
+/// single pass over a non-seekable stream with a single decompressor.
+/// Checksums of sections are verified, then dropped as well as the checksums
+/// of source windows and the metadata.
+///
+/// Target-relative copies (see `Bsdiff::target_copy`) are not supported by
+/// the interleaved layout, converting such patches fails with
+/// `ErrorKind::Unsupported`.
+///
+/// Example:
+///
+/// Prepare a patch for streaming over the network:
+/// ```
+/// use std::io;
+/// use qbsdiff::interleave::{apply_interleaved, interleave};
+/// use qbsdiff::{Bsdiff, Codec};
+///
+/// fn roundtrip(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let patch = Bsdiff::new(source, target).compare_to_vec()?;
+///     let mut stream = Vec::new();
+///     interleave(&patch[..], Codec::Bzip2, 6, &mut stream)?;
+///
+///     let mut patched = Vec::new();
+///     apply_interleaved(source, &stream[..], &mut patched)?;
+///     Ok(patched)
+/// }
+/// ```
+///
+/// Return error if the patch is corrupted.
+/// The size of the interleaved patch would be returned if no error occurs.
+pub fn interleave<W: Write>(patch: &[u8], codec: Codec, level: u32, mut out: W) -> Result<u64> {
+    let header = Header::parse(patch)?;
+    if header.has_target_copy() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "target-relative copies are not supported by the interleaved layout",
+        ));
+    }
+    let (ctrls, delta, extra) = header.sections(patch);
+    let [ccodec, dcodec, ecodec] = header.codecs;
+    let mut ctrls = Decoder::new(ccodec, ctrls)?;
+    let mut delta = Decoder::new(dcodec, delta)?;
+    let mut extra = Decoder::new(ecodec, extra)?;
+
+    let mut head = [0; INTERLEAVED_HEADER_SIZE];
+    head[..8].copy_from_slice(INTERLEAVED_MAGIC);
+    head[12] = codec.id();
+    LE::write_u64(&mut head[16..24], header.tsize);
+    let mut counter = Counter::new(&mut out);
+    counter.write_all(&head[..])?;
+
+    let mut encoder = Encoder::new(codec, level, counter)?;
+    let layout = header.control_layout();
+    let mut ctl = [0; 40];
+    let mut total = 0u64;
+    while layout.read(&mut ctrls, &mut ctl)? {
+        let add = decode_int(&ctl[0..8]);
+        let copy = decode_int(&ctl[8..16]);
+        if add < 0 || copy < 0 {
+            return Err(corrupted());
+        }
+        encoder.write_all(&ctl[..CONTROL_SIZE])?;
+        for (data, len) in [(&mut delta, add as u64), (&mut extra, copy as u64)] {
+            if io::copy(&mut data.take(len), &mut encoder)? != len {
+                return Err(corrupted());
+            }
+        }
+        total = total.saturating_add(add as u64).saturating_add(copy as u64);
+    }
+    if total != header.tsize {
+        return Err(corrupted());
+    }
+    let counter = encoder.finish()?;
+    let size = counter.count;
+    counter.inner.flush()?;
+    Ok(size)
+}
+
+/// Apply an interleaved patch (see `interleave`) read from a stream to the
+/// source data, and output the stream of target.
+///
+/// The patch is read sequentially in a single pass, thus it could be
+/// streamed from a pipe or a socket without buffering it as a whole, and
+/// memory usage is bounded by a fixed buffer plus the decompressor state.
+///
+/// Return error if the patch is corrupted.
+/// The target data size would be returned if no error occurs.
+pub fn apply_interleaved<R: Read, T: Write>(source: &[u8], mut patch: R, mut target: T) -> Result<u64> {
+    let mut head = [0; INTERLEAVED_HEADER_SIZE];
+    patch
+        .read_exact(&mut head[..])
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "not a valid patch"))?;
+    if &head[..8] != INTERLEAVED_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a valid patch"));
+    }
+    if LE::read_u32(&head[8..12]) != 0 || head[13..16] != [0; 3] {
+        return Err(Error::new(ErrorKind::Unsupported, "unsupported patch features"));
+    }
+    let codec = Codec::from_id(head[12]).ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown codec"))?;
+    let tsize = LE::read_u64(&head[16..24]);
+    let mut records = StreamDecoder::new(codec, patch)?;
+
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut ctl = [0; CONTROL_SIZE];
+    let mut spos = 0u64;
+    let mut total = 0u64;
+    while total < tsize {
+        records.read_exact(&mut ctl[..]).map_err(eof_corrupted)?;
+        let add = decode_int(&ctl[0..8]);
+        let copy = decode_int(&ctl[8..16]);
+        let seek = decode_int(&ctl[16..24]);
+        if add < 0 || copy < 0 || (add as u64).saturating_add(copy as u64) > tsize - total {
+            return Err(corrupted());
+        }
+
+        // Add delta to source.
+        let end = spos.checked_add(add as u64).filter(|&end| end <= source.len() as u64);
+        let end = end.ok_or_else(corrupted)?;
+        for old in source[spos as usize..end as usize].chunks(buf.len()) {
+            let new = &mut buf[..old.len()];
+            records.read_exact(new).map_err(eof_corrupted)?;
+            for (y, &x) in new.iter_mut().zip(old) {
+                *y = y.wrapping_add(x);
+            }
+            target.write_all(new)?;
+        }
+
+        // Copy extra data.
+        if io::copy(&mut (&mut records).take(copy as u64), &mut target)? != copy as u64 {
+            return Err(corrupted());
+        }
+
+        total += add as u64 + copy as u64;
+        spos = (end as i64)
+            .checked_add(seek)
+            .and_then(|spos| u64::try_from(spos).ok())
+            .ok_or_else(corrupted)?;
+    }
+    target.flush()?;
+    Ok(total)
+}
+
+/// Writer counting the bytes written.
+struct Counter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Counter<W> {
+    fn new(inner: W) -> Self {
+        Counter { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch corrupted")
+}
+
+fn eof_corrupted(e: Error) -> Error {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        corrupted()
+    } else {
+        e
+    }
+}
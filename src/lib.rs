@@ -53,15 +53,133 @@ fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
 }
 ```
 
+To update a file in place, only if the patched file matches the expected hash
+and with rollback on any failure, see `apply_verified` (feature `mmap`).
+
 Note that `qbsdiff` would not generate exactly the same patch file as `bsdiff`.
 Only the patch file format is promised to be compatible.
+
+Extended format
+---------------
+
+Patches in the extended format (`Format::Extended`) may use other codecs than
+bzip2 for each section, e.g. `Codec::Stored` for latency-critical patching or
+`Codec::Zstd` (feature `zstd`).
+These patches are only recognized by `qbspatch`, and `Bspatch` detects the
+format and codecs automatically.
+Sections could be protected by checksums (`Bsdiff::checksum`) of CRC-32,
+CRC-32C, XXH3 (feature `xxh3`) or SHA-256 (feature `sha256`), and patchers
+could require a minimum strength of them (`Bspatch::require_checksum`).
+
+Bzip2 backends
+--------------
+
+Bzip2 is backed by the C libbz2 (feature `libbz2`, enabled by default).
+To build without C dependencies (e.g. static musl builds or wasm), disable the
+default features and enable `bzip2-rs`, a pure Rust port of libbz2 producing
+the same output:
+```toml
+qbsdiff = { version = "1", default-features = false, features = ["bzip2-rs"] }
+```
+
+Parallelism
+-----------
+
+Searching is parallelized with rayon (feature `parallel`, enabled by default).
+For environments forbidding extra threads (e.g. plugins or sandboxes), disable
+the default features to remove rayon entirely, where any `ParallelScheme` is
+accepted but degraded to `ParallelScheme::Never`:
+```toml
+qbsdiff = { version = "1", default-features = false, features = ["libbz2"] }
+```
+
+C ABI
+-----
+
+Feature `capi` exposes a stable C ABI with streaming callbacks (see `capi` and
+`capi/qbsdiff.h`), for embedding into C/C++ updaters, built by:
+```shell
+$ cargo rustc --release --lib --features capi --crate-type staticlib
+```
+
+Panics
+------
+
+Library code does not panic on any patch data, source data or settings,
+however malformed or adversarial: corrupted patches are reported as
+`ErrorKind::InvalidData`, unsupported patches as `ErrorKind::Unsupported` and
+bad arguments as `ErrorKind::InvalidInput`.
+The only exception is indexing source data greater than MAX_LENGTH with
+`Bsdiff::new` or `SourceIndex::new`, see `Bsdiff::try_new` and
+`SourceIndex::try_new` for the fallible variants.
+This contract is exercised by randomly mutated patches in `tests/no_panic.rs`.
  */
 
-#![forbid(unsafe_code)]
+#![cfg_attr(not(any(feature = "mmap", feature = "capi")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "mmap", feature = "capi"), deny(unsafe_code))]
+
+#[cfg(not(any(feature = "libbz2", feature = "bzip2-rs")))]
+compile_error!("either feature `libbz2` or `bzip2-rs` is required for bzip2");
 
-pub use bsdiff::{Bsdiff, ParallelScheme};
-pub use bspatch::Bspatch;
+pub use bsdiff::{
+    tuning_info, Allocation, AllocationHook, AnchorFinder, Bsdiff, ChunkControls, Control, ParallelScheme, Scoring,
+    TuningInfo,
+};
+pub use bspatch::{
+    ApplyStats, Bspatch, ControlFault, CorruptControl, SectionCorruption, SourceCorruption, SourceOverrun, Stall,
+    TargetSizeMismatch, Tolerance,
+};
+pub use checksum::{ChecksumKind, Strength};
+pub use codec::{Codec, CodecPriority};
+pub use feeder::TargetFeeder;
+pub use format::{Capabilities, Format, PartialPatch, PatchError, PatchMetadata, PatchSizes, Section};
+pub use index::{similarity, SourceIndex};
+pub use profile::{DiffProfile, PatchProfile};
+pub use reader::PatchedReader;
+pub use report::{Anomaly, DiffReport, MatchHistogram};
+pub use sidecar::Sidecar;
+pub use source::{SourcePreload, SourceRead};
+pub use transcode::transcode;
+#[cfg(feature = "mmap")]
+pub use verified::{apply_verified, TargetHashMismatch};
 
+pub mod archive;
+pub mod batch;
 pub mod bsdiff;
 pub mod bspatch;
+pub mod bundle;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "cdc")]
+pub mod cdc;
+pub mod checksum;
+pub mod codec;
+pub mod conformance;
+mod dedupe;
+pub mod diagnostics;
+pub mod differential;
+pub mod feeder;
+#[cfg(feature = "mmap")]
+mod files;
+pub mod format;
+pub mod golden;
+pub mod index;
+#[cfg(feature = "mmap")]
+pub mod input;
+pub mod inspect;
+pub mod interleave;
+mod lines;
+pub mod profile;
+pub mod reader;
+#[cfg(feature = "gzip")]
+pub mod recompress;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod report;
+mod sais;
+pub mod sidecar;
+pub mod source;
+pub mod transcode;
 mod utils;
+#[cfg(feature = "mmap")]
+pub mod verified;
@@ -0,0 +1,250 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Min length of lines matched away from the expected source line, shorter
+/// lines (e.g. blank lines or closing brackets) are too common to relocate.
+const MIN_LINE: usize = 8;
+
+/// Run of target lines (or segments), either copied from consecutive source
+/// lines or changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct LineRun {
+    /// Bytes of the target lines.
+    pub target: Range<usize>,
+
+    /// Start of the matched source lines, `None` if changed.
+    pub source: Option<usize>,
+}
+
+/// Match target lines (terminated by `\n`) against identical source lines.
+///
+/// Target lines are matched greedily in order, preferring the source line
+/// after the previous match, then the first identical source line after it,
+/// then the first identical source line anywhere, thus inserted, deleted and
+/// moved lines of sorted or append-mostly data only break the runs locally.
+/// Returned runs cover the whole target in order.
+pub(crate) fn match_lines(source: &[u8], target: &[u8]) -> Vec<LineRun> {
+    match_segments(source, &split_lines(source), target, &split_lines(target))
+}
+
+/// Match target segments against identical source segments, like
+/// `match_lines`.
+pub(crate) fn match_segments(
+    source: &[u8],
+    slines: &[Range<usize>],
+    target: &[u8],
+    tlines: &[Range<usize>],
+) -> Vec<LineRun> {
+    let mut table: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, line) in slines.iter().enumerate() {
+        table.entry(&source[line.clone()]).or_default().push(i);
+    }
+
+    let mut runs: Vec<LineRun> = Vec::new();
+    let mut next = 0;
+    for tline in tlines.iter().cloned() {
+        let bytes = &target[tline.clone()];
+        let found = if slines.get(next).is_some_and(|s| &source[s.clone()] == bytes) {
+            Some(next)
+        } else if bytes.len() >= MIN_LINE {
+            table.get(bytes).map(|indices| {
+                let i = indices.partition_point(|&i| i < next);
+                indices.get(i).copied().unwrap_or(indices[0])
+            })
+        } else {
+            None
+        };
+
+        let spos = found.map(|i| slines[i].start);
+        if let Some(i) = found {
+            next = i + 1;
+        }
+        match runs.last_mut() {
+            Some(last) if last.source.is_none() && spos.is_none() => last.target.end = tline.end,
+            Some(LineRun {
+                target,
+                source: Some(start),
+            }) if spos == Some(*start + target.len()) => target.end = tline.end,
+            _ => runs.push(LineRun {
+                target: tline,
+                source: spos,
+            }),
+        }
+    }
+    runs
+}
+
+/// Piece of changed target lines.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Piece {
+    /// Bytes of source at the position plus delta.
+    Delta(usize, usize),
+
+    /// Bytes from the extra section.
+    Extra(usize),
+}
+
+/// Diff changed target lines record by record against the source lines in
+/// `gap`, i.e. the source lines between the neighbouring matches.
+///
+/// Each target line is paired in order with the most similar source line (by
+/// common prefix and suffix) in the gap, among those keeping the rest of
+/// target lines pairable, or otherwise the previously paired source line
+/// (e.g. the neighbouring record of an inserted record in sorted data).
+/// The paired lines are delta if of the same length, otherwise the common
+/// prefix and suffix are delta, and so is the middle if mostly equal, the
+/// rest of target lines is extra.
+pub(crate) fn diff_changed(source: &[u8], gap: Range<usize>, target: &[u8]) -> Vec<Piece> {
+    let slines: Vec<Range<usize>> = split_lines(&source[gap.clone()])
+        .into_iter()
+        .map(|line| line.start + gap.start..line.end + gap.start)
+        .collect();
+    let prev = Some(gap.start)
+        .filter(|&end| end > 0)
+        .map(|end| source[..end - 1].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1)..end);
+    diff_segments(source, &slines, prev, target, &split_lines(target))
+}
+
+/// Diff the changed target segments `run` against the source segments in
+/// `gap`, like `diff_changed`.
+pub(crate) fn diff_changed_segments(
+    source: &[u8],
+    ssegments: &[Range<usize>],
+    gap: Range<usize>,
+    target: &[u8],
+    tsegments: &[Range<usize>],
+    run: Range<usize>,
+) -> Vec<Piece> {
+    let slines = clip(ssegments, gap.clone());
+    let prev = ssegments
+        .get(ssegments.partition_point(|seg| seg.end < gap.start))
+        .filter(|seg| seg.start < gap.start)
+        .map(|seg| seg.start..gap.start);
+    let tlines: Vec<Range<usize>> = clip(tsegments, run.clone())
+        .into_iter()
+        .map(|seg| seg.start - run.start..seg.end - run.start)
+        .collect();
+    diff_segments(source, &slines, prev, &target[run], &tlines)
+}
+
+/// Diff target segments record by record against the source segments, see
+/// `diff_changed`.
+fn diff_segments(
+    source: &[u8],
+    slines: &[Range<usize>],
+    mut prev: Option<Range<usize>>,
+    target: &[u8],
+    tlines: &[Range<usize>],
+) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut next = 0;
+    for (i, tline) in tlines.iter().enumerate() {
+        let t = &target[tline.clone()];
+        let slack = (slines.len() - next).saturating_sub(tlines.len() - i);
+        let mut best = None;
+        let mut best_score = 0;
+        for j in next..Ord::min(next + slack + 1, slines.len()) {
+            let score = similarity(&source[slines[j].clone()], t);
+            if score > best_score {
+                best = Some(j);
+                best_score = score;
+            }
+        }
+        let sline = match (best, &prev) {
+            (Some(_), Some(p)) if similarity(&source[p.clone()], t) > best_score => p.clone(),
+            (Some(j), _) => {
+                next = j + 1;
+                prev = Some(slines[j].clone());
+                slines[j].clone()
+            }
+            (None, Some(p)) if similarity(&source[p.clone()], t) > 0 => p.clone(),
+            (None, _) => {
+                pieces.push(Piece::Extra(t.len()));
+                continue;
+            }
+        };
+
+        let s = &source[sline.clone()];
+        if s.len() == t.len() {
+            pieces.push(Piece::Delta(sline.start, t.len()));
+            continue;
+        }
+        let (prefix, suffix) = common(s, t);
+        let (smid, tmid) = (&s[prefix..s.len() - suffix], &t[prefix..t.len() - suffix]);
+        let equal = smid.iter().zip(tmid).filter(|(x, y)| x == y).count();
+        let delta = if equal * 2 >= Ord::min(smid.len(), tmid.len()) {
+            prefix + Ord::min(smid.len(), tmid.len())
+        } else {
+            prefix
+        };
+        if delta > 0 {
+            pieces.push(Piece::Delta(sline.start, delta));
+        }
+        if t.len() > delta + suffix {
+            pieces.push(Piece::Extra(t.len() - delta - suffix));
+        }
+        if suffix > 0 {
+            pieces.push(Piece::Delta(sline.end - suffix, suffix));
+        }
+    }
+    pieces
+}
+
+/// Length of common prefix plus common suffix.
+fn similarity(a: &[u8], b: &[u8]) -> usize {
+    let (prefix, suffix) = common(a, b);
+    prefix + suffix
+}
+
+/// Lengths of common prefix and common suffix, not overlapping.
+fn common(a: &[u8], b: &[u8]) -> (usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (prefix, suffix)
+}
+
+/// Split data into lines, each including its `\n` terminator except the last.
+fn split_lines(data: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, _) in data.iter().enumerate().filter(|&(_, &b)| b == b'\n') {
+        lines.push(start..i + 1);
+        start = i + 1;
+    }
+    if start < data.len() {
+        lines.push(start..data.len());
+    }
+    lines
+}
+
+/// Parts of the sorted segments within the range.
+fn clip(segments: &[Range<usize>], range: Range<usize>) -> Vec<Range<usize>> {
+    let first = segments.partition_point(|seg| seg.end <= range.start);
+    segments[first..]
+        .iter()
+        .take_while(|seg| seg.start < range.end)
+        .map(|seg| Ord::max(seg.start, range.start)..Ord::min(seg.end, range.end))
+        .collect()
+}
+
+/// Split data of the size at the anchors (ignoring those out of bounds) into
+/// segments.
+pub(crate) fn split_anchors(size: usize, mut anchors: Vec<usize>) -> Vec<Range<usize>> {
+    anchors.retain(|&pos| pos > 0 && pos < size);
+    anchors.sort_unstable();
+    anchors.dedup();
+    let mut segments = Vec::with_capacity(anchors.len() + 1);
+    let mut start = 0;
+    for pos in anchors.into_iter().chain(Some(size).filter(|&size| size > 0)) {
+        segments.push(start..pos);
+        start = pos;
+    }
+    segments
+}
@@ -0,0 +1,251 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Result};
+
+use super::bsdiff::{self, ParallelScheme};
+use super::bspatch;
+use super::checksum::ChecksumKind;
+use super::codec::{Codec, CodecPriority};
+use super::format::{Format, MAX_BLOCK_SIZE};
+
+/// Tuning profile of delta compression, see `Bsdiff::profile`.
+///
+/// Profiles capture all the plain settings of `Bsdiff`, so that they could be
+/// shipped as configuration (e.g. JSON with feature `serde`, missing fields
+/// take the defaults) rather than code.
+/// Profiles are plain data, thus could be shared by any threads.
+///
+/// Example:
+///
+/// Apply a validated profile:
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bsdiff, DiffProfile, ParallelScheme};
+///
+/// fn bsdiff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let profile = DiffProfile {
+///         parallel_scheme: ParallelScheme::NumJobs(2),
+///         compression_level: 1,
+///         ..DiffProfile::default()
+///     };
+///     profile.validate()?;
+///
+///     let mut patch = Vec::new();
+///     Bsdiff::new(source, target)
+///         .profile(&profile)
+///         .compare(io::Cursor::new(&mut patch))?;
+///     Ok(patch)
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct DiffProfile {
+    /// See `Bsdiff::parallel_scheme`.
+    pub parallel_scheme: ParallelScheme,
+
+    /// See `Bsdiff::small_match`.
+    pub small_match: usize,
+
+    /// See `Bsdiff::work_limit`, `None` for unbounded.
+    pub work_limit: Option<usize>,
+
+    /// See `Bsdiff::buffer_size`.
+    pub buffer_size: usize,
+
+    /// See `Bsdiff::format`.
+    pub format: Format,
+
+    /// See `Bsdiff::codec`.
+    pub codec: Codec,
+
+    /// See `Bsdiff::compression_level`.
+    pub compression_level: u32,
+
+    /// See `Bsdiff::section_codec`, overriding codecs and levels of the
+    /// control, delta and extra sections in order.
+    pub section_codecs: [Option<(Codec, u32)>; 3],
+
+    /// See `Bsdiff::auto_levels`.
+    pub auto_levels: bool,
+
+    /// See `Bsdiff::auto_codec`.
+    pub auto_codec: Option<CodecPriority>,
+
+    /// See `Bsdiff::target_copy`.
+    pub target_copy: bool,
+
+    /// See `Bsdiff::compact_seek`.
+    pub compact_seek: bool,
+
+    /// See `Bsdiff::decoded_sizes`.
+    pub decoded_sizes: bool,
+
+    /// See `Bsdiff::explicit_sizes`.
+    pub explicit_sizes: bool,
+
+    /// See `Bsdiff::block_size`.
+    pub block_size: usize,
+
+    /// See `Bsdiff::max_add`.
+    pub max_add: usize,
+
+    /// See `Bsdiff::max_copy`.
+    pub max_copy: usize,
+
+    /// See `Bsdiff::micro`.
+    pub micro: usize,
+
+    /// See `Bsdiff::dedupe`.
+    pub dedupe: bool,
+
+    /// See `Bsdiff::line_aware`.
+    pub line_aware: bool,
+
+    /// See `Bsdiff::append_mostly`.
+    pub append_mostly: bool,
+
+    /// See `Bsdiff::metadata`.
+    pub metadata: bool,
+
+    /// See `Bsdiff::source_checksum`.
+    pub source_checksum: usize,
+
+    /// See `Bsdiff::checksum`.
+    pub checksum: Option<ChecksumKind>,
+}
+
+impl Default for DiffProfile {
+    fn default() -> Self {
+        DiffProfile {
+            parallel_scheme: ParallelScheme::Auto,
+            small_match: bsdiff::SMALL_MATCH,
+            work_limit: None,
+            buffer_size: bsdiff::BUFFER_SIZE,
+            format: Format::Classic,
+            codec: Codec::Bzip2,
+            compression_level: bsdiff::COMPRESSION_LEVEL,
+            section_codecs: [None; 3],
+            auto_levels: false,
+            auto_codec: None,
+            target_copy: false,
+            compact_seek: false,
+            decoded_sizes: false,
+            explicit_sizes: false,
+            block_size: 0,
+            max_add: 0,
+            max_copy: 0,
+            micro: 0,
+            dedupe: false,
+            line_aware: false,
+            append_mostly: false,
+            metadata: false,
+            source_checksum: 0,
+            checksum: None,
+        }
+    }
+}
+
+impl DiffProfile {
+    /// Check that the settings are in range and consistent, rather than
+    /// silently adjusted by `Bsdiff` or rejected by `Bsdiff::compare`.
+    pub fn validate(&self) -> Result<()> {
+        use ParallelScheme::*;
+        if matches!(self.parallel_scheme, ChunkSize(0) | NumJobs(0)) {
+            return Err(invalid("parallel chunk size or jobs must be positive"));
+        }
+        if self.work_limit == Some(0) {
+            return Err(invalid("work limit must be positive"));
+        }
+        if self.buffer_size < 128 {
+            return Err(invalid("buffer size must be at least 128"));
+        }
+        let codecs = self
+            .section_codecs
+            .map(|codec| codec.unwrap_or((self.codec, self.compression_level)));
+        if codecs.iter().any(|&(codec, _)| !codec.is_supported()) {
+            return Err(Error::new(ErrorKind::Unsupported, "codec not compiled in"));
+        }
+        if self.format == Format::Classic && codecs.iter().any(|&(codec, _)| codec != Codec::Bzip2) {
+            return Err(invalid("classic bsdiff 4.x format only supports bzip2"));
+        }
+        if self.format == Format::Classic && codecs.iter().any(|&(_, level)| level == 0) {
+            return Err(invalid("compression level 0 (store) requires the extended format"));
+        }
+        if self.format == Format::Classic && (self.target_copy || self.dedupe) {
+            return Err(invalid("target-relative copies require the extended format"));
+        }
+        if self.format == Format::Classic && self.compact_seek {
+            return Err(invalid("compact seeks require the extended format"));
+        }
+        if self.format == Format::Classic && self.decoded_sizes {
+            return Err(invalid("decoded sizes require the extended format"));
+        }
+        if self.format == Format::Classic && self.explicit_sizes {
+            return Err(invalid("explicit sizes require the extended format"));
+        }
+        if self.format == Format::Classic && self.block_size > 0 {
+            return Err(invalid("blocks require the extended format"));
+        }
+        if self.block_size > MAX_BLOCK_SIZE {
+            return Err(invalid("block size is too large"));
+        }
+        if self.format == Format::Classic && self.source_checksum > 0 {
+            return Err(invalid("source checksums require the extended format"));
+        }
+        if self.format == Format::Classic && self.metadata {
+            return Err(invalid("metadata requires the extended format"));
+        }
+        if self.format == Format::Classic && self.auto_codec.is_some() {
+            return Err(invalid("automatic codec selection requires the extended format"));
+        }
+        if self.format == Format::Classic && self.checksum.is_some() {
+            return Err(invalid("checksums require the extended format"));
+        }
+        if self.checksum.is_some_and(|kind| !kind.is_supported()) {
+            return Err(Error::new(ErrorKind::Unsupported, "checksum not compiled in"));
+        }
+        if codecs.iter().any(|&(codec, level)| codec == Codec::Bzip2 && level > 9) {
+            return Err(invalid("bzip2 compression level must be in range 0-9"));
+        }
+        Ok(())
+    }
+}
+
+/// Tuning profile of patching, see `Bspatch::profile`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PatchProfile {
+    /// See `Bspatch::buffer_size`.
+    pub buffer_size: usize,
+
+    /// See `Bspatch::delta_min`.
+    pub delta_min: usize,
+
+    /// See `Bspatch::fuzzy_source`.
+    pub fuzzy_radius: usize,
+}
+
+impl Default for PatchProfile {
+    fn default() -> Self {
+        PatchProfile {
+            buffer_size: bspatch::BUFFER_SIZE,
+            delta_min: bspatch::DELTA_MIN,
+            fuzzy_radius: 0,
+        }
+    }
+}
+
+impl PatchProfile {
+    /// Check that the settings are in range, rather than silently adjusted by
+    /// `Bspatch`.
+    pub fn validate(&self) -> Result<()> {
+        if self.buffer_size < 128 || self.delta_min < 128 {
+            return Err(invalid("buffer sizes must be at least 128"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
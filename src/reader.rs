@@ -0,0 +1,261 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+use super::codec::{Codec, Decoder};
+use super::format::Header;
+use super::utils::*;
+
+/// Random-access reader of the target, materialized lazily from the source and
+/// the patch.
+///
+/// The controls are decoded into an index when created, then target bytes are
+/// produced on demand at any position.
+/// Sections stored uncompressed (`Codec::Stored` in the extended format) are
+/// accessed randomly, while compressed sections are decoded sequentially, thus
+/// seeking backwards in them restarts decoding from the beginning of the
+/// section.
+///
+/// Example:
+///
+/// Read the last 16 bytes of the target:
+/// ```
+/// use std::io::{self, Read, Seek, SeekFrom};
+/// use qbsdiff::PatchedReader;
+///
+/// fn tail(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut reader = PatchedReader::new(source, patch)?;
+///     reader.seek(SeekFrom::End(-16))?;
+///     let mut tail = Vec::new();
+///     reader.read_to_end(&mut tail)?;
+///     Ok(tail)
+/// }
+/// ```
+pub struct PatchedReader<'s, 'p> {
+    source: &'s [u8],
+    index: Vec<Segment>,
+    delta: Section<'p>,
+    extra: Section<'p>,
+    size: u64,
+    pos: u64,
+}
+
+/// Indexed control.
+struct Segment {
+    /// Target position.
+    tpos: u64,
+    /// Source position.
+    spos: i64,
+    /// Position in the decoded delta section.
+    dpos: u64,
+    /// Position in the decoded extra section.
+    epos: u64,
+    add: u64,
+    copy: u64,
+    tcopy: u64,
+    tdist: u64,
+}
+
+impl<'s, 'p> PatchedReader<'s, 'p> {
+    /// Parse the patch file and index its controls.
+    ///
+    /// Return error if failed to parse the patch or the controls are corrupted.
+    pub fn new(source: &'s [u8], patch: &'p [u8]) -> Result<Self> {
+        let header = Header::parse(patch)?;
+        let (ctrls, delta, extra) = header.sections(patch);
+        let [ccodec, dcodec, ecodec] = header.codecs;
+
+        let mut ctrls = Decoder::new(ccodec, ctrls)?;
+        let mut index = Vec::new();
+        let (mut tpos, mut spos, mut dpos, mut epos) = (0u64, 0i64, 0u64, 0u64);
+        let window = 1u64 << header.window_log;
+        let mut ctl = [0; 40];
+        while header.control_layout().read(&mut ctrls, &mut ctl)? {
+            let add = decode_int(&ctl[0..]);
+            let copy = decode_int(&ctl[8..]);
+            let seek = decode_int(&ctl[16..]);
+            let (tcopy, tdist) = if header.has_target_copy() {
+                (decode_int(&ctl[24..]), decode_int(&ctl[32..]))
+            } else {
+                (0, 0)
+            };
+            if add < 0 || copy < 0 || tcopy < 0 {
+                return Err(corrupted());
+            }
+            let (add, copy, tcopy, tdist) = (add as u64, copy as u64, tcopy as u64, tdist as u64);
+            let start = tpos
+                .checked_add(add)
+                .and_then(|n| n.checked_add(copy))
+                .ok_or_else(corrupted)?;
+            if tcopy > 0 && (tdist == 0 || tdist > start || tdist > window) {
+                return Err(corrupted());
+            }
+            let end = start.checked_add(tcopy).ok_or_else(corrupted)?;
+            if end > tpos {
+                index.push(Segment {
+                    tpos,
+                    spos,
+                    dpos,
+                    epos,
+                    add,
+                    copy,
+                    tcopy,
+                    tdist,
+                });
+            }
+            tpos = end;
+            dpos += add;
+            epos += copy;
+            spos = i64::try_from(add)
+                .ok()
+                .and_then(|n| n.checked_add(seek))
+                .and_then(|n| n.checked_add(spos))
+                .ok_or_else(corrupted)?;
+        }
+
+        Ok(PatchedReader {
+            source,
+            index,
+            delta: Section::new(dcodec, delta)?,
+            extra: Section::new(ecodec, extra)?,
+            size: tpos,
+            pos: 0,
+        })
+    }
+
+    /// Size of the target.
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Check whether the target is empty.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Current position in the target.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Resolve target-relative copies, return the segment producing the
+    /// bytes at `pos`, the position in target, and the count of contiguous
+    /// bytes (no more than `len`).
+    fn locate(&self, mut pos: u64, mut len: u64) -> (usize, u64, u64) {
+        loop {
+            let i = self
+                .index
+                .partition_point(|seg| seg.tpos + seg.add + seg.copy + seg.tcopy <= pos);
+            let seg = &self.index[i];
+            let start = seg.tpos + seg.add + seg.copy;
+            if pos < start {
+                return (i, pos, len);
+            }
+
+            // Copied bytes repeat with the period of distance.
+            let offset = (pos - start) % seg.tdist;
+            len = Ord::min(len, start + seg.tcopy - pos);
+            len = Ord::min(len, seg.tdist - offset);
+            pos = start - seg.tdist + offset;
+        }
+    }
+}
+
+impl<'s, 'p> Read for PatchedReader<'s, 'p> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let (i, pos, len) = self.locate(self.pos, buf.len() as u64);
+        let seg = &self.index[i];
+        let offset = pos - seg.tpos;
+
+        let n;
+        if offset < seg.add {
+            n = Ord::min(len, seg.add - offset) as usize;
+            let start = seg.spos.checked_add(offset as i64).ok_or_else(corrupted)?;
+            let src = usize::try_from(start)
+                .ok()
+                .and_then(|s| self.source.get(s..s.checked_add(n)?))
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "source out of range"))?;
+            self.delta.read_at(seg.dpos + offset, &mut buf[..n])?;
+            Iterator::zip(buf[..n].iter_mut(), src.iter()).for_each(|(x, y)| *x = x.wrapping_add(*y));
+        } else {
+            let offset = offset - seg.add;
+            n = Ord::min(len, seg.copy - offset) as usize;
+            self.extra.read_at(seg.epos + offset, &mut buf[..n])?;
+        }
+
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'s, 'p> Seek for PatchedReader<'s, 'p> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.size.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Delta or extra section supporting reads at any position.
+struct Section<'p> {
+    codec: Codec,
+    data: &'p [u8],
+    decoder: Decoder<'p>,
+    pos: u64,
+}
+
+impl<'p> Section<'p> {
+    fn new(codec: Codec, data: &'p [u8]) -> Result<Self> {
+        Ok(Section {
+            codec,
+            data,
+            decoder: Decoder::new(codec, data)?,
+            pos: 0,
+        })
+    }
+
+    /// Read exact bytes at the decoded position.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<()> {
+        if self.codec == Codec::Stored {
+            let chunk = usize::try_from(pos)
+                .ok()
+                .and_then(|p| self.data.get(p..p.checked_add(buf.len())?))
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"))?;
+            buf.copy_from_slice(chunk);
+            return Ok(());
+        }
+
+        if pos < self.pos {
+            self.decoder = Decoder::new(self.codec, self.data)?;
+            self.pos = 0;
+        }
+        // Restart on the next read if any error occurs.
+        let skip = pos - self.pos;
+        self.pos = u64::MAX;
+        if io::copy(&mut (&mut self.decoder).take(skip), &mut io::sink())? != skip {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        self.decoder.read_exact(buf)?;
+        self.pos = pos + buf.len() as u64;
+        Ok(())
+    }
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch corrupted")
+}
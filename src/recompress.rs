@@ -0,0 +1,261 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+
+use byteorder::{ByteOrder, LE};
+use flate2::bufread::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use super::bsdiff::Bsdiff;
+use super::bspatch::Bspatch;
+use super::profile::DiffProfile;
+use super::utils::crc32;
+
+/// Magic number bytes of recompressing patches.
+pub const RECOMPRESS_MAGIC: &[u8] = b"QBSRCMP1";
+
+/// The source is decompressed before patching.
+const FLAG_SOURCE_GZIP: u8 = 1;
+
+/// The target is recompressed after patching.
+const FLAG_TARGET_GZIP: u8 = 2;
+
+/// Size of the gzip trailer (CRC-32 and size of the member).
+const GZIP_TRAILER_SIZE: usize = 8;
+
+/// Parameters reproducing a gzip member byte-exactly from its decompressed
+/// content.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GzipRecipe {
+    /// Gzip header bytes, kept as is (file name, timestamp, etc.).
+    pub header: Vec<u8>,
+
+    /// Compression level of the DEFLATE stream.
+    pub level: u32,
+
+    /// Gzip trailer and any bytes after the member, kept as is.
+    pub tail: Vec<u8>,
+}
+
+impl GzipRecipe {
+    /// Find the parameters reproducing the gzip member, returning the recipe
+    /// and the decompressed content.
+    ///
+    /// Only the DEFLATE encoder of this crate (flate2) is reproduced, at each
+    /// compression level, thus members compressed by other encoders (e.g.
+    /// GNU gzip) may not be reproducible.
+    /// Return `None` if data is not a gzip member or not reproducible.
+    pub fn analyze(data: &[u8]) -> Option<(Self, Vec<u8>)> {
+        let header_size = gzip_header_size(data)?;
+        let (content, body_size) = inflate(&data[header_size..]).ok()?;
+        let tail = &data[header_size + body_size..];
+        if tail.len() < GZIP_TRAILER_SIZE
+            || LE::read_u32(tail) != crc32(0, &content[..])
+            || LE::read_u32(&tail[4..]) != content.len() as u32
+        {
+            return None;
+        }
+
+        let body = &data[header_size..header_size + body_size];
+        let level = (0..=9).find(|&level| deflate(&content[..], level).ok().as_deref() == Some(body))?;
+        let recipe = GzipRecipe {
+            header: data[..header_size].to_vec(),
+            level,
+            tail: tail.to_vec(),
+        };
+        Some((recipe, content))
+    }
+
+    /// Reproduce the gzip member from the decompressed content.
+    ///
+    /// Return `ErrorKind::InvalidData` if the content does not match the
+    /// checksum in the trailer.
+    pub fn reproduce(&self, content: &[u8]) -> Result<Vec<u8>> {
+        if self.tail.len() < GZIP_TRAILER_SIZE
+            || LE::read_u32(&self.tail[..]) != crc32(0, content)
+            || LE::read_u32(&self.tail[4..]) != content.len() as u32
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "recompressed content mismatch"));
+        }
+        let mut data = self.header.clone();
+        data.extend_from_slice(&deflate(content, self.level)?[..]);
+        data.extend_from_slice(&self.tail[..]);
+        Ok(data)
+    }
+}
+
+/// Compare gzip compressed source and target on the decompressed contents,
+/// and produce a patch reconstructing the compressed target byte-exactly.
+///
+/// The target member is analyzed by `GzipRecipe::analyze`, and the parameters
+/// are recorded in the patch, so that the delta does not degenerate into
+/// full-file extras as it does between compressed data.
+/// Inputs not being gzip members are compared as is, and so is the target if
+/// not reproducible, thus any inputs are accepted.
+///
+/// Example:
+///
+/// Diff gzipped tarballs:
+/// ```
+/// use std::io;
+/// use qbsdiff::recompress;
+/// use qbsdiff::DiffProfile;
+///
+/// fn diff(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut patch = Vec::new();
+///     recompress::compare(source, target, &DiffProfile::default(), io::Cursor::new(&mut patch))?;
+///     Ok(patch)
+/// }
+///
+/// fn patch(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut target = Vec::new();
+///     recompress::apply(source, patch, io::Cursor::new(&mut target))?;
+///     Ok(target)
+/// }
+/// ```
+///
+/// The size of patch would be returned if no error occurs.
+pub fn compare<P: Write>(source: &[u8], target: &[u8], profile: &DiffProfile, mut patch: P) -> Result<u64> {
+    let mut flags = 0;
+    let decoded_source = decompress(source);
+    if decoded_source.is_some() {
+        flags |= FLAG_SOURCE_GZIP;
+    }
+    let analyzed = GzipRecipe::analyze(target);
+    if analyzed.is_some() {
+        flags |= FLAG_TARGET_GZIP;
+    }
+
+    let mut header = RECOMPRESS_MAGIC.to_vec();
+    header.push(flags);
+    if let Some((ref recipe, _)) = analyzed {
+        header.push(recipe.level as u8);
+        put_bytes(&mut header, &recipe.header[..]);
+        put_bytes(&mut header, &recipe.tail[..]);
+    }
+    patch.write_all(&header[..])?;
+
+    let source = decoded_source.as_deref().unwrap_or(source);
+    let target = analyzed.as_ref().map(|(_, content)| &content[..]).unwrap_or(target);
+    let size = Bsdiff::new(source, target).try_profile(profile)?.compare(patch)?;
+    Ok(header.len() as u64 + size)
+}
+
+/// Apply patch produced by `compare` to the source, writing the target.
+///
+/// Return error if the patch is corrupted or does not match the source.
+/// The target data size would be returned if no error occurs.
+pub fn apply<W: Write>(source: &[u8], patch: &[u8], mut target: W) -> Result<u64> {
+    if !patch.starts_with(RECOMPRESS_MAGIC) || patch.len() < RECOMPRESS_MAGIC.len() + 1 {
+        return Err(Error::new(ErrorKind::InvalidData, "not a recompressing patch"));
+    }
+    let flags = patch[RECOMPRESS_MAGIC.len()];
+    let mut rest = &patch[RECOMPRESS_MAGIC.len() + 1..];
+    if flags & !(FLAG_SOURCE_GZIP | FLAG_TARGET_GZIP) != 0 {
+        return Err(corrupted());
+    }
+    let recipe = if flags & FLAG_TARGET_GZIP != 0 {
+        let level = *rest.first().ok_or_else(corrupted)? as u32;
+        rest = &rest[1..];
+        let header = take_bytes(&mut rest)?.to_vec();
+        let tail = take_bytes(&mut rest)?.to_vec();
+        if level > 9 {
+            return Err(corrupted());
+        }
+        Some(GzipRecipe { header, level, tail })
+    } else {
+        None
+    };
+
+    let decoded_source;
+    let source = if flags & FLAG_SOURCE_GZIP != 0 {
+        decoded_source =
+            decompress(source).ok_or_else(|| Error::new(ErrorKind::InvalidInput, "source is not gzip compressed"))?;
+        &decoded_source[..]
+    } else {
+        source
+    };
+
+    let bspatch = Bspatch::new(rest)?;
+    match recipe {
+        Some(recipe) => {
+            let mut content = Vec::new();
+            bspatch.apply(source, Cursor::new(&mut content))?;
+            let data = recipe.reproduce(&content[..])?;
+            target.write_all(&data[..])?;
+            Ok(data.len() as u64)
+        }
+        None => bspatch.apply(source, target),
+    }
+}
+
+/// Decompress the gzip member, ignoring bytes after it.
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let header_size = gzip_header_size(data)?;
+    inflate(&data[header_size..]).ok().map(|(content, _)| content)
+}
+
+/// Size of the gzip header (RFC 1952), `None` if not gzip.
+fn gzip_header_size(data: &[u8]) -> Option<usize> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if data.len() < 10 || data[..3] != [0x1f, 0x8b, 8] {
+        return None;
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = LE::read_u16(data.get(pos..pos + 2)?) as usize;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += data.get(pos..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return None;
+    }
+    Some(pos)
+}
+
+/// Decompress the raw DEFLATE stream, returning the content and the size of
+/// stream consumed.
+fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut decoder = DeflateDecoder::new(data);
+    let mut content = Vec::new();
+    decoder.read_to_end(&mut content)?;
+    let rest = decoder.into_inner().len();
+    Ok((content, data.len() - rest))
+}
+
+/// Compress the content as raw DEFLATE stream.
+fn deflate(content: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    let mut len = [0; 4];
+    LE::write_u32(&mut len, bytes.len() as u32);
+    buf.extend_from_slice(&len);
+    buf.extend_from_slice(bytes);
+}
+
+fn take_bytes<'a>(rest: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = LE::read_u32(rest.get(..4).ok_or_else(corrupted)?) as usize;
+    let (bytes, remaining) = rest[4..].split_at_checked(len).ok_or_else(corrupted)?;
+    *rest = remaining;
+    Ok(bytes)
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch corrupted")
+}
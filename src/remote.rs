@@ -0,0 +1,225 @@
+#![forbid(unsafe_code)]
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::ops::Range;
+
+use super::inspect;
+use super::source::SourceRead;
+
+/// Default size of range requests.
+pub const REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// Default number of re-requests of a range after transient errors.
+pub const RETRIES: u32 = 3;
+
+/// Reader of a remote object by byte-range requests, e.g. HTTP `Range`
+/// requests to object storage.
+///
+/// Ranges are requested by the `fetch` callback, which returns the bytes of
+/// the range requested or a prefix of them (as servers may cut responses
+/// short), the rest being re-requested.
+/// Requests failing with transient errors (`ErrorKind::Interrupted`,
+/// `TimedOut`, `WouldBlock`, `ConnectionReset` or `ConnectionAborted`) are
+/// re-requested up to `retries` times.
+///
+/// Implements `SourceRead` to patch a remote source by
+/// `Bspatch::apply_source`, and `Read` and `Seek` to stream a remote patch,
+/// e.g. into `interleave::apply_interleaved`.
+/// Each request fetches up to `request_size` bytes, clipped to the prefetch
+/// hints if any (see `RangeReader::hints`), so that only the source ranges
+/// needed by the patch are downloaded.
+///
+/// Example:
+///
+/// Patch a remote source, downloading only the ranges read by the patch:
+/// ```
+/// use std::io;
+/// use std::ops::Range;
+/// use qbsdiff::remote::RangeReader;
+/// use qbsdiff::Bspatch;
+///
+/// fn update<F>(patch: &[u8], source_size: u64, fetch: F) -> io::Result<Vec<u8>>
+/// where
+///     F: FnMut(Range<u64>) -> io::Result<Vec<u8>>,
+/// {
+///     let source = RangeReader::new(source_size, fetch).patch_hints(patch)?;
+///     let mut target = Vec::new();
+///     Bspatch::new(patch)?.apply_source(source, io::Cursor::new(&mut target))?;
+///     Ok(target)
+/// }
+/// ```
+pub struct RangeReader<F> {
+    fetch: F,
+    size: u64,
+    pos: u64,
+    request_size: usize,
+    retries: u32,
+    hints: VecDeque<Range<u64>>,
+    cache: Vec<u8>,
+    cache_start: u64,
+    requests: u64,
+    fetched: u64,
+}
+
+impl<F> RangeReader<F>
+where
+    F: FnMut(Range<u64>) -> Result<Vec<u8>>,
+{
+    /// Create reader of a remote object of the given size.
+    pub fn new(size: u64, fetch: F) -> Self {
+        RangeReader {
+            fetch,
+            size,
+            pos: 0,
+            request_size: REQUEST_SIZE,
+            retries: RETRIES,
+            hints: VecDeque::new(),
+            cache: Vec::new(),
+            cache_start: 0,
+            requests: 0,
+            fetched: 0,
+        }
+    }
+
+    /// Set the maximal size of each range request (default is `REQUEST_SIZE`,
+    /// at least 1), bounding the memory of the reader.
+    pub fn request_size(mut self, size: usize) -> Self {
+        self.request_size = Ord::max(size, 1);
+        self
+    }
+
+    /// Set the number of re-requests of a range after transient errors
+    /// (default is `RETRIES`).
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Hint the ranges to be read, in ascending order.
+    ///
+    /// Requests starting within a hinted range stop at its end, instead of
+    /// fetching bytes not to be read.
+    /// Reads outside the hinted ranges are still served.
+    pub fn hints<I: IntoIterator<Item = Range<u64>>>(mut self, ranges: I) -> Self {
+        self.hints = ranges.into_iter().filter(|range| range.start < range.end).collect();
+        self
+    }
+
+    /// Hint the source ranges read by applying the patch, derived from its
+    /// controls (see `inspect::source_ranges`).
+    ///
+    /// Return error if the patch is corrupted.
+    pub fn patch_hints(self, patch: &[u8]) -> Result<Self> {
+        Ok(self.hints(inspect::source_ranges(patch)?))
+    }
+
+    /// Get the number of range requests issued, including re-requests.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Get the number of bytes fetched.
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+
+    /// Fetch the bytes at `offset` into the cache.
+    fn fill(&mut self, offset: u64) -> Result<()> {
+        while self.hints.front().is_some_and(|range| range.end <= offset) {
+            self.hints.pop_front();
+        }
+        let mut end = offset.saturating_add(self.request_size as u64);
+        match self.hints.front() {
+            Some(range) if range.start <= offset => end = Ord::min(end, range.end),
+            Some(range) => end = Ord::min(end, range.start),
+            None => {}
+        }
+        let end = Ord::min(end, self.size);
+
+        self.cache.clear();
+        self.cache_start = offset;
+        let mut failures = 0;
+        while self.cache_start + (self.cache.len() as u64) < end {
+            let start = self.cache_start + self.cache.len() as u64;
+            self.requests += 1;
+            match (self.fetch)(start..end) {
+                Ok(bytes) if !bytes.is_empty() && bytes.len() as u64 <= end - start => {
+                    self.fetched += bytes.len() as u64;
+                    self.cache.extend_from_slice(&bytes[..]);
+                }
+                Ok(bytes) if bytes.is_empty() => {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "empty range response"));
+                }
+                Ok(_) => return Err(Error::new(ErrorKind::InvalidData, "oversized range response")),
+                Err(err) if is_transient(&err) && failures < self.retries => failures += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F> SourceRead for RangeReader<F>
+where
+    F: FnMut(Range<u64>) -> Result<Vec<u8>>,
+{
+    fn size(&self) -> Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.size) {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        while !buf.is_empty() {
+            if offset < self.cache_start || offset >= self.cache_start + self.cache.len() as u64 {
+                self.fill(offset)?;
+            }
+            let skip = (offset - self.cache_start) as usize;
+            let n = Ord::min(buf.len(), self.cache.len() - skip);
+            buf[..n].copy_from_slice(&self.cache[skip..skip + n]);
+            buf = &mut buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<F> Read for RangeReader<F>
+where
+    F: FnMut(Range<u64>) -> Result<Vec<u8>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = usize::try_from(self.size.saturating_sub(self.pos)).map_or(buf.len(), |rest| Ord::min(rest, buf.len()));
+        self.read_at(self.pos, &mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F> Seek for RangeReader<F>
+where
+    F: FnMut(Range<u64>) -> Result<Vec<u8>>,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.pos)
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+    )
+}
@@ -0,0 +1,303 @@
+#![forbid(unsafe_code)]
+
+use super::bsdiff::ParallelScheme;
+use super::utils::Control;
+
+/// Ratio of extra data considered excessive.
+const EXCESSIVE_EXTRA: f64 = 0.5;
+
+/// Similarity considered high.
+const HIGH_SIMILARITY: f64 = 0.8;
+
+/// Controls carrying less target bytes are tiny.
+const TINY_CONTROL: u64 = 32;
+
+/// Minimum number of controls to flag tiny controls.
+const TINY_CONTROLS_MIN: u64 = 64;
+
+/// Suspicious pattern of generated controls, see `Bsdiff::analyze`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Anomaly {
+    /// More than half of the target is emitted as extra data, although the
+    /// target is estimated to be highly similar to the source.
+    ExcessiveExtra {
+        /// Ratio of extra data in the target.
+        extra_ratio: f64,
+
+        /// Estimated similarity, see `SourceIndex::similarity`.
+        similarity: f64,
+    },
+
+    /// More than half of the controls carry less than 32 target bytes.
+    TinyControls {
+        /// Number of tiny controls.
+        tiny: u64,
+
+        /// Number of all controls.
+        controls: u64,
+    },
+}
+
+impl Anomaly {
+    /// Machine-readable code of the anomaly.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Anomaly::ExcessiveExtra { .. } => "excessive-extra",
+            Anomaly::TinyControls { .. } => "tiny-controls",
+        }
+    }
+}
+
+/// Statistics of generated controls.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct ControlStats {
+    pub controls: u64,
+    pub tiny: u64,
+    pub target_size: u64,
+    pub extra_size: u64,
+}
+
+impl ControlStats {
+    /// Account a control.
+    pub fn record(&mut self, ctl: &Control) {
+        let size = ctl.add + ctl.copy + ctl.tcopy;
+        self.controls += 1;
+        if size < TINY_CONTROL {
+            self.tiny += 1;
+        }
+        self.target_size += size;
+        self.extra_size += ctl.copy;
+    }
+
+    /// Flag suspicious patterns given the estimated similarity.
+    pub fn anomalies(&self, similarity: f64) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        if self.target_size > 0 {
+            let extra_ratio = self.extra_size as f64 / self.target_size as f64;
+            if extra_ratio > EXCESSIVE_EXTRA && similarity >= HIGH_SIMILARITY {
+                anomalies.push(Anomaly::ExcessiveExtra {
+                    extra_ratio,
+                    similarity,
+                });
+            }
+        }
+        if self.controls >= TINY_CONTROLS_MIN && self.tiny * 2 > self.controls {
+            anomalies.push(Anomaly::TinyControls {
+                tiny: self.tiny,
+                controls: self.controls,
+            });
+        }
+        anomalies
+    }
+}
+
+/// Summary of a delta compression, returned by `Bsdiff::compare_report`.
+///
+/// The decision of `ParallelScheme::Auto` is exposed here, so that it could be
+/// reproduced or overridden later, e.g. by passing
+/// `ParallelScheme::ChunkSize(report.chunk_size())`.
+/// Reports are serializable with feature `serde`, e.g. to be stored along
+/// with the patches.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffReport {
+    pub(crate) patch_size: u64,
+    pub(crate) source_size: u64,
+    pub(crate) target_size: u64,
+    pub(crate) parallel_scheme: ParallelScheme,
+    pub(crate) chunk_size: usize,
+    pub(crate) jobs: usize,
+    pub(crate) workers: usize,
+    pub(crate) threads: usize,
+    pub(crate) target_entropy: Option<f64>,
+    pub(crate) search_steps: u64,
+    pub(crate) fallback_chunks: usize,
+    pub(crate) controls: u64,
+    pub(crate) extra_size: u64,
+    pub(crate) similarity: Option<f64>,
+    pub(crate) anomalies: Vec<Anomaly>,
+    pub(crate) full_fallback: bool,
+}
+
+impl DiffReport {
+    /// Size of the patch file.
+    pub fn patch_size(&self) -> u64 {
+        self.patch_size
+    }
+
+    /// Size of the source data.
+    pub fn source_size(&self) -> u64 {
+        self.source_size
+    }
+
+    /// Size of the target data.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Parallel searching scheme requested.
+    pub fn parallel_scheme(&self) -> ParallelScheme {
+        self.parallel_scheme
+    }
+
+    /// Chunk size of each parallel job actually used.
+    ///
+    /// This equals to the target size if searching is not parallel.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Number of parallel jobs (chunks of target).
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    /// Number of workers searching the chunks concurrently.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Available parallelism when comparing.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Estimated Shannon entropy of target data in bits per byte (`0.0..=8.0`).
+    ///
+    /// Only sampled by `ParallelScheme::Auto`, `None` otherwise.
+    pub fn target_entropy(&self) -> Option<f64> {
+        self.target_entropy
+    }
+
+    /// Total searching steps, see `Bsdiff::work_limit`.
+    pub fn search_steps(&self) -> u64 {
+        self.search_steps
+    }
+
+    /// Number of chunks exceeding the searching budget of `Bsdiff::work_limit`,
+    /// whose remaining data are aligned cheaply.
+    pub fn fallback_chunks(&self) -> usize {
+        self.fallback_chunks
+    }
+
+    /// Number of controls generated.
+    pub fn controls(&self) -> u64 {
+        self.controls
+    }
+
+    /// Number of target bytes emitted as extra data.
+    pub fn extra_size(&self) -> u64 {
+        self.extra_size
+    }
+
+    /// Estimated similarity of target to source.
+    ///
+    /// Only estimated by `Bsdiff::analyze`, `None` otherwise.
+    pub fn similarity(&self) -> Option<f64> {
+        self.similarity
+    }
+
+    /// Suspicious patterns of generated controls flagged by `Bsdiff::analyze`.
+    pub fn anomalies(&self) -> &[Anomaly] {
+        &self.anomalies[..]
+    }
+
+    /// Whether the patch was replaced by the full patch, see
+    /// `Bsdiff::full_fallback`.
+    ///
+    /// Other statistics describe the patch searched rather than the full
+    /// patch.
+    pub fn full_fallback(&self) -> bool {
+        self.full_fallback
+    }
+}
+
+/// Histogram of exact match lengths found by the matcher, see
+/// `Bsdiff::match_histogram`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchHistogram {
+    counts: Vec<u64>,
+    bytes: Vec<u64>,
+}
+
+impl MatchHistogram {
+    /// Lengths of exact matches no less than this are counted together.
+    pub const LIMIT: usize = 256;
+
+    /// Count an exact match seeding a delta region.
+    pub(crate) fn record(&mut self, len: usize, delta: u64) {
+        if len == 0 {
+            return;
+        }
+        let len = Ord::min(len, Self::LIMIT);
+        if self.counts.len() <= len {
+            self.counts.resize(len + 1, 0);
+            self.bytes.resize(len + 1, 0);
+        }
+        self.counts[len] += 1;
+        self.bytes[len] += delta;
+    }
+
+    /// Merge another histogram into this.
+    pub(crate) fn merge(&mut self, other: &MatchHistogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+            self.bytes.resize(other.counts.len(), 0);
+        }
+        for (len, (&count, &bytes)) in other.counts.iter().zip(other.bytes.iter()).enumerate() {
+            self.counts[len] += count;
+            self.bytes[len] += bytes;
+        }
+    }
+
+    /// Number of exact matches of the length, or no less than `LIMIT` if
+    /// the length is `LIMIT`.
+    pub fn count(&self, len: usize) -> u64 {
+        self.counts.get(len).copied().unwrap_or(0)
+    }
+
+    /// Lengths (capped by `LIMIT`) and numbers of exact matches found, in
+    /// ascending order of length.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(len, &count)| (len, count))
+    }
+
+    /// Total number of exact matches found.
+    pub fn matches(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Number of exact matches longer than `len`, i.e. those kept with
+    /// `Bsdiff::small_match(len)`.
+    pub fn matches_over(&self, len: usize) -> u64 {
+        self.counts.iter().skip(len + 1).sum()
+    }
+
+    /// Total target bytes of delta regions (exact matches extended with
+    /// similar bytes around).
+    pub fn delta_bytes(&self) -> u64 {
+        self.bytes.iter().sum()
+    }
+
+    /// Target bytes of delta regions seeded by exact matches longer than
+    /// `len`, i.e. roughly those kept with `Bsdiff::small_match(len)`.
+    pub fn delta_bytes_over(&self, len: usize) -> u64 {
+        self.bytes.iter().skip(len + 1).sum()
+    }
+
+    /// The largest `small_match` keeping at least the given fraction (in
+    /// range `0.0..=1.0`) of delta bytes.
+    pub fn suggest_small_match(&self, fraction: f64) -> usize {
+        let total = self.delta_bytes() as f64;
+        (0..Self::LIMIT)
+            .take_while(|&len| self.delta_bytes_over(len) as f64 >= total * fraction)
+            .last()
+            .unwrap_or(0)
+    }
+}
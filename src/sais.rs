@@ -0,0 +1,287 @@
+#![forbid(unsafe_code)]
+
+//! Suffix array construction by induced sorting (SA-IS), reporting progress
+//! and checking for cancellation as it goes.
+
+use std::io::Result;
+
+/// Marker of empty slots of the suffix array under construction.
+const EMPTY: u32 = u32::MAX;
+
+/// Number of steps of each pass between two progress reports.
+const REPORT_INTERVAL: usize = 1 << 20;
+
+/// Progress hook, reporting the fraction done in range `[0, 1]` and
+/// cancelling construction by returning an error.
+pub type ProgressHook<'a> = dyn FnMut(f64) -> Result<()> + 'a;
+
+/// Progress of a (sub-)construction, mapped into a range of the whole.
+struct Progress<'h, 'a> {
+    hook: &'h mut ProgressHook<'a>,
+    start: f64,
+    width: f64,
+}
+
+impl<'h, 'a> Progress<'h, 'a> {
+    /// Report the fraction done of this construction.
+    fn at(&mut self, fraction: f64) -> Result<()> {
+        (self.hook)(self.start + self.width * fraction)
+    }
+
+    /// Report the progress of the i-th step of n steps in a pass spanning
+    /// the given fractions, once every `REPORT_INTERVAL` steps.
+    fn step(&mut self, i: usize, n: usize, pass: (f64, f64)) -> Result<()> {
+        if i.is_multiple_of(REPORT_INTERVAL) {
+            self.at(pass.0 + (pass.1 - pass.0) * i as f64 / n as f64)?;
+        }
+        Ok(())
+    }
+
+    /// Progress of a nested construction spanning the given fractions.
+    fn nested(&mut self, start: f64, end: f64) -> Progress<'_, 'a> {
+        Progress {
+            hook: &mut *self.hook,
+            start: self.start + self.width * start,
+            width: self.width * (end - start),
+        }
+    }
+}
+
+/// Symbols of texts, bytes at the top level and names of LMS substrings in
+/// the reduced problems.
+trait Symbol: Copy + Ord {
+    fn rank(self) -> usize;
+}
+
+impl Symbol for u8 {
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+impl Symbol for u32 {
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+/// Build the suffix array of the text in the layout of `suffix_array`, i.e.
+/// the empty suffix first, followed by the suffixes in lexicographical order.
+///
+/// The text must be shorter than `u32::MAX`.
+pub fn suffix_array(s: &[u8], hook: &mut ProgressHook<'_>) -> Result<Vec<u32>> {
+    let mut progress = Progress {
+        hook,
+        start: 0.0,
+        width: 1.0,
+    };
+    progress.at(0.0)?;
+    let mut sa = vec![0; s.len() + 1];
+    sa[0] = s.len() as u32;
+    sais(s, 256, &mut sa[1..], &mut progress)?;
+    progress.at(1.0)?;
+    Ok(sa)
+}
+
+/// Sort the suffixes of text over an alphabet of size `k` into `sa`, of the
+/// same length as the text.
+///
+/// Suffixes running to the end of text are smaller than any longer suffix
+/// they are prefix of, as if the text were terminated by a virtual sentinel.
+fn sais<T: Symbol>(s: &[T], k: usize, sa: &mut [u32], progress: &mut Progress<'_, '_>) -> Result<()> {
+    let n = s.len();
+    if n <= 1 {
+        sa.fill(0);
+        return Ok(());
+    }
+
+    // Classify suffixes, the last one is L-type against the sentinel.
+    let mut stype = Bits::new(n);
+    for i in (0..n - 1).rev() {
+        if s[i] < s[i + 1] || (s[i] == s[i + 1] && stype.get(i + 1)) {
+            stype.set(i);
+        }
+    }
+    let lms = |i: usize| i > 0 && i < n && stype.get(i) && !stype.get(i - 1);
+    let mut counts = vec![0u32; k];
+    for &c in s {
+        counts[c.rank()] += 1;
+    }
+    progress.at(0.05)?;
+
+    // Sort the LMS substrings by induced sorting of the LMS suffixes placed
+    // at the ends of buckets.
+    sa.fill(EMPTY);
+    let mut ends = bucket_ends(&counts);
+    for i in (1..n).rev() {
+        if lms(i) {
+            let c = s[i].rank();
+            ends[c] -= 1;
+            sa[ends[c] as usize] = i as u32;
+        }
+    }
+    induce(s, sa, &stype, &counts, progress, (0.05, 0.35))?;
+
+    // Name the LMS substrings in their sorted order, and store the names at
+    // the end of suffix array in the order of text.
+    let mut m = 0;
+    for i in 0..n {
+        if lms(sa[i] as usize) {
+            sa[m] = sa[i];
+            m += 1;
+        }
+    }
+    sa[m..].fill(EMPTY);
+    let mut names = 0;
+    let mut prev = None;
+    for i in 0..m {
+        let pos = sa[i] as usize;
+        if prev.is_none_or(|prev| !lms_equal(s, &stype, prev, pos)) {
+            names += 1;
+            prev = Some(pos);
+        }
+        sa[m + pos / 2] = names - 1;
+        progress.step(i, m, (0.35, 0.4))?;
+    }
+    let mut j = n;
+    for i in (m..n).rev() {
+        if sa[i] != EMPTY {
+            j -= 1;
+            sa[j] = sa[i];
+        }
+    }
+
+    // Sort the LMS suffixes by the suffixes of the reduced text, recursively
+    // unless the names are unique, then map them back to positions in text.
+    {
+        let (head, tail) = sa.split_at_mut(n - m);
+        let (sa1, s1) = (&mut head[..m], &mut tail[..]);
+        if (names as usize) < m {
+            sais(&s1[..], names as usize, sa1, &mut progress.nested(0.4, 0.6))?;
+        } else {
+            for i in 0..m {
+                sa1[s1[i] as usize] = i as u32;
+            }
+        }
+        progress.at(0.6)?;
+        let mut j = 0;
+        for i in 1..n {
+            if lms(i) {
+                s1[j] = i as u32;
+                j += 1;
+            }
+        }
+        for i in 0..m {
+            sa1[i] = s1[sa1[i] as usize];
+        }
+    }
+
+    // Induce the order of all suffixes from the sorted LMS suffixes.
+    sa[m..].fill(EMPTY);
+    let mut ends = bucket_ends(&counts);
+    for i in (0..m).rev() {
+        let j = sa[i] as usize;
+        sa[i] = EMPTY;
+        let c = s[j].rank();
+        ends[c] -= 1;
+        sa[ends[c] as usize] = j as u32;
+    }
+    induce(s, sa, &stype, &counts, progress, (0.6, 1.0))
+}
+
+/// Induce the L-type suffixes from the sorted LMS suffixes in `sa`, then the
+/// S-type suffixes from the L-type ones.
+fn induce<T: Symbol>(
+    s: &[T],
+    sa: &mut [u32],
+    stype: &Bits,
+    counts: &[u32],
+    progress: &mut Progress<'_, '_>,
+    pass: (f64, f64),
+) -> Result<()> {
+    let n = s.len();
+    let mid = (pass.0 + pass.1) / 2.0;
+
+    // The suffix before the sentinel is the smallest of its bucket.
+    let mut starts = bucket_starts(counts);
+    let c = s[n - 1].rank();
+    sa[starts[c] as usize] = (n - 1) as u32;
+    starts[c] += 1;
+    for i in 0..n {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && !stype.get(j as usize - 1) {
+            let c = s[j as usize - 1].rank();
+            sa[starts[c] as usize] = j - 1;
+            starts[c] += 1;
+        }
+        progress.step(i, n, (pass.0, mid))?;
+    }
+
+    let mut ends = bucket_ends(counts);
+    for i in (0..n).rev() {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && stype.get(j as usize - 1) {
+            let c = s[j as usize - 1].rank();
+            ends[c] -= 1;
+            sa[ends[c] as usize] = j - 1;
+        }
+        progress.step(n - i, n, (mid, pass.1))?;
+    }
+    Ok(())
+}
+
+/// Check whether the LMS substrings at `a` and `b` are equal, in both the
+/// symbols and the types. Substrings reaching the end of text are unique.
+fn lms_equal<T: Symbol>(s: &[T], stype: &Bits, a: usize, b: usize) -> bool {
+    let n = s.len();
+    let lms = |i: usize| stype.get(i) && !stype.get(i - 1);
+    for d in 0.. {
+        let (i, j) = (a + d, b + d);
+        if i == n || j == n || s[i] != s[j] || stype.get(i) != stype.get(j) {
+            return false;
+        }
+        if d > 0 && (lms(i) || lms(j)) {
+            return lms(i) && lms(j);
+        }
+    }
+    unreachable!()
+}
+
+fn bucket_starts(counts: &[u32]) -> Vec<u32> {
+    let mut sum = 0;
+    counts
+        .iter()
+        .map(|&count| {
+            sum += count;
+            sum - count
+        })
+        .collect()
+}
+
+fn bucket_ends(counts: &[u32]) -> Vec<u32> {
+    let mut sum = 0;
+    counts
+        .iter()
+        .map(|&count| {
+            sum += count;
+            sum
+        })
+        .collect()
+}
+
+/// Fixed-size bit vector of the suffix types.
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(n: usize) -> Self {
+        Bits(vec![0; n.div_ceil(64)])
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+}
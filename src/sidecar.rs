@@ -0,0 +1,197 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use byteorder::{ByteOrder, LE};
+
+use super::format::PatchMetadata;
+use super::utils::{crc32, decode_int, encode_int, Control};
+
+/// Magic of control sidecar files.
+const SIDECAR_MAGIC: &[u8; 8] = b"QBSCTRL1";
+
+/// Size of the fixed part of sidecar files, up to the size of parameters.
+const SIDECAR_HEADER_SIZE: usize = 36;
+
+/// Size of each encoded control.
+const CONTROL_SIZE: usize = 24;
+
+/// Raw control stream of a comparison, produced by `Bsdiff::compare_sidecar`
+/// and packed later by `Bsdiff::pack_sidecar`.
+///
+/// This decouples the expensive matching from packing: controls could be
+/// searched once, persisted, and packed with any format, codecs and checksums
+/// afterwards (e.g. once for each delivery channel).
+/// Sidecars record the sizes and CRC-32 checksums of the source and target
+/// they were searched on, and the matching parameters, as `key=value`
+/// entries like the metadata of patches.
+///
+/// The encoding consists of magic `QBSCTRL1`, the source size and target size
+/// (u64 each), the checksums of source and target (u32 each), the size of
+/// parameters (u32) and the parameters, the number of controls (u64) and the
+/// controls (add, copy and seek, encoded like classic bsdiff 4.x controls).
+/// All other integers are in little endian.
+///
+/// Example:
+///
+/// Search at night, pack for two channels later:
+/// ```
+/// use std::io;
+/// use qbsdiff::{Bsdiff, Codec, Format, Sidecar};
+///
+/// fn search(source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut sidecar = Vec::new();
+///     Bsdiff::new(source, target).compare_sidecar()?.write(&mut sidecar)?;
+///     Ok(sidecar)
+/// }
+///
+/// fn pack(source: &[u8], target: &[u8], sidecar: &[u8]) -> io::Result<(Vec<u8>, Vec<u8>)> {
+///     let sidecar = Sidecar::read(sidecar)?;
+///     let (mut classic, mut extended) = (Vec::new(), Vec::new());
+///     Bsdiff::new(source, target).pack_sidecar(&sidecar, io::Cursor::new(&mut classic))?;
+///     Bsdiff::new(source, target)
+///         .format(Format::Extended)
+///         .codec(Codec::Stored)
+///         .pack_sidecar(&sidecar, io::Cursor::new(&mut extended))?;
+///     Ok((classic, extended))
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sidecar {
+    source_size: u64,
+    target_size: u64,
+    source_checksum: u32,
+    target_checksum: u32,
+    parameters: String,
+    controls: Vec<Control>,
+}
+
+impl Sidecar {
+    /// Create sidecar of the controls searched on source and target.
+    pub(crate) fn new(source: &[u8], target: &[u8], parameters: String, controls: Vec<Control>) -> Self {
+        Sidecar {
+            source_size: source.len() as u64,
+            target_size: target.len() as u64,
+            source_checksum: crc32(0, source),
+            target_checksum: crc32(0, target),
+            parameters,
+            controls,
+        }
+    }
+
+    /// Size of the source searched.
+    pub fn source_size(&self) -> u64 {
+        self.source_size
+    }
+
+    /// Size of the target searched.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Matching parameters, e.g. `small_match` and `chunk_size`.
+    pub fn parameters(&self) -> PatchMetadata {
+        PatchMetadata::parse(self.parameters.as_bytes())
+    }
+
+    /// Controls in order, covering the target exactly.
+    pub fn controls(&self) -> &[Control] {
+        &self.controls[..]
+    }
+
+    /// Check whether the source and target are the ones searched.
+    ///
+    /// Return `ErrorKind::InvalidInput` if either of them mismatches.
+    pub fn check(&self, source: &[u8], target: &[u8]) -> Result<()> {
+        if self.source_size != source.len() as u64 || self.source_checksum != crc32(0, source) {
+            return Err(Error::new(ErrorKind::InvalidInput, "sidecar source mismatch"));
+        }
+        if self.target_size != target.len() as u64 || self.target_checksum != crc32(0, target) {
+            return Err(Error::new(ErrorKind::InvalidInput, "sidecar target mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Encode the sidecar.
+    ///
+    /// The size of encoded sidecar would be returned if no error occurs.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<u64> {
+        let mut header = [0; SIDECAR_HEADER_SIZE];
+        header[0..8].copy_from_slice(SIDECAR_MAGIC);
+        LE::write_u64(&mut header[8..16], self.source_size);
+        LE::write_u64(&mut header[16..24], self.target_size);
+        LE::write_u32(&mut header[24..28], self.source_checksum);
+        LE::write_u32(&mut header[28..32], self.target_checksum);
+        LE::write_u32(&mut header[32..36], self.parameters.len() as u32);
+        writer.write_all(&header)?;
+        writer.write_all(self.parameters.as_bytes())?;
+        writer.write_all(&(self.controls.len() as u64).to_le_bytes())?;
+
+        let mut ctl = [0; CONTROL_SIZE];
+        for control in self.controls.iter() {
+            encode_int(control.add as i64, &mut ctl[0..8]);
+            encode_int(control.copy as i64, &mut ctl[8..16]);
+            encode_int(control.seek, &mut ctl[16..24]);
+            writer.write_all(&ctl)?;
+        }
+        writer.flush()?;
+        Ok((header.len() + self.parameters.len() + 8 + self.controls.len() * CONTROL_SIZE) as u64)
+    }
+
+    /// Decode a sidecar.
+    ///
+    /// Return `ErrorKind::InvalidData` if it is not a valid sidecar.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0; SIDECAR_HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(eof_corrupted)?;
+        if &header[0..8] != SIDECAR_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a valid sidecar"));
+        }
+        let size = LE::read_u32(&header[32..36]) as u64;
+        let mut parameters = Vec::new();
+        if reader.by_ref().take(size).read_to_end(&mut parameters)? as u64 != size {
+            return Err(corrupted());
+        }
+        let parameters = String::from_utf8(parameters).map_err(|_| corrupted())?;
+
+        let mut count = [0; 8];
+        reader.read_exact(&mut count).map_err(eof_corrupted)?;
+        let count = u64::from_le_bytes(count);
+        let mut controls = Vec::with_capacity(Ord::min(count, 1 << 16) as usize);
+        let mut ctl = [0; CONTROL_SIZE];
+        for _ in 0..count {
+            reader.read_exact(&mut ctl).map_err(eof_corrupted)?;
+            let (add, copy) = (decode_int(&ctl[0..8]), decode_int(&ctl[8..16]));
+            if add < 0 || copy < 0 {
+                return Err(corrupted());
+            }
+            controls.push(Control {
+                add: add as u64,
+                copy: copy as u64,
+                seek: decode_int(&ctl[16..24]),
+                ..Control::default()
+            });
+        }
+        Ok(Sidecar {
+            source_size: LE::read_u64(&header[8..16]),
+            target_size: LE::read_u64(&header[16..24]),
+            source_checksum: LE::read_u32(&header[24..28]),
+            target_checksum: LE::read_u32(&header[28..32]),
+            parameters,
+            controls,
+        })
+    }
+}
+
+fn corrupted() -> Error {
+    Error::new(ErrorKind::InvalidData, "sidecar corrupted")
+}
+
+/// Treat truncated sidecars as corrupted.
+fn eof_corrupted(err: Error) -> Error {
+    if err.kind() == ErrorKind::UnexpectedEof {
+        corrupted()
+    } else {
+        err
+    }
+}
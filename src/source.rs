@@ -0,0 +1,265 @@
+#![forbid(unsafe_code)]
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+
+/// Maximum size of sources preloaded by `SourcePreload::Auto`.
+const PRELOAD_LIMIT: u64 = 64 << 20;
+
+/// Size of windows cached by `SourcePreload::Auto`.
+const WINDOW_SIZE: usize = 64 << 10;
+
+/// Number of windows cached by `SourcePreload::Auto`.
+const WINDOW_COUNT: usize = 256;
+
+/// Random access to the source data of patching, see `Bspatch::apply_source`.
+///
+/// Patchers read the source at increasing offsets most of the time, jumping
+/// back and forth by the seeks of controls.
+/// Implemented for byte slices and vectors, files (by positioned reads on
+/// Unix, seeking elsewhere) and memory maps (feature `mmap`), and could be
+/// implemented for block devices, partial local caches or remote backends
+/// serving range requests.
+///
+/// Example:
+///
+/// Read the source through a cache of fixed-size blocks, fetched on demand:
+/// ```
+/// use std::collections::HashMap;
+/// use std::io;
+/// use qbsdiff::SourceRead;
+///
+/// struct Blocks<F: FnMut(u64) -> io::Result<Vec<u8>>> {
+///     size: u64,
+///     fetch: F,
+///     cache: HashMap<u64, Vec<u8>>,
+/// }
+///
+/// impl<F: FnMut(u64) -> io::Result<Vec<u8>>> SourceRead for Blocks<F> {
+///     fn size(&self) -> io::Result<u64> {
+///         Ok(self.size)
+///     }
+///
+///     fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+///         while !buf.is_empty() {
+///             let (index, skip) = (offset / 4096, (offset % 4096) as usize);
+///             if !self.cache.contains_key(&index) {
+///                 let block = (self.fetch)(index)?;
+///                 self.cache.insert(index, block);
+///             }
+///             let block = &self.cache[&index];
+///             let k = Ord::min(buf.len(), block.len().saturating_sub(skip));
+///             if k == 0 {
+///                 return Err(io::ErrorKind::UnexpectedEof.into());
+///             }
+///             buf[..k].copy_from_slice(&block[skip..skip + k]);
+///             buf = &mut buf[k..];
+///             offset += k as u64;
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait SourceRead {
+    /// Size of the source.
+    fn size(&self) -> Result<u64>;
+
+    /// Read exactly `buf.len()` bytes starting at `offset`.
+    ///
+    /// Return `ErrorKind::UnexpectedEof` if the source ends before `buf` is
+    /// filled.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+/// Policy of reading the source of `Bspatch::apply_source`, see
+/// `Bspatch::source_preload`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SourcePreload {
+    /// Choose by the access pattern of the controls, scanned before
+    /// patching.
+    ///
+    /// Sources read at increasing offsets only are read as required
+    /// (`Seek`).
+    /// Otherwise, sources up to 64 MiB with at least a quarter of them read
+    /// are preloaded (`Whole`), and other sources are read through 256
+    /// windows of 64 KiB (`Windows`).
+    #[default]
+    Auto,
+
+    /// Read the whole source into memory before patching.
+    Whole,
+
+    /// Keep the `count` most recently read windows of `size` bytes, aligned
+    /// to multiples of `size`, in memory.
+    ///
+    /// This suits patches jumping back and forth within a few regions of a
+    /// large source.
+    Windows { size: usize, count: usize },
+
+    /// Read the source by `SourceRead::read_at` as required by each control.
+    Seek,
+}
+
+impl SourcePreload {
+    /// Resolve `Auto` by the source size, and the number of source bytes read
+    /// and the number of reads jumping backward, if the controls could be
+    /// scanned.
+    pub(crate) fn resolve(self, size: u64, access: Option<(u64, u64)>) -> SourcePreload {
+        if self != SourcePreload::Auto {
+            return self;
+        }
+        match access {
+            Some((_, 0)) | None => SourcePreload::Seek,
+            Some((bytes, _)) if size <= PRELOAD_LIMIT && bytes >= size / 4 => SourcePreload::Whole,
+            Some(_) => SourcePreload::Windows {
+                size: WINDOW_SIZE,
+                count: WINDOW_COUNT,
+            },
+        }
+    }
+}
+
+/// Read the whole source into memory.
+pub(crate) fn preload<S: SourceRead>(mut source: S) -> Result<Vec<u8>> {
+    let size = usize::try_from(source.size()?)
+        .map_err(|_| Error::new(ErrorKind::OutOfMemory, "source is too large to preload"))?;
+    let mut data = vec![0; size];
+    source.read_at(0, &mut data[..])?;
+    Ok(data)
+}
+
+/// Source read through a cache of the most recently read windows.
+pub(crate) struct WindowCache<S: SourceRead> {
+    inner: S,
+    size: u64,
+    window: usize,
+    count: usize,
+    /// Cached windows by index, the most recently read first.
+    windows: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl<S: SourceRead> WindowCache<S> {
+    pub fn new(inner: S, window: usize, count: usize) -> Result<Self> {
+        Ok(WindowCache {
+            size: inner.size()?,
+            inner,
+            window: Ord::max(window, 1),
+            count: Ord::max(count, 1),
+            windows: VecDeque::new(),
+        })
+    }
+
+    /// Get the window of given index (within the source), reading it if not
+    /// cached.
+    fn fetch(&mut self, index: u64) -> Result<&[u8]> {
+        match self.windows.iter().position(|&(i, _)| i == index) {
+            Some(k) => {
+                let window = self.windows.remove(k).unwrap();
+                self.windows.push_front(window);
+            }
+            None => {
+                let start = index * self.window as u64;
+                let len = Ord::min(self.window as u64, self.size - start) as usize;
+                let mut buf = match self.windows.len() >= self.count {
+                    true => self.windows.pop_back().unwrap().1,
+                    false => Vec::new(),
+                };
+                buf.resize(len, 0);
+                self.inner.read_at(start, &mut buf[..])?;
+                self.windows.push_front((index, buf));
+            }
+        }
+        Ok(&self.windows[0].1)
+    }
+}
+
+impl<S: SourceRead> SourceRead for WindowCache<S> {
+    fn size(&self) -> Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.size) {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        let window = self.window as u64;
+        while !buf.is_empty() {
+            let skip = (offset % window) as usize;
+            let data = self.fetch(offset / window)?;
+            let k = Ord::min(buf.len(), data.len() - skip);
+            buf[..k].copy_from_slice(&data[skip..skip + k]);
+            buf = &mut buf[k..];
+            offset += k as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<S: SourceRead + ?Sized> SourceRead for &mut S {
+    fn size(&self) -> Result<u64> {
+        (**self).size()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+}
+
+impl SourceRead for &[u8] {
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_slice(self, offset, buf)
+    }
+}
+
+impl SourceRead for Vec<u8> {
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_slice(self, offset, buf)
+    }
+}
+
+impl SourceRead for File {
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    #[cfg(unix)]
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl SourceRead for memmap2::Mmap {
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_slice(self, offset, buf)
+    }
+}
+
+fn read_slice(data: &[u8], offset: u64, buf: &mut [u8]) -> Result<()> {
+    let bytes = usize::try_from(offset)
+        .ok()
+        .and_then(|start| data.get(start..start.checked_add(buf.len())?))
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"))?;
+    buf.copy_from_slice(bytes);
+    Ok(())
+}
@@ -0,0 +1,172 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use super::bsdiff::COMPRESSION_LEVEL;
+use super::checksum::ChecksumKind;
+use super::codec::{Codec, Decoder, Encoder};
+use super::format::{Format, Header};
+use super::utils::ControlLayout;
+
+/// Convert a patch between the classic bsdiff 4.x and the extended formats.
+///
+/// Sections are rewrapped rather than recompressed whenever possible:
+/// * to `Format::Extended`, sections are kept as is and CRC-32 checksums of
+///   them are added (if not present yet, otherwise the algorithm is kept);
+/// * to `Format::Classic`, sections not compressed with bzip2 or split into
+///   blocks (see `Bsdiff::block_size`) are recompressed as a single bzip2
+///   stream, the checksums are verified then dropped, and so are the
+///   checksums of source windows, the decoded section sizes and the metadata;
+///   controls with compact seeks (see `Bsdiff::compact_seek`) are re-encoded
+///   in the classic layout.
+///
+/// Micro patches (see `Bsdiff::micro`) are converted to patches of regular
+/// headers, their controls are re-encoded in the classic layout and
+/// compressed with bzip2.
+///
+/// Target-relative copies (see `Bsdiff::target_copy`) could not be expressed
+/// in the classic format, converting such patches fails with
+/// `ErrorKind::Unsupported`.
+///
+/// Example:
+///
+/// Migrate an archive of bsdiff 4.x patches:
+/// ```
+/// use std::fs::File;
+/// use std::io::{self, BufReader, BufWriter};
+/// use qbsdiff::{transcode, Format};
+///
+/// fn migrate(classic: &str, extended: &str) -> io::Result<u64> {
+///     let reader = BufReader::new(File::open(classic)?);
+///     let writer = BufWriter::new(File::create(extended)?);
+///     transcode(reader, writer, Format::Classic, Format::Extended)
+/// }
+/// ```
+///
+/// Return error if the patch is not in the `from` format or corrupted.
+/// The size of the converted patch would be returned if no error occurs.
+pub fn transcode<R: Read, W: Write>(mut reader: R, mut writer: W, from: Format, to: Format) -> Result<u64> {
+    let mut patch = Vec::new();
+    reader.read_to_end(&mut patch)?;
+    let header = Header::parse(&patch[..])?;
+    if header.format != from {
+        return Err(Error::new(ErrorKind::InvalidData, "unexpected patch format"));
+    }
+    let (ctrls, delta, extra) = header.sections(&patch[..]);
+
+    let mut size = 0;
+    match to {
+        Format::Classic => {
+            if header.has_target_copy() {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "target-relative copies require the extended format",
+                ));
+            }
+            let mut sections = Vec::with_capacity(3);
+            for (codec, data) in header.codecs.iter().zip([ctrls, delta, extra]) {
+                sections.push(bzip2_section(*codec, data, header.has_blocks())?);
+            }
+            if header.has_compact_seek() || header.has_micro() {
+                sections[0] = classic_controls(header.codecs[0], ctrls, header.control_layout())?;
+            }
+            let [ctrls, delta, extra] = [&sections[0][..], &sections[1][..], &sections[2][..]];
+            let classic = Header::new(
+                Format::Classic,
+                [Codec::Bzip2; 3],
+                ctrls.len() as u64,
+                delta.len() as u64,
+                extra.len() as u64,
+                header.tsize,
+            );
+            for data in [&classic.encode()[..], ctrls, delta, extra] {
+                writer.write_all(data)?;
+                size += data.len() as u64;
+            }
+        }
+        Format::Extended => {
+            let micro_ctrls;
+            let mut codecs = header.codecs;
+            let ctrls = if header.has_micro() {
+                micro_ctrls = classic_controls(header.codecs[0], ctrls, header.control_layout())?;
+                codecs[0] = Codec::Bzip2;
+                &micro_ctrls[..]
+            } else {
+                ctrls
+            };
+            let mut extended = Header::new(
+                Format::Extended,
+                codecs,
+                ctrls.len() as u64,
+                header.dsize,
+                header.esize,
+                header.tsize,
+            )
+            .checksum(header.checksum_kind().unwrap_or(ChecksumKind::Crc32));
+            if header.has_target_copy() {
+                extended = extended.target_copy(header.window_log);
+            }
+            if header.has_compact_seek() {
+                extended = extended.compact_seek();
+            }
+            if let Some(decoded) = header.decoded_size_hint() {
+                extended = extended.decoded_sizes(decoded);
+            }
+            if header.has_explicit_sizes() {
+                extended = extended.explicit_sizes(header.source_size);
+            }
+            if header.has_source_checksum() {
+                extended = extended.source_checksum(header.ssize, header.swindow);
+            }
+            let metadata = if header.has_metadata() {
+                extended = extended.metadata(header.msize);
+                Header::encode_metadata(header.metadata_bytes(&patch[..]))
+            } else {
+                Vec::new()
+            };
+            if header.has_blocks() {
+                extended = extended.blocks(header.block_size, header.blocks);
+            }
+            let checksums = header.source_checksums(&patch[..]);
+            let trailer = Header::encode_checksums(extended.checksum, ctrls, delta, extra)?;
+            for data in [
+                &extended.encode()[..],
+                checksums,
+                &metadata[..],
+                header.block_table(&patch[..]),
+                ctrls,
+                delta,
+                extra,
+                &trailer[..],
+            ] {
+                writer.write_all(data)?;
+                size += data.len() as u64;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(size)
+}
+
+/// Re-encode the controls in the classic layout, compressed with bzip2.
+fn classic_controls(codec: Codec, data: &[u8], layout: ControlLayout) -> Result<Vec<u8>> {
+    let mut ctrls = Decoder::new(codec, data)?;
+    let mut encoder = Encoder::new(Codec::Bzip2, COMPRESSION_LEVEL, Vec::new())?;
+    let mut ctl = [0; 40];
+    while layout.read(&mut ctrls, &mut ctl)? {
+        encoder.write_all(&ctl[..24])?;
+    }
+    encoder.finish()
+}
+
+/// Recompress the section as a single bzip2 stream if encoded otherwise.
+fn bzip2_section(codec: Codec, data: &[u8], blocks: bool) -> Result<Vec<u8>> {
+    if codec == Codec::Bzip2 && !blocks {
+        return Ok(data.to_vec());
+    }
+    let mut decoded = Vec::new();
+    Decoder::new(codec, data)?.read_to_end(&mut decoded)?;
+    let mut encoder = Encoder::new(Codec::Bzip2, COMPRESSION_LEVEL, Vec::new())?;
+    encoder.write_all(&decoded[..])?;
+    encoder.finish()
+}
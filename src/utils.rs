@@ -1,13 +1,126 @@
 #![forbid(unsafe_code)]
 
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::{Error, ErrorKind, Read, Result};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
 use byteorder::{ByteOrder, LE};
 
 /// Single bsdiff control instruction.
-#[derive(Debug)]
+///
+/// The target-relative copy (`tcopy` bytes from `tdist` bytes back) follows
+/// the extra data, it is only available in extended patches with the feature
+/// flag, and always zero otherwise.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Control {
+    /// Length of delta data added to the source.
     pub add: u64,
+
+    /// Length of extra data copied.
     pub copy: u64,
+
+    /// Offset of the source cursor moved after this control.
     pub seek: i64,
+
+    /// Length of target-relative copy.
+    pub tcopy: u64,
+
+    /// Distance of target-relative copy.
+    pub tdist: u64,
+}
+
+/// Recent target history, kept for target-relative copies.
+pub struct History {
+    buf: Vec<u8>,
+    cap: usize,
+    pos: usize,
+}
+
+impl History {
+    /// Create history keeping at most `cap` recent bytes, it grows lazily.
+    pub fn new(cap: usize) -> Self {
+        History {
+            buf: Vec::new(),
+            cap,
+            pos: 0,
+        }
+    }
+
+    /// Append bytes produced.
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        if self.cap == 0 {
+            return;
+        }
+        if bytes.len() > self.cap {
+            bytes = &bytes[bytes.len() - self.cap..];
+        }
+        if self.buf.len() < self.cap {
+            let k = Ord::min(bytes.len(), self.cap - self.buf.len());
+            self.buf.extend_from_slice(&bytes[..k]);
+            bytes = &bytes[k..];
+        }
+        while !bytes.is_empty() {
+            let k = Ord::min(bytes.len(), self.cap - self.pos);
+            self.buf[self.pos..self.pos + k].copy_from_slice(&bytes[..k]);
+            self.pos = (self.pos + k) % self.cap;
+            bytes = &bytes[k..];
+        }
+    }
+
+    /// Read bytes starting `dist` bytes back (`out.len() <= dist <= len`).
+    pub fn get(&self, dist: usize, out: &mut [u8]) {
+        let start = if self.buf.len() < self.cap {
+            self.buf.len() - dist
+        } else {
+            (self.pos + self.cap - dist) % self.cap
+        };
+        let k = Ord::min(out.len(), self.buf.len() - start);
+        out[..k].copy_from_slice(&self.buf[start..start + k]);
+        let rest = out.len() - k;
+        out[k..].copy_from_slice(&self.buf[..rest]);
+    }
+}
+
+/// Table of CRC-32 (IEEE 802.3, reflected polynomial `0xEDB88320`).
+const CRC32_TABLE: [u32; 256] = crc_table(0xEDB88320);
+
+/// Table of CRC-32C (Castagnoli, reflected polynomial `0x82F63B78`).
+const CRC32C_TABLE: [u32; 256] = crc_table(0x82F63B78);
+
+/// Build the table of reflected CRC-32 polynomial.
+const fn crc_table(poly: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Update CRC-32 (IEEE 802.3) with bytes, starting with zero.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Update CRC-32C (Castagnoli) with bytes, starting with zero.
+pub fn crc32c(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
 }
 
 /// Decodes integer.
@@ -30,3 +143,154 @@ pub fn encode_int(x: i64, b: &mut [u8]) {
         LE::write_u64(b, x as u64);
     }
 }
+
+/// Read the next control, return false at the end of controls.
+pub fn read_control<R: Read>(r: &mut R, ctl: &mut [u8]) -> Result<bool> {
+    let mut cnt = 0;
+    while cnt < ctl.len() {
+        match r.read(&mut ctl[cnt..]) {
+            Ok(0) => break,
+            Ok(n) => cnt += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    match cnt {
+        0 => Ok(false),
+        n if n == ctl.len() => Ok(true),
+        _ => Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+    }
+}
+
+/// Encoding of controls in the control section, see `Header::control_layout`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ControlLayout {
+    /// Controls carry target-relative copies.
+    pub target_copy: bool,
+
+    /// Seeks are encoded as zig-zag varints rather than 8-byte integers.
+    pub compact_seek: bool,
+
+    /// All fields are encoded as zig-zag varints (micro patches).
+    pub varint: bool,
+}
+
+impl ControlLayout {
+    /// Maximum size of an encoded control.
+    pub const MAX_SIZE: usize = 50;
+
+    /// Encode the control of raw values `[add, copy, seek, tcopy, tdist]`,
+    /// return the encoded size.
+    pub fn encode(self, values: [i64; 5], out: &mut [u8; ControlLayout::MAX_SIZE]) -> usize {
+        if self.varint {
+            let fields = if self.target_copy { 5 } else { 3 };
+            return values[..fields]
+                .iter()
+                .fold(0, |n, &x| n + encode_varint(x, &mut out[n..n + 10]));
+        }
+        encode_int(values[0], &mut out[0..8]);
+        encode_int(values[1], &mut out[8..16]);
+        let mut n = if self.compact_seek {
+            16 + encode_varint(values[2], &mut out[16..26])
+        } else {
+            encode_int(values[2], &mut out[16..24]);
+            24
+        };
+        if self.target_copy {
+            encode_int(values[3], &mut out[n..n + 8]);
+            encode_int(values[4], &mut out[n + 8..n + 16]);
+            n += 16;
+        }
+        n
+    }
+
+    /// Read the next control into the classic layout of five 8-byte integers
+    /// (`add`, `copy`, `seek`, `tcopy`, `tdist`, the last two are zero unless
+    /// `target_copy`), return false at the end of controls.
+    pub fn read<R: Read>(self, r: &mut R, ctl: &mut [u8; 40]) -> Result<bool> {
+        if self.varint {
+            let mut first = [0];
+            if !read_control(r, &mut first)? {
+                return Ok(false);
+            }
+            let fields = if self.target_copy { 5 } else { 3 };
+            encode_int(read_varint(&mut Read::chain(&first[..], &mut *r))?, &mut ctl[0..8]);
+            for i in 1..fields {
+                encode_int(read_varint(r)?, &mut ctl[i * 8..i * 8 + 8]);
+            }
+            ctl[fields * 8..].fill(0);
+            return Ok(true);
+        }
+        if !self.compact_seek {
+            let size = if self.target_copy { 40 } else { 24 };
+            ctl[size..].fill(0);
+            return read_control(r, &mut ctl[..size]);
+        }
+        if !read_control(r, &mut ctl[..16])? {
+            return Ok(false);
+        }
+        encode_int(read_varint(r)?, &mut ctl[16..24]);
+        if self.target_copy {
+            r.read_exact(&mut ctl[24..40])?;
+        } else {
+            ctl[24..].fill(0);
+        }
+        Ok(true)
+    }
+}
+
+/// Encode integer as zig-zag LEB128 varint, return the encoded size (at most
+/// 10 bytes).
+fn encode_varint(x: i64, b: &mut [u8]) -> usize {
+    let mut z = ((x << 1) ^ (x >> 63)) as u64;
+    let mut n = 0;
+    while z >= 0x80 {
+        b[n] = z as u8 | 0x80;
+        z >>= 7;
+        n += 1;
+    }
+    b[n] = z as u8;
+    n + 1
+}
+
+/// Read integer encoded as zig-zag LEB128 varint.
+fn read_varint<R: Read>(r: &mut R) -> Result<i64> {
+    let mut z = 0u64;
+    for i in 0..10 {
+        let mut b = [0];
+        r.read_exact(&mut b)?;
+        if i == 9 && b[0] > 1 {
+            break;
+        }
+        z |= ((b[0] & 0x7f) as u64) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok((z >> 1) as i64 ^ -((z & 1) as i64));
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "patch corrupted"))
+}
+
+/// Yields to the executor once.
+#[cfg(feature = "async")]
+pub fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/// Future returned by `yield_now`.
+#[cfg(feature = "async")]
+pub struct YieldNow(bool);
+
+#[cfg(feature = "async")]
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
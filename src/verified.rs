@@ -0,0 +1,127 @@
+#![forbid(unsafe_code)]
+
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use super::bspatch::Bspatch;
+use super::checksum::ChecksumKind;
+use super::files::TempFile;
+use super::input::InputFile;
+
+/// Apply a patch to the source file and replace the target file, only if the
+/// target matches the expected hash (requires feature `mmap`).
+///
+/// This is the safe-by-default way to apply a patch:
+/// * the patch header is validated, and the section checksums (if any)
+///   verified, before anything is written;
+/// * the source file is memory-mapped read-only, and rejected if its size
+///   differs from the one declared by the patch, if any (see
+///   `Bspatch::sizes`), while classic bsdiff 4.x patches declare none;
+/// * the target is written to a temporary file in the same directory, with
+///   its hash computed meanwhile by the `ChecksumKind` of `expected`, and the
+///   target size enforced (see `Bspatch::strict_size`);
+/// * the temporary file is synced to disk and atomically renamed to `target`
+///   only if the hash matches, and the original target is restored if the
+///   rename could not be made durable.
+///
+/// On any failure, the temporary file is removed and the target file is left
+/// untouched.
+/// It is fine for `source` and `target` to be the same file, but the source
+/// file must not be modified by others during patching.
+///
+/// Example:
+///
+/// Update a file in place by a patch of a manifest listing SHA-256 hashes:
+/// ```no_run
+/// use std::io;
+/// use qbsdiff::{apply_verified, ChecksumKind};
+///
+/// fn update(patch: &[u8], sha256: &[u8]) -> io::Result<u64> {
+///     apply_verified("app.bin", patch, (ChecksumKind::Sha256, sha256), "app.bin")
+/// }
+/// ```
+///
+/// Return `ErrorKind::InvalidInput` if the expected hash is not of the size
+/// of its algorithm, or `ErrorKind::Unsupported` if the algorithm is not
+/// compiled in.
+/// A target of mismatching hash is reported by `TargetHashMismatch`.
+/// The target data size would be returned if no error occurs.
+pub fn apply_verified<S, T>(source: S, patch: &[u8], expected: (ChecksumKind, &[u8]), target: T) -> Result<u64>
+where
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let (kind, expected) = expected;
+    if expected.len() != kind.size() {
+        return Err(Error::new(ErrorKind::InvalidInput, "expected hash of wrong size"));
+    }
+    let hasher = kind.hasher()?;
+    let patcher = Bspatch::new(patch)?;
+
+    let target = target.as_ref();
+    let source = InputFile::open(source)?;
+    let mut temp = TempFile::create(target)?;
+    temp.preallocate(patcher.hint_target_size());
+    let (hasher, size) = patcher.apply_hashed(&source[..], temp.file(), hasher, |hasher, data| hasher.update(data))?;
+    let actual = hasher.digest();
+    if actual != expected {
+        return Err(TargetHashMismatch {
+            kind,
+            expected: expected.to_vec(),
+            actual,
+        }
+        .into());
+    }
+
+    // Windows cannot replace files still mapped.
+    drop(source);
+    temp.replace(target)?;
+    Ok(size)
+}
+
+/// Target whose hash differs from the expected one, see `apply_verified`.
+///
+/// It is wrapped in `io::Error` of kind `InvalidData`, see
+/// `TargetHashMismatch::of`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TargetHashMismatch {
+    /// Hash algorithm.
+    pub kind: ChecksumKind,
+
+    /// Expected hash.
+    pub expected: Vec<u8>,
+
+    /// Hash of the target produced.
+    pub actual: Vec<u8>,
+}
+
+impl TargetHashMismatch {
+    /// Get the target hash mismatch of the error, if any.
+    pub fn of(err: &Error) -> Option<TargetHashMismatch> {
+        err.get_ref()?.downcast_ref::<TargetHashMismatch>().cloned()
+    }
+}
+
+impl fmt::Display for TargetHashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "target {:?} hash mismatch: ", self.kind)?;
+        for byte in self.actual.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str(" produced, ")?;
+        for byte in self.expected.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str(" expected")
+    }
+}
+
+impl error::Error for TargetHashMismatch {}
+
+impl From<TargetHashMismatch> for Error {
+    fn from(err: TargetHashMismatch) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
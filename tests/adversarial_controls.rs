@@ -0,0 +1,183 @@
+use std::io;
+
+use qbsdiff::{Bspatch, ControlFault, CorruptControl, PatchedReader, TargetSizeMismatch};
+use qbsdiff_test_bench_utils::*;
+
+fn encode_int(x: i64) -> [u8; 8] {
+    let y = if x < 0 { x.unsigned_abs() | 1 << 63 } else { x as u64 };
+    y.to_le_bytes()
+}
+
+/// Extended patch with stored sections.
+fn extended(ctrls: &[(i64, i64, i64)], delta: &[u8], extra: &[u8], tsize: u64) -> Vec<u8> {
+    let mut c = Vec::new();
+    for &(add, copy, seek) in ctrls.iter() {
+        c.extend_from_slice(&encode_int(add));
+        c.extend_from_slice(&encode_int(copy));
+        c.extend_from_slice(&encode_int(seek));
+    }
+    let mut patch = b"QBSDIFF2".to_vec();
+    patch.extend_from_slice(&[0; 8]);
+    for size in [c.len() as u64, delta.len() as u64, extra.len() as u64, tsize] {
+        patch.extend_from_slice(&size.to_le_bytes());
+    }
+    patch.extend_from_slice(&c);
+    patch.extend_from_slice(delta);
+    patch.extend_from_slice(extra);
+    patch
+}
+
+fn apply(source: &[u8], patch: &[u8], bs: usize, dm: usize) -> io::Result<Vec<u8>> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)?
+        .buffer_size(bs)
+        .delta_min(dm)
+        .apply(source, io::Cursor::new(&mut target))?;
+    Ok(target)
+}
+
+fn source() -> Vec<u8> {
+    hashed_bytes(0, 8192)
+}
+
+#[test]
+fn delta_cache_boundaries() {
+    let s = source();
+    for (bs, dm) in [(128, 128), (256, 128), (1000, 300), (4096, 1 << 20)] {
+        for add in [
+            1, 127, 128, 129, 255, 256, 257, 299, 300, 301, 999, 1000, 1001, 4096, 8192,
+        ] {
+            let delta: Vec<u8> = (0..add).map(|i| (i * 7) as u8).collect();
+            let ctrls = [(add as i64, 3, -(add as i64) / 2), (add as i64 / 2, 0, 0)];
+            let total = add + add / 2;
+            let mut d = delta.clone();
+            d.extend_from_slice(&delta[..add / 2]);
+            let patch = extended(&ctrls, &d, b"xyz", total as u64 + 3);
+
+            let mut expected: Vec<u8> = s[..add]
+                .iter()
+                .zip(delta.iter())
+                .map(|(x, y)| x.wrapping_add(*y))
+                .collect();
+            expected.extend_from_slice(b"xyz");
+            let start = add - add / 2;
+            expected.extend(
+                s[start..start + add / 2]
+                    .iter()
+                    .zip(delta.iter())
+                    .map(|(x, y)| x.wrapping_add(*y)),
+            );
+
+            assert_eq!(
+                apply(&s, &patch, bs, dm).unwrap(),
+                expected,
+                "bs {} dm {} add {}",
+                bs,
+                dm,
+                add
+            );
+        }
+    }
+}
+
+#[test]
+fn delta_cache_many_small_adds() {
+    let s = source();
+    let ctrls: Vec<_> = (0..20000).map(|i| (1 + i % 3, i % 2, -(i % 3) - 1)).collect();
+    let delta = vec![1; ctrls.iter().map(|c| c.0 as usize).sum()];
+    let extra = vec![9; ctrls.iter().map(|c| c.1 as usize).sum()];
+    let tsize = (delta.len() + extra.len()) as u64;
+    let patch = extended(&ctrls, &delta, &extra, tsize);
+    let t = apply(&s, &patch, 256, 128).unwrap();
+    assert_eq!(t.len() as u64, tsize);
+
+    let mut t1 = Vec::new();
+    io::copy(&mut PatchedReader::new(&s, &patch).unwrap(), &mut t1).unwrap();
+    assert_eq!(t, t1);
+}
+
+#[test]
+fn delta_cache_hostile_adds() {
+    let s = source();
+    for ctrls in [
+        vec![(i64::MAX, 0, 0)],
+        vec![(1 << 40, 0, 0)],
+        vec![(-1, 0, 0)],
+        vec![(i64::MIN + 1, 0, 0)],
+        vec![(100, 0, 0), (8192, 0, 0)],
+        vec![(0, 0, 8000), (1000, 0, 0)],
+    ] {
+        let patch = extended(&ctrls, &[0; 100], &[], 1 << 20);
+        for (bs, dm) in [(128, 128), (1 << 20, 1 << 20)] {
+            assert!(apply(&s, &patch, bs, dm).is_err(), "{:?}", ctrls);
+        }
+        let mut t = Vec::new();
+        let result = Bspatch::new(&patch)
+            .unwrap()
+            .apply_range(&s, 0..1000, io::Cursor::new(&mut t));
+        assert!(result.is_err(), "{:?}", ctrls);
+        if let Ok(mut reader) = PatchedReader::new(&s, &patch) {
+            assert!(io::copy(&mut reader, &mut io::sink()).is_err(), "{:?}", ctrls);
+        }
+    }
+
+    // empty source
+    let patch = extended(&[(10, 0, 0)], &[0; 10], &[], 10);
+    assert!(apply(&[], &patch, 128, 128).is_err());
+}
+
+#[test]
+fn corrupt_control_index() {
+    let s = source();
+    for (ctrls, tsize, index, fault) in [
+        (vec![(1, 0, 0), (-1, 0, 0)], 10, 1, ControlFault::NegativeLength),
+        (vec![(1, 1, 0), (0, -5, 0)], 10, 1, ControlFault::NegativeLength),
+        (vec![(4, 4, 0), (1, 2, 0)], 10, 1, ControlFault::TargetOverflow),
+        (vec![(i64::MAX, i64::MAX, 0)], 10, 0, ControlFault::TargetOverflow),
+        (vec![(0, 1, 8190), (3, 0, 0)], 10, 1, ControlFault::SourceOutOfBounds),
+        (vec![(1, 0, 0), (1, 0, -3)], 10, 1, ControlFault::SeekOutOfRange),
+        (vec![(0, 1, i64::MAX), (0, 0, 1)], 10, 1, ControlFault::SeekOutOfRange),
+    ] {
+        let patch = extended(&ctrls, &[0; 10], &[0; 10], tsize);
+        let err = apply(&s, &patch, 128, 128).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", ctrls);
+        assert_eq!(
+            CorruptControl::of(&err),
+            Some(CorruptControl { index, fault }),
+            "{:?}",
+            ctrls
+        );
+    }
+
+    // Delta of empty source.
+    let patch = extended(&[(10, 0, 0)], &[0; 10], &[], 10);
+    let err = apply(&[], &patch, 128, 128).unwrap_err();
+    let corrupt = CorruptControl::of(&err).unwrap();
+    assert_eq!(corrupt.fault, ControlFault::SourceOutOfBounds);
+}
+
+#[test]
+fn target_size_mismatch() {
+    let s = source();
+    for (ctrls, source) in [(vec![(3, 2, 0)], &s[..]), (vec![(0, 5, 0)], &[][..])] {
+        let patch = extended(&ctrls, &[0; 3], &[1; 5], 10);
+        let err = apply(source, &patch, 128, 128).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            TargetSizeMismatch::of(&err),
+            Some(TargetSizeMismatch {
+                expected: 10,
+                actual: 5
+            })
+        );
+
+        let mut target = Vec::new();
+        let size = Bspatch::new(&patch)
+            .unwrap()
+            .strict_size(false)
+            .apply(source, io::Cursor::new(&mut target))
+            .unwrap();
+        assert_eq!(size, 5);
+        assert_eq!(target.len(), 5);
+    }
+}
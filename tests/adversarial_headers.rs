@@ -0,0 +1,76 @@
+use std::io;
+
+use qbsdiff::{inspect, Bspatch, PartialPatch, PatchError, PatchedReader, Tolerance};
+
+fn encode_int(x: i64) -> [u8; 8] {
+    let y = if x < 0 { x.unsigned_abs() | 1 << 63 } else { x as u64 };
+    y.to_le_bytes()
+}
+
+fn classic(csize: i64, dsize: i64, tsize: i64) -> Vec<u8> {
+    let mut patch = b"BSDIFF40".to_vec();
+    patch.extend_from_slice(&encode_int(csize));
+    patch.extend_from_slice(&encode_int(dsize));
+    patch.extend_from_slice(&encode_int(tsize));
+    patch.resize(64, 0);
+    patch
+}
+
+fn extended(csize: u64, dsize: u64, esize: u64) -> Vec<u8> {
+    let mut patch = b"QBSDIFF2".to_vec();
+    patch.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+    patch.extend_from_slice(&csize.to_le_bytes());
+    patch.extend_from_slice(&dsize.to_le_bytes());
+    patch.extend_from_slice(&esize.to_le_bytes());
+    patch.extend_from_slice(&0u64.to_le_bytes());
+    patch.resize(64, 0);
+    patch
+}
+
+fn reasons(patch: &[u8]) -> Vec<Option<PatchError>> {
+    let err = |r: io::Result<()>| PatchError::of(&r.unwrap_err());
+    vec![
+        err(Bspatch::new(patch).map(drop)),
+        err(Bspatch::with_tolerance(patch, Tolerance::Lenient).map(drop)),
+        err(PatchedReader::new(&[], patch).map(drop)),
+        err(inspect::regions(patch).map(drop)),
+    ]
+}
+
+#[test]
+fn negative_section_sizes() {
+    for patch in [
+        classic(-1, 0, 0),
+        classic(0, -1, 0),
+        classic(0, 0, -1),
+        classic(i64::MIN, 0, 0),
+        classic(-32, 16, 16),
+    ] {
+        for reason in reasons(&patch) {
+            assert_eq!(reason, Some(PatchError::NegativeSectionSize));
+        }
+        let err = PartialPatch::check(&patch).unwrap_err();
+        assert_eq!(PatchError::of(&err), Some(PatchError::NegativeSectionSize));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn overflowing_section_sizes() {
+    for patch in [
+        classic(i64::MAX, 0, 0),
+        classic(i64::MAX, i64::MAX, 0),
+        classic(33, 0, 0),
+        extended(u64::MAX, 1, 0),
+        extended(u64::MAX - 8, 0, 0),
+        extended(0, 0, 17),
+    ] {
+        for reason in reasons(&patch) {
+            assert_eq!(reason, Some(PatchError::SectionOverflow));
+        }
+    }
+    for patch in [classic(i64::MAX, i64::MAX, 0), extended(u64::MAX, 1, 0)] {
+        let err = PartialPatch::check(&patch).unwrap_err();
+        assert_eq!(PatchError::of(&err), Some(PatchError::SectionOverflow));
+    }
+}
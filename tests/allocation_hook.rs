@@ -0,0 +1,71 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use qbsdiff::{Allocation, Bsdiff, Bspatch, ParallelScheme, SourceIndex};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 600 * 1000);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(5003) {
+        t[i] ^= 0x3c;
+    }
+    (s, t)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn allocation_hook_reports() {
+    let (s, t) = sample();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let hooked = log.clone();
+    let p = Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::ChunkSize(256 * 1024))
+        .allocation_hook(move |alloc| {
+            hooked.lock().unwrap().push(alloc);
+            Ok(())
+        })
+        .compare_to_vec()
+        .unwrap();
+    assert!(apply(&s, &p) == t);
+
+    let log = log.lock().unwrap();
+    assert_eq!(log[0], Allocation::SuffixArray((s.len() + 1) * 4));
+    assert!(matches!(log[1], Allocation::Buckets(_)));
+    assert!(log.iter().any(|a| matches!(a, Allocation::Controls(size) if *size > 0)));
+    assert_eq!(*log.last().unwrap(), Allocation::Patch(p.len()));
+
+    // prebuilt index is not reported
+    let index = SourceIndex::new(&s);
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let hooked = log.clone();
+    Bsdiff::with_index(&index, &t)
+        .allocation_hook(move |alloc| {
+            hooked.lock().unwrap().push(alloc);
+            Ok(())
+        })
+        .compare_reader(&t[..], 0, io::sink())
+        .unwrap();
+    let log = log.lock().unwrap();
+    assert!(matches!(log[..], [Allocation::Window(_)]), "{:?}", log);
+}
+
+#[test]
+fn allocation_hook_bounds() {
+    let (s, t) = sample();
+    let err = Bsdiff::new(&s, &t)
+        .allocation_hook(|alloc| {
+            if alloc.size() > 1 << 20 {
+                return Err(io::Error::new(io::ErrorKind::OutOfMemory, "memory budget exceeded"));
+            }
+            Ok(())
+        })
+        .compare(io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+}
@@ -0,0 +1,69 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+/// Positions of record tags.
+fn tags(data: &[u8]) -> Vec<usize> {
+    data.windows(2)
+        .enumerate()
+        .filter(|(_, w)| w == b"\xfe\xed")
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Serialized record of given id with similar-looking fields, with a few
+/// inserted bytes if edited.
+fn record(id: u32, edited: bool) -> Vec<u8> {
+    let h = id.wrapping_mul(2654435761);
+    let mut r = b"\xfe\xed".to_vec();
+    r.extend_from_slice(&id.to_le_bytes());
+    for field in 0..6u8 {
+        r.extend_from_slice(&[0x08 + field, 4]);
+        r.extend_from_slice(&(h >> (field * 3) & 0x0f0f).to_le_bytes());
+    }
+    if edited {
+        let at = 8 + (h % 24) as usize;
+        r.splice(at..at, [0x30, (h >> 8) as u8 & 0x0f]);
+    }
+    r
+}
+
+#[test]
+fn anchors_small_insertions() {
+    let s: Vec<u8> = (0..20_000).flat_map(|id| record(id, false)).collect();
+    let t: Vec<u8> = (0..20_000).flat_map(|id| record(id, id % 7 == 0)).collect();
+
+    let plain = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    let anchored = Bsdiff::new(&s, &t).anchors(tags).compare_to_vec().unwrap();
+    assert!(apply(&s, &anchored) == t);
+    assert!(
+        anchored.len() * 5 < plain.len() * 4,
+        "{} vs {}",
+        anchored.len(),
+        plain.len()
+    );
+}
+
+#[test]
+fn anchors_degenerate() {
+    let s: Vec<u8> = (0..1000).flat_map(|id| record(id, false)).collect();
+    let t: Vec<u8> = (0..1000).rev().flat_map(|id| record(id, id % 5 == 0)).collect();
+    for anchors in [Vec::new(), vec![0, usize::MAX, 3, 3, 1 << 20]] {
+        for (s, t) in [(&s[..], &t[..]), (&[][..], &t[..]), (&s[..], &[][..])] {
+            let anchors = anchors.clone();
+            let p = Bsdiff::new(s, t)
+                .anchors(move |_| anchors.clone())
+                .compare_to_vec()
+                .unwrap();
+            assert!(apply(s, &p) == t);
+        }
+    }
+    let p = Bsdiff::new(&s, &t).anchors(tags).compare_to_vec().unwrap();
+    assert!(apply(&s, &p) == t);
+}
@@ -0,0 +1,64 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+/// Ledger entries of the given range, with pseudo-random fields.
+fn ledger(range: std::ops::Range<u32>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in range {
+        let h = i.wrapping_mul(2654435761);
+        data.extend_from_slice(format!("{:08} acct={:08x} amount={}.{:02}\n", i, h, h % 10000, h % 100).as_bytes());
+    }
+    data
+}
+
+#[test]
+fn append_mostly_roundtrip() {
+    let s = ledger(0..40000);
+    let mut appended = s.clone();
+    appended.extend_from_slice(&ledger(40000..40500));
+    let mut rewritten = appended.clone();
+    let n = s.len() - 100;
+    rewritten[n..n + 8].copy_from_slice(b"REWRITE!");
+    let mut early = appended.clone();
+    early[10..18].copy_from_slice(b"CHANGED!");
+
+    for t in [
+        appended.clone(),
+        rewritten,
+        early,
+        s[..s.len() / 2].to_vec(),
+        Vec::new(),
+    ] {
+        let p = Bsdiff::new(&s, &t).append_mostly(true).compare_to_vec().unwrap();
+        assert!(apply(&s, &p) == t);
+    }
+
+    // about as small as plain matching for appended data
+    let fast = Bsdiff::new(&s, &appended)
+        .append_mostly(true)
+        .compare_report(io::sink())
+        .unwrap();
+    let plain = Bsdiff::new(&s, &appended).compare_report(io::sink()).unwrap();
+    assert!(
+        fast.patch_size() * 5 <= plain.patch_size() * 6,
+        "{} vs {}",
+        fast.patch_size(),
+        plain.patch_size()
+    );
+    assert!(fast.search_steps() < plain.search_steps() / 10);
+
+    let report = Bsdiff::new(&s, &appended)
+        .append_mostly(true)
+        .analyze(true)
+        .compare_report(io::sink())
+        .unwrap();
+    let similarity = report.similarity().unwrap();
+    assert!(similarity > 0.9 && similarity < 1.0, "{}", similarity);
+}
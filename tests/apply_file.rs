@@ -0,0 +1,148 @@
+#![cfg(feature = "mmap")]
+
+use std::env;
+use std::fs;
+use std::path;
+use std::thread;
+
+use qbsdiff::Bspatch;
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_apply_file() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+    let dir = env::temp_dir().join("qbsdiff-test");
+    fs::create_dir_all(dir.as_path()).unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("apply file test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+        let tpath = dir.join(format!("{}.t", sample.name));
+        Bspatch::new(&p[..])
+            .unwrap()
+            .apply_file(sample.source.as_path(), tpath.as_path())
+            .unwrap();
+        let t1 = fs::read(tpath.as_path()).unwrap();
+        fs::remove_file(tpath.as_path()).unwrap();
+        if t != t1 {
+            panic!("apply file failed: `{}`", sample.name);
+        }
+    }
+}
+
+#[test]
+fn apply_file_in_place() {
+    let dir = env::temp_dir().join("qbsdiff-test");
+    fs::create_dir_all(dir.as_path()).unwrap();
+    let path = dir.join("in-place");
+    let s = b"the quick brown fox jumps over the lazy dog";
+    let t = b"the quick brown cat jumps over the lazy dog!";
+    fs::write(path.as_path(), &s[..]).unwrap();
+
+    let testing = Testing::new(dir.clone());
+    let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+    Bspatch::new(&p[..])
+        .unwrap()
+        .apply_file(path.as_path(), path.as_path())
+        .unwrap();
+    let t1 = fs::read(path.as_path()).unwrap();
+    fs::remove_file(path.as_path()).unwrap();
+    assert_eq!(&t1[..], &t[..]);
+}
+
+#[test]
+fn apply_to_path_concurrently() {
+    let dir = env::temp_dir().join(format!("qbsdiff-test-concurrent-{}", std::process::id()));
+    fs::create_dir_all(dir.as_path()).unwrap();
+    let path = dir.join("target");
+    let s = hashed_bytes(0, 1 << 18);
+    let mut t = s.clone();
+    t[1000..2000].fill(0);
+
+    let testing = Testing::new(dir.clone());
+    let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                Bspatch::new(&p[..])
+                    .unwrap()
+                    .apply_to_path(&s[..], path.as_path())
+                    .unwrap()
+            });
+        }
+    });
+    assert!(fs::read(path.as_path()).unwrap() == t);
+
+    // no temporary file left behind
+    let names: Vec<_> = fs::read_dir(dir.as_path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["target"]);
+    fs::remove_dir_all(dir.as_path()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn apply_to_path_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join("qbsdiff-test");
+    fs::create_dir_all(dir.as_path()).unwrap();
+    let path = dir.join("permissions");
+    let s = b"#!/bin/sh\necho the quick brown fox\n";
+    let t = b"#!/bin/sh\necho the quick brown cat\n";
+    fs::write(path.as_path(), &s[..]).unwrap();
+    fs::set_permissions(path.as_path(), fs::Permissions::from_mode(0o750)).unwrap();
+
+    let testing = Testing::new(dir.clone());
+    let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+    Bspatch::new(&p[..])
+        .unwrap()
+        .apply_to_path(&s[..], path.as_path())
+        .unwrap();
+    let mode = fs::metadata(path.as_path()).unwrap().permissions().mode();
+    let t1 = fs::read(path.as_path()).unwrap();
+    fs::remove_file(path.as_path()).unwrap();
+    assert_eq!(&t1[..], &t[..]);
+    assert_eq!(mode & 0o777, 0o750);
+}
+
+#[test]
+fn apply_to_mmap() {
+    let dir = env::temp_dir().join("qbsdiff-test");
+    fs::create_dir_all(dir.as_path()).unwrap();
+    let path = dir.join("mmap-target");
+    let s = hashed_bytes(0, 1 << 20);
+    let mut t = s.clone();
+    t[1000..2000].fill(0);
+    t.extend_from_slice(b"appended");
+
+    let testing = Testing::new(dir.clone());
+    for target in [&t[..], &[]] {
+        let p = testing.qbsdiff(&s[..], target).unwrap();
+        let size = Bspatch::new(&p[..])
+            .unwrap()
+            .apply_to_mmap(&s[..], path.as_path())
+            .unwrap();
+        assert_eq!(size, target.len() as u64);
+        let t1 = fs::read(path.as_path()).unwrap();
+        fs::remove_file(path.as_path()).unwrap();
+        assert!(t1 == target);
+    }
+
+    // target size in header larger than produced
+    let mut p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+    p[24] += 1;
+    let err = Bspatch::new(&p[..])
+        .unwrap()
+        .apply_to_mmap(&s[..], path.as_path())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(!path.exists());
+}
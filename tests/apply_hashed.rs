@@ -0,0 +1,45 @@
+use std::hash::{DefaultHasher, Hasher};
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Format};
+
+#[test]
+fn apply_hashed_target() {
+    let s: Vec<u8> = (0..300 * 1000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(4099) {
+        t[i] = t[i].wrapping_add(7);
+    }
+    t.extend_from_slice(b"appended tail");
+
+    let mut expected = DefaultHasher::new();
+    expected.write(&t[..]);
+    let expected = expected.finish();
+
+    for format in [Format::Classic, Format::Extended] {
+        let p = Bsdiff::new(&s, &t).format(format).compare_to_vec().unwrap();
+        let mut t1 = Vec::new();
+        let mut chunks = 0;
+        let ((hasher, fed), size) = Bspatch::new(&p)
+            .unwrap()
+            .buffer_size(4096)
+            .apply_hashed(
+                &s,
+                io::Cursor::new(&mut t1),
+                (DefaultHasher::new(), 0u64),
+                |(hasher, fed), data| {
+                    hasher.write(data);
+                    *fed += data.len() as u64;
+                    chunks += 1;
+                },
+            )
+            .unwrap();
+        assert!(t1 == t);
+        assert_eq!(size, t.len() as u64);
+        assert_eq!(fed, size);
+        assert_eq!(hasher.finish(), expected);
+        assert!(chunks > 1);
+    }
+}
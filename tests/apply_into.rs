@@ -0,0 +1,48 @@
+use std::io::ErrorKind;
+
+use qbsdiff::{Bsdiff, Bspatch, Format, TargetSizeMismatch};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s[20000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+#[test]
+fn apply_into_buffer() {
+    let (s, t) = sample();
+    for format in [Format::Classic, Format::Extended] {
+        let p = Bsdiff::new(&s, &t).format(format).compare_to_vec().unwrap();
+        let patcher = Bspatch::new(&p).unwrap();
+        assert_eq!(patcher.hint_target_size(), t.len() as u64);
+
+        let mut buf = vec![0xee; t.len() + 100];
+        let n = patcher.buffer_size(4096).apply_into(&s, &mut buf).unwrap();
+        assert_eq!(n, t.len());
+        assert!(buf[..n] == t[..]);
+        assert!(buf[n..].iter().all(|&x| x == 0xee));
+
+        let mut buf = vec![0; t.len() - 1];
+        let err = Bspatch::new(&p).unwrap().apply_into(&s, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    let p = Bsdiff::new(&s, &[]).compare_to_vec().unwrap();
+    assert_eq!(Bspatch::new(&p).unwrap().apply_into(&s, &mut []).unwrap(), 0);
+}
+
+#[test]
+fn apply_into_short_target() {
+    let (s, t) = sample();
+    let mut p = Bsdiff::new(&s, &t).format(Format::Extended).compare_to_vec().unwrap();
+    // claim a larger target than the controls produce
+    let tsize = u64::from_le_bytes(p[40..48].try_into().unwrap());
+    p[40..48].copy_from_slice(&(tsize + 10).to_le_bytes());
+    let mut buf = vec![0; t.len() + 10];
+    let err = Bspatch::new(&p).unwrap().apply_into(&s, &mut buf).unwrap_err();
+    assert!(TargetSizeMismatch::of(&err).is_some());
+}
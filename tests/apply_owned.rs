@@ -0,0 +1,41 @@
+#![cfg(feature = "bytes")]
+use std::io;
+use std::thread;
+
+use bytes::Bytes;
+use qbsdiff::bspatch::OwnedBspatch;
+use qbsdiff::{Bsdiff, PatchProfile};
+
+#[test]
+fn apply_owned_in_thread() {
+    let source: Vec<u8> = (0..1 << 16)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 9) as u8)
+        .collect();
+    let mut target = source.clone();
+    target[4000..4100].fill(7);
+    target.extend_from_slice(b"appended");
+    let patch = Bsdiff::new(&source, &target).compare_to_vec().unwrap();
+
+    let (source, patch) = (Bytes::from(source), Bytes::from(patch));
+    let patcher = OwnedBspatch::new(patch.clone()).unwrap().profile(&PatchProfile {
+        buffer_size: 256,
+        delta_min: 128,
+        ..PatchProfile::default()
+    });
+    assert_eq!(patcher.hint_target_size(), target.len() as u64);
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let (patcher, source) = (patcher.clone(), source.clone());
+            thread::spawn(move || {
+                let mut t = Vec::new();
+                patcher.apply_owned(source, io::Cursor::new(&mut t)).map(|_| t)
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().unwrap() == target);
+    }
+
+    let err = OwnedBspatch::new(patch.slice(..20)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
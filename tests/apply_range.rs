@@ -0,0 +1,42 @@
+use std::io;
+use std::path;
+
+use qbsdiff::{Bspatch, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_apply_range() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("apply_range test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec: Codec::Stored,
+            ..QbsdiffOptions::default()
+        };
+        let p = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+
+        let n = t.len() as u64;
+        let ranges = [0..n, 0..0, n..n, 0..n / 3, n / 3..n / 2, n / 2..n, n / 2..n / 2 + 1];
+        for range in ranges.iter().cloned() {
+            let mut t1 = Vec::new();
+            let size = Bspatch::new(&p[..])
+                .unwrap()
+                .buffer_size(4096)
+                .apply_range(&s[..], range.clone(), io::Cursor::new(&mut t1))
+                .unwrap();
+            assert_eq!(size, range.end - range.start);
+            if t1[..] != t[range.start as usize..range.end as usize] {
+                panic!("apply_range {:?} failed: `{}`", range, sample.name);
+            }
+        }
+
+        let patcher = Bspatch::new(&p[..]).unwrap();
+        assert!(patcher.apply_range(&s[..], 0..n + 1, io::sink()).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use qbsdiff::{inspect, ApplyStats, Bsdiff, Bspatch, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 200 * 1000);
+    let mut t = s[20000..120000].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    t.extend(b"appended data".iter().cycle().take(5000));
+    (s, t)
+}
+
+fn apply_stats(s: &[u8], p: &[u8], bspatch: impl FnOnce(Bspatch) -> Bspatch) -> (io::Result<u64>, ApplyStats) {
+    let stats = Arc::new(Mutex::new(None));
+    let slot = stats.clone();
+    let result = bspatch(Bspatch::new(p).unwrap())
+        .on_stats(move |stats| *slot.lock().unwrap() = Some(*stats))
+        .apply(s, io::sink());
+    let stats = stats.lock().unwrap().take().unwrap();
+    (result, stats)
+}
+
+#[test]
+fn apply_stats_counters() {
+    let (s, t) = sample();
+    for (format, target_copy) in [
+        (Format::Classic, false),
+        (Format::Extended, false),
+        (Format::Extended, true),
+    ] {
+        let p = Bsdiff::new(&s, &t)
+            .format(format)
+            .target_copy(target_copy)
+            .compare_to_vec()
+            .unwrap();
+        let summary = inspect::validate(&p, None).unwrap();
+        for vectored in [false, true] {
+            let (result, stats) = apply_stats(&s, &p, |bspatch| bspatch.vectored_writes(vectored));
+            assert_eq!(result.unwrap(), t.len() as u64);
+            assert_eq!(stats.target_size(), t.len() as u64);
+            assert_eq!(stats.controls, summary.controls);
+            assert_eq!(stats.decoded_sizes, summary.section_sizes);
+            assert_eq!(stats.added, summary.section_sizes[1]);
+            assert_eq!(stats.copied, summary.section_sizes[2]);
+            assert_eq!(stats.repeated > 0, target_copy);
+            assert_eq!(stats.source_span, summary.source_span);
+            assert!(stats.encoded_sizes.iter().sum::<u64>() < p.len() as u64);
+            let ratios = stats.decompression_ratios();
+            assert!(ratios[1] > 1.0 && ratios.iter().all(|&ratio| ratio >= 0.0));
+        }
+    }
+}
+
+#[test]
+fn apply_stats_on_failure() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    let (result, stats) = apply_stats(&s[..50000], &p, |bspatch| bspatch);
+    assert!(result.is_err());
+    assert!(stats.controls > 0);
+    assert!(stats.target_size() < t.len() as u64);
+    assert!(stats.source_span <= 50000);
+
+    // empty source
+    let p = Bsdiff::new(&[], &t).compare_to_vec().unwrap();
+    let (result, stats) = apply_stats(&[], &p, |bspatch| bspatch);
+    assert_eq!(result.unwrap(), t.len() as u64);
+    assert_eq!(stats.copied, t.len() as u64);
+    assert_eq!(stats.decoded_sizes[2], t.len() as u64);
+    assert_eq!(stats.source_span, 0);
+}
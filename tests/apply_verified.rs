@@ -0,0 +1,108 @@
+#![cfg(feature = "mmap")]
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use qbsdiff::{apply_verified, Bsdiff, ChecksumKind, TargetHashMismatch};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s[20000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join("qbsdiff-test").join(name);
+    let _ = fs::remove_dir_all(dir.as_path());
+    fs::create_dir_all(dir.as_path()).unwrap();
+    dir
+}
+
+/// Names of the files in the directory, no temporary files should be left.
+fn listing(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn crc32(source: &Path, patch: &[u8], expected: &[u8], target: &Path) -> io::Result<u64> {
+    apply_verified(source, patch, (ChecksumKind::Crc32, expected), target)
+}
+
+#[test]
+fn apply_verified_replaces_target() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let crc = ChecksumKind::Crc32.digest(&t[..]).unwrap();
+    let dir = test_dir("apply-verified");
+    let (spath, tpath) = (dir.join("source"), dir.join("target"));
+    fs::write(spath.as_path(), &s[..]).unwrap();
+
+    // new target
+    let size = crc32(&spath, &p[..], &crc[..], &tpath).unwrap();
+    assert_eq!(size, t.len() as u64);
+    assert!(fs::read(tpath.as_path()).unwrap() == t);
+
+    // existing target
+    fs::write(tpath.as_path(), b"old target").unwrap();
+    crc32(&spath, &p[..], &crc[..], &tpath).unwrap();
+    assert!(fs::read(tpath.as_path()).unwrap() == t);
+
+    // in place
+    crc32(&spath, &p[..], &crc[..], &spath).unwrap();
+    assert!(fs::read(spath.as_path()).unwrap() == t);
+    assert_eq!(listing(&dir), ["source", "target"]);
+}
+
+#[test]
+fn apply_verified_rollback() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let crc = ChecksumKind::Crc32.digest(&t[..]).unwrap();
+    let dir = test_dir("apply-verified-rollback");
+    let (spath, tpath) = (dir.join("source"), dir.join("target"));
+    fs::write(spath.as_path(), &s[..]).unwrap();
+    fs::write(tpath.as_path(), b"old target").unwrap();
+    let untouched = || {
+        assert_eq!(fs::read(tpath.as_path()).unwrap(), b"old target");
+        assert_eq!(listing(&dir), ["source", "target"]);
+    };
+
+    // mismatching hash
+    let wrong = [0u8; 4];
+    let err = crc32(&spath, &p[..], &wrong[..], &tpath).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let mismatch = TargetHashMismatch::of(&err).unwrap();
+    assert_eq!((mismatch.expected, mismatch.actual), (wrong.to_vec(), crc.clone()));
+    untouched();
+
+    // expected hash of wrong size
+    let err = crc32(&spath, &p[..], &crc[..2], &tpath).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    untouched();
+
+    // corrupted patch
+    let err = crc32(&spath, &p[..20], &crc[..], &tpath).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    untouched();
+
+    // truncated source
+    fs::write(spath.as_path(), &s[..50000]).unwrap();
+    let err = crc32(&spath, &p[..], &crc[..], &tpath).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof));
+    untouched();
+
+    // missing source
+    fs::remove_file(spath.as_path()).unwrap();
+    let err = crc32(&spath, &p[..], &crc[..], &tpath).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(listing(&dir), ["target"]);
+}
@@ -0,0 +1,85 @@
+use std::io;
+
+use qbsdiff::archive::{Archive, ArchiveWriter};
+use qbsdiff::Bsdiff;
+
+fn version(v: u32) -> Vec<u8> {
+    (0..50 * 1000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8 ^ (i % 997 == v) as u8)
+        .collect()
+}
+
+fn diff(s: &[u8], t: &[u8]) -> Vec<u8> {
+    Bsdiff::new(s, t).compare_to_vec().unwrap()
+}
+
+fn apply(archive: &Archive, id: &str, s: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    archive.apply(id, s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn archive_append_and_retrieve() {
+    let (v1, v2, v3) = (version(1), version(2), version(3));
+
+    let mut writer = ArchiveWriter::new(io::Cursor::new(Vec::new())).unwrap();
+    writer.add("app/1-2", &diff(&v1, &v2)).unwrap();
+    writer.add("app/1-3", &diff(&v1, &v1)).unwrap();
+    let data = writer.finish().unwrap().into_inner();
+
+    let archive = Archive::parse(&data).unwrap();
+    assert_eq!(archive.len(), 2);
+    assert!(apply(&archive, "app/1-2", &v1) == v2);
+    let entry = archive.get("app/1-2").unwrap();
+    let (start, end) = (entry.offset() as usize, (entry.offset() + entry.patch_size()) as usize);
+    assert!(data[start..end] == *entry.patch());
+    assert_eq!(entry.target_size(), v2.len() as u64);
+    let err = archive.apply("app/0-1", &v1, io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    // append, superseding an entry
+    let mut cursor = io::Cursor::new(data.clone());
+    let mut writer = ArchiveWriter::append(&mut cursor).unwrap();
+    assert_eq!(writer.len(), 2);
+    writer.add("app/2-3", &diff(&v2, &v3)).unwrap();
+    writer.add("app/1-3", &diff(&v1, &v3)).unwrap();
+    writer.finish().unwrap();
+    let appended = cursor.into_inner();
+    assert!(appended[..data.len()] == data[..]);
+
+    let archive = Archive::parse(&appended).unwrap();
+    let ids: Vec<&str> = archive.entries().iter().map(|e| e.id()).collect();
+    assert_eq!(ids, ["app/1-2", "app/1-3", "app/2-3"]);
+    assert!(apply(&archive, "app/1-2", &v1) == v2);
+    assert!(apply(&archive, "app/1-3", &v1) == v3);
+    assert!(apply(&archive, "app/2-3", &v2) == v3);
+
+    // the previous version is still readable
+    assert_eq!(Archive::parse(&appended[..data.len()]).unwrap().len(), 2);
+}
+
+#[test]
+fn archive_rejects_invalid() {
+    let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+    assert!(writer.add("", &diff(b"a", b"b")).is_err());
+    assert!(writer.add("x", b"not a patch").is_err());
+    assert!(writer.is_empty());
+    writer.add("x", &diff(b"source", b"target")).unwrap();
+    let data = writer.finish().unwrap();
+
+    for n in 0..data.len() {
+        let err = Archive::parse(&data[..n]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+    for i in 0..data.len() {
+        let mut corrupted = data.clone();
+        corrupted[i] ^= 0x80;
+        if let Ok(archive) = Archive::parse(&corrupted) {
+            for entry in archive.entries() {
+                let _ = archive.apply(entry.id(), b"source", io::sink());
+            }
+        }
+    }
+    assert!(ArchiveWriter::append(io::Cursor::new(data[..data.len() - 1].to_vec())).is_err());
+}
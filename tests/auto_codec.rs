@@ -0,0 +1,92 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, CodecPriority, DiffProfile, Format};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+fn random(n: usize) -> Vec<u8> {
+    let mut x = 0x9e37_79b9_7f4a_7c15u64;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        })
+        .collect()
+}
+
+/// Codec identifiers of the control, delta and extra sections in the
+/// extended header.
+fn codecs(p: &[u8]) -> [u8; 3] {
+    assert_eq!(&p[..8], b"QBSDIFF2");
+    [p[12], p[13], p[14]]
+}
+
+fn bsdiff(s: &[u8], t: &[u8], priority: CodecPriority) -> Vec<u8> {
+    Bsdiff::new(s, t)
+        .format(Format::Extended)
+        .auto_codec(Some(priority))
+        .compare_to_vec()
+        .unwrap()
+}
+
+#[test]
+fn auto_codec_roundtrip() {
+    let s: Vec<u8> = (0..256 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(1000) {
+        t[i] = t[i].wrapping_add(1);
+    }
+    t.splice(100_000..100_000, random(50_000));
+
+    for priority in [
+        CodecPriority::Smallest,
+        CodecPriority::Balanced,
+        CodecPriority::FastestApply,
+    ] {
+        let p = bsdiff(&s[..], &t[..], priority);
+        assert_eq!(apply(&s[..], &p[..]), t);
+    }
+}
+
+#[test]
+fn auto_codec_choices() {
+    // the delta section is mostly zeros, never worth storing
+    let s: Vec<u8> = (0..256 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(1000) {
+        t[i] = t[i].wrapping_add(1);
+    }
+    let p = bsdiff(&s[..], &t[..], CodecPriority::Smallest);
+    assert_ne!(codecs(&p[..])[1], 0);
+    let p = bsdiff(&s[..], &t[..], CodecPriority::FastestApply);
+    assert_ne!(codecs(&p[..])[1], 0);
+
+    // random new data is stored unless the smallest patch is requested
+    let t = random(100_000);
+    let p = bsdiff(&[], &t[..], CodecPriority::FastestApply);
+    assert_eq!(codecs(&p[..])[2], 0);
+    let p = bsdiff(&[], &t[..], CodecPriority::Balanced);
+    assert_eq!(codecs(&p[..])[2], 0);
+    assert_eq!(apply(&[], &p[..]), t);
+}
+
+#[test]
+fn auto_codec_classic() {
+    let err = Bsdiff::new(b"source", b"target")
+        .auto_codec(Some(CodecPriority::Balanced))
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let profile = DiffProfile {
+        auto_codec: Some(CodecPriority::Balanced),
+        ..DiffProfile::default()
+    };
+    assert_eq!(profile.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,88 @@
+use std::io;
+
+use qbsdiff::batch::{diff_many, diff_many_with, Job};
+use qbsdiff::{Bspatch, Codec, DiffProfile, Format, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+fn sample(n: usize, seed: u32) -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(seed, n);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(7919) {
+        t[i] ^= 0x5a;
+    }
+    t.extend_from_slice(b"appended");
+    (s, t)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn batch_diff_many() {
+    let files: Vec<_> = [100, 0, 5000, 1 << 20, 3000, 600 * 1024]
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| sample(n, i as u32))
+        .collect();
+
+    let mut patches = vec![Vec::new(); files.len()];
+    let jobs = files
+        .iter()
+        .zip(patches.iter_mut())
+        .map(|((s, t), p)| Job::new(&s[..], &t[..], io::Cursor::new(p)))
+        .collect();
+    let results = diff_many(jobs);
+    assert_eq!(results.len(), files.len());
+    for (((s, t), p), result) in files.iter().zip(patches.iter()).zip(results) {
+        assert_eq!(result.unwrap(), p.len() as u64);
+        assert!(apply(s, p) == *t);
+    }
+
+    // same patches as sequential single-threaded runs
+    let profile = DiffProfile {
+        parallel_scheme: ParallelScheme::Never,
+        format: Format::Extended,
+        codec: Codec::Stored,
+        compression_level: 0,
+        ..DiffProfile::default()
+    };
+    let mut patches = vec![Vec::new(); files.len()];
+    let jobs = files
+        .iter()
+        .zip(patches.iter_mut())
+        .map(|((s, t), p)| Job::new(&s[..], &t[..], io::Cursor::new(p)))
+        .collect();
+    for result in diff_many_with(jobs, &profile) {
+        result.unwrap();
+    }
+    for ((s, t), p) in files.iter().zip(patches.iter()) {
+        let mut expected = Vec::new();
+        qbsdiff::Bsdiff::new(s, t)
+            .profile(&profile)
+            .compare(io::Cursor::new(&mut expected))
+            .unwrap();
+        assert!(*p == expected);
+    }
+}
+
+#[test]
+fn batch_errors_are_per_job() {
+    let (s, t) = sample(4096, 1);
+    let profile = DiffProfile {
+        source_checksum: 1024,
+        ..DiffProfile::default()
+    };
+    let jobs = vec![
+        Job::new(&s[..], &t[..], io::sink()),
+        Job::new(&s[..], &t[..], io::sink()),
+    ];
+    let results = diff_many_with(jobs, &profile);
+    assert!(results
+        .iter()
+        .all(|r| r.as_ref().unwrap_err().kind() == io::ErrorKind::InvalidInput));
+
+    assert!(diff_many::<io::Sink>(Vec::new()).is_empty());
+}
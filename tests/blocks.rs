@@ -0,0 +1,153 @@
+use std::io::{self, ErrorKind, Read};
+
+use qbsdiff::{inspect, transcode, Bsdiff, Bspatch, Codec, Format, PatchedReader};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..1 << 18)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(997) {
+        target[i] ^= 0x5a;
+    }
+    target.splice(20000..20000, (0..50000u32).map(|i| ((i * 7) >> 3) as u8));
+    (source, target)
+}
+
+fn apply(source: &[u8], patch: &[u8], ahead: usize) -> Vec<u8> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)
+        .unwrap()
+        .decode_ahead(ahead)
+        .apply(source, io::Cursor::new(&mut target))
+        .unwrap();
+    target
+}
+
+fn codecs() -> Vec<Codec> {
+    let mut codecs = vec![Codec::Stored, Codec::Bzip2];
+    if cfg!(feature = "zstd") {
+        codecs.push(Codec::Zstd);
+    }
+    codecs
+}
+
+#[test]
+fn blocks_roundtrip() {
+    let (source, target) = sample();
+    for codec in codecs() {
+        for block_size in [4096, 1 << 16, 1 << 20] {
+            let patch = Bsdiff::new(&source, &target)
+                .format(Format::Extended)
+                .codec(codec)
+                .compression_level(if codec == Codec::Stored { 0 } else { 6 })
+                .block_size(block_size)
+                .compare_to_vec()
+                .unwrap();
+            for ahead in [0, 1, 4] {
+                assert!(apply(&source, &patch, ahead) == target);
+            }
+
+            let summary = inspect::validate(&patch, Some(&source)).unwrap();
+            assert_eq!(summary.section_sizes[1] + summary.section_sizes[2], target.len() as u64);
+            let mut reader = PatchedReader::new(&source, &patch).unwrap();
+            let mut t1 = Vec::new();
+            reader.read_to_end(&mut t1).unwrap();
+            assert!(t1 == target);
+        }
+    }
+}
+
+#[test]
+fn blocks_empty_sections() {
+    let (source, _) = sample();
+    for codec in codecs() {
+        let patch = Bsdiff::new(&source, &source)
+            .format(Format::Extended)
+            .codec(codec)
+            .compression_level(if codec == Codec::Stored { 0 } else { 6 })
+            .block_size(4096)
+            .compare_to_vec()
+            .unwrap();
+        assert!(apply(&source, &patch, 2) == source);
+        let patch = Bsdiff::new(&source, &[])
+            .format(Format::Extended)
+            .codec(codec)
+            .compression_level(if codec == Codec::Stored { 0 } else { 6 })
+            .block_size(4096)
+            .compare_to_vec()
+            .unwrap();
+        assert!(apply(&source, &patch, 2).is_empty());
+        inspect::validate(&patch, None).unwrap();
+    }
+}
+
+#[test]
+fn blocks_transcode() {
+    let (source, target) = sample();
+    let patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .block_size(1 << 16)
+        .compare_to_vec()
+        .unwrap();
+
+    let mut extended = Vec::new();
+    transcode(&patch[..], &mut extended, Format::Extended, Format::Extended).unwrap();
+    assert!(apply(&source, &extended, 2) == target);
+    inspect::validate(&extended, None).unwrap();
+
+    // Classic patchers read a single bzip2 stream per section.
+    let mut classic = Vec::new();
+    transcode(&patch[..], &mut classic, Format::Extended, Format::Classic).unwrap();
+    assert!(apply(&source, &classic, 2) == target);
+    let plain = Bsdiff::new(&source, &target).compare_to_vec().unwrap();
+    let mut classic1 = Vec::new();
+    transcode(&plain[..], &mut classic1, Format::Classic, Format::Classic).unwrap();
+    assert_eq!(classic.len(), classic1.len());
+}
+
+#[test]
+fn blocks_corrupted() {
+    let (source, target) = sample();
+    let patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .block_size(4096)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(u32::from_le_bytes(patch[4..8].try_into().unwrap()) & 0x400, 0x400);
+
+    // block size of the table
+    for block_size in [0u64, 4095, 4097] {
+        let mut p = patch.clone();
+        p[48..56].copy_from_slice(&block_size.to_le_bytes());
+        let err = Bspatch::new(&p)
+            .and_then(|patcher| patcher.decode_ahead(2).apply(&source, io::sink()))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(inspect::validate(&p, None).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    // encoded size of the first control block
+    let mut p = patch.clone();
+    let first = u32::from_le_bytes(p[68..72].try_into().unwrap());
+    p[68..72].copy_from_slice(&(first - 1).to_le_bytes());
+    assert_eq!(
+        Bspatch::new(&p).err().map(|err| err.kind()),
+        Some(ErrorKind::InvalidData)
+    );
+}
+
+#[test]
+fn blocks_require_extended() {
+    let err = Bsdiff::new(b"source", b"target")
+        .block_size(4096)
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = Bsdiff::new(b"source", b"target")
+        .format(Format::Extended)
+        .block_size(usize::MAX)
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,122 @@
+use std::env;
+#[cfg(feature = "mmap")]
+use std::fs;
+use std::path;
+
+use qbsdiff::bundle::{Bundle, BundleWriter};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_bundle() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    let mut files = Vec::new();
+    let mut writer = BundleWriter::new();
+    for sample in samples.iter() {
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+        writer.add(format!("samples/{}", sample.name), p).unwrap();
+        files.push((s, t));
+    }
+    let mut data = Vec::new();
+    writer.write_to(&mut data).unwrap();
+
+    let bundle = Bundle::parse(&data[..]).unwrap();
+    assert_eq!(bundle.len(), samples.len());
+    for (entry, (s, t)) in bundle.entries().iter().zip(files.iter()) {
+        eprintln!("bundle test on entry `{}`", entry.path());
+        assert_eq!(entry.target_size(), t.len() as u64);
+        let t1 = testing.qbspatch(&s[..], &entry.patch().unwrap()).unwrap();
+        if *t != t1 {
+            panic!("bundle entry mismatch: `{}`", entry.path());
+        }
+    }
+}
+
+#[test]
+fn bundle_rejects_invalid_paths() {
+    let testing = Testing::new(env::temp_dir());
+    let p = testing.qbsdiff(b"source", b"target").unwrap();
+    let mut writer = BundleWriter::new();
+    for path in ["", "/abs", "a//b", "a/../b", "./a", "a\\b"] {
+        assert!(writer.add(path, p.clone()).is_err(), "path `{}` accepted", path);
+    }
+    writer.add("a/b", p.clone()).unwrap();
+    assert!(writer.add("a/b", p).is_err());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn bundle_shared_dictionary() {
+    let testing = Testing::new(env::temp_dir());
+    let dict = b"the quick brown fox jumps over the lazy dog".repeat(16);
+    let mut writer = BundleWriter::new().dictionary(dict);
+    let mut files = Vec::new();
+    for i in 0..4u8 {
+        let s = vec![i; 4096];
+        let mut t = s.clone();
+        t[100 * i as usize] = 0xff;
+        writer
+            .add(format!("file{}", i), testing.qbsdiff(&s, &t).unwrap())
+            .unwrap();
+        files.push((s, t));
+    }
+    let mut data = Vec::new();
+    writer.write_to(&mut data).unwrap();
+
+    let bundle = Bundle::parse(&data[..]).unwrap();
+    for (entry, (s, t)) in bundle.entries().iter().zip(files.iter()) {
+        let t1 = testing.qbspatch(s, &entry.patch().unwrap()).unwrap();
+        assert_eq!(&t1, t);
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn bundle_apply_atomically() {
+    let dir = env::temp_dir().join("qbsdiff-test").join("bundle");
+    let _ = fs::remove_dir_all(dir.as_path());
+    fs::create_dir_all(dir.as_path()).unwrap();
+    let testing = Testing::new(dir.clone());
+
+    let files: [(&str, &[u8], &[u8]); 3] = [
+        ("app", b"application binary v1", b"application binary v2"),
+        ("assets/logo", b"logo v1", b"logo v2, redrawn"),
+        ("assets/new", b"", b"newly added asset"),
+    ];
+    let mut writer = BundleWriter::new();
+    for &(path, s, t) in files.iter() {
+        if !s.is_empty() {
+            fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            fs::write(dir.join(path), s).unwrap();
+        }
+        writer.add(path, testing.qbsdiff(s, t).unwrap()).unwrap();
+    }
+
+    // The last entry does not match its source, nothing should be replaced.
+    let mut data = Vec::new();
+    let mut failing = writer.clone();
+    failing
+        .add("broken", testing.qbsdiff(b"abc", b"abcdef").unwrap())
+        .unwrap();
+    fs::write(dir.join("broken"), b"").unwrap();
+    failing.write_to(&mut data).unwrap();
+    assert!(Bundle::parse(&data[..]).unwrap().apply(dir.as_path()).is_err());
+    for &(path, s, _) in files.iter() {
+        match fs::read(dir.join(path)) {
+            Ok(content) => assert_eq!(&content[..], s),
+            Err(_) => assert!(s.is_empty()),
+        }
+    }
+
+    data.clear();
+    writer.write_to(&mut data).unwrap();
+    Bundle::parse(&data[..]).unwrap().apply(dir.as_path()).unwrap();
+    for &(path, _, t) in files.iter() {
+        assert_eq!(&fs::read(dir.join(path)).unwrap()[..], t);
+    }
+    fs::remove_dir_all(dir.as_path()).unwrap();
+}
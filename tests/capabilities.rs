@@ -0,0 +1,148 @@
+use std::io::ErrorKind;
+
+use qbsdiff::format::{Feature, CAPABILITIES_MAGIC};
+use qbsdiff::{Bsdiff, Bspatch, Capabilities, ChecksumKind, Codec, CodecPriority, DiffProfile, Format, Section};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..100 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut t = s.clone();
+    t[100..200].fill(7);
+    t.extend_from_slice(&[0x5a; 3000]);
+    (s, t)
+}
+
+fn preferred() -> DiffProfile {
+    DiffProfile {
+        format: Format::Extended,
+        codec: Codec::Zstd,
+        compression_level: 19,
+        section_codecs: [None, Some((Codec::Stored, 0)), None],
+        auto_codec: Some(CodecPriority::FastestApply),
+        target_copy: true,
+        dedupe: true,
+        compact_seek: true,
+        decoded_sizes: true,
+        block_size: 4096,
+        metadata: true,
+        source_checksum: 4096,
+        checksum: Some(ChecksumKind::Sha256),
+        ..DiffProfile::default()
+    }
+}
+
+/// Produce the patch of the negotiated profile, which must be valid and
+/// accepted by the capabilities.
+fn negotiated(caps: &Capabilities, s: &[u8], t: &[u8]) -> (DiffProfile, Vec<u8>) {
+    let profile = caps.negotiate(&preferred()).unwrap();
+    profile.validate().unwrap();
+    let p = Bsdiff::new(s, t).profile(&profile).compare_to_vec().unwrap();
+    assert!(caps.accepts(&p).unwrap());
+    let mut t1 = Vec::new();
+    Bspatch::new(&p).unwrap().apply(s, &mut t1).unwrap();
+    assert!(t1 == t);
+    (profile, p)
+}
+
+#[test]
+fn capabilities_encode_decode() {
+    for caps in [
+        Capabilities::current(),
+        Capabilities::classic(),
+        Capabilities::current()
+            .without_codec(Codec::Zstd)
+            .without(Feature::TargetCopy)
+            .without_checksum(ChecksumKind::Crc32),
+    ] {
+        let data = caps.encode();
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[..8], CAPABILITIES_MAGIC);
+        assert_eq!(Capabilities::decode(&data).unwrap(), caps);
+    }
+
+    // Unknown bits of newer versions are ignored.
+    let mut data = Capabilities::classic().encode();
+    data[8..12].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+    data[13] |= 0x80;
+    assert_eq!(Capabilities::decode(&data).unwrap(), Capabilities::classic());
+
+    let err = Capabilities::decode(b"QBSCAPS0\0\0\0\0\0\0\0\0").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(Capabilities::decode(&data[..15]).is_err());
+}
+
+#[test]
+fn capabilities_negotiate() {
+    let (s, t) = sample();
+
+    let (profile, _) = negotiated(&Capabilities::current(), &s, &t);
+    assert_eq!(profile.format, Format::Extended);
+    assert!(profile.target_copy && profile.compact_seek && profile.block_size > 0);
+    assert!(profile.auto_codec.is_some());
+
+    let (profile, p) = negotiated(&Capabilities::classic(), &s, &t);
+    assert_eq!(profile.format, Format::Classic);
+    assert_eq!(profile.codec, Codec::Bzip2);
+    assert_eq!(profile.section_codecs[Section::Delta as usize], Some((Codec::Bzip2, 6)));
+    assert_eq!(profile.checksum, None);
+    assert!(!profile.target_copy && !profile.metadata && profile.source_checksum == 0);
+    assert_eq!(&p[..8], b"BSDIFF40");
+
+    let caps = Capabilities::current()
+        .without_codec(Codec::Zstd)
+        .without(Feature::TargetCopy)
+        .without(Feature::Blocks)
+        .without_checksum(ChecksumKind::Sha256);
+    let (profile, _) = negotiated(&caps, &s, &t);
+    assert_eq!(profile.format, Format::Extended);
+    assert_eq!(profile.codec, Codec::Bzip2);
+    assert_eq!(profile.compression_level, 9);
+    assert_eq!(profile.auto_codec.is_none(), cfg!(feature = "zstd"));
+    assert!(!profile.target_copy && !profile.dedupe && profile.block_size == 0);
+    assert!(profile.compact_seek && profile.decoded_sizes && profile.metadata);
+    assert!(profile.checksum.is_some_and(|kind| kind != ChecksumKind::Sha256));
+
+    let stored_only = Capabilities::current()
+        .without_codec(Codec::Bzip2)
+        .without_codec(Codec::Zstd);
+    let (profile, _) = negotiated(&stored_only, &s, &t);
+    assert_eq!(profile.format, Format::Extended);
+    assert_eq!(profile.codec, Codec::Stored);
+
+    let none = Capabilities::classic().without_codec(Codec::Bzip2);
+    let err = none.negotiate(&preferred()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn capabilities_accepts() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .target_copy(true)
+        .checksum(Some(ChecksumKind::Crc32c))
+        .compare_to_vec()
+        .unwrap();
+
+    assert!(Capabilities::current().accepts(&p).unwrap());
+    assert!(!Capabilities::classic().accepts(&p).unwrap());
+    assert!(!Capabilities::current()
+        .without_codec(Codec::Stored)
+        .accepts(&p)
+        .unwrap());
+    assert!(!Capabilities::current()
+        .without(Feature::TargetCopy)
+        .accepts(&p)
+        .unwrap());
+    assert!(!Capabilities::current()
+        .without_checksum(ChecksumKind::Crc32c)
+        .accepts(&p)
+        .unwrap());
+    assert!(Capabilities::current().without(Feature::Blocks).accepts(&p).unwrap());
+    assert_eq!(
+        Capabilities::current().accepts(b"not a patch").unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+}
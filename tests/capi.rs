@@ -0,0 +1,185 @@
+#![cfg(feature = "capi")]
+
+use std::ffi::{c_int, c_void, CStr};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ptr;
+use std::slice;
+
+use qbsdiff::capi::*;
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s[20000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+/// Append to the `Vec<u8>` of user data, at most 1000 bytes per call.
+unsafe extern "C" fn write_vec(user_data: *mut c_void, buf: *const u8, len: usize) -> isize {
+    let out = &mut *(user_data as *mut Vec<u8>);
+    let n = Ord::min(len, 1000);
+    out.extend_from_slice(slice::from_raw_parts(buf, n));
+    n as isize
+}
+
+unsafe extern "C" fn write_fail(_: *mut c_void, _: *const u8, _: usize) -> isize {
+    -1
+}
+
+unsafe extern "C" fn read_cursor(user_data: *mut c_void, buf: *mut u8, len: usize) -> isize {
+    let cursor = &mut *(user_data as *mut Cursor<&[u8]>);
+    match cursor.read(slice::from_raw_parts_mut(buf, len)) {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn seek_cursor(user_data: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let cursor = &mut *(user_data as *mut Cursor<&[u8]>);
+    let pos = match whence {
+        0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        _ => SeekFrom::End(offset),
+    };
+    cursor.seek(pos).map_or(-1, |n| n as i64)
+}
+
+fn writer(out: &mut Vec<u8>) -> QbsWriter {
+    QbsWriter {
+        write: Some(write_vec),
+        flush: None,
+        user_data: out as *mut Vec<u8> as *mut c_void,
+    }
+}
+
+fn source(cursor: &mut Cursor<&[u8]>) -> QbsSource {
+    QbsSource {
+        read: Some(read_cursor),
+        seek: Some(seek_cursor),
+        user_data: cursor as *mut Cursor<&[u8]> as *mut c_void,
+    }
+}
+
+#[test]
+fn capi_roundtrip() {
+    let (s, t) = sample();
+    assert_eq!(qbs_abi_version(), QBS_ABI_VERSION);
+
+    let mut p = Vec::new();
+    let mut psize = 0;
+    let code = unsafe { qbs_diff(s.as_ptr(), s.len(), t.as_ptr(), t.len(), &writer(&mut p), &mut psize) };
+    assert_eq!(code, QBS_OK);
+    assert_eq!(psize, p.len() as u64);
+
+    let mut tsize = 0;
+    assert_eq!(
+        unsafe { qbs_patch_target_size(p.as_ptr(), p.len(), &mut tsize) },
+        QBS_OK
+    );
+    assert_eq!(tsize, t.len() as u64);
+
+    let mut cursor = Cursor::new(&s[..]);
+    let mut t1 = Vec::new();
+    let code = unsafe { qbs_patch(p.as_ptr(), p.len(), &source(&mut cursor), &writer(&mut t1), &mut tsize) };
+    assert_eq!(code, QBS_OK);
+    assert!(t1 == t);
+
+    // empty inputs given by null pointers
+    let mut p = Vec::new();
+    let code = unsafe { qbs_diff(ptr::null(), 0, ptr::null(), 0, &writer(&mut p), ptr::null_mut()) };
+    assert_eq!(code, QBS_OK);
+    let mut cursor = Cursor::new(&[][..]);
+    let mut t1 = Vec::new();
+    let code = unsafe {
+        qbs_patch(
+            p.as_ptr(),
+            p.len(),
+            &source(&mut cursor),
+            &writer(&mut t1),
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(code, QBS_OK);
+    assert!(t1.is_empty());
+}
+
+#[test]
+fn capi_errors() {
+    let (s, t) = sample();
+    let mut p = Vec::new();
+    unsafe {
+        qbs_diff(
+            s.as_ptr(),
+            s.len(),
+            t.as_ptr(),
+            t.len(),
+            &writer(&mut p),
+            ptr::null_mut(),
+        )
+    };
+
+    // missing arguments
+    let code = unsafe {
+        qbs_diff(
+            ptr::null(),
+            1,
+            t.as_ptr(),
+            t.len(),
+            &writer(&mut Vec::new()),
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(code, QBS_EINVAL);
+    let code = unsafe { qbs_diff(s.as_ptr(), s.len(), t.as_ptr(), t.len(), ptr::null(), ptr::null_mut()) };
+    assert_eq!(code, QBS_EINVAL);
+    let none = QbsWriter {
+        write: None,
+        flush: None,
+        user_data: ptr::null_mut(),
+    };
+    let code = unsafe { qbs_diff(s.as_ptr(), s.len(), t.as_ptr(), t.len(), &none, ptr::null_mut()) };
+    assert_eq!(code, QBS_EINVAL);
+    assert_eq!(
+        unsafe { qbs_patch_target_size(p.as_ptr(), p.len(), ptr::null_mut()) },
+        QBS_EINVAL
+    );
+
+    // failing callbacks
+    let failing = QbsWriter {
+        write: Some(write_fail),
+        flush: None,
+        user_data: ptr::null_mut(),
+    };
+    let code = unsafe { qbs_diff(s.as_ptr(), s.len(), t.as_ptr(), t.len(), &failing, ptr::null_mut()) };
+    assert_eq!(code, QBS_EIO);
+
+    // corrupted patch and truncated source
+    let mut cursor = Cursor::new(&s[..]);
+    let code = unsafe {
+        qbs_patch(
+            p.as_ptr(),
+            10,
+            &source(&mut cursor),
+            &writer(&mut Vec::new()),
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(code, QBS_ECORRUPT);
+    let mut cursor = Cursor::new(&s[..50000]);
+    let code = unsafe {
+        qbs_patch(
+            p.as_ptr(),
+            p.len(),
+            &source(&mut cursor),
+            &writer(&mut Vec::new()),
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(code, QBS_ECORRUPT);
+
+    let message = unsafe { CStr::from_ptr(qbs_strerror(QBS_ECORRUPT)) };
+    assert_eq!(message.to_str().unwrap(), "patch or source corrupted");
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "cdc")]
+
+use std::path;
+
+use qbsdiff::cdc::{ChunkerConfig, Manifest};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn random_samples_cdc_sync() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+    let config = ChunkerConfig::new(2048, 8192, 32768);
+
+    for sample in samples.iter() {
+        eprintln!("cdc sync test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        let sm = Manifest::build(&s[..], config);
+        let tm = Manifest::build(&t[..], config);
+        for chunk in tm.chunks.iter() {
+            assert!(chunk.length > 0 && chunk.length <= config.max_size() as u64);
+        }
+
+        let mut encoded = Vec::new();
+        tm.write_to(&mut encoded).unwrap();
+        let tm = Manifest::parse(&encoded[..]).unwrap();
+
+        let delta = tm.diff(&sm);
+        let mut t1 = Vec::new();
+        let n = delta
+            .reconstruct(&s[..], |chunk| Ok(t[chunk.range()].to_vec()), &mut t1)
+            .unwrap();
+        if n != t.len() as u64 || t != t1 {
+            panic!("cdc reconstruction failed: `{}`", sample.name);
+        }
+    }
+}
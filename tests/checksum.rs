@@ -0,0 +1,133 @@
+use std::io;
+
+use qbsdiff::{transcode, Bsdiff, Bspatch, ChecksumKind, Format, PatchError, Strength};
+use qbsdiff_test_bench_utils::*;
+
+const KINDS: [ChecksumKind; 4] = [
+    ChecksumKind::Crc32,
+    ChecksumKind::Crc32c,
+    ChecksumKind::Xxh3,
+    ChecksumKind::Sha256,
+];
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 64 * 1024);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(997) {
+        t[i] = t[i].wrapping_add(i as u8);
+    }
+    t.extend_from_slice(b"appended new data");
+    (s, t)
+}
+
+fn bsdiff(s: &[u8], t: &[u8], kind: Option<ChecksumKind>) -> io::Result<Vec<u8>> {
+    Bsdiff::new(s, t)
+        .format(Format::Extended)
+        .checksum(kind)
+        .compare_to_vec()
+}
+
+fn apply(s: &[u8], p: &[u8]) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?.apply(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn checksum_digests() {
+    let check = b"123456789";
+    assert_eq!(ChecksumKind::Crc32.digest(check).unwrap(), 0xcbf43926u32.to_le_bytes());
+    assert_eq!(ChecksumKind::Crc32c.digest(check).unwrap(), 0xe3069283u32.to_le_bytes());
+    for kind in KINDS {
+        match kind.digest(check) {
+            Ok(digest) => assert_eq!(digest.len(), kind.size()),
+            Err(e) => {
+                assert!(!kind.is_supported());
+                assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+            }
+        }
+    }
+    if ChecksumKind::Sha256.is_supported() {
+        let digest = ChecksumKind::Sha256.digest(b"abc").unwrap();
+        assert_eq!(&digest[..4], &[0xba, 0x78, 0x16, 0xbf]);
+    }
+
+    // incremental updates match the one-shot digest
+    for kind in KINDS.into_iter().filter(|kind| kind.is_supported()) {
+        let mut hasher = kind.hasher().unwrap();
+        hasher.update(&check[..4]);
+        hasher.update(&check[4..]);
+        assert_eq!(hasher.digest(), kind.digest(check).unwrap());
+    }
+}
+
+#[test]
+fn checksum_roundtrip() {
+    let (s, t) = sample();
+    let plain = bsdiff(&s, &t, None).unwrap();
+    for kind in KINDS {
+        let p = match bsdiff(&s, &t, Some(kind)) {
+            Ok(p) => p,
+            Err(e) => {
+                assert!(!kind.is_supported());
+                assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+                continue;
+            }
+        };
+        assert_eq!(p.len(), plain.len() + 3 * kind.size());
+        assert_eq!(Bspatch::new(&p).unwrap().checksum(), Some(kind));
+        assert!(apply(&s, &p).unwrap() == t);
+
+        for i in [p.len() / 2, p.len() - 1] {
+            let mut q = p.clone();
+            q[i] ^= 0x10;
+            assert_eq!(apply(&s, &q).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        // transcoding keeps the algorithm
+        let mut q = Vec::new();
+        transcode(&p[..], &mut q, Format::Extended, Format::Extended).unwrap();
+        assert!(q == p);
+    }
+
+    let err = Bsdiff::new(&s, &t)
+        .checksum(Some(ChecksumKind::Crc32))
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn checksum_required_strength() {
+    let (s, t) = sample();
+    let plain = bsdiff(&s, &t, None).unwrap();
+    let weak = bsdiff(&s, &t, Some(ChecksumKind::Crc32c)).unwrap();
+
+    assert!(Bspatch::new(&plain).unwrap().require_checksum(Strength::None).is_ok());
+    let err = Bspatch::new(&plain)
+        .unwrap()
+        .require_checksum(Strength::Weak)
+        .err()
+        .unwrap();
+    assert_eq!(PatchError::of(&err), Some(PatchError::WeakChecksum));
+
+    assert!(Bspatch::new(&weak).unwrap().require_checksum(Strength::Weak).is_ok());
+    let err = Bspatch::new(&weak)
+        .unwrap()
+        .require_checksum(Strength::Strong)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    for kind in KINDS.into_iter().filter(|kind| kind.is_supported()) {
+        let p = bsdiff(&s, &t, Some(kind)).unwrap();
+        let mut target = Vec::new();
+        Bspatch::new(&p)
+            .unwrap()
+            .require_checksum(kind.strength())
+            .unwrap()
+            .apply(&s, io::Cursor::new(&mut target))
+            .unwrap();
+        assert!(target == t);
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Control, Format, ParallelScheme, SourceCorruption};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 1000 * 1000);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(10007) {
+        t[i] ^= 0x5a;
+    }
+    t.splice(300_000..300_000, b"inserted data".iter().copied());
+    (s, t)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn chunk_controls_roundtrip() {
+    let (s, t) = sample();
+    let bsdiff = Bsdiff::new(&s, &t).parallel_scheme(ParallelScheme::ChunkSize(256 * 1024));
+    let chunks = bsdiff.search_chunks();
+    assert_eq!(chunks.len(), t.len().div_ceil(256 * 1024));
+    let mut end = 0;
+    for chunk in chunks.iter() {
+        assert_eq!(chunk.range.start, end);
+        end = chunk.range.end;
+        let size: u64 = chunk.controls.iter().map(|c| c.add + c.copy).sum();
+        assert_eq!(size, chunk.range.len() as u64);
+        let pos: i64 = chunk.controls.iter().map(|c| c.add as i64 + c.seek).sum();
+        assert_eq!(pos, 0);
+    }
+    assert_eq!(end, t.len());
+
+    // concatenation is the patch of compare
+    let mut expected = Vec::new();
+    bsdiff.compare(io::Cursor::new(&mut expected)).unwrap();
+    let mut p = Vec::new();
+    bsdiff
+        .compare_controls(chunks.iter().flat_map(|c| c.controls.clone()), io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(p == expected);
+
+    // a custom merge strategy
+    let mut ctrls: Vec<Control> = chunks.into_iter().flat_map(|c| c.controls).collect();
+    ctrls.retain(|c| *c != Control::default());
+    let mut p = Vec::new();
+    bsdiff.compare_controls(ctrls, io::Cursor::new(&mut p)).unwrap();
+    assert!(apply(&s, &p) == t);
+
+    let single = Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::Never)
+        .search_chunks();
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].range, 0..t.len());
+    assert!(Bsdiff::new(&s, &[]).search_chunks().is_empty());
+    let fresh = Bsdiff::new(&[], &t[..1000]).search_chunks();
+    let mut p = Vec::new();
+    Bsdiff::new(&[], &t[..1000])
+        .compare_controls(fresh.into_iter().flat_map(|c| c.controls), io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(apply(&[], &p) == t[..1000]);
+}
+
+#[test]
+fn chunk_controls_rejected() {
+    let s = vec![1; 100];
+    let t = vec![2; 100];
+    let bsdiff = Bsdiff::new(&s, &t);
+    let ctl = |add, copy, seek| Control {
+        add,
+        copy,
+        seek,
+        ..Control::default()
+    };
+    for ctrls in [
+        vec![],
+        vec![ctl(50, 0, 0)],
+        vec![ctl(50, 51, 0)],
+        vec![ctl(101, 0, 0)],
+        vec![ctl(0, 0, -1), ctl(100, 0, 0)],
+        vec![ctl(50, 0, 10), ctl(50, 0, 0)],
+        vec![ctl(u64::MAX, 0, 0)],
+        vec![Control {
+            add: 100,
+            tcopy: 1,
+            tdist: 1,
+            ..Control::default()
+        }],
+    ] {
+        let err = bsdiff.compare_controls(ctrls.clone(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", ctrls);
+    }
+
+    let mut p = Vec::new();
+    bsdiff
+        .compare_controls(vec![ctl(50, 10, -50), ctl(40, 0, 0)], io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(apply(&s, &p) == t);
+}
+
+#[test]
+fn chunk_controls_external_matcher() {
+    let (s, t) = sample();
+    // matcher aligning source and target at the same offsets
+    let n = Ord::min(s.len(), t.len()) as u64;
+    let ctrls = vec![Control {
+        add: n,
+        copy: t.len() as u64 - n,
+        ..Control::default()
+    }];
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .source_checksum(4096)
+        .target_copy(true)
+        .compare_controls(ctrls, io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(apply(&s, &p) == t);
+
+    // source checksums are embedded
+    let mut s1 = s.clone();
+    s1[100] ^= 1;
+    let err = Bspatch::new(&p).unwrap().apply(&s1, io::sink()).unwrap_err();
+    assert!(SourceCorruption::of(&err).is_some());
+
+    // nothing is written on invalid controls
+    let mut p = Vec::new();
+    let ctrls = (0..10).map(|_| Control {
+        add: n,
+        ..Control::default()
+    });
+    assert!(Bsdiff::new(&s, &t)
+        .compare_controls(ctrls, io::Cursor::new(&mut p))
+        .is_err());
+    assert!(p.is_empty());
+}
@@ -0,0 +1,167 @@
+#![cfg(feature = "cmd")]
+use std::path::Path;
+use std::process::{Command, Output};
+use std::{env, fs, io};
+
+use qbsdiff::{Bsdiff, Codec, Format};
+use serde_json::Value;
+
+fn run(bin: &str, args: &[&Path]) -> (i32, Value, Output) {
+    let output = Command::new(bin).arg("--json").args(args).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let json = serde_json::from_str(stdout.trim()).unwrap();
+    (output.status.code().unwrap(), json, output)
+}
+
+#[test]
+fn cli_exit_codes_and_json() {
+    let root = env::temp_dir().join(format!("qbsdiff-cli-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let (s, t, p, t1) = (root.join("s"), root.join("t"), root.join("p"), root.join("t1"));
+    let source: Vec<u8> = (0..300 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    target[1000..2000].fill(3);
+    fs::write(&s, &source).unwrap();
+    fs::write(&t, &target).unwrap();
+
+    let qbsdiff = env!("CARGO_BIN_EXE_qbsdiff");
+    let qbspatch = env!("CARGO_BIN_EXE_qbspatch");
+
+    let (code, diff, _) = run(qbsdiff, &[&s, &t, &p]);
+    assert_eq!(code, 0);
+    assert_eq!(diff["ok"], true);
+    assert_eq!(diff["source_size"], source.len() as u64);
+    assert_eq!(diff["target_size"], target.len() as u64);
+    assert_eq!(diff["patch_size"], fs::metadata(&p).unwrap().len());
+    assert_eq!(diff["target_sha256"].as_str().unwrap().len(), 64);
+    assert!(diff["durations"]["total"].as_f64().unwrap() >= 0.0);
+
+    let (code, patch, _) = run(qbspatch, &[&s, &t1, &p]);
+    assert_eq!(code, 0);
+    assert_eq!(fs::read(&t1).unwrap(), target);
+    for field in ["source_sha256", "target_sha256", "patch_sha256", "target_size"] {
+        assert_eq!(patch[field], diff[field], "{}", field);
+    }
+
+    // JSON goes to stderr when the target is written to stdout
+    let output = Command::new(qbspatch)
+        .arg("--json")
+        .args([s.as_path(), Path::new("-"), p.as_path()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, target);
+    let json: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(json["target_sha256"], diff["target_sha256"]);
+
+    // compare only, without writing a patch
+    let output = Command::new(qbsdiff).args([&s, &t]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let output = Command::new(qbsdiff)
+        .args(["--json", "--compare-only"])
+        .args([&s, &t])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["patch_size"], diff["patch_size"]);
+    assert!(json["similarity"].as_f64().unwrap() > 0.9);
+    assert!(json["patch_ratio"].as_f64().unwrap() < 0.1);
+    let unwritten = root.join("unwritten");
+    let output = Command::new(qbsdiff)
+        .arg("--compare-only")
+        .args([&s, &t, &unwritten])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("similarity:"));
+    assert!(!unwritten.exists());
+
+    // deep check of the patch, against the source
+    let (code, json, _) = run(qbspatch, &[Path::new("--check"), &p, Path::new("--source"), &s]);
+    assert_eq!(code, 0);
+    assert_eq!(json["format"], "classic");
+    assert_eq!(json["target_size"], target.len() as u64);
+    assert_eq!(json["source_checked"], true);
+    assert_eq!(json["patch_sha256"], diff["patch_sha256"]);
+    let output = Command::new(qbspatch).arg("--check").arg(&p).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("source checked:    false"));
+
+    // benchmark of patching, the target is written once
+    let t2 = root.join("t2");
+    let (code, json, _) = run(qbspatch, &[Path::new("--bench"), Path::new("3"), &s, &t2, &p]);
+    assert_eq!(code, 0);
+    assert_eq!(json["bench_runs"], 3);
+    assert_eq!(json["target_size"], target.len() as u64);
+    assert!(json["min_throughput"].as_f64().unwrap() > 0.0);
+    assert!(json["avg_throughput"].as_f64().unwrap() >= json["min_throughput"].as_f64().unwrap());
+    assert_eq!(fs::read(&t2).unwrap(), target);
+    let output = Command::new(qbspatch)
+        .args([Path::new("--bench"), Path::new("2"), &s, Path::new("-"), &p])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, target);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("avg throughput:"));
+    let output = Command::new(qbspatch)
+        .args([Path::new("--bench"), Path::new("0"), &s, &t2, &p])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    // bad arguments
+    let output = Command::new(qbsdiff)
+        .args(["--json", "-z", "12"])
+        .args([&s, &t, &p])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["ok"], false);
+    assert_eq!(json["error"], "bad_args");
+
+    // corrupted patch
+    let bad = root.join("bad");
+    let mut corrupted = fs::read(&p).unwrap();
+    corrupted.truncate(40);
+    fs::write(&bad, &corrupted).unwrap();
+    let (code, json, _) = run(qbspatch, &[&s, &t1, &bad]);
+    assert_eq!(code, 3);
+    assert_eq!(json["error"], "corrupt_patch");
+    assert_eq!(json["exit_code"], 3);
+    let (code, json, _) = run(qbspatch, &[Path::new("--check"), &bad]);
+    assert_eq!(code, 3);
+    assert_eq!(json["error"], "corrupt_patch");
+
+    // source not matching the patch
+    let checked = root.join("checked");
+    let mut patch = Vec::new();
+    Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .source_checksum(4096)
+        .compare(io::Cursor::new(&mut patch))
+        .unwrap();
+    fs::write(&checked, &patch).unwrap();
+    let mut rotten = source.clone();
+    rotten[100] ^= 1;
+    fs::write(&s, &rotten).unwrap();
+    let (code, json, _) = run(qbspatch, &[&s, &t1, &checked]);
+    assert_eq!(code, 4);
+    assert_eq!(json["error"], "source_mismatch");
+    assert!(json["message"].as_str().unwrap().contains("0..4096"));
+
+    // i/o error, without JSON
+    let output = Command::new(qbspatch)
+        .args([&root.join("missing"), &t1, &p])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+
+    fs::remove_dir_all(&root).unwrap();
+}
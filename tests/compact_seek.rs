@@ -0,0 +1,125 @@
+use std::io::{self, ErrorKind, Read};
+
+use qbsdiff::interleave::{apply_interleaved, interleave};
+use qbsdiff::{inspect, transcode, Bsdiff, Bspatch, Codec, Format, PatchedReader};
+use qbsdiff_test_bench_utils::*;
+
+/// Binary-like data with many small displacements: records shifted by a few
+/// bytes here and there.
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let source = hashed_bytes(0, 1 << 18);
+    let mut target = Vec::new();
+    for (i, record) in source.chunks(256).enumerate() {
+        target.extend_from_slice(&record[i % 3..]);
+        target.extend_from_slice(&[i as u8; 2][..i % 2]);
+        if let Some(b) = target.last_mut() {
+            *b ^= 1;
+        }
+    }
+    (source, target)
+}
+
+fn apply(source: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)
+        .unwrap()
+        .apply(source, io::Cursor::new(&mut target))
+        .unwrap();
+    target
+}
+
+#[test]
+fn compact_seek_roundtrip() {
+    let (source, target) = sample();
+    for target_copy in [false, true] {
+        let bsdiff = || {
+            Bsdiff::new(&source, &target)
+                .format(Format::Extended)
+                .target_copy(target_copy)
+        };
+        let plain = bsdiff().compare_to_vec().unwrap();
+        let compact = bsdiff().compact_seek(true).compare_to_vec().unwrap();
+        assert!(apply(&source, &compact) == target);
+
+        let plain_info = inspect::validate(&plain, Some(&source)).unwrap();
+        let compact_info = inspect::validate(&compact, Some(&source)).unwrap();
+        assert_eq!(plain_info.controls, compact_info.controls);
+        let saved = plain_info.section_sizes[0] - compact_info.section_sizes[0];
+        assert!(saved >= 5 * compact_info.controls);
+        assert!(compact.len() < plain.len());
+        assert_eq!(inspect::regions(&plain).unwrap(), inspect::regions(&compact).unwrap());
+        assert_eq!(
+            inspect::source_ranges(&plain).unwrap(),
+            inspect::source_ranges(&compact).unwrap()
+        );
+
+        let mut t = Vec::new();
+        PatchedReader::new(&source, &compact)
+            .unwrap()
+            .read_to_end(&mut t)
+            .unwrap();
+        assert!(t == target);
+    }
+}
+
+#[test]
+fn compact_seek_conversions() {
+    let (source, target) = sample();
+    let patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .compact_seek(true)
+        .compare_to_vec()
+        .unwrap();
+
+    let mut classic = Vec::new();
+    transcode(&patch[..], &mut classic, Format::Extended, Format::Classic).unwrap();
+    assert!(apply(&source, &classic) == target);
+
+    let mut stream = Vec::new();
+    interleave(&patch, Codec::Bzip2, 6, &mut stream).unwrap();
+    let mut t = Vec::new();
+    apply_interleaved(&source, &stream[..], &mut t).unwrap();
+    assert!(t == target);
+}
+
+#[test]
+fn compact_seek_requires_extended() {
+    let err = Bsdiff::new(b"source", b"target")
+        .compact_seek(true)
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn compact_seek_overlong_varint() {
+    // A single control of 3 extra bytes with a seek of 11 continuation bytes.
+    let mut ctrls = vec![0; 16];
+    ctrls[8] = 3;
+    ctrls.extend_from_slice(&[0x80; 10]);
+    ctrls.push(0);
+    let patch = stored_patch(&ctrls, b"", b"abc", 3);
+    let err = Bspatch::new(&patch).unwrap().apply(b"", io::sink()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let mut ctrls = vec![0; 16];
+    ctrls[8] = 3;
+    ctrls.extend_from_slice(&[0x80, 0x00]);
+    let patch = stored_patch(&ctrls, b"", b"abc", 3);
+    assert_eq!(apply(b"", &patch), b"abc");
+}
+
+/// Extended patch with compact seeks and stored sections.
+fn stored_patch(ctrls: &[u8], delta: &[u8], extra: &[u8], tsize: u64) -> Vec<u8> {
+    let mut patch = b"QBSDIFF2".to_vec();
+    patch.extend_from_slice(&0x100u32.to_le_bytes());
+    // Stored sections, no target-relative copies.
+    patch.extend_from_slice(&[0; 4]);
+    for size in [ctrls.len() as u64, delta.len() as u64, extra.len() as u64, tsize] {
+        patch.extend_from_slice(&size.to_le_bytes());
+    }
+    patch.extend_from_slice(ctrls);
+    patch.extend_from_slice(delta);
+    patch.extend_from_slice(extra);
+    patch
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "async")]
+
+use std::future::Future;
+use std::io;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+
+use qbsdiff::{Bsdiff, Bspatch, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Poll the future until ready, returning its output and the number of times
+/// it yielded.
+fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+    let waker = Arc::new(Noop).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    let mut yields = 0;
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return (output, yields),
+            Poll::Pending => yields += 1,
+        }
+    }
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 1024 * 1024);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(4099) {
+        t[i] ^= 0x5a;
+    }
+    t.extend_from_slice(&s[..1000]);
+    (s, t)
+}
+
+#[test]
+fn compare_async_same_patch() {
+    let (s, t) = sample();
+    for bsdiff in [
+        Bsdiff::new(&s, &t),
+        Bsdiff::new(&s, &t).parallel_scheme(ParallelScheme::ChunkSize(256 * 1024)),
+        Bsdiff::new(&s, &t).parallel_scheme(ParallelScheme::Never),
+        Bsdiff::new(&s, &t).line_aware(true),
+        Bsdiff::new(&s, &t).full_fallback(Some(0.0)),
+        Bsdiff::new(&s, &s),
+        Bsdiff::new(&[], &t),
+        Bsdiff::new(&s, &[]),
+    ] {
+        let mut expected = Vec::new();
+        bsdiff.compare(io::Cursor::new(&mut expected)).unwrap();
+        let mut p = Vec::new();
+        let (size, _) = block_on(bsdiff.compare_async(&mut p));
+        assert_eq!(size.unwrap(), p.len() as u64);
+        assert!(p == expected);
+    }
+
+    let mut p = Vec::new();
+    block_on(Bsdiff::new(&s, &t).compare_async(&mut p)).0.unwrap();
+    let mut target = Vec::new();
+    Bspatch::new(&p).unwrap().apply(&s, &mut target).unwrap();
+    assert!(target == t);
+}
+
+#[test]
+fn compare_async_yields_between_chunks() {
+    let (s, t) = sample();
+    let bsdiff = Bsdiff::new(&s, &t).parallel_scheme(ParallelScheme::ChunkSize(256 * 1024));
+    let mut p = Vec::new();
+    let (size, yields) = block_on(bsdiff.compare_async(&mut p));
+    assert!(size.unwrap() < 4096);
+
+    // Five target chunks searched, then the header, controls, delta and extra
+    // sections of the classic patch written in a single chunk each.
+    assert_eq!(yields, 5 + 4);
+}
@@ -0,0 +1,34 @@
+use std::io;
+use std::path;
+
+use qbsdiff::{Bsdiff, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_compare_reader() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("compare_reader test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        for scheme in [ParallelScheme::Never, ParallelScheme::Auto] {
+            let mut p = Vec::new();
+            let size = Bsdiff::new(&s[..], &[])
+                .parallel_scheme(scheme)
+                .compare_reader(&t[..], 256 * 1024, io::Cursor::new(&mut p))
+                .unwrap();
+            assert_eq!(size, p.len() as u64);
+
+            if testing.qbspatch(&s[..], &p[..]).unwrap() != t {
+                panic!("windowed patch failed: `{}`", sample.name);
+            }
+            if testing.bspatch(&s[..], &p[..]).unwrap() != t {
+                panic!("windowed patch incompatible with bspatch: `{}`", sample.name);
+            }
+        }
+    }
+}
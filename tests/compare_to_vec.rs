@@ -0,0 +1,44 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 512 * 1024);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(4099) {
+        t[i] ^= 0x5a;
+    }
+    t.extend_from_slice(&s[..1000]);
+    (s, t)
+}
+
+#[test]
+fn compare_to_vec_exact_size() {
+    let (s, t) = sample();
+    for bsdiff in [
+        Bsdiff::new(&s, &t),
+        Bsdiff::new(&s, &t).format(Format::Extended).codec(Codec::Stored),
+        Bsdiff::new(&s, &s),
+        Bsdiff::new(&[], &t),
+    ] {
+        let mut expected = Vec::new();
+        bsdiff.compare(io::Cursor::new(&mut expected)).unwrap();
+
+        let p = bsdiff.compare_to_vec().unwrap();
+        assert!(p == expected);
+        assert_eq!(p.capacity(), p.len());
+
+        let p = bsdiff.compare_to_vec_with_capacity(p.len() * 2).unwrap();
+        assert!(p == expected);
+        assert!(p.capacity() >= p.len() * 2);
+        let p = bsdiff.compare_to_vec_with_capacity(16).unwrap();
+        assert!(p == expected);
+    }
+
+    let p = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    let mut t1 = Vec::new();
+    Bspatch::new(&p).unwrap().apply(&s, io::Cursor::new(&mut t1)).unwrap();
+    assert!(t1 == t);
+    assert!(Bsdiff::new(&s, &t).codec(Codec::Stored).compare_to_vec().is_err());
+}
@@ -0,0 +1,77 @@
+#![cfg(all(feature = "mmap", feature = "gzip"))]
+use std::io::{self, Write};
+use std::{env, fs};
+
+use flate2::write::GzEncoder;
+use qbsdiff::input::{self, Compression, InputFile};
+use qbsdiff::{Bsdiff, Bspatch};
+use qbsdiff_test_bench_utils::*;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 200 * 1000);
+    let mut t = s.clone();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+#[test]
+fn compressed_input_files() {
+    let root = env::temp_dir().join(format!("qbsdiff-compressed-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let (s, t) = sample();
+    let (plain, gz) = (root.join("s"), root.join("s.gz"));
+    fs::write(&plain, &s).unwrap();
+    fs::write(&gz, gzip(&s)).unwrap();
+
+    let input = InputFile::open_decompressed(&gz).unwrap();
+    assert_eq!(input.compression(), Some(Compression::Gzip));
+    assert!(input[..] == s[..]);
+    let input = InputFile::open_decompressed(&plain).unwrap();
+    assert_eq!(input.compression(), None);
+    assert!(input[..] == s[..]);
+    assert!(InputFile::open(&gz).unwrap()[..] == gzip(&s)[..]);
+
+    // patches are computed on uncompressed contents
+    let mut p = Vec::new();
+    Bsdiff::new(&InputFile::open_decompressed(&gz).unwrap(), &t)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    let target = root.join("t");
+    Bspatch::new(&p)
+        .unwrap()
+        .decompress_source(true)
+        .apply_file(&gz, &target)
+        .unwrap();
+    assert!(fs::read(&target).unwrap() == t);
+    assert!(Bspatch::new(&p).unwrap().apply_file(&gz, &target).is_err());
+
+    // truncated stream
+    let mut bad = gzip(&s);
+    bad.truncate(bad.len() / 2);
+    fs::write(&gz, &bad).unwrap();
+    assert!(InputFile::open_decompressed(&gz).is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn compressed_input_detection() {
+    assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Some(Compression::Gzip));
+    assert_eq!(Compression::detect(b"\x28\xb5\x2f\xfd"), Some(Compression::Zstd));
+    assert_eq!(Compression::detect(b"BSDIFF40"), None);
+    assert_eq!(Compression::detect(b""), None);
+    assert_eq!(Compression::Zstd.is_supported(), cfg!(feature = "zstd"));
+
+    let (s, _) = sample();
+    assert!(input::decompress(gzip(&s)).unwrap() == s);
+    assert!(input::decompress(s.clone()).unwrap() == s);
+    #[cfg(feature = "zstd")]
+    assert!(input::decompress(zstd::encode_all(&s[..], 3).unwrap()).unwrap() == s);
+}
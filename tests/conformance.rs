@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::path;
+
+use qbsdiff::conformance::{self, VECTORS};
+use qbsdiff::{Format, PatchedReader};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn conformance_vectors_qbspatch() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    conformance::verify(|s, p| testing.qbspatch(s, p)).unwrap();
+
+    let names: HashSet<_> = VECTORS.iter().map(|v| v.name).collect();
+    assert_eq!(names.len(), VECTORS.len());
+}
+
+#[test]
+fn conformance_vectors_patched_reader() {
+    conformance::verify(|s, p| {
+        let mut t = Vec::new();
+        PatchedReader::new(s, p)?.read_to_end(&mut t)?;
+        Ok(t)
+    })
+    .unwrap();
+}
+
+#[test]
+fn conformance_vectors_bspatch() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    for vector in VECTORS
+        .iter()
+        .filter(|v| v.format == Format::Classic && v.target.is_some())
+    {
+        eprintln!("conformance test of bspatch on vector `{}`", vector.name);
+        if !conformance::check(vector, |s, p| testing.bspatch(s, p)) {
+            panic!("bspatch failed on vector `{}`", vector.name);
+        }
+    }
+}
+
+#[test]
+fn conformance_detects_broken_patcher() {
+    let err = conformance::verify(|s, _| Ok(s.to_vec())).unwrap_err();
+    assert!(err.to_string().contains("negative-seek"));
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "cross-verify")]
+// Cross-verification against the `bsdiff` crate, whose raw patch stream is
+// exactly the record stream of the interleaved layout with stored codec.
+// The `bidiff` crate is not covered, its container shares nothing with the
+// bsdiff 4.x family.
+
+use std::path;
+
+use qbsdiff::interleave::{apply_interleaved, interleave};
+use qbsdiff::{Bsdiff, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+/// Size of the interleaved header prepended to the raw record stream.
+const HEADER_SIZE: usize = 24;
+
+/// Convert a qbsdiff patch into a raw patch of the `bsdiff` crate.
+fn to_raw(patch: &[u8]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    interleave(patch, Codec::Stored, 0, &mut stream).unwrap();
+    stream.split_off(HEADER_SIZE)
+}
+
+/// Convert a raw patch of the `bsdiff` crate into an interleaved patch.
+fn from_raw(raw: &[u8], tsize: u64) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(HEADER_SIZE + raw.len());
+    stream.extend_from_slice(b"QBSDIFF3");
+    stream.extend_from_slice(&[0; 4]);
+    stream.extend_from_slice(&[0; 4]); // stored codec
+    stream.extend_from_slice(&tsize.to_le_bytes());
+    stream.extend_from_slice(raw);
+    stream
+}
+
+fn cross_verify(name: &str, s: &[u8], t: &[u8]) {
+    // qbsdiff => bsdiff-rs
+    for format in [Format::Classic, Format::Extended] {
+        let p1 = Bsdiff::new(s, t).format(format).compare_to_vec().unwrap();
+        let mut t1 = Vec::new();
+        bsdiff::patch(s, &mut &to_raw(&p1[..])[..], &mut t1).unwrap();
+        if t1 != t {
+            panic!("qbsdiff/bsdiff-rs incompatible ({:?}): `{}`", format, name);
+        }
+    }
+
+    // bsdiff-rs => qbsdiff
+    let mut raw = Vec::new();
+    bsdiff::diff(s, t, &mut raw).unwrap();
+    let mut t2 = Vec::new();
+    apply_interleaved(s, &from_raw(&raw[..], t.len() as u64)[..], &mut t2).unwrap();
+    if t2 != t {
+        panic!("bsdiff-rs/qbsdiff incompatible: `{}`", name);
+    }
+}
+
+#[test]
+fn random_samples_cross_verify() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("cross verification on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        cross_verify(&sample.name, &s[..], &t[..]);
+    }
+}
+
+#[test]
+fn degenerate_cross_verify() {
+    let s: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8 ^ (i >> 9) as u8).collect();
+    let mut t = s.clone();
+    t.splice(20_000..20_000, (0..3000u32).map(|i| (i * 7 % 13) as u8));
+    t.drain(40_000..41_000);
+
+    cross_verify("edited", &s[..], &t[..]);
+    cross_verify("identical", &s[..], &s[..]);
+    cross_verify("empty target", &s[..], &[]);
+    cross_verify("empty source", &[], &t[..]);
+    cross_verify("tiny", b"x", b"y");
+}
@@ -0,0 +1,97 @@
+use std::io::{self, ErrorKind};
+
+use qbsdiff::{inspect, transcode, Bsdiff, Bspatch, Format, PartialPatch};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..1 << 16)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(997) {
+        target[i] ^= 0x5a;
+    }
+    target.splice(20000..20000, b"inserted bytes".iter().copied());
+    (source, target)
+}
+
+fn apply(source: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)
+        .unwrap()
+        .apply(source, io::Cursor::new(&mut target))
+        .unwrap();
+    target
+}
+
+#[test]
+fn decoded_sizes_roundtrip() {
+    let (source, target) = sample();
+    for (compact_seek, source_checksum) in [(false, 0), (true, 4096)] {
+        let bsdiff = || {
+            Bsdiff::new(&source, &target)
+                .format(Format::Extended)
+                .compact_seek(compact_seek)
+                .source_checksum(source_checksum)
+        };
+        let plain = bsdiff().compare_to_vec().unwrap();
+        let patch = bsdiff().decoded_sizes(true).compare_to_vec().unwrap();
+        assert_eq!(patch.len(), plain.len() + 24);
+        assert!(apply(&source, &patch) == target);
+
+        let patcher = Bspatch::new(&patch).unwrap();
+        let sizes = patcher.hint_section_sizes().unwrap();
+        assert_eq!(Bspatch::new(&plain).unwrap().hint_section_sizes(), None);
+        let summary = inspect::validate(&patch, Some(&source)).unwrap();
+        assert_eq!(sizes, summary.section_sizes);
+        assert_eq!(sizes[1] + sizes[2], target.len() as u64);
+
+        let partial = PartialPatch::check(&patch[..200]).unwrap();
+        assert_eq!(partial.total_size(), Some(patch.len() as u64));
+    }
+}
+
+#[test]
+fn decoded_sizes_transcode() {
+    let (source, target) = sample();
+    let patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .compact_seek(true)
+        .decoded_sizes(true)
+        .compare_to_vec()
+        .unwrap();
+    let sizes = Bspatch::new(&patch).unwrap().hint_section_sizes();
+
+    let mut extended = Vec::new();
+    transcode(&patch[..], &mut extended, Format::Extended, Format::Extended).unwrap();
+    assert_eq!(Bspatch::new(&extended).unwrap().hint_section_sizes(), sizes);
+    assert!(apply(&source, &extended) == target);
+
+    let mut classic = Vec::new();
+    transcode(&patch[..], &mut classic, Format::Extended, Format::Classic).unwrap();
+    assert_eq!(Bspatch::new(&classic).unwrap().hint_section_sizes(), None);
+    assert!(apply(&source, &classic) == target);
+}
+
+#[test]
+fn decoded_sizes_mismatch() {
+    let (source, target) = sample();
+    let mut patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .decoded_sizes(true)
+        .compare_to_vec()
+        .unwrap();
+    // Understated sizes only shrink the buffers of patching.
+    patch[56..64].copy_from_slice(&1u64.to_le_bytes());
+    assert!(apply(&source, &patch) == target);
+    let err = inspect::validate(&patch, None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn decoded_sizes_requires_extended() {
+    let err = Bsdiff::new(b"source", b"target")
+        .decoded_sizes(true)
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
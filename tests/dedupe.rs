@@ -0,0 +1,68 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, ParallelScheme};
+
+#[test]
+fn dedupe_repetitive_target() {
+    let noise = |k: u32, n: u32| -> Vec<u8> { (0..n).map(|i| (i.wrapping_mul(k) >> 13) as u8).collect() };
+    let s = noise(2654435761, 256 * 1024);
+    // new block absent in source
+    let block = noise(2246822519, 4000);
+    let mut t = Vec::new();
+    for i in 0..64 {
+        t.extend_from_slice(&s[i * 4096..i * 4096 + 3000]);
+        t.extend_from_slice(&block[..]);
+    }
+    // overlapping repeats
+    t.extend(std::iter::repeat_n(block[..100].iter().copied(), 50).flatten());
+
+    let diff = |dedupe: bool, codec: Codec, buffer_size: usize| {
+        let mut p = Vec::new();
+        let report = Bsdiff::new(&s[..], &t[..])
+            .format(Format::Extended)
+            .codec(codec)
+            .parallel_scheme(ParallelScheme::Never)
+            .dedupe(dedupe)
+            .compare_report(io::Cursor::new(&mut p))
+            .unwrap();
+        let mut t1 = Vec::new();
+        Bspatch::new(&p[..])
+            .unwrap()
+            .buffer_size(buffer_size)
+            .apply(&s[..], io::Cursor::new(&mut t1))
+            .unwrap();
+        assert!(t1 == t);
+        (p, report)
+    };
+
+    let (plain, plain_report) = diff(false, Codec::Stored, 4096);
+    let (deduped, report) = diff(true, Codec::Stored, 4096);
+    diff(true, Codec::Bzip2, 128);
+    assert!(report.extra_size() < plain_report.extra_size() / 16);
+    assert!(deduped.len() * 2 < plain.len());
+
+    // target-relative copies require the extended format
+    let classic = Bsdiff::new(&s[..], &t[..]).dedupe(true).compare(io::sink());
+    assert_eq!(classic.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // fresh install
+    let mut empty = Vec::new();
+    Bsdiff::new(&[], &t[..])
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .dedupe(true)
+        .compare(io::Cursor::new(&mut empty))
+        .unwrap();
+    let mut t1 = Vec::new();
+    Bspatch::new(&empty[..])
+        .unwrap()
+        .apply(&[], io::Cursor::new(&mut t1))
+        .unwrap();
+    assert!(t1 == t);
+
+    // distances beyond the target produced are corrupted
+    let mut corrupted = empty.clone();
+    corrupted[48 + 32..48 + 40].copy_from_slice(&(1u64 << 30).to_le_bytes());
+    let result = Bspatch::new(&corrupted[..]).unwrap().apply(&[], io::sink());
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,95 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+/// Default settings and variants not affecting canonical patches, then
+/// variants of format settings.
+const SETTINGS: usize = 5;
+
+fn configure<'s, 't>(b: Bsdiff<'s, 't>, i: usize) -> Bsdiff<'s, 't> {
+    match i {
+        0 => b,
+        1 => b.parallel_scheme(ParallelScheme::Never).small_match(0),
+        2 => b.parallel_scheme(ParallelScheme::ChunkSize(1)).small_match(64),
+        3 => b.format(Format::Extended).codec(Codec::Stored).compression_level(0),
+        _ => b.format(Format::Extended).target_copy(true).dedupe(true),
+    }
+}
+
+fn diff(s: &[u8], t: &[u8], i: usize) -> Vec<u8> {
+    let mut p = Vec::new();
+    let size = configure(Bsdiff::new(s, t), i)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    assert_eq!(size, p.len() as u64);
+    p
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+fn data(n: usize) -> Vec<u8> {
+    hashed_bytes(0, n)
+}
+
+#[test]
+fn degenerate_empty_inputs() {
+    for f in 0..SETTINGS {
+        let p = diff(&[], &[], f);
+        assert!(apply(&[], &p).is_empty());
+        assert!(Bspatch::new(&p).unwrap().is_identity().unwrap());
+        assert!(p == diff(b"source", &[], f));
+        assert!(apply(b"source", &p).is_empty());
+
+        let p = diff(&[], b"target", f);
+        assert_eq!(apply(&[], &p), b"target");
+        assert!(!Bspatch::new(&p).unwrap().is_identity().unwrap());
+    }
+}
+
+#[test]
+fn degenerate_short_inputs() {
+    let s = data(300 * 1024);
+    for f in 0..SETTINGS {
+        // target shorter than small_match
+        for t in [&b"x"[..], &s[1000..1005], &s[..11]] {
+            let p = diff(&s, t, f);
+            assert!(apply(&s, &p) == t);
+        }
+
+        // source shorter than the parallel chunk
+        let mut t = data(600 * 1024);
+        t[100..110].copy_from_slice(&s[..10]);
+        let p = diff(&s[..100], &t, f);
+        assert!(apply(&s[..100], &p) == t);
+    }
+}
+
+#[test]
+fn degenerate_identical_inputs() {
+    for n in [1, 100, 300 * 1024, 1 << 20] {
+        let s = data(n);
+        let canonical = diff(&s, &s, 0);
+        for f in 1..3 {
+            assert!(diff(&s, &s, f) == canonical);
+        }
+        assert!(apply(&s, &canonical) == s);
+        assert!(Bspatch::new(&canonical).unwrap().is_identity().unwrap());
+
+        for f in 3..SETTINGS {
+            let p = diff(&s, &s, f);
+            assert!(apply(&s, &p) == s);
+            assert!(Bspatch::new(&p).unwrap().is_identity().unwrap());
+        }
+        assert_eq!(diff(&s, &s, 3).len(), n + 72);
+
+        let mut t = s.clone();
+        t[n / 2] ^= 1;
+        assert!(!Bspatch::new(&diff(&s, &t, 0)).unwrap().is_identity().unwrap());
+        assert!(!Bspatch::new(&diff(&s[1..], &s, 0)).unwrap().is_identity().unwrap());
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Cursor;
+
+use qbsdiff::{Bsdiff, ParallelScheme};
+
+fn pseudo_random(n: usize, seed: u64) -> Vec<u8> {
+    let mut x = seed;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect()
+}
+
+#[test]
+fn auto_scheme_report() {
+    let source = pseudo_random(1 << 20, 0x2545f4914f6cdd1d);
+    let mut target = source.clone();
+    target.extend_from_slice(&pseudo_random(3 << 20, 0x9e3779b97f4a7c15));
+
+    let mut patch = Vec::new();
+    let report = Bsdiff::new(&source, &target)
+        .parallel_scheme(ParallelScheme::Auto)
+        .compare_report(Cursor::new(&mut patch))
+        .unwrap();
+    assert_eq!(report.patch_size(), patch.len() as u64);
+    assert_eq!(report.target_size(), target.len() as u64);
+    assert!(report.target_entropy().unwrap() > 7.5);
+    assert!(report.chunk_size() >= 256 * 1024 || report.chunk_size() == target.len());
+    assert_eq!(report.jobs(), target.len().div_ceil(report.chunk_size()));
+    if report.threads() == 1 {
+        assert_eq!(report.jobs(), 1);
+    }
+
+    // Overriding with the reported decision reproduces the patch.
+    let mut patch1 = Vec::new();
+    let report1 = Bsdiff::new(&source, &target)
+        .parallel_scheme(ParallelScheme::ChunkSize(report.chunk_size()))
+        .compare_report(Cursor::new(&mut patch1))
+        .unwrap();
+    assert_eq!(report1.chunk_size(), report.chunk_size());
+    assert_eq!(report1.target_entropy(), None);
+    assert_eq!(patch, patch1);
+}
+
+#[test]
+fn small_target_single_job() {
+    let source = b"the quick brown fox jumps over the lazy dog";
+    let target = b"the quick brown cat jumps over the lazy dog!";
+    let mut patch = Vec::new();
+    let report = Bsdiff::new(source, target)
+        .compare_report(Cursor::new(&mut patch))
+        .unwrap();
+    assert_eq!(report.jobs(), 1);
+    assert_eq!(report.chunk_size(), target.len());
+    assert!(report.target_entropy().unwrap() < 5.0);
+}
+
+#[test]
+fn analyze_anomalies() {
+    let source = pseudo_random(1 << 20, 0x2545f4914f6cdd1d);
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(4096) {
+        target[i] ^= 0xff;
+    }
+
+    let report = Bsdiff::new(&source, &target)
+        .analyze(true)
+        .compare_report(Cursor::new(Vec::new()))
+        .unwrap();
+    assert!(report.similarity().unwrap() > 0.9);
+    assert!(report.anomalies().is_empty());
+    assert!(report.extra_size() < target.len() as u64 / 10);
+    assert!(report.controls() > 0);
+
+    // Ignoring all matches emits the whole target as extra data.
+    let report = Bsdiff::new(&source, &target)
+        .small_match(1 << 30)
+        .scoring(|_, _| -1)
+        .analyze(true)
+        .compare_report(Cursor::new(Vec::new()))
+        .unwrap();
+    let codes: Vec<_> = report.anomalies().iter().map(|a| a.code()).collect();
+    assert_eq!(codes, ["excessive-extra"]);
+
+    let report = Bsdiff::new(&source, &target)
+        .small_match(1 << 30)
+        .compare_report(Cursor::new(Vec::new()))
+        .unwrap();
+    assert_eq!(report.similarity(), None);
+    assert!(report.anomalies().is_empty());
+}
@@ -0,0 +1,75 @@
+use std::io;
+
+use qbsdiff::{differential, Bspatch};
+use qbsdiff_test_bench_utils::*;
+
+fn xorshift(x: &mut u64) -> u64 {
+    *x ^= *x << 13;
+    *x ^= *x >> 7;
+    *x ^= *x << 17;
+    *x
+}
+
+/// Mutate data by random replacements, insertions, deletions and moves.
+fn mutate(data: &[u8], seed: u64) -> Vec<u8> {
+    let mut x = seed;
+    let mut out = data.to_vec();
+    for _ in 0..(xorshift(&mut x) % 16) {
+        let pos = (xorshift(&mut x) as usize) % (out.len() + 1);
+        let len = (xorshift(&mut x) % 64) as usize;
+        match xorshift(&mut x) % 4 {
+            0 => {
+                for b in out.iter_mut().skip(pos).take(len) {
+                    *b = xorshift(&mut x) as u8;
+                }
+            }
+            1 => {
+                let bytes: Vec<u8> = (0..len).map(|_| xorshift(&mut x) as u8).collect();
+                out.splice(pos..pos, bytes);
+            }
+            2 => {
+                let end = Ord::min(pos + len, out.len());
+                out.drain(pos..end);
+            }
+            _ => {
+                let end = Ord::min(pos + len, out.len());
+                let moved: Vec<u8> = out.drain(pos..end).collect();
+                let to = (xorshift(&mut x) as usize) % (out.len() + 1);
+                out.splice(to..to, moved);
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn differential_random_mutations() {
+    let mut x = 0x2545f4914f6cdd1d;
+    for seed in 1..=64u64 {
+        let len = (xorshift(&mut x) % 4096) as usize;
+        let source: Vec<u8> = (0..len).map(|_| (xorshift(&mut x) % 8) as u8).collect();
+        let target = mutate(&source[..], seed);
+        differential::check(&source[..], &target[..]).unwrap();
+    }
+    differential::check(&[], &[]).unwrap();
+    differential::check(b"abc", &[]).unwrap();
+    differential::check(&[], b"abc").unwrap();
+}
+
+#[test]
+fn reference_diff_matches() {
+    let source = hashed_bytes(0, 64 * 1024);
+    let mut target = source[32 * 1024..].to_vec();
+    target.extend_from_slice(&source[..32 * 1024]);
+
+    let mut patch = Vec::new();
+    differential::reference_diff(&source[..], &target[..], io::Cursor::new(&mut patch)).unwrap();
+    assert!(patch.len() < 1024);
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&patch[..])
+        .unwrap()
+        .apply(&source[..], io::Cursor::new(&mut t1))
+        .unwrap();
+    assert_eq!(t1, target);
+}
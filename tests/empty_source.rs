@@ -0,0 +1,45 @@
+use std::io;
+use std::path;
+
+use qbsdiff::inspect::{self, RegionKind};
+use qbsdiff::{Bsdiff, Bspatch, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn empty_source_pure_extra() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let t: Vec<u8> = (0..3 * 1024 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 9) as u8)
+        .collect();
+
+    let mut p = Vec::new();
+    let report = Bsdiff::new(&[], &t[..])
+        .parallel_scheme(ParallelScheme::NumJobs(4))
+        .compare_report(io::Cursor::new(&mut p))
+        .unwrap();
+    assert_eq!(report.jobs(), 1);
+    assert_eq!(report.search_steps(), 0);
+
+    let regions = inspect::regions(&p[..]).unwrap();
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[0].kind, RegionKind::Extra);
+    assert_eq!(regions[0].target, 0..t.len() as u64);
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&p[..])
+        .unwrap()
+        .buffer_size(4096)
+        .apply(&[], io::Cursor::new(&mut t1))
+        .unwrap();
+    assert!(t1 == t);
+    assert!(testing.bspatch(&[], &p[..]).unwrap() == t);
+
+    let mut empty = Vec::new();
+    Bsdiff::new(&[], &[]).compare(io::Cursor::new(&mut empty)).unwrap();
+    assert_eq!(testing.qbspatch(&[], &empty[..]).unwrap(), Vec::<u8>::new());
+
+    // delta from the empty source is corrupted
+    let p = testing.qbsdiff(b"abcdefgh", b"abcdefgh").unwrap();
+    assert!(Bspatch::new(&p[..]).unwrap().apply(&[], io::sink()).is_err());
+}
@@ -0,0 +1,92 @@
+use std::path;
+
+use qbsdiff::{Bsdiff, SourceIndex};
+use qbsdiff_test_bench_utils::*;
+
+fn noise(seed: u32, len: usize, alphabet: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            ((x >> 8) % alphabet) as u8
+        })
+        .collect()
+}
+
+fn texts() -> Vec<Vec<u8>> {
+    let mut texts = vec![Vec::new(), vec![7], vec![0; 1000], b"mississippi".to_vec()];
+    let mut fibonacci = (b"a".to_vec(), b"ab".to_vec());
+    while fibonacci.1.len() < 5000 {
+        let next = [&fibonacci.1[..], &fibonacci.0[..]].concat();
+        fibonacci = (fibonacci.1, next);
+    }
+    texts.push(fibonacci.1);
+    texts.push(b"abcab".iter().cycle().take(3001).copied().collect());
+    for (seed, alphabet) in (1..40).zip([2, 3, 4, 256].into_iter().cycle()) {
+        texts.push(noise(seed, seed as usize * 97 % 2000, alphabet));
+    }
+    let mut mixed = noise(99, 1 << 18, 256);
+    mixed.extend_from_slice(&[0; 1 << 17]);
+    mixed.extend(noise(99, 1 << 18, 4));
+    mixed.extend_from_within(1000..300000);
+    texts.push(mixed);
+    texts
+}
+
+fn serialized(index: SourceIndex) -> Vec<u8> {
+    let mut data = Vec::new();
+    index.serialize(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn enhanced_index_search() {
+    for s in texts() {
+        let index = SourceIndex::build_enhanced(&s).unwrap();
+        let built = SourceIndex::new(&s);
+        let patterns = [
+            &b""[..],
+            b"a",
+            b"ab",
+            b"abc",
+            b"abcabcabd",
+            b"\0\0\0\0",
+            b"is",
+            b"\x01\x02",
+        ];
+        let chunks = s.chunks(13).take(50).chain(s.windows(40).step_by(997));
+        let noisy: Vec<_> = s.windows(24).step_by(331).map(|w| [w, &w[..7]].concat()).collect();
+        for pattern in patterns
+            .into_iter()
+            .chain(chunks)
+            .chain(noisy.iter().map(Vec::as_slice))
+        {
+            let (offset, len) = index.search_lcp(pattern);
+            assert_eq!(len, built.search_lcp(pattern).1);
+            assert_eq!(&s[offset..offset + len], &pattern[..len]);
+            assert_eq!(index.search_all(pattern), built.search_all(pattern));
+            assert_eq!(index.contains(pattern), built.contains(pattern));
+        }
+        assert!(serialized(index) == serialized(built));
+    }
+}
+
+#[test]
+fn pathological_samples_enhanced_index() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_pathological_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("enhanced index test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        let index = SourceIndex::build_enhanced(&s[..]).unwrap();
+        let p = Bsdiff::with_index(&index, &t[..]).compare_to_vec().unwrap();
+        if testing.qbspatch(&s[..], &p[..]).unwrap() != t {
+            panic!("patch with enhanced index failed: `{}`", sample.name);
+        }
+    }
+}
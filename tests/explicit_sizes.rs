@@ -0,0 +1,108 @@
+use std::io::{self, ErrorKind};
+
+use qbsdiff::format::MAX_DECLARED_SIZE;
+use qbsdiff::{transcode, Bsdiff, Bspatch, Format, PatchError, PatchSizes};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..1 << 16)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(997) {
+        target[i] ^= 0x5a;
+    }
+    target.splice(20000..20000, b"inserted bytes".iter().copied());
+    (source, target)
+}
+
+fn explicit(source: &[u8], target: &[u8]) -> Vec<u8> {
+    Bsdiff::new(source, target)
+        .format(Format::Extended)
+        .explicit_sizes(true)
+        .compare_to_vec()
+        .unwrap()
+}
+
+fn apply(source: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)?.apply(source, io::Cursor::new(&mut target))?;
+    Ok(target)
+}
+
+#[test]
+fn explicit_sizes_roundtrip() {
+    let (source, target) = sample();
+    let plain = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .decoded_sizes(true)
+        .compare_to_vec()
+        .unwrap();
+    let patch = explicit(&source, &target);
+    assert_eq!(patch.len(), plain.len() + 12);
+    assert!(apply(&source, &patch).unwrap() == target);
+
+    let patcher = Bspatch::new(&patch).unwrap();
+    let tsize = target.len() as u64;
+    assert_eq!(
+        patcher.sizes(),
+        PatchSizes {
+            declared_target: tsize,
+            verified_target: Some(tsize),
+            declared_source: Some(source.len() as u64),
+        }
+    );
+    assert!(patcher.hint_section_sizes().is_some());
+    let sizes = Bspatch::new(&plain).unwrap().sizes();
+    assert_eq!((sizes.verified_target, sizes.declared_source), (None, None));
+
+    // carried by transcoding, dropped by the classic format
+    let mut extended = Vec::new();
+    transcode(&patch[..], &mut extended, Format::Extended, Format::Extended).unwrap();
+    assert_eq!(Bspatch::new(&extended).unwrap().sizes(), patcher.sizes());
+    let mut classic = Vec::new();
+    transcode(&patch[..], &mut classic, Format::Extended, Format::Classic).unwrap();
+    assert_eq!(Bspatch::new(&classic).unwrap().sizes().declared_source, None);
+
+    let err = Bsdiff::new(&source, &target)
+        .explicit_sizes(true)
+        .compare_to_vec()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn explicit_sizes_reject_source() {
+    let (source, target) = sample();
+    let patch = explicit(&source, &target);
+    let err = apply(&source[..source.len() - 1], &patch).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let mut longer = source.clone();
+    longer.push(0);
+    let err = apply(&longer, &patch).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn explicit_sizes_reject_header() {
+    let (source, target) = sample();
+    let patch = explicit(&source, &target);
+    let corrupt = |offset: usize, value: u64| {
+        let mut patch = patch.clone();
+        patch[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        Bspatch::new(&patch).err().and_then(|err| PatchError::of(&err))
+    };
+    let tsize = target.len() as u64;
+
+    // target size disagreeing with the decoded sections, or out of limits
+    assert_eq!(corrupt(40, tsize + 1), Some(PatchError::InvalidSize));
+    assert_eq!(corrupt(56, 1), Some(PatchError::InvalidSize));
+    assert_eq!(corrupt(40, MAX_DECLARED_SIZE + 1), Some(PatchError::InvalidSize));
+    assert_eq!(corrupt(72, MAX_DECLARED_SIZE + 1), Some(PatchError::InvalidSize));
+
+    // size flags are reserved
+    let mut flagged = patch.clone();
+    flagged[80] = 1;
+    let err = Bspatch::new(&flagged).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
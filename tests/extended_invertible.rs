@@ -0,0 +1,95 @@
+use std::path;
+
+use qbsdiff::{Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+// Codecs to test.
+const CODECS: &[Codec] = &[
+    Codec::Stored,
+    Codec::Bzip2,
+    #[cfg(feature = "zstd")]
+    Codec::Zstd,
+];
+
+#[test]
+fn regular_samples_ext_invert() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for &codec in CODECS.iter() {
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec,
+            ..QbsdiffOptions::default()
+        };
+        for sample in samples.iter() {
+            eprintln!("extended invertible test ({:?}) on sample `{}`", codec, sample.name);
+            let s = sample.load_source().unwrap();
+            let t = sample.load_target().unwrap();
+
+            let p = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+            let t1 = testing.qbspatch(&s[..], &p[..]).unwrap();
+            if t != t1 {
+                panic!("not extended invertible ({:?}): `{}`", codec, sample.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn random_samples_ext_invert() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+
+    for &codec in CODECS.iter() {
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec,
+            ..QbsdiffOptions::default()
+        };
+        for sample in samples.iter() {
+            eprintln!("extended invertible test ({:?}) on sample `{}`", codec, sample.name);
+            let s = sample.load_source().unwrap();
+            let t = sample.load_target().unwrap();
+
+            let p = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+            let t1 = testing.qbspatch(&s[..], &p[..]).unwrap();
+            if t != t1 {
+                panic!("not extended invertible ({:?}): `{}`", codec, sample.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn compression_level_zero_stores() {
+    let testing = Testing::new(std::env::temp_dir());
+    let s = b"the quick brown fox jumps over the lazy dog";
+    let t = b"the quick brown cat jumps over the lazy dog!";
+
+    let classic = QbsdiffOptions {
+        compression_level: 0,
+        ..QbsdiffOptions::default()
+    };
+    assert!(testing.qbsdiff_with(&s[..], &t[..], classic).is_err());
+
+    let stored = QbsdiffOptions {
+        format: Format::Extended,
+        codec: Codec::Stored,
+        ..QbsdiffOptions::default()
+    };
+    let p = testing.qbsdiff_with(&s[..], &t[..], stored).unwrap();
+    for &codec in CODECS.iter() {
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec,
+            compression_level: 0,
+            ..QbsdiffOptions::default()
+        };
+        let p1 = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+        assert_eq!(p, p1, "level 0 not stored ({:?})", codec);
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::{self, ErrorKind, Write};
+use std::sync::{Arc, Mutex};
+
+use qbsdiff::{Bsdiff, Bspatch};
+
+/// Sink recording flushes and the bytes pending between flushes.
+#[derive(Default)]
+struct Sink {
+    data: Vec<u8>,
+    flushed: usize,
+    flushes: usize,
+    max_pending: usize,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        self.max_pending = Ord::max(self.max_pending, self.data.len() - self.flushed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushed = self.data.len();
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..1 << 20)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(4099) {
+        target[i] ^= 0x5a;
+    }
+    let patch = Bsdiff::new(&source, &target).compare_to_vec().unwrap();
+    (source, target, patch)
+}
+
+#[test]
+fn flush_cadence() {
+    let (source, target, patch) = sample();
+
+    let mut sink = Sink::default();
+    Bspatch::new(&patch).unwrap().apply(&source, &mut sink).unwrap();
+    assert_eq!(sink.flushes, 1);
+    assert!(sink.data == target);
+
+    let mut sink = Sink::default();
+    Bspatch::new(&patch)
+        .unwrap()
+        .buffer_size(4096)
+        .flush_every(64 * 1024)
+        .apply(&source, &mut sink)
+        .unwrap();
+    assert!(sink.data == target);
+    assert!(sink.flushes >= target.len() / (64 * 1024));
+    assert!(sink.max_pending < 64 * 1024 + 4096);
+    assert_eq!(sink.flushed, target.len());
+}
+
+#[test]
+fn complete_hook() {
+    let (source, target, patch) = sample();
+
+    let seen = Arc::new(Mutex::new(None));
+    let hook = seen.clone();
+    let mut sink = Sink::default();
+    let size = Bspatch::new(&patch)
+        .unwrap()
+        .on_complete(move |size| {
+            *hook.lock().unwrap() = Some(size);
+            Ok(())
+        })
+        .apply(&source, &mut sink)
+        .unwrap();
+    assert_eq!(size, target.len() as u64);
+    assert_eq!(*seen.lock().unwrap(), Some(size));
+    assert_eq!(sink.flushed, target.len());
+
+    let mut t = Vec::new();
+    let size = Bspatch::new(&patch)
+        .unwrap()
+        .on_complete(|size| {
+            assert_eq!(size, 100);
+            Ok(())
+        })
+        .apply_range(&source, 1000..1100, io::Cursor::new(&mut t))
+        .unwrap();
+    assert_eq!(size, 100);
+    assert!(t[..] == target[1000..1100]);
+
+    let err = Bspatch::new(&patch)
+        .unwrap()
+        .on_complete(|_| Err(io::Error::other("sync failed")))
+        .apply(&source, io::sink())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "sync failed");
+
+    let called = Arc::new(Mutex::new(false));
+    let hook = called.clone();
+    let err = Bspatch::new(&patch)
+        .unwrap()
+        .on_complete(move |_| {
+            *hook.lock().unwrap() = true;
+            Ok(())
+        })
+        .apply(&source[..1000], io::sink())
+        .unwrap_err();
+    assert_ne!(err.kind(), ErrorKind::Other);
+    assert!(!*called.lock().unwrap());
+}
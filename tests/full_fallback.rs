@@ -0,0 +1,83 @@
+use std::io;
+
+use qbsdiff::inspect::{self, RegionKind};
+use qbsdiff::{Bsdiff, Bspatch, Format};
+
+fn noise(seed: u32, len: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            (x >> 24) as u8
+        })
+        .collect()
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn full_fallback_unrelated_inputs() {
+    let s = noise(1, 200 * 1000);
+    let t = noise(7, 150 * 1000);
+    for format in [Format::Classic, Format::Extended] {
+        let mut p = Vec::new();
+        let report = Bsdiff::new(&s[..], &t[..])
+            .format(format)
+            .full_fallback(Some(0.9))
+            .compare_report(io::Cursor::new(&mut p))
+            .unwrap();
+        assert!(report.full_fallback());
+        assert_eq!(report.patch_size(), p.len() as u64);
+        let regions = inspect::regions(&p[..]).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].kind, RegionKind::Extra);
+        assert!(apply(&s[..], &p[..]) == t);
+
+        // the full patch equals the patch of an empty source
+        let full = Bsdiff::new(&[], &t[..]).format(format).compare_to_vec().unwrap();
+        assert!(p == full);
+        let p1 = Bsdiff::new(&s[..], &t[..])
+            .format(format)
+            .full_fallback(Some(0.9))
+            .compare_to_vec()
+            .unwrap();
+        assert!(p1 == full);
+    }
+}
+
+#[test]
+fn full_fallback_keeps_small_delta() {
+    let s = noise(1, 200 * 1000);
+    let mut t = s.clone();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+
+    let mut p = Vec::new();
+    let report = Bsdiff::new(&s[..], &t[..])
+        .format(Format::Extended)
+        .full_fallback(Some(0.9))
+        .compare_report(io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(!report.full_fallback());
+    assert!(
+        p == Bsdiff::new(&s[..], &t[..])
+            .format(Format::Extended)
+            .compare_to_vec()
+            .unwrap()
+    );
+
+    // ratio 0 always falls back
+    let mut p = Vec::new();
+    let report = Bsdiff::new(&s[..], &t[..])
+        .full_fallback(Some(0.0))
+        .compare_report(io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(report.full_fallback());
+    assert!(apply(&s[..], &p[..]) == t);
+}
@@ -0,0 +1,100 @@
+use std::io::{self, ErrorKind};
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, PatchProfile, SourceCorruption};
+use qbsdiff_test_bench_utils::*;
+
+const WINDOW: usize = 4096;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 64 * 1000);
+    let mut t = s.clone();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+fn diff(s: &[u8], t: &[u8]) -> Vec<u8> {
+    let mut p = Vec::new();
+    Bsdiff::new(s, t)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .source_checksum(WINDOW)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    p
+}
+
+fn apply(s: &[u8], p: &[u8], radius: usize) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?
+        .fuzzy_source(radius)
+        .apply(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn fuzzy_source_shifted_regions() {
+    let (s, t) = sample();
+    let p = diff(&s, &t);
+    assert!(apply(&s, &p, 64).unwrap() == t);
+
+    // padding inserted at a window boundary shifts the following windows
+    let mut s1 = s.clone();
+    s1.splice(8192..8192, [0; 16]);
+    assert_eq!(apply(&s1, &p, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert!(apply(&s1, &p, 15).is_err());
+    assert!(apply(&s1, &p, 16).unwrap() == t);
+
+    // shifts accumulated at more boundaries
+    let mut s2 = s1.clone();
+    s2.splice(40976..40976, [0; 8]);
+    assert!(apply(&s2, &p, 16).is_err());
+    assert!(apply(&s2, &p, 24).unwrap() == t);
+
+    // timestamp rewritten in place, and the region shifted back
+    let mut s3 = s.clone();
+    s3[20480..20488].copy_from_slice(b"20261016");
+    s3.copy_within(24576..28672, 24580);
+    let err = apply(&s3, &p, 64).unwrap_err();
+    assert_eq!(SourceCorruption::of(&err).unwrap().range, 20480..24576);
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&p)
+        .unwrap()
+        .profile(&PatchProfile {
+            fuzzy_radius: 64,
+            ..PatchProfile::default()
+        })
+        .apply_range(&s2, 30000..40000, io::Cursor::new(&mut t1))
+        .unwrap();
+    assert!(t1[..] == t[30000..40000]);
+}
+
+#[test]
+fn fuzzy_source_without_checksums() {
+    let (s, t) = sample();
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &t).compare(io::Cursor::new(&mut p)).unwrap();
+    assert!(apply(&s, &p, 64).unwrap() == t);
+    let mut s1 = s.clone();
+    s1.splice(8192..8192, [0; 16]);
+    assert!(apply(&s1, &p, 64).unwrap() != t);
+}
+
+#[test]
+fn fuzzy_source_huge_declared_size() {
+    let (s, t) = sample();
+    let p = diff(&s, &t);
+
+    // a single checksum window of a source declared of i64::MAX bytes
+    let windows = s.len().div_ceil(WINDOW);
+    let mut huge = p[..48].to_vec();
+    huge.extend_from_slice(&(i64::MAX as u64).to_le_bytes());
+    huge.extend_from_slice(&(i64::MAX as u64).to_le_bytes());
+    huge.extend_from_slice(&p[64..68]);
+    huge.extend_from_slice(&p[64 + 4 * windows..]);
+    for radius in [64, usize::MAX] {
+        let err = apply(&s, &huge, radius).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+
+use qbsdiff::golden::{check_determinism, Baseline, Divergence, Fingerprint, Phase};
+use qbsdiff::{Bsdiff, Codec, DiffProfile, Format, ParallelScheme};
+
+fn corpus() -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let s: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 10) as u8).collect();
+    let mut edited = s.clone();
+    for i in (0..edited.len()).step_by(500) {
+        edited[i] = edited[i].wrapping_add(1);
+    }
+    let mut appended = s.clone();
+    appended.extend_from_slice(b"appended data");
+    vec![("edited", s.clone(), edited), ("appended", s, appended)]
+}
+
+fn measure() -> Baseline {
+    let corpus = corpus();
+    Baseline::measure(corpus.iter().map(|(n, s, t)| (*n, &s[..], &t[..])), |s, t| {
+        Bsdiff::new(s, t).compare_to_vec()
+    })
+    .unwrap()
+}
+
+#[test]
+fn golden_roundtrip() {
+    let sizes = measure();
+    assert_eq!(sizes.len(), 2);
+    assert!(sizes.get("edited").unwrap() > 0);
+
+    let text = format!("# patch sizes\n\n{}", sizes);
+    assert_eq!(Baseline::parse(&text).unwrap(), sizes);
+    assert_eq!(
+        Baseline::parse("a 1 2\n").unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert_eq!(Baseline::parse("a x\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert!(Baseline::new().record("a b", 1).is_err());
+}
+
+#[test]
+fn golden_regressions() {
+    let sizes = measure();
+    sizes.check(&sizes, 0.0).unwrap();
+
+    // smaller patches and new entries pass
+    let mut baseline = sizes.clone();
+    baseline.record("edited", sizes.get("edited").unwrap() + 100).unwrap();
+    sizes.check(&baseline, 0.0).unwrap();
+    let mut baseline = Baseline::new();
+    baseline.record("edited", sizes.get("edited").unwrap()).unwrap();
+    sizes.check(&baseline, 0.0).unwrap();
+
+    // larger patches beyond the tolerance and missing entries fail
+    let size = sizes.get("appended").unwrap();
+    let mut baseline = sizes.clone();
+    baseline.record("appended", size * 100 / 110).unwrap();
+    sizes.check(&baseline, 0.2).unwrap();
+    let err = sizes.check(&baseline, 0.05).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let message = err.to_string();
+    assert!(message.contains(&format!("! appended: {} -> {}", size * 100 / 110, size)));
+    assert!(!message.contains("edited"));
+
+    let mut baseline = sizes.clone();
+    baseline.record("gone", 1).unwrap();
+    let err = sizes.check(&baseline, 1.0).unwrap_err();
+    assert!(err.to_string().contains("! gone: 1 -> missing"));
+}
+
+#[test]
+fn golden_file() {
+    let path = std::env::temp_dir().join(format!("qbsdiff-golden-{}.baseline", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let sizes = measure();
+    sizes.check_file(&path, 0.0).unwrap();
+    assert_eq!(Baseline::load(&path).unwrap(), sizes);
+    sizes.check_file(&path, 0.0).unwrap();
+
+    let mut larger = sizes.clone();
+    larger.record("edited", sizes.get("edited").unwrap() * 2).unwrap();
+    assert!(larger.check_file(&path, 0.5).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn golden_determinism() {
+    let (_, source, target) = corpus().swap_remove(0);
+    let profile = DiffProfile {
+        parallel_scheme: ParallelScheme::ChunkSize(16 * 1024),
+        ..DiffProfile::default()
+    };
+    let patch = Bsdiff::new(&source, &target)
+        .profile(&profile)
+        .compare_to_vec()
+        .unwrap();
+    let expected = Fingerprint::of(&patch).unwrap();
+    assert_eq!(expected.to_string().parse::<Fingerprint>().unwrap(), expected);
+    assert!("0:0".parse::<Fingerprint>().is_err());
+    assert!(check_determinism(&source, &target, &profile, &expected).unwrap() == patch);
+
+    // same controls, other codec
+    let stored = DiffProfile {
+        format: Format::Extended,
+        codec: Codec::Stored,
+        ..profile.clone()
+    };
+    let err = check_determinism(&source, &target, &stored, &expected).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let divergence = Divergence::of(&err).unwrap();
+    assert_eq!(divergence.phase, Phase::Compression);
+    assert_eq!(divergence.actual.controls, expected.controls);
+
+    // other controls
+    let err = check_determinism(&source, &target[1..], &profile, &expected).unwrap_err();
+    assert_eq!(Divergence::of(&err).unwrap().phase, Phase::Controls);
+}
@@ -0,0 +1,90 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, ParallelScheme};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+/// Pseudo random "code" of given size.
+fn code(seed: u64, size: usize) -> Vec<u8> {
+    let mut x = seed.wrapping_mul(0x9e3779b97f4a7c15) | 1;
+    (0..size)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        })
+        .collect()
+}
+
+/// Relocate the code by patching an "address" every 8 bytes, leaving no
+/// exact matches longer than 7 bytes.
+fn relocate(code: &[u8]) -> Vec<u8> {
+    let mut code = code.to_vec();
+    for i in (0..code.len()).step_by(8) {
+        code[i] = code[i].wrapping_add(0x10);
+    }
+    code
+}
+
+/// Source of sections `a`, `b` and `c`, and target of `c`, `b` and relocated
+/// `a` moved around, with the hints of the moved sections.
+fn sample() -> (Vec<u8>, Vec<u8>, Vec<(usize, usize)>) {
+    let (a, b, c) = (code(1, 60_000), code(2, 40_000), code(3, 30_000));
+    let s = [&a[..], &b[..], &c[..]].concat();
+    let t = [&c[..], &b[..], &relocate(&a)[..]].concat();
+    let hints = vec![(100_000, 0), (60_000, 30_000), (0, 70_000)];
+    (s, t, hints)
+}
+
+#[test]
+fn hints_relocated_sections() {
+    let (s, t, hints) = sample();
+    let plain = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let hinted = Bsdiff::new(&s[..], &t[..])
+        .hints(hints.iter().copied())
+        .compare_to_vec()
+        .unwrap();
+    assert!(apply(&s[..], &plain[..]) == t);
+    assert!(apply(&s[..], &hinted[..]) == t);
+    assert!(
+        hinted.len() * 4 < plain.len(),
+        "hinted {} vs plain {}",
+        hinted.len(),
+        plain.len()
+    );
+
+    // hints are split across parallel chunks
+    let chunked = Bsdiff::new(&s[..], &t[..])
+        .parallel_scheme(ParallelScheme::ChunkSize(50_000))
+        .hints(hints.iter().copied())
+        .compare_to_vec()
+        .unwrap();
+    assert!(apply(&s[..], &chunked[..]) == t);
+    assert!(chunked.len() * 4 < plain.len());
+}
+
+#[test]
+fn hints_wrong_or_out_of_bounds() {
+    let (s, t, _) = sample();
+    let plain = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+
+    // wrong hints only cost a few controls
+    let wrong: Vec<(usize, usize)> = (0..t.len()).step_by(10_000).map(|j| ((j * 7) % s.len(), j)).collect();
+    let p = Bsdiff::new(&s[..], &t[..]).hints(wrong).compare_to_vec().unwrap();
+    assert!(apply(&s[..], &p[..]) == t);
+    assert!(p.len() < plain.len() + plain.len() / 10);
+
+    // out of bounds and duplicated hints are ignored
+    let p = Bsdiff::new(&s[..], &t[..])
+        .hints([(s.len(), 0), (0, t.len()), (usize::MAX, usize::MAX), (0, 5), (10, 5)])
+        .compare_to_vec()
+        .unwrap();
+    assert!(apply(&s[..], &p[..]) == t);
+    let p = Bsdiff::new(&s[..], &[]).hints([(0, 0)]).compare_to_vec().unwrap();
+    assert!(apply(&s[..], &p[..]).is_empty());
+}
@@ -0,0 +1,95 @@
+use std::io::{self, ErrorKind};
+
+use qbsdiff::{Bsdiff, SourceIndex};
+
+fn noise(seed: u32, len: usize, alphabet: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            ((x >> 8) % alphabet) as u8
+        })
+        .collect()
+}
+
+fn texts() -> Vec<Vec<u8>> {
+    let mut texts = vec![Vec::new(), vec![7], vec![0; 1000], b"mississippi".to_vec()];
+    let mut fibonacci = (b"a".to_vec(), b"ab".to_vec());
+    while fibonacci.1.len() < 5000 {
+        let next = [&fibonacci.1[..], &fibonacci.0[..]].concat();
+        fibonacci = (fibonacci.1, next);
+    }
+    texts.push(fibonacci.1);
+    texts.push(b"abcab".iter().cycle().take(3001).copied().collect());
+    for (seed, alphabet) in (1..40).zip([2, 3, 4, 256].into_iter().cycle()) {
+        texts.push(noise(seed, seed as usize * 97 % 2000, alphabet));
+    }
+    let mut mixed = noise(99, 1 << 20, 256);
+    mixed.extend_from_slice(&[0; 1 << 19]);
+    mixed.extend(noise(99, 1 << 20, 4));
+    mixed.extend_from_within(1000..300000);
+    texts.push(mixed);
+    texts
+}
+
+fn serialized(index: SourceIndex) -> Vec<u8> {
+    let mut data = Vec::new();
+    index.serialize(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn index_progress_same_index() {
+    for s in texts() {
+        let mut fractions = Vec::new();
+        let index = SourceIndex::build_with_progress(&s, |fraction| {
+            fractions.push(fraction);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fractions.first(), Some(&0.0));
+        assert_eq!(fractions.last(), Some(&1.0));
+        assert!(fractions.windows(2).all(|w| w[0] <= w[1]));
+
+        let built = SourceIndex::new(&s);
+        for pattern in [&b""[..], b"a", b"ab", b"abc", b"\0\0\0\0", b"is", b"\x01\x02"]
+            .into_iter()
+            .chain(s.chunks(13).take(50))
+        {
+            assert_eq!(index.search_lcp(pattern), built.search_lcp(pattern));
+            assert_eq!(index.search_all(pattern), built.search_all(pattern));
+            assert_eq!(index.contains(pattern), built.contains(pattern));
+        }
+        assert!(serialized(index) == serialized(built));
+    }
+}
+
+#[test]
+fn index_progress_same_patch() {
+    let s = noise(5, 300 * 1000, 256);
+    let mut t = s[1000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    let index = SourceIndex::build_with_progress(&s, |_| Ok(())).unwrap();
+    let p = Bsdiff::with_index(&index, &t).compare_to_vec().unwrap();
+    assert!(p == Bsdiff::new(&s, &t).compare_to_vec().unwrap());
+}
+
+#[test]
+fn index_progress_cancelled() {
+    let s = noise(3, 4 << 20, 16);
+    let mut calls = 0;
+    let err = SourceIndex::build_with_progress(&s, |fraction| {
+        calls += 1;
+        if fraction > 0.5 {
+            return Err(io::Error::new(ErrorKind::Interrupted, "cancelled"));
+        }
+        Ok(())
+    })
+    .err()
+    .unwrap();
+    assert_eq!(err.kind(), ErrorKind::Interrupted);
+    assert!(calls > 5);
+}
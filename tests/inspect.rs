@@ -0,0 +1,158 @@
+use std::io;
+
+use qbsdiff::conformance::VECTORS;
+use qbsdiff::inspect::{self, RegionKind};
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regions_cover_target() {
+    for vector in VECTORS.iter() {
+        let target = match vector.target {
+            Some(target) => target,
+            None => continue,
+        };
+        eprintln!("inspect test on vector `{}`", vector.name);
+        let regions = inspect::regions(vector.patch).unwrap();
+
+        let mut tpos = 0;
+        let mut rebuilt = Vec::new();
+        for region in regions.iter() {
+            assert_eq!(region.target.start, tpos);
+            tpos = region.target.end;
+            let t = &target[region.target.start as usize..region.target.end as usize];
+            match region.kind {
+                RegionKind::Delta => {
+                    let source = region.source.clone().unwrap();
+                    let s = &vector.source[source.start as usize..source.end as usize];
+                    let changed = Iterator::zip(s.iter(), t.iter()).filter(|(x, y)| x != y).count();
+                    assert_eq!(region.changed, changed as u64);
+                }
+                RegionKind::Extra | RegionKind::Repeat => assert!(region.source.is_none()),
+            }
+            rebuilt.extend_from_slice(t);
+        }
+        assert_eq!(&rebuilt[..], target);
+    }
+}
+
+#[test]
+fn regions_export() {
+    let vector = VECTORS.iter().find(|v| v.name == "mixed").unwrap();
+    let regions = inspect::regions(vector.patch).unwrap();
+
+    let mut csv = Vec::new();
+    inspect::write_csv(&regions, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), regions.len() + 1);
+    assert_eq!(lines[0], "kind,source_start,source_end,target_start,target_end,changed");
+    assert_eq!(lines[2], "extra,,,10,14,4");
+
+    let mut json = Vec::new();
+    inspect::write_json(&regions, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with('[') && json.trim_end().ends_with(']'));
+    assert_eq!(json.matches("\"kind\"").count(), regions.len());
+    assert!(json.contains("\"kind\": \"extra\", \"source_start\": null"));
+}
+
+#[test]
+fn validate_vectors() {
+    for vector in VECTORS.iter() {
+        eprintln!("validate test on vector `{}`", vector.name);
+        match vector.target {
+            Some(target) => {
+                let summary = inspect::validate(vector.patch, Some(vector.source)).unwrap();
+                assert_eq!(summary.format, vector.format);
+                assert_eq!(summary.target_size, target.len() as u64);
+                assert_eq!(summary.section_sizes[1] + summary.section_sizes[2], target.len() as u64);
+                assert!(summary.source_span <= vector.source.len() as u64);
+                assert!(summary.source_checked);
+            }
+            None => assert!(inspect::validate(vector.patch, Some(vector.source)).is_err()),
+        }
+    }
+}
+
+#[test]
+fn validate_without_source() {
+    let vector = VECTORS.iter().find(|v| v.name == "source-overrun").unwrap();
+    let summary = inspect::validate(vector.patch, None).unwrap();
+    assert!(summary.source_span > vector.source.len() as u64);
+    assert!(!summary.source_checked);
+    let err = inspect::validate(vector.patch, Some(vector.source)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // trailing extra data is left unused
+    let source: Vec<u8> = (0..4096u32).map(|i| (i * 7) as u8).collect();
+    let mut target = source.clone();
+    target[100] ^= 1;
+    let patch = Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .compare_to_vec()
+        .unwrap();
+    inspect::validate(&patch, None).unwrap();
+    let mut padded = patch.clone();
+    padded.push(0);
+    let esize = u64::from_le_bytes(padded[32..40].try_into().unwrap()) + 1;
+    padded[32..40].copy_from_slice(&esize.to_le_bytes());
+    assert!(Bspatch::new(&padded).is_ok());
+    let err = inspect::validate(&padded, None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+/// Source with the bytes outside of the ranges zeroed.
+fn sparse(source: &[u8], ranges: &[std::ops::Range<u64>]) -> Vec<u8> {
+    let mut sparse = vec![0; source.len()];
+    for range in ranges.iter() {
+        let range = range.start as usize..range.end as usize;
+        sparse[range.clone()].copy_from_slice(&source[range]);
+    }
+    sparse
+}
+
+#[test]
+fn source_ranges_cover_reads() {
+    for vector in VECTORS.iter().filter(|v| v.target.is_some()) {
+        let ranges = inspect::source_ranges(vector.patch).unwrap();
+        let mut expected: Vec<_> = inspect::regions(vector.patch)
+            .unwrap()
+            .into_iter()
+            .filter_map(|region| region.source)
+            .collect();
+        expected.sort_by_key(|range| range.start);
+        let covered: u64 = expected.iter().map(|range| range.end - range.start).sum();
+        assert!(ranges.windows(2).all(|w| w[0].end < w[1].start), "{}", vector.name);
+        assert!(ranges.iter().map(|range| range.end - range.start).sum::<u64>() <= covered);
+        for range in expected.iter() {
+            assert!(ranges.iter().any(|r| r.start <= range.start && range.end <= r.end));
+        }
+    }
+
+    let source = hashed_bytes(0, 256 * 1024);
+    let mut target = source[10_000..60_000].to_vec();
+    target.extend_from_slice(&source[200_000..230_000]);
+    target[100] ^= 1;
+    for window in [0, 4096] {
+        let patch = Bsdiff::new(&source, &target)
+            .format(Format::Extended)
+            .source_checksum(window)
+            .compare_to_vec()
+            .unwrap();
+        let ranges = inspect::source_ranges(&patch).unwrap();
+        let needed: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+        assert!(needed < source.len() as u64 / 2);
+        if window > 0 {
+            assert!(ranges.iter().all(|range| range.start % 4096 == 0));
+        }
+
+        let mut t = Vec::new();
+        Bspatch::new(&patch)
+            .unwrap()
+            .apply(&sparse(&source, &ranges), io::Cursor::new(&mut t))
+            .unwrap();
+        assert!(t == target);
+    }
+}
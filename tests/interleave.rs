@@ -0,0 +1,94 @@
+use std::io::{self, Read};
+
+use qbsdiff::interleave::{apply_interleaved, interleave};
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format};
+
+/// Non-seekable reader returning a few bytes per read.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = Ord::min(Ord::min(buf.len(), 7), self.0.len());
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8 ^ (i >> 11) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(1001) {
+        t[i] = t[i].wrapping_add(7);
+    }
+    t.splice(100_000..100_000, (0..20_000u32).map(|i| (i * 7 % 13) as u8));
+    t.drain(200_000..210_000);
+    (s, t)
+}
+
+#[test]
+fn interleave_roundtrip() {
+    let (s, t) = sample();
+    let classic = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let extended = Bsdiff::new(&s[..], &t[..])
+        .format(Format::Extended)
+        .compression_level(0)
+        .source_checksum(4096)
+        .compare_to_vec()
+        .unwrap();
+
+    for patch in [&classic[..], &extended[..]] {
+        for (codec, level) in [(Codec::Stored, 0), (Codec::Bzip2, 9)] {
+            let mut stream = Vec::new();
+            let size = interleave(patch, codec, level, &mut stream).unwrap();
+            assert_eq!(size, stream.len() as u64);
+            assert_eq!(&stream[..8], b"QBSDIFF3");
+
+            let mut t1 = Vec::new();
+            let n = apply_interleaved(&s[..], Trickle(&stream[..]), &mut t1).unwrap();
+            assert_eq!(n, t.len() as u64);
+            assert!(t1 == t);
+        }
+    }
+
+    // empty target
+    let empty = Bsdiff::new(&s[..], &[]).compare_to_vec().unwrap();
+    let mut stream = Vec::new();
+    interleave(&empty[..], Codec::Bzip2, 6, &mut stream).unwrap();
+    let mut t1 = Vec::new();
+    assert_eq!(apply_interleaved(&s[..], &stream[..], &mut t1).unwrap(), 0);
+
+    // the patcher itself still rejects the interleaved layout
+    assert!(Bspatch::new(&stream[..]).is_err());
+}
+
+#[test]
+fn interleave_rejected() {
+    let (s, t) = sample();
+    let copies = Bsdiff::new(&s[..], &t[..])
+        .format(Format::Extended)
+        .target_copy(true)
+        .compare_to_vec()
+        .unwrap();
+    let err = interleave(&copies[..], Codec::Stored, 0, io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+    let patch = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let mut stream = Vec::new();
+    interleave(&patch[..], Codec::Stored, 0, &mut stream).unwrap();
+
+    // truncated and corrupted streams fail without panicking
+    for n in [0, 10, 24, 40, stream.len() / 2, stream.len() - 1] {
+        let err = apply_interleaved(&s[..], &stream[..n], io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "truncated at {}", n);
+    }
+    for i in (24..stream.len()).step_by(997) {
+        let mut corrupt = stream.clone();
+        corrupt[i] ^= 0x80;
+        let _ = apply_interleaved(&s[..], &corrupt[..], io::sink());
+    }
+    let mut flagged = stream.clone();
+    flagged[8] = 1;
+    let err = apply_interleaved(&s[..], &flagged[..], io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
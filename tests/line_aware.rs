@@ -0,0 +1,121 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, DiffProfile};
+use qbsdiff_test_bench_utils::*;
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+fn diff(s: &[u8], t: &[u8], line_aware: bool) -> Vec<u8> {
+    let p = Bsdiff::new(s, t).line_aware(line_aware).compare_to_vec().unwrap();
+    assert!(apply(s, &p) == t);
+    p
+}
+
+/// Log line of given second, with pseudo-random fields.
+fn log(i: u32, salt: u32) -> String {
+    let h = i.wrapping_mul(2654435761) ^ salt.wrapping_mul(0x9e3779b9);
+    format!(
+        "2023-10-01 {:02}:{:02}:{:02}.{:03} {} [worker-{}] request id={:08x} status={}\n",
+        i / 3600 % 24,
+        i / 60 % 60,
+        i % 60,
+        h % 1000,
+        ["INFO", "WARN", "DEBUG", "INFO"][(h >> 7) as usize % 4],
+        h % 8,
+        h,
+        [200, 404, 500][(h >> 3) as usize % 3]
+    )
+}
+
+/// CSV record of given id.
+fn record(id: u32, balance: u32) -> String {
+    format!(
+        "{},user{:05},{}.{:02},2023-{:02}-{:02}\n",
+        id,
+        id,
+        balance,
+        id % 100,
+        id % 12 + 1,
+        id % 28 + 1
+    )
+}
+
+#[test]
+fn line_aware_merged_logs() {
+    // lines interleaved from another log, and some dropped
+    let s: String = (0..20_000).map(|i| log(i, 0)).collect();
+    let t: String = (0..20_000)
+        .flat_map(|i| {
+            let kept = Some(log(i, 0)).filter(|_| i % 13 != 0);
+            let merged = Some(log(i, 1)).filter(|_| i % 4 == 0);
+            kept.into_iter().chain(merged)
+        })
+        .collect();
+
+    let plain = diff(s.as_bytes(), t.as_bytes(), false);
+    let lines = diff(s.as_bytes(), t.as_bytes(), true);
+    assert!(lines.len() * 5 < plain.len() * 4, "{} vs {}", lines.len(), plain.len());
+}
+
+#[test]
+fn line_aware_updated_records() {
+    // deleted, updated and appended records
+    let balance = |id: u32| id.wrapping_mul(2654435761) % 100_000;
+    let s: String = (0..20_000).map(|id| record(id, balance(id))).collect();
+    let t: String = (0..20_000)
+        .filter(|id| id % 20 != 1)
+        .map(|id| match id % 10 {
+            3 => record(id, balance(id) * 37 + 5),
+            5 => record(id, balance(id) + 1),
+            _ => record(id, balance(id)),
+        })
+        .chain((20_000..20_100).map(|id| record(id, 0)))
+        .collect();
+
+    let plain = diff(s.as_bytes(), t.as_bytes(), false);
+    let lines = diff(s.as_bytes(), t.as_bytes(), true);
+    assert!(lines.len() <= plain.len(), "{} vs {}", lines.len(), plain.len());
+}
+
+#[test]
+fn line_aware_degenerate_inputs() {
+    let text: String = (0..1000).map(|i| log(i, 0)).collect();
+    let text = text.as_bytes();
+    let binary = hashed_bytes(0, 100_000);
+    let mut moved = text[20_000..].to_vec();
+    moved.extend_from_slice(&text[..20_000]);
+    let cases: Vec<(&[u8], &[u8])> = vec![
+        (&[], &[]),
+        (text, &[]),
+        (&[], text),
+        (text, text),
+        (text, &text[..text.len() - 1]),
+        (&text[1..], text),
+        (text, &moved),
+        (b"no line break", b"no line break either"),
+        (b"\n\n\n", b"\n\n\n\n"),
+        (b"a\nb\nc\n", b"c\nb\na\nb\n"),
+        (&binary, &binary[1000..]),
+        (text, &binary),
+        (&binary, text),
+    ];
+    for (s, t) in cases {
+        diff(s, t, true);
+    }
+
+    let profile = DiffProfile {
+        line_aware: true,
+        ..DiffProfile::default()
+    };
+    let mut p = Vec::new();
+    Bsdiff::new(text, &moved)
+        .try_profile(&profile)
+        .unwrap()
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    assert!(apply(text, &p) == moved);
+}
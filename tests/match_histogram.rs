@@ -0,0 +1,59 @@
+use qbsdiff::{Bsdiff, MatchHistogram, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 600 * 1024);
+    let mut t = Vec::new();
+    for (k, piece) in s.chunks(4096).enumerate() {
+        t.extend_from_slice(piece);
+        // short copies from elsewhere in the source
+        let start = k * 7919 % (s.len() - 8);
+        t.extend_from_slice(&s[start..start + 3 + k % 6]);
+    }
+    (s, t)
+}
+
+#[test]
+fn match_histogram_lengths() {
+    let (s, t) = sample();
+    let histogram = Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::Never)
+        .match_histogram();
+
+    assert!(histogram.matches() > 0);
+    assert_eq!(
+        histogram.matches(),
+        histogram.iter().map(|(_, count)| count).sum::<u64>()
+    );
+    assert!(histogram.iter().all(|(len, _)| len > 0 && len <= MatchHistogram::LIMIT));
+    assert!(histogram.count(MatchHistogram::LIMIT) > 0);
+    assert!(histogram.delta_bytes() <= t.len() as u64);
+    assert!(histogram.delta_bytes() * 10 > t.len() as u64 * 9);
+
+    let mut last = u64::MAX;
+    for len in 0..=MatchHistogram::LIMIT {
+        let kept = histogram.matches_over(len);
+        assert!(kept <= last);
+        last = kept;
+    }
+    assert_eq!(histogram.matches_over(0), histogram.matches());
+    assert_eq!(histogram.matches_over(MatchHistogram::LIMIT), 0);
+    assert_eq!(histogram.delta_bytes_over(0), histogram.delta_bytes());
+
+    let suggested = histogram.suggest_small_match(0.9);
+    assert!(histogram.delta_bytes_over(suggested) * 10 >= histogram.delta_bytes() * 9);
+    assert!(suggested >= histogram.suggest_small_match(0.99));
+    assert_eq!(histogram.suggest_small_match(0.0), MatchHistogram::LIMIT - 1);
+}
+
+#[test]
+fn match_histogram_parallel() {
+    let (s, t) = sample();
+    let histogram = Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::ChunkSize(1))
+        .match_histogram();
+    assert!(histogram.delta_bytes() * 10 > t.len() as u64 * 9);
+
+    assert_eq!(Bsdiff::new(&[], &t).match_histogram(), MatchHistogram::default());
+    assert_eq!(Bsdiff::new(&s, &[]).match_histogram().matches(), 0);
+}
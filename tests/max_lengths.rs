@@ -0,0 +1,75 @@
+use std::io;
+
+use qbsdiff::inspect::{self, RegionKind};
+use qbsdiff::{Bsdiff, Bspatch, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn samples() -> (Vec<u8>, Vec<u8>) {
+    let source = hashed_bytes(0, 200 * 1024);
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(997) {
+        target[i] ^= 0x5a;
+    }
+    target.splice(50_000..50_000, (0..30_000u32).map(|i| (i * 31 % 251) as u8));
+    target.extend((0..10_000u32).map(|i| (i * 7 % 253) as u8));
+    (source, target)
+}
+
+fn apply(source: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)
+        .unwrap()
+        .apply(source, io::Cursor::new(&mut target))
+        .unwrap();
+    target
+}
+
+#[test]
+fn max_lengths_split_controls() {
+    let (source, target) = samples();
+    let plain = Bsdiff::new(&source, &target).compare_to_vec().unwrap();
+    let plain_summary = inspect::validate(&plain, Some(&source)).unwrap();
+    for format in [Format::Classic, Format::Extended] {
+        for (max_add, max_copy) in [(4096, 0), (0, 1000), (1, 1), (4096, 1000)] {
+            let patch = Bsdiff::new(&source, &target)
+                .format(format)
+                .max_add(max_add)
+                .max_copy(max_copy)
+                .compare_to_vec()
+                .unwrap();
+            assert_eq!(apply(&source, &patch), target);
+
+            let summary = inspect::validate(&patch, Some(&source)).unwrap();
+            assert_eq!(summary.section_sizes[1..], plain_summary.section_sizes[1..]);
+            assert!(summary.controls > plain_summary.controls);
+            for region in inspect::regions(&patch).unwrap() {
+                let len = region.target.end - region.target.start;
+                let max = match region.kind {
+                    RegionKind::Delta => max_add,
+                    RegionKind::Extra => max_copy,
+                    RegionKind::Repeat => 0,
+                };
+                assert!(max == 0 || len <= max as u64, "{:?}", region);
+            }
+        }
+    }
+}
+
+#[test]
+fn max_lengths_unlimited() {
+    let (source, target) = samples();
+    let plain = Bsdiff::new(&source, &target).compare_to_vec().unwrap();
+    let unlimited = Bsdiff::new(&source, &target)
+        .max_add(0)
+        .max_copy(0)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(plain, unlimited);
+
+    let large = Bsdiff::new(&source, &target)
+        .max_add(target.len())
+        .max_copy(target.len())
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(plain, large);
+}
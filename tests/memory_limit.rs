@@ -0,0 +1,86 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use qbsdiff::{Allocation, Bsdiff, Bspatch};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+/// Firmware-like source and target of the same layout, the target grown by
+/// inserted blocks.
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let mut x = 0x9e37_79b9_7f4a_7c15u64;
+    let s: Vec<u8> = (0..1024 * 1024)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        })
+        .collect();
+    let mut t = Vec::new();
+    for (i, block) in s.chunks(64 * 1024).enumerate() {
+        t.extend_from_slice(block);
+        t.extend_from_slice(&[i as u8; 1000]);
+    }
+    for i in (0..t.len()).step_by(4099) {
+        t[i] = t[i].wrapping_add(1);
+    }
+    (s, t)
+}
+
+#[test]
+fn memory_limit_windowed() {
+    let (s, t) = sample();
+    let limit = 1024 * 1024;
+    let largest = Arc::new(AtomicUsize::new(0));
+    let hook = largest.clone();
+    let p = Bsdiff::new(&s[..], &t[..])
+        .memory_limit(Some(limit))
+        .allocation_hook(move |alloc| {
+            if let Allocation::SuffixArray(size) = alloc {
+                hook.fetch_max(size, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(apply(&s[..], &p[..]), t);
+    assert!(largest.load(Ordering::Relaxed) <= limit);
+
+    // matches within the proportional windows are kept
+    let unlimited = Bsdiff::new(&s[..], &t[..]).memory_limit(None).compare_to_vec().unwrap();
+    assert!(p.len() < unlimited.len() * 2, "{} vs {}", p.len(), unlimited.len());
+    assert!(p.len() < t.len() / 4);
+
+    // tiny limits are raised to the min window
+    let p = Bsdiff::new(&s[..], &t[..])
+        .memory_limit(Some(0))
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(apply(&s[..], &p[..]), t);
+}
+
+#[test]
+fn memory_limit_shrunk_target() {
+    let (s, _) = sample();
+    let t: Vec<u8> = s.chunks(4096).step_by(2).flatten().copied().collect();
+    let p = Bsdiff::new(&s[..], &t[..])
+        .memory_limit(Some(512 * 1024))
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(apply(&s[..], &p[..]), t);
+
+    // sources fitting in the limit are indexed as a whole
+    let small = &s[..100_000];
+    let p = Bsdiff::new(small, &t[..])
+        .memory_limit(Some(1 << 20))
+        .compare_to_vec()
+        .unwrap();
+    let q = Bsdiff::new(small, &t[..]).memory_limit(None).compare_to_vec().unwrap();
+    assert!(p == q);
+}
@@ -0,0 +1,65 @@
+use std::io;
+
+use qbsdiff::{transcode, Bsdiff, Bspatch, Codec, Format, ParallelScheme, PartialPatch};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 300 * 1000);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(7001) {
+        t[i] ^= 0x42;
+    }
+    (s, t)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn metadata_recorded() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .compression_level(0)
+        .small_match(16)
+        .parallel_scheme(ParallelScheme::ChunkSize(256 * 1024))
+        .source_checksum(4096)
+        .metadata(true)
+        .compare_to_vec()
+        .unwrap();
+    assert!(apply(&s, &p) == t);
+
+    let meta = Bspatch::new(&p).unwrap().metadata().unwrap();
+    assert_eq!(meta.version(), Some(env!("CARGO_PKG_VERSION")));
+    assert_eq!(meta.get("codecs"), Some("Stored:0,Stored:0,Stored:0"));
+    assert_eq!(meta.get("small_match"), Some("16"));
+    assert_eq!(meta.get("parallel_scheme"), Some("ChunkSize(262144)"));
+    assert_eq!(meta.get("chunk_size"), Some("262144"));
+    assert_eq!(meta.get("source_checksum"), Some("4096"));
+    assert_eq!(meta.get("unknown"), None);
+    assert!(meta.entries().count() >= 10);
+
+    // kept by transcoding, and sized by partial patches
+    let mut p1 = Vec::new();
+    transcode(&p[..], &mut p1, Format::Extended, Format::Extended).unwrap();
+    assert_eq!(Bspatch::new(&p1).unwrap().metadata(), Some(meta));
+    for n in 48..p.len() {
+        let partial = PartialPatch::check(&p[..n]).unwrap();
+        assert!(partial.min_total_size() <= p.len() as u64);
+        if partial.is_header_complete() {
+            assert_eq!(partial.total_size(), Some(p.len() as u64));
+        }
+    }
+
+    // absent unless enabled
+    let plain = Bsdiff::new(&s, &t).format(Format::Extended).compare_to_vec().unwrap();
+    assert!(Bspatch::new(&plain).unwrap().metadata().is_none());
+    let classic = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    assert!(Bspatch::new(&classic).unwrap().metadata().is_none());
+    let err = Bsdiff::new(&s, &t).metadata(true).compare(io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
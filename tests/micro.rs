@@ -0,0 +1,172 @@
+use std::io::{self, ErrorKind, Read};
+
+use qbsdiff::format::{Feature, QBSMICRO_MAGIC};
+use qbsdiff::{
+    inspect, transcode, Bsdiff, Bspatch, Capabilities, Codec, DiffProfile, Format, PartialPatch, PatchedReader,
+};
+use qbsdiff_test_bench_utils::*;
+
+fn samples() -> Vec<(Vec<u8>, Vec<u8>)> {
+    let config = b"name=qbsdiff\nversion=1.4.2\nthreads=4\nlevel=6\nenabled=true\n".to_vec();
+    let mut changed = config.clone();
+    changed[21] = b'5';
+    changed.extend_from_slice(b"window=64\n");
+    let blob = hashed_bytes(0, 3000);
+    let mut edited = blob.clone();
+    edited[1000..1010].fill(0);
+    edited.truncate(2900);
+    vec![
+        (config.clone(), changed),
+        (blob.clone(), edited),
+        (Vec::new(), b"new file".to_vec()),
+        (blob.clone(), Vec::new()),
+        (config.clone(), config),
+    ]
+}
+
+fn micro(s: &[u8], t: &[u8]) -> Vec<u8> {
+    Bsdiff::new(s, t)
+        .format(Format::Extended)
+        .micro(4096)
+        .compare_to_vec()
+        .unwrap()
+}
+
+fn apply(s: &[u8], p: &[u8]) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?.apply(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn micro_roundtrip() {
+    for (s, t) in samples() {
+        let p = micro(&s, &t);
+        assert_eq!(&p[..4], QBSMICRO_MAGIC);
+        assert_eq!(apply(&s, &p).unwrap(), t);
+
+        let extended = Bsdiff::new(&s, &t)
+            .format(Format::Extended)
+            .codec(Codec::Stored)
+            .compare_to_vec()
+            .unwrap();
+        let classic = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+        assert!(p.len() < extended.len() && p.len() < classic.len());
+        assert!(p.len() <= t.len() + 16, "{} bytes of {}", p.len(), t.len());
+
+        let summary = inspect::validate(&p, Some(&s)).unwrap();
+        assert_eq!(summary.format, Format::Extended);
+        assert_eq!(summary.target_size, t.len() as u64);
+
+        let mut reader = PatchedReader::new(&s, &p).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, t);
+
+        let mut range = Vec::new();
+        let end = t.len() as u64;
+        Bspatch::new(&p)
+            .unwrap()
+            .apply_range(&s, end / 3..end, io::Cursor::new(&mut range))
+            .unwrap();
+        assert_eq!(range, t[t.len() / 3..]);
+
+        for to in [Format::Classic, Format::Extended] {
+            let mut q = Vec::new();
+            transcode(&p[..], &mut q, Format::Extended, to).unwrap();
+            assert_eq!(apply(&s, &q).unwrap(), t);
+        }
+    }
+}
+
+#[test]
+fn micro_selection() {
+    let (s, t) = samples().swap_remove(1);
+    let regular = |p: &[u8]| assert_eq!(&p[..8], b"QBSDIFF2");
+
+    // Too large, or not allowed by the format and the features.
+    regular(
+        &Bsdiff::new(&s, &t)
+            .format(Format::Extended)
+            .micro(1024)
+            .compare_to_vec()
+            .unwrap(),
+    );
+    let classic = Bsdiff::new(&s, &t).micro(4096).compare_to_vec().unwrap();
+    assert_eq!(&classic[..8], b"BSDIFF40");
+    regular(
+        &Bsdiff::new(&s, &t)
+            .format(Format::Extended)
+            .micro(4096)
+            .metadata(true)
+            .compare_to_vec()
+            .unwrap(),
+    );
+
+    // Compact seeks are implied.
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .compact_seek(true)
+        .micro(4096)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(&p[..4], QBSMICRO_MAGIC);
+    assert_eq!(apply(&s, &p).unwrap(), t);
+
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &[])
+        .format(Format::Extended)
+        .micro(usize::MAX)
+        .compare_reader(&t[..], 1 << 20, &mut p)
+        .unwrap();
+    regular(&p);
+}
+
+#[test]
+fn micro_partial() {
+    let (s, t) = samples().swap_remove(1);
+    let p = micro(&s, &t);
+    let header_size = PartialPatch::check(&p).unwrap().header_size();
+    assert!((9..=16).contains(&header_size));
+    for n in 1..p.len() {
+        let partial = PartialPatch::check(&p[..n]).unwrap();
+        assert_eq!(partial.format(), Some(Format::Extended));
+        assert!(partial.min_total_size() <= p.len() as u64);
+        assert!(partial.header_size() <= header_size);
+        assert_eq!(partial.is_header_complete(), n >= header_size);
+        assert_eq!(partial.total_size().is_some(), n >= header_size);
+        assert!(!partial.is_complete());
+    }
+    assert!(PartialPatch::check(&p).unwrap().is_complete());
+
+    let capabilities = Capabilities::current();
+    assert!(capabilities.accepts(&p).unwrap());
+    assert!(!capabilities.without(Feature::Micro).accepts(&p).unwrap());
+    let profile = capabilities.without(Feature::Micro).negotiate(&DiffProfile {
+        format: Format::Extended,
+        micro: 4096,
+        ..Default::default()
+    });
+    assert_eq!(profile.unwrap().micro, 0);
+}
+
+#[test]
+fn micro_corrupted() {
+    let (s, t) = samples().swap_remove(0);
+    let p = micro(&s, &t);
+    assert_eq!(
+        Bspatch::new(&p[..p.len() - 1]).err().unwrap().kind(),
+        ErrorKind::InvalidData
+    );
+
+    // Unknown codec, reserved bits, non-minimal varints.
+    for (i, b) in [(4, 0x3), (4, 0x40)] {
+        let mut q = p.clone();
+        q[i] = b;
+        assert!(Bspatch::new(&q).is_err());
+    }
+    let mut q = p[..5].to_vec();
+    q.extend_from_slice(&[0x80 | p[5], 0]);
+    q.extend_from_slice(&p[6..]);
+    assert_eq!(Bspatch::new(&q).err().unwrap().kind(), ErrorKind::InvalidData);
+}
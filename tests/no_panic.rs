@@ -0,0 +1,250 @@
+use std::io::{self, Read};
+use std::panic;
+
+use qbsdiff::archive::{Archive, ArchiveWriter};
+use qbsdiff::bundle::{Bundle, BundleWriter};
+#[cfg(feature = "cdc")]
+use qbsdiff::cdc::{ChunkerConfig, Manifest};
+use qbsdiff::golden::Baseline;
+use qbsdiff::{
+    inspect, transcode, Bsdiff, Bspatch, Capabilities, Codec, Format, ParallelScheme, PartialPatch, PatchedReader,
+    Sidecar, SourceIndex,
+};
+use qbsdiff_test_bench_utils::*;
+
+/// Fixed pseudo-random generator.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 20_000);
+    let mut t = s.clone();
+    t[100..200].fill(0);
+    t.extend_from_slice(&s[..300]);
+    t.splice(5000..5000, b"inserted".iter().copied());
+    (s, t)
+}
+
+fn patches(s: &[u8], t: &[u8]) -> Vec<Vec<u8>> {
+    let bsdiffs = vec![
+        Bsdiff::new(s, t),
+        Bsdiff::new(s, t).format(Format::Extended).codec(Codec::Stored),
+        Bsdiff::new(s, t)
+            .format(Format::Extended)
+            .target_copy(true)
+            .dedupe(true)
+            .source_checksum(1024)
+            .metadata(true),
+    ];
+    bsdiffs.into_iter().map(|b| b.compare_to_vec().unwrap()).collect()
+}
+
+/// Call every patch consuming API, ignoring errors.
+fn consume(s: &[u8], p: &[u8]) {
+    let sink = || io::Cursor::new(Vec::new());
+    if let Ok(b) = Bspatch::new(p) {
+        let _ = b.metadata();
+        let _ = b.apply(s, sink());
+    }
+    if let Ok(b) = Bspatch::new(p) {
+        let _ = b.apply(&s[..s.len() / 2], sink());
+    }
+    if let Ok(b) = Bspatch::new(p) {
+        let _ = b.apply_range(s, 10..1000, sink());
+    }
+    if let Ok(b) = Bspatch::new(p) {
+        let _ = b.is_identity();
+    }
+    if let Ok(mut r) = PatchedReader::new(s, p) {
+        let mut buf = Vec::new();
+        let _ = r.by_ref().take(1 << 20).read_to_end(&mut buf);
+    }
+    let _ = inspect::regions(p);
+    let _ = PartialPatch::check(p);
+    for to in [Format::Classic, Format::Extended] {
+        let _ = transcode(p, sink(), Format::Classic, to);
+        let _ = transcode(p, sink(), Format::Extended, to);
+    }
+}
+
+fn assert_no_panic(s: &[u8], p: &[u8], what: &str) {
+    if panic::catch_unwind(|| consume(s, p)).is_err() {
+        panic!("panicked on {}: {:02x?}", what, p);
+    }
+}
+
+/// Feed truncated and randomly corrupted copies of the data.
+fn mutate<F: Fn(&[u8], &str)>(data: &[u8], rng: &mut XorShift, corruptions: usize, check: F) {
+    // truncated
+    for n in (0..data.len()).step_by(7) {
+        check(&data[..n], "truncated");
+    }
+
+    // corrupted header fields and bodies
+    for _ in 0..corruptions {
+        let mut q = data.to_vec();
+        for _ in 0..1 + rng.next() % 4 {
+            let i = if rng.next().is_multiple_of(2) {
+                rng.next() as usize % Ord::min(q.len(), 64)
+            } else {
+                rng.next() as usize % q.len()
+            };
+            q[i] = match rng.next() % 4 {
+                0 => 0,
+                1 => 0xff,
+                2 => 0x80,
+                _ => rng.next() as u8,
+            };
+        }
+        check(&q, "corrupted");
+    }
+}
+
+#[test]
+fn no_panic_malformed_patches() {
+    let (s, t) = sample();
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    for p in patches(&s, &t) {
+        mutate(&p, &mut rng, 2000, |q, what| assert_no_panic(&s, q, what));
+    }
+
+    // garbage behind valid magics
+    for magic in [&b"BSDIFF40"[..], b"QBSDIFF2"] {
+        for _ in 0..500 {
+            let mut q = magic.to_vec();
+            q.extend((0..rng.next() % 200).map(|_| rng.next() as u8));
+            assert_no_panic(&s, &q, "garbage patch");
+        }
+    }
+}
+
+/// Encoded archive, bundle, sidecar, source index, baseline, chunk manifest
+/// and capabilities.
+fn containers(s: &[u8], t: &[u8]) -> Vec<Vec<u8>> {
+    let p = Bsdiff::new(s, t).compare_to_vec().unwrap();
+    let mut archive = ArchiveWriter::new(Vec::new()).unwrap();
+    archive.add("v1", &p).unwrap();
+    archive.add("v2", &Bsdiff::new(t, s).compare_to_vec().unwrap()).unwrap();
+
+    let mut bundle = BundleWriter::new();
+    bundle.add("a/t", p.clone()).unwrap();
+    bundle.add("b/t", p.clone()).unwrap();
+    let mut bundled = Vec::new();
+    bundle.write_to(&mut bundled).unwrap();
+
+    let mut sidecar = Vec::new();
+    Bsdiff::new(s, t)
+        .compare_sidecar()
+        .unwrap()
+        .write(&mut sidecar)
+        .unwrap();
+
+    let mut index = Vec::new();
+    SourceIndex::new(s).serialize(&mut index).unwrap();
+
+    let mut baseline = Baseline::new();
+    baseline.record("sample", p.len() as u64).unwrap();
+    baseline.record("other-sample", 12345).unwrap();
+
+    let mut containers = vec![
+        archive.finish().unwrap(),
+        bundled,
+        sidecar,
+        index,
+        baseline.to_string().into_bytes(),
+        Capabilities::current().encode(),
+    ];
+    containers.extend(manifest(t));
+    containers
+}
+
+#[cfg(feature = "cdc")]
+fn manifest(t: &[u8]) -> Option<Vec<u8>> {
+    let mut manifest = Vec::new();
+    Manifest::build(t, ChunkerConfig::default())
+        .write_to(&mut manifest)
+        .unwrap();
+    Some(manifest)
+}
+
+#[cfg(not(feature = "cdc"))]
+fn manifest(_t: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Call every container parsing API and use the parsed containers, ignoring
+/// errors.
+fn parse_containers(s: &[u8], t: &[u8], data: &[u8]) {
+    if let Ok(archive) = Archive::parse(data) {
+        for entry in archive.entries() {
+            let _ = archive.apply(entry.id(), s, io::sink());
+        }
+    }
+    if let Ok(bundle) = Bundle::parse(data) {
+        for entry in bundle.entries() {
+            let _ = entry.patch();
+        }
+    }
+    if let Ok(sidecar) = Sidecar::read(data) {
+        let _ = sidecar.check(s, t);
+        let _ = Bsdiff::new(s, t).pack_sidecar(&sidecar, io::sink());
+    }
+    if let Ok(index) = SourceIndex::from_bytes(s, data) {
+        let _ = Bsdiff::with_index(&index, t).compare(io::sink());
+    }
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Baseline::parse(text);
+    }
+    #[cfg(feature = "cdc")]
+    let _ = Manifest::parse(data);
+    let _ = Capabilities::decode(data);
+}
+
+#[test]
+fn no_panic_malformed_containers() {
+    let (s, t) = sample();
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    for data in containers(&s, &t) {
+        mutate(&data, &mut rng, 500, |q, what| {
+            if panic::catch_unwind(|| parse_containers(&s, &t, q)).is_err() {
+                panic!("panicked on {} container: {:02x?}", what, q);
+            }
+        });
+    }
+}
+
+#[test]
+fn no_panic_adversarial_settings() {
+    let (s, t) = sample();
+    let schemes = [
+        ParallelScheme::ChunkSize(0),
+        ParallelScheme::NumJobs(0),
+        ParallelScheme::NumJobs(usize::MAX),
+        ParallelScheme::ChunkSize(usize::MAX),
+    ];
+    for scheme in schemes {
+        let p = Bsdiff::new(&s, &t)
+            .parallel_scheme(scheme)
+            .small_match(usize::MAX)
+            .buffer_size(0)
+            .work_limit(0)
+            .compare_to_vec()
+            .unwrap();
+        let mut t1 = Vec::new();
+        Bspatch::new(&p).unwrap().apply(&s, io::Cursor::new(&mut t1)).unwrap();
+        assert!(t1 == t);
+        let _ = Bsdiff::new(&s, &t).parallel_scheme(scheme).match_histogram();
+        let _ = Bsdiff::new(&s, &t).parallel_scheme(scheme).search_chunks();
+    }
+
+    let err = Bsdiff::new(&s, &t).compression_level(100).compare(io::sink());
+    assert!(err.is_ok());
+}
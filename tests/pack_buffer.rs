@@ -0,0 +1,26 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch};
+
+#[test]
+fn pack_buffer_size_independent() {
+    // highly similar data, i.e. long add controls spanning many buffers
+    let s: Vec<u8> = (0..1024 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(997) {
+        t[i] = t[i].wrapping_add(i as u8);
+    }
+
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    for size in [128, 1000, 65536] {
+        let q = Bsdiff::new(&s[..], &t[..]).buffer_size(size).compare_to_vec().unwrap();
+        assert!(p == q);
+    }
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&p[..])
+        .unwrap()
+        .apply(&s[..], io::Cursor::new(&mut t1))
+        .unwrap();
+    assert!(t1 == t);
+}
@@ -0,0 +1,72 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 400 * 1000);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(3001) {
+        t[i] ^= 0x5a;
+    }
+    t.splice(200_000..200_000, s[1000..9000].iter().copied());
+    (s, t)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn parallel_streaming_matches_chunks() {
+    let (s, t) = sample();
+    let bsdiff = Bsdiff::new(&s, &t).parallel_scheme(ParallelScheme::ChunkSize(16 * 1024));
+    let p = bsdiff.compare_to_vec().unwrap();
+    assert!(apply(&s, &p) == t);
+
+    // packing the concatenated chunks is what streaming does
+    let ctrls = bsdiff.search_chunks().into_iter().flat_map(|chunk| chunk.controls);
+    let mut q = Vec::new();
+    bsdiff.compare_controls(ctrls, io::Cursor::new(&mut q)).unwrap();
+    assert!(p == q);
+}
+
+#[test]
+#[cfg(feature = "parallel")]
+fn parallel_streaming_deterministic() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::ChunkSize(16 * 1024))
+        .compare_to_vec()
+        .unwrap();
+
+    // a single pool thread is busy consuming, chunks are searched in place
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let q = pool.install(|| {
+        Bsdiff::new(&s, &t)
+            .parallel_scheme(ParallelScheme::ChunkSize(16 * 1024))
+            .compare_to_vec()
+            .unwrap()
+    });
+    assert!(p == q);
+
+    let mut r = Vec::new();
+    Bsdiff::new(&s, &t)
+        .parallel_scheme(ParallelScheme::ChunkSize(16 * 1024))
+        .compare_reader(&t[..], 100 * 1024, io::Cursor::new(&mut r))
+        .unwrap();
+    assert!(apply(&s, &r) == t);
+}
+
+#[test]
+#[cfg(not(feature = "parallel"))]
+fn parallel_streaming_disabled() {
+    let (s, t) = sample();
+    let bsdiff = Bsdiff::new(&s, &t);
+    assert_eq!(bsdiff.effective_parallel_scheme(), ParallelScheme::Never);
+    let bsdiff = bsdiff.parallel_scheme(ParallelScheme::NumJobs(4));
+    assert_eq!(bsdiff.effective_parallel_scheme(), ParallelScheme::Never);
+    assert!(apply(&s, &bsdiff.compare_to_vec().unwrap()) == t);
+}
@@ -0,0 +1,65 @@
+use std::path;
+
+use qbsdiff::{Codec, Format, PartialPatch};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn partial_patch_prefixes() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let s: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut t = s.clone();
+    t[1000..1100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+
+    let classic = testing.qbsdiff(&s[..], &t[..]).unwrap();
+    let opts = QbsdiffOptions {
+        format: Format::Extended,
+        codec: Codec::Stored,
+        ..QbsdiffOptions::default()
+    };
+    let extended = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+
+    for (p, format, header_size) in [(classic, Format::Classic, 32), (extended, Format::Extended, 48)] {
+        let empty = PartialPatch::check(&[]).unwrap();
+        assert_eq!(empty.format(), None);
+        assert_eq!(empty.missing(), Some(0..u64::MAX));
+
+        for n in (1..p.len())
+            .step_by(7)
+            .chain([header_size - 1, header_size, p.len() - 1])
+        {
+            let partial = PartialPatch::check(&p[..n]).unwrap();
+            assert_eq!(partial.format(), Some(format));
+            assert!(!partial.is_complete());
+            assert!(partial.min_total_size() <= p.len() as u64);
+            assert_eq!(partial.is_header_complete(), n >= header_size);
+            if n >= header_size {
+                assert_eq!(partial.target_size(), Some(t.len() as u64));
+            }
+            if let Some(total) = partial.total_size() {
+                assert_eq!(total, p.len() as u64);
+                assert_eq!(partial.missing(), Some(n as u64..total));
+            } else {
+                assert!(format == Format::Classic || n < header_size);
+                assert_eq!(partial.missing(), Some(n as u64..u64::MAX));
+            }
+        }
+        if format == Format::Extended {
+            let partial = PartialPatch::check(&p[..header_size]).unwrap();
+            assert_eq!(partial.total_size(), Some(p.len() as u64));
+        }
+
+        let mut padded = p.clone();
+        padded.extend_from_slice(&[0; 100]);
+        for whole in [&p[..], &padded[..]] {
+            let partial = PartialPatch::check(whole).unwrap();
+            assert!(partial.is_complete());
+            assert_eq!(partial.total_size(), Some(p.len() as u64));
+            assert_eq!(partial.missing(), None);
+        }
+    }
+
+    assert!(PartialPatch::check(b"BSDIFX").is_err());
+    assert!(PartialPatch::check(b"X").is_err());
+}
@@ -0,0 +1,68 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path;
+
+use qbsdiff::{Codec, Format, PatchedReader};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_patched_reader() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("patched reader test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        let classic = testing.qbsdiff(&s[..], &t[..]).unwrap();
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec: Codec::Stored,
+            ..QbsdiffOptions::default()
+        };
+        let stored = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+
+        for p in [classic, stored] {
+            let mut reader = PatchedReader::new(&s[..], &p[..]).unwrap();
+            assert_eq!(reader.len(), t.len() as u64);
+
+            let mut t1 = Vec::new();
+            reader.read_to_end(&mut t1).unwrap();
+            if t != t1 {
+                panic!("patched reader failed: `{}`", sample.name);
+            }
+
+            // Read backwards in windows.
+            let mut offset = t.len();
+            while offset > 0 {
+                let start = offset.saturating_sub(t.len() / 7 + 1);
+                let mut buf = vec![0; offset - start];
+                reader.seek(SeekFrom::Start(start as u64)).unwrap();
+                reader.read_exact(&mut buf[..]).unwrap();
+                if buf[..] != t[start..offset] {
+                    panic!("patched reader failed: `{}` at {}", sample.name, start);
+                }
+                offset = start;
+            }
+        }
+    }
+}
+
+#[test]
+fn patched_reader_seek_bounds() {
+    let testing = Testing::new(std::env::temp_dir());
+    let s = b"the quick brown fox jumps over the lazy dog";
+    let t = b"the quick brown cat jumps over the lazy dog!";
+    let p = testing.qbsdiff(&s[..], &t[..]).unwrap();
+
+    let mut reader = PatchedReader::new(&s[..], &p[..]).unwrap();
+    assert!(reader.seek(SeekFrom::Current(-1)).is_err());
+    assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap(), t.len() as u64 - 4);
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..], b"dog!");
+
+    reader.seek(SeekFrom::End(10)).unwrap();
+    assert_eq!(reader.read(&mut buf[..]).unwrap(), 0);
+}
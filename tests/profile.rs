@@ -0,0 +1,234 @@
+use std::io;
+
+use qbsdiff::{tuning_info, Bsdiff, Bspatch, ChecksumKind, Codec, DiffProfile, Format, ParallelScheme, PatchProfile};
+
+#[test]
+fn profile_equals_builders() {
+    let source: Vec<u8> = (0..300 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    target[100..200].fill(7);
+
+    let profile = DiffProfile {
+        parallel_scheme: ParallelScheme::Never,
+        small_match: 16,
+        work_limit: Some(32),
+        buffer_size: 1024,
+        format: Format::Extended,
+        codec: Codec::Stored,
+        compression_level: 0,
+        section_codecs: [None; 3],
+        auto_levels: false,
+        auto_codec: None,
+        target_copy: false,
+        compact_seek: true,
+        decoded_sizes: true,
+        explicit_sizes: false,
+        block_size: 4096,
+        max_add: 1 << 16,
+        max_copy: 1000,
+        micro: 0,
+        dedupe: true,
+        line_aware: false,
+        append_mostly: false,
+        metadata: false,
+        source_checksum: 4096,
+        checksum: Some(ChecksumKind::Crc32c),
+    };
+    profile.validate().unwrap();
+
+    let mut p1 = Vec::new();
+    Bsdiff::new(&source, &target)
+        .profile(&profile)
+        .compare(io::Cursor::new(&mut p1))
+        .unwrap();
+    let mut p2 = Vec::new();
+    Bsdiff::new(&source, &target)
+        .parallel_scheme(ParallelScheme::Never)
+        .small_match(16)
+        .work_limit(32)
+        .buffer_size(1024)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .compression_level(0)
+        .compact_seek(true)
+        .decoded_sizes(true)
+        .block_size(4096)
+        .max_add(1 << 16)
+        .max_copy(1000)
+        .dedupe(true)
+        .source_checksum(4096)
+        .checksum(Some(ChecksumKind::Crc32c))
+        .compare(io::Cursor::new(&mut p2))
+        .unwrap();
+    assert_eq!(p1, p2);
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&p1)
+        .unwrap()
+        .profile(&PatchProfile::default())
+        .apply(&source, io::Cursor::new(&mut t1))
+        .unwrap();
+    assert_eq!(t1, target);
+}
+
+#[test]
+fn profile_validation() {
+    DiffProfile::default().validate().unwrap();
+    PatchProfile::default().validate().unwrap();
+
+    let invalid = [
+        DiffProfile {
+            parallel_scheme: ParallelScheme::NumJobs(0),
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            work_limit: Some(0),
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            buffer_size: 64,
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            codec: Codec::Stored,
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            compression_level: 0,
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            compression_level: 10,
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            checksum: Some(ChecksumKind::Crc32),
+            ..DiffProfile::default()
+        },
+        DiffProfile {
+            block_size: 4096,
+            ..DiffProfile::default()
+        },
+    ];
+    for profile in invalid.iter() {
+        assert!(profile.validate().is_err(), "{:?}", profile);
+    }
+    let patch = PatchProfile {
+        delta_min: 0,
+        ..PatchProfile::default()
+    };
+    assert!(patch.validate().is_err());
+}
+
+#[test]
+fn profile_tuning_info() {
+    let info = tuning_info();
+    let diff = DiffProfile::default();
+    assert_eq!(diff.small_match, info.small_match);
+    assert_eq!(diff.buffer_size, info.diff_buffer_size);
+    assert_eq!(diff.compression_level, info.compression_level);
+    let patch = PatchProfile::default();
+    assert_eq!(patch.buffer_size, info.patch_buffer_size);
+    assert_eq!(patch.delta_min, info.delta_min);
+    assert!(info.threads >= 1 && info.min_chunk > 0 && info.mismatch_count > 0);
+
+    let bsdiff = Bsdiff::new(b"source", b"target");
+    assert_eq!(bsdiff.effective_buffer_size(), info.diff_buffer_size);
+    assert_eq!(
+        Bsdiff::new(b"source", b"target")
+            .parallel_scheme(ParallelScheme::ChunkSize(1))
+            .effective_parallel_scheme(),
+        if cfg!(feature = "parallel") {
+            ParallelScheme::ChunkSize(info.min_chunk)
+        } else {
+            ParallelScheme::Never
+        }
+    );
+}
+
+#[test]
+fn strict_builders() {
+    let (s, t) = (vec![1u8; 1024], vec![2u8; 1024]);
+
+    let bsdiff = Bsdiff::new(&s, &t)
+        .buffer_size(64)
+        .work_limit(0)
+        .parallel_scheme(ParallelScheme::ChunkSize(1024));
+    assert_eq!(bsdiff.effective_buffer_size(), 128);
+    assert_eq!(bsdiff.effective_work_limit(), Some(1));
+    assert_eq!(
+        bsdiff.effective_parallel_scheme(),
+        ParallelScheme::ChunkSize(256 * 1024)
+    );
+    let bsdiff = bsdiff.parallel_scheme(ParallelScheme::NumJobs(0));
+    assert_eq!(bsdiff.effective_parallel_scheme(), ParallelScheme::Auto);
+
+    let err = |r: io::Result<Bsdiff>| r.err().map(|e| e.kind());
+    let invalid = Some(io::ErrorKind::InvalidInput);
+    assert_eq!(err(Bsdiff::new(&s, &t).try_buffer_size(64)), invalid);
+    assert_eq!(err(Bsdiff::new(&s, &t).try_work_limit(0)), invalid);
+    assert_eq!(
+        err(Bsdiff::new(&s, &t).try_parallel_scheme(ParallelScheme::ChunkSize(0))),
+        invalid
+    );
+    assert_eq!(
+        err(Bsdiff::new(&s, &t).try_parallel_scheme(ParallelScheme::NumJobs(0))),
+        invalid
+    );
+    let bad = DiffProfile {
+        buffer_size: 64,
+        ..DiffProfile::default()
+    };
+    assert_eq!(err(Bsdiff::new(&s, &t).try_profile(&bad)), invalid);
+
+    let bsdiff = Bsdiff::new(&s, &t)
+        .try_buffer_size(1024)
+        .and_then(|b| b.try_work_limit(8))
+        .and_then(|b| b.try_parallel_scheme(ParallelScheme::NumJobs(2)))
+        .unwrap();
+    assert_eq!(bsdiff.effective_buffer_size(), 1024);
+    assert_eq!(bsdiff.effective_work_limit(), Some(8));
+    assert_eq!(bsdiff.effective_parallel_scheme(), ParallelScheme::NumJobs(2));
+    let mut p = Vec::new();
+    bsdiff.compare(io::Cursor::new(&mut p)).unwrap();
+
+    let bspatch = Bspatch::new(&p).unwrap().buffer_size(256).delta_min(0);
+    assert_eq!(bspatch.effective_buffer_size(), 256);
+    assert_eq!(bspatch.effective_delta_min(), 128);
+    let bspatch = bspatch.delta_min(4096);
+    assert_eq!(bspatch.effective_delta_min(), 256);
+
+    let err = |r: io::Result<Bspatch>| r.err().map(|e| e.kind());
+    assert_eq!(err(Bspatch::new(&p).unwrap().try_buffer_size(64)), invalid);
+    assert_eq!(err(Bspatch::new(&p).unwrap().try_delta_min(0)), invalid);
+    let bad = PatchProfile {
+        delta_min: 0,
+        ..PatchProfile::default()
+    };
+    assert_eq!(err(Bspatch::new(&p).unwrap().try_profile(&bad)), invalid);
+
+    let mut t1 = Vec::new();
+    Bspatch::new(&p)
+        .unwrap()
+        .try_buffer_size(4096)
+        .and_then(|b| b.try_delta_min(1024))
+        .unwrap()
+        .apply(&s, io::Cursor::new(&mut t1))
+        .unwrap();
+    assert_eq!(t1, t);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn profile_serde() {
+    let profile: DiffProfile =
+        serde_json::from_str(r#"{"parallel_scheme": {"ChunkSize": 1048576}, "format": "Extended", "codec": "Stored"}"#)
+            .unwrap();
+    assert_eq!(profile.parallel_scheme, ParallelScheme::ChunkSize(1 << 20));
+    assert_eq!(profile.small_match, DiffProfile::default().small_match);
+
+    let json = serde_json::to_string(&profile).unwrap();
+    assert_eq!(serde_json::from_str::<DiffProfile>(&json).unwrap(), profile);
+}
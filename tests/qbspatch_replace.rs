@@ -0,0 +1,38 @@
+#![cfg(all(feature = "cmd", unix))]
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use std::{env, fs};
+
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn qbspatch_replaces_target_keeping_mode() {
+    let root = env::temp_dir().join(format!("qbspatch-replace-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let (s, t, p) = (root.join("s"), root.join("t"), root.join("p"));
+    let source = hashed_bytes(0, 300 * 1024);
+    let mut target = source.clone();
+    target[1000..2000].fill(3);
+    fs::write(&s, &source).unwrap();
+    fs::write(&t, &target).unwrap();
+
+    let qbsdiff = env!("CARGO_BIN_EXE_qbsdiff");
+    let qbspatch = env!("CARGO_BIN_EXE_qbspatch");
+    assert!(Command::new(qbsdiff).args([&s, &t, &p]).status().unwrap().success());
+
+    // an executable target replaced in place
+    let exe = root.join("exe");
+    fs::copy(&s, &exe).unwrap();
+    fs::set_permissions(&exe, fs::Permissions::from_mode(0o751)).unwrap();
+    assert!(Command::new(qbspatch)
+        .args([&exe, &exe, &p])
+        .status()
+        .unwrap()
+        .success());
+    assert_eq!(fs::read(&exe).unwrap(), target);
+    assert_eq!(fs::metadata(&exe).unwrap().permissions().mode() & 0o777, 0o751);
+    let names: Vec<_> = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names.len(), 4, "temporary files left: {:?}", names);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "gzip")]
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::{Compression, GzBuilder};
+use qbsdiff::recompress::{self, GzipRecipe};
+use qbsdiff::{Bsdiff, DiffProfile};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..200 * 1000u32)
+        .flat_map(|i| format!("line {} of {}\n", i % 997, i / 13).into_bytes())
+        .take(200 * 1000)
+        .collect();
+    let mut t = s.clone();
+    t[5000..5100].fill(b'x');
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn roundtrip(s: &[u8], t: &[u8]) -> Vec<u8> {
+    let mut p = Vec::new();
+    let size = recompress::compare(s, t, &DiffProfile::default(), io::Cursor::new(&mut p)).unwrap();
+    assert_eq!(size, p.len() as u64);
+    let mut t1 = Vec::new();
+    let tsize = recompress::apply(s, &p, io::Cursor::new(&mut t1)).unwrap();
+    assert_eq!(tsize, t.len() as u64);
+    assert!(t1 == t);
+    p
+}
+
+#[test]
+fn recompress_gzip_members() {
+    let (s, t) = sample();
+    for level in [0, 1, 6, 9] {
+        let (sz, tz) = (gzip(&s, level), gzip(&t, level));
+        let (recipe, content) = GzipRecipe::analyze(&tz).unwrap();
+        assert_eq!(recipe.level, level);
+        assert!(content == t);
+        assert!(recipe.reproduce(&content).unwrap() == tz);
+        assert!(recipe.reproduce(&s).is_err());
+
+        let p = roundtrip(&sz, &tz);
+        let mut plain = Vec::new();
+        Bsdiff::new(&sz, &tz).compare(io::Cursor::new(&mut plain)).unwrap();
+        if level > 0 {
+            assert!(
+                p.len() * 4 < plain.len(),
+                "level {}: {} vs {}",
+                level,
+                p.len(),
+                plain.len()
+            );
+        }
+    }
+
+    // header fields and trailing bytes are kept
+    let mut encoder = GzBuilder::new()
+        .filename("data.txt")
+        .comment("comment")
+        .mtime(1234)
+        .write(Vec::new(), Compression::best());
+    encoder.write_all(&t).unwrap();
+    let mut tz = encoder.finish().unwrap();
+    tz.extend_from_slice(b"\0\0\0\0trailing");
+    assert!(GzipRecipe::analyze(&tz).is_some());
+    roundtrip(&gzip(&s, 6), &tz);
+}
+
+#[test]
+fn recompress_fallbacks() {
+    let (s, t) = sample();
+    let (sz, tz) = (gzip(&s, 6), gzip(&t, 6));
+
+    // any inputs are accepted
+    roundtrip(&s, &t);
+    roundtrip(&s, &tz);
+    roundtrip(&sz, &t);
+    let mut broken = tz.clone();
+    broken[tz.len() - 6] ^= 1;
+    assert!(GzipRecipe::analyze(&broken).is_none());
+    roundtrip(&sz, &broken);
+
+    // wrong or corrupted inputs of patching
+    let p = roundtrip(&sz, &tz);
+    assert!(recompress::apply(&s, &p, io::sink()).is_err());
+    assert!(recompress::apply(&sz, &p[..20], io::sink()).is_err());
+    assert!(recompress::apply(&sz, b"BSDIFF40", io::sink()).is_err());
+    let mut q = p.clone();
+    q[8] = 0x80;
+    assert!(recompress::apply(&sz, &q, io::sink()).is_err());
+}
@@ -0,0 +1,114 @@
+#![cfg(feature = "remote")]
+
+use std::cell::Cell;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use qbsdiff::remote::RangeReader;
+use qbsdiff::{inspect, interleave, Bsdiff, Bspatch, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 400 * 1000);
+    let mut t = s[100000..150000].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[300000..320000]);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+/// Serve ranges of the data, cutting responses to `cut` bytes.
+fn server(data: &[u8], cut: usize) -> impl FnMut(Range<u64>) -> io::Result<Vec<u8>> + '_ {
+    move |range| {
+        let start = Ord::min(range.start as usize, data.len());
+        let end = Ord::min(Ord::min(range.end as usize, data.len()), start.saturating_add(cut));
+        Ok(data[start..end].to_vec())
+    }
+}
+
+#[test]
+fn remote_source_hinted() {
+    let (s, t) = sample();
+    for format in [Format::Classic, Format::Extended] {
+        let p = Bsdiff::new(&s, &t).format(format).compare_to_vec().unwrap();
+        let needed: u64 = inspect::source_ranges(&p)
+            .unwrap()
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+
+        let mut source = RangeReader::new(s.len() as u64, server(&s, usize::MAX))
+            .request_size(16 * 1024)
+            .patch_hints(&p)
+            .unwrap();
+        let mut t1 = Vec::new();
+        Bspatch::new(&p)
+            .unwrap()
+            .apply_source(&mut source, io::Cursor::new(&mut t1))
+            .unwrap();
+        assert!(t1 == t);
+        assert_eq!(source.fetched(), needed);
+        assert!(source.fetched() < s.len() as u64 / 4);
+
+        // without hints, requests read ahead of the source
+        let mut source = RangeReader::new(s.len() as u64, server(&s, usize::MAX));
+        let mut t2 = Vec::new();
+        Bspatch::new(&p)
+            .unwrap()
+            .apply_source(&mut source, io::Cursor::new(&mut t2))
+            .unwrap();
+        assert!(t2 == t);
+        assert!(source.fetched() > needed);
+    }
+}
+
+#[test]
+fn remote_patch_stream() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    let mut interleaved = Vec::new();
+    interleave::interleave(&p[..], Codec::Bzip2, 9, &mut interleaved).unwrap();
+
+    let mut patch = RangeReader::new(interleaved.len() as u64, server(&interleaved, 1000)).request_size(4096);
+    let mut t1 = Vec::new();
+    interleave::apply_interleaved(&s, &mut patch, &mut t1).unwrap();
+    assert!(t1 == t);
+    assert_eq!(patch.fetched(), interleaved.len() as u64);
+    assert!(patch.requests() >= interleaved.len() as u64 / 1000);
+
+    let mut p1 = Vec::new();
+    patch.seek(SeekFrom::Start(0)).unwrap();
+    patch.read_to_end(&mut p1).unwrap();
+    assert!(p1 == interleaved);
+    assert_eq!(patch.seek(SeekFrom::End(-4)).unwrap(), interleaved.len() as u64 - 4);
+    assert!(patch.seek(SeekFrom::Current(-(interleaved.len() as i64))).is_err());
+}
+
+#[test]
+fn remote_re_requests() {
+    let (s, _) = sample();
+    let failures = Cell::new(0);
+    let flaky = |range: Range<u64>| {
+        failures.set(failures.get() + 1);
+        if failures.get() % 3 != 0 {
+            return Err(io::Error::from(ErrorKind::TimedOut));
+        }
+        Ok(s[range.start as usize..range.end as usize].to_vec())
+    };
+    let mut reader = RangeReader::new(s.len() as u64, flaky).request_size(1000);
+    let mut buf = vec![0; 2500];
+    io::Read::read_exact(&mut reader, &mut buf).unwrap();
+    assert!(buf[..] == s[..2500]);
+    assert_eq!(reader.requests(), 9);
+
+    let mut reader = RangeReader::new(s.len() as u64, flaky).retries(1);
+    let err = reader.read_exact(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+    let mut reader = RangeReader::new(s.len() as u64 + 10, server(&s, usize::MAX));
+    let err = reader
+        .seek(SeekFrom::End(-20))
+        .and_then(|_| reader.read_exact(&mut buf))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,32 @@
+use std::io;
+use std::path;
+
+use qbsdiff::Bsdiff;
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_scoring_invert() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("scoring invertible test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        let mut p = Vec::new();
+        Bsdiff::new(&s[..], &t[..])
+            .scoring(|x, y| match (x == y, x) {
+                (true, 0x00 | 0xff) => 0,
+                (true, _) => 2,
+                (false, _) => -1,
+            })
+            .compare(io::Cursor::new(&mut p))
+            .unwrap();
+        let t1 = testing.qbspatch(&s[..], &p[..]).unwrap();
+        if t != t1 {
+            panic!("not invertible with custom scoring: `{}`", sample.name);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, DiffProfile, Format, Section};
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+/// Source and target with both similar and random new data.
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..512 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(1000) {
+        t[i] = t[i].wrapping_add(1);
+    }
+    let mut x = 0x9e37_79b9_7f4a_7c15u64;
+    let random = (0..100_000).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x >> 32) as u8
+    });
+    t.splice(200_000..200_000, random);
+    (s, t)
+}
+
+/// Codec identifiers of the control, delta and extra sections in the
+/// extended header.
+fn codecs(p: &[u8]) -> [u8; 3] {
+    assert_eq!(&p[..8], b"QBSDIFF2");
+    [p[12], p[13], p[14]]
+}
+
+#[test]
+fn section_codecs_explicit() {
+    let (s, t) = sample();
+
+    // bzip2 levels per section in the classic format
+    let p = Bsdiff::new(&s, &t)
+        .section_codec(Section::Delta, Codec::Bzip2, 9)
+        .section_codec(Section::Extra, Codec::Bzip2, 1)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(&p[..8], b"BSDIFF40");
+    assert!(apply(&s, &p) == t);
+
+    // mixed codecs in the extended format
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .section_codec(Section::Extra, Codec::Stored, 1)
+        .section_codec(Section::Control, Codec::Bzip2, 0)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(codecs(&p), [0, 1, 0]);
+    assert!(apply(&s, &p) == t);
+
+    // classic format only supports bzip2
+    for (codec, level) in [(Codec::Stored, 1), (Codec::Bzip2, 0)] {
+        let err = Bsdiff::new(&s, &t)
+            .section_codec(Section::Extra, codec, level)
+            .compare(io::sink())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    let profile = DiffProfile {
+        section_codecs: [None, None, Some((Codec::Stored, 0))],
+        ..DiffProfile::default()
+    };
+    assert_eq!(profile.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let profile = DiffProfile {
+        format: Format::Extended,
+        ..profile
+    };
+    let p = Bsdiff::new(&s, &t)
+        .try_profile(&profile)
+        .unwrap()
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(codecs(&p), [1, 1, 0]);
+}
+
+#[test]
+fn section_codecs_auto() {
+    let (s, t) = sample();
+
+    // random extra data is stored, delta is compressed
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .auto_levels(true)
+        .compare_to_vec()
+        .unwrap();
+    assert_eq!(codecs(&p)[1..], [1, 0]);
+    assert!(apply(&s, &p) == t);
+
+    // not stored in the classic format
+    let p = Bsdiff::new(&s, &t).auto_levels(true).compare_to_vec().unwrap();
+    assert!(apply(&s, &p) == t);
+
+    // degenerate inputs
+    for (s, t) in [
+        (&[][..], &[][..]),
+        (&s[..], &[][..]),
+        (&[][..], &t[..]),
+        (&s[..], &s[..]),
+    ] {
+        let p = Bsdiff::new(s, t)
+            .format(Format::Extended)
+            .auto_levels(true)
+            .compare_to_vec()
+            .unwrap();
+        assert!(apply(s, &p) == t);
+    }
+}
@@ -0,0 +1,75 @@
+use std::io;
+
+use qbsdiff::{inspect, Bsdiff, Bspatch, Codec, Format, Section, SectionCorruption};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..1 << 18)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(997) {
+        t[i] ^= 0x5a;
+    }
+    t.splice(20000..20000, (0..50000u32).map(|i| ((i * 7) >> 3) as u8));
+    (s, t)
+}
+
+/// Apply the corrupted patch, then re-download the range reported and resume.
+fn apply_resumed(s: &[u8], p: &[u8], corrupted: &[u8], pos: usize) -> (SectionCorruption, Vec<u8>) {
+    let mut t = Vec::new();
+    let err = Bspatch::new(corrupted)
+        .unwrap()
+        .apply(s, io::Cursor::new(&mut t))
+        .unwrap_err();
+    let corruption = SectionCorruption::of(&err).unwrap();
+    assert_eq!(err.kind(), corruption.kind);
+    assert_eq!(t.len() as u64, corruption.written);
+    assert!(corruption.range.contains(&(pos as u64)));
+
+    let mut repaired = corrupted.to_vec();
+    let range = corruption.range.start as usize..corruption.range.end as usize;
+    repaired[range.clone()].copy_from_slice(&p[range]);
+    let tsize = Bspatch::new(&repaired).unwrap().hint_target_size();
+    Bspatch::new(&repaired)
+        .unwrap()
+        .apply_range(s, corruption.written..tsize, &mut t)
+        .unwrap();
+    (corruption, t)
+}
+
+#[test]
+fn section_corruption_blocks() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .codec(Codec::Bzip2)
+        .block_size(4096)
+        .compare_to_vec()
+        .unwrap();
+    let summary = inspect::validate(&p, None).unwrap();
+    let pos = p.len() * 3 / 4;
+    let mut corrupted = p.clone();
+    corrupted[pos..pos + 16].fill(0xff);
+
+    let (corruption, t1) = apply_resumed(&s, &p, &corrupted, pos);
+    assert!(t1 == t);
+    assert!(corruption.written > 0);
+    assert!(corruption.control < summary.controls);
+    assert!(corruption.range.end - corruption.range.start < 4096);
+    assert!((0..3).all(|i| corruption.decoded[i] <= summary.section_sizes[i]));
+}
+
+#[test]
+fn section_corruption_streams() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s, &t).compare_to_vec().unwrap();
+    let pos = p.len() - 100;
+    let mut corrupted = p.clone();
+    corrupted[pos..pos + 16].fill(0xff);
+
+    let (corruption, t1) = apply_resumed(&s, &p, &corrupted, pos);
+    assert!(t1 == t);
+    assert_eq!(corruption.section, Section::Extra);
+    assert_eq!(corruption.range.end, p.len() as u64);
+    assert!(corruption.written < t.len() as u64);
+}
@@ -0,0 +1,20 @@
+use qbsdiff::diagnostics;
+
+#[test]
+fn self_test_reports() {
+    let report = diagnostics::self_test().unwrap();
+    let names: Vec<_> = report.samples.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["text", "binary", "random", "fresh"]);
+    for sample in report.samples.iter() {
+        assert!(sample.target_size > 0);
+        assert!(sample.patch_size > 0);
+        assert!(sample.diff_throughput() > 0.0);
+        assert!(sample.patch_throughput() > 0.0);
+        assert_eq!(sample.diff_peak_rss.is_some(), cfg!(target_os = "linux"));
+    }
+    assert_eq!(report.samples[3].source_size, 0);
+    assert!(report.samples[0].patch_size * 10 < report.samples[0].target_size);
+    assert!(report.min_diff_throughput() <= report.samples[0].diff_throughput());
+    assert!(report.min_patch_throughput() > 0.0);
+    assert_eq!(report.max_peak_rss().is_some(), cfg!(target_os = "linux"));
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "serde")]
+
+use std::io;
+
+use qbsdiff::inspect::{self, Region, Validation};
+use qbsdiff::{ApplyStats, Bsdiff, Bspatch, Control, DiffReport, Format, MatchHistogram, PatchMetadata};
+use qbsdiff_test_bench_utils::*;
+
+fn roundtrip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+    serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s[20000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+#[test]
+fn serde_reports() {
+    let (s, t) = sample();
+    let mut p = Vec::new();
+    let report = Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .metadata(true)
+        .analyze(true)
+        .compare_report(io::Cursor::new(&mut p))
+        .unwrap();
+    let report1: DiffReport = roundtrip(&report);
+    assert_eq!(format!("{:?}", report1), format!("{:?}", report));
+    assert_eq!(report1.patch_size(), p.len() as u64);
+
+    let histogram = Bsdiff::new(&s, &t).match_histogram();
+    assert_eq!(roundtrip::<MatchHistogram>(&histogram), histogram);
+
+    let validation = inspect::validate(&p, Some(&s)).unwrap();
+    assert_eq!(roundtrip::<Validation>(&validation), validation);
+    let regions = inspect::regions(&p).unwrap();
+    assert_eq!(roundtrip::<Vec<Region>>(&regions), regions);
+
+    let metadata = Bspatch::new(&p).unwrap().metadata().unwrap();
+    assert_eq!(roundtrip::<PatchMetadata>(&metadata), metadata);
+
+    let stats = std::sync::Arc::new(std::sync::Mutex::new(ApplyStats::default()));
+    let slot = stats.clone();
+    Bspatch::new(&p)
+        .unwrap()
+        .on_stats(move |stats| *slot.lock().unwrap() = *stats)
+        .apply(&s, io::sink())
+        .unwrap();
+    let stats = *stats.lock().unwrap();
+    assert_eq!(roundtrip::<ApplyStats>(&stats), stats);
+}
+
+#[test]
+fn serde_controls() {
+    let (s, t) = sample();
+    let bsdiff = Bsdiff::new(&s, &t);
+    let chunks = bsdiff.search_chunks();
+    assert_eq!(roundtrip(&chunks), chunks);
+
+    // controls stored as JSON are packed later
+    let json = serde_json::to_string(&chunks[0].controls).unwrap();
+    let controls: Vec<Control> = serde_json::from_str(&json).unwrap();
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &t[chunks[0].range.clone()])
+        .compare_controls(controls, io::Cursor::new(&mut p))
+        .unwrap();
+    let mut t1 = Vec::new();
+    Bspatch::new(&p).unwrap().apply(&s, io::Cursor::new(&mut t1)).unwrap();
+    assert!(t1 == t[chunks[0].range.clone()]);
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "cmd")]
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::{env, fs, thread};
+
+use qbsdiff::Bspatch;
+
+fn request(addr: &str, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    request_with_length(addr, method, path, body, body.len())
+}
+
+fn request_with_length(addr: &str, method: &str, path: &str, body: &[u8], length: usize) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+        method, path, addr, length
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+#[test]
+fn server_diff_requests() {
+    let root = env::temp_dir().join(format!("qbsdiff-server-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let sources: Vec<Vec<u8>> = (1..4u32)
+        .map(|k| {
+            (0..300 * 1024u32)
+                .map(|i| (i.wrapping_mul(2654435761u32.wrapping_add(k)) >> 11) as u8)
+                .collect()
+        })
+        .collect();
+    for (i, s) in sources.iter().enumerate() {
+        fs::write(root.join(format!("s{}.bin", i)), s).unwrap();
+    }
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_qbsdiff-server"))
+        .arg(&root)
+        .args(["--listen", "127.0.0.1:0", "--pool", "2", "--max-target", "1000000"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line.trim().strip_prefix("listening on ").unwrap().to_string();
+
+    assert_eq!(request(&addr, "GET", "/health", b"").0, 200);
+    assert_eq!(request(&addr, "POST", "/diff/missing", b"").0, 404);
+    assert_eq!(request(&addr, "POST", "/diff/..%2Fs0.bin", b"").0, 400);
+    assert_eq!(request_with_length(&addr, "POST", "/diff/s0.bin", b"", 1000001).0, 413);
+
+    // concurrent requests, evicting warm sources
+    let handles: Vec<_> = (0..6)
+        .map(|i| {
+            let addr = addr.clone();
+            let s = sources[i % 3].clone();
+            thread::spawn(move || {
+                let mut t = s.clone();
+                t[1000 * i..1000 * i + 500].fill(i as u8);
+                t.extend_from_slice(b"appended");
+                let (status, p) = request(&addr, "POST", &format!("/diff/s{}.bin", i % 3), &t);
+                assert_eq!(status, 200);
+                let mut t1 = Vec::new();
+                Bspatch::new(&p[..])
+                    .unwrap()
+                    .apply(&s[..], io::Cursor::new(&mut t1))
+                    .unwrap();
+                assert!(t1 == t);
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    assert!(results.iter().all(|r| r.is_ok()));
+}
@@ -0,0 +1,72 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, ParallelScheme, SourceIndex};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s: Vec<u8> = (0..128 * 1024u32).map(|i| (i % 251) as u8 ^ (i >> 9) as u8).collect();
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(777) {
+        t[i] = t[i].wrapping_add(3);
+    }
+    t.splice(5000..5000, b"inserted text".iter().copied());
+    t.drain(70_000..71_000);
+    (s, t)
+}
+
+#[test]
+fn shared_index_roundtrip() {
+    let (s, t) = sample();
+    let mut data = Vec::new();
+    let size = SourceIndex::new(&s[..]).serialize(&mut data).unwrap();
+    assert_eq!(size, data.len() as u64);
+    assert_eq!(&data[..8], b"QBSINDX1");
+
+    let built = SourceIndex::new(&s[..]);
+    let shared = SourceIndex::from_bytes(&s[..], &data[..]).unwrap();
+    for pattern in [&b""[..], b"\x00\x01", &t[5000..5013], &t[..100], &s[90_000..91_000]] {
+        assert_eq!(shared.search_lcp(pattern).1, built.search_lcp(pattern).1);
+        let mut all = shared.search_all(pattern);
+        let mut expected = built.search_all(pattern);
+        all.sort();
+        expected.sort();
+        assert_eq!(all, expected);
+        assert_eq!(shared.contains(pattern), built.contains(pattern));
+    }
+
+    for scheme in [ParallelScheme::Never, ParallelScheme::NumJobs(3)] {
+        let p = Bsdiff::with_index(&shared, &t[..])
+            .parallel_scheme(scheme)
+            .compare_to_vec()
+            .unwrap();
+        let mut t1 = Vec::new();
+        Bspatch::new(&p[..])
+            .unwrap()
+            .apply(&s[..], io::Cursor::new(&mut t1))
+            .unwrap();
+        assert!(t1 == t);
+    }
+
+    // serializing the loaded index reproduces it
+    let mut copy = Vec::new();
+    shared.serialize(&mut copy).unwrap();
+    assert!(copy == data);
+}
+
+#[test]
+fn shared_index_rejected() {
+    let (s, _) = sample();
+    let mut data = Vec::new();
+    SourceIndex::new(&s[..]).serialize(&mut data).unwrap();
+
+    let err = SourceIndex::from_bytes(&s[1..], &data[..]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    for corrupt in [&data[..20], &data[..data.len() - 4], &data[1..]] {
+        let err = SourceIndex::from_bytes(&s[..], corrupt).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+    let mut corrupt = data.clone();
+    let n = corrupt.len();
+    corrupt[n - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = SourceIndex::from_bytes(&s[..], &corrupt[..]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
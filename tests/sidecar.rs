@@ -0,0 +1,103 @@
+use std::io::{self, ErrorKind};
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, ParallelScheme, Sidecar};
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..1 << 18)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 15) as u8)
+        .collect();
+    let mut target = source.clone();
+    for i in (0..target.len()).step_by(1531) {
+        target[i] = target[i].wrapping_add(3);
+    }
+    target.splice(100000..100000, b"inserted record".iter().copied());
+    target.drain(200000..200400);
+    (source, target)
+}
+
+fn apply(source: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)
+        .unwrap()
+        .apply(source, io::Cursor::new(&mut target))
+        .unwrap();
+    target
+}
+
+#[test]
+fn sidecar_pack_matches_compare() {
+    let (source, target) = sample();
+    let schemes = [
+        (ParallelScheme::Never, None),
+        (ParallelScheme::ChunkSize(1 << 16), None),
+        (ParallelScheme::Never, Some(1 << 20)),
+    ];
+    for (scheme, memory_limit) in schemes {
+        let bsdiff = || {
+            Bsdiff::new(&source, &target)
+                .parallel_scheme(scheme)
+                .memory_limit(memory_limit)
+                .small_match(16)
+        };
+        let sidecar = bsdiff().compare_sidecar().unwrap();
+        assert_eq!(sidecar.source_size(), source.len() as u64);
+        assert_eq!(sidecar.target_size(), target.len() as u64);
+        assert_eq!(sidecar.parameters().get("small_match"), Some("16"));
+        assert_eq!(sidecar.parameters().get("codecs"), None);
+
+        let mut encoded = Vec::new();
+        let size = sidecar.write(&mut encoded).unwrap();
+        assert_eq!(size, encoded.len() as u64);
+        let decoded = Sidecar::read(&encoded[..]).unwrap();
+        assert_eq!(decoded, sidecar);
+
+        let mut packed = Vec::new();
+        bsdiff().pack_sidecar(&decoded, io::Cursor::new(&mut packed)).unwrap();
+        assert!(packed == bsdiff().compare_to_vec().unwrap());
+        assert!(apply(&source, &packed) == target);
+    }
+}
+
+#[test]
+fn sidecar_pack_settings() {
+    let (source, target) = sample();
+    let sidecar = Bsdiff::new(&source, &target).small_match(20).compare_sidecar().unwrap();
+
+    let mut patch = Vec::new();
+    Bsdiff::new(&source, &target)
+        .format(Format::Extended)
+        .codec(Codec::Stored)
+        .target_copy(true)
+        .metadata(true)
+        .pack_sidecar(&sidecar, io::Cursor::new(&mut patch))
+        .unwrap();
+    assert!(apply(&source, &patch) == target);
+    let patcher = Bspatch::new(&patch).unwrap();
+    let metadata = patcher.metadata().unwrap();
+    assert_eq!(metadata.get("small_match"), Some("20"));
+    assert_eq!(metadata.get("codecs"), Some("Stored:6,Stored:6,Stored:6"));
+}
+
+#[test]
+fn sidecar_mismatch() {
+    let (source, target) = sample();
+    let sidecar = Bsdiff::new(&source, &target).compare_sidecar().unwrap();
+
+    let mut other = target.clone();
+    other[0] ^= 1;
+    let err = Bsdiff::new(&source, &other)
+        .pack_sidecar(&sidecar, io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = Bsdiff::new(&target, &target)
+        .pack_sidecar(&sidecar, io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let mut encoded = Vec::new();
+    sidecar.write(&mut encoded).unwrap();
+    for len in [0, 20, encoded.len() - 1] {
+        let err = Sidecar::read(&encoded[..len]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,21 @@
+use qbsdiff::{similarity, SourceIndex};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn similarity_estimation() {
+    let source = hashed_bytes(0, 256 * 1024);
+    let unrelated: Vec<u8> = (0..256 * 1024u32).map(|i| (i.wrapping_mul(40503) >> 7) as u8).collect();
+    let mut half = source[..128 * 1024].to_vec();
+    half.extend_from_slice(&unrelated[..128 * 1024]);
+
+    let index = SourceIndex::new(&source[..]);
+    assert_eq!(index.similarity(&source[..], 64), 1.0);
+    assert_eq!(index.similarity(&source[1000..], 0), 1.0);
+    assert!(index.similarity(&unrelated[..], 64) < 0.05);
+    let estimation = index.similarity(&half[..], 16);
+    assert!((0.45..0.55).contains(&estimation), "estimation = {}", estimation);
+
+    assert_eq!(similarity(&source[..], &half[..]), similarity(&source[..], &half[..]));
+    assert_eq!(index.similarity(&[], 64), 1.0);
+    assert_eq!(index.similarity(&source[10..20], 64), 1.0);
+}
@@ -0,0 +1,125 @@
+use std::io::{self, ErrorKind, Read};
+
+use qbsdiff::{transcode, Bsdiff, Bspatch, Codec, Format, PartialPatch, PatchedReader, SourceCorruption};
+use qbsdiff_test_bench_utils::*;
+
+const WINDOW: usize = 4096;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s.clone();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+fn diff(s: &[u8], t: &[u8], codec: Codec) -> Vec<u8> {
+    let mut p = Vec::new();
+    Bsdiff::new(s, t)
+        .format(Format::Extended)
+        .codec(codec)
+        .source_checksum(WINDOW)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    p
+}
+
+fn apply(s: &[u8], p: &[u8]) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?.apply(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn source_checksum_detects_corruption() {
+    let (s, t) = sample();
+    for codec in [Codec::Stored, Codec::Bzip2] {
+        let p = diff(&s[..], &t[..], codec);
+        assert!(apply(&s[..], &p[..]).unwrap() == t);
+
+        for pos in [0, 4095, 4096, 50000, s.len() - 1] {
+            let mut s1 = s.clone();
+            s1[pos] ^= 1;
+            let err = apply(&s1[..], &p[..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            let start = (pos / WINDOW * WINDOW) as u64;
+            let end = Ord::min(start + WINDOW as u64, s.len() as u64);
+            assert_eq!(SourceCorruption::of(&err).unwrap().range, start..end);
+            assert!(err.to_string().contains(&format!("{}..{}", start, end)));
+
+            // only windows being read are verified
+            let mut t1 = Vec::new();
+            let result = Bspatch::new(&p[..])
+                .unwrap()
+                .apply_range(&s1[..], 60000..70000, io::Cursor::new(&mut t1));
+            if (60000..70000).contains(&pos) || (start..end).contains(&60000) {
+                assert!(SourceCorruption::of(&result.unwrap_err()).is_some());
+            } else {
+                result.unwrap();
+                assert!(t1[..] == t[60000..70000]);
+            }
+        }
+
+        let err = apply(&s[1..], &p[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(SourceCorruption::of(&err).is_none());
+
+        let mut t1 = Vec::new();
+        PatchedReader::new(&s[..], &p[..])
+            .unwrap()
+            .read_to_end(&mut t1)
+            .unwrap();
+        assert!(t1 == t);
+    }
+}
+
+#[test]
+fn source_checksum_header() {
+    let (s, t) = sample();
+    let p = diff(&s[..], &t[..], Codec::Stored);
+    let header_size = 48 + 16 + s.len().div_ceil(WINDOW) * 4;
+
+    for n in [48, 63, 64, header_size - 1, header_size, p.len() - 1] {
+        let partial = PartialPatch::check(&p[..n]).unwrap();
+        assert_eq!(partial.is_header_complete(), n >= header_size);
+        assert!(partial.header_size() <= header_size);
+        if n >= 64 {
+            assert_eq!(partial.header_size(), header_size);
+        }
+        assert_eq!(partial.total_size().is_some(), n >= header_size);
+        assert!(partial.min_total_size() <= p.len() as u64);
+    }
+    assert_eq!(PartialPatch::check(&p[..]).unwrap().total_size(), Some(p.len() as u64));
+
+    // truncated checksums
+    assert!(Bspatch::new(&p[..header_size - 1]).is_err());
+
+    // zero window
+    let mut p1 = p.clone();
+    p1[56..64].fill(0);
+    assert_eq!(Bspatch::new(&p1[..]).err().unwrap().kind(), ErrorKind::InvalidData);
+
+    let err = Bsdiff::new(&s[..], &t[..])
+        .source_checksum(WINDOW)
+        .compare(io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn source_checksum_transcode() {
+    let (s, t) = sample();
+    let p = diff(&s[..], &t[..], Codec::Bzip2);
+
+    let mut q = Vec::new();
+    transcode(&p[..], &mut q, Format::Extended, Format::Extended).unwrap();
+    assert!(apply(&s[..], &q[..]).unwrap() == t);
+    let mut s1 = s.clone();
+    s1[12345] ^= 1;
+    let err = apply(&s1[..], &q[..]).unwrap_err();
+    assert_eq!(SourceCorruption::of(&err).unwrap().range, 12288..16384);
+
+    let mut r = Vec::new();
+    transcode(&p[..], &mut r, Format::Extended, Format::Classic).unwrap();
+    assert!(apply(&s[..], &r[..]).unwrap() == t);
+}
@@ -0,0 +1,85 @@
+use std::io::Cursor;
+use std::path;
+
+use qbsdiff::{Bsdiff, ParallelScheme, SourceIndex};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn random_samples_source_index() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("source index test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+        let index = SourceIndex::new(&s[..]);
+
+        for scheme in [ParallelScheme::Never, ParallelScheme::NumJobs(3)] {
+            let mut p = Vec::new();
+            Bsdiff::new(&s[..], &t[..])
+                .parallel_scheme(scheme)
+                .compare(Cursor::new(&mut p))
+                .unwrap();
+            let mut p1 = Vec::new();
+            Bsdiff::with_index(&index, &t[..])
+                .parallel_scheme(scheme)
+                .compare(Cursor::new(&mut p1))
+                .unwrap();
+            if p != p1 {
+                panic!("patch differs with prebuilt index: `{}`", sample.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_output_deterministic() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+    let (s, t) = samples
+        .iter()
+        .map(|sample| (sample.load_source().unwrap(), sample.load_target().unwrap()))
+        .find(|(_, t)| t.len() >= 4 * 1024 * 1024)
+        .unwrap();
+    let index = SourceIndex::new(&s[..]);
+
+    let diff = |jobs| {
+        let mut p = Vec::new();
+        let report = Bsdiff::with_index(&index, &t[..])
+            .parallel_scheme(ParallelScheme::NumJobs(jobs))
+            .compare_report(Cursor::new(&mut p))
+            .unwrap();
+        assert!(report.workers() <= jobs);
+        (p, report)
+    };
+    let (p, report) = diff(2);
+    assert!(report.jobs() > 2);
+    for _ in 0..3 {
+        assert_eq!(diff(2).0, p);
+    }
+    assert_eq!(testing.qbspatch(&s[..], &p[..]).unwrap(), t);
+}
+
+#[test]
+fn source_index_search() {
+    let s = b"the quick brown fox jumps over the lazy dog, the end";
+    let index = SourceIndex::new(&s[..]);
+
+    let (offset, len) = index.search_lcp(b"the lazy cat");
+    assert_eq!((offset, len), (31, 9));
+    assert_eq!(&s[offset..offset + len], b"the lazy ");
+    assert_eq!(index.search_lcp(b"zzz").1, 1);
+    assert_eq!(index.search_lcp(b"#").1, 0);
+
+    let mut all = index.search_all(b"the").to_vec();
+    all.sort_unstable();
+    assert_eq!(all, [0, 31, 45]);
+    assert!(index.search_all(b"cat").is_empty());
+    assert!(index.contains(b"brown fox"));
+    assert!(!index.contains(b"brown cat"));
+}
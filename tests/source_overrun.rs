@@ -0,0 +1,103 @@
+use std::io;
+
+use qbsdiff::{Bspatch, ControlFault, CorruptControl, SourceOverrun};
+use qbsdiff_test_bench_utils::*;
+
+fn encode_int(x: i64) -> [u8; 8] {
+    let y = if x < 0 { x.unsigned_abs() | 1 << 63 } else { x as u64 };
+    y.to_le_bytes()
+}
+
+/// Extended patch with stored sections.
+fn extended(ctrls: &[(i64, i64, i64)], delta: &[u8], extra: &[u8], tsize: u64) -> Vec<u8> {
+    let mut c = Vec::new();
+    for &(add, copy, seek) in ctrls.iter() {
+        c.extend_from_slice(&encode_int(add));
+        c.extend_from_slice(&encode_int(copy));
+        c.extend_from_slice(&encode_int(seek));
+    }
+    let mut patch = b"QBSDIFF2".to_vec();
+    patch.extend_from_slice(&[0; 8]);
+    for size in [c.len() as u64, delta.len() as u64, extra.len() as u64, tsize] {
+        patch.extend_from_slice(&size.to_le_bytes());
+    }
+    patch.extend_from_slice(&c);
+    patch.extend_from_slice(delta);
+    patch.extend_from_slice(extra);
+    patch
+}
+
+fn apply(source: &[u8], patch: &[u8], policy: SourceOverrun, bs: usize) -> io::Result<Vec<u8>> {
+    let mut target = Vec::new();
+    Bspatch::new(patch)?
+        .buffer_size(bs)
+        .delta_min(bs)
+        .source_overrun(policy)
+        .apply(source, io::Cursor::new(&mut target))?;
+    Ok(target)
+}
+
+fn expected(source: &[u8], start: usize, delta: &[u8]) -> Vec<u8> {
+    (0..delta.len())
+        .map(|i| source.get(start + i).copied().unwrap_or(0).wrapping_add(delta[i]))
+        .collect()
+}
+
+#[test]
+fn source_overrun_error() {
+    let source: Vec<u8> = (0..100u8).collect();
+    let patch = extended(&[(10, 0, 80), (20, 0, 0)], &[1; 30], &[], 30);
+    let err = apply(&source, &patch, SourceOverrun::Error, 16).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let corrupt = CorruptControl::of(&err).unwrap();
+    assert_eq!((corrupt.index, corrupt.fault), (1, ControlFault::SourceOutOfBounds));
+
+    // Default policy.
+    let mut target = Vec::new();
+    let err = Bspatch::new(&patch)
+        .unwrap()
+        .apply(&source, io::Cursor::new(&mut target))
+        .unwrap_err();
+    assert!(CorruptControl::of(&err).is_some());
+}
+
+#[test]
+fn source_overrun_zero_fill() {
+    let source: Vec<u8> = (0..100u8).map(|x| x.wrapping_mul(37)).collect();
+    let delta: Vec<u8> = (0..300u32).map(|i| (i * 11) as u8).collect();
+    for bs in [1, 7, 16, 4096] {
+        // Straddling the end of source.
+        let patch = extended(&[(10, 0, 80), (20, 0, 0)], &delta[..30], &[], 30);
+        let mut want = expected(&source, 0, &delta[..10]);
+        want.extend(expected(&source, 90, &delta[10..30]));
+        assert_eq!(apply(&source, &patch, SourceOverrun::ZeroFill, bs).unwrap(), want);
+
+        // Seeking beyond the end of source.
+        let patch = extended(&[(0, 3, 200), (5, 0, 0)], &delta[..5], b"abc", 8);
+        let mut want = b"abc".to_vec();
+        want.extend_from_slice(&delta[..5]);
+        assert_eq!(apply(&source, &patch, SourceOverrun::ZeroFill, bs).unwrap(), want);
+
+        // Empty source.
+        let patch = extended(&[(300, 2, 0)], &delta, b"xy", 302);
+        let mut want = delta.clone();
+        want.extend_from_slice(b"xy");
+        assert_eq!(apply(&[], &patch, SourceOverrun::ZeroFill, bs).unwrap(), want);
+    }
+}
+
+#[test]
+fn source_overrun_in_bounds() {
+    let source = hashed_bytes(0, 4096);
+    let mut target = source.clone();
+    target[100..200].fill(7);
+    target.extend_from_slice(b"grown");
+
+    let mut patch = Vec::new();
+    qbsdiff::Bsdiff::new(&source, &target)
+        .compare(io::Cursor::new(&mut patch))
+        .unwrap();
+    for policy in [SourceOverrun::Error, SourceOverrun::ZeroFill] {
+        assert_eq!(apply(&source, &patch, policy, 4096).unwrap(), target);
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+
+use qbsdiff::{Bsdiff, Bspatch, SourcePreload, SourceRead};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 200 * 1000);
+    let mut t = s[120_000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+/// Source recording the ranges read.
+struct Recorded<'a> {
+    data: &'a [u8],
+    reads: Vec<Range<u64>>,
+}
+
+impl SourceRead for Recorded<'_> {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.reads.push(offset..offset + buf.len() as u64);
+        let start = offset as usize;
+        let bytes = self
+            .data
+            .get(start..start + buf.len())
+            .ok_or(ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+fn apply_source(p: &[u8], s: &[u8], policy: SourcePreload) -> io::Result<(Vec<u8>, Vec<Range<u64>>)> {
+    let mut t = Vec::new();
+    let mut source = Recorded {
+        data: s,
+        reads: Vec::new(),
+    };
+    Bspatch::new(p)?
+        .buffer_size(1024)
+        .source_preload(policy)
+        .apply_source(&mut source, io::Cursor::new(&mut t))?;
+    Ok((t, source.reads))
+}
+
+#[test]
+fn source_preload_policies() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+
+    let (t1, reads) = apply_source(&p[..], &s[..], SourcePreload::Seek).unwrap();
+    assert!(t1 == t);
+    assert!(reads.len() > 1);
+
+    let (t1, reads) = apply_source(&p[..], &s[..], SourcePreload::Whole).unwrap();
+    assert!(t1 == t);
+    assert_eq!(reads, vec![0..s.len() as u64]);
+
+    let policy = SourcePreload::Windows { size: 4096, count: 4 };
+    let (t1, reads) = apply_source(&p[..], &s[..], policy).unwrap();
+    assert!(t1 == t);
+    assert!(reads.iter().all(|r| r.start % 4096 == 0 && r.end - r.start <= 4096));
+    for (i, r) in reads.iter().enumerate() {
+        // windows cached are not read again until evicted
+        let next = reads[i + 1..].iter().position(|next| next == r);
+        assert!(next.is_none_or(|k| k >= 4));
+    }
+
+    // degenerate windows are clamped
+    let policy = SourcePreload::Windows { size: 0, count: 0 };
+    let (t1, _) = apply_source(&p[..], &s[..], policy).unwrap();
+    assert!(t1 == t);
+
+    // truncated sources are still rejected
+    for policy in [SourcePreload::Whole, SourcePreload::Windows { size: 4096, count: 4 }] {
+        let err = apply_source(&p[..], &s[..150_000], policy).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn source_preload_auto() {
+    let (s, t) = sample();
+
+    // reading backward, most of a small source
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+    let patcher = Bspatch::new(&p[..]).unwrap();
+    assert_eq!(patcher.effective_source_preload(s.len() as u64), SourcePreload::Whole);
+    let (t1, reads) = apply_source(&p[..], &s[..], SourcePreload::Auto).unwrap();
+    assert!(t1 == t);
+    assert_eq!(reads.len(), 1);
+
+    // reading backward, a small part of the source
+    assert!(matches!(
+        patcher.effective_source_preload(s.len() as u64 * 10),
+        SourcePreload::Windows { .. }
+    ));
+
+    // reading forward only
+    let p = Bsdiff::new(&s[..], &s[100..]).compare_to_vec().unwrap();
+    let patcher = Bspatch::new(&p[..]).unwrap();
+    assert_eq!(patcher.effective_source_preload(s.len() as u64), SourcePreload::Seek);
+
+    // explicit policies are kept
+    let patcher = Bspatch::new(&p[..]).unwrap().source_preload(SourcePreload::Whole);
+    assert_eq!(patcher.effective_source_preload(s.len() as u64), SourcePreload::Whole);
+}
@@ -0,0 +1,110 @@
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+
+use qbsdiff::{Bsdiff, Bspatch, Format, SourceCorruption, SourceRead};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 100 * 1000);
+    let mut t = s[20000..].to_vec();
+    t[5000..5100].fill(0x5a);
+    t.extend_from_slice(&s[..30000]);
+    t.extend_from_slice(b"appended data");
+    (s, t)
+}
+
+/// Source counting the reads, failing reads out of bounds.
+struct Counted<'a> {
+    data: &'a [u8],
+    reads: usize,
+}
+
+impl SourceRead for Counted<'_> {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.reads += 1;
+        let start = offset as usize;
+        let bytes = self
+            .data
+            .get(start..start + buf.len())
+            .ok_or(ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+fn apply_source<S: SourceRead>(p: &[u8], s: S) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?
+        .buffer_size(1024)
+        .apply_source(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn apply_source_matches_apply() {
+    let (s, t) = sample();
+    for format in [Format::Classic, Format::Extended] {
+        let mut p = Vec::new();
+        Bsdiff::new(&s[..], &t[..])
+            .format(format)
+            .compare(io::Cursor::new(&mut p))
+            .unwrap();
+
+        assert!(apply_source(&p[..], &s[..]).unwrap() == t);
+        assert!(apply_source(&p[..], s.clone()).unwrap() == t);
+        let mut counted = Counted { data: &s[..], reads: 0 };
+        assert!(apply_source(&p[..], &mut counted).unwrap() == t);
+        assert!(counted.reads > 0);
+
+        let dir = env::temp_dir().join("qbsdiff-test");
+        fs::create_dir_all(dir.as_path()).unwrap();
+        let spath = dir.join(format!("source-read-{:?}.s", format));
+        fs::write(spath.as_path(), &s[..]).unwrap();
+        let t1 = apply_source(&p[..], fs::File::open(spath.as_path()).unwrap());
+        fs::remove_file(spath.as_path()).unwrap();
+        assert!(t1.unwrap() == t);
+
+        // truncated sources are reported by the source
+        let err = apply_source(&p[..], &s[..50000]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn apply_source_verifies_checksums() {
+    let (s, t) = sample();
+    let mut p = Vec::new();
+    Bsdiff::new(&s[..], &t[..])
+        .format(Format::Extended)
+        .source_checksum(4096)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    let mut counted = Counted { data: &s[..], reads: 0 };
+    assert!(apply_source(&p[..], &mut counted).unwrap() == t);
+
+    let mut s1 = s.clone();
+    s1[50000] ^= 1;
+    let err = apply_source(
+        &p[..],
+        Counted {
+            data: &s1[..],
+            reads: 0,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(SourceCorruption::of(&err).unwrap().range, 49152..53248);
+    let err = apply_source(&p[..], &s[..50000]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let err = Bspatch::new(&p[..])
+        .unwrap()
+        .fuzzy_source(1024)
+        .apply_source(&s[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
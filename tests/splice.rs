@@ -0,0 +1,103 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 400 * 1000);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(10007) {
+        t[i] ^= 0x5a;
+    }
+    t.splice(100_000..100_000, b"inserted data".iter().copied());
+    (s, t)
+}
+
+fn diff(s: &[u8], t: &[u8]) -> Vec<u8> {
+    Bsdiff::new(s, t).compare_to_vec().unwrap()
+}
+
+fn apply(s: &[u8], p: &[u8]) -> Vec<u8> {
+    let mut t = Vec::new();
+    Bspatch::new(p).unwrap().apply(s, io::Cursor::new(&mut t)).unwrap();
+    t
+}
+
+#[test]
+fn splice_changed_regions() {
+    let (s, t) = sample();
+    let p = diff(&s, &t);
+
+    // rewritten in place, grown, shrunk, at both ends, and moved from source
+    let edits: Vec<(usize, usize, Vec<u8>)> = vec![
+        (200_000, 200_100, vec![0x11; 100]),
+        (200_000, 200_100, vec![0x22; 5000]),
+        (200_000, 220_000, b"short".to_vec()),
+        (0, 10, vec![0x33; 20]),
+        (t.len() - 100, t.len(), Vec::new()),
+        (300_000, 300_000, s[100_000..150_000].to_vec()),
+        (0, t.len(), s[..1000].to_vec()),
+    ];
+    for (start, end, data) in edits {
+        let mut t1 = t.clone();
+        t1.splice(start..end, data.iter().copied());
+        let changed = start..start + data.len();
+
+        let mut p1 = Vec::new();
+        let size = Bsdiff::new(&s, &t1)
+            .splice(&p, changed.clone(), io::Cursor::new(&mut p1))
+            .unwrap();
+        assert_eq!(size, p1.len() as u64);
+        assert!(apply(&s, &p1) == t1, "{:?}", changed);
+        let full = diff(&s, &t1);
+        assert!(
+            p1.len() < full.len() * 2 + 1000,
+            "{:?}: {} vs {}",
+            changed,
+            p1.len(),
+            full.len()
+        );
+    }
+
+    // nothing changed
+    let mut p1 = Vec::new();
+    Bsdiff::new(&s, &t)
+        .splice(&p, 1000..1000, io::Cursor::new(&mut p1))
+        .unwrap();
+    assert!(apply(&s, &p1) == t);
+}
+
+#[test]
+fn splice_other_patches() {
+    let (s, t) = sample();
+    let mut t1 = t.clone();
+    t1[350_000..350_010].fill(0);
+
+    // target-relative copies become extra data
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &t)
+        .format(Format::Extended)
+        .target_copy(true)
+        .dedupe(true)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    let mut p1 = Vec::new();
+    Bsdiff::new(&s, &t1)
+        .format(Format::Extended)
+        .splice(&p, 350_000..350_010, io::Cursor::new(&mut p1))
+        .unwrap();
+    assert!(apply(&s, &p1) == t1);
+
+    // range not fitting the patch
+    let p = diff(&s, &t);
+    #[allow(clippy::reversed_empty_ranges)]
+    for changed in [10..5, 0..t.len() + 1] {
+        let err = Bsdiff::new(&s, &t).splice(&p, changed, io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    let mut t2 = t.clone();
+    t2.extend_from_slice(&[0; 1000]);
+    let err = Bsdiff::new(&s, &t2).splice(&p, 0..10, io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(Bsdiff::new(&s, &t).splice(&p[..40], 0..10, io::sink()).is_err());
+}
@@ -0,0 +1,80 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use qbsdiff::inspect::{self, RegionKind};
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, ParallelScheme, PatchedReader};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn target_copy_runs() {
+    let s = hashed_bytes(0, 128 * 1024);
+    let mut t = Vec::new();
+    for i in 0..32u32 {
+        t.extend_from_slice(&s[i as usize * 4096..i as usize * 4096 + 2048]);
+        // zero fills and repeated small structures
+        t.resize(t.len() + 1000 + i as usize * 10, 0);
+        for j in 0..200u32 {
+            t.extend_from_slice(&(i * 7919).to_le_bytes());
+            t.extend_from_slice(&(j % 2).to_le_bytes());
+        }
+    }
+
+    let diff = |target_copy: bool| {
+        let mut p = Vec::new();
+        Bsdiff::new(&s[..], &t[..])
+            .format(Format::Extended)
+            .codec(Codec::Stored)
+            .parallel_scheme(ParallelScheme::Never)
+            .target_copy(target_copy)
+            .compare(io::Cursor::new(&mut p))
+            .unwrap();
+        p
+    };
+    let plain = diff(false);
+    let p = diff(true);
+    assert!(p.len() * 2 < plain.len());
+
+    for bs in [128, 4096, 1 << 20] {
+        let mut t1 = Vec::new();
+        Bspatch::new(&p[..])
+            .unwrap()
+            .buffer_size(bs)
+            .apply(&s[..], io::Cursor::new(&mut t1))
+            .unwrap();
+        assert!(t1 == t);
+    }
+
+    // random access
+    let mut reader = PatchedReader::new(&s[..], &p[..]).unwrap();
+    assert_eq!(reader.len(), t.len() as u64);
+    let mut t1 = Vec::new();
+    reader.read_to_end(&mut t1).unwrap();
+    assert!(t1 == t);
+    let ranges = [
+        0..1,
+        2040..2100,
+        3000..3333,
+        10000..50000,
+        0..t.len(),
+        t.len() - 5..t.len(),
+    ];
+    for range in ranges.iter().cloned() {
+        let mut buf = vec![0; range.len()];
+        reader.seek(SeekFrom::Start(range.start as u64)).unwrap();
+        reader.read_exact(&mut buf[..]).unwrap();
+        assert!(buf[..] == t[range.clone()]);
+
+        let mut buf = Vec::new();
+        let range64 = range.start as u64..range.end as u64;
+        let n = Bspatch::new(&p[..])
+            .unwrap()
+            .apply_range(&s[..], range64, io::Cursor::new(&mut buf))
+            .unwrap();
+        assert_eq!(n, range.len() as u64);
+        assert!(buf[..] == t[range]);
+    }
+
+    // regions
+    let regions = inspect::regions(&p[..]).unwrap();
+    assert!(regions.iter().any(|r| r.kind == RegionKind::Repeat));
+    assert_eq!(regions.last().unwrap().target.end, t.len() as u64);
+}
@@ -0,0 +1,56 @@
+use std::io;
+
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, SourceIndex, TargetFeeder};
+
+#[test]
+fn target_feeder_out_of_order() {
+    let source: Vec<u8> = (0..200 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+        .collect();
+    let mut target = source.clone();
+    target[5000..6000].fill(0x33);
+    target.extend_from_slice(&source[..30000]);
+
+    let index = SourceIndex::new(&source[..]);
+    let config = Bsdiff::with_index(&index, &[])
+        .format(Format::Extended)
+        .codec(Codec::Stored);
+    let mut feeder = TargetFeeder::with_config(&config, target.len() as u64);
+    let cuts = [0, 4096, 70000, 150000, target.len()];
+    let order = [2, 0, 3, 1];
+
+    let mut assembled = vec![0; target.len()];
+    for (k, &i) in order.iter().enumerate() {
+        let (start, end) = (cuts[i], cuts[i + 1]);
+        let fragment = feeder.feed(start as u64, &target[start..end]).unwrap();
+        assert_eq!(fragment.target, start as u64..end as u64);
+        assert_eq!(feeder.is_complete(), k == order.len() - 1);
+
+        let mut bytes = Vec::new();
+        Bspatch::new(&fragment.patch[..])
+            .unwrap()
+            .apply(&source[..], io::Cursor::new(&mut bytes))
+            .unwrap();
+        assembled[start..end].copy_from_slice(&bytes[..]);
+    }
+    assert_eq!(assembled, target);
+    assert!(feeder.missing().is_empty());
+}
+
+#[test]
+fn target_feeder_bad_ranges() {
+    let source = b"the quick brown fox jumps over the lazy dog".to_vec();
+    let index = SourceIndex::new(&source[..]);
+    let mut feeder = TargetFeeder::new(&index, 100);
+
+    feeder.feed(10, &[1; 20]).unwrap();
+    feeder.feed(50, &[2; 10]).unwrap();
+    assert_eq!(feeder.fed_size(), 30);
+    assert_eq!(feeder.missing(), vec![0..10, 30..50, 60..100]);
+
+    assert!(feeder.feed(25, &[0; 10]).is_err());
+    assert!(feeder.feed(0, &[0; 11]).is_err());
+    assert!(feeder.feed(95, &[0; 10]).is_err());
+    feeder.feed(30, &[3; 20]).unwrap();
+    assert_eq!(feeder.missing(), vec![0..10, 60..100]);
+}
@@ -0,0 +1,39 @@
+use std::path;
+
+use qbsdiff::{Bspatch, Codec, Format, Tolerance};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn regular_samples_trailing_bytes() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let samples = testing.get_regular_samples().unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("tolerance test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        let classic = testing.qbsdiff(&s[..], &t[..]).unwrap();
+        let opts = QbsdiffOptions {
+            format: Format::Extended,
+            codec: Codec::Stored,
+            ..QbsdiffOptions::default()
+        };
+        let extended = testing.qbsdiff_with(&s[..], &t[..], opts).unwrap();
+
+        for (mut p, strict_ok) in [(classic, true), (extended, false)] {
+            let padding = 512 - p.len() % 512;
+            p.resize(p.len() + padding, 0);
+            assert_eq!(Bspatch::new(&p[..]).is_ok(), strict_ok);
+
+            let patcher = Bspatch::with_tolerance(&p[..], Tolerance::Lenient).unwrap();
+            assert_eq!(patcher.trailing_bytes(), padding as u64);
+            let mut t1 = Vec::new();
+            patcher.apply(&s[..], &mut t1).unwrap();
+            if t != t1 {
+                panic!("lenient patching failed: `{}`", sample.name);
+            }
+        }
+    }
+}
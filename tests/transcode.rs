@@ -0,0 +1,102 @@
+use std::io::{self, ErrorKind};
+use std::path;
+
+use qbsdiff::{transcode, Bsdiff, Bspatch, Codec, Format};
+use qbsdiff_test_bench_utils::*;
+
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 64 * 1024);
+    let mut t = s.clone();
+    for i in (0..t.len()).step_by(997) {
+        t[i] = t[i].wrapping_add(i as u8);
+    }
+    t.extend_from_slice(b"appended new data, appended new data");
+    t.resize(t.len() + 4096, 0);
+    (s, t)
+}
+
+fn diff(s: &[u8], t: &[u8], format: Format, codec: Codec, target_copy: bool) -> Vec<u8> {
+    let mut p = Vec::new();
+    Bsdiff::new(s, t)
+        .format(format)
+        .codec(codec)
+        .target_copy(target_copy)
+        .compare(io::Cursor::new(&mut p))
+        .unwrap();
+    p
+}
+
+fn convert(p: &[u8], from: Format, to: Format) -> io::Result<Vec<u8>> {
+    let mut q = Vec::new();
+    let size = transcode(p, &mut q, from, to)?;
+    assert_eq!(size, q.len() as u64);
+    Ok(q)
+}
+
+fn apply(s: &[u8], p: &[u8]) -> io::Result<Vec<u8>> {
+    let mut t = Vec::new();
+    Bspatch::new(p)?.apply(s, io::Cursor::new(&mut t))?;
+    Ok(t)
+}
+
+#[test]
+fn transcode_classic_round_trip() {
+    let (s, t) = sample();
+    let p = diff(&s[..], &t[..], Format::Classic, Codec::Bzip2, false);
+
+    let q = convert(&p[..], Format::Classic, Format::Extended).unwrap();
+    assert!(q.starts_with(b"QBSDIFF2"));
+    assert!(apply(&s[..], &q[..]).unwrap() == t);
+
+    // sections are rewrapped as is
+    let r = convert(&q[..], Format::Extended, Format::Classic).unwrap();
+    assert!(r == p);
+
+    // transcoding is idempotent
+    assert!(convert(&q[..], Format::Extended, Format::Extended).unwrap() == q);
+
+    // mismatched format
+    let err = convert(&p[..], Format::Extended, Format::Classic).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn transcode_checksums_verified() {
+    let (s, t) = sample();
+    let p = diff(&s[..], &t[..], Format::Classic, Codec::Bzip2, false);
+    let q = convert(&p[..], Format::Classic, Format::Extended).unwrap();
+
+    for i in [60, q.len() / 2, q.len() - 20, q.len() - 1] {
+        let mut r = q.clone();
+        r[i] ^= 0x10;
+        let err = apply(&s[..], &r[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = convert(&r[..], Format::Extended, Format::Classic).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn transcode_extended_to_bspatch() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let testing = Testing::new(assets);
+    let (s, t) = sample();
+    for codec in [Codec::Stored, Codec::Bzip2] {
+        let p = diff(&s[..], &t[..], Format::Extended, codec, false);
+        let q = convert(&p[..], Format::Extended, Format::Classic).unwrap();
+        assert!(q.starts_with(b"BSDIFF40"));
+        assert!(testing.bspatch(&s[..], &q[..]).unwrap() == t);
+    }
+}
+
+#[test]
+fn transcode_target_copy() {
+    let (s, t) = sample();
+    let p = diff(&s[..], &t[..], Format::Extended, Codec::Stored, true);
+
+    let q = convert(&p[..], Format::Extended, Format::Extended).unwrap();
+    assert!(apply(&s[..], &q[..]).unwrap() == t);
+
+    let err = convert(&p[..], Format::Extended, Format::Classic).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
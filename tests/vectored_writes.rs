@@ -0,0 +1,140 @@
+use std::io::{self, IoSlice, Write};
+
+use qbsdiff::{Bsdiff, Bspatch};
+
+/// Sink counting write calls, writing at most `limit` bytes per call.
+struct Sink {
+    data: Vec<u8>,
+    calls: usize,
+    limit: usize,
+    vectored: bool,
+}
+
+impl Sink {
+    fn new(limit: usize, vectored: bool) -> Self {
+        Sink {
+            data: Vec::new(),
+            calls: 0,
+            limit,
+            vectored,
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.calls += 1;
+        let n = Ord::min(buf.len(), self.limit);
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if !self.vectored {
+            let buf = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| &b[..]);
+            return self.write(buf);
+        }
+        self.calls += 1;
+        let mut n = 0;
+        for buf in bufs {
+            let k = Ord::min(buf.len(), self.limit - n);
+            self.data.extend_from_slice(&buf[..k]);
+            n += k;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn random(n: usize) -> Vec<u8> {
+    let mut x = 0x9e37_79b9_7f4a_7c15u64;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        })
+        .collect()
+}
+
+/// Source and target with similar data interleaved with long new data.
+fn sample() -> (Vec<u8>, Vec<u8>) {
+    let s = random(200_000);
+    let mut t = Vec::new();
+    for (i, chunk) in s.chunks(20_000).enumerate() {
+        t.extend_from_slice(chunk);
+        t.extend(random(30_000 + i).into_iter().rev());
+    }
+    (s, t)
+}
+
+#[test]
+fn vectored_writes_roundtrip() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+
+    let mut plain = Sink::new(usize::MAX, true);
+    Bspatch::new(&p[..])
+        .unwrap()
+        .buffer_size(4096)
+        .apply(&s[..], &mut plain)
+        .unwrap();
+    assert!(plain.data == t);
+
+    let mut vectored = Sink::new(usize::MAX, true);
+    Bspatch::new(&p[..])
+        .unwrap()
+        .buffer_size(4096)
+        .vectored_writes(true)
+        .apply(&s[..], &mut vectored)
+        .unwrap();
+    assert!(vectored.data == t);
+    assert!(vectored.calls < plain.calls);
+
+    // partial writes and sinks without vectored IO
+    for (limit, supported) in [(1000, true), (usize::MAX, false), (777, false)] {
+        let mut sink = Sink::new(limit, supported);
+        Bspatch::new(&p[..])
+            .unwrap()
+            .buffer_size(4096)
+            .vectored_writes(true)
+            .apply(&s[..], &mut sink)
+            .unwrap();
+        assert!(sink.data == t);
+    }
+}
+
+#[test]
+fn vectored_writes_hashed_and_ranged() {
+    let (s, t) = sample();
+    let p = Bsdiff::new(&s[..], &t[..]).compare_to_vec().unwrap();
+
+    let (sum, size) = Bspatch::new(&p[..])
+        .unwrap()
+        .buffer_size(4096)
+        .vectored_writes(true)
+        .apply_hashed(&s[..], Sink::new(1000, true), 0u64, |sum, data| {
+            *sum = data
+                .iter()
+                .fold(*sum, |h, &b| h.wrapping_mul(31).wrapping_add(b as u64));
+        })
+        .unwrap();
+    assert_eq!(size, t.len() as u64);
+    assert_eq!(
+        sum,
+        t.iter().fold(0u64, |h, &b| h.wrapping_mul(31).wrapping_add(b as u64))
+    );
+
+    let mut body = Vec::new();
+    Bspatch::new(&p[..])
+        .unwrap()
+        .buffer_size(4096)
+        .vectored_writes(true)
+        .apply_range(&s[..], 12_345..234_567, io::Cursor::new(&mut body))
+        .unwrap();
+    assert!(body[..] == t[12_345..234_567]);
+}
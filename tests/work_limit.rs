@@ -0,0 +1,63 @@
+use std::io::Cursor;
+use std::path;
+
+use qbsdiff::{Bsdiff, ParallelScheme};
+use qbsdiff_test_bench_utils::*;
+
+#[test]
+fn random_samples_work_limit_invert() {
+    let assets = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let descs = default_random_samples();
+    let testing = Testing::new(assets);
+    let samples = testing.get_random_samples(descs.as_ref()).unwrap();
+
+    for sample in samples.iter() {
+        eprintln!("work limit test on sample `{}`", sample.name);
+        let s = sample.load_source().unwrap();
+        let t = sample.load_target().unwrap();
+
+        for limit in [1, 4] {
+            let mut p = Vec::new();
+            Bsdiff::new(&s[..], &t[..])
+                .work_limit(limit)
+                .compare(Cursor::new(&mut p))
+                .unwrap();
+            let t1 = testing.qbspatch(&s[..], &p[..]).unwrap();
+            if t != t1 {
+                panic!("not invertible: `{}` with work limit {}", sample.name, limit);
+            }
+        }
+    }
+}
+
+#[test]
+fn repetitive_input_work_limit() {
+    let testing = Testing::new(std::env::temp_dir());
+    let pattern = b"0123456789abcdef";
+    let mut s: Vec<u8> = pattern.iter().cycle().take(1 << 20).copied().collect();
+    let mut t = s.clone();
+    for i in (0..s.len()).step_by(97) {
+        s[i] ^= 0x55;
+    }
+    for i in (0..t.len()).step_by(89) {
+        t[i] ^= 0xaa;
+    }
+
+    let diff = |limit: Option<usize>| {
+        let mut p = Vec::new();
+        let mut bsdiff = Bsdiff::new(&s[..], &t[..]).parallel_scheme(ParallelScheme::Never);
+        if let Some(limit) = limit {
+            bsdiff = bsdiff.work_limit(limit);
+        }
+        let report = bsdiff.compare_report(Cursor::new(&mut p)).unwrap();
+        assert_eq!(testing.qbspatch(&s[..], &p[..]).unwrap(), t);
+        report
+    };
+
+    let unbounded = diff(None);
+    let bounded = diff(Some(4));
+    assert_eq!(unbounded.fallback_chunks(), 0);
+    assert!(unbounded.search_steps() > 4 * t.len() as u64);
+    assert_eq!(bounded.fallback_chunks(), 1);
+    assert!(bounded.search_steps() < unbounded.search_steps());
+}
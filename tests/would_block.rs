@@ -0,0 +1,104 @@
+use std::io::{self, ErrorKind, Write};
+use std::time::Duration;
+
+use qbsdiff::{bspatch, Bsdiff, Bspatch, Stall};
+use qbsdiff_test_bench_utils::*;
+
+/// Pipe accepting a few bytes per write, and blocking every other write.
+struct Pipe {
+    data: Vec<u8>,
+    calls: usize,
+    block: bool,
+}
+
+impl Pipe {
+    fn new(block: bool) -> Self {
+        Pipe {
+            data: Vec::new(),
+            calls: 0,
+            block,
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.calls += 1;
+        if self.block || self.calls.is_multiple_of(2) {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let n = Ord::min(buf.len(), 1000);
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.calls += 1;
+        if self.block || self.calls.is_multiple_of(2) {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        Ok(())
+    }
+}
+
+fn sample() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let s = hashed_bytes(0, 64 * 1024);
+    let mut t = s.clone();
+    t[1000..1100].fill(0x5a);
+    t.extend_from_slice(&[7; 5000]);
+    let mut p = Vec::new();
+    Bsdiff::new(&s, &t).compare(io::Cursor::new(&mut p)).unwrap();
+    (s, t, p)
+}
+
+#[test]
+fn would_block_retried() {
+    let (s, t, p) = sample();
+
+    let mut pipe = Pipe::new(false);
+    let err = Bspatch::new(&p).unwrap().apply(&s, &mut pipe).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+    let mut stalls = Vec::new();
+    let mut pipe = Pipe::new(false);
+    let size = Bspatch::new(&p)
+        .unwrap()
+        .buffer_size(4096)
+        .wait_strategy(|stall: &Stall| {
+            stalls.push(*stall);
+            Ok(())
+        })
+        .apply(&s, &mut pipe)
+        .unwrap();
+    assert_eq!(size, t.len() as u64);
+    assert!(pipe.data == t);
+    assert!(!stalls.is_empty());
+    assert!(stalls.iter().all(|stall| stall.attempts == 1));
+    assert!(stalls.windows(2).all(|w| w[0].position <= w[1].position));
+
+    let mut pipe = Pipe::new(false);
+    Bspatch::new(&p)
+        .unwrap()
+        .wait_strategy(bspatch::backoff(Duration::from_secs(10)))
+        .apply_range(&s, 500..60000, &mut pipe)
+        .unwrap();
+    assert!(pipe.data[..] == t[500..60000]);
+}
+
+#[test]
+fn would_block_timeout() {
+    let (s, _, p) = sample();
+
+    let mut attempts = 0;
+    let mut wait = bspatch::backoff(Duration::from_millis(20));
+    let err = Bspatch::new(&p)
+        .unwrap()
+        .wait_strategy(|stall: &Stall| {
+            attempts = stall.attempts;
+            wait(stall)
+        })
+        .apply(&s, Pipe::new(true))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(attempts > 1);
+}
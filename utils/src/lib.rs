@@ -12,7 +12,7 @@ use rand::distributions::uniform::{SampleUniform, Uniform};
 use rand::prelude::*;
 use rand::random;
 
-use qbsdiff::{Bsdiff, Bspatch, ParallelScheme};
+use qbsdiff::{Bsdiff, Bspatch, Codec, Format, ParallelScheme};
 
 /// Options for qbsdiff.
 #[derive(Copy, Clone, Debug)]
@@ -21,6 +21,8 @@ pub struct QbsdiffOptions {
     pub small_match: usize,
     pub compression_level: u32,
     pub buffer_size: usize,
+    pub format: Format,
+    pub codec: Codec,
 }
 
 impl Default for QbsdiffOptions {
@@ -30,6 +32,8 @@ impl Default for QbsdiffOptions {
             small_match: qbsdiff::bsdiff::SMALL_MATCH,
             compression_level: qbsdiff::bsdiff::COMPRESSION_LEVEL,
             buffer_size: qbsdiff::bsdiff::BUFFER_SIZE,
+            format: Format::Classic,
+            codec: Codec::Bzip2,
         }
     }
 }
@@ -88,6 +92,8 @@ impl Testing {
             .small_match(opts.small_match)
             .compression_level(opts.compression_level)
             .buffer_size(opts.buffer_size)
+            .format(opts.format)
+            .codec(opts.codec)
             .compare(io::Cursor::new(&mut p))?;
         Ok(p)
     }
@@ -174,6 +180,8 @@ impl Benchmarking {
             .small_match(opts.small_match)
             .compression_level(opts.compression_level)
             .buffer_size(opts.buffer_size)
+            .format(opts.format)
+            .codec(opts.codec)
             .compare(io::sink())?;
         Ok(())
     }
@@ -289,7 +297,7 @@ where
     let mut stderr = proc
         .stderr
         .take()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to get command stderr"))?;
+        .ok_or_else(|| io::Error::other("failed to get command stderr"))?;
     stderr.read_to_string(&mut errors)?;
 
     let status = proc.wait()?;
@@ -304,7 +312,7 @@ where
             status,
             errors
         );
-        return Err(io::Error::new(io::ErrorKind::Other, message));
+        Err(io::Error::other(message))
     } else {
         Ok(())
     }
@@ -342,12 +350,12 @@ pub struct Sample {
 impl Sample {
     /// Load source data.
     pub fn load_source(&self) -> io::Result<Vec<u8>> {
-        Ok(fs::read(self.source.as_path())?)
+        fs::read(self.source.as_path())
     }
 
     /// Load target data.
     pub fn load_target(&self) -> io::Result<Vec<u8>> {
-        Ok(fs::read(self.target.as_path())?)
+        fs::read(self.target.as_path())
     }
 }
 
@@ -356,12 +364,12 @@ fn get_samples_in<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Sample>> {
     let pat = dir.as_ref().join("*.s");
     let walker;
     if let Some(p) = pat.to_str() {
-        walker = glob(p).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        walker = glob(p).map_err(io::Error::other)?;
     } else {
-        return Err(io::Error::new(io::ErrorKind::Other, "cannot convert to str"));
+        return Err(io::Error::other("cannot convert to str"));
     }
     for result in walker.into_iter() {
-        let source = result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?.into_path();
+        let source = result.map_err(io::Error::other)?.into_path();
 
         let name;
         let target;
@@ -378,10 +386,10 @@ fn get_samples_in<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Sample>> {
             pbuf.push(".p");
             patch = path::PathBuf::from(d).join(pbuf.as_os_str());
         } else {
-            return Err(io::Error::new(io::ErrorKind::Other, "cannot make target or patch path"));
+            return Err(io::Error::other("cannot make target or patch path"));
         }
 
-        if let Err(_) = fs::metadata(target.as_path()) {
+        if fs::metadata(target.as_path()).is_err() {
             continue;
         }
 
@@ -541,6 +549,16 @@ pub fn default_random_bench_samples() -> Vec<RandomSample> {
     ]
 }
 
+/// Deterministic bytes of multiplicative hashes of positions, reproducible
+/// test data without sample files.
+///
+/// Data of different seeds are shifted copies of each other.
+pub fn hashed_bytes(seed: u32, n: usize) -> Vec<u8> {
+    (0..n as u32)
+        .map(|i| (i.wrapping_add(seed).wrapping_mul(2654435761) >> 13) as u8)
+        .collect()
+}
+
 fn random_bytes(n: usize) -> Vec<u8> {
     let mut rng = thread_rng();
     let mut bytes = Vec::with_capacity(n);